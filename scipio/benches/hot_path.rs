@@ -0,0 +1,166 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+//! Counts the instructions the hot paths of the executor retire, and fails if any of
+//! them goes over its budget. Timings are too noisy to catch small regressions, while
+//! instruction counts barely move from one run to the next.
+//!
+//! Build without default features to check the lean build, which gets tighter budgets:
+//!
+//! ```text
+//! cargo bench --bench hot_path --no-default-features
+//! ```
+//!
+//! Instructions are counted with perf events. Where they are not available, like in
+//! most containers, the benchmark fails rather than pass without checking anything: run
+//! it on a host that allows `perf_event_open` for unprivileged users
+//! (`kernel.perf_event_paranoid` of 2 or less).
+use scipio::{Local, LocalExecutor, Timer};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+const ITERATIONS: u64 = 10_000;
+
+// Instructions per iteration, with room for noise from the allocator and the kernel
+#[cfg(feature = "stats")]
+const SPAWN_AND_JOIN_BUDGET: u64 = 6_000;
+#[cfg(not(feature = "stats"))]
+const SPAWN_AND_JOIN_BUDGET: u64 = 5_000;
+#[cfg(feature = "stats")]
+const TIMER_ARM_BUDGET: u64 = 3_000;
+#[cfg(not(feature = "stats"))]
+const TIMER_ARM_BUDGET: u64 = 2_500;
+
+// The parts of struct perf_event_attr (PERF_ATTR_SIZE_VER5) we care about
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const DISABLED: u64 = 1 << 0;
+const EXCLUDE_KERNEL: u64 = 1 << 5;
+const EXCLUDE_HV: u64 = 1 << 6;
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+const PERF_EVENT_IOC_RESET: libc::c_ulong = 0x2403;
+
+// Counts the userspace instructions retired by this thread
+struct InstructionCounter(RawFd);
+
+impl InstructionCounter {
+    fn new() -> io::Result<InstructionCounter> {
+        let attr = PerfEventAttr {
+            type_: PERF_TYPE_HARDWARE,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config: PERF_COUNT_HW_INSTRUCTIONS,
+            flags: DISABLED | EXCLUDE_KERNEL | EXCLUDE_HV,
+            ..Default::default()
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                0,
+                -1,
+                -1,
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(InstructionCounter(fd as RawFd))
+    }
+
+    fn measure<F: FnOnce()>(&self, f: F) -> u64 {
+        unsafe {
+            libc::ioctl(self.0, PERF_EVENT_IOC_RESET, 0);
+            libc::ioctl(self.0, PERF_EVENT_IOC_ENABLE, 0);
+        }
+        f();
+        let mut count = 0u64;
+        unsafe {
+            libc::ioctl(self.0, PERF_EVENT_IOC_DISABLE, 0);
+            libc::read(self.0, &mut count as *mut u64 as *mut libc::c_void, 8);
+        }
+        count
+    }
+}
+
+impl Drop for InstructionCounter {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn check(counter: &InstructionCounter, name: &str, budget: u64, f: impl FnOnce()) -> bool {
+    let per_iteration = counter.measure(f) / ITERATIONS;
+    let verdict = if per_iteration <= budget {
+        "ok"
+    } else {
+        "OVER BUDGET"
+    };
+    println!(
+        "{:<24} {:>8} instructions (budget {:>8}) {}",
+        name, per_iteration, budget, verdict
+    );
+    per_iteration <= budget
+}
+
+fn main() {
+    let counter = match InstructionCounter::new() {
+        Ok(counter) => counter,
+        Err(err) => panic!("instructions can't be counted here: {}", err),
+    };
+
+    let ex = LocalExecutor::new(None).unwrap();
+    // warm up, so the first iterations don't pay for growing queues and slabs
+    ex.run(async {
+        for _ in 0..ITERATIONS {
+            Local::local(async {}).await;
+        }
+    });
+
+    let mut ok = true;
+    ok &= check(&counter, "spawn and join", SPAWN_AND_JOIN_BUDGET, || {
+        ex.run(async {
+            for _ in 0..ITERATIONS {
+                Local::local(async {}).await;
+            }
+        })
+    });
+    ok &= check(&counter, "timer arm and cancel", TIMER_ARM_BUDGET, || {
+        ex.run(async {
+            for _ in 0..ITERATIONS {
+                let mut timer = Timer::new(Duration::from_secs(60));
+                // Polling once is what arms it
+                let _ = futures::poll!(&mut timer);
+            }
+        })
+    });
+    assert!(ok, "hot paths went over their instruction budget");
+}
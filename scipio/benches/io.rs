@@ -0,0 +1,73 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use scipio::{DmaFile, LocalExecutor};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+
+// Scipio is meant to be used with NVMe-backed XFS or EXT4 volumes, so benchmarks run
+// there when SCIPIO_TEST_POLLIO_ROOTDIR points to one. Otherwise the temporary directory
+// is used, which is still good enough to measure the submission overhead.
+fn bench_file() -> PathBuf {
+    let mut path = std::env::var("SCIPIO_TEST_POLLIO_ROOTDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir());
+    path.push("scipio-io-bench");
+    path
+}
+
+fn io_submission(c: &mut Criterion) {
+    let ex = LocalExecutor::new(None).unwrap();
+    let path = bench_file();
+    let file = ex.run(async {
+        let file = DmaFile::create(&path).await.unwrap();
+        let buf = DmaFile::alloc_dma_buffer(1 << 20);
+        buf.memset(1);
+        file.write_dma(&buf, 0).await.unwrap();
+        Rc::new(file)
+    });
+
+    let mut group = c.benchmark_group("dma file");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("4k read", |b| {
+        b.iter_custom(|iters| {
+            let file = file.clone();
+            ex.run(async move {
+                let start = Instant::now();
+                for i in 0..iters {
+                    let pos = (i % 256) * 4096;
+                    file.read_dma_aligned(pos, 4096).await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+    group.bench_function("4k write", |b| {
+        b.iter_custom(|iters| {
+            let file = file.clone();
+            ex.run(async move {
+                let buf = DmaFile::alloc_dma_buffer(4096);
+                let start = Instant::now();
+                for i in 0..iters {
+                    let pos = (i % 256) * 4096;
+                    file.write_dma(&buf, pos).await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+    group.finish();
+
+    ex.run(async move {
+        let mut file = Rc::try_unwrap(file).unwrap();
+        file.close().await.unwrap();
+    });
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, io_submission);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+use scipio::{Async, Latency, Local, LocalExecutor, Task};
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
+
+fn spawn_latency(c: &mut Criterion) {
+    let ex = LocalExecutor::new(None).unwrap();
+    c.bench_function("spawn and join", |b| {
+        b.iter_custom(|iters| {
+            ex.run(async move {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    Task::local(async {}).await;
+                }
+                start.elapsed()
+            })
+        })
+    });
+
+    let tq = ex.create_task_queue(1000, Latency::NotImportant, "bench");
+    c.bench_function("spawn into task queue and join", |b| {
+        b.iter_custom(|iters| {
+            ex.run(async move {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    Local::local_into(async {}, tq).unwrap().await;
+                }
+                start.elapsed()
+            })
+        })
+    });
+}
+
+fn cross_shard_messages(c: &mut Criterion) {
+    let (local, remote) = UnixStream::pair().unwrap();
+    let echo = LocalExecutor::spawn_executor("echo", None, move || async move {
+        let mut remote = Async::new(remote).unwrap();
+        let mut buf = [0u8; 64];
+        while remote.read_exact(&mut buf).await.is_ok() {
+            if remote.write_all(&buf).await.is_err() {
+                break;
+            }
+        }
+    })
+    .unwrap();
+
+    let ex = LocalExecutor::new(None).unwrap();
+    let local = ex.run(async move { Async::new(local).unwrap() });
+
+    let mut group = c.benchmark_group("cross shard");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("64 byte message round trip", |b| {
+        b.iter_custom(|iters| {
+            let mut stream = &local;
+            ex.run(async move {
+                let mut buf = [0u8; 64];
+                let start = Instant::now();
+                for _ in 0..iters {
+                    stream.write_all(&buf).await.unwrap();
+                    stream.read_exact(&mut buf).await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+    group.finish();
+
+    drop(local);
+    echo.join().unwrap();
+}
+
+criterion_group!(benches, spawn_latency, cross_shard_messages);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use scipio::{LocalExecutor, Timer};
+use std::time::{Duration, Instant};
+
+fn timer_arm(c: &mut Criterion) {
+    let ex = LocalExecutor::new(None).unwrap();
+    c.bench_function("timer arm and cancel", |b| {
+        b.iter_custom(|iters| {
+            ex.run(async move {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    let mut timer = Timer::new(Duration::from_secs(60));
+                    // Polling once is what arms it
+                    let _ = futures::poll!(&mut timer);
+                }
+                start.elapsed()
+            })
+        })
+    });
+}
+
+fn timer_fire(c: &mut Criterion) {
+    let ex = LocalExecutor::new(None).unwrap();
+    c.bench_function("timer fire", |b| {
+        b.iter_custom(|iters| {
+            ex.run(async move {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    Timer::new(Duration::from_micros(1)).await;
+                }
+                start.elapsed()
+            })
+        })
+    });
+
+    c.bench_function("1000 timers fire together", |b| {
+        b.iter_batched(
+            || (),
+            |_| {
+                ex.run(async {
+                    let timers: Vec<_> = (0..1000)
+                        .map(|_| Timer::new(Duration::from_micros(100)))
+                        .collect();
+                    futures::future::join_all(timers).await;
+                })
+            },
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+criterion_group!(benches, timer_arm, timer_fire);
+criterion_main!(benches);
@@ -0,0 +1,325 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+//! Utilities to measure the performance of applications built on top of Scipio.
+//!
+//! This module is only available with the `bench` feature enabled. It provides a
+//! [`LatencyRecorder`], a [`ThroughputCounter`] and a workload driver, [`run`], that
+//! issues requests either at a fixed rate (open loop) or with a fixed amount of them
+//! in flight (closed loop) and reports the results together with the executor's
+//! scheduling statistics.
+//!
+//! [`LatencyRecorder`]: struct.LatencyRecorder.html
+//! [`ThroughputCounter`]: struct.ThroughputCounter.html
+//! [`run`]: fn.run.html
+use crate::{ExecutorStats, Local, Task, Timer};
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Records latency samples and computes statistics over them.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyRecorder {
+    samples: Vec<Duration>,
+    sorted: bool,
+}
+
+impl LatencyRecorder {
+    /// Creates an empty recorder
+    pub fn new() -> LatencyRecorder {
+        LatencyRecorder::default()
+    }
+
+    /// Adds a sample to the recorder
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+        self.sorted = false;
+    }
+
+    /// Adds all samples from `other` to this recorder
+    pub fn merge(&mut self, other: &LatencyRecorder) {
+        self.samples.extend_from_slice(&other.samples);
+        self.sorted = false;
+    }
+
+    /// Number of samples recorded so far
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// The smallest sample recorded, if any
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().copied()
+    }
+
+    /// The largest sample recorded, if any
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+
+    /// The average of all samples recorded, if any
+    pub fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total: Duration = self.samples.iter().sum();
+        Some(total / self.samples.len() as u32)
+    }
+
+    /// The sample below which `percentile` percent of all samples fall, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percentile` is not between 0 and 100.
+    pub fn percentile(&mut self, percentile: f64) -> Option<Duration> {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "percentile must be between 0 and 100"
+        );
+        if self.samples.is_empty() {
+            return None;
+        }
+        if !self.sorted {
+            self.samples.sort_unstable();
+            self.sorted = true;
+        }
+        let rank = (percentile / 100.0 * (self.samples.len() - 1) as f64).round() as usize;
+        Some(self.samples[rank])
+    }
+}
+
+/// Counts operations and bytes processed since it was created.
+#[derive(Debug, Clone)]
+pub struct ThroughputCounter {
+    started: Instant,
+    stopped: Option<Instant>,
+    operations: u64,
+    bytes: u64,
+}
+
+impl Default for ThroughputCounter {
+    fn default() -> Self {
+        ThroughputCounter::new()
+    }
+}
+
+impl ThroughputCounter {
+    /// Creates a counter that starts measuring now
+    pub fn new() -> ThroughputCounter {
+        ThroughputCounter {
+            started: Instant::now(),
+            stopped: None,
+            operations: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Accounts for one operation that processed `bytes` bytes
+    pub fn record(&mut self, bytes: u64) {
+        self.operations += 1;
+        self.bytes += bytes;
+    }
+
+    /// Number of operations accounted for so far
+    pub fn operations(&self) -> u64 {
+        self.operations
+    }
+
+    /// Number of bytes accounted for so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Stops the clock, so rates are no longer diluted by time that passes after the
+    /// measurement is over
+    pub fn stop(&mut self) {
+        self.stopped.get_or_insert_with(Instant::now);
+    }
+
+    /// Time elapsed between the creation of the counter and the moment it was stopped, or
+    /// now if it was not stopped
+    pub fn elapsed(&self) -> Duration {
+        let end = self.stopped.unwrap_or_else(Instant::now);
+        end - self.started
+    }
+
+    /// Average number of operations per second over the measured interval
+    pub fn operations_per_sec(&self) -> f64 {
+        self.operations as f64 / self.elapsed().as_secs_f64()
+    }
+
+    /// Average number of bytes per second over the measured interval
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed().as_secs_f64()
+    }
+}
+
+/// How requests are issued by [`run`]
+///
+/// [`run`]: fn.run.html
+#[derive(Debug, Copy, Clone)]
+pub enum Workload {
+    /// Requests are issued at a fixed rate, in requests per second, regardless of how long
+    /// the previous ones take to complete. Latencies are measured from the moment each
+    /// request was supposed to be issued, so a stalled executor shows up in the results.
+    OpenLoop(f64),
+
+    /// A fixed number of requests is kept in flight, each one issued as soon as the
+    /// previous one completes.
+    ClosedLoop(usize),
+}
+
+/// The results of a call to [`run`]
+///
+/// [`run`]: fn.run.html
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    /// Latency of every request that completed
+    pub latencies: LatencyRecorder,
+    /// Requests and bytes processed over the run
+    pub throughput: ThroughputCounter,
+    /// Statistics of the executor the benchmark ran on, taken at the end of the run
+    pub executor_stats: ExecutorStats,
+}
+
+/// Drives `request` according to `workload` for `duration`, and reports the results.
+///
+/// `request` returns the number of bytes each request processed, which is used to
+/// compute the throughput. Must be called from within a [`LocalExecutor`].
+///
+/// # Examples
+///
+/// ```
+/// use scipio::bench::{self, Workload};
+/// use scipio::LocalExecutor;
+/// use std::time::Duration;
+///
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async {
+///     let mut report = bench::run(Workload::ClosedLoop(4), Duration::from_millis(10), || async {
+///         0
+///     })
+///     .await;
+///     println!("p99: {:?}", report.latencies.percentile(99.0));
+/// });
+/// ```
+///
+/// [`LocalExecutor`]: ../struct.LocalExecutor.html
+pub async fn run<F, Fut>(workload: Workload, duration: Duration, request: F) -> BenchReport
+where
+    F: Fn() -> Fut + 'static,
+    Fut: Future<Output = u64> + 'static,
+{
+    let request = Rc::new(request);
+    let results = Rc::new(RefCell::new((
+        LatencyRecorder::new(),
+        ThroughputCounter::new(),
+    )));
+    let end = Instant::now() + duration;
+
+    let mut tasks = Vec::new();
+    match workload {
+        Workload::OpenLoop(rate) => {
+            assert!(rate > 0.0, "request rate must be positive");
+            let interval = Duration::from_secs_f64(1.0 / rate);
+            let mut issue_at = Instant::now();
+            while issue_at < end {
+                let now = Instant::now();
+                if issue_at > now {
+                    Timer::new(issue_at - now).await;
+                }
+                let request = request.clone();
+                let results = results.clone();
+                tasks.push(Task::local(async move {
+                    let bytes = request().await;
+                    let mut results = results.borrow_mut();
+                    results.0.record(issue_at.elapsed());
+                    results.1.record(bytes);
+                }));
+                issue_at += interval;
+            }
+        }
+        Workload::ClosedLoop(concurrency) => {
+            for _ in 0..concurrency {
+                let request = request.clone();
+                let results = results.clone();
+                tasks.push(Task::local(async move {
+                    while Instant::now() < end {
+                        let started = Instant::now();
+                        let bytes = request().await;
+                        let mut results = results.borrow_mut();
+                        results.0.record(started.elapsed());
+                        results.1.record(bytes);
+                    }
+                }));
+            }
+        }
+    }
+
+    for task in tasks {
+        task.await;
+    }
+
+    let (latencies, mut throughput) = results.borrow().clone();
+    throughput.stop();
+    BenchReport {
+        latencies,
+        throughput,
+        executor_stats: Local::executor_stats(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn latency_recorder_percentiles() {
+        let mut recorder = LatencyRecorder::new();
+        for ms in (1..=100).rev() {
+            recorder.record(Duration::from_millis(ms));
+        }
+        assert_eq!(recorder.count(), 100);
+        assert_eq!(recorder.min(), Some(Duration::from_millis(1)));
+        assert_eq!(recorder.max(), Some(Duration::from_millis(100)));
+        assert_eq!(recorder.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(recorder.percentile(50.0), Some(Duration::from_millis(51)));
+        assert_eq!(recorder.percentile(100.0), Some(Duration::from_millis(100)));
+        assert_eq!(LatencyRecorder::new().percentile(99.0), None);
+    }
+
+    #[test]
+    fn open_and_closed_loop_workloads() {
+        test_executor!(async move {
+            let report = run(
+                Workload::OpenLoop(1000.0),
+                Duration::from_millis(50),
+                || async {
+                    Timer::new(Duration::from_millis(1)).await;
+                    10
+                },
+            )
+            .await;
+            let ops = report.throughput.operations();
+            assert!(ops > 10 && ops <= 50, "{} requests issued", ops);
+            assert_eq!(report.throughput.bytes(), ops * 10);
+            assert_eq!(report.latencies.count() as u64, ops);
+
+            let report = run(
+                Workload::ClosedLoop(2),
+                Duration::from_millis(50),
+                || async {
+                    Timer::new(Duration::from_millis(5)).await;
+                    1
+                },
+            )
+            .await;
+            let ops = report.throughput.operations();
+            assert!(ops >= 2 && ops <= 22, "{} requests issued", ops);
+            assert!(report.latencies.min().unwrap() >= Duration::from_millis(5));
+        });
+    }
+}
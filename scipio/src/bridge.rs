@@ -0,0 +1,154 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::pollable::Async;
+use crate::sys;
+use futures::task::{waker, ArcWake};
+use futures_lite::pin;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// Tells apart the sockets of every bridge ever created by this process
+static BRIDGE_ID: AtomicU64 = AtomicU64::new(0);
+
+// The remote side of the bridge: wakers handed to the bridged future point here and
+// can be used from any thread.
+//
+// Every executor thread has a file descriptor table of its own, so a file descriptor
+// number can't be handed to other threads: on another executor it names a different
+// file, or nothing. Instead, the bridge binds a socket to a name in the abstract
+// namespace, and remote wakers send it a datagram from a socket of their own thread.
+// The notifier never owns a file descriptor, so it can be dropped anywhere.
+#[derive(Debug)]
+struct RemoteNotifier {
+    name: String,
+    notified: AtomicBool,
+}
+
+impl ArcWake for RemoteNotifier {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if !arc_self.notified.swap(true, Ordering::AcqRel) {
+            // Only fails if the bridge is gone, and nobody needs the wakeup then.
+            let _ = sys::send_wakeup(arc_self.name.as_bytes());
+        }
+    }
+}
+
+/// Awaits a future that may be woken up from other threads.
+///
+/// Wakers handed out by Scipio tasks can only be used from the executor thread that
+/// created them. Futures from thread-safe libraries, like channels shared with threads
+/// outside the executor, may send their wakers to other threads and wake them up from
+/// there. Those futures have to be wrapped with `bridge`, which polls them with a waker
+/// that is safe to use from anywhere, other executors included, and wakes the executor
+/// through its reactor.
+///
+/// Futures created by Scipio itself never need the bridge.
+///
+/// Returns an error if the resources needed for the remote wake ups can't be allocated.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{bridge, LocalExecutor};
+/// use futures::channel::oneshot;
+///
+/// let (sender, receiver) = oneshot::channel();
+/// std::thread::spawn(move || sender.send(42).unwrap());
+///
+/// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+/// local_ex.run(async move {
+///     let value = bridge(receiver).await.unwrap().unwrap();
+///     assert_eq!(value, 42);
+/// });
+/// ```
+pub async fn bridge<F: Future>(future: F) -> io::Result<F::Output> {
+    let name = format!(
+        "scipio-bridge-{}-{}",
+        std::process::id(),
+        BRIDGE_ID.fetch_add(1, Ordering::Relaxed)
+    );
+    let local = sys::bind_wakeup_socket(name.as_bytes())?;
+    // Closed when the bridge is done, on the executor thread that owns it
+    let local = Async::new(unsafe { UnixDatagram::from_raw_fd(local) })?;
+    let notifier = Arc::new(RemoteNotifier {
+        name,
+        notified: AtomicBool::new(false),
+    });
+    let waker = waker(notifier.clone());
+
+    pin!(future);
+    loop {
+        let mut cx = Context::from_waker(&waker);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Ok(output);
+        }
+
+        let mut wakeup = [0u8; 1];
+        local.read_with(|io| io.recv(&mut wakeup)).await?;
+        notifier.notified.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::{mpsc, oneshot};
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    #[test]
+    fn bridge_remote_wakeup() {
+        test_executor!(async move {
+            let (sender, receiver) = oneshot::channel();
+            let remote = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                sender.send(42).unwrap();
+            });
+            assert_eq!(bridge(receiver).await.unwrap().unwrap(), 42);
+            remote.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn bridge_many_remote_wakeups() {
+        test_executor!(async move {
+            let (mut sender, mut receiver) = mpsc::channel(1);
+            let remote = std::thread::spawn(move || {
+                for i in 0..100 {
+                    futures::executor::block_on(futures::SinkExt::send(&mut sender, i)).unwrap();
+                }
+            });
+            let mut total = 0;
+            while let Some(value) = bridge(receiver.next()).await.unwrap() {
+                total += value;
+            }
+            assert_eq!(total, (0..100).sum());
+            remote.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn bridge_wakeup_from_another_executor() {
+        test_executor!(async move {
+            let (sender, receiver) = oneshot::channel();
+            // An executor has a file descriptor table of its own, unlike a plain thread
+            let remote = std::thread::spawn(move || {
+                let local_ex = crate::LocalExecutor::new(None).unwrap();
+                local_ex.run(async move {
+                    crate::Timer::new(Duration::from_millis(10)).await;
+                    sender.send(42).unwrap();
+                });
+            });
+            assert_eq!(bridge(receiver).await.unwrap().unwrap(), 42);
+            remote.join().unwrap();
+        });
+    }
+}
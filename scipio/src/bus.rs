@@ -0,0 +1,297 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::bridge::bridge;
+use crate::executor::current_monitor;
+use crate::ExecutorMonitor;
+use futures::channel::mpsc;
+use futures::future::{self, Either};
+use futures::lock::Mutex as AsyncMutex;
+use futures::{SinkExt, StreamExt};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+type TopicKey = (String, TypeId);
+
+// Every subscription is fed by a single sender, shared by all publishers. mpsc gives each
+// sender a slot of its own on top of the channel's buffer, so handing out clones would
+// make the channel unbounded.
+#[derive(Debug)]
+struct Subscriber<T> {
+    sender: Arc<AsyncMutex<mpsc::Sender<T>>>,
+    // The executor the subscription was created in, if any. Once it is gone nobody will
+    // make room in the subscription anymore.
+    owner: Option<ExecutorMonitor>,
+}
+
+impl<T> Clone for Subscriber<T> {
+    fn clone(&self) -> Self {
+        Subscriber {
+            sender: self.sender.clone(),
+            owner: self.owner.clone(),
+        }
+    }
+}
+
+impl<T> Subscriber<T> {
+    fn owner_alive(&self) -> bool {
+        self.owner.as_ref().map_or(true, |owner| owner.is_alive())
+    }
+}
+
+#[derive(Debug)]
+struct Topic<T> {
+    subscribers: Vec<Subscriber<T>>,
+}
+
+/// A message bus that executors use to exchange typed messages by topic.
+///
+/// Executors subscribe to a topic and receive, in their own [`Subscription`], every
+/// message published to it from then on. Topics are identified by their name and the type
+/// of their messages, so two topics with the same name but different types are unrelated.
+///
+/// Each subscription has a bounded capacity. Publishing to a topic waits until every
+/// subscriber has room for the message, so a slow subscriber slows down its publishers
+/// instead of making the bus buffer an unbounded amount of messages. Subscriptions
+/// created in an executor that went away, for instance because it panicked, stop
+/// receiving messages, so they don't hold their publishers back forever.
+///
+/// The bus can be cloned and sent to other threads freely: all clones refer to the
+/// same topics.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, MessageBus};
+///
+/// let bus = MessageBus::new();
+/// let mut subscription = bus.subscribe::<u64>("config", 16);
+///
+/// let publisher = bus.publisher::<u64>("config");
+/// let handle = LocalExecutor::spawn_executor("publisher", None, move || async move {
+///     publisher.publish(42).await.unwrap();
+/// })
+/// .unwrap();
+///
+/// let local_ex = LocalExecutor::new(None).unwrap();
+/// local_ex.run(async move {
+///     assert_eq!(subscription.recv().await.unwrap(), Some(42));
+/// });
+/// handle.join().unwrap();
+/// ```
+///
+/// [`Subscription`]: struct.Subscription.html
+#[derive(Debug, Clone, Default)]
+pub struct MessageBus {
+    topics: Arc<Mutex<HashMap<TopicKey, Box<dyn Any + Send>>>>,
+}
+
+impl MessageBus {
+    /// Creates a bus with no topics
+    pub fn new() -> MessageBus {
+        MessageBus::default()
+    }
+
+    /// Subscribes to messages of type `T` published to `topic`. Up to `capacity` messages
+    /// can be waiting in the subscription before publishers have to wait.
+    pub fn subscribe<T: Clone + Send + 'static>(
+        &self,
+        topic: &str,
+        capacity: usize,
+    ) -> Subscription<T> {
+        let (sender, receiver) = mpsc::channel(capacity.saturating_sub(1));
+        let mut topics = self.topics.lock().unwrap();
+        let key = (topic.to_string(), TypeId::of::<T>());
+        let entry = topics.entry(key).or_insert_with(|| {
+            Box::new(Topic::<T> {
+                subscribers: Vec::new(),
+            })
+        });
+        entry
+            .downcast_mut::<Topic<T>>()
+            .unwrap()
+            .subscribers
+            .push(Subscriber {
+                sender: Arc::new(AsyncMutex::new(sender)),
+                owner: current_monitor(),
+            });
+        Subscription { receiver }
+    }
+
+    /// Returns a handle that publishes messages of type `T` to `topic`.
+    pub fn publisher<T: Clone + Send + 'static>(&self, topic: &str) -> Publisher<T> {
+        Publisher {
+            bus: self.clone(),
+            key: (topic.to_string(), TypeId::of::<T>()),
+            _marker: PhantomData,
+        }
+    }
+
+    fn subscribers<T: Send + 'static>(&self, key: &TopicKey) -> Vec<Subscriber<T>> {
+        let mut topics = self.topics.lock().unwrap();
+        match topics.get_mut(key) {
+            Some(topic) => {
+                let topic = topic.downcast_mut::<Topic<T>>().unwrap();
+                topic.subscribers.retain(|s| {
+                    s.owner_alive()
+                        && match s.sender.try_lock() {
+                            Some(sender) => !sender.is_closed(),
+                            None => true,
+                        }
+                });
+                topic.subscribers.clone()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Publishes messages to one of the topics of a [`MessageBus`]
+///
+/// [`MessageBus`]: struct.MessageBus.html
+#[derive(Debug, Clone)]
+pub struct Publisher<T> {
+    bus: MessageBus,
+    key: TopicKey,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Clone + Send + 'static> Publisher<T> {
+    /// Sends `message` to every current subscriber of the topic, waiting for those that
+    /// have no room left for it.
+    ///
+    /// Returns how many subscribers received the message.
+    pub async fn publish(&self, message: T) -> io::Result<usize> {
+        let mut delivered = 0;
+        for subscriber in self.bus.subscribers::<T>(&self.key) {
+            let send = Box::pin(async {
+                let mut sender = subscriber.sender.lock().await;
+                sender.send(message.clone()).await.is_ok()
+            });
+            // A subscriber going away is not an error, it just stops getting messages
+            let sent = match &subscriber.owner {
+                None => bridge(send).await?,
+                Some(owner) => match bridge(future::select(send, owner.gone())).await? {
+                    Either::Left((sent, _)) => sent,
+                    Either::Right(_) => false,
+                },
+            };
+            if sent {
+                delivered += 1;
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+/// Receives the messages published to a topic of a [`MessageBus`]
+///
+/// Dropping the subscription unsubscribes from the topic.
+///
+/// [`MessageBus`]: struct.MessageBus.html
+#[derive(Debug)]
+pub struct Subscription<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T> Subscription<T> {
+    /// Waits for the next message published to the topic.
+    ///
+    /// Returns `None` once the bus and all publishers for the topic are gone and all
+    /// messages were received.
+    pub async fn recv(&mut self) -> io::Result<Option<T>> {
+        bridge(self.receiver.next()).await
+    }
+
+    /// Returns the next message published to the topic, if one is already available.
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.receiver.try_next().ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{LocalExecutor, Timer};
+
+    #[test]
+    fn bus_routes_by_topic_and_type() {
+        let bus = MessageBus::new();
+        let mut numbers = bus.subscribe::<u32>("numbers", 4);
+        let mut more_numbers = bus.subscribe::<u32>("numbers", 4);
+        let mut names = bus.subscribe::<String>("numbers", 4);
+        let mut other = bus.subscribe::<u32>("other", 4);
+
+        let publisher = bus.publisher::<u32>("numbers");
+        let handle = LocalExecutor::spawn_executor("publisher", None, move || async move {
+            for i in 0..3 {
+                assert_eq!(publisher.publish(i).await.unwrap(), 2);
+            }
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            for i in 0..3 {
+                assert_eq!(numbers.recv().await.unwrap(), Some(i));
+                assert_eq!(more_numbers.recv().await.unwrap(), Some(i));
+            }
+            assert_eq!(names.try_recv(), None);
+            assert_eq!(other.try_recv(), None);
+        });
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn bus_applies_backpressure() {
+        let bus = MessageBus::new();
+        let mut subscription = bus.subscribe::<u32>("topic", 2);
+        let dropped = bus.subscribe::<u32>("topic", 2);
+        drop(dropped);
+
+        let publisher = bus.publisher::<u32>("topic");
+        test_executor!(async move {
+            assert_eq!(publisher.publish(1).await.unwrap(), 1);
+            assert_eq!(publisher.publish(2).await.unwrap(), 1);
+
+            let remote = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                let first = subscription.try_recv();
+                (first, subscription)
+            });
+            // Blocks until the other thread makes room
+            assert_eq!(publisher.publish(3).await.unwrap(), 1);
+            let (first, mut subscription) = remote.join().unwrap();
+            assert_eq!(first, Some(1));
+            assert_eq!(subscription.try_recv(), Some(2));
+            assert_eq!(subscription.try_recv(), Some(3));
+        });
+    }
+
+    #[test]
+    fn bus_skips_subscribers_of_poisoned_executors() {
+        let bus = MessageBus::new();
+        let publisher = bus.publisher::<u32>("topic");
+
+        let remote_bus = bus.clone();
+        let handle = LocalExecutor::spawn_executor("subscriber", None, move || async move {
+            let _subscription = remote_bus.subscribe::<u32>("topic", 1);
+            Timer::new(std::time::Duration::from_millis(10)).await;
+            panic!("the subscriber went away");
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            while publisher.publish(1).await.unwrap() == 0 {
+                Timer::new(std::time::Duration::from_millis(1)).await;
+            }
+            // The subscription fills up, and only the panic lets the publisher go
+            while publisher.publish(1).await.unwrap() == 1 {}
+        });
+        assert!(handle.join().is_err());
+    }
+}
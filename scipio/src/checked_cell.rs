@@ -0,0 +1,275 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+
+#[cfg(debug_assertions)]
+mod tracking {
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::panic::Location;
+
+    thread_local! {
+        // The guards alive in this thread, by id, and where they were taken
+        static LIVE: RefCell<BTreeMap<u64, &'static Location<'static>>> = RefCell::new(BTreeMap::new());
+        static NEXT_ID: Cell<u64> = Cell::new(0);
+    }
+
+    pub(super) fn register(location: &'static Location<'static>) -> u64 {
+        let id = NEXT_ID.with(|next| {
+            let id = next.get();
+            next.set(id + 1);
+            id
+        });
+        LIVE.with(|live| live.borrow_mut().insert(id, location));
+        id
+    }
+
+    pub(super) fn unregister(id: u64) {
+        LIVE.with(|live| live.borrow_mut().remove(&id));
+    }
+
+    pub(super) fn next_id() -> u64 {
+        NEXT_ID.with(|next| next.get())
+    }
+
+    // The guards taken since `since` that are still alive
+    pub(super) fn taken_since(since: u64) -> Vec<&'static Location<'static>> {
+        LIVE.with(|live| live.borrow().range(since..).map(|(_, loc)| *loc).collect())
+    }
+}
+
+// Identifies a guard in debug builds, so the executor can tell if it is alive after the
+// poll that took it. Nothing in release builds.
+struct GuardTicket {
+    #[cfg(debug_assertions)]
+    id: u64,
+}
+
+impl GuardTicket {
+    #[allow(unused_variables)]
+    fn new(location: &'static Location<'static>) -> GuardTicket {
+        GuardTicket {
+            #[cfg(debug_assertions)]
+            id: tracking::register(location),
+        }
+    }
+}
+
+impl Drop for GuardTicket {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        tracking::unregister(self.id);
+    }
+}
+
+/// Polls a task with `poll`, and reports the guards of [`CheckedRefCell`]s that the task
+/// took during the poll and still holds after it, which means the task is holding them
+/// across an await point.
+///
+/// [`CheckedRefCell`]: struct.CheckedRefCell.html
+#[cfg(debug_assertions)]
+pub(crate) fn check_held_across_await<R>(queue: &str, poll: impl FnOnce() -> R) -> R {
+    let since = tracking::next_id();
+    let res = poll();
+    for location in tracking::taken_since(since) {
+        eprintln!(
+            "A task in task queue {} is holding a CheckedRefCell borrow taken at {} across an \
+             await point. Other tasks touching the cell will panic.",
+            queue, location
+        );
+    }
+    res
+}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub(crate) fn check_held_across_await<R>(_queue: &str, poll: impl FnOnce() -> R) -> R {
+    poll()
+}
+
+/// A [`RefCell`] that, in debug builds, detects borrows held across await points.
+///
+/// Holding a `RefCell` borrow across an `.await` is the most common way for tasks of the
+/// same executor to panic: while the task is suspended, any other task that borrows the
+/// cell finds it already borrowed. Nothing goes wrong as long as no other task happens to
+/// touch the cell at that time, so the bug tends to stay hidden until production load
+/// makes the interleaving likely.
+///
+/// In debug builds, the executor checks after polling each task whether it kept any
+/// `CheckedRefCell` borrows it took during the poll. If it did, it reports to stderr the
+/// task queue the task runs in, and the location in the source where the borrow was
+/// taken. In release builds the checks compile to nothing, and `CheckedRefCell` behaves
+/// exactly like a `RefCell`.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{CheckedRefCell, Local, LocalExecutor};
+///
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async {
+///     let cell = CheckedRefCell::new(Vec::new());
+///     cell.borrow_mut().push(1);
+///     // fine: the borrow is released before the await
+///     Local::later().await;
+///     assert_eq!(*cell.borrow(), vec![1]);
+/// });
+/// ```
+///
+/// [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
+pub struct CheckedRefCell<T> {
+    cell: RefCell<T>,
+}
+
+impl<T> CheckedRefCell<T> {
+    /// Creates a new `CheckedRefCell` holding `value`.
+    pub fn new(value: T) -> CheckedRefCell<T> {
+        CheckedRefCell {
+            cell: RefCell::new(value),
+        }
+    }
+
+    /// Immutably borrows the value, like [`RefCell::borrow`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    ///
+    /// [`RefCell::borrow`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html#method.borrow
+    #[track_caller]
+    pub fn borrow(&self) -> CheckedRef<'_, T> {
+        CheckedRef {
+            inner: self.cell.borrow(),
+            _ticket: GuardTicket::new(Location::caller()),
+        }
+    }
+
+    /// Mutably borrows the value, like [`RefCell::borrow_mut`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    ///
+    /// [`RefCell::borrow_mut`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html#method.borrow_mut
+    #[track_caller]
+    pub fn borrow_mut(&self) -> CheckedRefMut<'_, T> {
+        CheckedRefMut {
+            inner: self.cell.borrow_mut(),
+            _ticket: GuardTicket::new(Location::caller()),
+        }
+    }
+
+    /// Replaces the value, returning the old one, like [`RefCell::replace`].
+    ///
+    /// [`RefCell::replace`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html#method.replace
+    pub fn replace(&self, value: T) -> T {
+        self.cell.replace(value)
+    }
+
+    /// Returns a mutable reference to the value. No borrow is needed, as this borrows the
+    /// cell itself mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.cell.get_mut()
+    }
+
+    /// Consumes the cell, returning the value.
+    pub fn into_inner(self) -> T {
+        self.cell.into_inner()
+    }
+}
+
+impl<T: Default> Default for CheckedRefCell<T> {
+    fn default() -> Self {
+        CheckedRefCell::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CheckedRefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CheckedRefCell")
+            .field("value", &self.cell)
+            .finish()
+    }
+}
+
+/// An immutable borrow of a [`CheckedRefCell`]
+///
+/// [`CheckedRefCell`]: struct.CheckedRefCell.html
+pub struct CheckedRef<'a, T> {
+    inner: Ref<'a, T>,
+    _ticket: GuardTicket,
+}
+
+impl<T> Deref for CheckedRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CheckedRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// A mutable borrow of a [`CheckedRefCell`]
+///
+/// [`CheckedRefCell`]: struct.CheckedRefCell.html
+pub struct CheckedRefMut<'a, T> {
+    inner: RefMut<'a, T>,
+    _ticket: GuardTicket,
+}
+
+impl<T> Deref for CheckedRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for CheckedRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CheckedRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod test {
+    use super::*;
+    use crate::Local;
+
+    #[test]
+    fn borrows_held_across_awaits_are_detected() {
+        test_executor!(async move {
+            let cell = CheckedRefCell::new(0);
+            check_held_across_await("test", || {
+                let _guard = cell.borrow();
+            });
+            assert!(tracking::taken_since(0).is_empty());
+
+            let since = tracking::next_id();
+            let guard = cell.borrow_mut();
+            let location = tracking::taken_since(since);
+            assert_eq!(location.len(), 1);
+            assert_eq!(location[0].file(), file!());
+            Local::later().await;
+            drop(guard);
+            assert!(tracking::taken_since(since).is_empty());
+        });
+    }
+}
@@ -0,0 +1,152 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+//! Timers for code written against other runtimes
+//!
+//! Libraries that don't want to tie themselves to an executor usually take their timers
+//! from [`futures-timer`][docs-futures-timer] or [`async-io`][docs-async-io]. Both of them
+//! drive their timers from a hidden thread of their own, which wakes up the shard on
+//! every expiration, and neither lets another reactor take over.
+//!
+//! This module provides [`Delay`] and [`Timer`], with the same interfaces as the
+//! `futures_timer::Delay` and `async_io::Timer` types, but driven by the reactor of the
+//! shard they are polled in, like every other scipio timer. Code that runs on shards can
+//! switch to them by changing its imports:
+//!
+//! ```
+//! // use futures_timer::Delay;
+//! use scipio::compat::Delay;
+//! use scipio::LocalExecutor;
+//! use std::time::Duration;
+//!
+//! let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+//! local_ex.run(async {
+//!     Delay::new(Duration::from_millis(10)).await;
+//! });
+//! ```
+//!
+//! Libraries that are generic over the runtime tend to accept a function that returns a
+//! future to sleep on, instead of picking a timer crate. [`sleep`] is one that can be
+//! handed to them.
+//!
+//! Like the rest of scipio timers, these can't be sent to other threads, and have to be
+//! polled in the thread of a [`LocalExecutor`].
+//!
+//! [docs-futures-timer]: https://docs.rs/futures-timer
+//! [docs-async-io]: https://docs.rs/async-io
+//! [`Delay`]: struct.Delay.html
+//! [`Timer`]: struct.Timer.html
+//! [`sleep`]: fn.sleep.html
+//! [`LocalExecutor`]: ../struct.LocalExecutor.html
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// A future that completes after a given amount of time, with the interface of
+/// `futures_timer::Delay`.
+#[derive(Debug)]
+pub struct Delay {
+    timer: crate::Timer,
+}
+
+impl Delay {
+    /// Creates a future that completes `dur` from now.
+    pub fn new(dur: Duration) -> Delay {
+        Delay {
+            timer: crate::Timer::new(dur),
+        }
+    }
+
+    /// Moves the delay so it completes `dur` from now, even if it completed already.
+    pub fn reset(&mut self, dur: Duration) {
+        self.timer.reset(dur);
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.timer).poll(cx).map(|_| ())
+    }
+}
+
+/// A future that completes at a given point in time, with the interface of
+/// `async_io::Timer`.
+///
+/// Resolves to the instant the timer was set to fire at.
+#[derive(Debug)]
+pub struct Timer {
+    timer: crate::Timer,
+}
+
+impl Timer {
+    /// Creates a timer that fires `dur` from now.
+    pub fn after(dur: Duration) -> Timer {
+        Timer {
+            timer: crate::Timer::new(dur),
+        }
+    }
+
+    /// Creates a timer that fires at `when`.
+    pub fn at(when: Instant) -> Timer {
+        Timer {
+            timer: crate::Timer::at(when),
+        }
+    }
+
+    /// Moves the timer so it fires `dur` from now.
+    pub fn set_after(&mut self, dur: Duration) {
+        self.timer.reset(dur);
+    }
+
+    /// Moves the timer so it fires at `when`.
+    pub fn set_at(&mut self, when: Instant) {
+        self.timer.reset_at(when);
+    }
+}
+
+impl Future for Timer {
+    type Output = Instant;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.timer).poll(cx)
+    }
+}
+
+/// Returns a future that completes `dur` from now, for libraries that take their timers
+/// from a function.
+pub fn sleep(dur: Duration) -> Delay {
+    Delay::new(dur)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compat_timers_use_the_reactor() {
+        test_executor!(async move {
+            let start = Instant::now();
+            Delay::new(Duration::from_millis(10)).await;
+            assert!(start.elapsed() >= Duration::from_millis(10));
+
+            let mut delay = sleep(Duration::from_secs(10));
+            delay.reset(Duration::from_millis(1));
+            delay.await;
+
+            let when = Instant::now() + Duration::from_millis(5);
+            assert_eq!(Timer::at(when).await, when);
+
+            let mut timer = Timer::after(Duration::from_secs(10));
+            let when = Instant::now() + Duration::from_millis(1);
+            timer.set_at(when);
+            assert_eq!(timer.await, when);
+            assert!(start.elapsed() < Duration::from_secs(10));
+        });
+    }
+}
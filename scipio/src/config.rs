@@ -0,0 +1,411 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::parking::DEFAULT_MAX_BULK_TIMER_EXPIRATIONS;
+use crate::{sys, Latency, LocalExecutor, RingPolicy, TimerBackend};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The configuration of a task queue, as used by [`ExecutorConfig`]
+///
+/// [`ExecutorConfig`]: struct.ExecutorConfig.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaskQueueConfig {
+    /// The name of the task queue, unique within its executor
+    pub name: String,
+    /// The shares of the task queue
+    pub shares: usize,
+    /// The latency requirements of the task queue
+    pub latency: Latency,
+    /// Whether the task queue is ordered. See [`LocalExecutor::create_ordered_task_queue`]
+    ///
+    /// [`LocalExecutor::create_ordered_task_queue`]: struct.LocalExecutor.html#method.create_ordered_task_queue
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ordered: bool,
+}
+
+/// The configuration of a [`LocalExecutor`]: where it runs, its task queues and how its
+/// reactor is tuned.
+///
+/// A configuration can be taken from a running executor with [`LocalExecutor::config`]
+/// and used to create new ones with [`LocalExecutor::from_config`]. With the `serde`
+/// feature enabled it can also be serialized, so deployments can be tuned by editing a
+/// file instead of recompiling.
+///
+/// [`LocalExecutor`]: struct.LocalExecutor.html
+/// [`LocalExecutor::config`]: struct.LocalExecutor.html#method.config
+/// [`LocalExecutor::from_config`]: struct.LocalExecutor.html#method.from_config
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExecutorConfig {
+    /// The CPU the executor is bound to, if any
+    pub binding: Option<usize>,
+    /// Task queues to create, in addition to the default one
+    pub task_queues: Vec<TaskQueueConfig>,
+    /// See [`LocalExecutor::set_latency_target_mode`]
+    ///
+    /// [`LocalExecutor::set_latency_target_mode`]: struct.LocalExecutor.html#method.set_latency_target_mode
+    pub latency_target_mode: bool,
+    /// See [`LocalExecutor::set_max_bulk_timer_expirations`]
+    ///
+    /// [`LocalExecutor::set_max_bulk_timer_expirations`]: struct.LocalExecutor.html#method.set_max_bulk_timer_expirations
+    pub max_bulk_timer_expirations: usize,
+    /// See [`LocalExecutor::preallocate_timers`]
+    ///
+    /// [`LocalExecutor::preallocate_timers`]: struct.LocalExecutor.html#method.preallocate_timers
+    pub preallocated_timers: usize,
+    /// See [`LocalExecutor::set_ring_policy`]
+    ///
+    /// [`LocalExecutor::set_ring_policy`]: struct.LocalExecutor.html#method.set_ring_policy
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ring_policy: RingPolicy,
+    /// See [`LocalExecutor::set_timer_backend`]
+    ///
+    /// [`LocalExecutor::set_timer_backend`]: struct.LocalExecutor.html#method.set_timer_backend
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timer_backend: TimerBackend,
+    /// See [`LocalExecutor::set_io_progress_reserve`]
+    ///
+    /// [`LocalExecutor::set_io_progress_reserve`]: struct.LocalExecutor.html#method.set_io_progress_reserve
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub io_progress_reserve: Option<Duration>,
+    /// See [`LocalExecutor::set_slow_io_threshold`]
+    ///
+    /// [`LocalExecutor::set_slow_io_threshold`]: struct.LocalExecutor.html#method.set_slow_io_threshold
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub slow_io_threshold: Option<Duration>,
+    /// See [`LocalExecutor::set_busy_poll_threshold`]
+    ///
+    /// [`LocalExecutor::set_busy_poll_threshold`]: struct.LocalExecutor.html#method.set_busy_poll_threshold
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub busy_poll_threshold: Option<Duration>,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        ExecutorConfig {
+            binding: None,
+            task_queues: Vec::new(),
+            latency_target_mode: false,
+            max_bulk_timer_expirations: DEFAULT_MAX_BULK_TIMER_EXPIRATIONS,
+            preallocated_timers: 0,
+            ring_policy: RingPolicy::Shared,
+            timer_backend: TimerBackend::Wheel,
+            io_progress_reserve: None,
+            slow_io_threshold: None,
+            busy_poll_threshold: None,
+        }
+    }
+}
+
+impl ExecutorConfig {
+    /// Checks that the configuration can be used to create an executor
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_as(0, sys::allowed_cpus())
+    }
+
+    fn validate_as(&self, executor: usize, cpus: &[usize]) -> Result<(), ConfigError> {
+        if let Some(cpu) = self.binding {
+            if !cpus.contains(&cpu) {
+                return Err(ConfigError::InvalidCpu {
+                    executor,
+                    cpu,
+                    available: cpus.len(),
+                });
+            }
+        }
+        if self.max_bulk_timer_expirations == 0 {
+            return Err(ConfigError::NoBulkTimerExpirations { executor });
+        }
+
+        let mut names = HashMap::new();
+        for queue in &self.task_queues {
+            if queue.shares == 0 {
+                return Err(ConfigError::ZeroShares {
+                    executor,
+                    queue: queue.name.clone(),
+                });
+            }
+            if names.insert(queue.name.as_str(), ()).is_some() {
+                return Err(ConfigError::DuplicateQueue {
+                    executor,
+                    queue: queue.name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The configuration of a set of executors started together, one per shard.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoolConfig {
+    /// The name of the threads running the executors
+    pub name: String,
+    /// The configuration of each executor, indexed by shard id
+    pub executors: Vec<ExecutorConfig>,
+}
+
+impl PoolConfig {
+    /// Checks that the configuration can be used to create all executors
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.executors.is_empty() {
+            return Err(ConfigError::NoExecutors);
+        }
+
+        let cpus = sys::allowed_cpus();
+        let mut bound = HashMap::new();
+        for (id, executor) in self.executors.iter().enumerate() {
+            executor.validate_as(id, cpus)?;
+            if let Some(cpu) = executor.binding {
+                if let Some(other) = bound.insert(cpu, id) {
+                    return Err(ConfigError::SharedCpu {
+                        cpu,
+                        executors: (other, id),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the configuration and creates one executor per entry in it, each in its
+    /// own thread, like [`LocalExecutor::spawn_shards`] does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{ExecutorConfig, Latency, Local, PoolConfig, TaskQueueConfig};
+    ///
+    /// let executor = ExecutorConfig {
+    ///     task_queues: vec![TaskQueueConfig {
+    ///         name: "background".to_string(),
+    ///         shares: 100,
+    ///         latency: Latency::NotImportant,
+    ///         ordered: false,
+    ///     }],
+    ///     ..Default::default()
+    /// };
+    /// let config = PoolConfig {
+    ///     name: "shard".to_string(),
+    ///     executors: vec![executor; 2],
+    /// };
+    ///
+    /// let handles = config.spawn(|| async move {
+    ///     assert!(Local::task_queue_by_name("background").is_some());
+    /// })
+    /// .unwrap();
+    ///
+    /// for handle in handles {
+    ///     handle.join().unwrap();
+    /// }
+    /// ```
+    ///
+    /// [`LocalExecutor::spawn_shards`]: struct.LocalExecutor.html#method.spawn_shards
+    pub fn spawn<G, F, T>(&self, fut_gen: G) -> io::Result<Vec<JoinHandle<()>>>
+    where
+        G: FnOnce() -> F + Clone + std::marker::Send + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        self.validate()?;
+        let shards =
+            LocalExecutor::spawn_configured_shards(&self.name, self.executors.clone(), fut_gen)?;
+        Ok(shards.into_iter().map(|(handle, _)| handle).collect())
+    }
+}
+
+/// Why an executor configuration is not usable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The pool has no executors
+    NoExecutors,
+    /// An executor is bound to a CPU that does not exist, or that the process may not
+    /// run on
+    InvalidCpu {
+        /// The shard id of the executor
+        executor: usize,
+        /// The CPU it is bound to
+        cpu: usize,
+        /// How many CPUs the process may run on
+        available: usize,
+    },
+    /// Two executors are bound to the same CPU
+    SharedCpu {
+        /// The CPU they are bound to
+        cpu: usize,
+        /// The shard ids of the executors
+        executors: (usize, usize),
+    },
+    /// Bulk timer expirations are limited to zero per reactor loop, so they would never
+    /// fire
+    NoBulkTimerExpirations {
+        /// The shard id of the executor
+        executor: usize,
+    },
+    /// Two task queues of the same executor have the same name
+    DuplicateQueue {
+        /// The shard id of the executor
+        executor: usize,
+        /// The name of the task queues
+        queue: String,
+    },
+    /// A task queue has no shares, so it would never run
+    ZeroShares {
+        /// The shard id of the executor
+        executor: usize,
+        /// The name of the task queue
+        queue: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoExecutors => write!(f, "the pool has no executors"),
+            ConfigError::InvalidCpu {
+                executor,
+                cpu,
+                available,
+            } => write!(
+                f,
+                "executor {} is bound to CPU {}, which the process may not run on ({} CPUs allowed)",
+                executor, cpu, available
+            ),
+            ConfigError::SharedCpu { cpu, executors } => write!(
+                f,
+                "executors {} and {} are both bound to CPU {}",
+                executors.0, executors.1, cpu
+            ),
+            ConfigError::NoBulkTimerExpirations { executor } => write!(
+                f,
+                "executor {} would never expire timers of task queues that are not latency \
+                 sensitive",
+                executor
+            ),
+            ConfigError::DuplicateQueue { executor, queue } => write!(
+                f,
+                "executor {} has more than one task queue named {}",
+                executor, queue
+            ),
+            ConfigError::ZeroShares { executor, queue } => write!(
+                f,
+                "task queue {} of executor {} has no shares",
+                queue, executor
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(err: ConfigError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn queue(name: &str, shares: usize) -> TaskQueueConfig {
+        TaskQueueConfig {
+            name: name.to_string(),
+            shares,
+            latency: Latency::NotImportant,
+            ordered: false,
+        }
+    }
+
+    #[test]
+    fn config_validation() {
+        let mut config = PoolConfig {
+            name: "test".to_string(),
+            executors: Vec::new(),
+        };
+        assert_eq!(config.validate(), Err(ConfigError::NoExecutors));
+
+        config.executors.push(ExecutorConfig::default());
+        config.executors[0].task_queues = vec![queue("a", 10), queue("a", 20)];
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::DuplicateQueue {
+                executor: 0,
+                queue: "a".to_string()
+            })
+        );
+
+        config.executors[0].task_queues = vec![queue("a", 10), queue("b", 0)];
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::ZeroShares {
+                executor: 0,
+                queue: "b".to_string()
+            })
+        );
+
+        config.executors[0].task_queues.pop();
+        config.executors.push(ExecutorConfig {
+            binding: Some(libc::CPU_SETSIZE as usize),
+            ..Default::default()
+        });
+        match config.validate() {
+            Err(ConfigError::InvalidCpu { executor: 1, .. }) => {}
+            other => panic!("unexpected validation result: {:?}", other),
+        }
+
+        config.executors[0].binding = Some(0);
+        config.executors[1].binding = Some(0);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::SharedCpu {
+                cpu: 0,
+                executors: (0, 1)
+            })
+        );
+
+        config.executors[1].binding = None;
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn executor_config_roundtrip() {
+        let config = ExecutorConfig {
+            task_queues: vec![
+                queue("background", 10),
+                TaskQueueConfig {
+                    name: "latency".to_string(),
+                    shares: 1000,
+                    latency: Latency::Matters(Duration::from_millis(1)),
+                    ordered: true,
+                },
+            ],
+            latency_target_mode: true,
+            max_bulk_timer_expirations: 32,
+            preallocated_timers: 64,
+            ring_policy: RingPolicy::SeparateLatency,
+            io_progress_reserve: Some(Duration::from_millis(2)),
+            ..Default::default()
+        };
+
+        let local_ex = LocalExecutor::from_config(&config).unwrap();
+        let snapshot = local_ex.config();
+        assert_eq!(snapshot.task_queues, config.task_queues);
+        assert!(snapshot.latency_target_mode);
+        assert_eq!(snapshot.max_bulk_timer_expirations, 32);
+        assert!(snapshot.preallocated_timers >= 64);
+        assert_eq!(snapshot.ring_policy, RingPolicy::SeparateLatency);
+        assert_eq!(snapshot.io_progress_reserve, Some(Duration::from_millis(2)));
+        assert!(local_ex.task_queue_by_name("latency").is_some());
+        assert!(local_ex.task_queue_by_name("nonexistent").is_none());
+    }
+}
@@ -0,0 +1,387 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::bridge::bridge;
+use crate::host_metrics::read_file;
+use crate::sys;
+use crate::Async;
+use futures::future::poll_fn;
+use std::cell::RefCell;
+use std::error::Error as StdError;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+type Parser<T> = Box<dyn Fn(&[u8]) -> io::Result<T>>;
+
+#[derive(Debug)]
+struct Shared<T> {
+    // the version of the configuration, bumped on every change, and the configuration
+    current: Mutex<(u64, Arc<T>)>,
+    waiters: Mutex<Vec<Waker>>,
+}
+
+impl<T> Shared<T> {
+    fn publish(&self, config: T) -> Arc<T> {
+        let config = Arc::new(config);
+        {
+            let mut current = self.current.lock().unwrap();
+            current.0 += 1;
+            current.1 = config.clone();
+        }
+        for waiter in self.waiters.lock().unwrap().drain(..) {
+            waiter.wake();
+        }
+        config
+    }
+}
+
+/// A view of the configuration published by a [`ConfigWatcher`], that tells when it
+/// changes.
+///
+/// Handles can be cloned and sent to other executors, so every shard can hold one.
+///
+/// [`ConfigWatcher`]: struct.ConfigWatcher.html
+#[derive(Debug)]
+pub struct ConfigHandle<T> {
+    shared: Arc<Shared<T>>,
+    seen: u64,
+}
+
+impl<T> Clone for ConfigHandle<T> {
+    fn clone(&self) -> Self {
+        ConfigHandle {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> ConfigHandle<T> {
+    /// Returns the current configuration.
+    pub fn current(&self) -> Arc<T> {
+        self.shared.current.lock().unwrap().1.clone()
+    }
+
+    /// Waits until the configuration changes from the last one this handle saw, and
+    /// returns the new configuration. A handle starts out having seen the configuration
+    /// current when it was created or cloned.
+    ///
+    /// If the configuration changes many times before this is called, only the latest
+    /// one is returned.
+    pub async fn changed(&mut self) -> io::Result<Arc<T>> {
+        let shared = self.shared.clone();
+        let seen = self.seen;
+        let (version, config) = bridge(poll_fn(move |cx| {
+            let current = shared.current.lock().unwrap();
+            if current.0 != seen {
+                Poll::Ready((current.0, current.1.clone()))
+            } else {
+                // Registered while holding the lock, so the change can't slip between
+                // the check and the registration.
+                shared.waiters.lock().unwrap().push(cx.waker().clone());
+                Poll::Pending
+            }
+        }))
+        .await?;
+        self.seen = version;
+        Ok(config)
+    }
+}
+
+// An inotify instance
+#[derive(Debug)]
+struct Inotify(RawFd);
+
+impl Inotify {
+    fn watch_dir(dir: &Path) -> io::Result<Inotify> {
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        Ok(Inotify(sys::inotify_watch_dir(dir, mask)?))
+    }
+
+    // Reads the pending events, and returns whether any of them may be about the file
+    // called `name`. When the kernel dropped events because too many were queued, there
+    // is no telling, so it may have changed.
+    fn read_changed(&self, name: &[u8]) -> io::Result<bool> {
+        let mut buf = [0u8; 4096];
+        let len = sys::read_fd(self.0, &mut buf)?;
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut changed = false;
+        let mut offset = 0;
+        while offset + header <= len {
+            let event = unsafe {
+                std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event)
+            };
+            if event.mask & libc::IN_IGNORED != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "the configuration directory went away",
+                ));
+            }
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                changed = true;
+            }
+            let event_name = &buf[offset + header..offset + header + event.len as usize];
+            let event_name = event_name.split(|b| *b == 0).next().unwrap_or(&[]);
+            changed |= event_name == name;
+            offset += header + event.len as usize;
+        }
+        Ok(changed)
+    }
+}
+
+impl AsRawFd for Inotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Watches a configuration file, and publishes it to every shard each time it changes.
+///
+/// The watcher parses the file with a function provided by the application into a type of
+/// its choosing, and publishes the result to the [`ConfigHandle`]s that it hands out.
+/// Handles can be sent to other executors, which can then wait for the configuration to
+/// change, or just look at the current one whenever they need it.
+///
+/// Changes are detected with inotify, by watching the directory that holds the file.
+/// That catches both writes to the file and the common pattern of writing a new file
+/// and renaming it over the old one. Applications that prefer to reload on some other
+/// trigger, like a signal or a message, can call [`reload`] instead of [`run`].
+///
+/// When the file changes into something that doesn't parse, the error is reported to the
+/// handler set with [`on_reload_error`], or to stderr if there is none, and the previous
+/// configuration stays in place, so a bad edit doesn't take the service down. If the
+/// kernel drops change notifications because too many piled up, the file is reloaded
+/// anyway, as it may have changed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use scipio::{ConfigWatcher, Local, LocalExecutor};
+///
+/// let local_ex = LocalExecutor::new(None).unwrap();
+/// local_ex.run(async move {
+///     let watcher = ConfigWatcher::new("/etc/myapp/workers", |bytes| {
+///         String::from_utf8_lossy(bytes).trim().parse::<usize>()
+///     })
+///     .await
+///     .unwrap();
+///
+///     let mut config = watcher.handle();
+///     Local::local(async move { watcher.run().await }).detach();
+///     loop {
+///         let workers = config.changed().await.unwrap();
+///         println!("now using {} workers", workers);
+///     }
+/// });
+/// ```
+///
+/// [`ConfigHandle`]: struct.ConfigHandle.html
+/// [`on_reload_error`]: struct.ConfigWatcher.html#method.on_reload_error
+/// [`reload`]: struct.ConfigWatcher.html#method.reload
+/// [`run`]: struct.ConfigWatcher.html#method.run
+pub struct ConfigWatcher<T> {
+    path: PathBuf,
+    parse: Parser<T>,
+    shared: Arc<Shared<T>>,
+    inotify: Async<Inotify>,
+    // the contents last published, so rewrites of the same contents are not published
+    last: RefCell<Vec<u8>>,
+    on_error: RefCell<Option<Rc<dyn Fn(&io::Error)>>>,
+}
+
+impl<T> fmt::Debug for ConfigWatcher<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigWatcher")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl<T: Send + Sync + 'static> ConfigWatcher<T> {
+    /// Reads and parses the configuration file at `path`, and starts watching it for
+    /// changes. Fails if the file can't be read or parsed.
+    pub async fn new<P, F, E>(path: P, parse: F) -> io::Result<ConfigWatcher<T>>
+    where
+        P: AsRef<Path>,
+        F: Fn(&[u8]) -> Result<T, E> + 'static,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        // Watch before reading, so a change right after the read isn't missed
+        let inotify = Async::new(Inotify::watch_dir(dir)?)?;
+        let parse: Parser<T> = Box::new(move |bytes| {
+            parse(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        });
+
+        let contents = read_file(&path).await?;
+        let config = parse(&contents)?;
+        Ok(ConfigWatcher {
+            path,
+            parse,
+            shared: Arc::new(Shared {
+                current: Mutex::new((0, Arc::new(config))),
+                waiters: Mutex::new(Vec::new()),
+            }),
+            inotify,
+            last: RefCell::new(contents),
+            on_error: RefCell::new(None),
+        })
+    }
+
+    /// Returns a handle to the configuration, to be sent to the shards that use it.
+    pub fn handle(&self) -> ConfigHandle<T> {
+        ConfigHandle {
+            shared: self.shared.clone(),
+            seen: self.shared.current.lock().unwrap().0,
+        }
+    }
+
+    /// Reads and parses the configuration file again, and publishes it if it changed.
+    ///
+    /// Returns the current configuration. If the file can't be read or parsed, returns
+    /// the error and keeps the previous configuration.
+    pub async fn reload(&self) -> io::Result<Arc<T>> {
+        let contents = read_file(&self.path).await?;
+        if *self.last.borrow() == contents {
+            return Ok(self.shared.current.lock().unwrap().1.clone());
+        }
+        let config = (self.parse)(&contents)?;
+        self.last.replace(contents);
+        Ok(self.shared.publish(config))
+    }
+
+    /// Sets a handler to be called with the errors [`run`] runs into reloading the file,
+    /// replacing the previous one. Without one they are reported to stderr.
+    ///
+    /// [`run`]: struct.ConfigWatcher.html#method.run
+    pub fn on_reload_error<F>(&self, handler: F)
+    where
+        F: Fn(&io::Error) + 'static,
+    {
+        self.on_error.replace(Some(Rc::new(handler)));
+    }
+
+    /// Watches the file, and reloads it each time it changes. Errors reloading it are
+    /// reported to the handler set with [`on_reload_error`], and don't stop the watcher.
+    ///
+    /// Only returns if watching fails, for instance because the directory holding the
+    /// file was removed.
+    ///
+    /// [`on_reload_error`]: struct.ConfigWatcher.html#method.on_reload_error
+    pub async fn run(self) -> io::Result<()> {
+        let name = self.path.file_name().unwrap_or_else(|| OsStr::new(""));
+        loop {
+            let changed = self
+                .inotify
+                .read_with(|inotify| inotify.read_changed(name.as_bytes()))
+                .await?;
+            if !changed {
+                continue;
+            }
+            if let Err(err) = self.reload().await {
+                let handler = self.on_error.borrow().clone();
+                match handler {
+                    Some(handler) => handler(&err),
+                    None => eprintln!(
+                        "Failed to reload configuration from {}: {}",
+                        self.path.display(),
+                        err
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Local, LocalExecutor};
+    use futures::channel::oneshot;
+
+    fn parse(bytes: &[u8]) -> Result<u64, std::num::ParseIntError> {
+        String::from_utf8_lossy(bytes).trim().parse()
+    }
+
+    #[test]
+    fn config_changes_reach_other_executors() {
+        let dir = std::env::temp_dir().join("config_changes_reach_other_executors");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("config");
+        std::fs::write(&path, "1").unwrap();
+
+        test_executor!(async move {
+            let watcher = ConfigWatcher::new(&path, parse).await.unwrap();
+            let mut handle = watcher.handle();
+            assert_eq!(*handle.current(), 1);
+
+            let (done, finished) = oneshot::channel();
+            let remote = LocalExecutor::spawn_executor("config-user", None, move || async move {
+                loop {
+                    if *handle.changed().await.unwrap() == 3 {
+                        break;
+                    }
+                }
+                done.send(()).unwrap();
+            })
+            .unwrap();
+
+            Local::local(async move { watcher.run().await }).detach();
+            // a bad edit is ignored, and the next good one is published
+            std::fs::write(&path, "not a number").unwrap();
+            std::fs::write(&path, "3").unwrap();
+
+            bridge(finished).await.unwrap().unwrap();
+            remote.join().unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn reload_errors_reach_the_handler() {
+        let dir = std::env::temp_dir().join("reload_errors_reach_the_handler");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("config");
+        std::fs::write(&path, "1").unwrap();
+
+        test_executor!(async move {
+            let watcher = ConfigWatcher::new(&path, parse).await.unwrap();
+            let handle = watcher.handle();
+            let (reported, errored) = oneshot::channel();
+            let reported = RefCell::new(Some(reported));
+            watcher.on_reload_error(move |err| {
+                if let Some(reported) = reported.borrow_mut().take() {
+                    reported.send(err.kind()).unwrap();
+                }
+            });
+            Local::local(async move { watcher.run().await }).detach();
+
+            std::fs::write(&path, "not a number").unwrap();
+            assert_eq!(errored.await.unwrap(), io::ErrorKind::InvalidData);
+            // the previous configuration stays in place
+            assert_eq!(*handle.current(), 1);
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+}
@@ -0,0 +1,339 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How far ahead to look for a match before deciding there is none, like for February 30th
+const SEARCH_DAYS: i64 = 8 * 366;
+
+/// A schedule written as a cron expression, to drive a [`TimerActionSchedule`].
+///
+/// The expression has the five fields of crontab: minute, hour, day of the month, month
+/// and day of the week, in that order, separated by spaces. Each field is either `*`, a
+/// number, a range like `1-5`, or a comma separated list of those, and any of them but
+/// plain numbers can be followed by a step like `*/15`. Days of the week go from 0 for
+/// Sunday to 6, and 7 is Sunday too. As in cron, when both day fields are restricted a
+/// day matches if either of them does.
+///
+/// The shorthands `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are also
+/// understood.
+///
+/// Times are in UTC, unless the schedule is given a fixed offset from it with
+/// [`utc_offset`]. There is no support for time zones: an offset doesn't follow daylight
+/// saving time changes.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::CronSchedule;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// // every day at 03:00
+/// let schedule: CronSchedule = "0 3 * * *".parse().unwrap();
+/// let midnight = UNIX_EPOCH + Duration::from_secs(1609459200); // 2021-01-01 00:00
+/// let next = schedule.next_after(midnight).unwrap();
+/// assert_eq!(next, midnight + Duration::from_secs(3 * 3600));
+/// ```
+///
+/// [`TimerActionSchedule`]: struct.TimerActionSchedule.html
+/// [`utc_offset`]: struct.CronSchedule.html#method.utc_offset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // whether the day fields were `*`, which changes how they combine
+    any_day: bool,
+    any_weekday: bool,
+    // seconds east of UTC of the times in the expression
+    offset: i64,
+}
+
+fn invalid(expr: &str, why: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid cron expression {:?}: {}", expr, why),
+    )
+}
+
+// Parses one field into a bit mask of the values it matches
+fn parse_field(expr: &str, field: &str, min: u32, max: u32) -> io::Result<u64> {
+    let number = |s: &str| -> io::Result<u32> {
+        match s.parse::<u32>() {
+            Ok(n) if n >= min && n <= max => Ok(n),
+            _ => Err(invalid(
+                expr,
+                &format!("{} is not a number between {} and {}", s, min, max),
+            )),
+        }
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(idx) => {
+                let step = part[idx + 1..]
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| invalid(expr, &format!("bad step in {}", part)))?;
+                (&part[..idx], Some(step))
+            }
+            None => (part, None),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some(idx) = range.find('-') {
+            (number(&range[..idx])?, number(&range[idx + 1..])?)
+        } else {
+            let first = number(range)?;
+            match step {
+                Some(_) => (first, max),
+                None => (first, first),
+            }
+        };
+        if first > last {
+            return Err(invalid(expr, &format!("empty range {}", range)));
+        }
+        let mut value = first;
+        while value <= last {
+            mask |= 1 << value;
+            value += step.unwrap_or(1);
+        }
+    }
+    Ok(mask)
+}
+
+// The days since the epoch of a date, in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// The date of a number of days since the epoch, as year, month and day
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = (if days >= 0 { days } else { days - 146_096 }) / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + (if month <= 2 { 1 } else { 0 });
+    (year, month, day)
+}
+
+impl CronSchedule {
+    /// Parses a cron expression. See [`CronSchedule`] for the syntax.
+    ///
+    /// [`CronSchedule`]: struct.CronSchedule.html
+    pub fn parse(expr: &str) -> io::Result<CronSchedule> {
+        let expanded = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(invalid(expr, "expected 5 fields"));
+        }
+        let mut weekdays = parse_field(expr, fields[4], 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(CronSchedule {
+            minutes: parse_field(expr, fields[0], 0, 59)?,
+            hours: parse_field(expr, fields[1], 0, 23)?,
+            days: parse_field(expr, fields[2], 1, 31)?,
+            months: parse_field(expr, fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+            offset: 0,
+        })
+    }
+
+    /// Matches the expression against the local time of a fixed offset from UTC, in
+    /// seconds east of it, instead of against UTC. Fails if the offset is a day or more.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::CronSchedule;
+    ///
+    /// // every day at 03:00 UTC+05:30
+    /// let schedule = CronSchedule::parse("0 3 * * *")
+    ///     .unwrap()
+    ///     .utc_offset(5 * 3600 + 1800)
+    ///     .unwrap();
+    /// ```
+    pub fn utc_offset(mut self, seconds: i32) -> io::Result<CronSchedule> {
+        if seconds.abs() >= 86400 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("UTC offset of {} seconds is a day or more", seconds),
+            ));
+        }
+        self.offset = seconds as i64;
+        Ok(self)
+    }
+
+    fn day_matches(&self, day: u32, weekday: u32) -> bool {
+        let day = self.days & (1 << day) != 0;
+        let weekday = self.weekdays & (1 << weekday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// Returns the first time the schedule matches strictly after `after`, or `None` if it
+    /// never does, like for February 30th. Matches are at the start of a minute.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        // the search runs in local time, and the match goes back to UTC at the end
+        let secs = match after.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64) - 1,
+        } + self.offset;
+        let mut t = secs.div_euclid(60) * 60 + 60;
+        let give_up = t + SEARCH_DAYS * 86400;
+
+        while t < give_up {
+            let days = t.div_euclid(86400);
+            let (year, month, day) = civil_from_days(days);
+            if self.months & (1 << month) == 0 {
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                t = days_from_civil(year, month, 1) * 86400;
+                continue;
+            }
+            // the epoch was a Thursday
+            let weekday = (days + 4).rem_euclid(7) as u32;
+            if !self.day_matches(day, weekday) {
+                t = (days + 1) * 86400;
+                continue;
+            }
+            let hour = t.rem_euclid(86400) / 3600;
+            if self.hours & (1 << hour) == 0 {
+                t = days * 86400 + (hour + 1) * 3600;
+                continue;
+            }
+            let minute = t.rem_euclid(3600) / 60;
+            if self.minutes & (1 << minute) == 0 {
+                t += 60;
+                continue;
+            }
+            let t = t - self.offset;
+            return if t >= 0 {
+                Some(UNIX_EPOCH + Duration::from_secs(t as u64))
+            } else {
+                Some(UNIX_EPOCH - Duration::from_secs((-t) as u64))
+            };
+        }
+        None
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = io::Error;
+
+    fn from_str(expr: &str) -> io::Result<CronSchedule> {
+        CronSchedule::parse(expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // 2021-01-01 00:00 UTC, a Friday
+    const NEW_YEAR: u64 = 1_609_459_200;
+
+    #[test]
+    fn cron_expressions_find_their_next_match() {
+        let next = |expr: &str, after: u64| {
+            CronSchedule::parse(expr)
+                .unwrap()
+                .next_after(at(after))
+                .map(|t| t.duration_since(UNIX_EPOCH).unwrap().as_secs())
+        };
+
+        assert_eq!(next("0 3 * * *", NEW_YEAR), Some(NEW_YEAR + 3 * 3600));
+        // strictly after
+        assert_eq!(
+            next("0 3 * * *", NEW_YEAR + 3 * 3600),
+            Some(NEW_YEAR + 27 * 3600)
+        );
+        assert_eq!(
+            next("*/15 * * * *", NEW_YEAR + 7 * 60),
+            Some(NEW_YEAR + 900)
+        );
+        assert_eq!(next("30 12 * * 1", NEW_YEAR), Some(1_609_763_400));
+        assert_eq!(next("0 0 29 2 *", NEW_YEAR), Some(1_709_164_800));
+        assert_eq!(next("@monthly", NEW_YEAR), Some(NEW_YEAR + 31 * 86400));
+        // either day field matches when both are restricted: the 3rd, or a Monday
+        assert_eq!(next("0 0 3 * 1", NEW_YEAR), Some(NEW_YEAR + 2 * 86400));
+        assert_eq!(next("0 0 31 2 *", NEW_YEAR), None);
+    }
+
+    #[test]
+    fn cron_expressions_match_at_a_utc_offset() {
+        let next = |offset: i32| {
+            CronSchedule::parse("0 3 * * *")
+                .unwrap()
+                .utc_offset(offset)
+                .unwrap()
+                .next_after(at(NEW_YEAR))
+                .map(|t| t.duration_since(UNIX_EPOCH).unwrap().as_secs())
+        };
+
+        // 01:00 local on the 1st, so 03:00 local is 02:00 UTC
+        assert_eq!(next(3600), Some(NEW_YEAR + 2 * 3600));
+        // 19:00 local on the 31st, so 03:00 local is 08:00 UTC
+        assert_eq!(next(-5 * 3600), Some(NEW_YEAR + 8 * 3600));
+        assert!(CronSchedule::parse("0 3 * * *")
+            .unwrap()
+            .utc_offset(86400)
+            .is_err());
+    }
+
+    #[test]
+    fn bad_cron_expressions_are_rejected() {
+        for expr in &[
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            let err = CronSchedule::parse(expr).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", expr);
+        }
+        assert!("1,2,5-10/2 0-23/6 1 */3 1-5"
+            .parse::<CronSchedule>()
+            .is_ok());
+    }
+}
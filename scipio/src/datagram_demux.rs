@@ -0,0 +1,427 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::{Async, Local, RecvMeta, SendMeta};
+use futures::future::poll_fn;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+// Big enough for a whole GRO batch
+const RECV_BUFFER_SIZE: usize = 64 << 10;
+
+/// Returns the destination connection id of a QUIC packet, to be used as the key of a
+/// [`DatagramDemux`].
+///
+/// Packets with a long header carry the length of their connection id, but short header
+/// packets don't, so it has to be the length of the ids the endpoint hands out,
+/// `short_id_len`. Returns `None` if the packet is too short to hold the id.
+///
+/// [`DatagramDemux`]: struct.DatagramDemux.html
+pub fn quic_connection_id(packet: &[u8], short_id_len: usize) -> Option<&[u8]> {
+    let first = *packet.first()?;
+    let (start, len) = if first & 0x80 != 0 {
+        // long header: flags, 4 bytes of version, then the length of the id and the id
+        (6, *packet.get(5)? as usize)
+    } else {
+        (1, short_id_len)
+    };
+    packet.get(start..start + len)
+}
+
+/// A datagram received by a [`DatagramDemux`]
+///
+/// [`DatagramDemux`]: struct.DatagramDemux.html
+#[derive(Debug, Clone)]
+pub struct DemuxDatagram {
+    data: Vec<u8>,
+    from: SocketAddr,
+    meta: RecvMeta,
+}
+
+impl DemuxDatagram {
+    /// The contents of the datagram
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Takes the contents of the datagram
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// The address the datagram came from
+    pub fn addr(&self) -> SocketAddr {
+        self.from
+    }
+
+    /// The ancillary data received with the datagram, like its ECN codepoint
+    pub fn meta(&self) -> &RecvMeta {
+        &self.meta
+    }
+}
+
+#[derive(Debug)]
+struct Route<K> {
+    keys: RefCell<Vec<K>>,
+    inbox: RefCell<VecDeque<DemuxDatagram>>,
+    waker: RefCell<Option<Waker>>,
+    closed: Cell<bool>,
+}
+
+impl<K> Route<K> {
+    fn wake(&self) {
+        if let Some(waker) = self.waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared<K> {
+    routes: RefCell<HashMap<K, Rc<Route<K>>>>,
+    queue_limit: usize,
+    dropped: Cell<u64>,
+}
+
+/// Routes the datagrams received by a UDP socket to one task per connection, for
+/// datagram protocols like QUIC that run many connections over a single socket.
+///
+/// Every datagram is mapped to a key by a function given to [`run`], usually the
+/// connection id that [`quic_connection_id`] extracts. The first datagram with a new
+/// key spawns a task for a new connection, which receives that datagram and all the
+/// following ones with the same key through its [`DemuxConnection`]. A connection can
+/// answer to more than one key, to follow the peer when it switches connection ids.
+///
+/// Each connection queues up to `queue_limit` datagrams. Datagrams for a connection
+/// that doesn't keep up are dropped, like the kernel would drop them if the socket
+/// buffer was full, and counted in [`dropped`]. Batches of datagrams received with
+/// generic receive offload are split apart before they are routed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use scipio::{quic_connection_id, Async, DatagramDemux, LocalExecutor};
+/// use std::net::UdpSocket;
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     let socket = Async::<UdpSocket>::bind(([0, 0, 0, 0], 4433)).unwrap();
+///     let demux = DatagramDemux::new(socket, 256);
+///     demux
+///         .run(
+///             |data, _| quic_connection_id(data, 8).map(|id| id.to_vec()),
+///             |connection| async move {
+///                 while let Some(datagram) = connection.recv().await {
+///                     // feed the datagram to the QUIC state machine of the connection
+///                 }
+///             },
+///         )
+///         .await
+///         .unwrap();
+/// });
+/// ```
+///
+/// [`run`]: struct.DatagramDemux.html#method.run
+/// [`quic_connection_id`]: fn.quic_connection_id.html
+/// [`DemuxConnection`]: struct.DemuxConnection.html
+/// [`dropped`]: struct.DatagramDemux.html#method.dropped
+#[derive(Debug)]
+pub struct DatagramDemux<K> {
+    socket: Rc<Async<UdpSocket>>,
+    shared: Rc<Shared<K>>,
+}
+
+impl<K: Eq + Hash + Clone + 'static> DatagramDemux<K> {
+    /// Creates a demultiplexer for the datagrams received by `socket`, that queues up to
+    /// `queue_limit` datagrams for each connection.
+    pub fn new(socket: Async<UdpSocket>, queue_limit: usize) -> DatagramDemux<K> {
+        DatagramDemux {
+            socket: Rc::new(socket),
+            shared: Rc::new(Shared {
+                routes: RefCell::new(HashMap::new()),
+                queue_limit,
+                dropped: Cell::new(0),
+            }),
+        }
+    }
+
+    /// The socket datagrams are received from
+    pub fn socket(&self) -> &Async<UdpSocket> {
+        &self.socket
+    }
+
+    /// How many connections are open
+    pub fn connections(&self) -> usize {
+        // connections with many keys are counted once
+        let routes = self.shared.routes.borrow();
+        let unique: HashSet<*const Route<K>> = routes.values().map(|r| &**r as *const _).collect();
+        unique.len()
+    }
+
+    /// How many datagrams were dropped because their connection's queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.get()
+    }
+
+    /// Receives datagrams and routes them until the socket fails.
+    ///
+    /// `classify` maps each datagram and the address it came from to the key of its
+    /// connection, or to `None` to drop it. The first time a key is seen, `handler` is
+    /// spawned as a task to serve the new connection. Returning `None` from `classify`
+    /// for keys that can't start a connection, like the ones of QUIC short header
+    /// packets, keeps stray datagrams from spawning tasks.
+    ///
+    /// When this returns, the connections get `None` from [`recv`] once they drained
+    /// their queues.
+    ///
+    /// [`recv`]: struct.DemuxConnection.html#method.recv
+    pub async fn run<C, H, F>(&self, mut classify: C, mut handler: H) -> io::Result<()>
+    where
+        C: FnMut(&[u8], SocketAddr) -> Option<K>,
+        H: FnMut(DemuxConnection<K>) -> F,
+        F: Future<Output = ()> + 'static,
+    {
+        let mut buf = vec![0u8; RECV_BUFFER_SIZE];
+        let res = loop {
+            let (len, from, meta) = match self.socket.recv_msg(&mut buf).await {
+                Ok(received) => received,
+                Err(err) => break Err(err),
+            };
+            let segment = meta.segment_size().unwrap_or(len).max(1);
+            for data in buf[..len].chunks(segment) {
+                let key = match classify(data, from) {
+                    Some(key) => key,
+                    None => continue,
+                };
+                let datagram = DemuxDatagram {
+                    data: data.to_vec(),
+                    from,
+                    meta: meta.clone(),
+                };
+                let route = self.shared.routes.borrow().get(&key).cloned();
+                match route {
+                    Some(route) => self.deliver(&route, datagram),
+                    None => {
+                        let connection = self.open(key);
+                        connection.route.inbox.borrow_mut().push_back(datagram);
+                        Local::local(handler(connection)).detach();
+                    }
+                }
+            }
+            Local::yield_if_needed().await;
+        };
+
+        for route in self.shared.routes.borrow().values() {
+            route.closed.set(true);
+            route.wake();
+        }
+        res
+    }
+
+    fn deliver(&self, route: &Route<K>, datagram: DemuxDatagram) {
+        let mut inbox = route.inbox.borrow_mut();
+        if inbox.len() >= self.shared.queue_limit {
+            self.shared.dropped.set(self.shared.dropped.get() + 1);
+            return;
+        }
+        inbox.push_back(datagram);
+        drop(inbox);
+        route.wake();
+    }
+
+    fn open(&self, key: K) -> DemuxConnection<K> {
+        let route = Rc::new(Route {
+            keys: RefCell::new(vec![key.clone()]),
+            inbox: RefCell::new(VecDeque::new()),
+            waker: RefCell::new(None),
+            closed: Cell::new(false),
+        });
+        self.shared.routes.borrow_mut().insert(key, route.clone());
+        DemuxConnection {
+            route,
+            shared: self.shared.clone(),
+            socket: self.socket.clone(),
+        }
+    }
+}
+
+/// A connection of a [`DatagramDemux`], that receives the datagrams routed to it.
+///
+/// Dropping it closes the connection: its keys are forgotten, and a new datagram with
+/// one of them starts a new connection.
+///
+/// [`DatagramDemux`]: struct.DatagramDemux.html
+pub struct DemuxConnection<K: Eq + Hash> {
+    route: Rc<Route<K>>,
+    shared: Rc<Shared<K>>,
+    socket: Rc<Async<UdpSocket>>,
+}
+
+impl<K: Eq + Hash + fmt::Debug> fmt::Debug for DemuxConnection<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DemuxConnection")
+            .field("keys", &self.route.keys.borrow())
+            .finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone> DemuxConnection<K> {
+    /// The keys the connection answers to, starting with the one that opened it
+    pub fn keys(&self) -> Vec<K> {
+        self.route.keys.borrow().clone()
+    }
+
+    /// Routes the datagrams with `key` to this connection too, like when the peer is
+    /// given a new connection id. Replaces the connection the key was routed to, if any.
+    pub fn add_key(&self, key: K) {
+        let previous = self
+            .shared
+            .routes
+            .borrow_mut()
+            .insert(key.clone(), self.route.clone());
+        if let Some(previous) = previous {
+            previous.keys.borrow_mut().retain(|k| *k != key);
+        }
+        self.route.keys.borrow_mut().push(key);
+    }
+
+    /// Stops routing the datagrams with `key` to this connection, like when the peer
+    /// retires a connection id.
+    pub fn remove_key(&self, key: &K) {
+        let mut keys = self.route.keys.borrow_mut();
+        if let Some(idx) = keys.iter().position(|k| k == key) {
+            keys.remove(idx);
+            self.shared.routes.borrow_mut().remove(key);
+        }
+    }
+
+    /// Takes the next datagram routed to the connection, if one is queued.
+    pub fn try_recv(&self) -> Option<DemuxDatagram> {
+        self.route.inbox.borrow_mut().pop_front()
+    }
+
+    /// Waits for the next datagram routed to the connection. Returns `None` once the
+    /// [`DatagramDemux`] stopped running and the queue is empty.
+    ///
+    /// [`DatagramDemux`]: struct.DatagramDemux.html
+    pub async fn recv(&self) -> Option<DemuxDatagram> {
+        poll_fn(|cx| {
+            if let Some(datagram) = self.try_recv() {
+                return Poll::Ready(Some(datagram));
+            }
+            if self.route.closed.get() {
+                return Poll::Ready(None);
+            }
+            *self.route.waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Sends a datagram through the shared socket, with the ancillary data in `meta`,
+    /// like ECN codepoints or a segment size to send a batch of datagrams at once.
+    pub async fn send_msg(
+        &self,
+        buf: &[u8],
+        addr: SocketAddr,
+        meta: &SendMeta,
+    ) -> io::Result<usize> {
+        self.socket.send_msg(buf, addr, meta).await
+    }
+}
+
+impl<K: Eq + Hash> Drop for DemuxConnection<K> {
+    fn drop(&mut self) {
+        let mut routes = self.shared.routes.borrow_mut();
+        for key in self.route.keys.borrow().iter() {
+            if routes
+                .get(key)
+                .filter(|r| Rc::ptr_eq(r, &self.route))
+                .is_some()
+            {
+                routes.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Timer;
+    use std::time::Duration;
+
+    #[test]
+    fn quic_connection_ids_are_found() {
+        let long = [0xc0, 0, 0, 0, 1, 4, 9, 8, 7, 6, 0xff];
+        assert_eq!(quic_connection_id(&long, 8), Some(&[9, 8, 7, 6][..]));
+        let short = [0x40, 1, 2, 3, 0xff];
+        assert_eq!(quic_connection_id(&short, 3), Some(&[1, 2, 3][..]));
+        assert_eq!(quic_connection_id(&short, 8), None);
+        assert_eq!(quic_connection_id(&[], 8), None);
+    }
+
+    #[test]
+    fn datagrams_are_routed_to_their_connection() {
+        test_executor!(async move {
+            let socket = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = socket.get_ref().local_addr().unwrap();
+            let demux = Rc::new(DatagramDemux::new(socket, 2));
+            let received = Rc::new(RefCell::new(Vec::new()));
+
+            let runner = demux.clone();
+            let seen = received.clone();
+            Local::local(async move {
+                runner
+                    .run(
+                        |data, _| data.first().cloned(),
+                        move |connection| {
+                            let seen = seen.clone();
+                            async move {
+                                // b rotates to a new key, c
+                                if connection.keys() == vec![b'b'] {
+                                    connection.add_key(b'c');
+                                }
+                                while let Some(datagram) = connection.recv().await {
+                                    let key = connection.keys()[0];
+                                    seen.borrow_mut().push((key, datagram.into_data()));
+                                }
+                            }
+                        },
+                    )
+                    .await
+                    .unwrap();
+            })
+            .detach();
+
+            let sender = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            for msg in &[&b"a1"[..], b"b1", b"a2", b"c1"] {
+                sender.send_to(msg, addr).await.unwrap();
+                Timer::new(Duration::from_millis(5)).await;
+            }
+
+            assert_eq!(demux.connections(), 2);
+            let mut received = received.borrow().clone();
+            received.sort();
+            assert_eq!(
+                received,
+                vec![
+                    (b'a', b"a1".to_vec()),
+                    (b'a', b"a2".to_vec()),
+                    (b'b', b"b1".to_vec()),
+                    (b'b', b"c1".to_vec()),
+                ]
+            );
+        });
+    }
+}
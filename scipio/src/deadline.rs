@@ -0,0 +1,141 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::error::DeadlineExceeded;
+use crate::Timer;
+use std::cell::Cell;
+use std::cmp;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+thread_local!(static CURRENT_DEADLINE: Cell<Option<Instant>> = Cell::new(None));
+
+/// The deadline of the future being polled, if it runs within a [`WithDeadline`].
+///
+/// [`WithDeadline`]: struct.WithDeadline.html
+pub(crate) fn current() -> Option<Instant> {
+    CURRENT_DEADLINE.with(|deadline| deadline.get())
+}
+
+// Restores the enclosing deadline when the inner future is done being polled, even if
+// it panics
+struct Restore(Option<Instant>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT_DEADLINE.with(|deadline| deadline.set(self.0));
+    }
+}
+
+/// A future that runs another one under a deadline, created by [`Local::with_deadline`]
+/// and [`Local::with_timeout`].
+///
+/// Resolves to the output of the inner future, or to a [`DeadlineExceeded`] error if the
+/// deadline passes first, in which case the inner future is dropped.
+///
+/// [`Local::with_deadline`]: type.Local.html#method.with_deadline
+/// [`Local::with_timeout`]: type.Local.html#method.with_timeout
+/// [`DeadlineExceeded`]: struct.DeadlineExceeded.html
+pub struct WithDeadline<F: Future> {
+    future: Pin<Box<F>>,
+    deadline: Instant,
+    timer: Timer,
+}
+
+impl<F: Future> WithDeadline<F> {
+    pub(crate) fn new(deadline: Instant, future: F) -> WithDeadline<F> {
+        // A nested deadline can only make the enclosing one tighter
+        let deadline = match current() {
+            Some(outer) => cmp::min(outer, deadline),
+            None => deadline,
+        };
+        WithDeadline {
+            future: Box::pin(future),
+            deadline,
+            timer: Timer::at(deadline),
+        }
+    }
+
+    /// The deadline the inner future runs under, which is the earlier of its own and the
+    /// one of the enclosing [`WithDeadline`], if any
+    ///
+    /// [`WithDeadline`]: struct.WithDeadline.html
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl<F: Future> fmt::Debug for WithDeadline<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithDeadline")
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+impl<F: Future> Future for WithDeadline<F> {
+    type Output = Result<F::Output, DeadlineExceeded>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let deadline = self.deadline;
+        let enclosing = CURRENT_DEADLINE.with(|current| current.replace(Some(deadline)));
+        let restore = Restore(enclosing);
+        let res = self.future.as_mut().poll(cx);
+        drop(restore);
+
+        if let Poll::Ready(output) = res {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut self.timer).poll(cx) {
+            Poll::Ready(_) => Poll::Ready(Err(DeadlineExceeded { deadline })),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Local, Timer};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn deadlines_nest_and_expire() {
+        test_executor!(async move {
+            assert!(Local::deadline().is_none());
+
+            let outer = Instant::now() + Duration::from_millis(50);
+            let res = Local::with_deadline(outer, async move {
+                assert_eq!(Local::deadline(), Some(outer));
+
+                // an inner operation can shorten the budget, and the enclosing one is
+                // back after it
+                let tighter = Local::with_timeout(Duration::from_millis(1), async move {
+                    assert!(Local::deadline().unwrap() < outer);
+                    7
+                })
+                .await;
+                assert_eq!(tighter.unwrap(), 7);
+                assert_eq!(Local::deadline(), Some(outer));
+
+                // but it can't extend it
+                let inner = Local::with_timeout(Duration::from_secs(10), async move {
+                    assert_eq!(Local::deadline(), Some(outer));
+                    assert!(Local::time_left().unwrap() <= Duration::from_millis(50));
+                    Timer::new(Duration::from_secs(10)).await;
+                })
+                .await;
+                assert_eq!(inner.unwrap_err().deadline(), outer);
+                assert_eq!(Local::time_left(), Some(Duration::from_secs(0)));
+            })
+            .await;
+            assert!(res.is_ok());
+            assert!(Instant::now() >= outer);
+            assert!(Local::deadline().is_none());
+        });
+    }
+}
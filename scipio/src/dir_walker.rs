@@ -0,0 +1,449 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::error::Error;
+use crate::parking::Reactor;
+use crate::Result;
+use futures::future::{FutureExt, LocalBoxFuture};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{FileType, ReadDir};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+// How many entries a directory read hands over at a time. Directories are read in
+// batches so a huge directory neither holds the walk up nor piles up in memory.
+const BATCH_SIZE: usize = 128;
+
+fn walk_error(inner: io::Error, op: &'static str, path: &Path) -> Error {
+    Error {
+        inner,
+        op,
+        path: Some(path.to_path_buf()),
+        fd: None,
+    }
+}
+
+/// What a [`DirWalker`] does with the symbolic links it comes across
+///
+/// [`DirWalker`]: struct.DirWalker.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Symbolic links are returned as entries of their own, and never followed. This is
+    /// the default.
+    Report,
+    /// Symbolic links are neither returned nor followed
+    Skip,
+    /// Symbolic links are followed, and returned as what they point to. Directories
+    /// that were already visited are not read again, so links pointing back up the tree
+    /// don't make the walk go on forever.
+    Follow,
+}
+
+impl Default for SymlinkPolicy {
+    fn default() -> Self {
+        SymlinkPolicy::Report
+    }
+}
+
+/// An entry found by a [`DirWalk`]
+///
+/// [`DirWalk`]: struct.DirWalk.html
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    path: PathBuf,
+    file_type: FileType,
+    depth: usize,
+}
+
+impl WalkEntry {
+    /// The path of this entry, which starts with the root of the walk
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Consumes the entry, returning its path
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// The type of this entry. When following symbolic links, this is the type of what
+    /// the link points to.
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// How deep in the tree this entry is: the entries of the root have depth 1
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Walks a directory tree recursively, producing a [`Stream`] of its entries.
+///
+/// Up to [`max_concurrency`] directories are read at the same time, so the walk
+/// neither fans out without bounds nor keeps more than that many directories open.
+/// Directories are read in batches, by the helper threads the executor runs blocking
+/// system calls in, and so are the symbolic links followed: there is no asynchronous
+/// way to do either, and traversing millions of files must not block the executor.
+/// Entries come out in no particular order.
+///
+/// Errors opening or reading a directory are returned in the stream, and the walk goes
+/// on with the rest of the tree.
+///
+/// # Examples
+///
+/// ```
+/// use futures::StreamExt;
+/// use scipio::{DirWalker, LocalExecutor, SymlinkPolicy};
+///
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async move {
+///     let mut walk = DirWalker::new(std::env::temp_dir())
+///         .max_concurrency(8)
+///         .max_depth(2)
+///         .symlinks(SymlinkPolicy::Skip)
+///         .walk();
+///     while let Some(entry) = walk.next().await {
+///         if let Ok(entry) = entry {
+///             println!("{}", entry.path().display());
+///         }
+///     }
+/// });
+/// ```
+///
+/// [`Stream`]: https://docs.rs/futures/0.3/futures/stream/trait.Stream.html
+/// [`max_concurrency`]: struct.DirWalker.html#method.max_concurrency
+#[derive(Debug, Clone)]
+pub struct DirWalker {
+    root: PathBuf,
+    max_concurrency: usize,
+    max_depth: usize,
+    symlinks: SymlinkPolicy,
+}
+
+impl DirWalker {
+    /// Prepares a walk of the tree under `root`. The root itself is not returned.
+    pub fn new<P: AsRef<Path>>(root: P) -> DirWalker {
+        DirWalker {
+            root: root.as_ref().to_path_buf(),
+            max_concurrency: 4,
+            max_depth: usize::MAX,
+            symlinks: SymlinkPolicy::default(),
+        }
+    }
+
+    /// Sets how many directories are read at the same time. Defaults to 4.
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = std::cmp::max(max_concurrency, 1);
+        self
+    }
+
+    /// Sets how deep the walk goes: entries deeper than `max_depth` are not returned,
+    /// and the directories holding them are not read. Defaults to no limit.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets what the walk does with symbolic links. See [`SymlinkPolicy`]
+    ///
+    /// [`SymlinkPolicy`]: enum.SymlinkPolicy.html
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Starts the walk
+    pub fn walk(self) -> DirWalk {
+        let mut walk = DirWalk {
+            pending: Vec::new(),
+            reads: FuturesUnordered::new(),
+            ready: VecDeque::new(),
+            visited: HashSet::new(),
+            config: self,
+        };
+        let root = walk.config.root.clone();
+        if walk.config.max_depth > 0 {
+            walk.pending.push((root, 0));
+        }
+        walk
+    }
+}
+
+// An entry of a directory: its path and type, and when following symbolic links, the
+// device and inode of the directory it is or points to.
+type Found = (PathBuf, FileType, Option<(u64, u64)>);
+
+// What a directory read hands over: a batch of entries, and what is left of the
+// directory, if anything. When following symbolic links, the root is stat'ed as well, so
+// links back to it are told apart.
+struct Batch {
+    dir: PathBuf,
+    depth: usize,
+    dir_id: Option<(u64, u64)>,
+    entries: Vec<Result<Found>>,
+    rest: Option<ReadDir>,
+}
+
+// Reads a batch of entries from `dir`. Runs in a blocking helper.
+fn fill_batch(dir: PathBuf, depth: usize, read_dir: Option<ReadDir>, follow: bool) -> Batch {
+    let mut batch = Batch {
+        dir,
+        depth,
+        dir_id: None,
+        entries: Vec::new(),
+        rest: None,
+    };
+    if follow && depth == 0 && read_dir.is_none() {
+        batch.dir_id = std::fs::metadata(&batch.dir)
+            .ok()
+            .filter(|meta| meta.is_dir())
+            .map(|meta| (meta.dev(), meta.ino()));
+    }
+    let mut read_dir = match read_dir {
+        Some(read_dir) => read_dir,
+        None => match std::fs::read_dir(&batch.dir) {
+            Ok(read_dir) => read_dir,
+            Err(inner) => {
+                let err = walk_error(inner, "Opening directory", &batch.dir);
+                batch.entries.push(Err(err));
+                return batch;
+            }
+        },
+    };
+
+    while batch.entries.len() < BATCH_SIZE {
+        let entry = match read_dir.next() {
+            Some(entry) => entry,
+            None => return batch,
+        };
+        let entry = entry
+            .and_then(|entry| Ok((entry.path(), entry.file_type()?)))
+            .map_err(|inner| walk_error(inner, "Reading directory", &batch.dir));
+        let found = entry.and_then(|(path, file_type)| {
+            // Only walks that follow links can come across the same directory twice,
+            // so only they pay for a stat of every directory.
+            if !follow || !(file_type.is_dir() || file_type.is_symlink()) {
+                return Ok((path, file_type, None));
+            }
+            match std::fs::metadata(&path) {
+                Ok(meta) => {
+                    let id = Some((meta.dev(), meta.ino())).filter(|_| meta.is_dir());
+                    Ok((path, meta.file_type(), id))
+                }
+                Err(inner) => Err(walk_error(inner, "Following symbolic link", &path)),
+            }
+        });
+        batch.entries.push(found);
+    }
+    batch.rest = Some(read_dir);
+    batch
+}
+
+fn read_batch(
+    dir: PathBuf,
+    depth: usize,
+    read_dir: Option<ReadDir>,
+    follow: bool,
+) -> LocalBoxFuture<'static, Batch> {
+    async move {
+        let filled = Arc::new(Mutex::new(None));
+        let slot = filled.clone();
+        let job_dir = dir.clone();
+        let source = Reactor::get().run_blocking(-1, "getdents", move || {
+            *slot.lock().unwrap() = Some(fill_batch(job_dir, depth, read_dir, follow));
+            Ok(0)
+        });
+        match source.collect_rw().await {
+            Ok(_) => filled.lock().unwrap().take().unwrap(),
+            // No helper could take the job, and the rest of the directory went with it
+            Err(inner) => {
+                let err = walk_error(inner, "Reading directory", &dir);
+                Batch {
+                    dir,
+                    depth,
+                    dir_id: None,
+                    entries: vec![Err(err)],
+                    rest: None,
+                }
+            }
+        }
+    }
+    .boxed_local()
+}
+
+/// A walk over a directory tree, created by [`DirWalker::walk`]
+///
+/// [`DirWalker::walk`]: struct.DirWalker.html#method.walk
+pub struct DirWalk {
+    config: DirWalker,
+    // Directories waiting to be read. Reading the deepest ones first keeps this short.
+    pending: Vec<(PathBuf, usize)>,
+    reads: FuturesUnordered<LocalBoxFuture<'static, Batch>>,
+    ready: VecDeque<Result<WalkEntry>>,
+    // Device and inode of the directories read, when following symbolic links
+    visited: HashSet<(u64, u64)>,
+}
+
+impl std::fmt::Debug for DirWalk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirWalk")
+            .field("config", &self.config)
+            .field("pending", &self.pending.len())
+            .field("reads", &self.reads.len())
+            .finish()
+    }
+}
+
+impl DirWalk {
+    fn follows_links(&self) -> bool {
+        self.config.symlinks == SymlinkPolicy::Follow
+    }
+
+    fn accept(&mut self, batch: Batch) {
+        let depth = batch.depth + 1;
+        if let Some(id) = batch.dir_id {
+            self.visited.insert(id);
+        }
+        if let Some(rest) = batch.rest {
+            // The rest of the directory keeps its slot, so at most max_concurrency
+            // directories are ever open.
+            let follow = self.follows_links();
+            self.reads
+                .push(read_batch(batch.dir, batch.depth, Some(rest), follow));
+        }
+
+        for entry in batch.entries {
+            let (path, file_type, id) = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    self.ready.push_back(Err(err));
+                    continue;
+                }
+            };
+
+            let descend = match self.config.symlinks {
+                SymlinkPolicy::Skip if file_type.is_symlink() => continue,
+                SymlinkPolicy::Report | SymlinkPolicy::Skip => file_type.is_dir(),
+                SymlinkPolicy::Follow => id.map_or(false, |id| self.visited.insert(id)),
+            };
+
+            if descend && depth < self.config.max_depth {
+                self.pending.push((path.clone(), depth));
+            }
+            self.ready.push_back(Ok(WalkEntry {
+                path,
+                file_type,
+                depth,
+            }));
+        }
+    }
+}
+
+impl Stream for DirWalk {
+    type Item = Result<WalkEntry>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(entry) = this.ready.pop_front() {
+                return Poll::Ready(Some(entry));
+            }
+            while this.reads.len() < this.config.max_concurrency {
+                match this.pending.pop() {
+                    Some((dir, depth)) => {
+                        let follow = this.follows_links();
+                        this.reads.push(read_batch(dir, depth, None, follow));
+                    }
+                    None => break,
+                }
+            }
+            match this.reads.poll_next_unpin(cx) {
+                Poll::Ready(Some(batch)) => this.accept(batch),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::create_dir_all(root.join("c")).unwrap();
+        for file in &["f", "a/f", "a/b/f", "c/f"] {
+            std::fs::write(root.join(file), b"x").unwrap();
+        }
+        // points back up the tree
+        std::os::unix::fs::symlink(&root, root.join("a/b/up")).unwrap();
+        root
+    }
+
+    async fn walk_paths(walker: DirWalker, root: &Path) -> Vec<String> {
+        let mut paths: Vec<String> = walker
+            .walk()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.path().strip_prefix(root).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect()
+            .await;
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn walk_whole_tree() {
+        test_executor!(async move {
+            let root = make_tree("walk_whole_tree");
+            let paths = walk_paths(DirWalker::new(&root).max_concurrency(1), &root).await;
+            assert_eq!(
+                paths,
+                vec!["a", "a/b", "a/b/f", "a/b/up", "a/f", "c", "c/f", "f"]
+            );
+
+            let paths =
+                walk_paths(DirWalker::new(&root).symlinks(SymlinkPolicy::Skip), &root).await;
+            assert_eq!(paths, vec!["a", "a/b", "a/b/f", "a/f", "c", "c/f", "f"]);
+
+            let paths = walk_paths(DirWalker::new(&root).max_depth(1), &root).await;
+            assert_eq!(paths, vec!["a", "c", "f"]);
+            std::fs::remove_dir_all(&root).unwrap();
+        });
+    }
+
+    #[test]
+    fn walk_follows_symlinks_without_looping() {
+        test_executor!(async move {
+            let root = make_tree("walk_follows_symlinks_without_looping");
+            let walker = DirWalker::new(&root).symlinks(SymlinkPolicy::Follow);
+            let mut walk = walker.walk();
+            let mut up = None;
+            let mut count = 0;
+            while let Some(entry) = walk.next().await {
+                let entry = entry.unwrap();
+                if entry.path().ends_with("up") {
+                    up = Some(entry.file_type());
+                }
+                count += 1;
+            }
+            // the link is returned as the directory it points to, which isn't read again
+            assert!(up.unwrap().is_dir());
+            assert_eq!(count, 8);
+            std::fs::remove_dir_all(&root).unwrap();
+        });
+    }
+}
@@ -3,15 +3,19 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
+use crate::dma_pool::DmaLease;
 use crate::error::Error;
+use crate::file_id::FileId;
 use crate::parking::Reactor;
 use crate::sys;
 use crate::sys::{DmaBuffer, PollableStatus, SourceType};
-use crate::Result;
+use crate::{Local, Result};
+use futures::future::join_all;
 use std::hash::{Hash, Hasher};
 use std::io;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 macro_rules! enhanced_try {
     ($expr:expr, $op:expr, $path:expr, $fd:expr) => {{
@@ -62,6 +66,38 @@ macro_rules! bad_buffer {
     }};
 }
 
+macro_rules! incomplete_io {
+    ($obj:expr, $kind:expr, $msg:expr, $op:expr) => {{
+        Error {
+            inner: io::Error::new($kind, $msg),
+            op: $op,
+            path: $obj.path.clone(),
+            fd: Some($obj.as_raw_fd()),
+        }
+    }};
+}
+
+// Adds up the sizes of the sub-operations of a split I/O from its start, in the order of
+// `chunks` rather than the one they completed in, stopping at the first one that failed
+// or was short, much like a single short I/O. Errors are only reported if nothing was
+// transferred. Sub-operations past that point may have transferred data too.
+fn reassemble(results: Vec<io::Result<usize>>, chunks: &[(usize, usize)]) -> io::Result<usize> {
+    let mut total = 0;
+    for (res, (_, len)) in results.into_iter().zip(chunks) {
+        match res {
+            Ok(size) => {
+                total += size;
+                if size < *len {
+                    break;
+                }
+            }
+            Err(err) if total == 0 => return Err(err),
+            Err(_) => break,
+        }
+    }
+    Ok(total)
+}
+
 fn align_up(v: u64, align: u64) -> u64 {
     (v + align - 1) & !(align - 1)
 }
@@ -75,6 +111,7 @@ fn align_down(v: u64, align: u64) -> u64 {
 pub struct Directory {
     file: std::fs::File,
     path: Option<PathBuf>,
+    id: FileId,
 }
 
 impl AsRawFd for Directory {
@@ -97,6 +134,7 @@ impl Directory {
         Ok(Directory {
             file: unsafe { std::fs::File::from_raw_fd(fd as _) },
             path: self.path.clone(),
+            id: Reactor::get().register_file(fd as _, self.path.as_deref()),
         })
     }
 
@@ -112,6 +150,7 @@ impl Directory {
         )?;
         Ok(Directory {
             file: unsafe { std::fs::File::from_raw_fd(fd as _) },
+            id: Reactor::get().register_file(fd as _, Some(&path)),
             path: Some(path),
         })
     }
@@ -129,6 +168,7 @@ impl Directory {
         )?;
         Ok(Directory {
             file: unsafe { std::fs::File::from_raw_fd(fd as _) },
+            id: Reactor::get().register_file(fd as _, Some(&path)),
             path: Some(path),
         })
     }
@@ -159,22 +199,125 @@ impl Directory {
         enhanced_try!(std::fs::read_dir(path), "Reading a directory", self)
     }
 
+    /// Returns the id of this directory in the reactor. See [`FileId`]
+    ///
+    /// [`FileId`]: struct.FileId.html
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
     /// Issues fdatasync into the underlying file.
     pub async fn sync(&self) -> io::Result<()> {
-        let source = Reactor::get().fdatasync(self.as_raw_fd());
+        let fd = Reactor::get().check_file(self.id)?;
+        let source = Reactor::get().fdatasync(fd);
         source.collect_rw().await?;
         Ok(())
     }
 
+    /// Removes many files, given by their names relative to this directory, and then
+    /// syncs the directory so the removals are durable.
+    ///
+    /// Files are removed through the rings in batches of at most `batch_size` files in
+    /// flight, and the current task yields between batches. Removing very large sets of
+    /// files (like an old snapshot) will then not monopolize the executor. Files that no
+    /// longer exist are skipped.
+    ///
+    /// Returns the number of files that were actually removed.
+    pub async fn remove_files<P: AsRef<Path>>(
+        &self,
+        names: &[P],
+        batch_size: usize,
+    ) -> Result<usize> {
+        let fd = enhanced_try!(
+            Reactor::get().check_file(self.id),
+            "Removing file in batch",
+            self
+        )?;
+        let mut removed = 0;
+        for batch in names.chunks(std::cmp::max(batch_size, 1)) {
+            let sources: Vec<_> = batch
+                .iter()
+                .map(|name| Reactor::get().unlink_at(fd, name.as_ref()))
+                .collect();
+            let results = join_all(sources.iter().map(|source| source.collect_rw())).await;
+            for (res, name) in results.into_iter().zip(batch) {
+                let name = name.as_ref();
+                match res {
+                    Ok(_) => removed += 1,
+                    Err(x) if x.kind() == io::ErrorKind::NotFound => {}
+                    Err(inner) => {
+                        return Err(Error {
+                            inner,
+                            op: "Removing file in batch",
+                            path: self.path.as_ref().and_then(|x| Some(x.join(name))),
+                            fd: Some(fd),
+                        });
+                    }
+                }
+            }
+            Local::later().await;
+        }
+        enhanced_try!(self.sync().await, "Syncing directory", self)?;
+        Ok(removed)
+    }
+
     /// Closes this DMA file.
     pub async fn close(&mut self) -> io::Result<()> {
-        let source = Reactor::get().close(self.as_raw_fd());
+        let fd = Reactor::get().check_file(self.id)?;
+        let source = Reactor::get().close(fd);
         source.collect_rw().await?;
+        Reactor::get().unregister_file(self.id);
         self.file = unsafe { std::fs::File::from_raw_fd(-1) };
         Ok(())
     }
 }
 
+impl Drop for Directory {
+    fn drop(&mut self) {
+        if self.as_raw_fd() != -1 {
+            Reactor::unregister_dropped_file(self.id);
+        }
+    }
+}
+
+/// How the device holding a [`DmaFile`] is capable of making the writes issued with
+/// [`write_dma_dsync`] durable
+///
+/// This is what the device advertises, read from sysfs when the file is opened. It says
+/// nothing of how a given write was handled: the kernel doesn't report that, and the
+/// device configuration could have changed since.
+///
+/// [`DmaFile`]: struct.DmaFile.html
+/// [`write_dma_dsync`]: struct.DmaFile.html#method.write_dma_dsync
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WriteBarrier {
+    /// The device has no volatile write cache, so every write is durable once it
+    /// completes and dsync writes cost nothing extra
+    WriteThrough,
+    /// The device honors Force Unit Access: dsync writes bypass its write cache without
+    /// flushing the rest of it
+    ForceUnitAccess,
+    /// The device has a volatile write cache and no FUA support, so the kernel follows
+    /// dsync writes with a flush of the whole cache. They are as expensive as a write
+    /// followed by [`fdatasync`], but save a round trip
+    ///
+    /// [`fdatasync`]: struct.DmaFile.html#method.fdatasync
+    Flush,
+    /// The file is not backed by a block device whose cache could be inspected
+    Unknown,
+}
+
+impl WriteBarrier {
+    fn of(queue: &sys::DeviceQueue) -> WriteBarrier {
+        match queue.write_cache {
+            Some((false, _)) => WriteBarrier::WriteThrough,
+            Some((true, true)) => WriteBarrier::ForceUnitAccess,
+            Some((true, false)) => WriteBarrier::Flush,
+            None => WriteBarrier::Unknown,
+        }
+    }
+}
+
 #[derive(Debug)]
 /// Constructs a file that can issue DMA operations.
 /// All access uses Direct I/O, and all operations including
@@ -186,7 +329,10 @@ pub struct DmaFile {
     // facilitate error displaying.
     path: Option<PathBuf>,
     o_direct_alignment: u64,
+    max_io_size: usize,
+    write_barrier: WriteBarrier,
     pollable: PollableStatus,
+    id: FileId,
 }
 
 impl DmaFile {
@@ -210,7 +356,7 @@ impl AsRawFd for DmaFile {
 
 impl PartialEq for DmaFile {
     fn eq(&self, other: &Self) -> bool {
-        self.as_raw_fd() == other.as_raw_fd()
+        self.id == other.id
     }
 }
 
@@ -218,7 +364,7 @@ impl Eq for DmaFile {}
 
 impl Hash for DmaFile {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.as_raw_fd().hash(state);
+        self.id.hash(state);
     }
 }
 
@@ -228,7 +374,10 @@ impl Default for DmaFile {
             file: unsafe { std::fs::File::from_raw_fd(-1) },
             path: None,
             o_direct_alignment: 4096,
+            max_io_size: usize::MAX,
+            write_barrier: WriteBarrier::Unknown,
             pollable: PollableStatus::Pollable,
+            id: FileId::invalid(),
         }
     }
 }
@@ -242,6 +391,7 @@ I will close it and turn a leak bug into a performance bug. Please investigate",
                 self.path,
                 self.as_raw_fd()
             );
+            Reactor::unregister_dropped_file(self.id);
         }
     }
 }
@@ -272,14 +422,81 @@ impl DmaFile {
             Ok(res) => Ok(res),
         };
 
+        let fd = res? as RawFd;
+        let queue = sys::device_queue(fd);
         Ok(DmaFile {
-            file: unsafe { std::fs::File::from_raw_fd(res? as _) },
+            file: unsafe { std::fs::File::from_raw_fd(fd) },
             path: Some(path.to_path_buf()),
             o_direct_alignment: 4096,
+            max_io_size: queue
+                .max_io_size
+                .map(|size| align_down(size as u64, 4096) as usize)
+                .filter(|size| *size > 0)
+                .unwrap_or(usize::MAX),
+            write_barrier: WriteBarrier::of(&queue),
             pollable,
+            id: Reactor::get().register_file(fd, Some(path)),
         })
     }
 
+    /// Returns the largest I/O this file issues to the kernel as a single operation.
+    ///
+    /// It is read from the limits of the block device holding the file when the first
+    /// file of that device is opened. Larger reads and writes are split into sub-operations of at most this
+    /// size, issued in parallel and reassembled before completing, so callers don't
+    /// need to know the limits of the device.
+    ///
+    /// The sub-operations complete in no particular order. A split I/O reports the
+    /// contiguous prefix that was transferred from its start, stopping at the first
+    /// sub-operation that failed or was short, and only fails if nothing was
+    /// transferred. Like for a single short I/O, data past the reported size may or
+    /// may not have been transferred, even if the I/O failed.
+    pub fn max_io_size(&self) -> usize {
+        self.max_io_size
+    }
+
+    /// Overrides the largest I/O this file issues to the kernel as a single operation.
+    /// See [`max_io_size`]
+    ///
+    /// The size is rounded down to the alignment of the file, but never below it.
+    ///
+    /// [`max_io_size`]: struct.DmaFile.html#method.max_io_size
+    pub fn set_max_io_size(&mut self, size: usize) {
+        let aligned = self.align_down(size as u64) as usize;
+        self.max_io_size = std::cmp::max(aligned, self.o_direct_alignment as usize);
+    }
+
+    /// Returns how the device holding this file is capable of making the writes issued
+    /// with [`write_dma_dsync`] durable, as it advertised when the file was opened. See
+    /// [`WriteBarrier`]
+    ///
+    /// [`write_dma_dsync`]: struct.DmaFile.html#method.write_dma_dsync
+    /// [`WriteBarrier`]: enum.WriteBarrier.html
+    pub fn device_write_barrier(&self) -> WriteBarrier {
+        self.write_barrier
+    }
+
+    // Splits `size` bytes into (offset, len) chunks of at most max_io_size bytes
+    fn split_io(&self, size: usize) -> Vec<(usize, usize)> {
+        let max = self.max_io_size;
+        (0..size)
+            .step_by(max)
+            .map(|offset| (offset, std::cmp::min(max, size - offset)))
+            .collect()
+    }
+
+    /// Returns the id of this file in the reactor. See [`FileId`]
+    ///
+    /// [`FileId`]: struct.FileId.html
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    // Returns the file descriptor to issue `op` with, unless the file was closed
+    fn checked_fd(&self, op: &'static str) -> Result<RawFd> {
+        enhanced_try!(Reactor::get().check_file(self.id), op, self)
+    }
+
     /// Allocates a buffer that is suitable for using to write to this file.
     pub fn alloc_dma_buffer(size: usize) -> DmaBuffer {
         Reactor::get().alloc_dma_buffer(size)
@@ -301,6 +518,16 @@ impl DmaFile {
         Ok(f)
     }
 
+    // Creates a file that must not exist yet, open for both reading and writing
+    pub(crate) async fn create_new(path: &Path) -> Result<DmaFile> {
+        let flags = libc::O_DIRECT | libc::O_CLOEXEC | libc::O_CREAT | libc::O_EXCL | libc::O_RDWR;
+        let res = DmaFile::open_at(-1 as _, path, flags, 0o600).await;
+
+        let mut f = enhanced_try!(res, "Creating", Some(path), None)?;
+        f.o_direct_alignment = 4096;
+        Ok(f)
+    }
+
     /// Similar to open() in the standard library, but returns a DMA file
     pub async fn open<P: AsRef<Path>>(path: P) -> Result<DmaFile> {
         let path = path.as_ref().to_owned();
@@ -320,9 +547,172 @@ impl DmaFile {
     /// for Direct I/O. In most platforms that means 4096 bytes. There is no
     /// write_dma_aligned, since a non aligned write would require a
     /// read-modify-write.
+    ///
+    /// Writes larger than [`max_io_size`] are split into parallel writes, and only
+    /// complete once all of them did. Those are independent writes: they reach the file
+    /// in no particular order, and are not atomic as a whole, so a crash or an error
+    /// can leave any of them written and the others not. The returned size is the
+    /// contiguous prefix of `buf` that was written: if it is short, the data past it
+    /// may or may not have reached the file, even if the write failed.
+    ///
+    /// If the returned future is dropped before the write completes, the write may
+    /// still reach the file. Like for reads, the memory of `buf` is held by the reactor
+    /// until the kernel is done with it, so `buf` can be dropped in the meantime.
+    ///
+    /// [`max_io_size`]: struct.DmaFile.html#method.max_io_size
     pub async fn write_dma(&self, buf: &DmaBuffer, pos: u64) -> Result<usize> {
-        let source = Reactor::get().write_dma(self.as_raw_fd(), buf, pos, self.pollable);
-        enhanced_try!(source.collect_rw().await, "Writing", self)
+        self.write_dma_with(buf, pos, false).await
+    }
+
+    /// Writes all of the buffer in buf to a specific position in the file, with the same
+    /// alignment requirements as [`write_dma`].
+    ///
+    /// A short write is followed by another one for the rest of the buffer, and so on
+    /// until all of it was written. If the file stops taking data, like when the device
+    /// is full, it fails with an error of kind [`io::ErrorKind::WriteZero`] unless the
+    /// kernel reported a more specific one.
+    ///
+    /// Writes are positional, so if the returned future is dropped or fails it is safe to
+    /// call this again with the same arguments: it rewrites whatever part of the buffer
+    /// had already reached the file.
+    ///
+    /// [`write_dma`]: struct.DmaFile.html#method.write_dma
+    /// [`io::ErrorKind::WriteZero`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub async fn write_all_at(&self, buf: &DmaBuffer, pos: u64) -> Result<()> {
+        let mut written = 0;
+        while written < buf.len() {
+            // A short write can end anywhere, but the next one has to start aligned, so
+            // it takes back the part of the last block that was written
+            let from = self.align_down(written as u64) as usize;
+            let res = if from == 0 {
+                self.write_dma(buf, pos).await?
+            } else {
+                let rest = DmaFile::alloc_dma_buffer(buf.len() - from);
+                rest.as_mut_bytes().copy_from_slice(&buf.as_bytes()[from..]);
+                self.write_dma(&rest, pos + from as u64).await?
+            };
+            if from + res <= written {
+                return Err(incomplete_io!(
+                    self,
+                    io::ErrorKind::WriteZero,
+                    "failed to write the whole buffer",
+                    "Writing"
+                ));
+            }
+            written = from + res;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffer in buf to a specific position in the file, and only completes
+    /// once the data is durable, like a write followed by [`fdatasync`] would.
+    ///
+    /// The write is issued with `RWF_DSYNC`. On devices that support Force Unit Access
+    /// only this write bypasses the write cache, instead of flushing all of it, which
+    /// makes it much cheaper than a separate [`fdatasync`] for commit-latency-sensitive
+    /// writes. [`device_write_barrier`] reports how the device is capable of honoring it.
+    ///
+    /// Other than that it behaves like [`write_dma`]. Note that if the write is split
+    /// because it is larger than [`max_io_size`], each part is durable on its own, and
+    /// the whole write only once all of them are.
+    ///
+    /// [`fdatasync`]: struct.DmaFile.html#method.fdatasync
+    /// [`device_write_barrier`]: struct.DmaFile.html#method.device_write_barrier
+    /// [`write_dma`]: struct.DmaFile.html#method.write_dma
+    /// [`max_io_size`]: struct.DmaFile.html#method.max_io_size
+    pub async fn write_dma_dsync(&self, buf: &DmaBuffer, pos: u64) -> Result<usize> {
+        self.write_dma_with(buf, pos, true).await
+    }
+
+    async fn write_dma_with(&self, buf: &DmaBuffer, pos: u64, dsync: bool) -> Result<usize> {
+        let fd = self.checked_fd("Writing")?;
+        let bytes = buf.as_bytes();
+        if let Some(engine) = Reactor::get().io_engine() {
+            return enhanced_try!(
+                engine.write_dma(fd, buf, 0..bytes.len(), pos, dsync).await,
+                "Writing",
+                self
+            );
+        }
+        if bytes.len() <= self.max_io_size {
+            let source =
+                Reactor::get().write_dma(fd, buf, 0, bytes.len(), pos, self.pollable, dsync);
+            return enhanced_try!(source.collect_rw().await, "Writing", self);
+        }
+
+        let chunks = self.split_io(bytes.len());
+        let sources: Vec<_> = chunks
+            .iter()
+            .map(|(offset, len)| {
+                let pos = pos + *offset as u64;
+                Reactor::get().write_dma(fd, buf, *offset, *len, pos, self.pollable, dsync)
+            })
+            .collect();
+        let results = join_all(sources.iter().map(|source| source.collect_rw())).await;
+        enhanced_try!(reassemble(results, &chunks), "Writing", self)
+    }
+
+    /// Writes the contents of a leased buffer to a specific position in the file, with
+    /// the same alignment requirements as [`write_dma`].
+    ///
+    /// The write holds a lease of its own on the buffer until the kernel is done with
+    /// it, so the buffer can be shared with caches and readers in the meantime without
+    /// copying it, and stays valid even if the returned future is dropped.
+    ///
+    /// [`write_dma`]: struct.DmaFile.html#method.write_dma
+    pub async fn write_dma_lease(&self, lease: &DmaLease, pos: u64) -> Result<usize> {
+        let fd = self.checked_fd("Writing")?;
+        if let Some(engine) = Reactor::get().io_engine() {
+            let res = engine
+                .write_dma(fd, lease.buffer(), lease.range(), pos, false)
+                .await;
+            return enhanced_try!(res, "Writing", self);
+        }
+        let chunks = self.split_io(lease.len());
+        let sources: Vec<_> = chunks
+            .iter()
+            .map(|(offset, len)| {
+                let chunk = lease.slice(*offset..*offset + *len);
+                Reactor::get().write_dma_lease(fd, &chunk, pos + *offset as u64, self.pollable)
+            })
+            .collect();
+        let results = join_all(sources.iter().map(|source| source.collect_rw())).await;
+        enhanced_try!(reassemble(results, &chunks), "Writing", self)
+    }
+
+    // Reads `size` bytes at `pos` into a new buffer, splitting the read if it is larger
+    // than max_io_size. Returns the buffer and how many bytes were read into it.
+    async fn read_into_buffer(&self, pos: u64, size: usize) -> Result<(DmaBuffer, usize)> {
+        let fd = self.checked_fd("Reading")?;
+        if let Some(engine) = Reactor::get().io_engine() {
+            let buffer = enhanced_try!(engine.read_dma(fd, pos, size).await, "Reading", self)?;
+            let read_size = buffer.len();
+            return Ok((buffer, read_size));
+        }
+        if size <= self.max_io_size {
+            let mut source = Reactor::get().read_dma(fd, pos, size, self.pollable);
+            let read_size = enhanced_try!(source.collect_rw().await, "Reading", self)?;
+            return match source.as_mut().extract_source_type() {
+                SourceType::DmaRead(_, Some(buffer)) => Ok((buffer, read_size)),
+                _ => Err(bad_buffer!(self)),
+            };
+        }
+
+        let buffer = Rc::new(Reactor::get().alloc_dma_buffer(size));
+        let chunks = self.split_io(size);
+        let sources: Vec<_> = chunks
+            .iter()
+            .map(|(offset, len)| {
+                let pos = pos + *offset as u64;
+                Reactor::get().read_dma_into(fd, &buffer, *offset, *len, pos, self.pollable)
+            })
+            .collect();
+        let results = join_all(sources.iter().map(|source| source.collect_rw())).await;
+        // The sources hold the buffer until they are gone
+        drop(sources);
+        let read_size = enhanced_try!(reassemble(results, &chunks), "Reading", self)?;
+        let buffer = Rc::try_unwrap(buffer).map_err(|_| bad_buffer!(self))?;
+        Ok((buffer, read_size))
     }
 
     /// Reads from a specific position in the file and returns the buffer.
@@ -330,18 +720,59 @@ impl DmaFile {
     /// The position must be aligned to for Direct I/O. In most platforms
     /// that means 512 bytes.
     pub async fn read_dma_aligned(&self, pos: u64, size: usize) -> Result<DmaBuffer> {
-        let mut source = Reactor::get().read_dma(self.as_raw_fd(), pos, size, self.pollable);
-        let read_size = enhanced_try!(source.collect_rw().await, "Reading", self)?;
-        let stype = source.as_mut().extract_source_type();
-        match stype {
-            SourceType::DmaRead(_, buffer) => buffer
-                .and_then(|mut buffer| {
-                    buffer.trim_to_size(read_size);
-                    Some(buffer)
-                })
-                .ok_or(bad_buffer!(self)),
-            _ => Err(bad_buffer!(self)),
+        let (mut buffer, read_size) = self.read_into_buffer(pos, size).await?;
+        buffer.trim_to_size(read_size);
+        Ok(buffer)
+    }
+
+    /// Reads from a specific position in the file into a leased buffer, instead of one
+    /// allocated for the read, and returns the lease along with how many bytes were
+    /// read into it.
+    ///
+    /// Buffers can then come from a [`DmaBufferPool`] of the application and be reused
+    /// from one read to the next. The read fills the whole leased range, or stops at the
+    /// end of the file. Like for [`read_dma_aligned`], the position and the range must be
+    /// aligned for Direct I/O.
+    ///
+    /// The lease has to be the only one on its buffer, so no other lease sees its
+    /// contents change, or the read fails with an error of kind
+    /// [`io::ErrorKind::InvalidInput`]. If the returned future is dropped before the read
+    /// completes, the read holds a lease of its own until the kernel is done with the
+    /// buffer, which then goes back to its pool.
+    ///
+    /// [`DmaBufferPool`]: struct.DmaBufferPool.html
+    /// [`read_dma_aligned`]: struct.DmaFile.html#method.read_dma_aligned
+    /// [`io::ErrorKind::InvalidInput`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub async fn read_dma_lease(&self, mut lease: DmaLease, pos: u64) -> Result<(DmaLease, usize)> {
+        let fd = self.checked_fd("Reading")?;
+        if lease.leases() > 1 {
+            return Err(incomplete_io!(
+                self,
+                io::ErrorKind::InvalidInput,
+                "the buffer has other leases",
+                "Reading"
+            ));
+        }
+        if let Some(engine) = Reactor::get().io_engine() {
+            let buffer =
+                enhanced_try!(engine.read_dma(fd, pos, lease.len()).await, "Reading", self)?;
+            let read_size = buffer.len();
+            lease.as_mut_bytes().unwrap()[..read_size].copy_from_slice(buffer.as_bytes());
+            return Ok((lease, read_size));
         }
+        let chunks = self.split_io(lease.len());
+        let sources: Vec<_> = chunks
+            .iter()
+            .map(|(offset, len)| {
+                let chunk = lease.slice(*offset..*offset + *len);
+                Reactor::get().read_dma_lease(fd, &chunk, pos + *offset as u64, self.pollable)
+            })
+            .collect();
+        let results = join_all(sources.iter().map(|source| source.collect_rw())).await;
+        // The sources hold leases on the buffer until they are gone
+        drop(sources);
+        let read_size = enhanced_try!(reassemble(results, &chunks), "Reading", self)?;
+        Ok((lease, read_size))
     }
 
     /// Reads into buffer in buf from a specific position in the file.
@@ -355,26 +786,122 @@ impl DmaFile {
         let b = (pos - eff_pos) as usize;
 
         let eff_size = self.align_up((size + b) as u64) as usize;
-        let mut source =
-            Reactor::get().read_dma(self.as_raw_fd(), eff_pos, eff_size, self.pollable);
+        let (mut buffer, read_size) = self.read_into_buffer(eff_pos, eff_size).await?;
+        buffer.trim_front(b);
+        buffer.trim_to_size(std::cmp::min(read_size.saturating_sub(b), size));
+        Ok(buffer)
+    }
 
-        let read_size = enhanced_try!(source.collect_rw().await, "Reading", self)?;
-        let stype = source.as_mut().extract_source_type();
-        match stype {
-            SourceType::DmaRead(_, buffer) => buffer
-                .and_then(|mut buffer| {
-                    buffer.trim_front(b);
-                    buffer.trim_to_size(std::cmp::min(read_size, size));
-                    Some(buffer)
-                })
-                .ok_or(bad_buffer!(self)),
-            _ => Err(bad_buffer!(self)),
+    /// Reads exactly `size` bytes from a specific position in the file, like [`read_dma`]
+    /// does.
+    ///
+    /// A short read is followed by another one for the rest, and so on until all `size`
+    /// bytes were read. If the file ends before that, it fails with an error of kind
+    /// [`io::ErrorKind::UnexpectedEof`].
+    ///
+    /// Reads are positional and the buffers are owned by the reactor until the kernel is
+    /// done with them, so it is safe to drop the returned future, and to call this again
+    /// with the same arguments.
+    ///
+    /// [`read_dma`]: struct.DmaFile.html#method.read_dma
+    /// [`io::ErrorKind::UnexpectedEof`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub async fn read_exact_at(&self, pos: u64, size: usize) -> Result<DmaBuffer> {
+        let first = self.read_dma(pos, size).await?;
+        if first.len() == size {
+            return Ok(first);
+        }
+        let buffer = DmaFile::alloc_dma_buffer(size);
+        let mut read = first.len();
+        buffer.as_mut_bytes()[..read].copy_from_slice(first.as_bytes());
+        while read < size {
+            let chunk = self.read_dma(pos + read as u64, size - read).await?;
+            if chunk.len() == 0 {
+                return Err(incomplete_io!(
+                    self,
+                    io::ErrorKind::UnexpectedEof,
+                    "the file ended before the buffer was full",
+                    "Reading"
+                ));
+            }
+            buffer.as_mut_bytes()[read..read + chunk.len()].copy_from_slice(chunk.as_bytes());
+            read += chunk.len();
         }
+        Ok(buffer)
+    }
+
+    /// Reads the whole file into a single buffer, as long as it is no larger than `limit`
+    /// bytes.
+    ///
+    /// The size of the file is looked up first, so the buffer is allocated only once,
+    /// already aligned for Direct I/O, and files larger than [`max_io_size`] are read in
+    /// parallel chunks. Files larger than `limit` are not read at all, and fail with an
+    /// error of kind [`io::ErrorKind::InvalidData`].
+    ///
+    /// The file is read up to the size it had when it was looked up. If it shrinks
+    /// meanwhile, the read fails like [`read_exact_at`] does.
+    ///
+    /// [`max_io_size`]: struct.DmaFile.html#method.max_io_size
+    /// [`read_exact_at`]: struct.DmaFile.html#method.read_exact_at
+    /// [`io::ErrorKind::InvalidData`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub async fn read_to_end(&self, limit: u64) -> Result<DmaBuffer> {
+        let size = self.file_size().await?;
+        if size > limit {
+            return Err(incomplete_io!(
+                self,
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the file has {} bytes, more than the limit of {}",
+                    size, limit
+                ),
+                "Reading"
+            ));
+        }
+        if size == 0 {
+            let mut buffer = DmaFile::alloc_dma_buffer(self.o_direct_alignment as usize);
+            buffer.trim_to_size(0);
+            return Ok(buffer);
+        }
+        self.read_exact_at(0, size as usize).await
+    }
+
+    /// Opens the file at `path`, reads all of it like [`read_to_end`] does, and closes
+    /// it. Handy to load configuration files and other small blobs.
+    ///
+    /// Returns the buffer the file was read into, so its contents are only allocated
+    /// once and never copied.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{DmaFile, LocalExecutor};
+    ///
+    /// let ex = LocalExecutor::new(None).unwrap();
+    /// ex.run(async {
+    ///     let config = DmaFile::read_all("/etc/myapp.toml", 1 << 20)
+    ///         .await
+    ///         .expect("failed to read the configuration");
+    ///     println!("{}", String::from_utf8_lossy(config.as_bytes()));
+    /// });
+    /// ```
+    ///
+    /// [`read_to_end`]: struct.DmaFile.html#method.read_to_end
+    pub async fn read_all<P: AsRef<Path>>(path: P, limit: u64) -> Result<DmaBuffer> {
+        let mut file = DmaFile::open(path).await?;
+        let read = file.read_to_end(limit).await;
+        let closed = file.close().await;
+        let buffer = read?;
+        closed?;
+        Ok(buffer)
     }
 
     /// Issues fdatasync into the underlying file.
     pub async fn fdatasync(&self) -> Result<()> {
-        let source = Reactor::get().fdatasync(self.as_raw_fd());
+        let fd = self.checked_fd("Syncing")?;
+        if let Some(engine) = Reactor::get().io_engine() {
+            enhanced_try!(engine.fdatasync(fd).await, "Syncing", self)?;
+            return Ok(());
+        }
+        let source = Reactor::get().fdatasync(fd);
         enhanced_try!(source.collect_rw().await, "Syncing", self)?;
         Ok(())
     }
@@ -382,7 +909,13 @@ impl DmaFile {
     /// pre-allocates space in the filesystem to hold a file at least as big as the size argument
     pub async fn pre_allocate(&self, size: u64) -> Result<()> {
         let flags = libc::FALLOC_FL_ZERO_RANGE;
-        let source = Reactor::get().fallocate(self.as_raw_fd(), 0, size, flags);
+        let fd = self.checked_fd("Pre-allocate space")?;
+        if let Some(engine) = Reactor::get().io_engine() {
+            let res = engine.fallocate(fd, 0, size, flags).await;
+            enhanced_try!(res, "Pre-allocate space", self)?;
+            return Ok(());
+        }
+        let source = Reactor::get().fallocate(fd, 0, size, flags);
         enhanced_try!(source.collect_rw().await, "Pre-allocate space", self)?;
         Ok(())
     }
@@ -440,7 +973,8 @@ impl DmaFile {
     async fn statx(&self) -> Result<libc::statx> {
         let path = path_required!(self, "stat")?;
 
-        let mut source = Reactor::get().statx(self.as_raw_fd(), path);
+        let fd = self.checked_fd("getting file metadata")?;
+        let mut source = Reactor::get().statx(fd, path);
         enhanced_try!(source.collect_rw().await, "getting file metadata", self)?;
         let stype = source.as_mut().extract_source_type();
         let stat_buf = match stype {
@@ -458,11 +992,38 @@ impl DmaFile {
 
     /// Closes this DMA file.
     pub async fn close(&mut self) -> Result<()> {
-        let source = Reactor::get().close(self.as_raw_fd());
+        let fd = self.checked_fd("Closing")?;
+        let source = Reactor::get().close(fd);
         enhanced_try!(source.collect_rw().await, "Closing", self)?;
+        Reactor::get().unregister_file(self.id);
         self.file = unsafe { std::fs::File::from_raw_fd(-1) };
         Ok(())
     }
+
+    /// Closes many DMA files, keeping at most `max_in_flight` close operations
+    /// pending at any given time.
+    ///
+    /// The current task yields between each batch of closes. All files are closed
+    /// even if some of the closes fail, in which case the first error is returned.
+    pub async fn close_many(files: Vec<DmaFile>, max_in_flight: usize) -> Result<()> {
+        let mut files = files;
+        let mut first_error = None;
+        while !files.is_empty() {
+            let at = files.len().saturating_sub(std::cmp::max(max_in_flight, 1));
+            let mut batch = files.split_off(at);
+            let results = join_all(batch.iter_mut().map(|f| f.close())).await;
+            for res in results {
+                if let Err(x) = res {
+                    first_error.get_or_insert(x);
+                }
+            }
+            Local::later().await;
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -528,6 +1089,25 @@ fn fallback_drop_closes_the_file() {
     }
 }
 
+#[test]
+fn files_dropped_off_executor_dont_create_a_reactor() {
+    let paths = make_test_directories("files_dropped_off_executor_dont_create_a_reactor");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+            std::thread::spawn(move || {
+                drop(file);
+                assert!(Reactor::try_get().is_none());
+            })
+            .join()
+            .unwrap();
+        });
+    }
+}
+
 #[test]
 fn file_create_close() {
     let paths = make_test_directories("io_file_create_close");
@@ -544,6 +1124,32 @@ fn file_create_close() {
     }
 }
 
+#[test]
+fn file_operations_after_close_are_stale() {
+    let paths = make_test_directories("io_file_operations_after_close_are_stale");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let mut file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+            let id = file.id();
+            file.close().await.expect("failed to close file");
+
+            // Whatever gets the same descriptor now is a different file
+            let mut other = DmaFile::create(path.join("otherfile"))
+                .await
+                .expect("failed to create file");
+            assert_ne!(other.id(), id);
+
+            let err: io::Error = file.fdatasync().await.unwrap_err().into();
+            assert_eq!(crate::StaleFileError::from_io(&err).unwrap().id(), id);
+            other.fdatasync().await.expect("failed to sync file");
+            other.close().await.expect("failed to close file");
+        });
+    }
+}
+
 #[test]
 fn file_open() {
     let paths = make_test_directories("file_open");
@@ -721,6 +1327,75 @@ fn file_simple_readwrite() {
     }
 }
 
+#[test]
+fn file_operations_are_emulated_on_old_kernels() {
+    let paths = make_test_directories("file_operations_are_emulated_on_old_kernels");
+
+    for (path, _) in paths {
+        sys::force_fallbacks(true);
+        test_executor!(async move {
+            let mut new_file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+            let buf = DmaBuffer::new(4096).expect("failed to allocate dma buffer");
+            buf.memset(42);
+            new_file.write_dma(&buf, 0).await.expect("failed to write");
+            new_file.fdatasync().await.expect("failed to sync");
+            new_file.close().await.expect("failed to close file");
+
+            let mut new_file = DmaFile::open(path.join("testfile"))
+                .await
+                .expect("failed to open file");
+            assert_eq!(new_file.file_size().await.unwrap(), 4096);
+            let read_buf = new_file.read_dma(0, 500).await.expect("failed to read");
+            assert_eq!(read_buf.as_bytes(), &buf.as_bytes()[..500]);
+            new_file.close().await.expect("failed to close file");
+
+            // Timers work without ring timeouts too
+            crate::Timer::new(std::time::Duration::from_millis(10)).await;
+        });
+        sys::force_fallbacks(false);
+    }
+}
+
+#[test]
+fn file_io_with_separate_latency_rings() {
+    let paths = make_test_directories("file_io_with_separate_latency_rings");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            Reactor::get().set_ring_policy(crate::RingPolicy::SeparateLatency);
+            let latency = Local::create_task_queue(
+                1000,
+                crate::Latency::Matters(std::time::Duration::from_millis(1)),
+                "latency",
+            );
+
+            let mut tasks = Vec::new();
+            for (name, tq) in vec![("bulk", Local::current_task_queue()), ("latency", latency)] {
+                let path = path.join(name);
+                tasks.push(
+                    Local::local_into(
+                        async move {
+                            let mut file = DmaFile::create(&path).await.unwrap();
+                            let buf = DmaBuffer::new(4096).unwrap();
+                            buf.memset(42);
+                            file.write_dma(&buf, 0).await.unwrap();
+                            let read_buf = file.read_dma(0, 4096).await.unwrap();
+                            assert_eq!(read_buf.as_bytes(), buf.as_bytes());
+                            file.close().await.unwrap();
+                        },
+                        tq,
+                    )
+                    .unwrap(),
+                );
+            }
+            join_all(tasks).await;
+            Reactor::get().set_ring_policy(crate::RingPolicy::Shared);
+        });
+    }
+}
+
 #[test]
 fn file_invalid_readonly_write() {
     let paths = make_test_directories("file_invalid_readonly_write");
@@ -754,6 +1429,59 @@ fn file_invalid_readonly_write() {
     }
 }
 
+#[test]
+fn directory_remove_files_in_batches() {
+    let paths = make_test_directories("directory_remove_files_in_batches");
+
+    for (path, _) in paths {
+        let names: Vec<String> = (0..10).map(|x| format!("testfile{}", x)).collect();
+        for name in &names {
+            std::fs::File::create(path.join(name)).expect("failed to create file");
+        }
+
+        test_executor!(async move {
+            let mut dir = Directory::open(&path).await.expect("failed to open dir");
+            let removed = dir
+                .remove_files(&names, 3)
+                .await
+                .expect("failed to remove files");
+            std::assert_eq!(removed, 10);
+            for name in &names {
+                std::assert!(!path.join(name).exists());
+            }
+
+            // already gone, nothing to remove
+            let removed = dir
+                .remove_files(&names, 3)
+                .await
+                .expect("failed to remove files");
+            std::assert_eq!(removed, 0);
+            dir.close().await.expect("failed to close dir");
+        });
+    }
+}
+
+#[test]
+fn file_close_many() {
+    let paths = make_test_directories("file_close_many");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let mut files = Vec::new();
+            for i in 0..10 {
+                files.push(
+                    DmaFile::create(path.join(format!("testfile{}", i)))
+                        .await
+                        .expect("failed to create file"),
+                );
+            }
+            DmaFile::close_many(files, 4)
+                .await
+                .expect("failed to close files");
+        });
+    }
+}
+
 #[test]
 fn file_empty_read() {
     let paths = make_test_directories("file_empty_read");
@@ -771,3 +1499,261 @@ fn file_empty_read() {
         });
     }
 }
+
+#[test]
+fn file_write_lease_outlives_future() {
+    use crate::DmaBufferPool;
+
+    let paths = make_test_directories("io_file_write_lease_outlives_future");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let mut new_file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+            let pool = DmaBufferPool::new(4096, 4);
+            let mut lease = pool.lease();
+            lease.as_mut_bytes().unwrap().copy_from_slice(&[7; 4096]);
+
+            let write = new_file.write_dma_lease(&lease, 0);
+            futures::pin_mut!(write);
+            // Issue the write and drop it while the kernel may still be using the buffer
+            let _ = futures::poll!(write.as_mut());
+            drop(write);
+            drop(lease);
+
+            // The write still holds a lease, until it completes
+            while pool.stats().free() == 0 {
+                Local::later().await;
+            }
+
+            let read = new_file
+                .read_dma_aligned(0, 4096)
+                .await
+                .expect("failed to read");
+            assert_eq!(read.as_bytes(), &[7; 4096][..]);
+            new_file.close().await.expect("failed to close file");
+        });
+    }
+}
+
+#[test]
+fn file_large_io_is_split() {
+    let paths = make_test_directories("io_file_large_io_is_split");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let mut new_file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+            new_file.set_max_io_size(4096);
+            assert_eq!(new_file.max_io_size(), 4096);
+
+            let buf = DmaBuffer::new(16384).expect("failed to allocate dma buffer");
+            for (i, x) in buf.as_mut_bytes().iter_mut().enumerate() {
+                *x = (i / 4096) as u8;
+            }
+            let written = new_file.write_dma(&buf, 0).await.expect("failed to write");
+            assert_eq!(written, 16384);
+            new_file.close().await.expect("failed to close file");
+
+            let mut new_file = DmaFile::open(path.join("testfile"))
+                .await
+                .expect("failed to open file");
+            new_file.set_max_io_size(1000);
+            assert_eq!(new_file.max_io_size(), 512);
+
+            let read = new_file
+                .read_dma_aligned(0, 16384)
+                .await
+                .expect("failed to read");
+            assert_eq!(read.as_bytes(), buf.as_bytes());
+
+            // Reads past the end of the file are short
+            let read = new_file
+                .read_dma(4000, 16384)
+                .await
+                .expect("failed to read");
+            assert_eq!(read.len(), 16384 - 4000);
+            assert_eq!(read.as_bytes(), &buf.as_bytes()[4000..]);
+            new_file.close().await.expect("failed to close file");
+        });
+    }
+}
+
+#[test]
+fn split_io_reports_contiguous_prefix() {
+    let chunks = [(0, 4096), (4096, 4096), (8192, 4096)];
+    let err = || Err(io::Error::from_raw_os_error(libc::EIO));
+
+    // later parts that made it don't count past a short one
+    let results = vec![Ok(4096), Ok(100), Ok(4096)];
+    assert_eq!(reassemble(results, &chunks).unwrap(), 4196);
+
+    // nor past a failed one
+    let results = vec![Ok(4096), err(), Ok(4096)];
+    assert_eq!(reassemble(results, &chunks).unwrap(), 4096);
+
+    // failing first fails the whole operation
+    let results = vec![err(), Ok(4096), Ok(4096)];
+    assert!(reassemble(results, &chunks).is_err());
+
+    let results = vec![Ok(4096), Ok(4096), Ok(4096)];
+    assert_eq!(reassemble(results, &chunks).unwrap(), 12288);
+}
+
+#[test]
+fn file_dsync_write() {
+    let paths = make_test_directories("io_file_dsync_write");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let mut new_file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+
+            let buf = DmaBuffer::new(4096).expect("failed to allocate dma buffer");
+            buf.memset(42);
+            let written = new_file
+                .write_dma_dsync(&buf, 0)
+                .await
+                .expect("failed to write");
+            assert_eq!(written, 4096);
+            new_file.close().await.expect("failed to close file");
+
+            let mut new_file = DmaFile::open(path.join("testfile"))
+                .await
+                .expect("failed to open file");
+            let read = new_file.read_dma(0, 4096).await.expect("failed to read");
+            assert_eq!(read.as_bytes(), buf.as_bytes());
+            new_file.close().await.expect("failed to close file");
+        });
+    }
+}
+
+#[test]
+fn file_write_all_and_read_exact() {
+    let paths = make_test_directories("io_file_write_all_and_read_exact");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let mut new_file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+            let buf = DmaFile::alloc_dma_buffer(8192);
+            for (i, x) in buf.as_mut_bytes().iter_mut().enumerate() {
+                *x = i as u8;
+            }
+            new_file
+                .write_all_at(&buf, 4096)
+                .await
+                .expect("failed to write");
+
+            let read = new_file
+                .read_exact_at(4100, 8000)
+                .await
+                .expect("failed to read");
+            assert_eq!(read.as_bytes(), &buf.as_bytes()[4..8004]);
+
+            let err = new_file.read_exact_at(8192, 8192).await.unwrap_err();
+            assert_eq!(
+                std::io::Error::from(err).kind(),
+                io::ErrorKind::UnexpectedEof
+            );
+            new_file.close().await.expect("failed to close file");
+        });
+    }
+}
+
+#[test]
+fn file_read_to_end_with_limit() {
+    let paths = make_test_directories("io_file_read_to_end_with_limit");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let file_path = path.join("testfile");
+            let mut new_file = DmaFile::create(&file_path)
+                .await
+                .expect("failed to create file");
+            let buf = DmaFile::alloc_dma_buffer(12288);
+            for (i, x) in buf.as_mut_bytes().iter_mut().enumerate() {
+                *x = i as u8;
+            }
+            new_file
+                .write_all_at(&buf, 0)
+                .await
+                .expect("failed to write");
+            new_file.truncate(10000).await.expect("failed to truncate");
+            new_file.close().await.expect("failed to close file");
+
+            let read = DmaFile::read_all(&file_path, 1 << 20)
+                .await
+                .expect("failed to read");
+            assert_eq!(read.as_bytes(), &buf.as_bytes()[..10000]);
+
+            let err = DmaFile::read_all(&file_path, 9999).await.unwrap_err();
+            assert_eq!(std::io::Error::from(err).kind(), io::ErrorKind::InvalidData);
+
+            let mut empty = DmaFile::create(path.join("empty"))
+                .await
+                .expect("failed to create file");
+            empty.close().await.expect("failed to close file");
+            let read = DmaFile::read_all(path.join("empty"), 0)
+                .await
+                .expect("failed to read");
+            assert_eq!(read.len(), 0);
+        });
+    }
+}
+
+#[test]
+fn file_read_into_lease() {
+    use crate::DmaBufferPool;
+
+    let paths = make_test_directories("io_file_read_into_lease");
+
+    for (path, _) in paths {
+        test_executor!(async move {
+            let mut new_file = DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file");
+            let buf = DmaFile::alloc_dma_buffer(8192);
+            for (i, x) in buf.as_mut_bytes().iter_mut().enumerate() {
+                *x = (i / 4096) as u8 + 1;
+            }
+            new_file
+                .write_all_at(&buf, 0)
+                .await
+                .expect("failed to write");
+            new_file.set_max_io_size(4096);
+
+            let pool = DmaBufferPool::new(8192, 1);
+            for _ in 0..3 {
+                let (lease, read) = new_file
+                    .read_dma_lease(pool.lease(), 0)
+                    .await
+                    .expect("failed to read");
+                assert_eq!(read, 8192);
+                assert_eq!(lease.as_bytes(), buf.as_bytes());
+                assert_eq!(lease.leases(), 1);
+            }
+            assert_eq!(pool.stats().allocations(), 1);
+
+            let (lease, read) = new_file
+                .read_dma_lease(pool.lease().slice(..4096), 4096)
+                .await
+                .expect("failed to read");
+            assert_eq!(read, 4096);
+            assert_eq!(lease.as_bytes(), &[2; 4096][..]);
+
+            let shared = pool.lease();
+            let _other = shared.clone();
+            let err = new_file.read_dma_lease(shared, 0).await.unwrap_err();
+            assert_eq!(
+                std::io::Error::from(err).kind(),
+                io::ErrorKind::InvalidInput
+            );
+            new_file.close().await.expect("failed to close file");
+        });
+    }
+}
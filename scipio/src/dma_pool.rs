@@ -0,0 +1,449 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::sys::DmaBuffer;
+use std::cell::RefCell;
+use std::ops::{Bound, Range, RangeBounds};
+use std::rc::{Rc, Weak};
+
+#[derive(Debug)]
+struct PoolInner {
+    buffer_size: usize,
+    max_free: usize,
+    free: Vec<DmaBuffer>,
+    allocations: u64,
+    reuses: u64,
+}
+
+/// Statistics about a [`DmaBufferPool`]
+///
+/// [`DmaBufferPool`]: struct.DmaBufferPool.html
+#[derive(Debug, Clone, Default)]
+pub struct DmaPoolStats {
+    free: usize,
+    allocations: u64,
+    reuses: u64,
+}
+
+impl DmaPoolStats {
+    /// Buffers currently sitting in the pool, ready to be leased again
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// How many times leasing a buffer required memory to be allocated
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
+    /// How many times a buffer returned to the pool was leased again
+    pub fn reuses(&self) -> u64 {
+        self.reuses
+    }
+}
+
+/// A pool of DMA buffers of the same size, shared through counted leases.
+///
+/// Every buffer taken from the pool is wrapped in a [`DmaLease`]. Leases can be cloned
+/// and sliced, so the same buffer can back an in-flight write, a cache entry and a
+/// reader at the same time without copying it. The buffer goes back to the pool only
+/// when all its leases are dropped.
+///
+/// Pools are local to the executor they are created in.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{DmaBufferPool, LocalExecutor};
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     let pool = DmaBufferPool::new(4096, 16);
+///     let mut lease = pool.lease();
+///     lease.as_mut_bytes().unwrap().copy_from_slice(&[1; 4096]);
+///
+///     let cached = lease.clone();
+///     let header = lease.slice(..512);
+///     assert_eq!(lease.leases(), 3);
+///
+///     drop(lease);
+///     drop(cached);
+///     assert_eq!(header.as_bytes(), &[1; 512][..]);
+///     drop(header);
+///     assert_eq!(pool.stats().free(), 1);
+/// });
+/// ```
+///
+/// [`DmaLease`]: struct.DmaLease.html
+#[derive(Debug, Clone)]
+pub struct DmaBufferPool {
+    inner: Rc<RefCell<PoolInner>>,
+}
+
+impl DmaBufferPool {
+    /// Creates a pool of buffers of `buffer_size` bytes, that keeps at most `max_free`
+    /// buffers around when they are not leased.
+    pub fn new(buffer_size: usize, max_free: usize) -> DmaBufferPool {
+        DmaBufferPool {
+            inner: Rc::new(RefCell::new(PoolInner {
+                buffer_size,
+                max_free,
+                free: Vec::new(),
+                allocations: 0,
+                reuses: 0,
+            })),
+        }
+    }
+
+    /// The size of the buffers of this pool
+    pub fn buffer_size(&self) -> usize {
+        self.inner.borrow().buffer_size
+    }
+
+    /// Leases a buffer from the pool, allocating a new one if the pool is empty.
+    ///
+    /// The contents of a reused buffer are whatever its previous leases left in it.
+    pub fn lease(&self) -> DmaLease {
+        let mut inner = self.inner.borrow_mut();
+        let buffer = match inner.free.pop() {
+            Some(buffer) => {
+                inner.reuses += 1;
+                buffer
+            }
+            None => {
+                inner.allocations += 1;
+                DmaBuffer::new(inner.buffer_size).expect("Buffer allocation failed")
+            }
+        };
+        DmaLease::new(buffer, Rc::downgrade(&self.inner))
+    }
+
+    /// How many buffers the pool keeps around when they are not leased
+    pub fn max_free(&self) -> usize {
+        self.inner.borrow().max_free
+    }
+
+    /// Changes how many buffers the pool keeps around when they are not leased. Buffers
+    /// over the new limit are freed right away.
+    pub fn set_max_free(&self, max_free: usize) {
+        let mut inner = self.inner.borrow_mut();
+        inner.max_free = max_free;
+        inner.free.truncate(max_free);
+    }
+
+    /// Allocates buffers until the pool holds `count` buffers that are not leased, so
+    /// that leasing them later doesn't have to allocate. The pool is allowed to keep at
+    /// least that many buffers around from then on.
+    ///
+    /// Returns how many buffers were allocated. Buffers allocated here don't count as
+    /// allocations in the [`stats`] of the pool.
+    ///
+    /// [`stats`]: struct.DmaBufferPool.html#method.stats
+    pub fn warm_up(&self, count: usize) -> usize {
+        let mut inner = self.inner.borrow_mut();
+        inner.max_free = std::cmp::max(inner.max_free, count);
+        let missing = count.saturating_sub(inner.free.len());
+        inner.free.reserve(missing);
+        for _ in 0..missing {
+            let buffer = DmaBuffer::new(inner.buffer_size).expect("Buffer allocation failed");
+            inner.free.push(buffer);
+        }
+        missing
+    }
+
+    /// Returns statistics about this pool
+    pub fn stats(&self) -> DmaPoolStats {
+        let inner = self.inner.borrow();
+        DmaPoolStats {
+            free: inner.free.len(),
+            allocations: inner.allocations,
+            reuses: inner.reuses,
+        }
+    }
+}
+
+/// A set of [`DmaBufferPool`]s of different buffer sizes, shaped after the sizes an
+/// application expects to need, and how many of each.
+///
+/// Leases are taken from the pool of the smallest buffers that fit the requested length.
+/// The set is warmed up when it is created, so the first requests served don't pay for
+/// allocating their buffers, and can be reshaped at any time as the traffic changes.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{DmaPoolSet, LocalExecutor};
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     // 1000 small buffers and 10 large ones, ready to go
+///     let pools = DmaPoolSet::new(&[(4096, 1000), (128 << 10, 10)]);
+///     let lease = pools.lease(1000).unwrap();
+///     assert_eq!(lease.len(), 1000);
+///     assert_eq!(pools.pool(4096).unwrap().stats().allocations(), 0);
+///
+///     // the small buffers are not needed anymore, so they are released
+///     pools.reshape(&[(128 << 10, 100)]);
+///     assert!(pools.pool(4096).is_none());
+/// });
+/// ```
+///
+/// [`DmaBufferPool`]: struct.DmaBufferPool.html
+#[derive(Debug, Clone)]
+pub struct DmaPoolSet {
+    // sorted by buffer size
+    pools: Rc<RefCell<Vec<DmaBufferPool>>>,
+}
+
+impl DmaPoolSet {
+    /// Creates a set of pools, given pairs of buffer size and how many buffers of that
+    /// size to keep around. The buffers are allocated right away.
+    pub fn new(shape: &[(usize, usize)]) -> DmaPoolSet {
+        let pools = DmaPoolSet {
+            pools: Rc::new(RefCell::new(Vec::new())),
+        };
+        pools.reshape(shape);
+        pools
+    }
+
+    /// Changes the shape of the set. Pools of sizes that are in `shape` keep and warm up
+    /// as many buffers as it says, and pools of sizes that are not are removed, releasing
+    /// their buffers that are not leased. Leases taken on removed pools stay valid.
+    pub fn reshape(&self, shape: &[(usize, usize)]) {
+        let mut pools = self.pools.borrow_mut();
+        let old = std::mem::replace(&mut *pools, Vec::with_capacity(shape.len()));
+        for &(size, count) in shape {
+            let pool = match old.iter().find(|p| p.buffer_size() == size) {
+                Some(pool) => pool.clone(),
+                None => DmaBufferPool::new(size, count),
+            };
+            pool.set_max_free(count);
+            pool.warm_up(count);
+            if !pools.iter().any(|p| p.buffer_size() == size) {
+                pools.push(pool);
+            }
+        }
+        pools.sort_by_key(|p| p.buffer_size());
+        for pool in old {
+            if !pools.iter().any(|p| p.buffer_size() == pool.buffer_size()) {
+                pool.set_max_free(0);
+            }
+        }
+    }
+
+    /// Leases `len` bytes from the pool of the smallest buffers that fit them, or
+    /// returns `None` if no pool has buffers that big.
+    pub fn lease(&self, len: usize) -> Option<DmaLease> {
+        self.pools
+            .borrow()
+            .iter()
+            .find(|p| p.buffer_size() >= len)
+            .map(|p| p.lease().slice(..len))
+    }
+
+    /// The pool of buffers of `buffer_size` bytes, if the set has one
+    pub fn pool(&self, buffer_size: usize) -> Option<DmaBufferPool> {
+        self.pools
+            .borrow()
+            .iter()
+            .find(|p| p.buffer_size() == buffer_size)
+            .cloned()
+    }
+}
+
+// The buffer shared by all the leases taken on it. Returns it to its pool, if the pool
+// is still around, once the last lease is dropped.
+#[derive(Debug)]
+struct Leased {
+    buffer: Option<DmaBuffer>,
+    pool: Weak<RefCell<PoolInner>>,
+}
+
+impl Drop for Leased {
+    fn drop(&mut self) {
+        let mut buffer = self.buffer.take().unwrap();
+        if let Some(pool) = self.pool.upgrade() {
+            let mut pool = pool.borrow_mut();
+            if pool.free.len() < pool.max_free {
+                buffer.trim_front(0);
+                buffer.trim_to_size(pool.buffer_size);
+                pool.free.push(buffer);
+            }
+        }
+    }
+}
+
+/// A counted lease on a buffer of a [`DmaBufferPool`], or on a range of it.
+///
+/// Cloning and slicing a lease takes a new lease on the same buffer. The buffer is
+/// returned to its pool when all its leases are dropped. Leases can be written to
+/// files with [`DmaFile::write_dma_lease`], and read into with
+/// [`DmaFile::read_dma_lease`], which hold a lease of their own until the kernel is done
+/// with the buffer.
+///
+/// [`DmaBufferPool`]: struct.DmaBufferPool.html
+/// [`DmaFile::write_dma_lease`]: struct.DmaFile.html#method.write_dma_lease
+/// [`DmaFile::read_dma_lease`]: struct.DmaFile.html#method.read_dma_lease
+#[derive(Debug, Clone)]
+pub struct DmaLease {
+    leased: Rc<Leased>,
+    start: usize,
+    len: usize,
+}
+
+impl DmaLease {
+    fn new(buffer: DmaBuffer, pool: Weak<RefCell<PoolInner>>) -> DmaLease {
+        let len = buffer.len();
+        DmaLease {
+            leased: Rc::new(Leased {
+                buffer: Some(buffer),
+                pool,
+            }),
+            start: 0,
+            len,
+        }
+    }
+
+    // The whole buffer the lease was taken on
+    pub(crate) fn buffer(&self) -> &DmaBuffer {
+        self.leased.buffer.as_ref().unwrap()
+    }
+
+    // Where the leased range lies in the buffer
+    pub(crate) fn range(&self) -> Range<usize> {
+        self.start..self.start + self.len
+    }
+
+    /// The length of the leased range
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the leased range is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many leases are currently held on the underlying buffer, including this one
+    /// and the ones held by in-flight writes.
+    pub fn leases(&self) -> usize {
+        Rc::strong_count(&self.leased)
+    }
+
+    /// The contents of the leased range
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer().as_bytes()[self.start..self.start + self.len]
+    }
+
+    /// The contents of the leased range, for writing. Only available while this is the
+    /// only lease on the buffer, so leases never see their contents change under them.
+    pub fn as_mut_bytes(&mut self) -> Option<&mut [u8]> {
+        let (start, len) = (self.start, self.len);
+        Rc::get_mut(&mut self.leased)
+            .map(|leased| &mut leased.buffer.as_mut().unwrap().as_mut_bytes()[start..start + len])
+    }
+
+    // Where the leased range starts, for the kernel to write to. Only reads that took
+    // the buffer while this was its only lease write through it.
+    pub(crate) fn as_mut_ptr(&self) -> *mut u8 {
+        unsafe { self.buffer().as_mut_ptr().add(self.start) }
+    }
+
+    /// Takes a new lease on a range of this one. The range is relative to the start of
+    /// this lease.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of the bounds of this lease
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> DmaLease {
+        let start = match range.start_bound() {
+            Bound::Included(x) => *x,
+            Bound::Excluded(x) => x + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(x) => x + 1,
+            Bound::Excluded(x) => *x,
+            Bound::Unbounded => self.len,
+        };
+        assert!(
+            start <= end && end <= self.len,
+            "range {}..{} out of the bounds of a lease of {} bytes",
+            start,
+            end,
+            self.len
+        );
+        DmaLease {
+            leased: self.leased.clone(),
+            start: self.start + start,
+            len: end - start,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn leases_return_buffers_to_the_pool() {
+        test_executor!(async move {
+            let pool = DmaBufferPool::new(4096, 1);
+            let mut first = pool.lease();
+            first.as_mut_bytes().unwrap()[0] = 42;
+            let shared = first.slice(0..1);
+            assert!(first.as_mut_bytes().is_none());
+            assert_eq!(first.leases(), 2);
+
+            let second = pool.lease();
+            assert_eq!(pool.stats().allocations(), 2);
+            drop(first);
+            assert_eq!(pool.stats().free(), 0);
+            drop(shared);
+            assert_eq!(pool.stats().free(), 1);
+
+            // Only one buffer is kept around
+            drop(second);
+            assert_eq!(pool.stats().free(), 1);
+
+            let reused = pool.lease();
+            assert_eq!(reused.as_bytes()[0], 42);
+            assert_eq!(reused.len(), 4096);
+            assert_eq!(pool.stats().reuses(), 1);
+        });
+    }
+
+    #[test]
+    fn pools_can_be_warmed_up_and_reshaped() {
+        test_executor!(async move {
+            let pools = DmaPoolSet::new(&[(128 << 10, 2), (4096, 8)]);
+            let small = pools.pool(4096).unwrap();
+            assert_eq!(small.stats().free(), 8);
+            assert_eq!(small.max_free(), 8);
+
+            let lease = pools.lease(100).unwrap();
+            assert_eq!(lease.len(), 100);
+            assert_eq!(small.stats().free(), 7);
+            assert_eq!(small.stats().allocations(), 0);
+            let large = pools.lease(5000).unwrap();
+            assert_eq!(pools.pool(128 << 10).unwrap().stats().free(), 1);
+            assert!(pools.lease(1 << 20).is_none());
+
+            // shrinking frees buffers right away, and growing warms up the new ones
+            pools.reshape(&[(4096, 2), (1 << 20, 1)]);
+            assert_eq!(small.stats().free(), 2);
+            assert!(pools.pool(128 << 10).is_none());
+            assert_eq!(pools.pool(1 << 20).unwrap().stats().free(), 1);
+
+            // a lease on a removed pool is still good, but its buffer isn't kept
+            drop(large);
+            drop(lease);
+            assert_eq!(small.stats().free(), 2);
+            assert_eq!(small.warm_up(4), 2);
+        });
+    }
+}
@@ -6,6 +6,7 @@
 use std::fmt;
 use std::os::unix::io::RawFd;
 use std::path::PathBuf;
+use std::time::Instant;
 
 /// Augments an io::Error with more information about what was happening
 /// and to which file when the error ocurred.
@@ -54,3 +55,189 @@ impl From<Error> for std::io::Error {
         err.inner
     }
 }
+
+/// An io_uring operation that the running kernel doesn't support, and that can't be
+/// emulated.
+///
+/// It is returned wrapped in an [`io::Error`] of kind `Other`, and can be recovered with
+/// `get_ref` and `downcast_ref`.
+///
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedOperation {
+    pub(crate) operation: &'static str,
+    pub(crate) min_kernel: (u32, u32),
+}
+
+impl UnsupportedOperation {
+    /// The name of the operation, like `IORING_OP_TIMEOUT`
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// The first Linux version, as major and minor numbers, that supports the operation
+    pub fn min_kernel(&self) -> (u32, u32) {
+        self.min_kernel
+    }
+}
+
+impl fmt::Display for UnsupportedOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the kernel doesn't support {}, which needs Linux {}.{} or newer",
+            self.operation, self.min_kernel.0, self.min_kernel.1
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedOperation {}
+
+impl From<UnsupportedOperation> for std::io::Error {
+    fn from(err: UnsupportedOperation) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Other, err)
+    }
+}
+
+/// The error returned when a [`ConnectionBudget`] can't fit more memory.
+///
+/// It is returned wrapped in an [`io::Error`] of kind `Other` by the I/O types that
+/// enforce budgets, and can be recovered with `get_ref` and `downcast_ref`.
+///
+/// [`ConnectionBudget`]: struct.ConnectionBudget.html
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetExceeded {
+    pub(crate) requested: usize,
+    pub(crate) available: usize,
+}
+
+impl BudgetExceeded {
+    /// How many bytes were asked for
+    pub fn requested(&self) -> usize {
+        self.requested
+    }
+
+    /// How many bytes were left in the budget at the time
+    pub fn available(&self) -> usize {
+        self.available
+    }
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the memory budget can't fit {} more bytes, only {} are available",
+            self.requested, self.available
+        )
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+impl From<BudgetExceeded> for std::io::Error {
+    fn from(err: BudgetExceeded) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Other, err)
+    }
+}
+
+/// The deadline set with [`Local::with_deadline`] passed before the future it was set
+/// for completed.
+///
+/// [`Local::with_deadline`]: type.Local.html#method.with_deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    pub(crate) deadline: Instant,
+}
+
+impl DeadlineExceeded {
+    /// The deadline that passed
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the operation did not complete before its deadline")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+impl From<DeadlineExceeded> for std::io::Error {
+    fn from(err: DeadlineExceeded) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, err)
+    }
+}
+
+/// The executor an operation depended on went away, either cleanly or because it was
+/// poisoned by a panic. See [`ExecutorMonitor`]
+///
+/// [`ExecutorMonitor`]: struct.ExecutorMonitor.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutorGone {
+    pub(crate) id: usize,
+    pub(crate) poisoned: bool,
+}
+
+impl ExecutorGone {
+    /// The id of the executor that went away
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Whether the executor went away because something running in it panicked
+    pub fn poisoned(&self) -> bool {
+        self.poisoned
+    }
+}
+
+impl fmt::Display for ExecutorGone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.poisoned {
+            write!(f, "executor {} was poisoned by a panic", self.id)
+        } else {
+            write!(f, "executor {} is gone", self.id)
+        }
+    }
+}
+
+impl std::error::Error for ExecutorGone {}
+
+impl From<ExecutorGone> for std::io::Error {
+    fn from(err: ExecutorGone) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::BrokenPipe, err)
+    }
+}
+
+/// A [`CancellableTimer`] was cancelled through a [`TimerHandle`] before it expired.
+///
+/// [`CancellableTimer`]: struct.CancellableTimer.html
+/// [`TimerHandle`]: struct.TimerHandle.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerCancelled {
+    pub(crate) scheduled: Instant,
+}
+
+impl TimerCancelled {
+    /// The instant the timer was scheduled to fire at
+    pub fn scheduled(&self) -> Instant {
+        self.scheduled
+    }
+}
+
+impl fmt::Display for TimerCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the timer was cancelled")
+    }
+}
+
+impl std::error::Error for TimerCancelled {}
+
+impl From<TimerCancelled> for std::io::Error {
+    fn from(err: TimerCancelled) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Interrupted, err)
+    }
+}
@@ -27,29 +27,67 @@
 
 #![warn(missing_docs, missing_debug_implementations)]
 
-use std::cell::RefCell;
-use std::collections::{BinaryHeap, HashMap};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
 use std::fmt;
 use std::future::Future;
 use std::io;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::task::{Context, Poll};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 use std::thread::{Builder, JoinHandle};
 use std::time::{Duration, Instant};
 
+use futures::channel::oneshot;
+use futures::StreamExt;
 use futures_lite::pin;
 use scoped_tls::scoped_thread_local;
 
+use crate::checked_cell;
+use crate::config::{ExecutorConfig, PoolConfig, TaskQueueConfig};
+use crate::deadline::{self, WithDeadline};
+use crate::hot_path::{self, HotPathAllocations};
+use crate::in_flight_io::InFlightIo;
+use crate::io_engine::IoEngine;
+use crate::io_tag::{self, IoTagStats, WithIoTag};
+use crate::monitor::ExecutorMonitor;
 use crate::multitask;
 use crate::parking;
+use crate::rng::Rng;
+use crate::scoped::{self, Scope};
+use crate::slow_io::{self, SlowIo};
+use crate::sys;
 use crate::task::{self, waker_fn::waker_fn};
+use crate::timer::{ClockJump, TimerStats};
+#[cfg(feature = "wakeup-tracking")]
+use crate::wakeup_tracker::{self, Tracked, WakeupReport};
+use crate::watchdog::{CpuSliceGuard, Heartbeat, Watchdog, WatchdogAction, WatchdogReport};
 use crate::Reactor;
-use crate::{IoRequirements, Latency};
+use crate::{DeadlineExceeded, IoRequirements, Latency, RingPolicy, TimerBackend};
 
 static EXECUTOR_ID: AtomicUsize = AtomicUsize::new(0);
 
+lazy_static! {
+    // Task queue names that came from configurations, leaked once each
+    static ref QUEUE_NAMES: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+}
+
+// Task queue names are static so they are free to pass around. Names that aren't are
+// interned, so configuring executors over and over only leaks each distinct name once.
+fn intern_queue_name(name: &str) -> &'static str {
+    let mut names = QUEUE_NAMES.lock().unwrap();
+    match names.get(name) {
+        Some(interned) => *interned,
+        None => {
+            let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Error thrown when a Task Queue is not found.
 pub struct QueueNotFoundError {
@@ -94,7 +132,128 @@ impl fmt::Display for QueueStillActiveError {
 
 scoped_thread_local!(static LOCAL_EX: LocalExecutor);
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+/// The maximum factor by which the latency target mode will shrink the preemption
+/// interval of competing queues.
+const MAX_PREEMPT_SCALE: u32 = 64;
+
+#[derive(Debug, Clone, Default)]
+/// Statistics about the scheduling decisions of a [`LocalExecutor`]
+///
+/// [`LocalExecutor`]: struct.LocalExecutor.html
+pub struct ExecutorStats {
+    wake_latency_violations: u64,
+    preempt_adjustments: u64,
+    max_wake_latency: Duration,
+    allocations: u64,
+    hot_path_allocations: u64,
+    orphaned_io: u64,
+    total_orphaned_io: u64,
+    io_reserve_breaks: u64,
+    priority_donations: u64,
+    latency_preemptions: u64,
+}
+
+impl ExecutorStats {
+    /// Number of times a task queue marked as [`Latency::Matters`] took longer than its
+    /// latency target to run after being woken up.
+    ///
+    /// [`Latency::Matters`]: enum.Latency.html
+    pub fn wake_latency_violations(&self) -> u64 {
+        self.wake_latency_violations
+    }
+
+    /// Number of times the latency target mode adjusted the preemption interval
+    pub fn preempt_adjustments(&self) -> u64 {
+        self.preempt_adjustments
+    }
+
+    /// The largest wake-to-run latency observed for a latency sensitive task queue
+    pub fn max_wake_latency(&self) -> Duration {
+        self.max_wake_latency
+    }
+
+    /// Number of memory allocations made by the executor thread, including the ones
+    /// made by its tasks. Always zero unless [`CountingAllocator`] is the global allocator.
+    ///
+    /// [`CountingAllocator`]: struct.CountingAllocator.html
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
+    /// Number of memory allocations made by the reactor and the scheduler themselves, as
+    /// opposed to the tasks they run. Always zero unless [`CountingAllocator`] is the
+    /// global allocator. In steady state this is not expected to grow.
+    ///
+    /// [`CountingAllocator`]: struct.CountingAllocator.html
+    pub fn hot_path_allocations(&self) -> u64 {
+        self.hot_path_allocations
+    }
+
+    /// Number of I/O requests whose futures were dropped before the kernel completed
+    /// them, and that are still waiting for the kernel to be done with their buffers.
+    pub fn orphaned_io(&self) -> u64 {
+        self.orphaned_io
+    }
+
+    /// Number of I/O requests whose futures were dropped before the kernel completed
+    /// them, over the lifetime of the executor thread.
+    pub fn total_orphaned_io(&self) -> u64 {
+        self.total_orphaned_io
+    }
+
+    /// Number of times a task queue was cut short to let the reactor collect I/O
+    /// completions, because it ran for longer than the I/O progress reserve allows.
+    /// See [`LocalExecutor::set_io_progress_reserve`]
+    ///
+    /// [`LocalExecutor::set_io_progress_reserve`]: struct.LocalExecutor.html#method.set_io_progress_reserve
+    pub fn io_reserve_breaks(&self) -> u64 {
+        self.io_reserve_breaks
+    }
+
+    /// Number of times a task queue holding a [`Semaphore`] that a latency sensitive
+    /// task queue was waiting on had its priority boosted until the units were released.
+    ///
+    /// [`Semaphore`]: struct.Semaphore.html
+    pub fn priority_donations(&self) -> u64 {
+        self.priority_donations
+    }
+
+    /// Number of times a task queue that is not latency sensitive was cut short because
+    /// a task queue marked as [`Latency::Matters`] had been waiting to run for longer
+    /// than its latency target.
+    ///
+    /// [`Latency::Matters`]: enum.Latency.html
+    pub fn latency_preemptions(&self) -> u64 {
+        self.latency_preemptions
+    }
+}
+
+/// How urgent a task is compared to the other tasks of its task queue.
+///
+/// Task queues share the CPU according to their shares, but within a queue, runnable
+/// tasks of a higher priority always run before those of a lower one, and tasks of the
+/// same priority run in the order they became runnable. Tasks keep their priority for
+/// their whole life, so a high priority task that keeps yielding delays the others in
+/// its queue until it is done.
+///
+/// Tasks of ordered task queues still start in the order they were spawned.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TaskPriority {
+    /// Runs before any other task of its queue
+    High = 0,
+    /// The priority of tasks spawned without one
+    Normal = 1,
+    /// Only runs when no other task of its queue is runnable
+    Low = 2,
+}
+
+impl Default for TaskPriority {
+    fn default() -> Self {
+        TaskPriority::Normal
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 /// An opaque handler indicating in which queue a group of tasks will execute.
 /// Tasks in the same group will execute in FIFO order but no guarantee is made
 /// about ordering on different task queues.
@@ -109,6 +268,10 @@ impl Default for TaskQueueHandle {
 }
 
 impl TaskQueueHandle {
+    pub(crate) fn from_index(index: usize) -> TaskQueueHandle {
+        TaskQueueHandle { index }
+    }
+
     /// Sets the number of shares used for a particular TaskQueue
     pub fn set_task_queue_shares(&self, shares: usize) -> Result<(), QueueNotFoundError> {
         if LOCAL_EX.is_set() {
@@ -126,6 +289,208 @@ impl TaskQueueHandle {
             panic!("`Task::local()` must be called from a `LocalExecutor`")
         }
     }
+
+    /// Changes the number of shares of the task queue while it is in use, so CPU can be
+    /// rebalanced between queues as load changes, without recreating them. A queue
+    /// with no shares gets 1.
+    ///
+    /// The new shares apply to the time the queue runs from now on: what it was charged
+    /// for so far stays as it was.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Latency, Local, LocalExecutor};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.run(async {
+    ///     let compaction = Local::create_task_queue(100, Latency::NotImportant, "compaction");
+    ///     // foreground traffic went away, compaction can take more of the CPU
+    ///     compaction.set_shares(1000).unwrap();
+    ///     assert_eq!(compaction.shares().unwrap(), 1000);
+    /// });
+    /// ```
+    pub fn set_shares(&self, shares: usize) -> Result<(), QueueNotFoundError> {
+        self.set_task_queue_shares(shares)
+    }
+
+    /// The number of shares of the task queue. See [`set_shares`]
+    ///
+    /// [`set_shares`]: struct.TaskQueueHandle.html#method.set_shares
+    pub fn shares(&self) -> Result<usize, QueueNotFoundError> {
+        self.get_task_queue_shares()
+    }
+
+    /// How long the tasks of the task queue ran so far. See
+    /// [`LocalExecutor::task_queue_runtime`]
+    ///
+    /// [`LocalExecutor::task_queue_runtime`]: struct.LocalExecutor.html#method.task_queue_runtime
+    pub fn runtime(&self) -> Result<Duration, QueueNotFoundError> {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.task_queue_runtime(*self))
+        } else {
+            panic!("`TaskQueueHandle::runtime()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Gets the number of times a particular TaskQueue missed its latency target. See
+    /// [`LocalExecutor::task_queue_latency_misses`]
+    ///
+    /// [`LocalExecutor::task_queue_latency_misses`]: struct.LocalExecutor.html#method.task_queue_latency_misses
+    pub fn latency_misses(&self) -> Result<u64, QueueNotFoundError> {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.task_queue_latency_misses(*self))
+        } else {
+            panic!("`Task::local()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Gets the number of tasks of a particular TaskQueue that are waiting to run. See
+    /// [`LocalExecutor::task_queue_runnable_tasks`]
+    ///
+    /// [`LocalExecutor::task_queue_runnable_tasks`]: struct.LocalExecutor.html#method.task_queue_runnable_tasks
+    pub fn runnable_tasks(&self) -> Result<usize, QueueNotFoundError> {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.task_queue_runnable_tasks(*self))
+        } else {
+            panic!("`Task::local()` must be called from a `LocalExecutor`")
+        }
+    }
+}
+
+/// A task queue marked as [`Latency::Matters`] that waited longer than its latency
+/// target to run, as reported to the handlers set with
+/// [`LocalExecutor::on_latency_miss`].
+///
+/// [`Latency::Matters`]: enum.Latency.html
+/// [`LocalExecutor::on_latency_miss`]: struct.LocalExecutor.html#method.on_latency_miss
+#[derive(Debug, Clone)]
+pub struct LatencyMiss {
+    queue: TaskQueueHandle,
+    name: &'static str,
+    waited: Duration,
+    target: Duration,
+}
+
+impl LatencyMiss {
+    /// The task queue that missed its target
+    pub fn queue(&self) -> TaskQueueHandle {
+        self.queue
+    }
+
+    /// The name of the task queue that missed its target
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// How long the task queue waited to run after being woken up
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+
+    /// The latency target of the task queue
+    pub fn target(&self) -> Duration {
+        self.target
+    }
+}
+
+#[derive(Clone)]
+struct LatencyMissHandler(Rc<dyn Fn(&LatencyMiss)>);
+
+impl fmt::Debug for LatencyMissHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LatencyMissHandler")
+    }
+}
+
+// Hands out turns to the tasks of an ordered task queue, in the order they were spawned.
+// A task only starts once every task spawned before it finished or was dropped.
+#[derive(Debug, Default)]
+struct FifoOrder {
+    next_ticket: Cell<u64>,
+    serving: Cell<u64>,
+    // tickets of tasks dropped before their turn came, to be skipped over
+    abandoned: RefCell<BTreeSet<u64>>,
+    waiters: RefCell<HashMap<u64, Waker>>,
+    // whether the task whose turn it is is being polled
+    in_turn: Cell<bool>,
+}
+
+impl FifoOrder {
+    fn wrap<T>(self: &Rc<Self>, future: impl Future<Output = T>) -> impl Future<Output = T> {
+        let ticket = self.next_ticket.get();
+        self.next_ticket.set(ticket + 1);
+        let turn = FifoTurn {
+            order: self.clone(),
+            ticket,
+        };
+        async move {
+            (&turn).await;
+            pin!(future);
+            let res = futures::future::poll_fn(|cx| {
+                let was_in_turn = turn.order.in_turn.replace(true);
+                let res = future.as_mut().poll(cx);
+                turn.order.in_turn.set(was_in_turn);
+                res
+            })
+            .await;
+            drop(turn);
+            res
+        }
+    }
+
+    // Tasks spawned by the task whose turn it is can't wait for their own turn: it
+    // comes after the spawner finishes, and the spawner may be waiting for them
+    fn spawned_in_turn(&self) -> bool {
+        self.in_turn.get()
+    }
+
+    fn finish(&self, ticket: u64) {
+        self.waiters.borrow_mut().remove(&ticket);
+        if ticket != self.serving.get() {
+            self.abandoned.borrow_mut().insert(ticket);
+            return;
+        }
+        let mut serving = ticket + 1;
+        let mut abandoned = self.abandoned.borrow_mut();
+        while abandoned.remove(&serving) {
+            serving += 1;
+        }
+        self.serving.set(serving);
+        if let Some(waker) = self.waiters.borrow_mut().remove(&serving) {
+            waker.wake();
+        }
+    }
+}
+
+// The place of a task in an ordered task queue. Resolves when it is the task's turn to
+// start, and lets the next task start when dropped.
+#[derive(Debug)]
+struct FifoTurn {
+    order: Rc<FifoOrder>,
+    ticket: u64,
+}
+
+impl Future for &FifoTurn {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.order.serving.get() == self.ticket {
+            Poll::Ready(())
+        } else {
+            self.order
+                .waiters
+                .borrow_mut()
+                .insert(self.ticket, cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for FifoTurn {
+    fn drop(&mut self) {
+        self.order.finish(self.ticket);
+    }
 }
 
 #[derive(Debug)]
@@ -139,6 +504,12 @@ struct TaskQueue {
     io_requirements: IoRequirements,
     name: &'static str,
     index: usize, // so we can easily produce a handle
+    activated_at: Option<Instant>,
+    latency_misses: u64,
+    on_latency_miss: Option<LatencyMissHandler>,
+    ordered: Option<Rc<FifoOrder>>,
+    // Latency sensitive queues waiting on locks this queue holds
+    boosts: usize,
 }
 
 // Impl a custom order so we use a min-heap
@@ -183,11 +554,25 @@ impl TaskQueue {
             io_requirements: ioreq,
             name,
             index,
+            activated_at: None,
+            latency_misses: 0,
+            on_latency_miss: None,
+            ordered: None,
+            boosts: 0,
         };
         tq.set_shares(shares);
         Rc::new(RefCell::new(tq))
     }
 
+    fn to_config(&self) -> TaskQueueConfig {
+        TaskQueueConfig {
+            name: self.name.to_string(),
+            shares: self.shares,
+            latency: self.io_requirements.latency_req,
+            ordered: self.ordered.is_some(),
+        }
+    }
+
     fn is_active(&self) -> bool {
         self.active
     }
@@ -198,6 +583,50 @@ impl TaskQueue {
         r
     }
 
+    // Only latency sensitive queues pay the price of reading the clock.
+    fn mark_activated(&mut self) {
+        if let Latency::Matters(_) = self.io_requirements.latency_req {
+            self.activated_at = Some(Instant::now());
+        }
+    }
+
+    // The point in time by which this queue should be running, if it is latency
+    // sensitive and waiting to run.
+    fn latency_deadline(&self) -> Option<Instant> {
+        match self.io_requirements.latency_req {
+            Latency::Matters(target) => self.activated_at.map(|at| at + target),
+            Latency::NotImportant => None,
+        }
+    }
+
+    // Returns the time elapsed since this queue was woken up, together with its
+    // latency target, if this is a latency sensitive queue.
+    fn take_wake_latency(&mut self) -> Option<(Duration, Duration)> {
+        let activated_at = self.activated_at.take()?;
+        match self.io_requirements.latency_req {
+            Latency::Matters(target) => Some((activated_at.elapsed(), target)),
+            Latency::NotImportant => None,
+        }
+    }
+
+    // Counts a miss of the latency target, and returns what to tell the handler about
+    // it, if the queue has one.
+    fn account_latency_miss(
+        &mut self,
+        waited: Duration,
+        target: Duration,
+    ) -> Option<(LatencyMissHandler, LatencyMiss)> {
+        self.latency_misses += 1;
+        let handler = self.on_latency_miss.clone()?;
+        let miss = LatencyMiss {
+            queue: TaskQueueHandle { index: self.index },
+            name: self.name,
+            waited,
+            target,
+        };
+        Some((handler, miss))
+    }
+
     fn set_shares(&mut self, shares: usize) {
         self.shares = std::cmp::max(shares, 1);
         self.reciprocal_shares = (1u64 << 22) / (self.shares as u64);
@@ -211,7 +640,10 @@ impl TaskQueue {
             .checked_mul(delta.as_micros() as u64)
             .unwrap_or(0)
             >> 12;
-        self.vruntime += delta_scaled;
+        // A boosted queue runs on somebody else's behalf, so it is not charged for it
+        if self.boosts == 0 {
+            self.vruntime += delta_scaled;
+        }
         self.runtime += delta.as_micros() as u64;
 
         //println!("Ran task for {} us, adding {} of vruntime (shares = {})", delta.as_micros(), delta_scaled, self.shares);
@@ -232,12 +664,23 @@ macro_rules! to_io_error {
 }
 
 fn bind_to_cpu(cpu: usize) -> io::Result<()> {
+    // Remember what the process may run on before this thread is restricted
+    sys::allowed_cpus();
     let mut cpuset = nix::sched::CpuSet::new();
     to_io_error!(&cpuset.set(cpu as usize))?;
     let pid = nix::unistd::Pid::from_raw(0);
     to_io_error!(nix::sched::sched_setaffinity(pid, &cpuset))
 }
 
+fn unbind_from_cpu() -> io::Result<()> {
+    let mut cpuset = nix::sched::CpuSet::new();
+    for cpu in sys::allowed_cpus() {
+        to_io_error!(&cpuset.set(*cpu))?;
+    }
+    let pid = nix::unistd::Pid::from_raw(0);
+    to_io_error!(nix::sched::sched_setaffinity(pid, &cpuset))
+}
+
 #[derive(Debug)]
 struct ExecutorQueues {
     active_executors: BinaryHeap<Rc<RefCell<TaskQueue>>>,
@@ -247,6 +690,15 @@ struct ExecutorQueues {
     executor_index: usize,
     last_vruntime: u64,
     preempt_timer_duration: Duration,
+    latency_target_mode: bool,
+    preempt_scale: u32,
+    io_progress_reserve: Option<Duration>,
+    // The latency sensitive queues waiting to run, by deadline
+    latency_deadlines: BTreeSet<(Instant, usize)>,
+    // The earliest of those deadlines, shared with the executor so it can be checked
+    // between tasks without borrowing the queues
+    latency_deadline: Rc<Cell<Option<Instant>>>,
+    stats: ExecutorStats,
 }
 
 impl ExecutorQueues {
@@ -259,6 +711,12 @@ impl ExecutorQueues {
             executor_index: 1, // 0 is the default
             last_vruntime: 0,
             preempt_timer_duration: Duration::from_secs(1),
+            latency_target_mode: false,
+            preempt_scale: 1,
+            io_progress_reserve: None,
+            latency_deadlines: BTreeSet::new(),
+            latency_deadline: Rc::new(Cell::new(None)),
+            stats: ExecutorStats::default(),
         }))
     }
 
@@ -271,8 +729,69 @@ impl ExecutorQueues {
                 Latency::Matters(d) => d,
             })
             .min()
-            .unwrap_or(Duration::from_secs(1));
+            .unwrap_or(Duration::from_secs(1))
+            / self.preempt_scale;
+    }
+
+    // Called when a queue joins the line of active queues
+    fn track_latency_deadline(&mut self, state: &TaskQueue) {
+        if let Some(deadline) = state.latency_deadline() {
+            self.latency_deadlines.insert((deadline, state.index));
+            self.publish_latency_deadline();
+        }
+    }
+
+    // Called when a queue leaves the line of active queues to run
+    fn untrack_latency_deadline(&mut self, state: &TaskQueue) {
+        if let Some(deadline) = state.latency_deadline() {
+            self.latency_deadlines.remove(&(deadline, state.index));
+            self.publish_latency_deadline();
+        }
+    }
+
+    fn publish_latency_deadline(&self) {
+        let earliest = self.latency_deadlines.iter().next().map(|(at, _)| *at);
+        self.latency_deadline.set(earliest);
+    }
+
+    // Takes the latency sensitive queue that is past its deadline for the longest out of
+    // the line, if any, so it runs next no matter its vruntime. Queues that yield to it
+    // only run again once it had its turn.
+    fn take_overdue(&mut self) -> Option<Rc<RefCell<TaskQueue>>> {
+        let &(deadline, index) = self.latency_deadlines.iter().next()?;
+        if Instant::now() < deadline {
+            return None;
+        }
+        let mut queues = std::mem::take(&mut self.active_executors).into_vec();
+        let queue = queues
+            .iter()
+            .position(|tq| tq.borrow().index == index)
+            .map(|pos| queues.swap_remove(pos));
+        self.active_executors = queues.into_iter().collect();
+        queue
+    }
+
+    // In latency target mode, every time a latency sensitive queue misses its target
+    // we halve the preemption interval so competing queues yield sooner. Once we are
+    // comfortably within the target again, we slowly give that time back.
+    fn account_wake_latency(&mut self, latency: Duration, target: Duration) {
+        stat! {
+            self.stats.max_wake_latency = std::cmp::max(self.stats.max_wake_latency, latency);
+        }
+        if latency > target {
+            stat! { self.stats.wake_latency_violations += 1; }
+            if self.latency_target_mode && self.preempt_scale < MAX_PREEMPT_SCALE {
+                self.preempt_scale *= 2;
+                stat! { self.stats.preempt_adjustments += 1; }
+                self.reevaluate_preempt_timer();
+            }
+        } else if self.latency_target_mode && self.preempt_scale > 1 && latency < target / 4 {
+            self.preempt_scale /= 2;
+            stat! { self.stats.preempt_adjustments += 1; }
+            self.reevaluate_preempt_timer();
+        }
     }
+
     fn maybe_activate(&mut self, index: usize) {
         let queue = self
             .available_executors
@@ -284,11 +803,89 @@ impl ExecutorQueues {
         if !state.is_active() {
             state.vruntime = self.last_vruntime;
             state.active = true;
+            state.mark_activated();
+            self.track_latency_deadline(&state);
             drop(state);
             self.active_executors.push(queue);
             self.reevaluate_preempt_timer();
         }
     }
+
+    // Lends priority to a queue that holds a lock a latency sensitive queue waits on:
+    // it moves to the front of the line, and stays there until the lock is released.
+    // Latency sensitive holders already get their turn soon enough.
+    fn boost(&mut self, index: usize) -> bool {
+        let queue = match self.available_executors.get(&index) {
+            Some(queue) => queue.clone(),
+            None => return false,
+        };
+
+        let mut state = queue.borrow_mut();
+        if let Latency::Matters(_) = state.io_requirements.latency_req {
+            return false;
+        }
+        state.boosts += 1;
+        if state.boosts == 1 {
+            stat! { self.stats.priority_donations += 1; }
+            if state.vruntime > self.last_vruntime {
+                state.vruntime = self.last_vruntime;
+                let active = state.is_active();
+                drop(state);
+                if active {
+                    let heap = std::mem::take(&mut self.active_executors);
+                    self.active_executors = heap.into_vec().into_iter().collect();
+                }
+            }
+        }
+        true
+    }
+
+    fn unboost(&mut self, index: usize) {
+        if let Some(queue) = self.available_executors.get(&index) {
+            let mut state = queue.borrow_mut();
+            state.boosts = state.boosts.saturating_sub(1);
+        }
+    }
+}
+
+// The task queue running right now, if it is latency sensitive. Locks use it to find
+// out whether somebody waiting on them should donate its priority to the holder.
+pub(crate) fn latency_sensitive_task_queue() -> Option<TaskQueueHandle> {
+    if !LOCAL_EX.is_set() {
+        return None;
+    }
+    LOCAL_EX.with(|local_ex| {
+        let queues = local_ex.queues.borrow();
+        let queue = queues.active_executing.as_ref()?.borrow();
+        match queue.io_requirements.latency_req {
+            Latency::Matters(_) => Some(TaskQueueHandle { index: queue.index }),
+            Latency::NotImportant => None,
+        }
+    })
+}
+
+// The task queue running right now, if any
+pub(crate) fn running_task_queue() -> Option<TaskQueueHandle> {
+    if !LOCAL_EX.is_set() {
+        return None;
+    }
+    LOCAL_EX.with(|local_ex| {
+        let queues = local_ex.queues.borrow();
+        let queue = queues.active_executing.as_ref()?.borrow();
+        Some(TaskQueueHandle { index: queue.index })
+    })
+}
+
+// Boosts the priority of a task queue until a matching call to unboost_task_queue.
+// Returns false if the queue was not boosted, in which case it must not be unboosted.
+pub(crate) fn boost_task_queue(handle: TaskQueueHandle) -> bool {
+    LOCAL_EX.is_set() && LOCAL_EX.with(|local_ex| local_ex.queues.borrow_mut().boost(handle.index))
+}
+
+pub(crate) fn unboost_task_queue(handle: TaskQueueHandle) {
+    if LOCAL_EX.is_set() {
+        LOCAL_EX.with(|local_ex| local_ex.queues.borrow_mut().unboost(handle.index))
+    }
 }
 
 /// Single-threaded executor.
@@ -309,386 +906,2188 @@ impl ExecutorQueues {
 #[derive(Debug)]
 pub struct LocalExecutor {
     queues: Rc<RefCell<ExecutorQueues>>,
+    // The earliest deadline of the latency sensitive queues waiting to run
+    latency_deadline: Rc<Cell<Option<Instant>>>,
     parker: parking::Parker,
-    binding: Option<usize>,
+    binding: Cell<Option<usize>>,
     id: usize,
+    shard: Shard,
+    watchdog: RefCell<Option<Watchdog>>,
+    monitor: ExecutorMonitor,
+    // Shared by every handle returned by `migration_handle`
+    migration: RefCell<Option<MigrationHandle>>,
 }
 
-impl LocalExecutor {
-    fn init(&mut self) -> io::Result<()> {
-        if let Some(cpu) = self.binding {
-            bind_to_cpu(cpu)?;
+// Where an executor sits among the executors started together by spawn_shards. Executors
+// created on their own are a set of a single shard.
+#[derive(Debug, Clone)]
+struct Shard {
+    id: usize,
+    bindings: Arc<Vec<Option<usize>>>,
+}
+
+impl Shard {
+    fn standalone(binding: Option<usize>) -> Shard {
+        Shard {
+            id: 0,
+            bindings: Arc::new(vec![binding]),
         }
+    }
+}
 
-        let queues = self.queues.clone();
-        let index = 0;
+// How the thread of a supervised shard ended: the shard id, and the panic it unwound
+// with, if any
+pub(crate) type ShardExit = (usize, std::thread::Result<()>);
 
-        let io_requirements = IoRequirements::new(Latency::NotImportant, 0);
-        self.queues.borrow_mut().available_executors.insert(
-            0,
-            TaskQueue::new(0, "default", 1000, io_requirements, move || {
-                let mut queues = queues.borrow_mut();
-                queues.maybe_activate(index);
-            }),
-        );
-        Ok(())
-    }
+// What a standby executor needs to start running
+struct Activation {
+    fut_gen: Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>> + Send>,
+    started: mpsc::Sender<()>,
+}
 
-    /// Creates a single-threaded executor, optionally bound to a specific CPU
+/// An executor created ahead of time by [`LocalExecutor::spawn_standby`], waiting in its
+/// own thread to be handed work.
+///
+/// Everything that makes creating an executor slow already happened by the time it is
+/// returned: its thread exists and is bound to its CPU, its rings are allocated, and its
+/// task queues and preallocated timers are in place. [`activate`] only has to start
+/// running a future, so a shard that fails can be replaced right away.
+///
+/// Dropping a standby executor that was never activated lets its thread exit.
+///
+/// [`LocalExecutor::spawn_standby`]: struct.LocalExecutor.html#method.spawn_standby
+/// [`activate`]: struct.StandbyExecutor.html#method.activate
+#[derive(Debug)]
+pub struct StandbyExecutor {
+    id: usize,
+    monitor: ExecutorMonitor,
+    activation: mpsc::Sender<Activation>,
+    thread: JoinHandle<()>,
+}
+
+impl StandbyExecutor {
+    /// The id the executor will have once activated. See [`LocalExecutor::id`]
     ///
-    /// # Examples
+    /// [`LocalExecutor::id`]: struct.LocalExecutor.html#method.id
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns a monitor of the executor. See [`ExecutorMonitor`]
     ///
-    /// ```
-    /// use scipio::LocalExecutor;
+    /// [`ExecutorMonitor`]: struct.ExecutorMonitor.html
+    pub fn monitor(&self) -> ExecutorMonitor {
+        self.monitor.clone()
+    }
+
+    /// Runs the future created by `fut_gen` in the executor, like
+    /// [`LocalExecutor::spawn_executor`] would.
     ///
-    /// // executor is a single thread, but not bound to any particular CPU.
-    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// Returns the handle of the thread of the executor, or an error if the executor
+    /// went away.
     ///
-    /// // executor is a a single thread, bound to logical CPU 1.
-    /// let bound_ex = LocalExecutor::new(Some(1)).expect("failed to create local executor");
-    /// ```
-    pub fn new(binding: Option<usize>) -> io::Result<LocalExecutor> {
-        let p = parking::Parker::new();
-        let mut le = LocalExecutor {
-            queues: ExecutorQueues::new(),
-            parker: p,
-            binding,
-            id: EXECUTOR_ID.fetch_add(1, Ordering::Relaxed),
+    /// [`LocalExecutor::spawn_executor`]: struct.LocalExecutor.html#method.spawn_executor
+    pub fn activate<G, F, T>(self, fut_gen: G) -> io::Result<JoinHandle<()>>
+    where
+        G: FnOnce() -> F + std::marker::Send + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        let gone = || io::Error::new(io::ErrorKind::BrokenPipe, "standby executor is gone");
+        let (started, started_rx) = mpsc::channel();
+        let activation = Activation {
+            fut_gen: Box::new(move || {
+                let fut: Pin<Box<dyn Future<Output = ()>>> = Box::pin(async move {
+                    fut_gen().await;
+                });
+                fut
+            }),
+            started,
         };
-
-        le.init()?;
-        Ok(le)
+        self.activation.send(activation).map_err(|_| gone())?;
+        match started_rx.recv() {
+            Ok(()) => Ok(self.thread),
+            Err(_) => Err(gone()),
+        }
     }
+}
 
-    /// Creates a single-threaded executor, optionally bound to a specific CPU, inside
-    /// a newly craeted thread. The parameter `name` specifies the name of the thread.
-    ///
-    /// This is a more ergonomic way to create a thread and then run an executor inside it
-    /// This function panics if creating the thread or the executor fails. If you need more
-    /// fine-grained error handling consider initializing those entities manually.
-    ///
+/// A handle to move an executor to another CPU from any thread, created with
+/// [`LocalExecutor::migration_handle`].
+///
+/// Requests travel to the executor and wake it up if needed, so something that balances
+/// load over a whole machine can move shards around without restarting them.
+///
+/// [`LocalExecutor::migration_handle`]: struct.LocalExecutor.html#method.migration_handle
+#[derive(Debug, Clone)]
+pub struct MigrationHandle {
+    id: usize,
+    sender: futures::channel::mpsc::UnboundedSender<MigrationRequest>,
+}
+
+// Where to move the executor, and where to tell how it went
+type MigrationRequest = (Option<usize>, oneshot::Sender<io::Result<()>>);
+
+impl MigrationHandle {
+    /// The id of the executor this handle moves. See [`LocalExecutor::id`]
     ///
-    /// # Examples
+    /// [`LocalExecutor::id`]: struct.LocalExecutor.html#method.id
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Moves the executor to the CPU `binding`, or lets it run anywhere if it is `None`,
+    /// like [`LocalExecutor::migrate`] does, once the executor gets to it.
     ///
-    /// ```
-    /// use scipio::LocalExecutor;
+    /// Resolves once the executor moved, or to an error if it couldn't, or if it went
+    /// away.
     ///
-    /// // executor is a single thread, but not bound to any particular CPU.
-    /// let handle = LocalExecutor::spawn_executor("myname", None, || async move {
-    ///     println!("hello");
-    /// }).unwrap();
+    /// [`LocalExecutor::migrate`]: struct.LocalExecutor.html#method.migrate
+    pub async fn migrate(&self, binding: Option<usize>) -> io::Result<()> {
+        let gone = || io::Error::new(io::ErrorKind::BrokenPipe, "executor is gone");
+        let (done, done_rx) = oneshot::channel();
+        self.sender
+            .unbounded_send((binding, done))
+            .map_err(|_| gone())?;
+        done_rx.await.map_err(|_| gone())?
+    }
+}
+
+/// Creates a [`LocalExecutor`] step by step: the CPU it is bound to, and the name and
+/// stack size of the thread it gets if it is spawned in a thread of its own.
+///
+/// The executor binds its thread to the CPU before it runs anything, so thread-per-core
+/// designs don't need to pin threads on their own after the fact, racing the first
+/// tasks.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{Local, LocalExecutorBuilder};
+///
+/// let handle = LocalExecutorBuilder::new()
+///     .bind_to_cpu(0)
+///     .name("shard-0")
+///     .stack_size(4 << 20)
+///     .spawn(|| async move {
+///         assert_eq!(Local::binding(), Some(0));
+///     })
+///     .unwrap();
+/// handle.join().unwrap();
+/// ```
+///
+/// [`LocalExecutor`]: struct.LocalExecutor.html
+#[derive(Debug, Default)]
+pub struct LocalExecutorBuilder {
+    config: ExecutorConfig,
+    name: Option<String>,
+    stack_size: Option<usize>,
+}
+
+impl LocalExecutorBuilder {
+    /// Creates a builder of an executor that isn't bound to any CPU, with the default
+    /// configuration.
+    pub fn new() -> LocalExecutorBuilder {
+        LocalExecutorBuilder::default()
+    }
+
+    /// Starts from `config` instead of the default configuration. Settings made with
+    /// this builder before are lost.
+    pub fn config(mut self, config: ExecutorConfig) -> LocalExecutorBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Binds the executor to the logical CPU `cpu`.
+    pub fn bind_to_cpu(mut self, cpu: usize) -> LocalExecutorBuilder {
+        self.config.binding = Some(cpu);
+        self
+    }
+
+    /// Names the thread the executor is spawned in. Threads are named after the
+    /// executor id otherwise.
+    pub fn name(mut self, name: &str) -> LocalExecutorBuilder {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Sets the size of the stack of the thread the executor is spawned in, in bytes.
+    /// The standard library picks it otherwise.
+    pub fn stack_size(mut self, stack_size: usize) -> LocalExecutorBuilder {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Creates the executor in the current thread, which is bound to the CPU if one was
+    /// set. The name and stack size only apply to executors that are spawned.
+    pub fn make(self) -> io::Result<LocalExecutor> {
+        LocalExecutor::from_config(&self.config)
+    }
+
+    /// Creates the executor in a thread of its own, and runs the future created by
+    /// `fut_gen` in it.
     ///
-    /// handle.join().unwrap();
-    /// ```
+    /// Returns once the executor is ready, with the handle of its thread, or fails if the
+    /// thread couldn't be created or bound to the CPU.
     #[must_use = "This spawns an executor on a thread, so you must acquire its handle and then join() to keep it alive"]
-    pub fn spawn_executor<G, F, T>(
-        name: &'static str,
-        binding: Option<usize>,
-        fut_gen: G,
-    ) -> io::Result<JoinHandle<()>>
+    pub fn spawn<G, F, T>(self, fut_gen: G) -> io::Result<JoinHandle<()>>
     where
         G: FnOnce() -> F + std::marker::Send + 'static,
         F: Future<Output = T> + 'static,
     {
-        let id = EXECUTOR_ID.fetch_add(1, Ordering::Relaxed);
+        let LocalExecutorBuilder {
+            config,
+            name,
+            stack_size,
+        } = self;
+        config.validate()?;
+        let shard = Shard::standalone(config.binding);
+        let name = move |id: usize| name.unwrap_or_else(|| format!("executor-{}", id));
+        LocalExecutor::spawn_shard(name, stack_size, config, shard, fut_gen, None, None)
+            .map(|(handle, _)| handle)
+    }
+}
 
-        Builder::new()
-            .name(format!("{}-{}", name, id).to_string())
-            .spawn(move || {
-                let mut le = LocalExecutor {
-                    queues: ExecutorQueues::new(),
-                    parker: parking::Parker::new(),
-                    binding,
-                    id,
-                };
-                le.init().unwrap();
-                le.run(async move {
-                    let task = Task::local(async move {
-                        fut_gen().await;
-                    });
-                    task.await;
-                })
-            })
+/// Creates a pool of executors, one per CPU, each in its own thread and bound to its
+/// CPU, and runs a future in each of them.
+///
+/// By default the pool has an executor for every CPU of the system. The executors are
+/// the shards of the application, and each can find out its position among the others
+/// with [`Local::shard_id`].
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{Local, LocalExecutorPoolBuilder};
+///
+/// let pool = LocalExecutorPoolBuilder::new()
+///     .name("shard")
+///     .spawn(|| async move {
+///         println!("shard {} on cpu {:?}", Local::shard_id(), Local::binding());
+///     })
+///     .unwrap();
+///
+/// for result in pool.join_all() {
+///     result.unwrap();
+/// }
+/// ```
+///
+/// [`Local::shard_id`]: type.Local.html#method.shard_id
+#[derive(Debug)]
+pub struct LocalExecutorPoolBuilder {
+    name: String,
+    cpus: Option<Vec<usize>>,
+    config: ExecutorConfig,
+}
+
+impl Default for LocalExecutorPoolBuilder {
+    fn default() -> Self {
+        LocalExecutorPoolBuilder {
+            name: "executor".to_string(),
+            cpus: None,
+            config: ExecutorConfig::default(),
+        }
     }
+}
 
-    /// Returns a unique identifier for this Executor.
-    ///
-    /// # Examples
-    /// ```
-    /// use scipio::LocalExecutor;
-    ///
-    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
-    /// println!("My ID: {}", local_ex.id());
-    /// ```
-    pub fn id(&self) -> usize {
-        self.id
+impl LocalExecutorPoolBuilder {
+    /// Creates a builder of a pool with an executor for every CPU the process may run on.
+    pub fn new() -> LocalExecutorPoolBuilder {
+        LocalExecutorPoolBuilder::default()
     }
 
-    /// Creates a task queue in the executor.
-    ///
-    /// Returns an opaque handler that can later be used to launch tasks into that queue with spawn_into
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use std::time::Duration;
-    /// use scipio::{LocalExecutor, Latency};
-    ///
-    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
-    ///
-    /// let task_queue = local_ex.create_task_queue(1000, Latency::Matters(Duration::from_secs(1)), "my_tq");
-    /// let task = local_ex.spawn_into(async {
-    ///     println!("Hello world");
-    /// }, task_queue).expect("failed to spawn task");
-    /// ```
-    pub fn create_task_queue(
-        &self,
-        shares: usize,
-        latency: Latency,
-        name: &'static str,
-    ) -> TaskQueueHandle {
-        let queues = self.queues.clone();
-        let index = {
-            let mut ex = queues.borrow_mut();
-            let index = ex.executor_index;
-            ex.executor_index += 1;
-            index
-        };
+    /// Creates an executor for each of `cpus` instead, bound to it. Shard ids follow
+    /// the order of `cpus`.
+    pub fn cpus(mut self, cpus: Vec<usize>) -> LocalExecutorPoolBuilder {
+        self.cpus = Some(cpus);
+        self
+    }
 
-        let io_requirements = IoRequirements::new(latency, index);
-        let tq = TaskQueue::new(index, name, shares, io_requirements, move || {
-            let mut queues = queues.borrow_mut();
-            queues.maybe_activate(index);
-        });
+    /// Names the threads of the executors after `name`, followed by the executor id.
+    pub fn name(mut self, name: &str) -> LocalExecutorPoolBuilder {
+        self.name = name.to_string();
+        self
+    }
 
-        self.queues
-            .borrow_mut()
-            .available_executors
-            .insert(index, tq);
-        TaskQueueHandle { index }
+    /// Configures every executor as `config` says, task queues included. Its binding is
+    /// ignored, as each executor is bound to its own CPU.
+    pub fn config(mut self, config: ExecutorConfig) -> LocalExecutorPoolBuilder {
+        self.config = config;
+        self
     }
 
-    /// Removes a task queue.
+    /// Returns the configuration of the pool as it would be spawned.
+    pub fn pool_config(&self) -> PoolConfig {
+        let cpus = match &self.cpus {
+            Some(cpus) => cpus.clone(),
+            None => sys::allowed_cpus().to_vec(),
+        };
+        PoolConfig {
+            name: self.name.clone(),
+            executors: cpus
+                .into_iter()
+                .map(|cpu| ExecutorConfig {
+                    binding: Some(cpu),
+                    ..self.config.clone()
+                })
+                .collect(),
+        }
+    }
+
+    /// Spawns the executors, each running a future created by a clone of `fut_gen`.
     ///
-    /// The task queue cannot be removed if there are still pending tasks.
-    pub fn remove_task_queue(
-        &self,
-        handle: TaskQueueHandle,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut queues = self.queues.borrow_mut();
+    /// Fails if the pool has no executors, if some of them would be bound to the same
+    /// CPU or one that doesn't exist, or if a thread couldn't be created.
+    #[must_use = "This spawns executors on threads, so you must acquire their handles and then join() to keep them alive"]
+    pub fn spawn<G, F, T>(self, fut_gen: G) -> io::Result<LocalExecutorPool>
+    where
+        G: FnOnce() -> F + Clone + std::marker::Send + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        let config = self.pool_config();
+        config.validate()?;
+        let shards =
+            LocalExecutor::spawn_configured_shards(&config.name, config.executors, fut_gen)?;
+        let (handles, monitors) = shards.into_iter().unzip();
+        Ok(LocalExecutorPool { handles, monitors })
+    }
+}
 
-        if let Some(tq) = queues.available_executors.get(&handle.index) {
-            if tq.borrow().is_active() {
-                return Err(Box::new(QueueStillActiveError::new(handle)));
-            }
-            queues
-                .available_executors
-                .remove(&handle.index)
-                .expect("test already done");
-            return Ok(());
-        }
-        Err(Box::new(QueueNotFoundError::new(handle)))
+/// The executors spawned by a [`LocalExecutorPoolBuilder`]
+///
+/// [`LocalExecutorPoolBuilder`]: struct.LocalExecutorPoolBuilder.html
+#[derive(Debug)]
+pub struct LocalExecutorPool {
+    handles: Vec<JoinHandle<()>>,
+    monitors: Vec<ExecutorMonitor>,
+}
+
+impl LocalExecutorPool {
+    /// How many executors the pool has
+    pub fn len(&self) -> usize {
+        self.handles.len()
     }
 
-    fn get_queue(&self, handle: &TaskQueueHandle) -> Option<Rc<RefCell<TaskQueue>>> {
-        self.queues
-            .borrow()
-            .available_executors
-            .get(&handle.index)
-            .and_then(|x| Some(x.clone()))
+    /// Whether the pool has no executors, which never happens
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
     }
 
-    fn get_executor(&self, handle: &TaskQueueHandle) -> Option<Rc<multitask::LocalExecutor>> {
-        self.get_queue(handle)
-            .and_then(|x| Some(x.borrow().ex.clone()))
+    /// Returns monitors of the executors, indexed by shard id, to find out from any
+    /// thread whether they went away without joining them. See [`ExecutorMonitor`]
+    ///
+    /// [`ExecutorMonitor`]: struct.ExecutorMonitor.html
+    pub fn monitors(&self) -> Vec<ExecutorMonitor> {
+        self.monitors.clone()
     }
 
-    fn current_task_queue(&self) -> TaskQueueHandle {
-        TaskQueueHandle {
-            index: self
-                .queues
-                .borrow()
-                .active_executing
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .index,
-        }
+    /// Waits for every executor to finish, and returns how each of its threads ended,
+    /// indexed by shard id. Executors poisoned by a panic return the panic.
+    pub fn join_all(self) -> Vec<std::thread::Result<()>> {
+        self.handles.into_iter().map(|h| h.join()).collect()
     }
 
-    /// Sets the number of shares used for a particular TaskQueue
-    pub fn set_task_queue_shares(
-        &self,
-        handle: TaskQueueHandle,
-        shares: usize,
-    ) -> Result<(), QueueNotFoundError> {
-        self.get_queue(&handle)
-            .and_then(|tq| Some(tq.borrow_mut().set_shares(shares)))
-            .ok_or(QueueNotFoundError::new(handle))
+    /// Returns the handles of the threads of the executors, indexed by shard id.
+    pub fn into_handles(self) -> Vec<JoinHandle<()>> {
+        self.handles
     }
+}
 
-    /// Gets the number of shares used for a particular TaskQueue
-    pub fn get_task_queue_shares(
-        &self,
-        handle: TaskQueueHandle,
-    ) -> Result<usize, QueueNotFoundError> {
-        self.get_queue(&handle)
-            .and_then(|tq| Some(tq.borrow().shares))
-            .ok_or(QueueNotFoundError::new(handle))
+impl LocalExecutor {
+    fn init(&mut self) -> io::Result<()> {
+        if let Some(cpu) = self.binding.get() {
+            bind_to_cpu(cpu)?;
+        }
+
+        let queues = self.queues.clone();
+        let index = 0;
+
+        let io_requirements = IoRequirements::new(Latency::NotImportant, 0);
+        self.queues.borrow_mut().available_executors.insert(
+            0,
+            TaskQueue::new(0, "default", 1000, io_requirements, move || {
+                let mut queues = queues.borrow_mut();
+                queues.maybe_activate(index);
+            }),
+        );
+        Ok(())
     }
 
-    /// Spawns a task onto the executor.
+    /// Creates a single-threaded executor, optionally bound to a specific CPU
     ///
     /// # Examples
     ///
     /// ```
     /// use scipio::LocalExecutor;
     ///
+    /// // executor is a single thread, but not bound to any particular CPU.
     /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
     ///
-    /// let task = local_ex.spawn(async {
-    ///     println!("Hello world");
-    /// });
+    /// // executor is a a single thread, bound to logical CPU 1.
+    /// let bound_ex = LocalExecutor::new(Some(1)).expect("failed to create local executor");
     /// ```
-    pub fn spawn<T: 'static>(&self, future: impl Future<Output = T> + 'static) -> Task<T> {
-        let ex = self
-            .queues
-            .borrow()
-            .active_executing
-            .as_ref()
-            .and_then(|x| Some(x.borrow().ex.clone()))
-            .or(self.get_executor(&TaskQueueHandle { index: 0 }))
-            .unwrap();
-        Task(ex.spawn(future))
+    pub fn new(binding: Option<usize>) -> io::Result<LocalExecutor> {
+        let id = EXECUTOR_ID.fetch_add(1, Ordering::Relaxed);
+        let mut le = Self::uninit(id, binding, Shard::standalone(binding));
+        le.init()?;
+        Ok(le)
     }
 
-    /// Spawns a task onto the executor, to be run at a particular task queue indicated by the
-    /// TaskQueueHandle
+    // An executor that still has to be init()ed in the thread it runs on
+    fn uninit(id: usize, binding: Option<usize>, shard: Shard) -> LocalExecutor {
+        let queues = ExecutorQueues::new();
+        let latency_deadline = queues.borrow().latency_deadline.clone();
+        LocalExecutor {
+            queues,
+            latency_deadline,
+            parker: parking::Parker::new(),
+            binding: Cell::new(binding),
+            id,
+            shard,
+            watchdog: RefCell::new(None),
+            monitor: ExecutorMonitor::new(id),
+            migration: RefCell::new(None),
+        }
+    }
+
+    /// Creates a single-threaded executor from a configuration, usually obtained from
+    /// another executor with [`config`] or read from a file.
+    ///
+    /// Fails if the configuration does not pass [`ExecutorConfig::validate`].
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::LocalExecutor;
-    ///
-    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
-    /// let handle = local_ex.create_task_queue(1000, scipio::Latency::NotImportant, "test_queue");
+    /// use scipio::{ExecutorConfig, LocalExecutor};
     ///
-    /// let task = local_ex.spawn_into(async {
-    ///     println!("Hello world");
-    /// }, handle).expect("failed to spawn task");
+    /// let config = ExecutorConfig {
+    ///     max_bulk_timer_expirations: 64,
+    ///     ..Default::default()
+    /// };
+    /// let local_ex = LocalExecutor::from_config(&config).expect("invalid configuration");
+    /// assert_eq!(local_ex.config().max_bulk_timer_expirations, 64);
     /// ```
-    pub fn spawn_into<T, F>(
-        &self,
-        future: F,
-        handle: TaskQueueHandle,
-    ) -> Result<Task<T>, QueueNotFoundError>
+    ///
+    /// [`config`]: struct.LocalExecutor.html#method.config
+    /// [`ExecutorConfig::validate`]: struct.ExecutorConfig.html#method.validate
+    pub fn from_config(config: &ExecutorConfig) -> io::Result<LocalExecutor> {
+        config.validate()?;
+        let le = Self::new(config.binding)?;
+        le.apply_config(config);
+        Ok(le)
+    }
+
+    fn apply_config(&self, config: &ExecutorConfig) {
+        for tq in &config.task_queues {
+            let name = intern_queue_name(&tq.name);
+            if tq.ordered {
+                self.create_ordered_task_queue(tq.shares, tq.latency, name);
+            } else {
+                self.create_task_queue(tq.shares, tq.latency, name);
+            }
+        }
+        self.set_latency_target_mode(config.latency_target_mode);
+        self.set_max_bulk_timer_expirations(config.max_bulk_timer_expirations);
+        self.preallocate_timers(config.preallocated_timers);
+        self.set_ring_policy(config.ring_policy);
+        self.set_timer_backend(config.timer_backend);
+        self.set_io_progress_reserve(config.io_progress_reserve);
+        self.set_slow_io_threshold(config.slow_io_threshold);
+        self.set_busy_poll_threshold(config.busy_poll_threshold);
+    }
+
+    /// Returns a snapshot of the configuration of this executor: its binding, the task
+    /// queues created so far besides the default one, and how its reactor is tuned.
+    ///
+    /// The snapshot can be used to create identical executors with [`from_config`].
+    ///
+    /// [`from_config`]: struct.LocalExecutor.html#method.from_config
+    pub fn config(&self) -> ExecutorConfig {
+        let queues = self.queues.borrow();
+        let mut task_queues: Vec<_> = queues
+            .available_executors
+            .values()
+            .map(|tq| tq.borrow())
+            .filter(|tq| tq.index != 0)
+            .map(|tq| (tq.index, tq.to_config()))
+            .collect();
+        task_queues.sort_by_key(|(index, _)| *index);
+
+        let reactor = Reactor::get();
+        ExecutorConfig {
+            binding: self.binding.get(),
+            task_queues: task_queues.into_iter().map(|(_, tq)| tq).collect(),
+            latency_target_mode: queues.latency_target_mode,
+            max_bulk_timer_expirations: reactor.max_bulk_timer_expirations(),
+            preallocated_timers: reactor.timer_stats().capacity(),
+            ring_policy: reactor.ring_policy(),
+            timer_backend: reactor.timer_backend(),
+            io_progress_reserve: queues.io_progress_reserve,
+            slow_io_threshold: self.slow_io_threshold(),
+            busy_poll_threshold: reactor.busy_poll_threshold(),
+        }
+    }
+
+    /// Creates a single-threaded executor, optionally bound to a specific CPU, inside
+    /// a newly craeted thread. The parameter `name` specifies the name of the thread.
+    ///
+    /// This is a more ergonomic way to create a thread and then run an executor inside it
+    /// This function panics if creating the thread or the executor fails. If you need more
+    /// fine-grained error handling consider initializing those entities manually.
+    ///
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// // executor is a single thread, but not bound to any particular CPU.
+    /// let handle = LocalExecutor::spawn_executor("myname", None, || async move {
+    ///     println!("hello");
+    /// }).unwrap();
+    ///
+    /// handle.join().unwrap();
+    /// ```
+    #[must_use = "This spawns an executor on a thread, so you must acquire its handle and then join() to keep it alive"]
+    pub fn spawn_executor<G, F, T>(
+        name: &'static str,
+        binding: Option<usize>,
+        fut_gen: G,
+    ) -> io::Result<JoinHandle<()>>
     where
-        T: 'static,
+        G: FnOnce() -> F + std::marker::Send + 'static,
         F: Future<Output = T> + 'static,
     {
-        self.get_executor(&handle)
-            .and_then(|ex| Some(Task(ex.spawn(future))))
-            .ok_or(QueueNotFoundError::new(handle))
+        let config = ExecutorConfig {
+            binding,
+            ..Default::default()
+        };
+        let name = |id: usize| format!("{}-{}", name, id);
+        let shard = Shard::standalone(binding);
+        Self::spawn_shard(name, None, config, shard, fut_gen, None, None).map(|(handle, _)| handle)
     }
 
-    fn preempt_timer_duration(&self) -> Duration {
-        self.queues.borrow().preempt_timer_duration
+    /// Creates one executor per entry in `bindings`, each in its own thread and bound to
+    /// the CPU in that entry, if any. Every executor runs a future created by a clone of
+    /// `fut_gen`.
+    ///
+    /// The executors are the shards of the application: each of them can find out its
+    /// position among the others with [`Local::shard_id`] and [`Local::shard_count`],
+    /// without the need for thread-local plumbing of its own.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Local, LocalExecutor};
+    ///
+    /// let handles = LocalExecutor::spawn_shards("shard", vec![None; 4], || async move {
+    ///     println!("shard {} of {}", Local::shard_id(), Local::shard_count());
+    /// })
+    /// .unwrap();
+    ///
+    /// for handle in handles {
+    ///     handle.join().unwrap();
+    /// }
+    /// ```
+    ///
+    /// [`Local::shard_id`]: struct.Task.html#method.shard_id
+    /// [`Local::shard_count`]: struct.Task.html#method.shard_count
+    #[must_use = "This spawns executors on threads, so you must acquire their handles and then join() to keep them alive"]
+    pub fn spawn_shards<G, F, T>(
+        name: &'static str,
+        bindings: Vec<Option<usize>>,
+        fut_gen: G,
+    ) -> io::Result<Vec<JoinHandle<()>>>
+    where
+        G: FnOnce() -> F + Clone + std::marker::Send + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        let configs = bindings
+            .into_iter()
+            .map(|binding| ExecutorConfig {
+                binding,
+                ..Default::default()
+            })
+            .collect();
+        let shards = Self::spawn_configured_shards(name, configs, fut_gen)?;
+        Ok(shards.into_iter().map(|(handle, _)| handle).collect())
     }
 
-    fn run_one_task_queue(&self) -> bool {
-        let mut tq = self.queues.borrow_mut();
-        let candidate = tq.active_executors.pop();
+    /// Creates an executor in its own thread as `config` says, but leaves it waiting to be
+    /// activated with [`StandbyExecutor::activate`] instead of running anything.
+    ///
+    /// Returns once the executor is ready, or fails if it couldn't be created. The thread
+    /// is bound to the CPU in `config`, if any, before anything is allocated, so the
+    /// memory of the executor is placed in the NUMA node of that CPU.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{ExecutorConfig, LocalExecutor};
+    ///
+    /// let standby = LocalExecutor::spawn_standby("spare", ExecutorConfig::default()).unwrap();
+    ///
+    /// // later, when a shard fails
+    /// let handle = standby
+    ///     .activate(|| async move {
+    ///         println!("taking over");
+    ///     })
+    ///     .unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    ///
+    /// [`StandbyExecutor::activate`]: struct.StandbyExecutor.html#method.activate
+    pub fn spawn_standby(name: &str, config: ExecutorConfig) -> io::Result<StandbyExecutor> {
+        config.validate()?;
+        let id = EXECUTOR_ID.fetch_add(1, Ordering::Relaxed);
+        let (ready, ready_rx) = mpsc::channel();
+        let (activation, activation_rx) = mpsc::channel::<Activation>();
 
-        match candidate {
-            Some(queue) => {
-                tq.active_executing = Some(queue.clone());
-                drop(tq);
+        let thread = Builder::new()
+            .name(format!("{}-{}", name, id).to_string())
+            .spawn(move || {
+                let binding = config.binding;
+                let mut le = Self::uninit(id, binding, Shard::standalone(binding));
+                // binds the thread first, so what follows is allocated on its NUMA node
+                if let Err(err) = le.init() {
+                    let _ = ready.send(Err(err));
+                    return;
+                }
+                le.apply_config(&config);
+                let _ = ready.send(Ok(le.monitor()));
 
-                let time = Instant::now();
-                loop {
-                    if Reactor::need_preempt() {
-                        break;
+                // the standby was dropped without being activated
+                let activation = match activation_rx.recv() {
+                    Ok(activation) => activation,
+                    Err(_) => return,
+                };
+                let _ = activation.started.send(());
+                let fut_gen = activation.fut_gen;
+                le.run(async move {
+                    let task = Task::local(fut_gen());
+                    task.await;
+                })
+            })?;
+
+        match ready_rx.recv() {
+            Ok(Ok(monitor)) => Ok(StandbyExecutor {
+                id,
+                monitor,
+                activation,
+                thread,
+            }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "standby executor failed to start",
+            )),
+        }
+    }
+
+    /// Creates `shards` executors that share the current thread, and runs a future
+    /// created by a clone of `fut_gen` in each of them until all of them complete.
+    /// Returns the output of each future, indexed by shard id.
+    ///
+    /// The executors are scheduled cooperatively: each of them runs its task queues for
+    /// up to its preemption interval before handing the thread to the next one. They all
+    /// share the reactor of the thread and its rings, but otherwise behave like the
+    /// shards started by [`spawn_shards`]: each has its own task queues, and can find out
+    /// its position among the others with [`Local::shard_id`] and [`Local::shard_count`].
+    ///
+    /// This is useful to test many-shard topologies in a machine with fewer CPUs, and to
+    /// host tenants with little traffic without dedicating a thread to each of them. If
+    /// `binding` is set, the current thread is bound to that CPU.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Local, LocalExecutor};
+    ///
+    /// let ids = LocalExecutor::run_multiplexed(None, 4, || async move {
+    ///     Local::later().await;
+    ///     Local::shard_id()
+    /// })
+    /// .unwrap();
+    ///
+    /// assert_eq!(ids, vec![0, 1, 2, 3]);
+    /// ```
+    ///
+    /// [`spawn_shards`]: struct.LocalExecutor.html#method.spawn_shards
+    /// [`Local::shard_id`]: struct.Task.html#method.shard_id
+    /// [`Local::shard_count`]: struct.Task.html#method.shard_count
+    pub fn run_multiplexed<G, F, T>(
+        binding: Option<usize>,
+        shards: usize,
+        fut_gen: G,
+    ) -> io::Result<Vec<T>>
+    where
+        G: FnOnce() -> F + Clone,
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        if shards == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least one shard is needed",
+            ));
+        }
+
+        let bindings = Arc::new(vec![binding; shards]);
+        let mut executors = Vec::with_capacity(shards);
+        for id in 0..shards {
+            let executor_id = EXECUTOR_ID.fetch_add(1, Ordering::Relaxed);
+            let shard = Shard {
+                id,
+                bindings: bindings.clone(),
+            };
+            let mut le = Self::uninit(executor_id, binding, shard);
+            le.init()?;
+            let fut_gen = fut_gen.clone();
+            let task = le.spawn(async move { fut_gen().await });
+            executors.push((le, Some(task)));
+        }
+
+        let waker = waker_fn(|| {});
+        let cx = &mut Context::from_waker(&waker);
+        let mut outputs: Vec<Option<T>> = (0..shards).map(|_| None).collect();
+
+        loop {
+            let mut ran = false;
+            for ((le, task), output) in executors.iter_mut().zip(outputs.iter_mut()) {
+                LOCAL_EX.set(le, || {
+                    if let Some(t) = task.as_mut() {
+                        if let Poll::Ready(t) = Pin::new(t).poll(cx) {
+                            *output = Some(t);
+                            *task = None;
+                        }
                     }
-                    let mut queue_ref = queue.borrow_mut();
-                    if let Some(r) = queue_ref.get_task() {
-                        Reactor::get().inform_io_requirements(queue_ref.io_requirements);
-                        drop(queue_ref);
-                        r.run();
-                    } else {
-                        break;
+
+                    // Same as in `run`, but for a single turn, so the next executor can
+                    // have the thread.
+                    le.parker.poll_io(le.preempt_timer_duration());
+                    ran |= le.run_one_task_queue();
+                });
+            }
+
+            if outputs.iter().all(|o| o.is_some()) {
+                break;
+            }
+            // Polling the ring on behalf of an executor can wake the tasks of the
+            // executors that already had their turn.
+            if !ran && executors.iter().all(|(le, _)| !le.has_active_queues()) {
+                executors[0].0.parker.park();
+            }
+        }
+
+        Ok(outputs.into_iter().map(|o| o.unwrap()).collect())
+    }
+
+    fn has_active_queues(&self) -> bool {
+        !self.queues.borrow().active_executors.is_empty()
+    }
+
+    pub(crate) fn spawn_configured_shards<G, F, T>(
+        name: &str,
+        configs: Vec<ExecutorConfig>,
+        fut_gen: G,
+    ) -> io::Result<Vec<(JoinHandle<()>, ExecutorMonitor)>>
+    where
+        G: FnOnce() -> F + Clone + std::marker::Send + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        let bindings: Arc<Vec<_>> = Arc::new(configs.iter().map(|c| c.binding).collect());
+        let mut configs = configs.into_iter();
+        Self::start_together(bindings.len(), |id, start| {
+            let shard = Shard {
+                id,
+                bindings: bindings.clone(),
+            };
+            let config = configs.next().unwrap();
+            let name = |id: usize| format!("{}-{}", name, id);
+            Self::spawn_shard(
+                name,
+                None,
+                config,
+                shard,
+                fut_gen.clone(),
+                None,
+                Some(start),
+            )
+        })
+    }
+
+    /// Starts `count` shards with `spawn`, which hands each of them the receiver it waits
+    /// on before running anything. Once all of them are ready they are let go. If one of
+    /// them fails to start, the ones already started are stopped instead, and joined
+    /// before the error is returned.
+    pub(crate) fn start_together<S>(
+        count: usize,
+        mut spawn: impl FnMut(usize, mpsc::Receiver<()>) -> io::Result<(JoinHandle<()>, S)>,
+    ) -> io::Result<Vec<(JoinHandle<()>, S)>> {
+        let mut started = Vec::with_capacity(count);
+        let mut gates = Vec::with_capacity(count);
+        for id in 0..count {
+            let (gate, start) = mpsc::channel();
+            match spawn(id, start) {
+                Ok(shard) => {
+                    started.push(shard);
+                    gates.push(gate);
+                }
+                Err(err) => {
+                    // Dropping the gates tells the shards to stop
+                    drop(gates);
+                    for (handle, _) in started {
+                        let _ = handle.join();
                     }
+                    return Err(err);
                 }
+            }
+        }
+        for gate in gates {
+            let _ = gate.send(());
+        }
+        Ok(started)
+    }
 
-                let (need_repush, last_vruntime) = {
-                    let mut state = queue.borrow_mut();
-                    let last_vruntime = state.account_vruntime(time.elapsed());
-                    (state.is_active(), last_vruntime)
-                };
+    // Like spawn_configured_shards, but for shard `id` of `configs` alone, and telling
+    // `exited` how its thread ended instead of letting a panic unwind out of it. The
+    // shard waits for `start` first, if given, like in start_together.
+    pub(crate) fn spawn_supervised_shard<G, F, T>(
+        name: &str,
+        configs: &[ExecutorConfig],
+        id: usize,
+        fut_gen: G,
+        exited: mpsc::Sender<ShardExit>,
+        start: Option<mpsc::Receiver<()>>,
+    ) -> io::Result<(JoinHandle<()>, ExecutorMonitor)>
+    where
+        G: FnOnce() -> F + std::marker::Send + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        let shard = Shard {
+            id,
+            bindings: Arc::new(configs.iter().map(|c| c.binding).collect()),
+        };
+        let name = |id: usize| format!("{}-{}", name, id);
+        let config = configs[id].clone();
+        Self::spawn_shard(name, None, config, shard, fut_gen, Some(exited), start)
+    }
 
-                let mut tq = self.queues.borrow_mut();
-                tq.active_executing = None;
-                tq.last_vruntime = last_vruntime;
+    // Spawns the thread of an executor, named by `name` after the id of the executor, and
+    // returns once the executor is ready. With `start`, the executor then waits for it
+    // before running `fut_gen`, and goes away without running it if its sender is dropped.
+    fn spawn_shard<G, F, T>(
+        name: impl FnOnce(usize) -> String,
+        stack_size: Option<usize>,
+        config: ExecutorConfig,
+        shard: Shard,
+        fut_gen: G,
+        exited: Option<mpsc::Sender<ShardExit>>,
+        start: Option<mpsc::Receiver<()>>,
+    ) -> io::Result<(JoinHandle<()>, ExecutorMonitor)>
+    where
+        G: FnOnce() -> F + std::marker::Send + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        let id = EXECUTOR_ID.fetch_add(1, Ordering::Relaxed);
+        let shard_id = shard.id;
+        let (ready, ready_rx) = mpsc::channel();
 
-                if need_repush {
-                    tq.active_executors.push(queue);
-                } else {
-                    tq.reevaluate_preempt_timer();
+        let mut builder = Builder::new().name(name(id));
+        if let Some(stack_size) = stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let thread = builder.spawn(move || {
+            let mut le = Self::uninit(id, config.binding, shard);
+            if let Err(err) = le.init() {
+                let _ = ready.send(Err(err));
+                return;
+            }
+            le.apply_config(&config);
+            let _ = ready.send(Ok(le.monitor()));
+            if let Some(start) = start {
+                if start.recv().is_err() {
+                    return;
                 }
-                true
             }
-            None => false,
+            let run = move || {
+                le.run(async move {
+                    let task = Task::local(async move {
+                        fut_gen().await;
+                    });
+                    task.await;
+                })
+            };
+            match exited {
+                None => run(),
+                Some(exited) => {
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run));
+                    let _ = exited.send((shard_id, result));
+                }
+            }
+        })?;
+
+        match ready_rx.recv() {
+            Ok(Ok(monitor)) => Ok((thread, monitor)),
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                Err(err)
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "executor failed to start",
+            )),
         }
     }
 
-    /// Runs the executor until the given future completes.
+    /// Returns a unique identifier for this Executor.
     ///
     /// # Examples
-    ///
     /// ```
     /// use scipio::LocalExecutor;
     ///
     /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// println!("My ID: {}", local_ex.id());
+    /// ```
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns a monitor that tells, from any thread, whether this executor went away,
+    /// and whether it was poisoned by a panic. See [`ExecutorMonitor`]
     ///
-    /// let task = local_ex.spawn(async { 1 + 2 });
-    /// let res = local_ex.run(async { task.await * 2 });
+    /// [`ExecutorMonitor`]: struct.ExecutorMonitor.html
+    pub fn monitor(&self) -> ExecutorMonitor {
+        self.monitor.clone()
+    }
+
+    /// Returns the position of this executor among the shards started together with it
+    /// by [`spawn_shards`] or [`run_multiplexed`]. Executors created on their own are
+    /// shard 0.
     ///
-    /// assert_eq!(res, 6);
-    /// ```
-    pub fn run<T>(&self, future: impl Future<Output = T>) -> T {
-        pin!(future);
+    /// [`spawn_shards`]: struct.LocalExecutor.html#method.spawn_shards
+    /// [`run_multiplexed`]: struct.LocalExecutor.html#method.run_multiplexed
+    pub fn shard_id(&self) -> usize {
+        self.shard.id
+    }
 
-        let waker = waker_fn(|| {});
-        let cx = &mut Context::from_waker(&waker);
+    /// Returns how many shards were started together with this executor by
+    /// [`spawn_shards`]. Executors created on their own are a set of a single shard.
+    ///
+    /// [`spawn_shards`]: struct.LocalExecutor.html#method.spawn_shards
+    pub fn shard_count(&self) -> usize {
+        self.shard.bindings.len()
+    }
 
-        LOCAL_EX.set(self, || loop {
-            if let Poll::Ready(t) = future.as_mut().poll(cx) {
-                break t;
-            }
+    /// Returns the CPU each shard is bound to, if any, indexed by shard id.
+    ///
+    /// These are the CPUs the shards were started on: shards moved since then with
+    /// [`migrate`] tell where they are now with [`binding`].
+    ///
+    /// [`migrate`]: struct.LocalExecutor.html#method.migrate
+    /// [`binding`]: struct.LocalExecutor.html#method.binding
+    pub fn shard_bindings(&self) -> &[Option<usize>] {
+        &self.shard.bindings
+    }
 
-            // We want to do I/O before we call run_one_task queue,
-            // for the benefit of the latency ring. If there are pending
-            // requests that are latency sensitive we want them out of the
-            // ring ASAP (before we run the task queues). We will also use
-            // the opportunity to install the timer.
-            let duration = self.preempt_timer_duration();
-            self.parker.poll_io(duration);
-            if !self.run_one_task_queue() {
-                self.parker.park();
+    /// Returns the CPU this executor is bound to, if any.
+    pub fn binding(&self) -> Option<usize> {
+        self.binding.get()
+    }
+
+    /// Moves this executor to the CPU `binding`, or lets it run on any CPU if it is
+    /// `None`, while it keeps running.
+    ///
+    /// The thread of the executor is bound to the new CPU right away. Its rings, task
+    /// queues, timers and tasks stay as they are: nothing in them is tied to a CPU.
+    ///
+    /// Returns an error, and leaves the executor where it was, if the CPU doesn't exist
+    /// or the thread can't be bound to it. Other threads can move the executor with a
+    /// [`MigrationHandle`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(Some(0)).expect("failed to create local executor");
+    /// local_ex.migrate(None).expect("failed to unbind the executor");
+    /// assert_eq!(local_ex.binding(), None);
+    /// ```
+    ///
+    /// [`MigrationHandle`]: struct.MigrationHandle.html
+    pub fn migrate(&self, binding: Option<usize>) -> io::Result<()> {
+        match binding {
+            Some(cpu) if !sys::allowed_cpus().contains(&cpu) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CPU {} does not exist or is not allowed", cpu),
+                ));
             }
-        })
+            Some(cpu) => bind_to_cpu(cpu)?,
+            None => unbind_from_cpu()?,
+        }
+        self.binding.set(binding);
+        Ok(())
     }
-}
 
-/// A spawned future.
-///
-/// Tasks are also futures themselves and yield the output of the spawned future.
-///
-/// When a task is dropped, its gets canceled and won't be polled again. To cancel a task a bit
-/// more gracefully and wait until it stops running, use the [`cancel()`][`Task::cancel()`] method.
-///
-/// Tasks that panic get immediately canceled. Awaiting a canceled task also causes a panic.
-///
-/// # Examples
+    /// Returns a handle that moves this executor to another CPU from any thread. See
+    /// [`MigrationHandle`]
+    ///
+    /// The handle is served by a task of the executor, spawned the first time a handle
+    /// is requested.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("shard", Some(0), || async move {
+    ///     let migration = scipio::Local::migration_handle();
+    ///     std::thread::spawn(move || {
+    ///         futures::executor::block_on(migration.migrate(None)).unwrap();
+    ///     });
+    ///     while scipio::Local::binding().is_some() {
+    ///         scipio::Local::later().await;
+    ///     }
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    ///
+    /// [`MigrationHandle`]: struct.MigrationHandle.html
+    pub fn migration_handle(&self) -> MigrationHandle {
+        let mut migration = self.migration.borrow_mut();
+        if let Some(handle) = migration.as_ref() {
+            return handle.clone();
+        }
+
+        let (sender, mut receiver) = futures::channel::mpsc::unbounded::<MigrationRequest>();
+        self.spawn(async move {
+            let _ = crate::bridge(async move {
+                while let Some((binding, done)) = receiver.next().await {
+                    let res = LOCAL_EX.with(|local_ex| local_ex.migrate(binding));
+                    let _ = done.send(res);
+                }
+            })
+            .await;
+        })
+        .detach();
+
+        let handle = MigrationHandle {
+            id: self.id,
+            sender,
+        };
+        *migration = Some(handle.clone());
+        handle
+    }
+
+    /// Creates a task queue in the executor.
+    ///
+    /// Returns an opaque handler that can later be used to launch tasks into that queue with spawn_into
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use scipio::{LocalExecutor, Latency};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// let task_queue = local_ex.create_task_queue(1000, Latency::Matters(Duration::from_secs(1)), "my_tq");
+    /// let task = local_ex.spawn_into(async {
+    ///     println!("Hello world");
+    /// }, task_queue).expect("failed to spawn task");
+    /// ```
+    pub fn create_task_queue(
+        &self,
+        shares: usize,
+        latency: Latency,
+        name: &'static str,
+    ) -> TaskQueueHandle {
+        self.new_task_queue(shares, latency, name, false)
+    }
+
+    /// Creates an ordered task queue in the executor.
+    ///
+    /// Tasks spawned into an ordered task queue run one at a time, in the order they were
+    /// spawned: a task only starts once every task spawned into the queue before it has
+    /// completed, or was dropped. Wakeups can't reorder them, so a task can rely on the
+    /// effects of the tasks spawned before it, like a state machine fed by the tasks in
+    /// submission order.
+    ///
+    /// Tasks waiting for their turn don't count as active, and don't take time away from
+    /// other task queues. Everything else works like in a task queue created with
+    /// [`create_task_queue`].
+    ///
+    /// # Tasks spawned from the queue itself
+    ///
+    /// A task spawned into the queue by the task whose turn it is would get its turn
+    /// only after the spawner finished, so a spawner awaiting it would wait forever.
+    /// Those tasks start right away instead, without waiting for a turn and without
+    /// holding up the tasks after them, just like in a task queue that isn't ordered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Latency, Local, LocalExecutor};
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.run(async {
+    ///     let handle = Local::create_ordered_task_queue(1000, Latency::NotImportant, "ordered");
+    ///     let log = Rc::new(RefCell::new(Vec::new()));
+    ///     let mut tasks = Vec::new();
+    ///     for i in 0..3 {
+    ///         let log = log.clone();
+    ///         tasks.push(Local::local_into(async move {
+    ///             log.borrow_mut().push(i);
+    ///             Local::later().await;
+    ///             log.borrow_mut().push(i);
+    ///         }, handle).unwrap());
+    ///     }
+    ///     for task in tasks {
+    ///         task.await;
+    ///     }
+    ///     assert_eq!(*log.borrow(), vec![0, 0, 1, 1, 2, 2]);
+    /// });
+    /// ```
+    ///
+    /// [`create_task_queue`]: struct.LocalExecutor.html#method.create_task_queue
+    pub fn create_ordered_task_queue(
+        &self,
+        shares: usize,
+        latency: Latency,
+        name: &'static str,
+    ) -> TaskQueueHandle {
+        self.new_task_queue(shares, latency, name, true)
+    }
+
+    fn new_task_queue(
+        &self,
+        shares: usize,
+        latency: Latency,
+        name: &'static str,
+        ordered: bool,
+    ) -> TaskQueueHandle {
+        let queues = self.queues.clone();
+        let index = {
+            let mut ex = queues.borrow_mut();
+            let index = ex.executor_index;
+            ex.executor_index += 1;
+            index
+        };
+
+        let io_requirements = IoRequirements::new(latency, index);
+        let tq = TaskQueue::new(index, name, shares, io_requirements, move || {
+            let mut queues = queues.borrow_mut();
+            queues.maybe_activate(index);
+        });
+        if ordered {
+            tq.borrow_mut().ordered = Some(Rc::new(FifoOrder::default()));
+        }
+
+        self.queues
+            .borrow_mut()
+            .available_executors
+            .insert(index, tq);
+        TaskQueueHandle { index }
+    }
+
+    /// Removes a task queue.
+    ///
+    /// The task queue cannot be removed if there are still pending tasks.
+    pub fn remove_task_queue(
+        &self,
+        handle: TaskQueueHandle,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut queues = self.queues.borrow_mut();
+
+        if let Some(tq) = queues.available_executors.get(&handle.index) {
+            if tq.borrow().is_active() {
+                return Err(Box::new(QueueStillActiveError::new(handle)));
+            }
+            queues
+                .available_executors
+                .remove(&handle.index)
+                .expect("test already done");
+            return Ok(());
+        }
+        Err(Box::new(QueueNotFoundError::new(handle)))
+    }
+
+    /// Returns the handle of the task queue named `name`, if there is one.
+    ///
+    /// Task queues created from an [`ExecutorConfig`] are only known by their names, so
+    /// this is how tasks find them.
+    ///
+    /// [`ExecutorConfig`]: struct.ExecutorConfig.html
+    pub fn task_queue_by_name(&self, name: &str) -> Option<TaskQueueHandle> {
+        self.queues
+            .borrow()
+            .available_executors
+            .values()
+            .map(|tq| tq.borrow())
+            .find(|tq| tq.name == name)
+            .map(|tq| TaskQueueHandle { index: tq.index })
+    }
+
+    fn get_queue(&self, handle: &TaskQueueHandle) -> Option<Rc<RefCell<TaskQueue>>> {
+        self.queues
+            .borrow()
+            .available_executors
+            .get(&handle.index)
+            .and_then(|x| Some(x.clone()))
+    }
+
+    fn spawn_in_queue<T: 'static>(
+        queue: &Rc<RefCell<TaskQueue>>,
+        future: impl Future<Output = T> + 'static,
+        priority: TaskPriority,
+    ) -> Task<T> {
+        // The queue can't stay borrowed: spawning activates it
+        let (ex, ordered) = {
+            let tq = queue.borrow();
+            (tq.ex.clone(), tq.ordered.clone())
+        };
+
+        fn spawn<T: 'static>(
+            ex: Rc<multitask::LocalExecutor>,
+            ordered: Option<Rc<FifoOrder>>,
+            future: impl Future<Output = T> + 'static,
+            priority: usize,
+        ) -> Task<T> {
+            match ordered {
+                Some(order) if !order.spawned_in_turn() => {
+                    Task(ex.spawn(order.wrap(future), priority))
+                }
+                _ => Task(ex.spawn(future, priority)),
+            }
+        }
+
+        let priority = priority as usize;
+        #[cfg(feature = "wakeup-tracking")]
+        {
+            if wakeup_tracker::window().is_some() {
+                let name = queue.borrow().name;
+                return spawn(ex, ordered, Tracked::new(name, future), priority);
+            }
+        }
+        spawn(ex, ordered, future, priority)
+    }
+
+    fn current_task_queue(&self) -> TaskQueueHandle {
+        TaskQueueHandle {
+            index: self
+                .queues
+                .borrow()
+                .active_executing
+                .as_ref()
+                .unwrap()
+                .borrow()
+                .index,
+        }
+    }
+
+    /// Sets the number of shares used for a particular TaskQueue
+    pub fn set_task_queue_shares(
+        &self,
+        handle: TaskQueueHandle,
+        shares: usize,
+    ) -> Result<(), QueueNotFoundError> {
+        self.get_queue(&handle)
+            .and_then(|tq| Some(tq.borrow_mut().set_shares(shares)))
+            .ok_or(QueueNotFoundError::new(handle))
+    }
+
+    /// Gets the number of shares used for a particular TaskQueue
+    pub fn get_task_queue_shares(
+        &self,
+        handle: TaskQueueHandle,
+    ) -> Result<usize, QueueNotFoundError> {
+        self.get_queue(&handle)
+            .and_then(|tq| Some(tq.borrow().shares))
+            .ok_or(QueueNotFoundError::new(handle))
+    }
+
+    /// Gets how long the tasks of a particular TaskQueue ran so far.
+    ///
+    /// Comparing how it grows for different queues tells how the CPU is actually split
+    /// between them, which is what their shares are tuned for.
+    pub fn task_queue_runtime(
+        &self,
+        handle: TaskQueueHandle,
+    ) -> Result<Duration, QueueNotFoundError> {
+        self.get_queue(&handle)
+            .map(|tq| Duration::from_micros(tq.borrow().runtime))
+            .ok_or(QueueNotFoundError::new(handle))
+    }
+
+    /// Gets the number of times a particular TaskQueue, marked as [`Latency::Matters`],
+    /// waited longer than its latency target between being woken up and being run.
+    ///
+    /// A growing count means the shares given to the task queues of this executor can't
+    /// honor the latency promised to this one.
+    ///
+    /// [`Latency::Matters`]: enum.Latency.html
+    pub fn task_queue_latency_misses(
+        &self,
+        handle: TaskQueueHandle,
+    ) -> Result<u64, QueueNotFoundError> {
+        self.get_queue(&handle)
+            .and_then(|tq| Some(tq.borrow().latency_misses))
+            .ok_or(QueueNotFoundError::new(handle))
+    }
+
+    /// Gets the number of tasks of a particular TaskQueue that are ready to run and
+    /// waiting for their turn. A task queue that keeps a long backlog is not getting
+    /// enough shares for the work sent its way.
+    pub fn task_queue_runnable_tasks(
+        &self,
+        handle: TaskQueueHandle,
+    ) -> Result<usize, QueueNotFoundError> {
+        self.get_queue(&handle)
+            .and_then(|tq| Some(tq.borrow().ex.runnable_tasks()))
+            .ok_or(QueueNotFoundError::new(handle))
+    }
+
+    /// Sets a handler to be called every time a particular TaskQueue misses its latency
+    /// target, replacing the previous one. The handler runs right before the task queue
+    /// does, so it should be quick.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Latency, LocalExecutor};
+    /// use std::time::Duration;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// let handle = local_ex.create_task_queue(1000, Latency::Matters(Duration::from_millis(1)), "rpc");
+    ///
+    /// local_ex
+    ///     .on_latency_miss(handle, |miss| {
+    ///         eprintln!("{} waited {:?} to run", miss.name(), miss.waited());
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn on_latency_miss<F>(
+        &self,
+        handle: TaskQueueHandle,
+        handler: F,
+    ) -> Result<(), QueueNotFoundError>
+    where
+        F: Fn(&LatencyMiss) + 'static,
+    {
+        let handler = LatencyMissHandler(Rc::new(handler));
+        self.get_queue(&handle)
+            .and_then(|tq| Some(tq.borrow_mut().on_latency_miss = Some(handler)))
+            .ok_or(QueueNotFoundError::new(handle))
+    }
+
+    /// Spawns a task onto the executor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// let task = local_ex.spawn(async {
+    ///     println!("Hello world");
+    /// });
+    /// ```
+    pub fn spawn<T: 'static>(&self, future: impl Future<Output = T> + 'static) -> Task<T> {
+        let queue = self
+            .queues
+            .borrow()
+            .active_executing
+            .clone()
+            .or_else(|| self.get_queue(&TaskQueueHandle { index: 0 }))
+            .unwrap();
+        Self::spawn_in_queue(&queue, future, TaskPriority::Normal)
+    }
+
+    /// Spawns a task onto the executor, with a `priority` among the tasks of its task
+    /// queue. See [`TaskPriority`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TaskPriority};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// let task = local_ex.spawn_with_priority(
+    ///     async {
+    ///         println!("Hello world");
+    ///     },
+    ///     TaskPriority::High,
+    /// );
+    /// ```
+    ///
+    /// [`TaskPriority`]: enum.TaskPriority.html
+    pub fn spawn_with_priority<T: 'static>(
+        &self,
+        future: impl Future<Output = T> + 'static,
+        priority: TaskPriority,
+    ) -> Task<T> {
+        let queue = self
+            .queues
+            .borrow()
+            .active_executing
+            .clone()
+            .or_else(|| self.get_queue(&TaskQueueHandle { index: 0 }))
+            .unwrap();
+        Self::spawn_in_queue(&queue, future, priority)
+    }
+
+    /// Spawns a task onto the executor, to be run at a particular task queue indicated by the
+    /// TaskQueueHandle
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// let handle = local_ex.create_task_queue(1000, scipio::Latency::NotImportant, "test_queue");
+    ///
+    /// let task = local_ex.spawn_into(async {
+    ///     println!("Hello world");
+    /// }, handle).expect("failed to spawn task");
+    /// ```
+    pub fn spawn_into<T, F>(
+        &self,
+        future: F,
+        handle: TaskQueueHandle,
+    ) -> Result<Task<T>, QueueNotFoundError>
+    where
+        T: 'static,
+        F: Future<Output = T> + 'static,
+    {
+        self.spawn_into_with_priority(future, TaskPriority::Normal, handle)
+    }
+
+    /// Spawns a task onto the executor, to be run at a particular task queue indicated by the
+    /// TaskQueueHandle, with a `priority` among the other tasks of that queue. See
+    /// [`TaskPriority`]
+    ///
+    /// [`TaskPriority`]: enum.TaskPriority.html
+    pub fn spawn_into_with_priority<T, F>(
+        &self,
+        future: F,
+        priority: TaskPriority,
+        handle: TaskQueueHandle,
+    ) -> Result<Task<T>, QueueNotFoundError>
+    where
+        T: 'static,
+        F: Future<Output = T> + 'static,
+    {
+        self.get_queue(&handle)
+            .map(|queue| Self::spawn_in_queue(&queue, future, priority))
+            .ok_or(QueueNotFoundError::new(handle))
+    }
+
+    /// Enables or disables the latency target mode.
+    ///
+    /// In this mode the executor measures how long task queues marked as
+    /// [`Latency::Matters`] wait between being woken up and being run. Whenever that
+    /// exceeds their latency target, the preemption interval of competing queues is
+    /// automatically shrunk. Adjustments are reported through [`stats`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_latency_target_mode(true);
+    /// ```
+    ///
+    /// [`Latency::Matters`]: enum.Latency.html
+    /// [`stats`]: struct.LocalExecutor.html#method.stats
+    pub fn set_latency_target_mode(&self, enabled: bool) {
+        let mut queues = self.queues.borrow_mut();
+        queues.latency_target_mode = enabled;
+        if !enabled {
+            queues.preempt_scale = 1;
+            queues.reevaluate_preempt_timer();
+        }
+    }
+
+    /// Sets the I/O progress reserve: the longest a task queue runs before the executor
+    /// goes back to the reactor to collect I/O completions and wake the tasks waiting for
+    /// them, or `None` to only do that when task queues are preempted or run out of
+    /// tasks, which is the default.
+    ///
+    /// Without a reserve, task queues that always have tasks ready to run only give way
+    /// when their preemption timer fires, which can take long when no task queue is
+    /// latency sensitive. Meanwhile completions pile up in the rings, and the tasks
+    /// waiting for them, along with the I/O they would submit next, make no progress.
+    ///
+    /// The executor can only switch between tasks, so a task that never yields still
+    /// keeps the reactor away. How often the reserve cuts a task queue short is reported
+    /// by [`ExecutorStats::io_reserve_breaks`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    /// use std::time::Duration;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_io_progress_reserve(Some(Duration::from_millis(5)));
+    /// ```
+    ///
+    /// [`ExecutorStats::io_reserve_breaks`]: struct.ExecutorStats.html#method.io_reserve_breaks
+    pub fn set_io_progress_reserve(&self, reserve: Option<Duration>) {
+        self.queues.borrow_mut().io_progress_reserve = reserve;
+    }
+
+    /// Sets the maximum number of timers that are expired in a single reactor loop,
+    /// among the timers registered by task queues that are not latency sensitive.
+    ///
+    /// Timers registered by queues marked as [`Latency::Matters`] are always expired
+    /// first and are not subject to this limit, so a burst of bulk timers cannot delay
+    /// them. Bulk timers over the limit fire in the next loop.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_max_bulk_timer_expirations(64);
+    /// ```
+    ///
+    /// [`Latency::Matters`]: enum.Latency.html
+    pub fn set_max_bulk_timer_expirations(&self, max: usize) {
+        Reactor::get().set_max_bulk_timer_expirations(max);
+    }
+
+    /// Sets how the reads and writes of this executor are spread over its io_uring rings.
+    ///
+    /// With [`RingPolicy::SeparateLatency`], the reads and writes of task queues marked
+    /// as [`Latency::Matters`] don't share rings with the bulk transfers of the other
+    /// task queues, so a burst of large writes doesn't hold small reads back. Operations
+    /// already submitted stay in the rings they were submitted to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, RingPolicy};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_ring_policy(RingPolicy::SeparateLatency);
+    /// assert_eq!(local_ex.config().ring_policy, RingPolicy::SeparateLatency);
+    /// ```
+    ///
+    /// [`RingPolicy::SeparateLatency`]: enum.RingPolicy.html#variant.SeparateLatency
+    /// [`Latency::Matters`]: enum.Latency.html
+    pub fn set_ring_policy(&self, policy: RingPolicy) {
+        Reactor::get().set_ring_policy(policy);
+    }
+
+    /// Sets where the timers of this executor are armed. See [`TimerBackend`]
+    ///
+    /// Timers already armed stay where they are until they are reset or fire. Kernels
+    /// that can't take timeouts through io_uring keep using [`TimerBackend::Wheel`],
+    /// which [`timer_backend`] tells.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Timer, TimerBackend};
+    /// use std::time::Duration;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_timer_backend(TimerBackend::Ring);
+    /// local_ex.run(async {
+    ///     Timer::new(Duration::from_millis(10)).await;
+    /// });
+    /// ```
+    ///
+    /// [`TimerBackend`]: enum.TimerBackend.html
+    /// [`TimerBackend::Wheel`]: enum.TimerBackend.html#variant.Wheel
+    /// [`timer_backend`]: struct.LocalExecutor.html#method.timer_backend
+    pub fn set_timer_backend(&self, backend: TimerBackend) {
+        Reactor::get().set_timer_backend(backend);
+    }
+
+    /// Returns where the timers of this executor are armed.
+    pub fn timer_backend(&self) -> TimerBackend {
+        Reactor::get().timer_backend()
+    }
+
+    /// Makes the executor spin instead of going to sleep when it has nothing to run and
+    /// its next timer is due in `threshold` or less, or stops doing so if `threshold`
+    /// is `None`, which is the default.
+    ///
+    /// Going to sleep and waking up again takes the kernel a few hundred microseconds,
+    /// so timers in the tens of microseconds fire that much late. Spinning keeps them
+    /// accurate, at the cost of keeping the CPU busy while waiting: the threshold should
+    /// stay small, and the executor should have its own CPU. I/O keeps being processed
+    /// while spinning.
+    ///
+    /// Only timers in the [`TimerBackend::Wheel`] backend are waited for this way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Timer};
+    /// use std::time::Duration;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_busy_poll_threshold(Some(Duration::from_micros(100)));
+    /// local_ex.run(async {
+    ///     Timer::new(Duration::from_micros(50)).await;
+    /// });
+    /// ```
+    ///
+    /// [`TimerBackend::Wheel`]: enum.TimerBackend.html#variant.Wheel
+    pub fn set_busy_poll_threshold(&self, threshold: Option<Duration>) {
+        Reactor::get().set_busy_poll_threshold(threshold);
+    }
+
+    /// How close the next timer has to be for the executor to spin instead of sleeping,
+    /// if it ever does. See [`set_busy_poll_threshold`].
+    ///
+    /// [`set_busy_poll_threshold`]: struct.LocalExecutor.html#method.set_busy_poll_threshold
+    pub fn busy_poll_threshold(&self) -> Option<Duration> {
+        Reactor::get().busy_poll_threshold()
+    }
+
+    /// Reports every file and network operation that takes `threshold` or longer to
+    /// complete, or stops doing so if `threshold` is `None`, which is the default.
+    ///
+    /// Reports go to the handler set with [`on_slow_io`], or to standard error if there
+    /// is none. They tell what the operation was, the file or peer it was on, its result,
+    /// the task queue and [I/O tag] it was submitted with, and how long it waited to be
+    /// submitted to the kernel apart from how long the kernel took with it.
+    ///
+    /// Network operations are readiness polls, so their time includes waiting for the
+    /// other end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    /// use std::time::Duration;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_slow_io_threshold(Some(Duration::from_millis(50)));
+    /// local_ex.on_slow_io(|slow| eprintln!("{}", slow));
+    /// ```
+    ///
+    /// [`on_slow_io`]: struct.LocalExecutor.html#method.on_slow_io
+    /// [I/O tag]: type.Local.html#method.with_io_tag
+    pub fn set_slow_io_threshold(&self, threshold: Option<Duration>) {
+        slow_io::set_threshold(threshold);
+    }
+
+    /// How long an operation has to take to be reported as slow, if they are. See
+    /// [`set_slow_io_threshold`].
+    ///
+    /// [`set_slow_io_threshold`]: struct.LocalExecutor.html#method.set_slow_io_threshold
+    pub fn slow_io_threshold(&self) -> Option<Duration> {
+        slow_io::threshold()
+    }
+
+    /// Sets a handler to be called for every operation reported by
+    /// [`set_slow_io_threshold`], replacing the previous one.
+    ///
+    /// The handler runs from the reactor after it is done processing completions, so it
+    /// may submit I/O itself, but it should be quick.
+    ///
+    /// [`set_slow_io_threshold`]: struct.LocalExecutor.html#method.set_slow_io_threshold
+    pub fn on_slow_io<F>(&self, handler: F)
+    where
+        F: Fn(&SlowIo) + 'static,
+    {
+        slow_io::set_handler(Some(Rc::new(handler)));
+    }
+
+    /// How many operations were reported as slow in this executor
+    pub fn slow_io_reported(&self) -> u64 {
+        slow_io::reported()
+    }
+
+    /// Lists the I/O operations this executor submitted that did not complete yet,
+    /// oldest first, so a debug endpoint can show what it is waiting on.
+    ///
+    /// Timers are not listed, and neither is anything without the `io-tracing`
+    /// feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Local, LocalExecutor};
+    ///
+    /// let local_ex = LocalExecutor::new(None).unwrap();
+    /// local_ex.run(async {
+    ///     for op in Local::in_flight_io() {
+    ///         println!("{}", op);
+    ///     }
+    /// });
+    /// ```
+    pub fn in_flight_io(&self) -> Vec<InFlightIo> {
+        sys::in_flight_io()
+    }
+
+    /// Tracks the wakers of the tasks spawned from now on, and reports the ones that look
+    /// like they lost a wakeup for `window` or longer, or stops doing so if `window` is
+    /// `None`, which is the default.
+    ///
+    /// A task is reported when it returns `Poll::Pending` without keeping its waker
+    /// anywhere, when nobody wakes it up within `window`, or when it is woken up but not
+    /// polled again within `window`. The second case also catches tasks that just wait
+    /// for longer than that on purpose, so `window` should be longer than any legitimate
+    /// wait. Each task is reported at most once between two polls.
+    ///
+    /// Reports go to the handler set with [`on_lost_wakeup`], or to standard error if
+    /// there is none.
+    ///
+    /// Only available with the `wakeup-tracking` feature, as it costs every poll a few
+    /// atomic operations.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use scipio::LocalExecutor;
+    /// use std::time::Duration;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.track_wakeups(Some(Duration::from_secs(5)));
+    /// local_ex.on_lost_wakeup(|report| eprintln!("{}", report));
+    /// ```
+    ///
+    /// [`on_lost_wakeup`]: struct.LocalExecutor.html#method.on_lost_wakeup
+    #[cfg(feature = "wakeup-tracking")]
+    pub fn track_wakeups(&self, window: Option<Duration>) {
+        if let Some(checker) = wakeup_tracker::set_window(window) {
+            self.spawn(checker).detach();
+        }
+    }
+
+    /// How long a task may wait for a wakeup before being reported, if they are. See
+    /// [`track_wakeups`].
+    ///
+    /// [`track_wakeups`]: struct.LocalExecutor.html#method.track_wakeups
+    #[cfg(feature = "wakeup-tracking")]
+    pub fn wakeup_window(&self) -> Option<Duration> {
+        wakeup_tracker::window()
+    }
+
+    /// Sets a handler to be called for every task reported by [`track_wakeups`],
+    /// replacing the previous one.
+    ///
+    /// [`track_wakeups`]: struct.LocalExecutor.html#method.track_wakeups
+    #[cfg(feature = "wakeup-tracking")]
+    pub fn on_lost_wakeup<F>(&self, handler: F)
+    where
+        F: Fn(&WakeupReport) + 'static,
+    {
+        wakeup_tracker::set_handler(Some(Rc::new(handler)));
+    }
+
+    /// How many tasks were reported as having lost a wakeup in this executor
+    #[cfg(feature = "wakeup-tracking")]
+    pub fn lost_wakeups_reported(&self) -> u64 {
+        wakeup_tracker::reported()
+    }
+
+    /// Restarts the random number generator of this executor from `seed`, so that the
+    /// random choices made by the executor and its tasks repeat from one run to the
+    /// next. See [`Rng`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.seed_rng(42);
+    /// assert_eq!(local_ex.rng().seed(), 42);
+    /// ```
+    ///
+    /// [`Rng`]: struct.Rng.html
+    pub fn seed_rng(&self, seed: u64) {
+        Reactor::get().rng().reseed(seed);
+    }
+
+    /// Returns the random number generator of this executor.
+    pub fn rng(&self) -> &Rng {
+        Reactor::get().rng()
+    }
+
+    /// Hands the file I/O of this executor to `engine`, instead of its io_uring rings, or
+    /// gives it back to the rings with `None`. See [`IoEngine`].
+    ///
+    /// Operations already submitted complete where they were submitted.
+    ///
+    /// [`IoEngine`]: trait.IoEngine.html
+    pub fn set_io_engine(&self, engine: Option<Rc<dyn IoEngine>>) {
+        Reactor::get().set_io_engine(engine);
+    }
+
+    /// The engine set with [`set_io_engine`], if any
+    ///
+    /// [`set_io_engine`]: struct.LocalExecutor.html#method.set_io_engine
+    pub fn io_engine(&self) -> Option<Rc<dyn IoEngine>> {
+        Reactor::get().io_engine()
+    }
+
+    /// Preallocates the memory the reactor needs to keep track of `expected` timers
+    /// armed at the same time, so arming timers in the hot path does not allocate.
+    ///
+    /// This doesn't cover timers armed with [`TimerBackend::Ring`]: the kernel keeps
+    /// track of those, and each of them allocates what it hands to the kernel.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.preallocate_timers(1024);
+    /// assert!(local_ex.timer_stats().capacity() >= 1024);
+    /// ```
+    ///
+    /// [`TimerBackend::Ring`]: enum.TimerBackend.html#variant.Ring
+    pub fn preallocate_timers(&self, expected: usize) {
+        Reactor::get().preallocate_timers(expected);
+    }
+
+    /// Returns statistics about the timers armed in this executor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// println!("high water mark: {}", local_ex.timer_stats().high_water_mark());
+    /// ```
+    pub fn timer_stats(&self) -> TimerStats {
+        Reactor::get().timer_stats()
+    }
+
+    /// Registers a function to call every time the reactor sees the clocks jump, because
+    /// the system was suspended or the wall clock was set. See [`ClockJump`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.on_clock_jump(|jump| eprintln!("clock jumped: {:?}", jump));
+    /// ```
+    ///
+    /// [`ClockJump`]: enum.ClockJump.html
+    pub fn on_clock_jump(&self, handler: impl Fn(ClockJump) + 'static) {
+        Reactor::get().on_clock_jump(handler);
+    }
+
+    /// Returns the scheduling statistics for this executor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// println!("Latency violations: {}", local_ex.stats().wake_latency_violations());
+    /// ```
+    pub fn stats(&self) -> ExecutorStats {
+        let mut stats = self.queues.borrow().stats.clone();
+        let (allocations, hot_path_allocations) = hot_path::allocations();
+        stats.allocations = allocations;
+        stats.hot_path_allocations = hot_path_allocations;
+        let (orphaned_io, total_orphaned_io) = sys::orphaned_io();
+        stats.orphaned_io = orphaned_io;
+        stats.total_orphaned_io = total_orphaned_io;
+        stats
+    }
+
+    /// Sets what happens when the reactor or the scheduler allocate memory while
+    /// processing events and tasks. The default is to only count them.
+    ///
+    /// This only has an effect if [`CountingAllocator`] is installed as the global
+    /// allocator. Panicking is meant as a debugging aid to find out where steady-state
+    /// allocations come from.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{HotPathAllocations, LocalExecutor};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.set_hot_path_allocations(HotPathAllocations::Panic);
+    /// ```
+    ///
+    /// [`CountingAllocator`]: struct.CountingAllocator.html
+    pub fn set_hot_path_allocations(&self, mode: HotPathAllocations) {
+        hot_path::set_mode(mode);
+    }
+
+    /// Starts watching this executor from a separate thread. If its loop goes for longer
+    /// than `timeout` without making progress while it has work to do, `callback` is
+    /// invoked with a report of its state, and decides whether the executor should keep
+    /// running or be terminated.
+    ///
+    /// An executor is typically stuck because a task blocks the thread or runs for long
+    /// without yielding. Terminating it only takes effect once that task gives control
+    /// back to the executor: the thread then unwinds, dropping all its tasks, so a
+    /// supervisor joining it can start a fresh shard.
+    ///
+    /// Setting a watchdog replaces the previous one, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, WatchdogAction};
+    /// use std::time::Duration;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex
+    ///     .set_watchdog(Duration::from_secs(1), |report| {
+    ///         eprintln!("{}", report);
+    ///         WatchdogAction::Continue
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn set_watchdog<F>(&self, timeout: Duration, callback: F) -> io::Result<()>
+    where
+        F: Fn(&WatchdogReport) -> WatchdogAction + Send + 'static,
+    {
+        let watchdog = Watchdog::start(self.id, self.shard.id, timeout, callback)?;
+        watchdog.heartbeat().beat();
+        self.watchdog.replace(Some(watchdog));
+        Ok(())
+    }
+
+    /// Stops watching this executor, if it was being watched.
+    pub fn clear_watchdog(&self) {
+        self.watchdog.replace(None);
+    }
+
+    /// Declares that the current task is about to run a CPU-heavy computation for about
+    /// `expected`. See [`CpuSliceGuard`]
+    ///
+    /// [`CpuSliceGuard`]: struct.CpuSliceGuard.html
+    pub fn cpu_slice(&self, expected: Duration) -> CpuSliceGuard {
+        let heartbeat = self
+            .watchdog
+            .borrow()
+            .as_ref()
+            .map(|watchdog| watchdog.heartbeat().clone());
+        CpuSliceGuard::new(heartbeat, expected)
+    }
+
+    fn with_heartbeat(&self, f: impl FnOnce(&Heartbeat)) {
+        if let Some(watchdog) = self.watchdog.borrow().as_ref() {
+            f(watchdog.heartbeat());
+        }
+    }
+
+    fn preempt_timer_duration(&self) -> Duration {
+        self.queues.borrow().preempt_timer_duration
+    }
+
+    fn run_one_task_queue(&self) -> bool {
+        let scheduler = hot_path::enter("scheduler");
+        let mut tq = self.queues.borrow_mut();
+        let candidate = match tq.take_overdue() {
+            Some(queue) => Some(queue),
+            None => tq.active_executors.pop(),
+        };
+
+        match candidate {
+            Some(queue) => {
+                tq.untrack_latency_deadline(&queue.borrow());
+                let wake_latency = queue.borrow_mut().take_wake_latency();
+                let mut missed = None;
+                if let Some((latency, target)) = wake_latency {
+                    tq.account_wake_latency(latency, target);
+                    if latency > target {
+                        missed = queue.borrow_mut().account_latency_miss(latency, target);
+                    }
+                }
+                tq.active_executing = Some(queue.clone());
+                let io_progress_reserve = tq.io_progress_reserve;
+                // Queues that are latency sensitive themselves are not cut short on
+                // behalf of other latency sensitive queues: the preemption timer,
+                // sized after their targets, takes care of that.
+                let yields_to_latency =
+                    queue.borrow().io_requirements.latency_req == Latency::NotImportant;
+                drop(tq);
+                drop(scheduler);
+
+                if let Some((handler, miss)) = missed {
+                    (handler.0)(&miss);
+                }
+
+                let name = queue.borrow().name;
+                self.with_heartbeat(|heartbeat| heartbeat.enter_task_queue(Some(name)));
+
+                let time = Instant::now();
+                #[cfg_attr(not(feature = "stats"), allow(unused_variables, unused_assignments))]
+                let mut io_reserve_break = false;
+                #[cfg_attr(not(feature = "stats"), allow(unused_variables, unused_assignments))]
+                let mut latency_break = false;
+                let mut ran_any = false;
+                loop {
+                    if Reactor::need_preempt() {
+                        break;
+                    }
+                    // The clock is only read while a latency sensitive queue waits, and
+                    // the queue always gets to run at least one task.
+                    if yields_to_latency && ran_any {
+                        if let Some(deadline) = self.latency_deadline.get() {
+                            if Instant::now() >= deadline {
+                                latency_break = true;
+                                break;
+                            }
+                        }
+                    }
+                    if let Some(reserve) = io_progress_reserve {
+                        if time.elapsed() >= reserve {
+                            io_reserve_break = true;
+                            break;
+                        }
+                    }
+                    self.with_heartbeat(|heartbeat| heartbeat.check_terminated());
+                    let mut queue_ref = queue.borrow_mut();
+                    if let Some(r) = queue_ref.get_task() {
+                        Reactor::get().inform_io_requirements(queue_ref.io_requirements);
+                        drop(queue_ref);
+                        checked_cell::check_held_across_await(name, || r.run());
+                        ran_any = true;
+                    } else {
+                        break;
+                    }
+                }
+
+                self.with_heartbeat(|heartbeat| heartbeat.enter_task_queue(None));
+                let _scheduler = hot_path::enter("scheduler");
+                let (need_repush, last_vruntime) = {
+                    let mut state = queue.borrow_mut();
+                    let last_vruntime = state.account_vruntime(time.elapsed());
+                    (state.is_active(), last_vruntime)
+                };
+
+                let mut tq = self.queues.borrow_mut();
+                tq.active_executing = None;
+                tq.last_vruntime = last_vruntime;
+                stat! {
+                    if io_reserve_break && need_repush {
+                        tq.stats.io_reserve_breaks += 1;
+                    }
+                    if latency_break && need_repush {
+                        tq.stats.latency_preemptions += 1;
+                    }
+                }
+
+                if need_repush {
+                    let mut state = queue.borrow_mut();
+                    state.mark_activated();
+                    tq.track_latency_deadline(&state);
+                    drop(state);
+                    tq.active_executors.push(queue);
+                } else {
+                    tq.reevaluate_preempt_timer();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs the executor until the given future completes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::LocalExecutor;
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// let task = local_ex.spawn(async { 1 + 2 });
+    /// let res = local_ex.run(async { task.await * 2 });
+    ///
+    /// assert_eq!(res, 6);
+    /// ```
+    pub fn run<T>(&self, future: impl Future<Output = T>) -> T {
+        if self.monitor.is_poisoned() {
+            panic!("executor {} was poisoned by a panic", self.id);
+        }
+        // A panic unwinding out of here leaves the executor in an unknown state
+        let _poison = PoisonOnPanic(&self.monitor);
+        pin!(future);
+
+        let waker = waker_fn(|| {});
+        let cx = &mut Context::from_waker(&waker);
+
+        LOCAL_EX.set(self, || loop {
+            self.with_heartbeat(|heartbeat| {
+                heartbeat.check_terminated();
+                heartbeat.beat();
+            });
+            if let Poll::Ready(t) = future.as_mut().poll(cx) {
+                break t;
+            }
+
+            // We want to do I/O before we call run_one_task queue,
+            // for the benefit of the latency ring. If there are pending
+            // requests that are latency sensitive we want them out of the
+            // ring ASAP (before we run the task queues). We will also use
+            // the opportunity to install the timer.
+            let duration = self.preempt_timer_duration();
+            self.parker.poll_io(duration);
+            if !self.run_one_task_queue() {
+                self.with_heartbeat(|heartbeat| heartbeat.idle());
+                self.parker.park();
+            }
+        })
+    }
+}
+
+impl Drop for LocalExecutor {
+    fn drop(&mut self) {
+        self.monitor.finish(std::thread::panicking());
+    }
+}
+
+struct PoisonOnPanic<'a>(&'a ExecutorMonitor);
+
+impl Drop for PoisonOnPanic<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.finish(true);
+        }
+    }
+}
+
+// The monitor of the executor running right now, if any
+pub(crate) fn current_monitor() -> Option<ExecutorMonitor> {
+    if LOCAL_EX.is_set() {
+        Some(LOCAL_EX.with(|local_ex| local_ex.monitor()))
+    } else {
+        None
+    }
+}
+
+/// A spawned future.
+///
+/// Tasks are also futures themselves and yield the output of the spawned future.
+///
+/// When a task is dropped, its gets canceled and won't be polled again. To cancel a task a bit
+/// more gracefully and wait until it stops running, use the [`cancel()`][`Task::cancel()`] method.
+///
+/// Tasks that panic get immediately canceled. Awaiting a canceled task also causes a panic.
+///
+/// # Examples
 ///
 /// ```
 /// use scipio::{LocalExecutor, Task};
@@ -708,132 +3107,517 @@ impl LocalExecutor {
 #[derive(Debug)]
 pub struct Task<T>(multitask::Task<T>);
 
-impl<T> Task<T> {
-    /// Spawns a task onto the current single-threaded executor.
+impl<T> Task<T> {
+    /// Spawns a task onto the current single-threaded executor.
+    ///
+    /// If called from a [`LocalExecutor`], the task is spawned on it.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Task};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     let task = Task::local(async { 1 + 2 });
+    ///     assert_eq!(task.await, 3);
+    /// });
+    /// ```
+    pub fn local(future: impl Future<Output = T> + 'static) -> Task<T>
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.spawn(future))
+        } else {
+            panic!("`Task::local()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Unconditionally yields the current task, moving it back to the end of its queue.
+    /// It is not possible to yield futures that are not spawn'd, as they don't have a task
+    /// associated with them.
+    pub async fn later() {
+        struct Yield {
+            done: Option<()>,
+        }
+
+        // Returns pending once, so we are taken away from the execution queue.
+        // The next time returns Ready, so we can proceed. We don't want to pay
+        // the cost of calling schedule functions
+        impl Future for Yield {
+            type Output = ();
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                match self.done.take() {
+                    Some(_) => {
+                        cx.waker().clone().wake();
+                        Poll::Pending
+                    }
+                    None => Poll::Ready(()),
+                }
+            }
+        }
+        let y = Yield { done: Some(()) };
+        y.await;
+    }
+
+    /// Returns true if the current task queue ran for longer than its time slice, and
+    /// the task running should yield so the executor can run other task queues and poll
+    /// for I/O.
+    ///
+    /// Time slices are kept by a preemption timer in the reactor, sized after the
+    /// latency targets of the active task queues, so this is only a couple of loads from
+    /// memory shared with the kernel. It is cheap enough to be called at every
+    /// iteration of a long CPU-bound loop, unlike [`later`], which forces a reschedule
+    /// every time. Outside of a [`LocalExecutor`] it returns false.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Local, LocalExecutor};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// local_ex.run(async {
+    ///     let mut sum = 0u64;
+    ///     for i in 0..1_000_000u64 {
+    ///         sum = sum.wrapping_add(i);
+    ///         if Local::need_preempt() {
+    ///             Local::later().await;
+    ///         }
+    ///     }
+    ///     println!("{}", sum);
+    /// });
+    /// ```
+    ///
+    /// [`later`]: struct.Task.html#method.later
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    #[inline]
+    pub fn need_preempt() -> bool {
+        LOCAL_EX.is_set() && Reactor::need_preempt()
+    }
+
+    /// Runs futures that may borrow from the stack of the current task, instead of being
+    /// `'static` like spawned tasks have to be.
+    ///
+    /// `f` spawns the futures through the [`Scope`] it is given, and what it returns is
+    /// output once every future spawned in the scope completed. The futures run
+    /// concurrently with each other, as part of the current task, so they run in its task
+    /// queue. Dropping the scope before it completes drops the futures too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Local, LocalExecutor};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     let data = vec![1, 2, 3, 4];
+    ///     let (left, right) = data.split_at(2);
+    ///     let (a, b) = Local::scope(|s| {
+    ///         (
+    ///             s.spawn(async { left.iter().sum::<i32>() }),
+    ///             s.spawn(async { right.iter().sum::<i32>() }),
+    ///         )
+    ///     })
+    ///     .await;
+    ///     assert_eq!(a.await + b.await, 10);
+    /// });
+    /// ```
+    ///
+    /// [`Scope`]: struct.Scope.html
+    pub async fn scope<'a, F, R>(f: F) -> R
+    where
+        F: FnOnce(&Scope<'a>) -> R,
+    {
+        scoped::scope(f).await
+    }
+
+    /// Conditionally yields the current task, moving it back to the end of its queue, if the task
+    /// has run for too long. See [`need_preempt`]
+    ///
+    /// [`need_preempt`]: struct.Task.html#method.need_preempt
+    #[inline]
+    pub async fn yield_if_needed() {
+        if Reactor::need_preempt() {
+            Task::<()>::later().await;
+        }
+    }
+
+    /// Spawns a task onto the current single-threaded executor, in a particular task queue
+    ///
+    /// If called from a [`LocalExecutor`], the task is spawned on it.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Task};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    /// let handle = local_ex.create_task_queue(1000, scipio::Latency::NotImportant, "test_queue");
+    ///
+    /// local_ex.spawn_into(async {
+    ///     let task = Task::local(async { 1 + 2 });
+    ///     assert_eq!(task.await, 3);
+    /// }, handle).expect("failed to spawn task");
+    /// ```
+    pub fn local_into(
+        future: impl Future<Output = T> + 'static,
+        handle: TaskQueueHandle,
+    ) -> Result<Task<T>, QueueNotFoundError>
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.spawn_into(future, handle))
+        } else {
+            panic!("`Task::local()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Spawns a task onto the current single-threaded executor, in the current task
+    /// queue, with a `priority` among the other tasks of that queue. See [`TaskPriority`]
+    ///
+    /// If called from a [`LocalExecutor`], the task is spawned on it.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Task, TaskPriority};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     let urgent = Task::local_with_priority(async { 1 + 2 }, TaskPriority::High);
+    ///     assert_eq!(urgent.await, 3);
+    /// });
+    /// ```
+    ///
+    /// [`TaskPriority`]: enum.TaskPriority.html
+    pub fn local_with_priority(
+        future: impl Future<Output = T> + 'static,
+        priority: TaskPriority,
+    ) -> Task<T>
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.spawn_with_priority(future, priority))
+        } else {
+            panic!("`Task::local_with_priority()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Spawns a task onto the current single-threaded executor, in a particular task
+    /// queue, with a `priority` among the other tasks of that queue. See [`TaskPriority`]
+    ///
+    /// If called from a [`LocalExecutor`], the task is spawned on it.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// [`TaskPriority`]: enum.TaskPriority.html
+    pub fn local_into_with_priority(
+        future: impl Future<Output = T> + 'static,
+        priority: TaskPriority,
+        handle: TaskQueueHandle,
+    ) -> Result<Task<T>, QueueNotFoundError>
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.spawn_into_with_priority(future, priority, handle))
+        } else {
+            panic!("`Task::local_into_with_priority()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Returns the id of the current executor
+    ///
+    /// If called from a [`LocalExecutor`], returns the id of the executor.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Task};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     println!("my ID: {}", Task::<()>::id());
+    /// });
+    /// ```
+    pub fn id() -> usize
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.id())
+        } else {
+            panic!("`Task::id()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Returns the CPU the current executor is bound to, if any. See
+    /// [`LocalExecutor::binding`]
+    ///
+    /// If called from a [`LocalExecutor`], returns its binding.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// [`LocalExecutor::binding`]: struct.LocalExecutor.html#method.binding
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    pub fn binding() -> Option<usize>
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.binding())
+        } else {
+            panic!("`Task::binding()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Moves the current executor to another CPU. See [`LocalExecutor::migrate`]
+    ///
+    /// If called from a [`LocalExecutor`], moves it.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// [`LocalExecutor::migrate`]: struct.LocalExecutor.html#method.migrate
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    pub fn migrate(binding: Option<usize>) -> io::Result<()>
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.migrate(binding))
+        } else {
+            panic!("`Task::migrate()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Returns a handle that moves the current executor to another CPU from any thread.
+    /// See [`LocalExecutor::migration_handle`]
+    ///
+    /// If called from a [`LocalExecutor`], returns its handle.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// [`LocalExecutor::migration_handle`]: struct.LocalExecutor.html#method.migration_handle
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    pub fn migration_handle() -> MigrationHandle
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.migration_handle())
+        } else {
+            panic!("`Task::migration_handle()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Returns a monitor of the current executor, that other threads can use to find out
+    /// whether it went away. See [`ExecutorMonitor`]
+    ///
+    /// If called from a [`LocalExecutor`], returns its monitor.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// [`ExecutorMonitor`]: struct.ExecutorMonitor.html
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    pub fn monitor() -> ExecutorMonitor
+    where
+        T: 'static,
+    {
+        current_monitor().expect("`Task::monitor()` must be called from a `LocalExecutor`")
+    }
+
+    /// Returns the shard id of the current executor. See [`LocalExecutor::spawn_shards`]
+    ///
+    /// If called from a [`LocalExecutor`], returns its shard id.
+    ///
+    /// Otherwise, this method panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Local};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     assert_eq!(Local::shard_id(), 0);
+    /// });
+    /// ```
+    ///
+    /// [`LocalExecutor::spawn_shards`]: struct.LocalExecutor.html#method.spawn_shards
+    pub fn shard_id() -> usize
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.shard_id())
+        } else {
+            panic!("`Task::shard_id()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Returns how many shards the current executor belongs to. See
+    /// [`LocalExecutor::spawn_shards`]
     ///
-    /// If called from a [`LocalExecutor`], the task is spawned on it.
+    /// If called from a [`LocalExecutor`], returns its shard count.
     ///
     /// Otherwise, this method panics.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, Task};
+    /// use scipio::{LocalExecutor, Local};
     ///
     /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
     ///
     /// local_ex.run(async {
-    ///     let task = Task::local(async { 1 + 2 });
-    ///     assert_eq!(task.await, 3);
+    ///     assert_eq!(Local::shard_count(), 1);
     /// });
     /// ```
-    pub fn local(future: impl Future<Output = T> + 'static) -> Task<T>
+    ///
+    /// [`LocalExecutor::spawn_shards`]: struct.LocalExecutor.html#method.spawn_shards
+    pub fn shard_count() -> usize
     where
         T: 'static,
     {
         if LOCAL_EX.is_set() {
-            LOCAL_EX.with(|local_ex| local_ex.spawn(future))
+            LOCAL_EX.with(|local_ex| local_ex.shard_count())
         } else {
-            panic!("`Task::local()` must be called from a `LocalExecutor`")
-        }
-    }
-
-    /// Unconditionally yields the current task, moving it back to the end of its queue.
-    /// It is not possible to yield futures that are not spawn'd, as they don't have a task
-    /// associated with them.
-    pub async fn later() {
-        struct Yield {
-            done: Option<()>,
-        }
-
-        // Returns pending once, so we are taken away from the execution queue.
-        // The next time returns Ready, so we can proceed. We don't want to pay
-        // the cost of calling schedule functions
-        impl Future for Yield {
-            type Output = ();
-
-            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-                match self.done.take() {
-                    Some(_) => {
-                        cx.waker().clone().wake();
-                        Poll::Pending
-                    }
-                    None => Poll::Ready(()),
-                }
-            }
+            panic!("`Task::shard_count()` must be called from a `LocalExecutor`")
         }
-        let y = Yield { done: Some(()) };
-        y.await;
     }
 
-    /// Conditionally yields the current task, moving it back to the end of its queue, if the task
-    /// has run for too long
-    #[inline]
-    pub async fn yield_if_needed() {
-        if Reactor::need_preempt() {
-            Task::<()>::later().await;
+    /// Returns the CPU each shard of the current executor is bound to, if any, indexed
+    /// by shard id.
+    ///
+    /// If called from a [`LocalExecutor`], returns its shard bindings.
+    ///
+    /// Otherwise, this method panics.
+    pub fn shard_bindings() -> Vec<Option<usize>>
+    where
+        T: 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.shard_bindings().to_vec())
+        } else {
+            panic!("`Task::shard_bindings()` must be called from a `LocalExecutor`")
         }
     }
 
-    /// Spawns a task onto the current single-threaded executor, in a particular task queue
+    /// Returns the scheduling statistics of the current executor
     ///
-    /// If called from a [`LocalExecutor`], the task is spawned on it.
+    /// If called from a [`LocalExecutor`], returns its statistics.
     ///
     /// Otherwise, this method panics.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, Task};
+    /// use scipio::{LocalExecutor, Local};
     ///
     /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
-    /// let handle = local_ex.create_task_queue(1000, scipio::Latency::NotImportant, "test_queue");
     ///
-    /// local_ex.spawn_into(async {
-    ///     let task = Task::local(async { 1 + 2 });
-    ///     assert_eq!(task.await, 3);
-    /// }, handle).expect("failed to spawn task");
+    /// local_ex.run(async {
+    ///     println!("adjustments: {}", Local::executor_stats().preempt_adjustments());
+    /// });
     /// ```
-    pub fn local_into(
-        future: impl Future<Output = T> + 'static,
-        handle: TaskQueueHandle,
-    ) -> Result<Task<T>, QueueNotFoundError>
+    pub fn executor_stats() -> ExecutorStats
     where
         T: 'static,
     {
         if LOCAL_EX.is_set() {
-            LOCAL_EX.with(|local_ex| local_ex.spawn_into(future, handle))
+            LOCAL_EX.with(|local_ex| local_ex.stats())
         } else {
-            panic!("`Task::local()` must be called from a `LocalExecutor`")
+            panic!("`Task::executor_stats()` must be called from a `LocalExecutor`")
         }
     }
 
-    /// Returns the id of the current executor
+    /// Returns the random number generator of the current executor. See [`Rng`].
     ///
-    /// If called from a [`LocalExecutor`], returns the id of the executor.
+    /// # Examples
     ///
-    /// Otherwise, this method panics.
+    /// ```
+    /// use scipio::{LocalExecutor, Local};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     let coin = Local::rng().chance(0.5);
+    ///     println!("heads: {}", coin);
+    /// });
+    /// ```
+    ///
+    /// [`Rng`]: struct.Rng.html
+    pub fn rng() -> &'static Rng
+    where
+        T: 'static,
+    {
+        Reactor::get().rng()
+    }
+
+    /// Returns statistics about the timers of the current thread: how many are armed,
+    /// how many fire per reactor loop, and how late they fire.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, Task};
+    /// use scipio::{LocalExecutor, Local};
     ///
     /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
     ///
     /// local_ex.run(async {
-    ///     println!("my ID: {}", Task::<()>::id());
+    ///     println!("worst timer latency: {:?}", Local::timer_stats().max_fire_latency());
     /// });
     /// ```
-    pub fn id() -> usize
+    pub fn timer_stats() -> TimerStats
     where
         T: 'static,
     {
-        if LOCAL_EX.is_set() {
-            LOCAL_EX.with(|local_ex| local_ex.id())
-        } else {
-            panic!("`Task::id()` must be called from a `LocalExecutor`")
-        }
+        Reactor::get().timer_stats()
+    }
+
+    /// Registers a function to call every time the reactor of the current thread sees
+    /// the clocks jump, because the system was suspended or the wall clock was set. See
+    /// [`ClockJump`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{ClockJump, Local, LocalExecutor};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     Local::on_clock_jump(|jump| {
+    ///         if let ClockJump::Suspended(duration) = jump {
+    ///             println!("woke up after {:?}", duration);
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    ///
+    /// [`ClockJump`]: enum.ClockJump.html
+    pub fn on_clock_jump(handler: impl Fn(ClockJump) + 'static)
+    where
+        T: 'static,
+    {
+        Reactor::get().on_clock_jump(handler);
     }
 
     /// Detaches the task to let it keep running in the background.
@@ -873,6 +3657,61 @@ impl<T> Task<T> {
         }
     }
 
+    /// Creates a new ordered task queue, with a given latency hint and the provided name.
+    /// See [`LocalExecutor::create_ordered_task_queue`]
+    ///
+    /// [`LocalExecutor::create_ordered_task_queue`]: struct.LocalExecutor.html#method.create_ordered_task_queue
+    pub fn create_ordered_task_queue(
+        shares: usize,
+        latency: Latency,
+        name: &'static str,
+    ) -> TaskQueueHandle {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.create_ordered_task_queue(shares, latency, name))
+        } else {
+            panic!("`Task::create_ordered_task_queue()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Returns the handle of the task queue named `name` in the current executor, if
+    /// there is one. See [`LocalExecutor::task_queue_by_name`]
+    ///
+    /// [`LocalExecutor::task_queue_by_name`]: struct.LocalExecutor.html#method.task_queue_by_name
+    pub fn task_queue_by_name(name: &str) -> Option<TaskQueueHandle> {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.task_queue_by_name(name))
+        } else {
+            panic!("`Task::task_queue_by_name()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Starts watching the current executor from a separate thread. See
+    /// [`LocalExecutor::set_watchdog`]
+    ///
+    /// [`LocalExecutor::set_watchdog`]: struct.LocalExecutor.html#method.set_watchdog
+    pub fn set_watchdog<F>(timeout: Duration, callback: F) -> io::Result<()>
+    where
+        F: Fn(&WatchdogReport) -> WatchdogAction + Send + 'static,
+    {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.set_watchdog(timeout, callback))
+        } else {
+            panic!("`Task::set_watchdog()` must be called from a `LocalExecutor`")
+        }
+    }
+
+    /// Declares that the current task is about to run a CPU-heavy computation for about
+    /// `expected`. See [`CpuSliceGuard`]
+    ///
+    /// [`CpuSliceGuard`]: struct.CpuSliceGuard.html
+    pub fn cpu_slice(expected: Duration) -> CpuSliceGuard {
+        if LOCAL_EX.is_set() {
+            LOCAL_EX.with(|local_ex| local_ex.cpu_slice(expected))
+        } else {
+            panic!("`Task::cpu_slice()` must be called from a `LocalExecutor`")
+        }
+    }
+
     /// Returns the [`TaskQueueHandle`] that represents the TaskQueue currently running.
     /// This can be passed directly into [`local_into`]. This must be run from a task that
     /// was generated through [`local`] or [`local_into`]
@@ -903,6 +3742,128 @@ impl<T> Task<T> {
         }
     }
 
+    /// Runs `future` under a deadline, failing with [`DeadlineExceeded`] if it doesn't
+    /// complete by then.
+    ///
+    /// The deadline applies to everything `future` does: within it, [`deadline`] and
+    /// [`time_left`] tell how long is left, and nested calls to `with_deadline` or
+    /// [`with_timeout`] can only make the deadline earlier. A request handler can then
+    /// impose a total budget, and the operations it is made of get whatever is left of it
+    /// instead of their own independent timeouts. RPC calls consult it too.
+    ///
+    /// Tasks spawned from `future` don't inherit the deadline.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Local, Timer};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     let deadline = Instant::now() + Duration::from_millis(100);
+    ///     let res = Local::with_deadline(deadline, async {
+    ///         // doesn't get 10 seconds, only what is left of the 100 milliseconds
+    ///         Local::with_timeout(Duration::from_secs(10), async {
+    ///             Timer::new(Duration::from_secs(1)).await;
+    ///         })
+    ///         .await
+    ///     })
+    ///     .await;
+    ///     assert!(res.is_err());
+    /// });
+    /// ```
+    ///
+    /// [`DeadlineExceeded`]: struct.DeadlineExceeded.html
+    /// [`deadline`]: type.Local.html#method.deadline
+    /// [`time_left`]: type.Local.html#method.time_left
+    /// [`with_timeout`]: type.Local.html#method.with_timeout
+    pub fn with_deadline<F: Future>(deadline: Instant, future: F) -> WithDeadline<F> {
+        WithDeadline::new(deadline, future)
+    }
+
+    /// Runs `future` under a deadline `timeout` from now, like [`with_deadline`] does.
+    ///
+    /// [`with_deadline`]: type.Local.html#method.with_deadline
+    pub fn with_timeout<F: Future>(timeout: Duration, future: F) -> WithDeadline<F> {
+        WithDeadline::new(Instant::now() + timeout, future)
+    }
+
+    /// The deadline of the code that is running, if it runs within [`with_deadline`]
+    ///
+    /// [`with_deadline`]: type.Local.html#method.with_deadline
+    pub fn deadline() -> Option<Instant> {
+        deadline::current()
+    }
+
+    /// How long is left until the deadline of the code that is running, if it runs within
+    /// [`with_deadline`]. Zero once the deadline passed.
+    ///
+    /// [`with_deadline`]: type.Local.html#method.with_deadline
+    pub fn time_left() -> Option<Duration> {
+        deadline::current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Runs `future` with an opaque `tag`, like a request or trace id, attached to every
+    /// I/O operation it submits.
+    ///
+    /// The tag travels with the operations through the reactor, so once they complete
+    /// [`io_tag_stats`] tells how many there were and how long the kernel took with them.
+    /// Nested calls replace the tag for as long as the inner future runs. Tasks spawned
+    /// from `future` don't inherit the tag.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Local};
+    ///
+    /// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// local_ex.run(async {
+    ///     Local::with_io_tag(42, async {
+    ///         assert_eq!(Local::io_tag(), Some(42));
+    ///     })
+    ///     .await;
+    /// });
+    /// ```
+    ///
+    /// [`io_tag_stats`]: type.Local.html#method.io_tag_stats
+    pub fn with_io_tag<F: Future>(tag: u64, future: F) -> WithIoTag<F> {
+        WithIoTag::new(tag, future)
+    }
+
+    /// The I/O tag of the code that is running, if it runs within [`with_io_tag`]
+    ///
+    /// [`with_io_tag`]: type.Local.html#method.with_io_tag
+    pub fn io_tag() -> Option<u64> {
+        io_tag::current()
+    }
+
+    /// Statistics about the I/O operations submitted in this thread with `tag`, if any.
+    ///
+    /// Only the most recent few thousand tags are kept around, so long running programs
+    /// don't accumulate them forever.
+    pub fn io_tag_stats(tag: u64) -> Option<IoTagStats> {
+        io_tag::stats(tag)
+    }
+
+    /// Like [`io_tag_stats`], but also forgets about `tag`. Handy once the request it
+    /// stands for is done.
+    ///
+    /// [`io_tag_stats`]: type.Local.html#method.io_tag_stats
+    pub fn take_io_tag_stats(tag: u64) -> Option<IoTagStats> {
+        io_tag::take_stats(tag)
+    }
+
+    /// Lists the I/O operations submitted in this thread that did not complete yet,
+    /// oldest first. See [`LocalExecutor::in_flight_io`].
+    ///
+    /// [`LocalExecutor::in_flight_io`]: struct.LocalExecutor.html#method.in_flight_io
+    pub fn in_flight_io() -> Vec<InFlightIo> {
+        sys::in_flight_io()
+    }
+
     /// Cancels the task and waits for it to stop running.
     ///
     /// Returns the task's output if it was completed just before it got canceled, or [`None`] if
@@ -933,46 +3894,205 @@ impl<T> Task<T> {
     pub async fn cancel(self) -> Option<T> {
         self.0.cancel().await
     }
+
+    /// Waits up to `timeout` for the task to complete.
+    ///
+    /// Fails with [`DeadlineExceeded`] if the task didn't complete in time, in which case
+    /// it keeps running, and can still be canceled or awaited. Shutdown paths can then
+    /// give up on tasks that are stuck instead of waiting for them forever.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Task, Timer};
+    /// use std::time::Duration;
+    ///
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     let mut task = Task::local(async {
+    ///         Timer::new(Duration::from_secs(10)).await;
+    ///     });
+    ///     if task.join_timeout(Duration::from_millis(10)).await.is_err() {
+    ///         task.cancel().await;
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`DeadlineExceeded`]: struct.DeadlineExceeded.html
+    pub async fn join_timeout(&mut self, timeout: Duration) -> Result<T, DeadlineExceeded> {
+        WithDeadline::new(Instant::now() + timeout, self).await
+    }
 }
 
 impl<T> Future for Task<T> {
     type Output = T;
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        Pin::new(&mut self.0).poll(cx)
-    }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+#[test]
+fn create_and_destroy_executor() {
+    let mut var = Rc::new(RefCell::new(0));
+    let local_ex = LocalExecutor::new(None).unwrap();
+
+    let varclone = var.clone();
+    local_ex.run(async move {
+        let mut m = varclone.borrow_mut();
+        *m += 10;
+    });
+
+    let v = Rc::get_mut(&mut var).unwrap();
+    let v = v.replace(0);
+    assert_eq!(v, 10);
+}
+
+#[test]
+fn create_fail_to_bind() {
+    // If you have a system with 4 billion CPUs let me know and I will
+    // update this test.
+    if let Ok(_) = LocalExecutor::new(Some(usize::MAX)) {
+        panic!("Should have failed");
+    }
+}
+
+#[test]
+fn create_and_bind() {
+    if let Err(x) = LocalExecutor::new(Some(0)) {
+        panic!("got error {:?}", x);
+    }
+}
+
+#[test]
+fn pool_spawns_one_bound_executor_per_cpu() {
+    let builder = LocalExecutorPoolBuilder::new();
+    assert_eq!(
+        builder.pool_config().executors.len(),
+        sys::allowed_cpus().len()
+    );
+
+    let pool = LocalExecutorPoolBuilder::new()
+        .cpus(vec![0])
+        .name("pool")
+        .config(ExecutorConfig {
+            task_queues: vec![TaskQueueConfig {
+                name: "background".to_string(),
+                shares: 100,
+                latency: Latency::NotImportant,
+                ordered: false,
+            }],
+            ..Default::default()
+        })
+        .spawn(|| async move {
+            assert_eq!(Task::<()>::binding(), Some(0));
+            assert_eq!(Task::<()>::shard_count(), 1);
+            assert!(Task::<()>::task_queue_by_name("background").is_some());
+        })
+        .unwrap();
+    assert_eq!(pool.len(), 1);
+    for result in pool.join_all() {
+        result.unwrap();
+    }
+
+    let shared = LocalExecutorPoolBuilder::new()
+        .cpus(vec![0, 0])
+        .spawn(|| async move {});
+    assert!(shared.is_err());
+}
+
+#[test]
+fn builder_spawns_bound_and_named() {
+    let handle = LocalExecutorBuilder::new()
+        .bind_to_cpu(0)
+        .name("pinned")
+        .stack_size(1 << 20)
+        .spawn(|| async move {
+            assert_eq!(Task::<()>::binding(), Some(0));
+            assert_eq!(std::thread::current().name(), Some("pinned"));
+            let cpuset = nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0)).unwrap();
+            assert!(cpuset.is_set(0).unwrap());
+            assert!(!cpuset.is_set(1).unwrap_or(false));
+        })
+        .unwrap();
+    handle.join().unwrap();
+
+    let spawned = LocalExecutorBuilder::new()
+        .bind_to_cpu(usize::MAX)
+        .spawn(|| async move {
+            panic!("should not run");
+        });
+    assert!(spawned.is_err());
+
+    let local_ex = LocalExecutorBuilder::new().bind_to_cpu(0).make().unwrap();
+    assert_eq!(local_ex.binding(), Some(0));
 }
 
 #[test]
-fn create_and_destroy_executor() {
-    let mut var = Rc::new(RefCell::new(0));
+fn task_priorities_order_tasks_within_a_queue() {
     let local_ex = LocalExecutor::new(None).unwrap();
-
-    let varclone = var.clone();
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let o = order.clone();
     local_ex.run(async move {
-        let mut m = varclone.borrow_mut();
-        *m += 10;
+        let mut tasks = Vec::new();
+        for (name, priority) in &[
+            ("low", TaskPriority::Low),
+            ("normal", TaskPriority::Normal),
+            ("high", TaskPriority::High),
+            ("default", TaskPriority::default()),
+        ] {
+            let o = o.clone();
+            tasks.push(Task::local_with_priority(
+                async move {
+                    o.borrow_mut().push(*name);
+                    // Once woken up again, a task goes back to its own priority
+                    Task::<()>::later().await;
+                    o.borrow_mut().push(*name);
+                },
+                *priority,
+            ));
+        }
+        for task in tasks {
+            task.await;
+        }
     });
-
-    let v = Rc::get_mut(&mut var).unwrap();
-    let v = v.replace(0);
-    assert_eq!(v, 10);
+    assert_eq!(
+        *order.borrow(),
+        vec!["high", "high", "normal", "default", "normal", "default", "low", "low"]
+    );
 }
 
 #[test]
-fn create_fail_to_bind() {
-    // If you have a system with 4 billion CPUs let me know and I will
-    // update this test.
-    if let Ok(_) = LocalExecutor::new(Some(usize::MAX)) {
-        panic!("Should have failed");
-    }
-}
+fn task_queue_shares_change_at_runtime() {
+    let local_ex = LocalExecutor::new(None).unwrap();
+    let busy = local_ex.create_task_queue(100, Latency::NotImportant, "busy");
+    let idle = local_ex.create_task_queue(100, Latency::NotImportant, "idle");
+    local_ex.run(async move {
+        busy.set_shares(1000).unwrap();
+        assert_eq!(busy.shares().unwrap(), 1000);
+        idle.set_shares(0).unwrap();
+        assert_eq!(idle.shares().unwrap(), 1);
+        assert_eq!(busy.get_task_queue_shares().unwrap(), 1000);
 
-#[test]
-fn create_and_bind() {
-    if let Err(x) = LocalExecutor::new(Some(0)) {
-        panic!("got error {:?}", x);
-    }
+        Task::local_into(
+            async {
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(5) {}
+            },
+            busy,
+        )
+        .unwrap()
+        .await;
+        assert!(busy.runtime().unwrap() >= Duration::from_millis(5));
+        assert_eq!(idle.runtime().unwrap(), Duration::from_secs(0));
+    });
+
+    let config = local_ex.config();
+    assert_eq!(config.task_queues[0].shares, 1000);
+    assert_eq!(config.task_queues[1].shares, 1);
+    let gone = TaskQueueHandle { index: 100 };
+    assert!(local_ex.task_queue_runtime(gone).is_err());
 }
 
 #[test]
@@ -1091,6 +4211,31 @@ fn task_with_latency_requirements() {
     });
 }
 
+#[test]
+fn need_preempt_fires_in_cpu_bound_loops() {
+    let local_ex = LocalExecutor::new(None).unwrap();
+    assert!(!Task::<()>::need_preempt());
+
+    let latency =
+        local_ex.create_task_queue(1, Latency::Matters(Duration::from_millis(2)), "testlat");
+    let spin = local_ex
+        .spawn_into(
+            async {
+                let start = Instant::now();
+                while !Task::<()>::need_preempt() {
+                    if start.elapsed().as_secs() > 1 {
+                        panic!("Never received preempt signal");
+                    }
+                }
+                // The time slice is over, so yielding now lets the reactor run
+                Task::<()>::yield_if_needed().await;
+            },
+            latency,
+        )
+        .unwrap();
+    local_ex.run(spin);
+}
+
 #[test]
 fn current_task_queue_matches() {
     use crate::Local;
@@ -1202,3 +4347,578 @@ fn test_detach() {
         Timer::new(std::time::Duration::from_micros(100)).await;
     });
 }
+
+#[test]
+#[cfg(feature = "stats")]
+fn latency_target_mode_adjusts_preemption() {
+    use crate::Local;
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    local_ex.set_latency_target_mode(true);
+
+    local_ex.run(async {
+        let not_latency = Local::create_task_queue(1, Latency::NotImportant, "test");
+        let latency =
+            Local::create_task_queue(1, Latency::Matters(Duration::from_millis(1)), "testlat");
+
+        let spinner = Local::local_into(
+            async move {
+                // Wake the latency queue and then hog the CPU way past its target.
+                let lat = Local::local_into(async {}, latency).unwrap();
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(20) {}
+                lat.await;
+            },
+            not_latency,
+        )
+        .unwrap();
+        spinner.await;
+
+        let stats = Local::executor_stats();
+        assert!(stats.wake_latency_violations() >= 1);
+        assert!(stats.preempt_adjustments() >= 1);
+        assert!(stats.max_wake_latency() >= Duration::from_millis(20));
+    });
+}
+
+#[test]
+fn latency_misses_are_counted_per_queue() {
+    use crate::Local;
+    use std::cell::Cell;
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    let not_latency = local_ex.create_task_queue(1, Latency::NotImportant, "test");
+    let latency =
+        local_ex.create_task_queue(1, Latency::Matters(Duration::from_millis(1)), "testlat");
+
+    let reported = Rc::new(Cell::new(Duration::from_secs(0)));
+    let r = reported.clone();
+    local_ex
+        .on_latency_miss(latency, move |miss| {
+            assert_eq!(miss.name(), "testlat");
+            assert_eq!(miss.target(), Duration::from_millis(1));
+            r.set(miss.waited());
+        })
+        .unwrap();
+
+    local_ex.run(async move {
+        let spinner = Local::local_into(
+            async move {
+                let lat = Local::local_into(async {}, latency).unwrap();
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(20) {}
+                lat.await;
+            },
+            not_latency,
+        )
+        .unwrap();
+        spinner.await;
+
+        assert!(latency.latency_misses().unwrap() >= 1);
+        assert_eq!(not_latency.latency_misses().unwrap(), 0);
+    });
+    assert!(reported.get() >= Duration::from_millis(20));
+}
+
+#[test]
+fn spawn_shards_know_their_place() {
+    use crate::Local;
+    use std::sync::mpsc;
+
+    let (sender, receiver) = mpsc::channel();
+    let handles =
+        LocalExecutor::spawn_shards("shard", vec![None, None, None], move || async move {
+            let topology = (Local::shard_id(), Local::shard_count());
+            sender.send(topology).unwrap();
+        })
+        .unwrap();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let mut topology: Vec<_> = receiver.try_iter().collect();
+    topology.sort();
+    assert_eq!(topology, vec![(0, 3), (1, 3), (2, 3)]);
+}
+
+#[test]
+fn multiplexed_shards_share_the_thread() {
+    use crate::{Local, Timer};
+
+    let thread = std::thread::current().id();
+    let turn = Rc::new(RefCell::new(0));
+    let start = Instant::now();
+    let outputs = LocalExecutor::run_multiplexed(None, 3, move || async move {
+        assert_eq!(std::thread::current().id(), thread);
+        // Timers of all shards are armed at the same time
+        Timer::new(Duration::from_millis(100)).await;
+
+        // Each shard waits for the previous one, so they must make progress concurrently
+        let id = Local::shard_id();
+        while *turn.borrow() != id {
+            Local::later().await;
+        }
+        *turn.borrow_mut() += 1;
+        (id, Local::shard_count())
+    })
+    .unwrap();
+
+    assert_eq!(outputs, vec![(0, 3), (1, 3), (2, 3)]);
+    assert!(start.elapsed() < Duration::from_millis(300));
+    assert!(LocalExecutor::run_multiplexed(None, 0, || async {}).is_err());
+}
+
+#[test]
+fn ordered_task_queues_start_tasks_in_spawn_order() {
+    use crate::{Local, Timer};
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    local_ex.run(async {
+        let handle = Local::create_ordered_task_queue(1000, Latency::NotImportant, "ordered");
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for i in 0..4u64 {
+            let log = log.clone();
+            tasks.push(
+                Local::local_into(
+                    async move {
+                        log.borrow_mut().push(("start", i));
+                        // later tasks sleep less, so they would finish first if they
+                        // were allowed to start
+                        Timer::new(Duration::from_millis(10 * (4 - i))).await;
+                        log.borrow_mut().push(("end", i));
+                    },
+                    handle,
+                )
+                .unwrap(),
+            );
+        }
+        // a task dropped before its turn doesn't hold up the ones after it
+        let log2 = log.clone();
+        drop(
+            Local::local_into(async move { log2.borrow_mut().push(("start", 99)) }, handle)
+                .unwrap(),
+        );
+        let log2 = log.clone();
+        tasks.push(
+            Local::local_into(async move { log2.borrow_mut().push(("start", 4)) }, handle).unwrap(),
+        );
+        for task in tasks {
+            task.await;
+        }
+        let mut expected = Vec::new();
+        for i in 0..4 {
+            expected.push(("start", i));
+            expected.push(("end", i));
+        }
+        expected.push(("start", 4));
+        assert_eq!(*log.borrow(), expected);
+    });
+}
+
+#[test]
+fn ordered_tasks_can_await_the_tasks_they_spawn_into_their_queue() {
+    use crate::Local;
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    local_ex.run(async {
+        let handle = Local::create_ordered_task_queue(1000, Latency::NotImportant, "ordered");
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let l = log.clone();
+        let parent = Local::local_into(
+            async move {
+                l.borrow_mut().push("parent");
+                let l2 = l.clone();
+                let child = Local::local_into(
+                    async move {
+                        l2.borrow_mut().push("child");
+                    },
+                    handle,
+                )
+                .unwrap();
+                child.await;
+                l.borrow_mut().push("parent done");
+            },
+            handle,
+        )
+        .unwrap();
+        // spawned from outside the queue, so it still waits for its turn
+        let l = log.clone();
+        let next = Local::local_into(async move { l.borrow_mut().push("next") }, handle).unwrap();
+
+        next.await;
+        parent.await;
+        assert_eq!(
+            *log.borrow(),
+            vec!["parent", "child", "parent done", "next"]
+        );
+    });
+}
+
+#[test]
+fn io_progress_reserve_cuts_busy_queues_short() {
+    use crate::{Local, Timer};
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    local_ex.set_io_progress_reserve(Some(Duration::from_millis(1)));
+    local_ex.run(async {
+        let busy = Local::create_task_queue(1000, Latency::NotImportant, "busy");
+        let waiter = Local::create_task_queue(1000, Latency::NotImportant, "waiter");
+        let done = Rc::new(RefCell::new(Vec::new()));
+
+        let d = done.clone();
+        let busy = Local::local_into(
+            async move {
+                // always ready to run, so the queue never runs out of tasks
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(100) {
+                    Local::later().await;
+                }
+                d.borrow_mut().push("busy");
+            },
+            busy,
+        )
+        .unwrap();
+        let d = done.clone();
+        let waiter = Local::local_into(
+            async move {
+                Timer::new(Duration::from_millis(1)).await;
+                d.borrow_mut().push("waiter");
+            },
+            waiter,
+        )
+        .unwrap();
+
+        busy.await;
+        waiter.await;
+        assert_eq!(*done.borrow(), vec!["waiter", "busy"]);
+    });
+    #[cfg(feature = "stats")]
+    assert!(local_ex.stats().io_reserve_breaks() > 0);
+}
+
+#[test]
+fn latency_sensitive_queues_preempt_bulk_queues() {
+    use crate::Local;
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    local_ex.run(async {
+        let bulk = Local::create_task_queue(1000, Latency::NotImportant, "bulk");
+        let latency =
+            Local::create_task_queue(1000, Latency::Matters(Duration::from_millis(5)), "latency");
+        let done = Rc::new(RefCell::new(Vec::new()));
+
+        let d = done.clone();
+        let bulk = Local::local_into(
+            async move {
+                let d2 = d.clone();
+                // spawned from within the bulk queue, so it becomes runnable while
+                // the bulk queue is still executing
+                let spawned = Instant::now();
+                let sensitive = Local::local_into(
+                    async move {
+                        d2.borrow_mut().push("latency");
+                        spawned.elapsed()
+                    },
+                    latency,
+                )
+                .unwrap();
+                // The bulk queue never runs out of tasks, and nothing else would cut it
+                // short before its one second preemption timer fires.
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(200) {
+                    Local::later().await;
+                }
+                d.borrow_mut().push("bulk");
+                sensitive.await
+            },
+            bulk,
+        )
+        .unwrap();
+
+        let waited = bulk.await;
+        assert_eq!(*done.borrow(), vec!["latency", "bulk"]);
+        // Well above the target, so the test is not flaky on busy machines, but far
+        // below what the latency queue waits if it is not let in on time
+        assert!(waited < Duration::from_millis(100), "waited {:?}", waited);
+    });
+    #[cfg(feature = "stats")]
+    assert!(local_ex.stats().latency_preemptions() > 0);
+}
+
+#[test]
+fn lock_holders_inherit_waiter_priority() {
+    use crate::{Local, Semaphore};
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    let bulk = local_ex.create_task_queue(1, Latency::NotImportant, "bulk");
+    let latency =
+        local_ex.create_task_queue(1, Latency::Matters(Duration::from_millis(1)), "testlat");
+
+    local_ex.run(async move {
+        let sem = Rc::new(Semaphore::new(1));
+        let s = sem.clone();
+        let holder = Local::local_into(
+            async move {
+                let _permit = s.acquire_permit(1).await.unwrap();
+                for _ in 0..10 {
+                    Local::later().await;
+                }
+            },
+            bulk,
+        )
+        .unwrap();
+        let s = sem.clone();
+        let waiter = Local::local_into(
+            async move {
+                Local::later().await;
+                let _permit = s.acquire_permit(1).await.unwrap();
+            },
+            latency,
+        )
+        .unwrap();
+
+        holder.await;
+        waiter.await;
+        #[cfg(feature = "stats")]
+        assert_eq!(Local::executor_stats().priority_donations(), 1);
+    });
+    assert_eq!(local_ex.get_queue(&bulk).unwrap().borrow().boosts, 0);
+}
+
+#[test]
+fn acquirers_inherit_waiter_priority() {
+    use crate::{Local, Semaphore};
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    let bulk = local_ex.create_task_queue(1, Latency::NotImportant, "bulk");
+    let latency =
+        local_ex.create_task_queue(1, Latency::Matters(Duration::from_millis(1)), "testlat");
+
+    local_ex.run(async move {
+        let sem = Rc::new(Semaphore::new(1));
+        let s = sem.clone();
+        let holder = Local::local_into(
+            async move {
+                s.acquire(1).await.unwrap();
+                for _ in 0..10 {
+                    Local::later().await;
+                }
+                s.signal(1);
+            },
+            bulk,
+        )
+        .unwrap();
+        let s = sem.clone();
+        let waiter = Local::local_into(
+            async move {
+                Local::later().await;
+                s.acquire(1).await.unwrap();
+                s.signal(1);
+            },
+            latency,
+        )
+        .unwrap();
+
+        holder.await;
+        waiter.await;
+        #[cfg(feature = "stats")]
+        assert_eq!(Local::executor_stats().priority_donations(), 1);
+    });
+    assert_eq!(local_ex.get_queue(&bulk).unwrap().borrow().boosts, 0);
+}
+
+#[test]
+fn standby_executors_start_warm() {
+    use crate::Local;
+
+    let config = ExecutorConfig {
+        task_queues: vec![TaskQueueConfig {
+            name: "warm".to_string(),
+            shares: 100,
+            latency: Latency::NotImportant,
+            ordered: false,
+        }],
+        ..Default::default()
+    };
+    let standby = LocalExecutor::spawn_standby("standby", config).unwrap();
+    let monitor = standby.monitor();
+    assert!(monitor.is_alive());
+
+    let (sender, receiver) = mpsc::channel();
+    let id = standby.id();
+    let handle = standby
+        .activate(move || async move {
+            let queue = Local::task_queue_by_name("warm");
+            sender.send((Local::id(), queue.is_some())).unwrap();
+        })
+        .unwrap();
+    handle.join().unwrap();
+    assert_eq!(receiver.recv().unwrap(), (id, true));
+    assert!(!monitor.is_alive());
+
+    // nothing to do for a standby that is dropped
+    let standby = LocalExecutor::spawn_standby("standby", ExecutorConfig::default()).unwrap();
+    let monitor = standby.monitor();
+    drop(standby);
+    while monitor.is_alive() {
+        std::thread::yield_now();
+    }
+    assert!(!monitor.is_poisoned());
+}
+
+#[test]
+fn busy_poll_fires_short_timers() {
+    use crate::Timer;
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    local_ex.set_busy_poll_threshold(Some(Duration::from_micros(500)));
+    assert_eq!(
+        local_ex.config().busy_poll_threshold,
+        Some(Duration::from_micros(500))
+    );
+
+    local_ex.run(async {
+        for _ in 0..10 {
+            let start = Instant::now();
+            Timer::new(Duration::from_micros(50)).await;
+            assert!(start.elapsed() >= Duration::from_micros(50));
+        }
+        // longer timers still sleep
+        let start = Instant::now();
+        Timer::new(Duration::from_millis(5)).await;
+        assert!(start.elapsed() >= Duration::from_millis(5));
+    });
+
+    local_ex.set_busy_poll_threshold(None);
+    assert_eq!(local_ex.busy_poll_threshold(), None);
+}
+
+#[test]
+fn busy_poll_ends_with_its_timer_when_no_io_arrives() {
+    use crate::Timer;
+
+    let local_ex = LocalExecutor::new(None).unwrap();
+    local_ex.set_busy_poll_threshold(Some(Duration::from_millis(1)));
+
+    local_ex.run(async {
+        // Nothing but the timers themselves wakes the reactor up, so each spin has to
+        // end right after its timer, not some time after the threshold
+        let start = Instant::now();
+        for _ in 0..100 {
+            Timer::new(Duration::from_micros(200)).await;
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(elapsed < Duration::from_millis(100), "took {:?}", elapsed);
+    });
+}
+
+#[test]
+fn executors_migrate_while_running() {
+    use crate::Local;
+
+    let handle = LocalExecutor::spawn_executor("migrating", Some(0), || async move {
+        assert_eq!(Local::binding(), Some(0));
+        assert!(Local::migrate(Some(libc::CPU_SETSIZE as usize)).is_err());
+        assert_eq!(Local::binding(), Some(0));
+
+        Local::migrate(None).unwrap();
+        assert_eq!(Local::binding(), None);
+
+        // moved from another thread, while tasks keep running
+        let migration = Local::migration_handle();
+        let ticker = Local::local(async {
+            for _ in 0..10 {
+                Local::later().await;
+            }
+        });
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            futures::executor::block_on(migration.migrate(Some(0))).unwrap();
+            let err = futures::executor::block_on(migration.migrate(Some(usize::MAX)));
+            sender.send(err.unwrap_err().kind()).unwrap();
+        });
+        let err = loop {
+            match receiver.try_recv() {
+                Ok(err) => break err,
+                Err(_) => Local::later().await,
+            }
+        };
+        assert_eq!(err, io::ErrorKind::InvalidInput);
+        assert_eq!(Local::binding(), Some(0));
+        ticker.await;
+    })
+    .unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn configured_queue_names_are_interned() {
+    let config = ExecutorConfig {
+        task_queues: vec![TaskQueueConfig {
+            name: "interned".to_string(),
+            shares: 100,
+            latency: Latency::NotImportant,
+            ordered: false,
+        }],
+        ..Default::default()
+    };
+    for _ in 0..2 {
+        let local_ex = LocalExecutor::from_config(&config).unwrap();
+        assert!(local_ex.task_queue_by_name("interned").is_some());
+    }
+    // the name was leaked only the first time
+    let name = String::from("interned");
+    assert_eq!(
+        intern_queue_name(&name).as_ptr(),
+        intern_queue_name("interned").as_ptr()
+    );
+}
+
+#[test]
+fn spawned_executors_report_failures_to_start() {
+    // there is no such CPU to bind to
+    let res = LocalExecutor::spawn_executor("unbound", Some(usize::MAX >> 1), || async {});
+    assert!(res.is_err());
+}
+
+#[test]
+fn shards_started_before_a_failure_are_stopped() {
+    use std::sync::atomic::AtomicBool;
+
+    let ran = Arc::new(AtomicBool::new(false));
+    let r = ran.clone();
+    let configs = vec![
+        ExecutorConfig::default(),
+        // there is no such CPU to bind to
+        ExecutorConfig {
+            binding: Some(usize::MAX >> 1),
+            ..Default::default()
+        },
+    ];
+    let res = LocalExecutor::spawn_configured_shards("partial", configs, move || async move {
+        r.store(true, Ordering::Relaxed);
+    });
+    assert!(res.is_err());
+    // the first shard was joined without running anything
+    assert!(!ran.load(Ordering::Relaxed));
+}
+
+#[test]
+fn pool_monitors_report_poisoned_executors() {
+    let pool = LocalExecutorPoolBuilder::new()
+        .cpus(vec![0])
+        .spawn(|| async move {
+            Task::<()>::later().await;
+            panic!("the shard went away");
+        })
+        .unwrap();
+    let monitors = pool.monitors();
+    assert_eq!(monitors.len(), 1);
+
+    let results = pool.join_all();
+    assert!(results[0].is_err());
+    assert!(monitors[0].is_poisoned());
+}
@@ -0,0 +1,228 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::{Async, Timer};
+use futures::future::{self, Either};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+/// An event loop owned by a foreign library, like the ones of libuv or glib, that can be
+/// hosted inside an executor with an [`ExternalLoopDriver`].
+///
+/// Such loops usually multiplex their own sources into a single backend file descriptor
+/// that becomes readable when they have events to process, and may also have timers of
+/// their own. Mapping for the most common loops:
+///
+/// | Method           | libuv                           | glib                                 |
+/// |------------------|---------------------------------|--------------------------------------|
+/// | `backend_fd`     | `uv_backend_fd`                 | `g_main_context_query` poll fds      |
+/// | `next_timeout`   | `uv_backend_timeout`            | `g_main_context_prepare` timeout     |
+/// | `dispatch`       | `uv_run(loop, UV_RUN_NOWAIT)`   | `g_main_context_iteration(ctx, 0)`   |
+///
+/// [`ExternalLoopDriver`]: struct.ExternalLoopDriver.html
+pub trait ExternalLoop {
+    /// The file descriptor that becomes readable when the loop has events to process.
+    /// It must stay open and stay the same for as long as the loop is driven.
+    fn backend_fd(&self) -> RawFd;
+
+    /// How long until the loop needs to run again even if its backend file descriptor
+    /// does not become readable, usually because of its own timers. `None` means that
+    /// only the backend file descriptor can give the loop more work.
+    fn next_timeout(&self) -> Option<Duration>;
+
+    /// Runs the callbacks of the events that are ready, without blocking. Returns false
+    /// once the loop has nothing left to do and should no longer be driven.
+    fn dispatch(&mut self) -> bool;
+}
+
+// The backend file descriptor belongs to the external loop, so it must not be closed
+// when the driver goes away.
+#[derive(Debug)]
+struct BackendFd(RawFd);
+
+impl AsRawFd for BackendFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// Drives an [`ExternalLoop`] from a task, so foreign libraries that bring their own
+/// event loop can live inside a shard.
+///
+/// The driver registers the backend file descriptor of the loop with the reactor of the
+/// executor and only dispatches the loop when it is readable or when the loop's own
+/// timeout expires. It is meant to be spawned in a task queue with few shares and
+/// [`Latency::NotImportant`], so the callbacks of the foreign library run when the
+/// executor is otherwise idle.
+///
+/// # Examples
+///
+/// ```no_run
+/// use scipio::{ExternalLoop, ExternalLoopDriver, Latency, Local, LocalExecutor};
+/// use std::os::unix::io::RawFd;
+/// use std::time::Duration;
+///
+/// struct VendorLoop {
+///     fd: RawFd,
+/// }
+///
+/// impl ExternalLoop for VendorLoop {
+///     fn backend_fd(&self) -> RawFd {
+///         self.fd
+///     }
+///
+///     fn next_timeout(&self) -> Option<Duration> {
+///         None
+///     }
+///
+///     fn dispatch(&mut self) -> bool {
+///         // Call into the C library here
+///         true
+///     }
+/// }
+///
+/// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+/// let idle = local_ex.create_task_queue(1, Latency::NotImportant, "vendor");
+///
+/// local_ex.run(async move {
+///     let driver = ExternalLoopDriver::new(VendorLoop { fd: 0 }).unwrap();
+///     Local::local_into(async move { driver.run().await }, idle)
+///         .unwrap()
+///         .detach();
+/// });
+/// ```
+///
+/// [`ExternalLoop`]: trait.ExternalLoop.html
+/// [`Latency::NotImportant`]: enum.Latency.html#variant.NotImportant
+#[derive(Debug)]
+pub struct ExternalLoopDriver<L> {
+    backend: Async<BackendFd>,
+    external: L,
+}
+
+impl<L: ExternalLoop> ExternalLoopDriver<L> {
+    /// Registers the backend file descriptor of `external` with the reactor of the
+    /// current executor.
+    pub fn new(external: L) -> io::Result<ExternalLoopDriver<L>> {
+        Ok(ExternalLoopDriver {
+            backend: Async::new(BackendFd(external.backend_fd()))?,
+            external,
+        })
+    }
+
+    /// Gets a reference to the external loop
+    pub fn get_ref(&self) -> &L {
+        &self.external
+    }
+
+    /// Gets a mutable reference to the external loop
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.external
+    }
+
+    /// Drives the external loop until [`dispatch`] returns false, and returns it.
+    ///
+    /// The loop is dispatched once right away, so the events that were pending before
+    /// it started to be driven are not missed.
+    ///
+    /// [`dispatch`]: trait.ExternalLoop.html#tymethod.dispatch
+    pub async fn run(mut self) -> io::Result<L> {
+        while self.external.dispatch() {
+            let readable = self.backend.readable();
+            futures::pin_mut!(readable);
+            match self.external.next_timeout() {
+                Some(timeout) => {
+                    let timer = Timer::new(timeout);
+                    if let Either::Left((res, _)) = future::select(readable, timer).await {
+                        res?;
+                    }
+                }
+                None => readable.await?,
+            }
+        }
+        Ok(self.external)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Local;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct PipeLoop {
+        fds: [RawFd; 2],
+        received: Rc<RefCell<Vec<u8>>>,
+        timeouts: usize,
+    }
+
+    impl PipeLoop {
+        fn new(received: Rc<RefCell<Vec<u8>>>) -> PipeLoop {
+            let mut fds = [0; 2];
+            let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) };
+            assert_eq!(ret, 0);
+            PipeLoop {
+                fds,
+                received,
+                timeouts: 0,
+            }
+        }
+    }
+
+    impl Drop for PipeLoop {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fds[0]);
+                libc::close(self.fds[1]);
+            }
+        }
+    }
+
+    impl ExternalLoop for PipeLoop {
+        fn backend_fd(&self) -> RawFd {
+            self.fds[0]
+        }
+
+        fn next_timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(5))
+        }
+
+        fn dispatch(&mut self) -> bool {
+            let mut buf = [0u8; 16];
+            let ret = unsafe { libc::read(self.fds[0], buf.as_mut_ptr() as _, buf.len()) };
+            if ret > 0 {
+                self.received
+                    .borrow_mut()
+                    .extend_from_slice(&buf[..ret as usize]);
+            } else {
+                self.timeouts += 1;
+            }
+            self.received.borrow().len() < 3
+        }
+    }
+
+    #[test]
+    fn external_loop_is_driven() {
+        test_executor!(async move {
+            let received = Rc::new(RefCell::new(Vec::new()));
+            let external = PipeLoop::new(received.clone());
+            let writer = external.fds[1];
+            let driver = Local::local(ExternalLoopDriver::new(external).unwrap().run());
+
+            for byte in 1..=3u8 {
+                Timer::new(Duration::from_millis(20)).await;
+                let ret = unsafe { libc::write(writer, &byte as *const u8 as _, 1) };
+                assert_eq!(ret, 1);
+            }
+
+            let external = driver.await.unwrap();
+            assert_eq!(*received.borrow(), vec![1, 2, 3]);
+            // The loop's own timeout kept it running while the pipe was quiet
+            assert!(external.timeouts > 0);
+        });
+    }
+}
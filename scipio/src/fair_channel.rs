@@ -0,0 +1,489 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::bridge::bridge;
+use crate::monitor::Owner;
+use futures::channel::mpsc;
+use futures::future::{self, poll_fn, Either};
+use futures::task::noop_waker_ref;
+use futures::{SinkExt, StreamExt};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// The messages of a single producer, as seen by the receiver
+#[derive(Debug)]
+struct Lane<T> {
+    name: String,
+    weight: u32,
+    receiver: mpsc::Receiver<T>,
+    sent: Arc<AtomicU64>,
+    received: u64,
+    // How many more messages the lane can deliver before its turn ends
+    credit: u32,
+    // The executor the producer sends from
+    producer: Owner,
+}
+
+/// Creates a channel that many producers, usually in different executors, use to feed a
+/// single consumer, without one of them starving the others.
+///
+/// Every producer gets a queue of its own, created with [`FairProducers::producer`], in
+/// which up to `capacity` messages can wait before it has to wait too. The
+/// [`FairReceiver`] takes turns among the queues that have messages, and in its turn
+/// each producer delivers as many messages as its weight, so a producer that floods the
+/// channel only fills its own queue and delays nobody else. This is deficit round robin
+/// with messages of equal cost.
+///
+/// Both sides watch the executor the other side is used from. If the executor of the
+/// receiver goes away, for instance because it panicked, producers fail to send. If the
+/// executor of a producer goes away, the receiver takes the messages it left and stops
+/// waiting for more, as if the producer had been dropped.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{fair_channel, LocalExecutor};
+///
+/// let (producers, mut receiver) = fair_channel::<u64>(16);
+///
+/// let mut handles = Vec::new();
+/// for shard in 0..2 {
+///     let mut sender = producers.producer(&format!("shard-{}", shard), 1);
+///     handles.push(
+///         LocalExecutor::spawn_executor("producer", None, move || async move {
+///             sender.send(shard).await.unwrap();
+///         })
+///         .unwrap(),
+///     );
+/// }
+/// drop(producers);
+///
+/// let local_ex = LocalExecutor::new(None).unwrap();
+/// local_ex.run(async move {
+///     let mut total = 0;
+///     while let Some(value) = receiver.recv().await.unwrap() {
+///         total += value;
+///     }
+///     assert_eq!(total, 1);
+/// });
+/// for handle in handles {
+///     handle.join().unwrap();
+/// }
+/// ```
+///
+/// [`FairProducers::producer`]: struct.FairProducers.html#method.producer
+/// [`FairReceiver`]: struct.FairReceiver.html
+pub fn fair_channel<T>(capacity: usize) -> (FairProducers<T>, FairReceiver<T>) {
+    let (lanes, new_lanes) = mpsc::unbounded();
+    let owner = Owner::default();
+    (
+        FairProducers {
+            capacity,
+            lanes,
+            receiver: owner.clone(),
+        },
+        FairReceiver {
+            lanes: Vec::new(),
+            new_lanes,
+            registering: true,
+            current: 0,
+            owner,
+        },
+    )
+}
+
+/// Creates the producers of a [`fair_channel`]. It can be cloned and sent to other
+/// executors.
+///
+/// The channel is closed once this, all its clones and all the producers created with it
+/// are gone.
+///
+/// [`fair_channel`]: fn.fair_channel.html
+#[derive(Debug)]
+pub struct FairProducers<T> {
+    capacity: usize,
+    lanes: mpsc::UnboundedSender<Lane<T>>,
+    receiver: Owner,
+}
+
+impl<T> Clone for FairProducers<T> {
+    fn clone(&self) -> Self {
+        FairProducers {
+            capacity: self.capacity,
+            lanes: self.lanes.clone(),
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+impl<T> FairProducers<T> {
+    /// Adds a producer to the channel, with a queue of its own.
+    ///
+    /// In each of its turns, the producer delivers up to `weight` messages to the
+    /// receiver, so a producer of weight 2 gets twice the share of one of weight 1 when
+    /// both have messages waiting. A weight of 0 is taken as 1. The `name` identifies the
+    /// producer in the [`FairReceiver::stats`].
+    ///
+    /// [`FairReceiver::stats`]: struct.FairReceiver.html#method.stats
+    pub fn producer(&self, name: &str, weight: u32) -> FairSender<T> {
+        let (sender, receiver) = mpsc::channel(self.capacity.saturating_sub(1));
+        let sent = Arc::new(AtomicU64::new(0));
+        let owner = Owner::default();
+        // If the receiver is gone the sender finds out on its first send
+        let _ = self.lanes.unbounded_send(Lane {
+            name: name.to_string(),
+            weight: weight.max(1),
+            receiver,
+            sent: sent.clone(),
+            received: 0,
+            credit: 0,
+            producer: owner.clone(),
+        });
+        FairSender {
+            sender,
+            sent,
+            owner,
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+/// A producer of a [`fair_channel`], created with [`FairProducers::producer`]
+///
+/// [`fair_channel`]: fn.fair_channel.html
+/// [`FairProducers::producer`]: struct.FairProducers.html#method.producer
+#[derive(Debug)]
+pub struct FairSender<T> {
+    sender: mpsc::Sender<T>,
+    sent: Arc<AtomicU64>,
+    owner: Owner,
+    receiver: Owner,
+}
+
+impl<T> FairSender<T> {
+    /// Sends `message` to the receiver, waiting for room in this producer's queue if it
+    /// is full.
+    ///
+    /// Fails with [`io::ErrorKind::BrokenPipe`] if the receiver, or the executor it is
+    /// used from, is gone.
+    ///
+    /// [`io::ErrorKind::BrokenPipe`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub async fn send(&mut self, message: T) -> io::Result<()> {
+        self.owner.claim();
+        let receiver = &self.receiver;
+        let send = self.sender.send(message);
+        futures::pin_mut!(send);
+        let gone = poll_fn(|cx| receiver.poll_gone(cx));
+        match bridge(future::select(send, gone)).await? {
+            Either::Left((Ok(()), _)) => {}
+            Either::Left((Err(_), _)) => {
+                receiver.check()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "the receiver is gone",
+                ));
+            }
+            Either::Right((gone, _)) => return Err(gone.into()),
+        }
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Sends `message` to the receiver if there is room for it in this producer's queue,
+    /// or gives it back otherwise.
+    pub fn try_send(&mut self, message: T) -> Result<(), T> {
+        self.owner.claim();
+        self.sender
+            .try_send(message)
+            .map_err(|err| err.into_inner())?;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Statistics about one of the producers of a [`fair_channel`], as returned by
+/// [`FairReceiver::stats`].
+///
+/// [`fair_channel`]: fn.fair_channel.html
+/// [`FairReceiver::stats`]: struct.FairReceiver.html#method.stats
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProducerStats {
+    name: String,
+    weight: u32,
+    depth: u64,
+    received: u64,
+}
+
+impl ProducerStats {
+    /// The name the producer was created with
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The weight the producer was created with
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// How many messages of the producer are waiting for the receiver
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+
+    /// How many messages of the producer the receiver got so far
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+}
+
+/// The receiving side of a [`fair_channel`]
+///
+/// [`fair_channel`]: fn.fair_channel.html
+#[derive(Debug)]
+pub struct FairReceiver<T> {
+    lanes: Vec<Lane<T>>,
+    new_lanes: mpsc::UnboundedReceiver<Lane<T>>,
+    // Whether producers can still be added
+    registering: bool,
+    // The lane whose turn it is
+    current: usize,
+    // The executor the receiver is used from
+    owner: Owner,
+}
+
+impl<T> FairReceiver<T> {
+    /// Waits for the next message, from the producer whose turn it is.
+    ///
+    /// Returns `None` once all producers are gone and all their messages were received.
+    pub async fn recv(&mut self) -> io::Result<Option<T>> {
+        self.owner.claim();
+        bridge(poll_fn(|cx| self.poll_recv(cx))).await
+    }
+
+    /// Returns the next message, if one is already available.
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.owner.claim();
+        match self.poll_recv(&mut Context::from_waker(noop_waker_ref())) {
+            Poll::Ready(message) => message,
+            Poll::Pending => None,
+        }
+    }
+
+    /// Returns statistics about the producers that are still around, or still have
+    /// messages waiting, in the order they were added.
+    pub fn stats(&mut self) -> Vec<ProducerStats> {
+        self.add_new_lanes(&mut Context::from_waker(noop_waker_ref()));
+        self.lanes
+            .iter()
+            .map(|lane| ProducerStats {
+                name: lane.name.clone(),
+                weight: lane.weight,
+                depth: lane
+                    .sent
+                    .load(Ordering::Relaxed)
+                    .saturating_sub(lane.received),
+                received: lane.received,
+            })
+            .collect()
+    }
+
+    fn add_new_lanes(&mut self, cx: &mut Context<'_>) {
+        while self.registering {
+            match self.new_lanes.poll_next_unpin(cx) {
+                Poll::Ready(Some(lane)) => self.lanes.push(lane),
+                Poll::Ready(None) => self.registering = false,
+                Poll::Pending => break,
+            }
+        }
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.add_new_lanes(cx);
+        // Every lane gets a look, starting with the one whose turn it is
+        let mut empty = 0;
+        while empty < self.lanes.len() {
+            let lane = &mut self.lanes[self.current];
+            if lane.credit == 0 {
+                lane.credit = lane.weight;
+            }
+            match lane.receiver.poll_next_unpin(cx) {
+                Poll::Ready(Some(message)) => {
+                    lane.received += 1;
+                    lane.credit -= 1;
+                    if lane.credit == 0 {
+                        self.next_turn();
+                    }
+                    return Poll::Ready(Some(message));
+                }
+                Poll::Ready(None) => {
+                    self.lanes.remove(self.current);
+                    if self.current == self.lanes.len() {
+                        self.current = 0;
+                    }
+                }
+                Poll::Pending if lane.producer.poll_gone(cx).is_ready() => {
+                    // Nothing more is coming from the executor of the producer. Closing
+                    // the lane hands out what it sent last, then ends it.
+                    lane.receiver.close();
+                }
+                Poll::Pending => {
+                    // Idle producers don't save up credit for later
+                    lane.credit = 0;
+                    self.next_turn();
+                    empty += 1;
+                }
+            }
+        }
+        if self.lanes.is_empty() && !self.registering {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn next_turn(&mut self) {
+        self.current = (self.current + 1) % self.lanes.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ExecutorGone;
+    use crate::LocalExecutor;
+
+    #[test]
+    fn fair_channel_takes_turns_by_weight() {
+        let (producers, mut receiver) = fair_channel::<&'static str>(64);
+        let mut noisy = producers.producer("noisy", 1);
+        let mut quiet = producers.producer("quiet", 1);
+        let mut heavy = producers.producer("heavy", 2);
+        drop(producers);
+
+        for _ in 0..32 {
+            noisy.try_send("noisy").unwrap();
+        }
+        for _ in 0..2 {
+            quiet.try_send("quiet").unwrap();
+        }
+        for _ in 0..4 {
+            heavy.try_send("heavy").unwrap();
+        }
+
+        let stats = receiver.stats();
+        assert_eq!(stats[0].name(), "noisy");
+        assert_eq!(stats[0].depth(), 32);
+        assert_eq!(stats[2].weight(), 2);
+
+        let order: Vec<_> = (0..9).map(|_| receiver.try_recv().unwrap()).collect();
+        assert_eq!(
+            order,
+            vec!["noisy", "quiet", "heavy", "heavy", "noisy", "quiet", "heavy", "heavy", "noisy"]
+        );
+
+        let stats = receiver.stats();
+        assert_eq!(stats[0].depth(), 29);
+        assert_eq!(stats[0].received(), 3);
+        assert_eq!(stats[1].depth(), 0);
+        assert_eq!(stats[2].received(), 4);
+
+        drop(quiet);
+        drop(heavy);
+        drop(noisy);
+        test_executor!(async move {
+            let mut rest = 0;
+            while let Some(message) = receiver.recv().await.unwrap() {
+                assert_eq!(message, "noisy");
+                rest += 1;
+            }
+            assert_eq!(rest, 29);
+        });
+    }
+
+    #[test]
+    fn fair_channel_across_executors() {
+        let (producers, mut receiver) = fair_channel::<usize>(4);
+        let mut handles = Vec::new();
+        for shard in 0..4 {
+            let mut sender = producers.producer(&format!("shard-{}", shard), 1);
+            handles.push(
+                LocalExecutor::spawn_executor("producer", None, move || async move {
+                    for _ in 0..100 {
+                        sender.send(shard).await.unwrap();
+                    }
+                })
+                .unwrap(),
+            );
+        }
+        drop(producers);
+
+        test_executor!(async move {
+            let mut counts = [0; 4];
+            while let Some(shard) = receiver.recv().await.unwrap() {
+                counts[shard] += 1;
+            }
+            assert_eq!(counts, [100; 4]);
+        });
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn producers_fail_once_the_receiver_executor_is_poisoned() {
+        let (producers, mut receiver) = fair_channel::<usize>(4);
+        let mut sender = producers.producer("producer", 1);
+        let handle = LocalExecutor::spawn_executor("receiver", None, move || async move {
+            receiver.recv().await.unwrap();
+            // The receiver outlives the task that panics
+            crate::Local::local(async move {
+                futures::future::pending::<()>().await;
+                drop(receiver);
+            })
+            .detach();
+            panic!("the receiver went away");
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            sender.send(0).await.unwrap();
+            let err = loop {
+                if let Err(err) = sender.send(1).await {
+                    break err;
+                }
+            };
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            let gone = err.get_ref().unwrap().downcast_ref::<ExecutorGone>();
+            assert!(gone.unwrap().poisoned());
+        });
+        assert!(handle.join().is_err());
+    }
+
+    #[test]
+    fn receiver_stops_waiting_for_poisoned_producers() {
+        let (producers, mut receiver) = fair_channel::<usize>(4);
+        let mut sender = producers.producer("producer", 1);
+        drop(producers);
+        let handle = LocalExecutor::spawn_executor("producer", None, move || async move {
+            sender.send(1).await.unwrap();
+            sender.send(2).await.unwrap();
+            // The sender is never dropped, as if the executor leaked it
+            std::mem::forget(sender);
+            panic!("the producer went away");
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            let mut received = Vec::new();
+            while let Some(message) = receiver.recv().await.unwrap() {
+                received.push(message);
+            }
+            assert_eq!(received, vec![1, 2]);
+        });
+        assert!(handle.join().is_err());
+    }
+}
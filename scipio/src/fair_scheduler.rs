@@ -0,0 +1,469 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::Local;
+use futures::future;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+#[derive(Debug)]
+struct Connection<R> {
+    pending: VecDeque<R>,
+    in_flight: usize,
+    // whether the connection is in the ready ring
+    ready: bool,
+}
+
+#[derive(Debug)]
+struct State<K, R> {
+    connections: HashMap<K, Connection<R>>,
+    // connections with pending requests and room for more in flight, in the order they
+    // will be served
+    ready: VecDeque<K>,
+    max_in_flight: usize,
+    // the waker of each task waiting in `next`, by waiter
+    waiters: HashMap<u64, Waker>,
+    next_waiter: u64,
+    closed: bool,
+}
+
+impl<K: Hash + Eq + Clone, R> State<K, R> {
+    fn make_ready(&mut self, key: &K) {
+        let max_in_flight = self.max_in_flight;
+        let conn = match self.connections.get_mut(key) {
+            Some(conn) => conn,
+            None => return,
+        };
+        if !conn.ready && !conn.pending.is_empty() && conn.in_flight < max_in_flight {
+            conn.ready = true;
+            self.ready.push_back(key.clone());
+            self.wake_waiters();
+        }
+    }
+
+    fn wake_waiters(&mut self) {
+        for (_, waker) in self.waiters.drain() {
+            waker.wake();
+        }
+    }
+
+    fn forget_if_idle(&mut self, key: &K) {
+        if let Some(conn) = self.connections.get(key) {
+            if conn.pending.is_empty() && conn.in_flight == 0 {
+                self.connections.remove(key);
+            }
+        }
+    }
+
+    // Whether no request waits to be served, including those of connections that are
+    // at their cap of requests in flight
+    fn drained(&self) -> bool {
+        self.connections
+            .values()
+            .all(|conn| conn.pending.is_empty())
+    }
+}
+
+// The place of a task waiting in `next`, given up when it stops waiting
+struct Waiter<'a, K, R> {
+    state: &'a RefCell<State<K, R>>,
+    id: u64,
+}
+
+impl<K, R> Drop for Waiter<'_, K, R> {
+    fn drop(&mut self) {
+        self.state.borrow_mut().waiters.remove(&self.id);
+    }
+}
+
+/// Schedules the requests of many connections, so that each connection gets its turn.
+///
+/// Requests are submitted to the scheduler tagged with the connection they came from, and
+/// are handed out in round-robin order between connections: a connection that pipelines
+/// a thousand requests only gets one of them served before every other connection with
+/// pending requests gets one served too. On top of that, each connection can only have
+/// a limited number of requests in flight, so a chatty connection can't fill its task
+/// queue with work while others wait.
+///
+/// A request counts as in flight from the moment [`next`] hands it out until the
+/// [`InFlight`] guard returned with it is dropped.
+///
+/// Cloning the scheduler returns another handle to the same scheduler.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{FairScheduler, LocalExecutor};
+///
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async move {
+///     let scheduler = FairScheduler::new(2);
+///     scheduler.submit("chatty", 1);
+///     scheduler.submit("chatty", 2);
+///     scheduler.submit("quiet", 3);
+///
+///     let (first, _a) = scheduler.next().await.unwrap();
+///     let (second, _b) = scheduler.next().await.unwrap();
+///     assert_eq!((first, second), (1, 3));
+/// });
+/// ```
+///
+/// [`next`]: struct.FairScheduler.html#method.next
+/// [`InFlight`]: struct.InFlight.html
+pub struct FairScheduler<K, R> {
+    state: Rc<RefCell<State<K, R>>>,
+}
+
+impl<K, R> Clone for FairScheduler<K, R> {
+    fn clone(&self) -> Self {
+        FairScheduler {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<K, R> fmt::Debug for FairScheduler<K, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("FairScheduler")
+            .field("connections", &state.connections.len())
+            .field("ready", &state.ready.len())
+            .field("max_in_flight", &state.max_in_flight)
+            .field("closed", &state.closed)
+            .finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, R> FairScheduler<K, R> {
+    /// Creates a scheduler that lets each connection have at most `max_in_flight`
+    /// requests in flight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_in_flight` is zero.
+    pub fn new(max_in_flight: usize) -> FairScheduler<K, R> {
+        assert!(
+            max_in_flight > 0,
+            "connections must be allowed a request in flight"
+        );
+        FairScheduler {
+            state: Rc::new(RefCell::new(State {
+                connections: HashMap::new(),
+                ready: VecDeque::new(),
+                max_in_flight,
+                waiters: HashMap::new(),
+                next_waiter: 0,
+                closed: false,
+            })),
+        }
+    }
+
+    /// Queues `request`, which came from `connection`.
+    pub fn submit(&self, connection: K, request: R) {
+        let mut state = self.state.borrow_mut();
+        state
+            .connections
+            .entry(connection.clone())
+            .or_insert_with(|| Connection {
+                pending: VecDeque::new(),
+                in_flight: 0,
+                ready: false,
+            })
+            .pending
+            .push_back(request);
+        state.make_ready(&connection);
+    }
+
+    /// Waits for the next request to be served, and returns it along with the guard that
+    /// keeps it in flight.
+    ///
+    /// Returns `None` once the scheduler is closed and no requests are left.
+    pub async fn next(&self) -> Option<(R, InFlight<K, R>)> {
+        let waiter = {
+            let mut state = self.state.borrow_mut();
+            state.next_waiter += 1;
+            Waiter {
+                state: &self.state,
+                id: state.next_waiter,
+            }
+        };
+        future::poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+            let key = match state.ready.pop_front() {
+                Some(key) => key,
+                None if state.closed && state.drained() => return Poll::Ready(None),
+                // Connections at their cap become ready again as their requests in
+                // flight complete, which wakes us up
+                None => {
+                    let waker = state
+                        .waiters
+                        .entry(waiter.id)
+                        .or_insert_with(|| cx.waker().clone());
+                    if !waker.will_wake(cx.waker()) {
+                        *waker = cx.waker().clone();
+                    }
+                    return Poll::Pending;
+                }
+            };
+            let max_in_flight = state.max_in_flight;
+            let conn = state.connections.get_mut(&key).unwrap();
+            let request = conn.pending.pop_front().unwrap();
+            conn.in_flight += 1;
+            conn.ready = false;
+            if !conn.pending.is_empty() && conn.in_flight < max_in_flight {
+                conn.ready = true;
+                state.ready.push_back(key.clone());
+            }
+            Poll::Ready(Some((
+                request,
+                InFlight {
+                    state: self.state.clone(),
+                    connection: key,
+                },
+            )))
+        })
+        .await
+    }
+
+    /// Returns how many requests from `connection` wait to be served.
+    pub fn pending(&self, connection: &K) -> usize {
+        let state = self.state.borrow();
+        state
+            .connections
+            .get(connection)
+            .map_or(0, |conn| conn.pending.len())
+    }
+
+    /// Returns how many requests from `connection` are in flight.
+    pub fn in_flight(&self, connection: &K) -> usize {
+        let state = self.state.borrow();
+        state
+            .connections
+            .get(connection)
+            .map_or(0, |conn| conn.in_flight)
+    }
+
+    /// Drops the requests from `connection` that wait to be served, usually because it
+    /// went away, and returns them. Requests in flight are not affected.
+    pub fn disconnect(&self, connection: &K) -> Vec<R> {
+        let mut state = self.state.borrow_mut();
+        let dropped = match state.connections.get_mut(connection) {
+            Some(conn) => conn.pending.drain(..).collect(),
+            None => return Vec::new(),
+        };
+        state.ready.retain(|key| key != connection);
+        if let Some(conn) = state.connections.get_mut(connection) {
+            conn.ready = false;
+        }
+        state.forget_if_idle(connection);
+        // This may have been the last connection with requests left after a close
+        if state.closed {
+            state.wake_waiters();
+        }
+        dropped
+    }
+
+    /// Closes the scheduler. Requests already submitted are still served, including
+    /// those of connections that have to wait for their requests in flight to complete
+    /// first, but once they run out [`next`] returns `None` instead of waiting.
+    ///
+    /// [`next`]: struct.FairScheduler.html#method.next
+    pub fn close(&self) {
+        let mut state = self.state.borrow_mut();
+        state.closed = true;
+        state.wake_waiters();
+    }
+}
+
+impl<K: Hash + Eq + Clone + 'static, R: 'static> FairScheduler<K, R> {
+    /// Serves requests until the scheduler is closed and drained, running `handler` for
+    /// each of them in a task of its own. A request is in flight until its handler
+    /// completes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{FairScheduler, Local, LocalExecutor};
+    ///
+    /// let ex = LocalExecutor::new(None).unwrap();
+    /// ex.run(async move {
+    ///     let scheduler = FairScheduler::new(4);
+    ///     let handler = |conn: u32, request: u64| async move {
+    ///         println!("{} asked for {}", conn, request);
+    ///     };
+    ///     let server = Local::local(scheduler.clone().serve(handler));
+    ///     scheduler.submit(1, 10);
+    ///     scheduler.submit(2, 20);
+    ///     scheduler.close();
+    ///     server.await;
+    /// });
+    /// ```
+    pub async fn serve<F, Fut>(self, handler: F)
+    where
+        F: Fn(K, R) -> Fut,
+        Fut: Future<Output = ()> + 'static,
+    {
+        while let Some((request, in_flight)) = self.next().await {
+            let work = handler(in_flight.connection().clone(), request);
+            Local::local(async move {
+                work.await;
+                drop(in_flight);
+            })
+            .detach();
+        }
+    }
+}
+
+/// Keeps a request handed out by a [`FairScheduler`] in flight. Dropping it lets the
+/// connection the request came from have another request served.
+///
+/// [`FairScheduler`]: struct.FairScheduler.html
+pub struct InFlight<K: Hash + Eq + Clone, R> {
+    state: Rc<RefCell<State<K, R>>>,
+    connection: K,
+}
+
+impl<K: Hash + Eq + Clone, R> InFlight<K, R> {
+    /// Returns the connection the request came from.
+    pub fn connection(&self) -> &K {
+        &self.connection
+    }
+}
+
+impl<K: Hash + Eq + Clone + fmt::Debug, R> fmt::Debug for InFlight<K, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InFlight")
+            .field("connection", &self.connection)
+            .finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, R> Drop for InFlight<K, R> {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        if let Some(conn) = state.connections.get_mut(&self.connection) {
+            conn.in_flight -= 1;
+        }
+        state.make_ready(&self.connection);
+        state.forget_if_idle(&self.connection);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn connections_take_turns() {
+        test_executor!(async move {
+            let scheduler = FairScheduler::new(8);
+            for i in 0..4 {
+                scheduler.submit("chatty", i);
+            }
+            scheduler.submit("quiet", 100);
+            scheduler.submit("quiet", 101);
+
+            let mut served = Vec::new();
+            let mut guards = Vec::new();
+            for _ in 0..6 {
+                let (request, guard) = scheduler.next().await.unwrap();
+                served.push(request);
+                guards.push(guard);
+            }
+            assert_eq!(served, vec![0, 100, 1, 101, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn in_flight_is_capped() {
+        test_executor!(async move {
+            let scheduler = FairScheduler::new(2);
+            for i in 0..4 {
+                scheduler.submit(1, i);
+            }
+
+            let (_, first) = scheduler.next().await.unwrap();
+            let (_, _second) = scheduler.next().await.unwrap();
+            assert_eq!(scheduler.in_flight(&1), 2);
+            assert_eq!(scheduler.pending(&1), 2);
+
+            // the connection is at its cap, so only another connection can be served
+            scheduler.submit(2, 10);
+            let (request, _other) = scheduler.next().await.unwrap();
+            assert_eq!(request, 10);
+
+            drop(first);
+            let (request, _third) = scheduler.next().await.unwrap();
+            assert_eq!(request, 2);
+
+            assert_eq!(scheduler.disconnect(&1), vec![3]);
+            scheduler.close();
+            assert!(scheduler.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn close_serves_capped_connections() {
+        test_executor!(async move {
+            let scheduler = FairScheduler::new(1);
+            scheduler.submit(1, 0);
+            scheduler.submit(1, 1);
+
+            let (_, first) = scheduler.next().await.unwrap();
+            scheduler.close();
+
+            // the second request is only served once the first one completes
+            let releaser = Local::local(async move {
+                crate::Timer::new(std::time::Duration::from_millis(10)).await;
+                drop(first);
+            });
+            let (request, _second) = scheduler.next().await.unwrap();
+            assert_eq!(request, 1);
+            releaser.await;
+        });
+    }
+
+    #[test]
+    fn close_with_capped_connections_disconnected() {
+        test_executor!(async move {
+            let scheduler = FairScheduler::new(1);
+            scheduler.submit(1, 0);
+            scheduler.submit(1, 1);
+            let (_, _first) = scheduler.next().await.unwrap();
+            scheduler.close();
+
+            // with what was left dropped, there is nothing to wait for
+            let waiting = scheduler.clone();
+            let next = Local::local(async move { waiting.next().await.is_none() });
+            Local::later().await;
+            assert_eq!(scheduler.disconnect(&1), vec![1]);
+            assert!(next.await);
+        });
+    }
+
+    #[test]
+    fn waiting_keeps_one_waker_per_waiter() {
+        test_executor!(async move {
+            let scheduler = FairScheduler::<u32, u32>::new(1);
+            {
+                let next = scheduler.next();
+                futures::pin_mut!(next);
+                for _ in 0..10 {
+                    assert!(futures::poll!(next.as_mut()).is_pending());
+                }
+                assert_eq!(scheduler.state.borrow().waiters.len(), 1);
+            }
+
+            // waiters that give up leave no waker behind
+            assert_eq!(scheduler.state.borrow().waiters.len(), 0);
+        });
+    }
+}
@@ -0,0 +1,182 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Identifies a file or socket registered in the reactor of an executor.
+///
+/// File descriptors are recycled by the operating system as soon as they are closed, so
+/// an operation issued through a stale descriptor could silently hit an unrelated file
+/// that happened to be opened later. A `FileId` pairs the descriptor with the generation
+/// of its registration: once the resource is closed, operations issued with its id fail
+/// with a [`StaleFileError`] instead, even if the descriptor number was reused.
+///
+/// [`StaleFileError`]: struct.StaleFileError.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FileId {
+    fd: RawFd,
+    generation: u64,
+}
+
+impl FileId {
+    /// The id of resources that were never registered, like a default [`DmaFile`]
+    ///
+    /// [`DmaFile`]: struct.DmaFile.html
+    pub(crate) const fn invalid() -> FileId {
+        FileId {
+            fd: -1,
+            generation: 0,
+        }
+    }
+
+    /// The file descriptor this id was given to
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// The generation of the registration of the file descriptor. Generations start at 1
+    /// and are never reused within an executor.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fd {} (generation {})", self.fd, self.generation)
+    }
+}
+
+/// The error returned when an operation is issued on a file or socket that was already
+/// closed.
+///
+/// It reaches callers wrapped in an `io::Error`, from which [`StaleFileError::from_io`]
+/// recovers it.
+///
+/// [`StaleFileError::from_io`]: struct.StaleFileError.html#method.from_io
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleFileError {
+    id: FileId,
+}
+
+impl StaleFileError {
+    /// The id the stale operation was issued with
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// Returns the `StaleFileError` carried by `err`, if any
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{DmaFile, StaleFileError};
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let mut file = DmaFile::open("myfile").await.unwrap();
+    /// file.close().await.unwrap();
+    /// let err = file.fdatasync().await.unwrap_err();
+    /// assert!(StaleFileError::from_io(&err.into()).is_some());
+    /// # });
+    /// ```
+    pub fn from_io(err: &io::Error) -> Option<&StaleFileError> {
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<StaleFileError>())
+    }
+}
+
+impl fmt::Display for StaleFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} was already closed", self.id)
+    }
+}
+
+impl std::error::Error for StaleFileError {}
+
+impl From<StaleFileError> for io::Error {
+    fn from(err: StaleFileError) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, err)
+    }
+}
+
+/// Keeps track of the current registration of every file descriptor in the reactor
+#[derive(Debug, Default)]
+pub(crate) struct FileRegistry {
+    last_generation: u64,
+    // The current generation of every registered descriptor, and the path it was opened
+    // with, if it is a file
+    live: HashMap<RawFd, (u64, Option<Rc<Path>>)>,
+}
+
+impl FileRegistry {
+    pub(crate) fn register(&mut self, fd: RawFd, path: Option<&Path>) -> FileId {
+        self.last_generation += 1;
+        // A descriptor that is still registered was closed behind our back and reused,
+        // so its previous registration is stale.
+        self.live
+            .insert(fd, (self.last_generation, path.map(Rc::from)));
+        FileId {
+            fd,
+            generation: self.last_generation,
+        }
+    }
+
+    pub(crate) fn unregister(&mut self, id: FileId) {
+        if self.check(id).is_ok() {
+            self.live.remove(&id.fd);
+        }
+    }
+
+    /// Returns the file descriptor of `id` if it is still the current registration
+    pub(crate) fn check(&self, id: FileId) -> Result<RawFd, StaleFileError> {
+        match self.live.get(&id.fd) {
+            Some((generation, _)) if *generation == id.generation => Ok(id.fd),
+            _ => Err(StaleFileError { id }),
+        }
+    }
+
+    /// Returns the path the file currently registered with `fd` was opened with
+    pub(crate) fn path(&self, fd: RawFd) -> Option<Rc<Path>> {
+        self.live.get(&fd).and_then(|(_, path)| path.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn registry_detects_reused_descriptors() {
+        let mut registry = FileRegistry::default();
+        let first = registry.register(10, Some(Path::new("/first")));
+        assert_eq!(registry.check(first), Ok(10));
+        assert_eq!(registry.path(10).as_deref(), Some(Path::new("/first")));
+
+        registry.unregister(first);
+        assert_eq!(registry.check(first), Err(StaleFileError { id: first }));
+
+        assert_eq!(registry.path(10), None);
+
+        let second = registry.register(10, None);
+        assert_eq!(second.fd(), first.fd());
+        assert!(second.generation() > first.generation());
+        assert!(registry.check(first).is_err());
+        assert_eq!(registry.check(second), Ok(10));
+
+        // Unregistering a stale id leaves the current registration alone
+        registry.unregister(first);
+        assert_eq!(registry.check(second), Ok(10));
+
+        let third = registry.register(10, None);
+        assert!(registry.check(second).is_err());
+        assert!(registry.check(FileId::invalid()).is_err());
+        assert_eq!(registry.check(third), Ok(10));
+    }
+}
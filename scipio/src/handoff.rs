@@ -0,0 +1,292 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::pollable::Async;
+use crate::sys;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use std::io;
+use std::marker::PhantomData;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+const KIND_TCP_LISTENER: u8 = 0;
+const KIND_UNIX_LISTENER: u8 = 1;
+const KIND_TCP_STREAM: u8 = 2;
+
+// The entry count comes from the peer, so only trust it this far when reserving
+// room for the manifest; a longer one still grows as its entries arrive.
+const MAX_PREALLOCATED_ENTRIES: usize = 1024;
+
+// A file descriptor owned by a handoff, closed if nobody takes it
+#[derive(Debug)]
+struct HandoffFd(RawFd);
+
+impl HandoffFd {
+    fn take(mut self) -> RawFd {
+        std::mem::replace(&mut self.0, -1)
+    }
+}
+
+impl Drop for HandoffFd {
+    fn drop(&mut self) {
+        if self.0 != -1 {
+            unsafe { libc::close(self.0) };
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: u8,
+    name: String,
+    fd: HandoffFd,
+}
+
+/// A set of sockets handed from one generation of a process to the next, for
+/// zero-downtime upgrades.
+///
+/// The old generation adds the sockets it listens on, and optionally the connections it
+/// accepted but wants the new generation to serve, to a `Handoff` and sends it over a
+/// Unix stream connected to the new generation. Each socket has a name, so the new
+/// generation can tell them apart. The kernel keeps the sockets open while they are in
+/// flight, so connection attempts are queued in the listen backlog instead of refused,
+/// and no connection is lost.
+///
+/// The new generation receives the `Handoff` and takes the sockets out of it, as
+/// standard library sockets to register with [`Async::new`]. The kernel installs them
+/// in the file descriptor table of the thread that receives the `Handoff`, and every
+/// executor has a table of its own, so they are only valid in the executor that
+/// received them. That is why a `Handoff` can't be sent to other threads. To spread a
+/// listener over the executors of a pool, have each executor receive a handoff of its
+/// own, or hand the sockets over to them with [`send_tcp_stream`] and the like.
+///
+/// Once the handoff is sent, the old generation is expected to stop accepting
+/// connections and finish serving the ones it kept.
+///
+/// # Examples
+///
+/// ```no_run
+/// use scipio::{Async, Handoff, LocalExecutor};
+/// use std::net::TcpListener;
+/// use std::os::unix::net::UnixStream;
+///
+/// // old generation
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async move {
+///     let listener = Async::<TcpListener>::bind(([0, 0, 0, 0], 8000)).unwrap();
+///     let channel = Async::<UnixStream>::connect("/run/myapp/handoff.sock").await.unwrap();
+///     let mut handoff = Handoff::new();
+///     handoff.add_tcp_listener("http", &listener).unwrap();
+///     handoff.send(&channel).await.unwrap();
+/// });
+/// ```
+///
+/// [`Async::new`]: struct.Async.html#method.new
+/// [`send_tcp_stream`]: struct.Async.html#method.send_tcp_stream
+#[derive(Debug, Default)]
+pub struct Handoff {
+    entries: Vec<Entry>,
+    // The descriptors only mean something in the file descriptor table of this thread
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Handoff {
+    /// Creates an empty handoff.
+    pub fn new() -> Handoff {
+        Handoff::default()
+    }
+
+    fn add<S: AsRawFd>(&mut self, kind: u8, name: &str, socket: &S) -> io::Result<()> {
+        if name.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket name is too long",
+            ));
+        }
+        let fd = HandoffFd(sys::duplicate_file(socket.as_raw_fd())?);
+        self.entries.push(Entry {
+            kind,
+            name: name.to_string(),
+            fd,
+        });
+        Ok(())
+    }
+
+    /// Adds a TCP listener to the handoff. The handoff holds a duplicate of the socket,
+    /// so the listener can still be used until the handoff is sent.
+    pub fn add_tcp_listener<S: AsRawFd>(&mut self, name: &str, listener: &S) -> io::Result<()> {
+        self.add(KIND_TCP_LISTENER, name, listener)
+    }
+
+    /// Adds a Unix listener to the handoff.
+    pub fn add_unix_listener<S: AsRawFd>(&mut self, name: &str, listener: &S) -> io::Result<()> {
+        self.add(KIND_UNIX_LISTENER, name, listener)
+    }
+
+    /// Adds an accepted TCP connection to the handoff. Many connections can share a
+    /// name. The caller should stop using its copy of the connection.
+    pub fn add_tcp_stream<S: AsRawFd>(&mut self, name: &str, stream: &S) -> io::Result<()> {
+        self.add(KIND_TCP_STREAM, name, stream)
+    }
+
+    /// Returns the amount of sockets in the handoff.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the handoff holds no sockets.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sends the handoff over `channel` to the generation that receives it with
+    /// [`receive`].
+    ///
+    /// [`receive`]: struct.Handoff.html#method.receive
+    pub async fn send(self, channel: &Async<UnixStream>) -> io::Result<()> {
+        // A manifest describing the sockets goes first, followed by the sockets themselves
+        let mut manifest = Vec::new();
+        manifest.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            manifest.push(entry.kind);
+            manifest.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+            manifest.extend_from_slice(entry.name.as_bytes());
+        }
+        let mut writer = channel;
+        writer.write_all(&manifest).await?;
+
+        for entry in &self.entries {
+            let fd = entry.fd.0;
+            channel
+                .write_with(|io| sys::send_fd(io.as_raw_fd(), fd))
+                .await?;
+        }
+        // The in-flight messages hold references to the sockets on behalf of the
+        // receiver, so our copies can go.
+        Ok(())
+    }
+
+    /// Receives a handoff sent with [`send`] over `channel`.
+    ///
+    /// [`send`]: struct.Handoff.html#method.send
+    pub async fn receive(channel: &Async<UnixStream>) -> io::Result<Handoff> {
+        let mut reader = channel;
+        let mut count = [0u8; 4];
+        reader.read_exact(&mut count).await?;
+        let count = u32::from_be_bytes(count) as usize;
+
+        let mut manifest = Vec::with_capacity(count.min(MAX_PREALLOCATED_ENTRIES));
+        for _ in 0..count {
+            let mut header = [0u8; 3];
+            reader.read_exact(&mut header).await?;
+            let mut name = vec![0u8; u16::from_be_bytes([header[1], header[2]]) as usize];
+            reader.read_exact(&mut name).await?;
+            let name = String::from_utf8(name).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "socket name is not UTF-8")
+            })?;
+            manifest.push((header[0], name));
+        }
+
+        let mut handoff = Handoff::new();
+        for (kind, name) in manifest {
+            let fd = channel.read_with(|io| sys::recv_fd(io.as_raw_fd())).await?;
+            handoff.entries.push(Entry {
+                kind,
+                name,
+                fd: HandoffFd(fd),
+            });
+        }
+        Ok(handoff)
+    }
+
+    fn take(&mut self, kind: u8, name: &str) -> Vec<RawFd> {
+        let mut taken = Vec::new();
+        let mut idx = 0;
+        while idx < self.entries.len() {
+            if self.entries[idx].kind == kind && self.entries[idx].name == name {
+                taken.push(self.entries.remove(idx).fd.take());
+            } else {
+                idx += 1;
+            }
+        }
+        taken
+    }
+
+    /// Takes the TCP listener named `name` out of the handoff. Like every socket taken
+    /// out of the handoff, it is only valid in the current executor.
+    pub fn take_tcp_listener(&mut self, name: &str) -> Option<TcpListener> {
+        let fd = self.take(KIND_TCP_LISTENER, name).pop()?;
+        Some(unsafe { TcpListener::from_raw_fd(fd) })
+    }
+
+    /// Takes the Unix listener named `name` out of the handoff.
+    pub fn take_unix_listener(&mut self, name: &str) -> Option<UnixListener> {
+        let fd = self.take(KIND_UNIX_LISTENER, name).pop()?;
+        Some(unsafe { UnixListener::from_raw_fd(fd) })
+    }
+
+    /// Takes the TCP connections named `name` out of the handoff.
+    pub fn take_tcp_streams(&mut self, name: &str) -> Vec<TcpStream> {
+        self.take(KIND_TCP_STREAM, name)
+            .into_iter()
+            .map(|fd| unsafe { TcpStream::from_raw_fd(fd) })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LocalExecutor;
+
+    #[test]
+    fn listeners_survive_the_handoff() {
+        let (old, new) = UnixStream::pair().unwrap();
+
+        let handle = LocalExecutor::spawn_executor("old-generation", None, move || async move {
+            let channel = Async::new(old).unwrap();
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+
+            // A client connects before the handoff and its connection is handed over
+            let mut client = TcpStream::connect(addr).unwrap();
+            let (moved, _) = listener.accept().await.unwrap();
+
+            let mut handoff = Handoff::new();
+            handoff.add_tcp_listener("http", &listener).unwrap();
+            handoff.add_tcp_stream("http", &moved).unwrap();
+            assert_eq!(handoff.len(), 2);
+            handoff.send(&channel).await.unwrap();
+            drop(listener);
+            drop(moved);
+
+            use std::io::Read;
+            let mut buf = [0u8; 5];
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"moved");
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            let channel = Async::new(new).unwrap();
+            let mut handoff = Handoff::receive(&channel).await.unwrap();
+            assert!(handoff.take_unix_listener("http").is_none());
+            let listener = Async::new(handoff.take_tcp_listener("http").unwrap()).unwrap();
+            let mut streams = handoff.take_tcp_streams("http");
+            assert_eq!(streams.len(), 1);
+            assert!(handoff.is_empty());
+
+            let mut stream = Async::new(streams.pop().unwrap()).unwrap();
+            stream.write_all(b"moved").await.unwrap();
+
+            // The listener is still accepting connections in the new generation
+            let addr = listener.get_ref().local_addr().unwrap();
+            let _client = Async::<TcpStream>::connect(addr).await.unwrap();
+            listener.accept().await.unwrap();
+        });
+        handle.join().unwrap();
+    }
+}
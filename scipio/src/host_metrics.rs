@@ -0,0 +1,405 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::parking::Reactor;
+use crate::sys::{PollableStatus, SourceType};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+// Most files in /proc and /sys are much smaller than this, so they are read with a
+// single read, plus the one that finds their end
+const READ_SIZE: usize = 16 << 10;
+
+fn invalid_data(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}", what))
+}
+
+fn parse_fields<'a>(fields: impl Iterator<Item = &'a str>, what: &str) -> io::Result<Vec<u64>> {
+    fields
+        .map(|field| field.parse().map_err(|_| invalid_data(what)))
+        .collect()
+}
+
+/// The time a CPU spent in each state, in clock ticks (`USER_HZ`), as reported by
+/// `/proc/stat`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuTimes {
+    /// Time running user code
+    pub user: u64,
+    /// Time running niced user code
+    pub nice: u64,
+    /// Time running kernel code
+    pub system: u64,
+    /// Time doing nothing
+    pub idle: u64,
+    /// Time waiting for I/O to complete
+    pub iowait: u64,
+    /// Time serving interrupts
+    pub irq: u64,
+    /// Time serving softirqs
+    pub softirq: u64,
+    /// Time stolen by the hypervisor
+    pub steal: u64,
+}
+
+impl CpuTimes {
+    /// Returns the time the CPU was doing anything but waiting.
+    pub fn busy(&self) -> u64 {
+        self.user + self.nice + self.system + self.irq + self.softirq + self.steal
+    }
+
+    /// Returns the time accounted for in all states.
+    pub fn total(&self) -> u64 {
+        self.busy() + self.idle + self.iowait
+    }
+
+    fn parse(fields: &[u64]) -> CpuTimes {
+        let field = |idx: usize| fields.get(idx).copied().unwrap_or(0);
+        CpuTimes {
+            user: field(0),
+            nice: field(1),
+            system: field(2),
+            idle: field(3),
+            iowait: field(4),
+            irq: field(5),
+            softirq: field(6),
+            steal: field(7),
+        }
+    }
+}
+
+/// A sample of `/proc/stat`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuStat {
+    /// The times of all CPUs added together
+    pub total: CpuTimes,
+    /// The times of each CPU, indexed by CPU id
+    pub cpus: Vec<CpuTimes>,
+    /// Context switches across all CPUs since boot
+    pub context_switches: u64,
+    /// Threads currently runnable
+    pub procs_running: u64,
+    /// Threads currently blocked waiting for I/O
+    pub procs_blocked: u64,
+}
+
+impl CpuStat {
+    fn parse(contents: &str) -> io::Result<CpuStat> {
+        let mut stat = CpuStat::default();
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            let key = match fields.next() {
+                Some(key) => key,
+                None => continue,
+            };
+            if key == "cpu" {
+                stat.total = CpuTimes::parse(&parse_fields(fields, "/proc/stat")?);
+            } else if let Some(id) = key.strip_prefix("cpu") {
+                let id: usize = id.parse().map_err(|_| invalid_data("/proc/stat"))?;
+                if stat.cpus.len() <= id {
+                    stat.cpus.resize(id + 1, CpuTimes::default());
+                }
+                stat.cpus[id] = CpuTimes::parse(&parse_fields(fields, "/proc/stat")?);
+            } else {
+                let value = match key {
+                    "ctxt" => &mut stat.context_switches,
+                    "procs_running" => &mut stat.procs_running,
+                    "procs_blocked" => &mut stat.procs_blocked,
+                    _ => continue,
+                };
+                *value = parse_fields(fields.take(1), "/proc/stat")?
+                    .pop()
+                    .ok_or_else(|| invalid_data("/proc/stat"))?;
+            }
+        }
+        Ok(stat)
+    }
+}
+
+/// A sample of `/proc/meminfo`. All values are in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemInfo {
+    /// Usable memory
+    pub total: u64,
+    /// Memory not used for anything
+    pub free: u64,
+    /// Memory that can be made available without swapping
+    pub available: u64,
+    /// Memory used by block device buffers
+    pub buffers: u64,
+    /// Memory used by the page cache
+    pub cached: u64,
+    /// Swap space
+    pub swap_total: u64,
+    /// Swap space not used
+    pub swap_free: u64,
+}
+
+impl MemInfo {
+    fn parse(contents: &str) -> io::Result<MemInfo> {
+        let mut info = MemInfo::default();
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            let value = match fields.next() {
+                Some("MemTotal:") => &mut info.total,
+                Some("MemFree:") => &mut info.free,
+                Some("MemAvailable:") => &mut info.available,
+                Some("Buffers:") => &mut info.buffers,
+                Some("Cached:") => &mut info.cached,
+                Some("SwapTotal:") => &mut info.swap_total,
+                Some("SwapFree:") => &mut info.swap_free,
+                _ => continue,
+            };
+            let amount: u64 = fields
+                .next()
+                .and_then(|amount| amount.parse().ok())
+                .ok_or_else(|| invalid_data("/proc/meminfo"))?;
+            *value = match fields.next() {
+                Some("kB") => amount << 10,
+                _ => amount,
+            };
+        }
+        Ok(info)
+    }
+}
+
+/// The I/O statistics of a block device, as reported by `/proc/diskstats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiskStats {
+    /// The name of the device, like `sda` or `nvme0n1`
+    pub name: String,
+    /// Reads completed
+    pub reads: u64,
+    /// Sectors read, always counted in 512 byte units
+    pub sectors_read: u64,
+    /// Milliseconds spent reading
+    pub read_ms: u64,
+    /// Writes completed
+    pub writes: u64,
+    /// Sectors written, always counted in 512 byte units
+    pub sectors_written: u64,
+    /// Milliseconds spent writing
+    pub write_ms: u64,
+    /// I/Os currently in progress
+    pub in_progress: u64,
+    /// Milliseconds the device had I/O in progress
+    pub io_ms: u64,
+}
+
+impl DiskStats {
+    fn parse(contents: &str) -> io::Result<Vec<DiskStats>> {
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut fields = line.split_whitespace().skip(2);
+                let name = fields
+                    .next()
+                    .ok_or_else(|| invalid_data("/proc/diskstats"))?;
+                let values = parse_fields(fields, "/proc/diskstats")?;
+                if values.len() < 10 {
+                    return Err(invalid_data("/proc/diskstats"));
+                }
+                Ok(DiskStats {
+                    name: name.to_string(),
+                    reads: values[0],
+                    sectors_read: values[2],
+                    read_ms: values[3],
+                    writes: values[4],
+                    sectors_written: values[6],
+                    write_ms: values[7],
+                    in_progress: values[8],
+                    io_ms: values[9],
+                })
+            })
+            .collect()
+    }
+}
+
+/// Samples host metrics from `/proc` and `/sys` without blocking the executor.
+///
+/// Reading those files with `std::fs` looks harmless, but the kernel generates their
+/// contents on the spot and can take a long time doing so, for instance under memory
+/// pressure. `HostMetrics` reads them through the reactor instead, so a shard can
+/// monitor the host it runs on while it keeps serving requests.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{HostMetrics, LocalExecutor};
+///
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async move {
+///     let metrics = HostMetrics::new();
+///     let mem = metrics.mem_info().await.unwrap();
+///     println!("{} of {} bytes available", mem.available, mem.total);
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct HostMetrics {
+    root: PathBuf,
+}
+
+impl Default for HostMetrics {
+    fn default() -> Self {
+        HostMetrics::new()
+    }
+}
+
+impl HostMetrics {
+    /// Creates a sampler for the host the process runs on.
+    pub fn new() -> HostMetrics {
+        HostMetrics::with_root("/")
+    }
+
+    /// Creates a sampler that looks for `proc` and `sys` under `root` instead of `/`,
+    /// which is useful when the host filesystems are mounted elsewhere, like in
+    /// containers.
+    pub fn with_root<P: AsRef<Path>>(root: P) -> HostMetrics {
+        HostMetrics {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Samples `/proc/stat`.
+    pub async fn cpu_stat(&self) -> io::Result<CpuStat> {
+        CpuStat::parse(&self.read_to_string("proc/stat").await?)
+    }
+
+    /// Samples `/proc/meminfo`.
+    pub async fn mem_info(&self) -> io::Result<MemInfo> {
+        MemInfo::parse(&self.read_to_string("proc/meminfo").await?)
+    }
+
+    /// Samples `/proc/diskstats`.
+    pub async fn disk_stats(&self) -> io::Result<Vec<DiskStats>> {
+        DiskStats::parse(&self.read_to_string("proc/diskstats").await?)
+    }
+
+    /// Reads any other file, like `sys/block/sda/queue/scheduler`, relative to the root
+    /// of the sampler.
+    pub async fn read_to_string<P: AsRef<Path>>(&self, path: P) -> io::Result<String> {
+        let contents = read_file(&self.root.join(path)).await?;
+        String::from_utf8(contents)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file is not valid UTF-8"))
+    }
+}
+
+/// Reads the whole file at `path` through the reactor, without blocking the executor.
+pub(crate) async fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let flags = libc::O_CLOEXEC | libc::O_RDONLY;
+    let source = Reactor::get().open_at(libc::AT_FDCWD, path, flags, 0);
+    let fd = source.collect_rw().await? as RawFd;
+    let contents = read_all(fd).await;
+    Reactor::get().close(fd).collect_rw().await?;
+    contents
+}
+
+// Files in /proc hand out their contents a page or a record at a time, so a read that
+// returns less than asked for is not the end of the file: only an empty one is.
+async fn read_all(fd: RawFd) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    loop {
+        let pos = contents.len() as u64;
+        let mut source = Reactor::get().read_dma(fd, pos, READ_SIZE, PollableStatus::NonPollable);
+        let read_size = source.collect_rw().await?;
+        if read_size == 0 {
+            return Ok(contents);
+        }
+        match source.as_mut().extract_source_type() {
+            SourceType::DmaRead(_, Some(buffer)) => {
+                contents.extend_from_slice(&buffer.as_bytes()[..read_size])
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "read returned no buffer",
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_proc_stat() {
+        let stat = CpuStat::parse(
+            "cpu  10 1 5 100 2 0 1 0 0 0\n\
+             cpu0 4 0 2 50 1 0 0 0 0 0\n\
+             cpu1 6 1 3 50 1 0 1 0 0 0\n\
+             intr 1234 0 0\n\
+             ctxt 5678\n\
+             procs_running 3\n\
+             procs_blocked 1\n",
+        )
+        .unwrap();
+        assert_eq!(stat.total.busy(), 17);
+        assert_eq!(stat.total.total(), 119);
+        assert_eq!(stat.cpus.len(), 2);
+        assert_eq!(stat.cpus[1].user, 6);
+        assert_eq!(stat.context_switches, 5678);
+        assert_eq!(stat.procs_running, 3);
+        assert_eq!(stat.procs_blocked, 1);
+    }
+
+    #[test]
+    fn parse_meminfo_and_diskstats() {
+        let info = MemInfo::parse(
+            "MemTotal:       16384 kB\n\
+             MemFree:         1024 kB\n\
+             MemAvailable:    8192 kB\n\
+             HugePages_Total:    0\n",
+        )
+        .unwrap();
+        assert_eq!(info.total, 16 << 20);
+        assert_eq!(info.free, 1 << 20);
+        assert_eq!(info.available, 8 << 20);
+
+        let disks = DiskStats::parse(
+            "   8       0 sda 100 2 800 30 50 1 400 20 0 45 50 0 0 0 0\n\
+             259       0 nvme0n1 1 0 8 0 2 0 16 1 1 2 1\n",
+        )
+        .unwrap();
+        assert_eq!(disks.len(), 2);
+        assert_eq!(disks[0].name, "sda");
+        assert_eq!(disks[0].sectors_written, 400);
+        assert_eq!(disks[1].in_progress, 1);
+
+        assert!(MemInfo::parse("MemTotal: lots kB\n").is_err());
+    }
+
+    #[test]
+    fn sample_host() {
+        test_executor!(async move {
+            let metrics = HostMetrics::new();
+            let stat = metrics.cpu_stat().await.unwrap();
+            assert!(!stat.cpus.is_empty());
+            let mem = metrics.mem_info().await.unwrap();
+            assert!(mem.total > 0);
+            metrics.disk_stats().await.unwrap();
+            assert!(metrics.read_to_string("proc/does-not-exist").await.is_err());
+        });
+    }
+
+    #[test]
+    fn files_are_read_to_their_end() {
+        let path = std::env::temp_dir().join("host_metrics_files_are_read_to_their_end");
+        let expected: Vec<u8> = (0..READ_SIZE * 3 + 100).map(|x| x as u8).collect();
+        std::fs::write(&path, &expected).unwrap();
+        let file = path.clone();
+        test_executor!(async move {
+            assert_eq!(read_file(&file).await.unwrap(), expected);
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// What the executor does when it allocates memory on one of its hot paths.
+///
+/// Allocations are only visible to the executor if [`CountingAllocator`] is installed as
+/// the global allocator.
+///
+/// [`CountingAllocator`]: struct.CountingAllocator.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HotPathAllocations {
+    /// Allocations are counted and reported through [`ExecutorStats`]
+    ///
+    /// [`ExecutorStats`]: struct.ExecutorStats.html
+    Count,
+    /// Allocations are counted, and the executor panics at the end of any hot path
+    /// that allocated.
+    Panic,
+}
+
+#[derive(Debug, Default)]
+struct AllocationState {
+    hot_path: Cell<Option<&'static str>>,
+    allocations: Cell<u64>,
+    hot_path_allocations: Cell<u64>,
+    panic: Cell<bool>,
+}
+
+thread_local!(static STATE: AllocationState = AllocationState::default());
+
+fn note_allocation() {
+    // try_with, because the allocator can be called while the thread is going away
+    let _ = STATE.try_with(|state| {
+        state.allocations.set(state.allocations.get() + 1);
+        if state.hot_path.get().is_some() {
+            state
+                .hot_path_allocations
+                .set(state.hot_path_allocations.get() + 1);
+        }
+    });
+}
+
+/// A global allocator that counts allocations per thread, so that executors can tell
+/// whether their hot paths allocate.
+///
+/// It forwards all requests to another allocator, the system allocator by default.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::CountingAllocator;
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator = CountingAllocator::new(System);
+///
+/// fn main() {
+///     println!("allocations are now counted");
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+impl<A> CountingAllocator<A> {
+    /// Creates a counting allocator that forwards requests to `inner`
+    pub const fn new(inner: A) -> CountingAllocator<A> {
+        CountingAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        note_allocation();
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        note_allocation();
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        note_allocation();
+        self.inner.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+pub(crate) fn set_mode(mode: HotPathAllocations) {
+    STATE.with(|state| state.panic.set(mode == HotPathAllocations::Panic));
+}
+
+/// Returns how many allocations this thread made in total, and how many of them
+/// happened on a hot path.
+pub(crate) fn allocations() -> (u64, u64) {
+    STATE.with(|state| (state.allocations.get(), state.hot_path_allocations.get()))
+}
+
+/// Marks the code that runs until the returned guard is dropped as a hot path.
+#[cfg(feature = "stats")]
+pub(crate) fn enter(name: &'static str) -> HotPathGuard {
+    STATE.with(|state| HotPathGuard {
+        previous: state.hot_path.replace(Some(name)),
+        hot_path_allocations: state.hot_path_allocations.get(),
+    })
+}
+
+/// Hot paths are only tracked with the `stats` feature.
+#[cfg(not(feature = "stats"))]
+pub(crate) fn enter(_name: &'static str) -> HotPathGuard {
+    HotPathGuard
+}
+
+#[cfg(feature = "stats")]
+#[derive(Debug)]
+pub(crate) struct HotPathGuard {
+    previous: Option<&'static str>,
+    hot_path_allocations: u64,
+}
+
+#[cfg(not(feature = "stats"))]
+#[derive(Debug)]
+pub(crate) struct HotPathGuard;
+
+#[cfg(feature = "stats")]
+impl Drop for HotPathGuard {
+    fn drop(&mut self) {
+        let violation = STATE.with(|state| {
+            let name = state.hot_path.replace(self.previous);
+            let allocated = state.hot_path_allocations.get() - self.hot_path_allocations;
+            if state.panic.get() && allocated > 0 {
+                name.map(|name| (name, allocated))
+            } else {
+                None
+            }
+        });
+
+        if let Some((name, allocated)) = violation {
+            if !std::thread::panicking() {
+                panic!("{} allocations in hot path {}", allocated, name);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod test {
+    use super::*;
+
+    // The library tests don't run under the counting allocator, see tests/hot_path.rs,
+    // so allocations are reported by hand
+
+    #[test]
+    fn hot_path_allocations_are_counted() {
+        let (total, hot) = allocations();
+        {
+            let _guard = enter("test");
+            note_allocation();
+        }
+        let (new_total, new_hot) = allocations();
+        assert_eq!(new_total, total + 1);
+        assert_eq!(new_hot, hot + 1);
+
+        note_allocation();
+        assert_eq!(allocations(), (new_total + 1, new_hot));
+    }
+
+    #[test]
+    #[should_panic(expected = "hot path test")]
+    fn hot_path_allocations_panic() {
+        set_mode(HotPathAllocations::Panic);
+        let _guard = enter("test");
+        note_allocation();
+    }
+}
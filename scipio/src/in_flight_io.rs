@@ -0,0 +1,171 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+// Nothing is tracked without the `io-tracing` feature
+#![cfg_attr(not(feature = "io-tracing"), allow(dead_code))]
+use crate::slow_io::fd_peer;
+use crate::TaskQueueHandle;
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// An I/O operation the executor is still waiting for, as listed by
+/// [`LocalExecutor::in_flight_io`].
+///
+/// The listing is a snapshot: by the time it is looked at, some of the operations may
+/// have completed already.
+///
+/// [`LocalExecutor::in_flight_io`]: struct.LocalExecutor.html#method.in_flight_io
+#[derive(Debug, Clone)]
+pub struct InFlightIo {
+    operation: &'static str,
+    fd: RawFd,
+    path: Option<PathBuf>,
+    peer: Option<SocketAddr>,
+    queue: TaskQueueHandle,
+    tag: Option<u64>,
+    age: Duration,
+    in_kernel: bool,
+    orphaned: bool,
+}
+
+impl InFlightIo {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        operation: &'static str,
+        fd: RawFd,
+        path: Option<PathBuf>,
+        queue: usize,
+        tag: Option<u64>,
+        age: Duration,
+        in_kernel: bool,
+        orphaned: bool,
+    ) -> InFlightIo {
+        InFlightIo {
+            operation,
+            fd,
+            path,
+            peer: fd_peer(fd),
+            queue: TaskQueueHandle::from_index(queue),
+            tag,
+            age,
+            in_kernel,
+            orphaned,
+        }
+    }
+
+    /// What the operation is, like "read", "write" or "poll"
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// The file descriptor the operation is on, or -1 if there is none, like for opens
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// The path of the file the operation is on, if it is on a file
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The address of the other end, if the operation is on a connected socket
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// The task queue whose task submitted the operation
+    pub fn queue(&self) -> TaskQueueHandle {
+        self.queue
+    }
+
+    /// The tag of the operation, if it was submitted within [`Local::with_io_tag`]
+    ///
+    /// [`Local::with_io_tag`]: type.Local.html#method.with_io_tag
+    pub fn tag(&self) -> Option<u64> {
+        self.tag
+    }
+
+    /// How long ago the operation was submitted
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+
+    /// Whether the operation was handed to the kernel already, or is still waiting in
+    /// the executor to be.
+    pub fn in_kernel(&self) -> bool {
+        self.in_kernel
+    }
+
+    /// Whether whoever submitted the operation stopped waiting for it, for instance
+    /// because its future was dropped on a timeout.
+    pub fn orphaned(&self) -> bool {
+        self.orphaned
+    }
+}
+
+impl fmt::Display for InFlightIo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on fd {}", self.operation, self.fd)?;
+        if let Some(path) = &self.path {
+            write!(f, " ({})", path.display())?;
+        }
+        if let Some(peer) = &self.peer {
+            write!(f, " (peer {})", peer)?;
+        }
+        write!(
+            f,
+            ": {} for {:?}, task queue {:?}",
+            if self.in_kernel {
+                "in the kernel"
+            } else {
+                "queued"
+            },
+            self.age,
+            self.queue
+        )?;
+        if let Some(tag) = self.tag {
+            write!(f, ", tag {:#x}", tag)?;
+        }
+        if self.orphaned {
+            write!(f, ", orphaned")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "io-tracing"))]
+mod test {
+    use crate::timer::Timer;
+    use crate::{Async, Local};
+    use std::net::TcpListener;
+    use std::os::unix::io::AsRawFd;
+    use std::time::Duration;
+
+    #[test]
+    fn in_flight_io_is_listed() {
+        test_executor!(async move {
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let fd = listener.as_raw_fd();
+            let accept = Local::local(Local::with_io_tag(7, async move {
+                let _ = listener.accept().await;
+            }));
+            Timer::new(Duration::from_millis(10)).await;
+
+            let listing = Local::in_flight_io();
+            let op = listing.iter().find(|op| op.fd() == fd).unwrap();
+            assert_eq!(op.operation(), "poll");
+            assert_eq!(op.tag(), Some(7));
+            assert_eq!(op.queue(), Local::current_task_queue());
+            assert!(op.age() >= Duration::from_millis(10));
+            assert!(op.in_kernel());
+            assert!(!op.orphaned());
+            assert!(op.to_string().contains("tag 0x7"));
+            accept.cancel().await;
+        });
+    }
+}
@@ -0,0 +1,198 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::sys::DmaBuffer;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+
+/// The future an [`IoEngine`] returns for each operation
+///
+/// [`IoEngine`]: trait.IoEngine.html
+pub type IoFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + 'a>>;
+
+/// A backend that carries out the reads and writes of [`DmaFile`]s, in place of the
+/// io_uring rings of the executor.
+///
+/// Installed with [`LocalExecutor::set_io_engine`], an engine serves every [`DmaFile`]
+/// of the executor through the same API, so a kernel bypass engine built on SPDK, or a
+/// mock engine that injects latencies and failures in tests, can be plugged in without
+/// changing the code that uses the files. Files are still opened, closed and renamed by
+/// the kernel, and the engine is handed their file descriptors to tell them apart.
+///
+/// Operations reach the engine with the alignment [`DmaFile`] requires, but they are
+/// not split by [`DmaFile::max_io_size`]: each engine knows best how large its own
+/// operations can be.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{DmaBuffer, DmaFile, IoEngine, IoFuture, LocalExecutor};
+/// use std::ops::Range;
+/// use std::os::unix::io::RawFd;
+/// use std::rc::Rc;
+///
+/// // An engine that refuses to do anything, to test how an application handles errors
+/// struct Failing;
+///
+/// impl IoEngine for Failing {
+///     fn read_dma(&self, _: RawFd, _: u64, _: usize) -> IoFuture<'static, DmaBuffer> {
+///         Box::pin(async { Err(std::io::ErrorKind::Other.into()) })
+///     }
+///
+///     fn write_dma<'a>(
+///         &self,
+///         _: RawFd,
+///         _: &'a DmaBuffer,
+///         _: Range<usize>,
+///         _: u64,
+///         _: bool,
+///     ) -> IoFuture<'a, usize> {
+///         Box::pin(async { Err(std::io::ErrorKind::Other.into()) })
+///     }
+///
+///     fn fdatasync(&self, _: RawFd) -> IoFuture<'static, ()> {
+///         Box::pin(async { Err(std::io::ErrorKind::Other.into()) })
+///     }
+///
+///     fn fallocate(&self, _: RawFd, _: u64, _: u64, _: i32) -> IoFuture<'static, ()> {
+///         Box::pin(async { Err(std::io::ErrorKind::Other.into()) })
+///     }
+/// }
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+/// ex.set_io_engine(Some(Rc::new(Failing)));
+/// ex.run(async {
+///     let file = DmaFile::create("/tmp/scipio-io-engine-example").await.unwrap();
+///     let buf = DmaFile::alloc_dma_buffer(4096);
+///     assert!(file.write_dma(&buf, 0).await.is_err());
+/// });
+/// ```
+///
+/// [`DmaFile`]: struct.DmaFile.html
+/// [`DmaFile::max_io_size`]: struct.DmaFile.html#method.max_io_size
+/// [`LocalExecutor::set_io_engine`]: struct.LocalExecutor.html#method.set_io_engine
+pub trait IoEngine {
+    /// Reads `size` bytes at `pos` of the file, into a new buffer holding the bytes that
+    /// were read. The buffer is shorter than `size` if the file ends before.
+    fn read_dma(&self, fd: RawFd, pos: u64, size: usize) -> IoFuture<'static, DmaBuffer>;
+
+    /// Writes the bytes of `buf` in `range` at `pos` of the file, and returns how many
+    /// bytes were written. With `dsync`, the write only completes once the data is
+    /// durable.
+    ///
+    /// The buffer is the one the caller allocated, so engines that need memory suitable
+    /// for DMA can hand it to their device as is instead of copying it.
+    fn write_dma<'a>(
+        &self,
+        fd: RawFd,
+        buf: &'a DmaBuffer,
+        range: Range<usize>,
+        pos: u64,
+        dsync: bool,
+    ) -> IoFuture<'a, usize>;
+
+    /// Makes the data written to the file durable.
+    fn fdatasync(&self, fd: RawFd) -> IoFuture<'static, ()>;
+
+    /// Allocates `size` bytes of the file from `offset`, with the `fallocate` `flags`.
+    fn fallocate(&self, fd: RawFd, offset: u64, size: u64, flags: i32) -> IoFuture<'static, ()>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DmaFile, LocalExecutor};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Does the I/O with blocking system calls, and counts the operations it sees
+    #[derive(Default)]
+    struct Blocking {
+        reads: Cell<usize>,
+        writes: Cell<usize>,
+        syncs: Cell<usize>,
+    }
+
+    impl IoEngine for Blocking {
+        fn read_dma(&self, fd: RawFd, pos: u64, size: usize) -> IoFuture<'static, DmaBuffer> {
+            self.reads.set(self.reads.get() + 1);
+            let mut buf = DmaFile::alloc_dma_buffer(size);
+            let res = unsafe { libc::pread(fd, buf.as_mut_ptr() as _, size, pos as _) };
+            Box::pin(async move {
+                if res < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                buf.trim_to_size(res as usize);
+                Ok(buf)
+            })
+        }
+
+        fn write_dma<'a>(
+            &self,
+            fd: RawFd,
+            buf: &'a DmaBuffer,
+            range: Range<usize>,
+            pos: u64,
+            _: bool,
+        ) -> IoFuture<'a, usize> {
+            self.writes.set(self.writes.get() + 1);
+            let bytes = &buf.as_bytes()[range];
+            let res = unsafe { libc::pwrite(fd, bytes.as_ptr() as _, bytes.len(), pos as _) };
+            let res = if res < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(res as usize)
+            };
+            Box::pin(async move { res })
+        }
+
+        fn fdatasync(&self, fd: RawFd) -> IoFuture<'static, ()> {
+            self.syncs.set(self.syncs.get() + 1);
+            let res = unsafe { libc::fdatasync(fd) };
+            let res = if res < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            };
+            Box::pin(async move { res })
+        }
+
+        fn fallocate(&self, _: RawFd, _: u64, _: u64, _: i32) -> IoFuture<'static, ()> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn dma_files_go_through_the_io_engine() {
+        let mut path = std::env::temp_dir();
+        path.push("scipio_io_engine_test");
+        let engine = Rc::new(Blocking::default());
+
+        let ex = LocalExecutor::new(None).unwrap();
+        ex.set_io_engine(Some(engine.clone()));
+        ex.run(async {
+            let file = DmaFile::create(&path).await.unwrap();
+            let buf = DmaFile::alloc_dma_buffer(4096);
+            buf.memset(42);
+            assert_eq!(file.write_dma(&buf, 0).await.unwrap(), 4096);
+            file.fdatasync().await.unwrap();
+
+            let file = DmaFile::open(&path).await.unwrap();
+            let read = file.read_dma(100, 10).await.unwrap();
+            assert_eq!(read.as_bytes(), &[42; 10]);
+            std::fs::remove_file(&path).unwrap();
+        });
+        assert_eq!(engine.writes.get(), 1);
+        assert_eq!(engine.syncs.get(), 1);
+        assert_eq!(engine.reads.get(), 1);
+
+        // without the engine the rings are back in charge
+        ex.set_io_engine(None);
+        assert!(ex.io_engine().is_none());
+    }
+}
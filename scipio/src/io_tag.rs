@@ -0,0 +1,218 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+// Nothing is recorded or reported without the `io-tracing` feature
+#![cfg_attr(not(feature = "io-tracing"), allow(dead_code))]
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+thread_local!(static CURRENT_IO_TAG: Cell<Option<u64>> = Cell::new(None));
+thread_local!(static TAGGED_IO: RefCell<TaggedIo> = RefCell::new(TaggedIo::default()));
+
+// Tags are usually request or trace ids, so there is no end to them. Past this many, the
+// statistics of the tags seen the longest ago are dropped.
+const MAX_TRACKED_TAGS: usize = 4096;
+
+/// The tag of the future being polled, if it runs within a [`WithIoTag`].
+///
+/// [`WithIoTag`]: struct.WithIoTag.html
+pub(crate) fn current() -> Option<u64> {
+    CURRENT_IO_TAG.with(|tag| tag.get())
+}
+
+// Restores the enclosing tag when the inner future is done being polled, even if it
+// panics
+struct Restore(Option<u64>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT_IO_TAG.with(|tag| tag.set(self.0));
+    }
+}
+
+/// A future that tags the I/O operations submitted by another one, created by
+/// [`Local::with_io_tag`].
+///
+/// [`Local::with_io_tag`]: type.Local.html#method.with_io_tag
+pub struct WithIoTag<F: Future> {
+    future: Pin<Box<F>>,
+    tag: u64,
+}
+
+impl<F: Future> WithIoTag<F> {
+    pub(crate) fn new(tag: u64, future: F) -> WithIoTag<F> {
+        WithIoTag {
+            future: Box::pin(future),
+            tag,
+        }
+    }
+
+    /// The tag attached to the I/O operations of the inner future
+    pub fn tag(&self) -> u64 {
+        self.tag
+    }
+}
+
+impl<F: Future> fmt::Debug for WithIoTag<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithIoTag").field("tag", &self.tag).finish()
+    }
+}
+
+impl<F: Future> Future for WithIoTag<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let tag = self.tag;
+        let enclosing = CURRENT_IO_TAG.with(|current| current.replace(Some(tag)));
+        let _restore = Restore(enclosing);
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Statistics about the I/O operations that carried a tag, as returned by
+/// [`Local::io_tag_stats`].
+///
+/// [`Local::io_tag_stats`]: type.Local.html#method.io_tag_stats
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct IoTagStats {
+    submitted: u64,
+    completed: u64,
+    failed: u64,
+    total_latency: Duration,
+    max_latency: Duration,
+}
+
+impl IoTagStats {
+    /// How many operations were submitted to the kernel with this tag
+    pub fn submitted(&self) -> u64 {
+        self.submitted
+    }
+
+    /// How many operations with this tag completed, successfully or not
+    pub fn completed(&self) -> u64 {
+        self.completed
+    }
+
+    /// How many operations with this tag completed with an error
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+
+    /// The sum of the time between submission and completion of the operations with this
+    /// tag
+    pub fn total_latency(&self) -> Duration {
+        self.total_latency
+    }
+
+    /// The longest time an operation with this tag took to complete
+    pub fn max_latency(&self) -> Duration {
+        self.max_latency
+    }
+
+    /// The average time operations with this tag took to complete, or zero if none did
+    pub fn mean_latency(&self) -> Duration {
+        match self.completed {
+            0 => Duration::from_secs(0),
+            n => self.total_latency / n as u32,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TaggedIo {
+    stats: HashMap<u64, IoTagStats>,
+    // the order in which tags were first seen, to know which ones to drop
+    order: VecDeque<u64>,
+}
+
+impl TaggedIo {
+    fn entry(&mut self, tag: u64) -> &mut IoTagStats {
+        if !self.stats.contains_key(&tag) {
+            while self.order.len() >= MAX_TRACKED_TAGS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.stats.remove(&oldest);
+                }
+            }
+            self.order.push_back(tag);
+        }
+        self.stats.entry(tag).or_default()
+    }
+}
+
+pub(crate) fn record_submission(tag: u64) {
+    TAGGED_IO.with(|io| io.borrow_mut().entry(tag).submitted += 1);
+}
+
+pub(crate) fn record_completion(tag: u64, latency: Duration, failed: bool) {
+    // try_with: the reactor's own sources complete while the thread goes away
+    let _ = TAGGED_IO.try_with(|io| {
+        let mut io = io.borrow_mut();
+        let stats = io.entry(tag);
+        stats.completed += 1;
+        if failed {
+            stats.failed += 1;
+        }
+        stats.total_latency += latency;
+        stats.max_latency = std::cmp::max(stats.max_latency, latency);
+    });
+}
+
+pub(crate) fn stats(tag: u64) -> Option<IoTagStats> {
+    TAGGED_IO.with(|io| io.borrow().stats.get(&tag).copied())
+}
+
+pub(crate) fn take_stats(tag: u64) -> Option<IoTagStats> {
+    TAGGED_IO.with(|io| {
+        let mut io = io.borrow_mut();
+        let stats = io.stats.remove(&tag)?;
+        io.order.retain(|t| *t != tag);
+        Some(stats)
+    })
+}
+
+#[cfg(all(test, feature = "io-tracing"))]
+mod test {
+    use crate::{Async, Local};
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn tagged_io_is_accounted_for() {
+        test_executor!(async move {
+            assert!(Local::io_tag().is_none());
+
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+            let accept = Local::local(async move {
+                listener.accept().await.unwrap();
+            });
+
+            Local::with_io_tag(0xfeed, async move {
+                assert_eq!(Local::io_tag(), Some(0xfeed));
+                // nesting replaces the tag, and the enclosing one is back after it
+                Local::with_io_tag(7, async { assert_eq!(Local::io_tag(), Some(7)) }).await;
+                assert_eq!(Local::io_tag(), Some(0xfeed));
+
+                Async::<TcpStream>::connect(addr).await.unwrap();
+            })
+            .await;
+            accept.await;
+            assert!(Local::io_tag().is_none());
+
+            let stats = Local::io_tag_stats(0xfeed).unwrap();
+            assert!(stats.submitted() > 0);
+            assert_eq!(stats.completed(), stats.submitted());
+            assert!(stats.max_latency() >= stats.mean_latency());
+
+            assert_eq!(Local::take_io_tag_stats(0xfeed), Some(stats));
+            assert!(Local::io_tag_stats(0xfeed).is_none());
+        });
+    }
+}
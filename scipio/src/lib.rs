@@ -47,6 +47,17 @@ use crate::parking::Reactor;
 use std::fmt::Debug;
 use std::time::Duration;
 
+// Statistics that only builds with the `stats` feature keep. Without it they compile to
+// nothing, so lean builds don't pay for them.
+macro_rules! stat {
+    ($($body:tt)*) => {
+        #[cfg(feature = "stats")]
+        {
+            $($body)*
+        }
+    };
+}
+
 pub mod parking;
 mod sys;
 pub mod task;
@@ -128,23 +139,122 @@ macro_rules! make_shared_var_mut {
 }
 
 mod async_collections;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod bridge;
+mod bus;
+mod checked_cell;
+pub mod compat;
+mod config;
+mod config_watcher;
+mod cron;
+mod datagram_demux;
+mod deadline;
+mod dir_walker;
 mod dma_file;
+mod dma_pool;
 mod error;
+mod external_loop;
+mod fair_channel;
+mod fair_scheduler;
+mod file_id;
+mod handoff;
+mod host_metrics;
+mod hot_path;
+mod in_flight_io;
+mod io_engine;
+mod io_tag;
+mod load_balancer;
 mod local_semaphore;
+mod mailbox;
+mod memory_budget;
+mod monitor;
 mod multitask;
+mod mux;
 mod networking;
+mod pacer;
 mod pollable;
+mod rng;
+mod rpc;
+mod scoped;
+mod scratch;
+mod send_queue;
+mod slow_io;
+mod supervisor;
 mod timer;
+mod timer_wheel;
+#[cfg(feature = "wakeup-tracking")]
+mod wakeup_tracker;
+mod watchdog;
+#[cfg(feature = "xdp")]
+mod xdp;
 
 pub use crate::async_collections::AsyncDeque;
-pub use crate::dma_file::{Directory, DmaFile};
-pub use crate::error::Error;
-pub use crate::executor::{LocalExecutor, QueueNotFoundError, Task, TaskQueueHandle};
+pub use crate::bridge::bridge;
+pub use crate::bus::{MessageBus, Publisher, Subscription};
+pub use crate::checked_cell::{CheckedRef, CheckedRefCell, CheckedRefMut};
+pub use crate::config::{ConfigError, ExecutorConfig, PoolConfig, TaskQueueConfig};
+pub use crate::config_watcher::{ConfigHandle, ConfigWatcher};
+pub use crate::cron::CronSchedule;
+pub use crate::datagram_demux::{
+    quic_connection_id, DatagramDemux, DemuxConnection, DemuxDatagram,
+};
+pub use crate::deadline::WithDeadline;
+pub use crate::dir_walker::{DirWalk, DirWalker, SymlinkPolicy, WalkEntry};
+pub use crate::dma_file::{Directory, DmaFile, WriteBarrier};
+pub use crate::dma_pool::{DmaBufferPool, DmaLease, DmaPoolSet, DmaPoolStats};
+pub use crate::error::{
+    BudgetExceeded, DeadlineExceeded, Error, ExecutorGone, TimerCancelled, UnsupportedOperation,
+};
+pub use crate::executor::{
+    ExecutorStats, LatencyMiss, LocalExecutor, LocalExecutorBuilder, LocalExecutorPool,
+    LocalExecutorPoolBuilder, MigrationHandle, QueueNotFoundError, StandbyExecutor, Task,
+    TaskPriority, TaskQueueHandle,
+};
+pub use crate::external_loop::{ExternalLoop, ExternalLoopDriver};
+pub use crate::fair_channel::{
+    fair_channel, FairProducers, FairReceiver, FairSender, ProducerStats,
+};
+pub use crate::fair_scheduler::{FairScheduler, InFlight};
+pub use crate::file_id::{FileId, StaleFileError};
+pub use crate::handoff::Handoff;
+pub use crate::host_metrics::{CpuStat, CpuTimes, DiskStats, HostMetrics, MemInfo};
+pub use crate::hot_path::{CountingAllocator, HotPathAllocations};
+pub use crate::in_flight_io::InFlightIo;
+pub use crate::io_engine::{IoEngine, IoFuture};
+pub use crate::io_tag::{IoTagStats, WithIoTag};
+pub use crate::load_balancer::{BalanceError, QueueBalancer};
 pub use crate::local_semaphore::Semaphore;
+pub use crate::mailbox::{Actor, Address, Mailbox};
+pub use crate::memory_budget::{ConnectionBudget, MemoryBudget};
+pub use crate::monitor::{ExecutorMonitor, Gone};
+pub use crate::mux::{Multiplexer, MuxChannel};
 pub use crate::networking::*;
+pub use crate::pacer::Pacer;
 pub use crate::pollable::Async;
-pub use crate::sys::DmaBuffer;
-pub use crate::timer::{Timer, TimerActionOnce, TimerActionRepeat};
+pub use crate::rng::Rng;
+#[cfg(feature = "bincode-codec")]
+pub use crate::rpc::BincodeCodec;
+pub use crate::rpc::{
+    rpc_channel, serve_rpc, Codec, RemoteRpcClient, RpcClient, RpcError, RpcServer,
+};
+pub use crate::scoped::{Scope, ScopedTask};
+pub use crate::scratch::{ScratchDir, ScratchFile, ScratchSpace};
+pub use crate::send_queue::SendQueue;
+pub use crate::slow_io::SlowIo;
+pub use crate::supervisor::{ShardFailure, ShardStart, Supervisor};
+pub use crate::sys::{DmaBuffer, RecvMeta, SendMeta, SendTimestamp};
+pub use crate::timer::{
+    sleep_until, AutoTimer, CancellableTimer, ClockJump, Debouncer, KernelTimer, MissedTicks,
+    RearmHandle, RepeatSchedule, ReportingTimer, Throttler, Timer, TimerActionOnce,
+    TimerActionRepeat, TimerActionSchedule, TimerFired, TimerHandle, TimerInterval, TimerKind,
+    TimerScope, TimerStats, WallClockTimer, WatchdogTimer, KERNEL_TIMER_THRESHOLD,
+};
+#[cfg(feature = "wakeup-tracking")]
+pub use crate::wakeup_tracker::{WakeupIssue, WakeupReport};
+pub use crate::watchdog::{CpuSliceGuard, WatchdogAction, WatchdogReport, WatchdogTerminated};
+#[cfg(feature = "xdp")]
+pub use crate::xdp::{XdpConfig, XdpFrame, XdpSocket, XdpStats};
 
 /// Local is an ergonomic way to access the local executor.
 /// The local is executed through a Task type, but the Task type has a type
@@ -163,10 +273,13 @@ pub type Local = Task<()>;
 /// sensitive. Latency sensitive tasks will be placed in their own I/O ring,
 /// and tasks in background classes can cooperatively preempt themselves in
 /// the faces of pending events for latency classes.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Latency {
     /// Tasks marked as Latency::Matters will cooperatively signal to other tasks that the should
-    /// preempt often
+    /// preempt often. The duration is the latency target: a task queue that is not latency
+    /// sensitive is cut short as soon as a latency sensitive queue has been waiting to run
+    /// for longer than that.
     Matters(Duration),
 
     /// Tasks marked as Latency::NotImportant will not signal to other tasks that the should
@@ -174,6 +287,61 @@ pub enum Latency {
     NotImportant,
 }
 
+/// How an executor spreads storage I/O over its io_uring rings.
+///
+/// Set with [`LocalExecutor::set_ring_policy`].
+///
+/// [`LocalExecutor::set_ring_policy`]: struct.LocalExecutor.html#method.set_ring_policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RingPolicy {
+    /// Reads and writes of every task queue share the same rings: one for files opened
+    /// for polled I/O, and one for the others.
+    Shared,
+
+    /// Reads and writes submitted by task queues marked as [`Latency::Matters`] go to
+    /// rings of their own, apart from the ones used by the other task queues. Their
+    /// completions are collected before the others', and large bulk transfers can't
+    /// delay them in the queues of a shared ring.
+    ///
+    /// [`Latency::Matters`]: enum.Latency.html
+    SeparateLatency,
+}
+
+impl Default for RingPolicy {
+    fn default() -> Self {
+        RingPolicy::Shared
+    }
+}
+
+/// Where an executor arms its [`Timer`]s.
+///
+/// Set with [`LocalExecutor::set_timer_backend`].
+///
+/// [`Timer`]: struct.Timer.html
+/// [`LocalExecutor::set_timer_backend`]: struct.LocalExecutor.html#method.set_timer_backend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimerBackend {
+    /// In a timer wheel kept by the reactor. Before going to sleep, the reactor arms a
+    /// single timeout in the kernel for the timer that expires first. Arming and
+    /// cancelling timers costs no system calls, and timers with slack fire together.
+    Wheel,
+
+    /// Each timer is armed in the rings as a timeout request of its own, and the kernel
+    /// wakes the ring up when it expires. The reactor does not need to look for the next
+    /// timer to expire before going to sleep, and timers fire with the precision of the
+    /// kernel's high resolution timers, but arming and cancelling a timer costs a request
+    /// in the ring, and slack is ignored.
+    Ring,
+}
+
+impl Default for TimerBackend {
+    fn default() -> Self {
+        TimerBackend::Wheel
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct IoRequirements {
     latency_req: Latency,
@@ -0,0 +1,279 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::parking::Reactor;
+use crate::{QueueNotFoundError, Task, TaskQueueHandle};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+
+#[derive(Debug, Clone, Copy)]
+struct Target {
+    handle: TaskQueueHandle,
+    weight: u32,
+}
+
+/// Why a [`QueueBalancer`] could not spawn a task
+///
+/// [`QueueBalancer`]: struct.QueueBalancer.html
+#[derive(Debug, Clone)]
+pub enum BalanceError {
+    /// No task queue has a weight above zero
+    NoQueue,
+    /// The task queue picked is not in the executor
+    QueueNotFound(QueueNotFoundError),
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BalanceError::NoQueue => write!(f, "no task queue with a weight above zero"),
+            BalanceError::QueueNotFound(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+impl From<QueueNotFoundError> for BalanceError {
+    fn from(err: QueueNotFoundError) -> BalanceError {
+        BalanceError::QueueNotFound(err)
+    }
+}
+
+/// Distributes work across task queues at random, in proportion to their weights.
+///
+/// Each call to [`pick`] or [`spawn`] chooses a task queue with a probability given by its
+/// weight over the sum of the weights. Unlike hashing the work to a queue, the split
+/// follows the weights closely no matter how the work is keyed, and the weights can be
+/// changed at any time with [`set_weight`].
+///
+/// With an overload threshold set, task queues that have that many tasks or more waiting
+/// to run are skipped, so a queue that falls behind stops receiving new work until it
+/// catches up. If every task queue is overloaded, the weights alone decide.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{Latency, Local, LocalExecutor, QueueBalancer};
+///
+/// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+/// local_ex.run(async {
+///     let fast = Local::create_task_queue(1000, Latency::NotImportant, "fast");
+///     let slow = Local::create_task_queue(1000, Latency::NotImportant, "slow");
+///
+///     let balancer = QueueBalancer::new();
+///     balancer.add(fast, 3);
+///     balancer.add(slow, 1);
+///     balancer.set_overload_threshold(Some(128));
+///
+///     let task = balancer.spawn(async { 1 + 1 }).unwrap();
+///     assert_eq!(task.await, 2);
+/// });
+/// ```
+///
+/// [`pick`]: struct.QueueBalancer.html#method.pick
+/// [`spawn`]: struct.QueueBalancer.html#method.spawn
+/// [`set_weight`]: struct.QueueBalancer.html#method.set_weight
+#[derive(Debug)]
+pub struct QueueBalancer {
+    targets: RefCell<Vec<Target>>,
+    overload_threshold: Cell<Option<usize>>,
+}
+
+impl Default for QueueBalancer {
+    fn default() -> Self {
+        QueueBalancer::new()
+    }
+}
+
+impl QueueBalancer {
+    /// Creates a balancer with no task queues.
+    pub fn new() -> QueueBalancer {
+        QueueBalancer {
+            targets: RefCell::new(Vec::new()),
+            overload_threshold: Cell::new(None),
+        }
+    }
+
+    /// Adds a task queue with the given weight, or changes its weight if it was already
+    /// added.
+    pub fn add(&self, handle: TaskQueueHandle, weight: u32) {
+        if !self.set_weight(handle, weight) {
+            self.targets.borrow_mut().push(Target { handle, weight });
+        }
+    }
+
+    /// Changes the weight of a task queue. A weight of zero stops sending work to it.
+    ///
+    /// Returns false if the task queue was not added to the balancer.
+    pub fn set_weight(&self, handle: TaskQueueHandle, weight: u32) -> bool {
+        let mut targets = self.targets.borrow_mut();
+        match targets.iter_mut().find(|t| t.handle == handle) {
+            Some(target) => {
+                target.weight = weight;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the weight of a task queue, if it was added to the balancer.
+    pub fn weight(&self, handle: TaskQueueHandle) -> Option<u32> {
+        self.targets
+            .borrow()
+            .iter()
+            .find(|t| t.handle == handle)
+            .map(|t| t.weight)
+    }
+
+    /// Removes a task queue from the balancer. Returns false if it was not there.
+    pub fn remove(&self, handle: TaskQueueHandle) -> bool {
+        let mut targets = self.targets.borrow_mut();
+        let before = targets.len();
+        targets.retain(|t| t.handle != handle);
+        targets.len() != before
+    }
+
+    /// Sets how many tasks waiting to run make a task queue overloaded, or `None` to
+    /// ignore how busy the task queues are. See [`TaskQueueHandle::runnable_tasks`]
+    ///
+    /// [`TaskQueueHandle::runnable_tasks`]: struct.TaskQueueHandle.html#method.runnable_tasks
+    pub fn set_overload_threshold(&self, threshold: Option<usize>) {
+        self.overload_threshold.set(threshold);
+    }
+
+    fn choose(&self, weights: &[u64]) -> Option<usize> {
+        let total: u64 = weights.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let mut point = Reactor::get().rng().below(total);
+        for (idx, weight) in weights.iter().enumerate() {
+            if point < *weight {
+                return Some(idx);
+            }
+            point -= weight;
+        }
+        unreachable!()
+    }
+
+    /// Picks a task queue for the next piece of work, or `None` if no task queue has a
+    /// weight above zero.
+    ///
+    /// Task queues that were removed from the executor are never picked. Must be called
+    /// from the [`LocalExecutor`] that owns the task queues.
+    ///
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    pub fn pick(&self) -> Option<TaskQueueHandle> {
+        let targets = self.targets.borrow();
+        let load: Vec<_> = targets.iter().map(|t| t.handle.runnable_tasks()).collect();
+        let weights: Vec<u64> = targets
+            .iter()
+            .zip(load.iter())
+            .map(|(t, load)| match load {
+                Ok(_) => t.weight as u64,
+                Err(_) => 0,
+            })
+            .collect();
+
+        let idle: Vec<u64> = match self.overload_threshold.get() {
+            Some(threshold) => weights
+                .iter()
+                .zip(load.iter())
+                .map(|(weight, load)| match load {
+                    Ok(load) if *load < threshold => *weight,
+                    _ => 0,
+                })
+                .collect(),
+            None => weights.clone(),
+        };
+
+        self.choose(&idle)
+            .or_else(|| self.choose(&weights))
+            .map(|idx| targets[idx].handle)
+    }
+
+    /// Spawns `future` into the task queue returned by [`pick`].
+    ///
+    /// Fails with [`BalanceError::NoQueue`] if there is no task queue to pick.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from a [`LocalExecutor`].
+    ///
+    /// [`pick`]: struct.QueueBalancer.html#method.pick
+    /// [`BalanceError::NoQueue`]: enum.BalanceError.html#variant.NoQueue
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    pub fn spawn<T: 'static>(
+        &self,
+        future: impl Future<Output = T> + 'static,
+    ) -> Result<Task<T>, BalanceError> {
+        let handle = self.pick().ok_or(BalanceError::NoQueue)?;
+        Ok(Task::local_into(future, handle)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Latency, Local};
+
+    #[test]
+    fn picks_follow_the_weights() {
+        test_executor!(async move {
+            let light = Local::create_task_queue(1000, Latency::NotImportant, "light");
+            let heavy = Local::create_task_queue(1000, Latency::NotImportant, "heavy");
+            let balancer = QueueBalancer::new();
+            assert!(balancer.pick().is_none());
+            balancer.add(light, 1);
+            balancer.add(heavy, 3);
+
+            let heavy_picks = (0..10000)
+                .filter(|_| balancer.pick().unwrap() == heavy)
+                .count();
+            assert!(heavy_picks > 7000 && heavy_picks < 8000, "{}", heavy_picks);
+
+            // re-weighting takes effect right away
+            assert!(balancer.set_weight(heavy, 0));
+            assert!((0..100).all(|_| balancer.pick().unwrap() == light));
+            assert!(balancer.remove(light));
+            assert!(balancer.pick().is_none());
+            match balancer.spawn(async {}) {
+                Err(BalanceError::NoQueue) => {}
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        });
+    }
+
+    #[test]
+    fn overloaded_queues_are_skipped() {
+        test_executor!(async move {
+            let busy = Local::create_task_queue(1000, Latency::NotImportant, "busy");
+            let idle = Local::create_task_queue(1000, Latency::NotImportant, "idle");
+            let balancer = QueueBalancer::new();
+            balancer.add(busy, 100);
+            balancer.add(idle, 1);
+            balancer.set_overload_threshold(Some(10));
+
+            // nothing runs until this task yields, so the backlog builds up
+            let tasks: Vec<_> = (0..10)
+                .map(|_| Local::local_into(async {}, busy).unwrap())
+                .collect();
+            assert_eq!(busy.runnable_tasks().unwrap(), 10);
+            assert!((0..100).all(|_| balancer.pick().unwrap() == idle));
+
+            // with every queue overloaded, the weights decide again
+            balancer.set_overload_threshold(Some(0));
+            assert!((0..100).any(|_| balancer.pick().unwrap() == busy));
+
+            for task in tasks {
+                task.await;
+            }
+            balancer.set_overload_threshold(Some(10));
+            assert!((0..1000).any(|_| balancer.pick().unwrap() == busy));
+        });
+    }
+}
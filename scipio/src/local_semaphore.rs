@@ -3,6 +3,10 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
+use crate::executor::{
+    boost_task_queue, latency_sensitive_task_queue, running_task_queue, unboost_task_queue,
+};
+use crate::TaskQueueHandle;
 use futures::prelude::*;
 use futures::task::{Context, Poll, Waker};
 use std::cell::RefCell;
@@ -31,6 +35,9 @@ impl Future for Waiter {
             Ok(true) => Poll::Ready(Ok(())),
             Ok(false) => {
                 state.add_waker(WaiterId(self.id), self.units, cx.waker().clone());
+                if let Some(waiting) = latency_sensitive_task_queue() {
+                    state.donate_priority(waiting);
+                }
                 Poll::Pending
             }
         }
@@ -44,6 +51,11 @@ struct State {
     waiterset: HashMap<u64, (u64, Waker)>,
     list: VecDeque<u64>,
     closed: bool,
+    // Task queues holding permits, and whether we boosted them.
+    holders: HashMap<u64, (Option<TaskQueueHandle>, bool)>,
+    // Same, for the units taken with acquire, which are given back with signal without
+    // saying by whom: how many each task queue holds, in the order they took them.
+    acquired: VecDeque<(Option<TaskQueueHandle>, u64, bool)>,
 }
 
 impl State {
@@ -54,6 +66,8 @@ impl State {
             waiterset: HashMap::new(),
             closed: false,
             idgen: 0,
+            holders: HashMap::new(),
+            acquired: VecDeque::new(),
         }
     }
 
@@ -95,6 +109,79 @@ impl State {
         self.avail += units;
     }
 
+    fn add_holder(&mut self) -> u64 {
+        self.idgen += 1;
+        self.holders
+            .insert(self.idgen, (running_task_queue(), false));
+        self.idgen
+    }
+
+    fn remove_holder(&mut self, id: u64) {
+        if let Some((Some(queue), true)) = self.holders.remove(&id) {
+            unboost_task_queue(queue);
+        }
+    }
+
+    fn add_acquired(&mut self, units: u64) {
+        if units == 0 {
+            return;
+        }
+        let queue = running_task_queue();
+        match self.acquired.iter_mut().find(|(q, _, _)| *q == queue) {
+            Some((_, held, _)) => *held += units,
+            None => self.acquired.push_back((queue, units, false)),
+        }
+    }
+
+    // Units signaled are taken from what the running task queue acquired first, as it
+    // is usually the one giving them back, then from whoever acquired units first.
+    fn remove_acquired(&mut self, mut units: u64) {
+        let queue = running_task_queue();
+        if let Some(pos) = self.acquired.iter().position(|(q, _, _)| *q == queue) {
+            units = self.release_acquired(pos, units);
+        }
+        while units > 0 && !self.acquired.is_empty() {
+            units = self.release_acquired(0, units);
+        }
+    }
+
+    // Gives back up to `units` of the ones held at `pos`, and returns what is left
+    fn release_acquired(&mut self, pos: usize, units: u64) -> u64 {
+        let (queue, held, boosted) = &mut self.acquired[pos];
+        if *held > units {
+            *held -= units;
+            return 0;
+        }
+        let left = units - *held;
+        if let (Some(queue), true) = (*queue, *boosted) {
+            unboost_task_queue(queue);
+        }
+        self.acquired.remove(pos);
+        left
+    }
+
+    // A latency sensitive queue is waiting on us, so the queues holding permits run
+    // with its priority until they release them. Otherwise a bulk queue could be
+    // sitting on the lock waiting for its turn to run.
+    fn donate_priority(&mut self, waiting: TaskQueueHandle) {
+        let holders = self
+            .holders
+            .values_mut()
+            .map(|holder| (&mut holder.0, &mut holder.1));
+        let acquired = self
+            .acquired
+            .iter_mut()
+            .map(|(queue, _, boosted)| (queue, boosted));
+        for (queue, boosted) in holders.chain(acquired) {
+            match queue {
+                Some(queue) if !*boosted && *queue != waiting => {
+                    *boosted = boost_task_queue(*queue);
+                }
+                _ => {}
+            }
+        }
+    }
+
     fn try_wake_one(&mut self) -> Option<Waker> {
         if let Some(id) = self.list.front() {
             let id = *id;
@@ -126,13 +213,16 @@ impl Waiter {
 #[derive(Debug)]
 pub struct Permit {
     units: u64,
+    holder: u64,
     sem: Rc<RefCell<State>>,
 }
 
 impl Permit {
     fn new(units: u64, sem: Rc<RefCell<State>>) -> Permit {
+        let holder = sem.borrow_mut().add_holder();
         Permit {
             units,
+            holder,
             sem: sem.clone(),
         }
     }
@@ -140,7 +230,9 @@ impl Permit {
 
 impl Drop for Permit {
     fn drop(&mut self) {
-        self.sem.borrow_mut().signal(self.units);
+        let mut state = self.sem.borrow_mut();
+        state.remove_holder(self.holder);
+        state.signal(self.units);
     }
 }
 
@@ -205,7 +297,7 @@ impl Semaphore {
     /// });
     /// ```
     pub async fn acquire_permit(&self, units: u64) -> Result<Permit> {
-        self.acquire(units).await?;
+        self.acquire_units(units).await?;
         Ok(Permit::new(units, self.state.clone()))
     }
 
@@ -228,6 +320,14 @@ impl Semaphore {
     /// });
     /// ```
     pub async fn acquire(&self, units: u64) -> Result<()> {
+        self.acquire_units(units).await?;
+        // The task queue holds the units until they are signaled, so it is boosted like
+        // the holders of permits are
+        self.state.borrow_mut().add_acquired(units);
+        Ok(())
+    }
+
+    async fn acquire_units(&self, units: u64) -> Result<()> {
         // Try acquiring first without paying the price to construct a waker.
         // If that fails then we construct a waker and wait on it.
         if self.state.borrow_mut().try_acquire(units)? {
@@ -260,7 +360,11 @@ impl Semaphore {
     /// });
     /// ```
     pub fn signal(&self, units: u64) {
-        self.state.borrow_mut().signal(units);
+        {
+            let mut state = self.state.borrow_mut();
+            state.remove_acquired(units);
+            state.signal(units);
+        }
         loop {
             if let Some(waiter) = self.state.borrow_mut().try_wake_one() {
                 waiter.wake();
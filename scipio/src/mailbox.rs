@@ -0,0 +1,412 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::{Local, QueueNotFoundError, Task, TaskQueueHandle};
+use futures::future::poll_fn;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+#[derive(Debug)]
+struct Shared<M> {
+    messages: RefCell<VecDeque<M>>,
+    capacity: usize,
+    // Set once the actor stops taking new messages
+    closed: Cell<bool>,
+    addresses: Cell<usize>,
+    receiver: RefCell<Option<Waker>>,
+    // Senders waiting for room in the mailbox, by sender
+    senders: RefCell<HashMap<u64, Waker>>,
+    next_sender: Cell<u64>,
+}
+
+impl<M> Shared<M> {
+    fn close(&self) {
+        self.closed.set(true);
+        self.wake_receiver();
+        self.wake_senders();
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(waker) = self.receiver.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_senders(&self) {
+        let senders: Vec<_> = self.senders.borrow_mut().drain().collect();
+        for (_, waker) in senders {
+            waker.wake();
+        }
+    }
+
+    fn try_send(&self, message: M) -> Result<(), M> {
+        if self.closed.get() || self.messages.borrow().len() >= self.capacity {
+            return Err(message);
+        }
+        self.messages.borrow_mut().push_back(message);
+        self.wake_receiver();
+        Ok(())
+    }
+}
+
+/// A task that owns its state and is only reached through the messages sent to its
+/// mailbox, like an actor.
+///
+/// The task runs the future created by the body the actor is spawned with, which gets
+/// the [`Mailbox`] to receive messages from. Other tasks send messages through the
+/// [`Address`]es of the actor, which can be cloned freely and wait for room in the mailbox
+/// once it holds `capacity` messages.
+///
+/// The actor stops gracefully: once [`stop`] is called, or once it is being joined and
+/// all its addresses are gone, the mailbox takes no new messages, but still hands over those already in it before
+/// telling the actor there are no more. Whatever the body returns, usually its state,
+/// is returned by [`stop`] and [`join`].
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{Actor, LocalExecutor};
+///
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async {
+///     let counter = Actor::spawn(16, |mut mailbox| async move {
+///         let mut total = 0;
+///         while let Some(add) = mailbox.recv().await {
+///             total += add;
+///         }
+///         total
+///     });
+///
+///     let address = counter.address();
+///     address.send(1).await.unwrap();
+///     address.send(2).await.unwrap();
+///     assert_eq!(counter.stop().await, 3);
+///     assert_eq!(address.send(3).await, Err(3));
+/// });
+/// ```
+///
+/// [`Mailbox`]: struct.Mailbox.html
+/// [`Address`]: struct.Address.html
+/// [`stop`]: struct.Actor.html#method.stop
+/// [`join`]: struct.Actor.html#method.join
+#[derive(Debug)]
+pub struct Actor<M, S> {
+    // Keeps the mailbox open until the actor is joined
+    address: Address<M>,
+    task: Task<S>,
+}
+
+impl<M: 'static, S: 'static> Actor<M, S> {
+    /// Spawns an actor in a specific Task Queue, running the future created by `body`
+    /// with its mailbox, which holds up to `capacity` messages.
+    pub fn spawn_into<F, Fut>(
+        capacity: usize,
+        body: F,
+        tq: TaskQueueHandle,
+    ) -> Result<Actor<M, S>, QueueNotFoundError>
+    where
+        F: FnOnce(Mailbox<M>) -> Fut,
+        Fut: Future<Output = S> + 'static,
+    {
+        let shared = Rc::new(Shared {
+            messages: RefCell::new(VecDeque::new()),
+            capacity: capacity.max(1),
+            closed: Cell::new(false),
+            addresses: Cell::new(0),
+            receiver: RefCell::new(None),
+            senders: RefCell::new(HashMap::new()),
+            next_sender: Cell::new(0),
+        });
+        let address = Address::new(shared.clone());
+        let task = Task::local_into(body(Mailbox { shared }), tq)?;
+        Ok(Actor { address, task })
+    }
+
+    /// Spawns an actor in the current Task Queue. See [`spawn_into`]
+    ///
+    /// [`spawn_into`]: struct.Actor.html#method.spawn_into
+    pub fn spawn<F, Fut>(capacity: usize, body: F) -> Actor<M, S>
+    where
+        F: FnOnce(Mailbox<M>) -> Fut,
+        Fut: Future<Output = S> + 'static,
+    {
+        Self::spawn_into(capacity, body, Local::current_task_queue()).unwrap()
+    }
+}
+
+impl<M, S> Actor<M, S> {
+    /// Returns an address to send messages to the actor.
+    pub fn address(&self) -> Address<M> {
+        self.address.clone()
+    }
+
+    /// Stops the actor gracefully, and waits for it to finish.
+    ///
+    /// The mailbox takes no new messages from now on, but the actor still receives the
+    /// ones that were already in it.
+    pub async fn stop(self) -> S {
+        self.address.shared.close();
+        self.task.await
+    }
+
+    /// Waits for the actor to finish on its own, or because all of its other addresses
+    /// are gone and it received all their messages.
+    pub async fn join(self) -> S {
+        let Actor { address, task } = self;
+        drop(address);
+        task.await
+    }
+
+    /// Stops the actor right away, dropping the messages in its mailbox, and waits for
+    /// its task to go away. Returns what the actor returned, if it finished before
+    /// that.
+    pub async fn abort(self) -> Option<S> {
+        let shared = &self.address.shared;
+        shared.close();
+        shared.messages.borrow_mut().clear();
+        self.task.cancel().await
+    }
+}
+
+/// Where an [`Actor`] receives its messages from
+///
+/// [`Actor`]: struct.Actor.html
+#[derive(Debug)]
+pub struct Mailbox<M> {
+    shared: Rc<Shared<M>>,
+}
+
+impl<M> Mailbox<M> {
+    /// Waits for the next message. Returns `None` once the actor was stopped, or all its
+    /// addresses are gone, and the messages sent before that were all received.
+    pub async fn recv(&mut self) -> Option<M> {
+        poll_fn(|cx| {
+            if let Some(message) = self.try_recv() {
+                return Poll::Ready(Some(message));
+            }
+            if self.is_stopped() {
+                return Poll::Ready(None);
+            }
+            *self.shared.receiver.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Returns the next message, if there is one already.
+    pub fn try_recv(&mut self) -> Option<M> {
+        let message = self.shared.messages.borrow_mut().pop_front()?;
+        self.shared.wake_senders();
+        Some(message)
+    }
+
+    /// How many messages are waiting in the mailbox
+    pub fn len(&self) -> usize {
+        self.shared.messages.borrow().len()
+    }
+
+    /// Whether no messages are waiting in the mailbox
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops taking new messages, like [`Actor::stop`] does, for actors that decide to
+    /// stop on their own.
+    ///
+    /// [`Actor::stop`]: struct.Actor.html#method.stop
+    pub fn stop(&self) {
+        self.shared.close();
+    }
+
+    /// Whether the mailbox takes no new messages anymore
+    pub fn is_stopped(&self) -> bool {
+        self.shared.closed.get() || self.shared.addresses.get() == 0
+    }
+}
+
+impl<M> Drop for Mailbox<M> {
+    fn drop(&mut self) {
+        // Nobody is going to receive what is left, or what would be sent from now on
+        self.shared.close();
+        self.shared.messages.borrow_mut().clear();
+    }
+}
+
+/// A handle to send messages to an [`Actor`], created with [`Actor::address`].
+///
+/// Addresses can be cloned and handed to as many tasks of the executor as needed.
+///
+/// [`Actor`]: struct.Actor.html
+/// [`Actor::address`]: struct.Actor.html#method.address
+#[derive(Debug)]
+pub struct Address<M> {
+    shared: Rc<Shared<M>>,
+}
+
+impl<M> Address<M> {
+    fn new(shared: Rc<Shared<M>>) -> Address<M> {
+        shared.addresses.set(shared.addresses.get() + 1);
+        Address { shared }
+    }
+
+    /// Sends `message` to the actor, waiting for room in its mailbox if it is full.
+    ///
+    /// Gives the message back if the actor stopped taking messages.
+    pub async fn send(&self, message: M) -> Result<(), M> {
+        let mut message = Some(message);
+        let sender = Sender {
+            shared: &self.shared,
+            id: self.shared.next_sender.get(),
+        };
+        self.shared.next_sender.set(sender.id + 1);
+        poll_fn(|cx| match self.shared.try_send(message.take().unwrap()) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(m) if self.shared.closed.get() => Poll::Ready(Err(m)),
+            Err(m) => {
+                message = Some(m);
+                let mut senders = self.shared.senders.borrow_mut();
+                let waker = senders
+                    .entry(sender.id)
+                    .or_insert_with(|| cx.waker().clone());
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Sends `message` to the actor if there is room for it in its mailbox, or gives it
+    /// back otherwise.
+    pub fn try_send(&self, message: M) -> Result<(), M> {
+        self.shared.try_send(message)
+    }
+
+    /// Whether the actor stopped taking messages
+    pub fn is_stopped(&self) -> bool {
+        self.shared.closed.get()
+    }
+}
+
+// The place of a task waiting for room in the mailbox, given up when it stops waiting
+struct Sender<'a, M> {
+    shared: &'a Shared<M>,
+    id: u64,
+}
+
+impl<M> Drop for Sender<'_, M> {
+    fn drop(&mut self) {
+        self.shared.senders.borrow_mut().remove(&self.id);
+    }
+}
+
+impl<M> Clone for Address<M> {
+    fn clone(&self) -> Self {
+        Address::new(self.shared.clone())
+    }
+}
+
+impl<M> Drop for Address<M> {
+    fn drop(&mut self) {
+        let addresses = self.shared.addresses.get() - 1;
+        self.shared.addresses.set(addresses);
+        if addresses == 0 {
+            self.shared.wake_receiver();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::timer::Timer;
+    use std::time::Duration;
+
+    #[test]
+    fn actor_owns_its_state() {
+        test_executor!(async move {
+            let actor = Actor::spawn(1, |mut mailbox| async move {
+                let mut received = Vec::new();
+                while let Some(message) = mailbox.recv().await {
+                    received.push(message);
+                    Timer::new(Duration::from_millis(1)).await;
+                }
+                received
+            });
+
+            let address = actor.address();
+            let other = address.clone();
+            let sender = Local::local(async move {
+                for i in 0..5 {
+                    other.send(i).await.unwrap();
+                }
+            });
+            // The mailbox holds a single message, so senders wait for each other
+            address.send(100).await.unwrap();
+            assert_eq!(address.try_send(101), Err(101));
+            sender.await;
+
+            let received = actor.stop().await;
+            assert_eq!(received.len(), 6);
+            assert!(received.contains(&100));
+            assert!(address.is_stopped());
+            assert_eq!(address.send(7).await, Err(7));
+        });
+    }
+
+    #[test]
+    fn actor_stops_when_addresses_are_gone() {
+        test_executor!(async move {
+            let actor = Actor::spawn(8, |mut mailbox| async move {
+                let mut total = 0;
+                while let Some(add) = mailbox.recv().await {
+                    total += add;
+                }
+                total
+            });
+            let address = actor.address();
+            for i in 1..=3 {
+                address.try_send(i).unwrap();
+            }
+            drop(address);
+            // The messages sent before the last address went away still arrive
+            assert_eq!(actor.join().await, 6);
+
+            let stuck = Actor::spawn(8, |mut mailbox| async move {
+                mailbox.recv().await;
+                Timer::new(Duration::from_secs(60)).await;
+            });
+            stuck.address().try_send(()).unwrap();
+            Local::later().await;
+            assert_eq!(stuck.abort().await, None);
+        });
+    }
+
+    #[test]
+    fn waiting_senders_keep_one_waker_each() {
+        test_executor!(async move {
+            // the actor never receives, so the mailbox stays full
+            let actor = Actor::spawn(1, |mailbox| async move { mailbox });
+            let address = actor.address();
+            address.try_send(1).unwrap();
+            {
+                let send = address.send(2);
+                futures::pin_mut!(send);
+                for _ in 0..10 {
+                    assert!(futures::poll!(send.as_mut()).is_pending());
+                }
+                assert_eq!(address.shared.senders.borrow().len(), 1);
+            }
+
+            // senders that give up leave no waker behind
+            assert_eq!(address.shared.senders.borrow().len(), 0);
+            actor.abort().await;
+        });
+    }
+}
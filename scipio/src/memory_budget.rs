@@ -0,0 +1,368 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::error::BudgetExceeded;
+use crate::sys;
+use futures::future::poll_fn;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+// Someone waiting in line for memory. It keeps its place, and its single waker, until
+// the memory fits or it gives up.
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    bytes: usize,
+    // What the connection of the waiter uses
+    used: Rc<Cell<usize>>,
+    waker: Waker,
+    woken: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    per_connection: usize,
+    total: Option<usize>,
+    used: Cell<usize>,
+    connections: Cell<usize>,
+    rejections: Cell<u64>,
+    waiters: RefCell<VecDeque<Waiter>>,
+    next_waiter: Cell<u64>,
+}
+
+impl Shared {
+    // Wakes the waiters that fit in the budget now, in the order they arrived. A waiter
+    // held back by the limit of its own connection doesn't hold back the ones behind it,
+    // but one held back by the total limit does.
+    fn wake_waiters(&self) {
+        let mut free = self
+            .total
+            .map(|total| total.saturating_sub(self.used.get()));
+        for waiter in self.waiters.borrow_mut().iter_mut() {
+            if waiter.bytes > self.per_connection.saturating_sub(waiter.used.get()) {
+                continue;
+            }
+            match &mut free {
+                Some(free) if waiter.bytes > *free => break,
+                Some(free) => *free -= waiter.bytes,
+                None => {}
+            }
+            if !waiter.woken {
+                waiter.woken = true;
+                waiter.waker.wake_by_ref();
+            }
+        }
+    }
+
+    fn leave(&self, id: u64) {
+        self.waiters.borrow_mut().retain(|waiter| waiter.id != id);
+        self.wake_waiters();
+    }
+}
+
+// A place in the line of a budget, given up when dropped
+#[derive(Debug, Default)]
+pub(crate) struct BudgetTicket {
+    place: Option<(Rc<Shared>, u64)>,
+}
+
+impl Drop for BudgetTicket {
+    fn drop(&mut self) {
+        if let Some((shared, id)) = self.place.take() {
+            shared.leave(id);
+        }
+    }
+}
+
+/// Limits how much memory each connection, and optionally all of them together, can
+/// hold in buffers.
+///
+/// Every connection takes a [`ConnectionBudget`] from the `MemoryBudget`, and charges
+/// it for the data it buffers. Once a connection reaches its limit, or all of them
+/// together reach the total limit, buffering more either fails with a
+/// [`BudgetExceeded`] error or waits until memory is released, at the choice of the
+/// caller. That keeps many slow connections, each holding a little data, from
+/// exhausting the memory of the process.
+///
+/// [`SendQueue`]s charge the budget set with [`SendQueue::set_budget`] for the data they
+/// buffer, and [`ConnectionBudget::limit_socket_buffers`] bounds the buffers the kernel
+/// keeps for the socket. Budgets are local to the executor they are created in.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, MemoryBudget};
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     // 64kB per connection, 64MB for all of them
+///     let budget = MemoryBudget::with_total_limit(64 << 10, 64 << 20);
+///     let connection = budget.connection();
+///     connection.try_reserve(4096).unwrap();
+///     assert!(connection.try_reserve(64 << 10).is_err());
+///     connection.release(4096);
+///     assert_eq!(budget.used(), 0);
+/// });
+/// ```
+///
+/// [`ConnectionBudget`]: struct.ConnectionBudget.html
+/// [`BudgetExceeded`]: struct.BudgetExceeded.html
+/// [`SendQueue`]: struct.SendQueue.html
+/// [`SendQueue::set_budget`]: struct.SendQueue.html#method.set_budget
+/// [`ConnectionBudget::limit_socket_buffers`]: struct.ConnectionBudget.html#method.limit_socket_buffers
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    shared: Rc<Shared>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget that allows each connection to hold up to `per_connection` bytes.
+    pub fn new(per_connection: usize) -> MemoryBudget {
+        MemoryBudget::new_with_total(per_connection, None)
+    }
+
+    /// Creates a budget that allows each connection to hold up to `per_connection` bytes,
+    /// and all the connections together up to `total` bytes.
+    pub fn with_total_limit(per_connection: usize, total: usize) -> MemoryBudget {
+        MemoryBudget::new_with_total(per_connection, Some(total))
+    }
+
+    fn new_with_total(per_connection: usize, total: Option<usize>) -> MemoryBudget {
+        MemoryBudget {
+            shared: Rc::new(Shared {
+                per_connection,
+                total,
+                used: Cell::new(0),
+                connections: Cell::new(0),
+                rejections: Cell::new(0),
+                waiters: RefCell::new(VecDeque::new()),
+                next_waiter: Cell::new(0),
+            }),
+        }
+    }
+
+    /// Takes the budget for a new connection.
+    pub fn connection(&self) -> ConnectionBudget {
+        self.shared
+            .connections
+            .set(self.shared.connections.get() + 1);
+        ConnectionBudget {
+            shared: self.shared.clone(),
+            used: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// The bytes currently charged by all connections
+    pub fn used(&self) -> usize {
+        self.shared.used.get()
+    }
+
+    /// The connections currently holding a [`ConnectionBudget`]
+    ///
+    /// [`ConnectionBudget`]: struct.ConnectionBudget.html
+    pub fn connections(&self) -> usize {
+        self.shared.connections.get()
+    }
+
+    /// How many times a connection was refused memory because it was over budget
+    pub fn rejections(&self) -> u64 {
+        self.shared.rejections.get()
+    }
+}
+
+/// The memory budget of a single connection, taken from a [`MemoryBudget`].
+///
+/// Whatever is still charged to the connection is released when it is dropped.
+///
+/// [`MemoryBudget`]: struct.MemoryBudget.html
+#[derive(Debug)]
+pub struct ConnectionBudget {
+    shared: Rc<Shared>,
+    used: Rc<Cell<usize>>,
+}
+
+impl ConnectionBudget {
+    /// The bytes currently charged to this connection
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// The most bytes this connection can hold
+    pub fn limit(&self) -> usize {
+        self.shared.per_connection
+    }
+
+    /// The bytes this connection can still be charged, given both its own limit and the
+    /// total limit of the budget
+    pub fn available(&self) -> usize {
+        let own = self.limit().saturating_sub(self.used());
+        match self.shared.total {
+            Some(total) => cmp::min(own, total.saturating_sub(self.shared.used.get())),
+            None => own,
+        }
+    }
+
+    /// Charges `bytes` to the connection, or fails if they don't fit in the budget.
+    pub fn try_reserve(&self, bytes: usize) -> Result<(), BudgetExceeded> {
+        let available = self.available();
+        if bytes > available {
+            self.shared.rejections.set(self.shared.rejections.get() + 1);
+            return Err(BudgetExceeded {
+                requested: bytes,
+                available,
+            });
+        }
+        self.charge(bytes);
+        Ok(())
+    }
+
+    /// Charges `bytes` to the connection, waiting for memory to be released if they don't
+    /// fit in the budget yet. Connections waiting for memory get it in the order they
+    /// asked for it.
+    ///
+    /// Fails right away if `bytes` is more than the connection can ever hold.
+    pub async fn reserve(&self, bytes: usize) -> Result<(), BudgetExceeded> {
+        if bytes > self.limit() || bytes > self.shared.total.unwrap_or(usize::MAX) {
+            return self.try_reserve(bytes);
+        }
+        let mut ticket = BudgetTicket::default();
+        poll_fn(|cx| self.poll_fit(cx, bytes, &mut ticket)).await;
+        self.charge(bytes);
+        Ok(())
+    }
+
+    // Waits in line until `bytes` fit in the budget. The caller keeps its place in
+    // `ticket` between polls, and must charge what it takes as soon as this is ready.
+    pub(crate) fn poll_fit(
+        &self,
+        cx: &mut Context<'_>,
+        bytes: usize,
+        ticket: &mut BudgetTicket,
+    ) -> Poll<()> {
+        let mut waiters = self.shared.waiters.borrow_mut();
+        match ticket.place.as_ref().map(|(_, id)| *id) {
+            None if waiters.is_empty() && bytes <= self.available() => return Poll::Ready(()),
+            None => {
+                let id = self.shared.next_waiter.get();
+                self.shared.next_waiter.set(id + 1);
+                waiters.push_back(Waiter {
+                    id,
+                    bytes,
+                    used: self.used.clone(),
+                    waker: cx.waker().clone(),
+                    woken: false,
+                });
+                ticket.place = Some((self.shared.clone(), id));
+            }
+            Some(id) => {
+                let pos = waiters
+                    .iter()
+                    .position(|waiter| waiter.id == id)
+                    .expect("waiter lost its place in line");
+                if waiters[pos].woken && bytes <= self.available() {
+                    waiters.remove(pos);
+                    ticket.place = None;
+                    return Poll::Ready(());
+                }
+                let waiter = &mut waiters[pos];
+                waiter.woken = false;
+                if !waiter.waker.will_wake(cx.waker()) {
+                    waiter.waker = cx.waker().clone();
+                }
+            }
+        }
+        drop(waiters);
+        // Whoever is ahead may be held back by the limits of their own connections only
+        self.shared.wake_waiters();
+        Poll::Pending
+    }
+
+    /// Releases `bytes` previously charged to the connection.
+    pub fn release(&self, bytes: usize) {
+        let bytes = cmp::min(bytes, self.used());
+        if bytes == 0 {
+            return;
+        }
+        self.used.set(self.used() - bytes);
+        self.shared.used.set(self.shared.used.get() - bytes);
+        self.shared.wake_waiters();
+    }
+
+    // Charges `bytes` whether they fit or not, for data that was already buffered
+    pub(crate) fn charge(&self, bytes: usize) {
+        self.used.set(self.used() + bytes);
+        self.shared.used.set(self.shared.used.get() + bytes);
+    }
+
+    /// Caps the buffers the kernel keeps for `socket`, so that together they stay within
+    /// the limit of the connection.
+    ///
+    /// Linux doubles the buffer sizes it is asked for to account for its bookkeeping, so
+    /// the send and receive buffers are each asked for a quarter of the limit. The kernel
+    /// doesn't go below its own minimum sizes, however small the limit.
+    pub fn limit_socket_buffers<S: AsRawFd>(&self, socket: &S) -> io::Result<()> {
+        let fd = socket.as_raw_fd();
+        let size = cmp::min(self.limit() / 4, libc::c_int::MAX as usize) as libc::c_int;
+        sys::set_socket_option(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, size)?;
+        sys::set_socket_option(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, size)
+    }
+}
+
+impl Drop for ConnectionBudget {
+    fn drop(&mut self) {
+        self.release(self.used());
+        self.shared
+            .connections
+            .set(self.shared.connections.get() - 1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Local;
+
+    #[test]
+    fn connections_are_held_to_their_budget() {
+        test_executor!(async move {
+            let budget = MemoryBudget::with_total_limit(1000, 1500);
+            let first = budget.connection();
+            let second = budget.connection();
+            assert_eq!(budget.connections(), 2);
+
+            first.try_reserve(1000).unwrap();
+            let err = first.try_reserve(1).unwrap_err();
+            assert_eq!(err.available(), 0);
+
+            // the total limit leaves less than the connection's own
+            assert_eq!(second.available(), 500);
+            assert!(second.try_reserve(600).is_err());
+            assert_eq!(budget.rejections(), 2);
+
+            // waiting for memory is backpressure, not an error
+            let waiter = Local::local(async move {
+                second.reserve(600).await.unwrap();
+                second
+            });
+            Local::later().await;
+            first.release(200);
+            let second = waiter.await;
+            assert_eq!(second.used(), 600);
+            assert!(second.reserve(2000).await.is_err());
+
+            drop(first);
+            assert_eq!(budget.used(), 600);
+            drop(second);
+            assert_eq!(budget.used(), 0);
+            assert_eq!(budget.connections(), 0);
+        });
+    }
+}
@@ -0,0 +1,274 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::error::ExecutorGone;
+use crate::executor::current_monitor;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+const RUNNING: u8 = 0;
+const STOPPED: u8 = 1;
+const POISONED: u8 = 2;
+
+#[derive(Debug)]
+struct Status {
+    id: usize,
+    state: AtomicU8,
+    // Woken from the thread of the executor when it goes away
+    waiters: Mutex<Vec<Waker>>,
+}
+
+/// Watches over an executor from anywhere, to find out when it goes away.
+///
+/// Executors go away either cleanly, when the [`LocalExecutor`] is dropped, or because
+/// something running in them panicked. In the latter case the executor is *poisoned*:
+///
+/// * the panic unwinds out of [`LocalExecutor::run`], and the executor can't be run again.
+///   Calling `run` on it panics right away.
+/// * the thread of executors started with [`LocalExecutor::spawn_executor`] and friends
+///   exits, so joining it returns the panic as an error.
+/// * every monitor of the executor reports it gone, and wakes up the tasks waiting on
+///   [`gone`], in whichever thread they are.
+/// * the [`MessageBus`] stops sending messages to the subscriptions that were created in
+///   the executor, so publishers don't wait forever for room in them.
+/// * calls to an [`rpc_channel`] served by the executor fail with
+///   [`RpcError::ExecutorGone`].
+/// * [`fair_channel`] producers fail to send if the receiver was used from the executor,
+///   and the receiver stops waiting for producers that were used from it.
+/// * the [`LocalExecutorPool`] it belongs to, if any, has its monitor in
+///   [`LocalExecutorPool::monitors`].
+///
+/// Tasks of the executor that were still pending when it went away are never polled
+/// again. Anything outside the executor that waits on them should also watch its
+/// monitor.
+///
+/// Monitors are cheap to clone and can be sent to other threads.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::LocalExecutor;
+///
+/// let local_ex = LocalExecutor::new(None).expect("failed to create local executor");
+/// let monitor = local_ex.monitor();
+/// assert!(monitor.is_alive());
+///
+/// drop(local_ex);
+/// assert!(!monitor.is_alive());
+/// assert!(!monitor.check().unwrap_err().poisoned());
+/// ```
+///
+/// [`LocalExecutor`]: struct.LocalExecutor.html
+/// [`LocalExecutor::run`]: struct.LocalExecutor.html#method.run
+/// [`LocalExecutor::spawn_executor`]: struct.LocalExecutor.html#method.spawn_executor
+/// [`MessageBus`]: struct.MessageBus.html
+/// [`rpc_channel`]: fn.rpc_channel.html
+/// [`RpcError::ExecutorGone`]: enum.RpcError.html#variant.ExecutorGone
+/// [`fair_channel`]: fn.fair_channel.html
+/// [`LocalExecutorPool`]: struct.LocalExecutorPool.html
+/// [`LocalExecutorPool::monitors`]: struct.LocalExecutorPool.html#method.monitors
+/// [`gone`]: struct.ExecutorMonitor.html#method.gone
+#[derive(Debug, Clone)]
+pub struct ExecutorMonitor {
+    status: Arc<Status>,
+}
+
+impl ExecutorMonitor {
+    pub(crate) fn new(id: usize) -> ExecutorMonitor {
+        ExecutorMonitor {
+            status: Arc::new(Status {
+                id,
+                state: AtomicU8::new(RUNNING),
+                waiters: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    // Marks the executor gone and wakes up everybody waiting for it. The first call wins,
+    // so an executor poisoned while running is still poisoned once it is dropped.
+    pub(crate) fn finish(&self, poisoned: bool) {
+        let state = if poisoned { POISONED } else { STOPPED };
+        if self
+            .status
+            .state
+            .compare_exchange(RUNNING, state, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
+        }
+        let waiters = std::mem::replace(&mut *self.status.waiters.lock().unwrap(), Vec::new());
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+
+    /// The id of the executor, as returned by [`LocalExecutor::id`]
+    ///
+    /// [`LocalExecutor::id`]: struct.LocalExecutor.html#method.id
+    pub fn id(&self) -> usize {
+        self.status.id
+    }
+
+    /// Whether the executor is still around
+    pub fn is_alive(&self) -> bool {
+        self.status.state.load(Ordering::Acquire) == RUNNING
+    }
+
+    /// Whether the executor went away because something running in it panicked
+    pub fn is_poisoned(&self) -> bool {
+        self.status.state.load(Ordering::Acquire) == POISONED
+    }
+
+    /// Returns an error if the executor went away.
+    pub fn check(&self) -> Result<(), ExecutorGone> {
+        match self.status.state.load(Ordering::Acquire) {
+            RUNNING => Ok(()),
+            state => Err(ExecutorGone {
+                id: self.status.id,
+                poisoned: state == POISONED,
+            }),
+        }
+    }
+
+    /// Waits until the executor goes away.
+    ///
+    /// The wakeup comes from the thread of the executor that went away, so tasks of
+    /// other executors have to wrap the future returned with [`bridge`].
+    ///
+    /// [`bridge`]: fn.bridge.html
+    pub fn gone(&self) -> Gone {
+        Gone {
+            monitor: self.clone(),
+        }
+    }
+}
+
+/// A future that resolves when an executor goes away, created with
+/// [`ExecutorMonitor::gone`].
+///
+/// [`ExecutorMonitor::gone`]: struct.ExecutorMonitor.html#method.gone
+#[derive(Debug)]
+pub struct Gone {
+    monitor: ExecutorMonitor,
+}
+
+impl Future for Gone {
+    type Output = ExecutorGone;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Err(gone) = self.monitor.check() {
+            return Poll::Ready(gone);
+        }
+        let mut waiters = self.monitor.status.waiters.lock().unwrap();
+        // The executor may have gone away while we took the lock
+        if let Err(gone) = self.monitor.check() {
+            return Poll::Ready(gone);
+        }
+        if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+            waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[derive(Debug, Default)]
+struct OwnerState {
+    monitor: Option<ExecutorMonitor>,
+    // Waiting for the owner to be known, so they can wait for it to go away
+    watchers: Vec<Waker>,
+}
+
+// The executor the end of a channel is used from, which is only known once it is used.
+// The other end watches it, so it doesn't wait forever on an executor that went away.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Owner {
+    state: Arc<Mutex<OwnerState>>,
+}
+
+impl Owner {
+    // Makes the current executor the owner, unless there is one already
+    pub(crate) fn claim(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.monitor.is_some() {
+            return;
+        }
+        state.monitor = current_monitor();
+        if state.monitor.is_some() {
+            for watcher in state.watchers.drain(..) {
+                watcher.wake();
+            }
+        }
+    }
+
+    // Returns an error if the owner went away
+    pub(crate) fn check(&self) -> Result<(), ExecutorGone> {
+        match &self.state.lock().unwrap().monitor {
+            Some(monitor) => monitor.check(),
+            None => Ok(()),
+        }
+    }
+
+    // Resolves once the owner went away. Wakeups come from other threads.
+    pub(crate) fn poll_gone(&self, cx: &mut Context<'_>) -> Poll<ExecutorGone> {
+        let mut state = self.state.lock().unwrap();
+        match state.monitor.clone() {
+            Some(monitor) => {
+                drop(state);
+                Pin::new(&mut monitor.gone()).poll(cx)
+            }
+            None => {
+                if !state.watchers.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.watchers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bridge, LocalExecutor};
+
+    #[test]
+    fn panics_poison_the_executor() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = LocalExecutor::spawn_executor("poisoned", None, move || async move {
+            sender.send(crate::Local::monitor()).unwrap();
+            crate::Local::later().await;
+            panic!("boom");
+        })
+        .unwrap();
+        let monitor = receiver.recv().unwrap();
+
+        test_executor!(async move {
+            let gone = bridge(monitor.gone()).await.unwrap();
+            assert!(gone.poisoned());
+            assert_eq!(gone.id(), monitor.id());
+            assert!(monitor.is_poisoned());
+        });
+        assert!(handle.join().is_err());
+    }
+
+    #[test]
+    fn poisoned_executors_cannot_run_again() {
+        let local_ex = LocalExecutor::new(None).unwrap();
+        let monitor = local_ex.monitor();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            local_ex.run(async { panic!("boom") });
+        }));
+        assert!(panicked.is_err());
+        assert!(monitor.is_poisoned());
+
+        let again = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            local_ex.run(async {});
+        }));
+        assert!(again.is_err());
+    }
+}
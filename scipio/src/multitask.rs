@@ -128,24 +128,35 @@ impl<T> Future for Task<T> {
     }
 }
 
+/// How many priority levels tasks can have, 0 being the highest.
+pub(crate) const PRIORITY_LEVELS: usize = 3;
+
 #[derive(Debug)]
 struct LocalQueue {
-    queue: RefCell<VecDeque<Runnable>>,
+    /// Runnable tasks, in one lane per priority level.
+    lanes: RefCell<[VecDeque<Runnable>; PRIORITY_LEVELS]>,
 }
 
 impl LocalQueue {
     fn new() -> Rc<Self> {
         Rc::new(LocalQueue {
-            queue: RefCell::new(VecDeque::new()),
+            lanes: RefCell::new([VecDeque::new(), VecDeque::new(), VecDeque::new()]),
         })
     }
 
-    fn push(&self, runnable: Runnable) {
-        self.queue.borrow_mut().push_back(runnable);
+    fn push(&self, runnable: Runnable, priority: usize) {
+        self.lanes.borrow_mut()[priority].push_back(runnable);
     }
 
     fn pop(&self) -> Option<Runnable> {
-        self.queue.borrow_mut().pop_front()
+        self.lanes
+            .borrow_mut()
+            .iter_mut()
+            .find_map(|lane| lane.pop_front())
+    }
+
+    fn len(&self) -> usize {
+        self.lanes.borrow().iter().map(|lane| lane.len()).sum()
     }
 }
 
@@ -175,13 +186,20 @@ impl LocalExecutor {
     }
 
     /// Spawns a thread-local future onto this executor.
-    pub(crate) fn spawn<T: 'static>(&self, future: impl Future<Output = T> + 'static) -> Task<T> {
+    ///
+    /// Whenever it is runnable, the task is run before those of a lower `priority`
+    /// level, 0 being the highest.
+    pub(crate) fn spawn<T: 'static>(
+        &self,
+        future: impl Future<Output = T> + 'static,
+        priority: usize,
+    ) -> Task<T> {
         let callback = self.callback.clone();
         let queue = self.local_queue.clone();
 
         // The function that schedules a runnable task when it gets woken up.
         let schedule = move |runnable: Runnable| {
-            queue.push(runnable);
+            queue.push(runnable, priority);
             callback.call();
         };
 
@@ -197,6 +215,11 @@ impl LocalExecutor {
     pub(crate) fn get_task(&self) -> Option<Runnable> {
         self.local_queue.pop()
     }
+
+    /// Returns the number of tasks waiting in the queue to be run.
+    pub(crate) fn runnable_tasks(&self) -> usize {
+        self.local_queue.len()
+    }
 }
 
 impl Drop for LocalExecutor {
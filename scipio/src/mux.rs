@@ -0,0 +1,649 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::local_semaphore::Semaphore;
+use crate::{Local, Task};
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+// Every frame starts with the id of its channel, its kind and the length of its payload.
+const HEADER_SIZE: usize = 9;
+
+const FRAME_OPEN: u8 = 0;
+const FRAME_DATA: u8 = 1;
+const FRAME_CREDIT: u8 = 2;
+const FRAME_CLOSE: u8 = 3;
+
+// The most channels the peer can have open at once, so it can't make us hold an
+// unbounded amount of them
+const MAX_REMOTE_CHANNELS: usize = 4096;
+
+#[derive(Debug)]
+struct Frame {
+    id: u32,
+    kind: u8,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.push(self.kind);
+        buf.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+    }
+}
+
+#[derive(Debug)]
+struct ChannelState {
+    id: u32,
+    inbox: RefCell<VecDeque<Vec<u8>>>,
+    recv_waker: RefCell<Option<Waker>>,
+    // Bytes we can still send before the peer grants us more
+    credit: Semaphore,
+    // Bytes we received and handed out, but did not give back to the peer as credit yet
+    consumed: Cell<u32>,
+    // Bytes we received, but did not give back to the peer as credit yet. The peer is
+    // never allowed to have more than the window in flight.
+    received: Cell<u32>,
+    remote_closed: Cell<bool>,
+}
+
+impl ChannelState {
+    fn new(id: u32, window: u32) -> Rc<ChannelState> {
+        Rc::new(ChannelState {
+            id,
+            inbox: RefCell::new(VecDeque::new()),
+            recv_waker: RefCell::new(None),
+            credit: Semaphore::new(window as u64),
+            consumed: Cell::new(0),
+            received: Cell::new(0),
+            remote_closed: Cell::new(false),
+        })
+    }
+
+    fn remote_close(&self) {
+        self.remote_closed.set(true);
+        self.credit.close();
+        if let Some(waker) = self.recv_waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    window: u32,
+    next_id: u32,
+    // The last channel the peer opened. Each side opens its channels in order, and the
+    // ones of the peer have the other parity than ours.
+    last_remote_id: u32,
+    remote_channels: usize,
+    closed: bool,
+    channels: HashMap<u32, Rc<ChannelState>>,
+    incoming: VecDeque<Rc<ChannelState>>,
+    accept_waker: Option<Waker>,
+    outbound: VecDeque<Frame>,
+    writer_waker: Option<Waker>,
+}
+
+impl Shared {
+    fn is_remote(&self, id: u32) -> bool {
+        id % 2 != self.next_id % 2
+    }
+
+    fn push(&mut self, frame: Frame) {
+        self.outbound.push_back(frame);
+        if let Some(waker) = self.writer_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        for channel in self.channels.values() {
+            channel.remote_close();
+        }
+        for waker in self
+            .accept_waker
+            .take()
+            .into_iter()
+            .chain(self.writer_waker.take())
+        {
+            waker.wake();
+        }
+    }
+}
+
+/// Multiplexes many logical channels over a single byte stream, usually a
+/// `Async<TcpStream>` connecting two shards.
+///
+/// Each channel carries a sequence of messages in each direction. Messages are framed
+/// with the id of their channel, so channels are independent of each other: every channel
+/// has a window of bytes it can send before its peer consumes them, and a channel whose
+/// receiver is slow stops its senders without holding back the other channels.
+///
+/// One side of the connection must be created as the initiator and the other one not,
+/// so the ids of the channels each of them opens never collide. A peer that opens a
+/// channel with an id that isn't its own, opens the same channel twice, or keeps more
+/// than 4096 channels open at once breaks the protocol, and the connection is closed.
+///
+/// The multiplexer runs two tasks of its own in the current executor, one reading from
+/// the stream and one writing to it. They stop, and all channels are closed, when the
+/// multiplexer is dropped or when the stream fails.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{Async, LocalExecutor, Multiplexer};
+/// use std::os::unix::net::UnixStream;
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     let (a, b) = Async::<UnixStream>::pair().unwrap();
+///     let client = Multiplexer::new(a, true, 64 << 10);
+///     let server = Multiplexer::new(b, false, 64 << 10);
+///
+///     let channel = client.open().unwrap();
+///     channel.send(b"ping").await.unwrap();
+///
+///     let peer = server.accept().await.unwrap();
+///     assert_eq!(peer.recv().await.unwrap(), b"ping");
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Multiplexer {
+    shared: Rc<RefCell<Shared>>,
+    _reader: Task<()>,
+    _writer: Task<()>,
+}
+
+impl Multiplexer {
+    /// Starts multiplexing channels over `stream`. Every channel can send `window`
+    /// bytes before its peer consumes them, which bounds the size of its messages too.
+    ///
+    /// This must be called from a [`LocalExecutor`]
+    ///
+    /// [`LocalExecutor`]: struct.LocalExecutor.html
+    pub fn new<S>(stream: S, initiator: bool, window: u32) -> Multiplexer
+    where
+        S: AsyncRead + AsyncWrite + Unpin + 'static,
+    {
+        let shared = Rc::new(RefCell::new(Shared {
+            window,
+            next_id: if initiator { 1 } else { 2 },
+            last_remote_id: 0,
+            remote_channels: 0,
+            closed: false,
+            channels: HashMap::new(),
+            incoming: VecDeque::new(),
+            accept_waker: None,
+            outbound: VecDeque::new(),
+            writer_waker: None,
+        }));
+
+        let (reader, writer) = stream.split();
+        let reader_shared = shared.clone();
+        let reader = Local::local(async move {
+            let _ = read_frames(reader, &reader_shared).await;
+            reader_shared.borrow_mut().close();
+        });
+        let writer_shared = shared.clone();
+        let writer = Local::local(async move {
+            let _ = write_frames(writer, &writer_shared).await;
+            writer_shared.borrow_mut().close();
+        });
+
+        Multiplexer {
+            shared,
+            _reader: reader,
+            _writer: writer,
+        }
+    }
+
+    /// Opens a new channel. The peer receives it from [`accept`]
+    ///
+    /// [`accept`]: struct.Multiplexer.html#method.accept
+    pub fn open(&self) -> io::Result<MuxChannel> {
+        let mut shared = self.shared.borrow_mut();
+        if shared.closed {
+            return Err(closed_error());
+        }
+        let id = shared.next_id;
+        shared.next_id += 2;
+        let state = ChannelState::new(id, shared.window);
+        shared.channels.insert(id, state.clone());
+        shared.push(Frame {
+            id,
+            kind: FRAME_OPEN,
+            payload: Vec::new(),
+        });
+        Ok(MuxChannel {
+            state,
+            shared: self.shared.clone(),
+        })
+    }
+
+    /// Waits for the peer to open a channel. Returns None once the multiplexer is
+    /// closed.
+    pub async fn accept(&self) -> Option<MuxChannel> {
+        let state = poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            if let Some(state) = shared.incoming.pop_front() {
+                Poll::Ready(Some(state))
+            } else if shared.closed {
+                Poll::Ready(None)
+            } else {
+                shared.accept_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await?;
+        Some(MuxChannel {
+            state,
+            shared: self.shared.clone(),
+        })
+    }
+
+    /// Whether the underlying stream failed or was closed by the peer
+    pub fn is_closed(&self) -> bool {
+        self.shared.borrow().closed
+    }
+}
+
+impl Drop for Multiplexer {
+    fn drop(&mut self) {
+        self.shared.borrow_mut().close();
+    }
+}
+
+fn closed_error() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the channel is closed")
+}
+
+// The peer broke the framing or flow control, so the connection can't be trusted anymore
+fn protocol_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+async fn read_frames<R: AsyncRead + Unpin>(
+    mut reader: R,
+    shared: &RefCell<Shared>,
+) -> io::Result<()> {
+    let mut header = [0u8; HEADER_SIZE];
+    loop {
+        reader.read_exact(&mut header).await?;
+        let id = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = header[4];
+        let len = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+        // No frame can be larger than the window, so don't trust the peer with more
+        if len > shared.borrow().window {
+            return Err(protocol_error(format!(
+                "frame of {} bytes for channel {} is larger than the window",
+                len, id
+            )));
+        }
+        let mut payload = vec![0u8; len as usize];
+        reader.read_exact(&mut payload).await?;
+
+        let mut shared = shared.borrow_mut();
+        if kind == FRAME_OPEN {
+            if !shared.is_remote(id) {
+                return Err(protocol_error(format!(
+                    "the peer opened channel {}, which is in our id space",
+                    id
+                )));
+            }
+            if id <= shared.last_remote_id {
+                return Err(protocol_error(format!(
+                    "the peer opened channel {} again",
+                    id
+                )));
+            }
+            if shared.remote_channels >= MAX_REMOTE_CHANNELS {
+                return Err(protocol_error(format!(
+                    "the peer opened more than {} channels",
+                    MAX_REMOTE_CHANNELS
+                )));
+            }
+            shared.last_remote_id = id;
+            shared.remote_channels += 1;
+            let state = ChannelState::new(id, shared.window);
+            shared.channels.insert(id, state.clone());
+            shared.incoming.push_back(state);
+            if let Some(waker) = shared.accept_waker.take() {
+                waker.wake();
+            }
+            continue;
+        }
+
+        // Frames for channels we already dropped are ignored
+        let state = match shared.channels.get(&id) {
+            Some(state) => state.clone(),
+            None => continue,
+        };
+        match kind {
+            FRAME_DATA => {
+                let received = state.received.get() + len;
+                if received > shared.window {
+                    return Err(protocol_error(format!(
+                        "channel {} sent more than its window",
+                        id
+                    )));
+                }
+                state.received.set(received);
+                state.inbox.borrow_mut().push_back(payload);
+                if let Some(waker) = state.recv_waker.borrow_mut().take() {
+                    waker.wake();
+                }
+            }
+            FRAME_CREDIT if payload.len() == 4 => {
+                let credit = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                state.credit.signal(credit as u64);
+            }
+            FRAME_CLOSE => state.remote_close(),
+            _ => {
+                return Err(protocol_error(format!(
+                    "invalid frame of kind {} for channel {}",
+                    kind, id
+                )));
+            }
+        }
+    }
+}
+
+async fn write_frames<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    shared: &RefCell<Shared>,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        // Coalesce all the frames queued so far into a single write
+        let closed = poll_fn(|cx| {
+            let mut shared = shared.borrow_mut();
+            while let Some(frame) = shared.outbound.pop_front() {
+                frame.encode(&mut buf);
+            }
+            if !buf.is_empty() || shared.closed {
+                Poll::Ready(shared.closed)
+            } else {
+                shared.writer_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+
+        writer.write_all(&buf).await?;
+        writer.flush().await?;
+        buf.clear();
+        if closed {
+            return writer.close().await;
+        }
+    }
+}
+
+/// A logical channel of a [`Multiplexer`]
+///
+/// Dropping the channel closes it: the peer receives the messages that were already
+/// sent, and then its [`recv`] returns None.
+///
+/// [`Multiplexer`]: struct.Multiplexer.html
+/// [`recv`]: struct.MuxChannel.html#method.recv
+#[derive(Debug)]
+pub struct MuxChannel {
+    state: Rc<ChannelState>,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl MuxChannel {
+    /// The id of the channel, unique within its multiplexer
+    pub fn id(&self) -> u32 {
+        self.state.id
+    }
+
+    /// Sends a message to the peer, waiting for the peer to consume enough of the
+    /// previous messages if the window of the channel is full.
+    ///
+    /// Fails if the message is larger than the window of the channel, or if the
+    /// channel was closed.
+    pub async fn send(&self, msg: &[u8]) -> io::Result<()> {
+        if msg.len() > self.shared.borrow().window as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message larger than the window of the channel",
+            ));
+        }
+        self.state
+            .credit
+            .acquire(msg.len() as u64)
+            .await
+            .map_err(|_| closed_error())?;
+
+        let mut shared = self.shared.borrow_mut();
+        if shared.closed || self.state.remote_closed.get() {
+            return Err(closed_error());
+        }
+        shared.push(Frame {
+            id: self.state.id,
+            kind: FRAME_DATA,
+            payload: msg.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Receives the next message from the peer. Returns None once the peer closed the
+    /// channel and all its messages were received.
+    pub async fn recv(&self) -> Option<Vec<u8>> {
+        let msg = poll_fn(|cx| {
+            if let Some(msg) = self.state.inbox.borrow_mut().pop_front() {
+                Poll::Ready(Some(msg))
+            } else if self.state.remote_closed.get() {
+                Poll::Ready(None)
+            } else {
+                *self.state.recv_waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await?;
+
+        // Give credit back in batches, so not every message causes a frame of its own
+        let mut shared = self.shared.borrow_mut();
+        let consumed = self.state.consumed.get() + msg.len() as u32;
+        if consumed >= shared.window / 2 && !shared.closed {
+            shared.push(Frame {
+                id: self.state.id,
+                kind: FRAME_CREDIT,
+                payload: consumed.to_be_bytes().to_vec(),
+            });
+            self.state.consumed.set(0);
+            self.state
+                .received
+                .set(self.state.received.get().saturating_sub(consumed));
+        } else {
+            self.state.consumed.set(consumed);
+        }
+        Some(msg)
+    }
+}
+
+impl Drop for MuxChannel {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        let id = self.state.id;
+        if shared.channels.remove(&id).is_some() && shared.is_remote(id) {
+            shared.remote_channels -= 1;
+        }
+        if !shared.closed {
+            shared.push(Frame {
+                id: self.state.id,
+                kind: FRAME_CLOSE,
+                payload: Vec::new(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Async, Timer};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    #[test]
+    fn channels_are_multiplexed() {
+        test_executor!(async move {
+            let (a, b) = Async::<UnixStream>::pair().unwrap();
+            let client = Multiplexer::new(a, true, 1024);
+            let server = Multiplexer::new(b, false, 1024);
+
+            let echo = Local::local(async move {
+                while let Some(channel) = server.accept().await {
+                    Local::local(async move {
+                        while let Some(msg) = channel.recv().await {
+                            channel.send(&msg).await.unwrap();
+                        }
+                    })
+                    .detach();
+                }
+            });
+
+            let first = client.open().unwrap();
+            let second = client.open().unwrap();
+            assert_ne!(first.id(), second.id());
+
+            second.send(b"second").await.unwrap();
+            first.send(b"first").await.unwrap();
+            assert_eq!(first.recv().await.unwrap(), b"first");
+            assert_eq!(second.recv().await.unwrap(), b"second");
+
+            assert!(first.send(&[0; 2048]).await.is_err());
+            drop(client);
+            assert!(first.recv().await.is_none());
+            echo.await;
+        });
+    }
+
+    #[test]
+    fn channels_have_flow_control() {
+        test_executor!(async move {
+            let (a, b) = Async::<UnixStream>::pair().unwrap();
+            let client = Multiplexer::new(a, true, 16);
+            let server = Multiplexer::new(b, false, 16);
+
+            let sender = client.open().unwrap();
+            let receiver = server.accept().await.unwrap();
+            let sent = Rc::new(Cell::new(0));
+            let counter = sent.clone();
+            let sending = Local::local(async move {
+                for _ in 0..3 {
+                    sender.send(&[1; 8]).await.unwrap();
+                    counter.set(counter.get() + 1);
+                }
+                sender
+            });
+
+            // The third message does not fit in the window until the first ones are read
+            Timer::new(Duration::from_millis(50)).await;
+            assert_eq!(sent.get(), 2);
+
+            assert_eq!(receiver.recv().await.unwrap(), vec![1; 8]);
+            let sender = sending.await;
+            assert_eq!(sent.get(), 3);
+
+            drop(sender);
+            assert!(receiver.recv().await.is_some());
+            assert!(receiver.recv().await.is_some());
+            assert!(receiver.recv().await.is_none());
+        });
+    }
+
+    #[test]
+    fn peers_that_overrun_the_window_are_disconnected() {
+        test_executor!(async move {
+            let frame = |id, kind, payload: &[u8]| {
+                let mut buf = Vec::new();
+                Frame {
+                    id,
+                    kind,
+                    payload: payload.to_vec(),
+                }
+                .encode(&mut buf);
+                buf
+            };
+
+            // Each message fits in the window, but not both before any credit comes back
+            let (mut a, b) = Async::<UnixStream>::pair().unwrap();
+            let server = Multiplexer::new(b, false, 16);
+            let mut bytes = frame(1, FRAME_OPEN, &[]);
+            bytes.extend(frame(1, FRAME_DATA, &[1; 10]));
+            bytes.extend(frame(1, FRAME_DATA, &[2; 10]));
+            AsyncWriteExt::write_all(&mut a, &bytes).await.unwrap();
+
+            let channel = server.accept().await.unwrap();
+            assert_eq!(channel.recv().await.unwrap(), vec![1; 10]);
+            assert!(channel.recv().await.is_none());
+            assert!(server.is_closed());
+
+            // A frame larger than the window is rejected before its payload is read
+            let (mut a, b) = Async::<UnixStream>::pair().unwrap();
+            let server = Multiplexer::new(b, false, 16);
+            let mut header = frame(1, FRAME_OPEN, &[]);
+            header.extend(&1u32.to_be_bytes());
+            header.push(FRAME_DATA);
+            header.extend(&u32::MAX.to_be_bytes());
+            AsyncWriteExt::write_all(&mut a, &header).await.unwrap();
+
+            let channel = server.accept().await.unwrap();
+            assert!(channel.recv().await.is_none());
+            assert!(server.is_closed());
+        });
+    }
+
+    #[test]
+    fn peers_that_open_invalid_channels_are_disconnected() {
+        test_executor!(async move {
+            let frame = |id| {
+                let mut buf = Vec::new();
+                Frame {
+                    id,
+                    kind: FRAME_OPEN,
+                    payload: Vec::new(),
+                }
+                .encode(&mut buf);
+                buf
+            };
+            let opens = |ids: Vec<u32>| ids.into_iter().flat_map(frame).collect::<Vec<u8>>();
+
+            // Ids of our own, reused ids, and more channels than allowed
+            let too_many = (0..=MAX_REMOTE_CHANNELS as u32)
+                .map(|n| 2 * n + 1)
+                .collect();
+            for (ids, accepted) in vec![
+                (vec![1, 2], 1),
+                (vec![3, 3], 1),
+                (vec![5, 3], 1),
+                (too_many, MAX_REMOTE_CHANNELS),
+            ] {
+                let (mut a, b) = Async::<UnixStream>::pair().unwrap();
+                let server = Multiplexer::new(b, false, 16);
+                AsyncWriteExt::write_all(&mut a, &opens(ids)).await.unwrap();
+
+                let mut channels = Vec::new();
+                while let Some(channel) = server.accept().await {
+                    channels.push(channel);
+                }
+                assert_eq!(channels.len(), accepted);
+                assert!(server.is_closed());
+            }
+        });
+    }
+}
@@ -3,17 +3,28 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
-use std::io;
-use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::{
     os::unix::net::{SocketAddr as UnixSocketAddr, UnixDatagram, UnixListener, UnixStream},
     path::Path,
+    sync::Arc,
 };
 
 use futures_lite::stream::{self, Stream};
-use socket2::{Domain, Protocol, Socket, Type};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 
+use crate::parking::Reactor;
 use crate::pollable::Async;
+use crate::sys::{self, RecvMeta, SendMeta, SendTimestamp};
+use crate::Local;
+
+// How much we ask the kernel to send in a single sendfile call.
+const SEND_FILE_CHUNK: u64 = 1 << 20;
 
 impl Async<TcpListener> {
     /// Creates a TCP listener bound to the specified address.
@@ -54,7 +65,7 @@ impl Async<TcpListener> {
     /// # std::io::Result::Ok(()) });
     /// ```
     pub async fn accept(&self) -> io::Result<(Async<TcpStream>, SocketAddr)> {
-        let (stream, addr) = self.read_with(|io| io.accept()).await?;
+        let (stream, addr) = self.accept_with(|io| io.accept()).await?;
         Ok((Async::new(stream)?, addr))
     }
 
@@ -85,6 +96,100 @@ impl Async<TcpListener> {
             Some((res, listener))
         }))
     }
+
+    /// Returns a stream of incoming TCP connections that pass `filter`.
+    ///
+    /// `filter` is called with the remote address of every connection right after it is
+    /// accepted. Connections for which it returns `false` are closed on the spot and never
+    /// show up in the stream, so there is no need to spawn a task only to reject them. IP
+    /// allow lists or connection rate limits are good candidates to be implemented here.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::Async;
+    /// use futures_lite::stream::StreamExt;
+    /// use std::net::TcpListener;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let listener = Async::<TcpListener>::bind(([0, 0, 0, 0], 8000))?;
+    /// let mut incoming = listener.incoming_filtered(|addr| addr.ip().is_loopback());
+    ///
+    /// while let Some(stream) = incoming.next().await {
+    ///     let stream = stream?;
+    ///     println!("Accepted local client: {}", stream.get_ref().peer_addr()?);
+    /// }
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn incoming_filtered<'a, F>(
+        &'a self,
+        filter: F,
+    ) -> impl Stream<Item = io::Result<Async<TcpStream>>> + Unpin + 'a
+    where
+        F: FnMut(&SocketAddr) -> bool + 'a,
+    {
+        Box::pin(stream::unfold(
+            (self, filter),
+            |(listener, mut filter)| async move {
+                loop {
+                    match listener.accept_with(|io| io.accept()).await {
+                        Ok((stream, addr)) => {
+                            if filter(&addr) {
+                                return Some((Async::new(stream), (listener, filter)));
+                            }
+                            drop(stream);
+                            Local::yield_if_needed().await;
+                        }
+                        Err(err) => return Some((Err(err), (listener, filter))),
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Stops accepting connections, so an overloaded server stops its intake at the
+    /// kernel boundary instead of accepting connections only to close them.
+    ///
+    /// The poll waiting for new connections is taken out of the kernel, and tasks waiting
+    /// in [`accept`] or on [`incoming`] streams keep waiting until [`resume_accepting`] is
+    /// called. New connections queue up in the kernel backlog meanwhile, and once that
+    /// is full the kernel stops completing handshakes, so clients back off.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::Async;
+    /// use std::net::TcpListener;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 8000))?;
+    /// listener.pause_accepting();
+    /// // ... once the load goes down
+    /// listener.resume_accepting();
+    /// let (stream, addr) = listener.accept().await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [`accept`]: struct.Async.html#method.accept
+    /// [`incoming`]: struct.Async.html#method.incoming
+    /// [`resume_accepting`]: struct.Async.html#method.resume_accepting
+    pub fn pause_accepting(&self) {
+        self.pause_intake();
+    }
+
+    /// Resumes accepting connections after [`pause_accepting`]
+    ///
+    /// [`pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn resume_accepting(&self) {
+        self.resume_intake();
+    }
+
+    /// Returns whether accepting connections is paused. See [`pause_accepting`]
+    ///
+    /// [`pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn is_accepting_paused(&self) -> bool {
+        self.is_intake_paused()
+    }
 }
 
 impl Async<TcpStream> {
@@ -163,6 +268,84 @@ impl Async<TcpStream> {
     pub async fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
         self.read_with(|io| io.peek(buf)).await
     }
+
+    /// Sends `len` bytes of `file`, starting at `offset`, through this stream without
+    /// copying them through user space.
+    ///
+    /// Reading the file may block on the disk, so the transfer is carried out by a helper
+    /// thread of the reactor, and the executor keeps running other tasks meanwhile.
+    ///
+    /// Returns the number of bytes sent, which is smaller than `len` only if the end of
+    /// the file is reached. Dropping the returned future cancels the transfer, although
+    /// some data may already have been sent.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::Async;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let stream = Async::<TcpStream>::connect(([127, 0, 0, 1], 8000)).await?;
+    /// let file = std::fs::File::open("/etc/hosts")?;
+    /// let sent = stream.send_file(&file, 0, 1024).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn send_file<F: AsRawFd>(&self, file: &F, offset: u64, len: u64) -> io::Result<u64> {
+        self.send_file_with_progress(file, offset, len, |_| {})
+            .await
+    }
+
+    /// Same as [`send_file`], but calls `progress` with the total amount of bytes sent
+    /// so far every time more data makes it into the socket.
+    ///
+    /// [`send_file`]: struct.Async.html#method.send_file
+    pub async fn send_file_with_progress<F, P>(
+        &self,
+        file: &F,
+        offset: u64,
+        len: u64,
+        progress: P,
+    ) -> io::Result<u64>
+    where
+        F: AsRawFd,
+        P: FnMut(u64),
+    {
+        let mut progress = progress;
+        // The helper gets descriptors of its own, so a transfer that is still running
+        // after the future is dropped can't write to whatever reuses our descriptors.
+        let fds = unsafe {
+            Arc::new((
+                File::from_raw_fd(sys::duplicate_file(self.as_raw_fd())?),
+                File::from_raw_fd(sys::duplicate_file(file.as_raw_fd())?),
+            ))
+        };
+        let mut sent = 0;
+        while sent < len {
+            let chunk = std::cmp::min(len - sent, SEND_FILE_CHUNK) as usize;
+            let pos = offset + sent;
+            let job_fds = fds.clone();
+            let source = Reactor::get().run_blocking(self.as_raw_fd(), "sendfile", move || {
+                let (sock, file) = &*job_fds;
+                sys::send_file(sock.as_raw_fd(), file.as_raw_fd(), pos, chunk)
+            });
+            let res = match source.collect_rw().await {
+                // The socket is full, but the transfer can go on once it drains
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.writable().await?;
+                    continue;
+                }
+                res => res?,
+            };
+            if res == 0 {
+                break;
+            }
+            sent += res as u64;
+            progress(sent);
+            Local::yield_if_needed().await;
+        }
+        Ok(sent)
+    }
 }
 
 impl Async<UdpSocket> {
@@ -338,6 +521,180 @@ impl Async<UdpSocket> {
     pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
         self.write_with(|io| io.send(buf)).await
     }
+
+    fn is_ipv6(&self) -> io::Result<bool> {
+        Ok(self.get_ref().local_addr()?.is_ipv6())
+    }
+
+    /// Enables or disables receive timestamps, reported by [`recv_msg`].
+    ///
+    /// Datagrams are stamped by the kernel, and by the network card too if it supports
+    /// it and hardware timestamping was turned on for the device, which takes privileges
+    /// and is left to the system configuration.
+    ///
+    /// [`recv_msg`]: struct.Async.html#method.recv_msg
+    pub fn set_recv_timestamps(&self, enabled: bool) -> io::Result<()> {
+        let fd = self.get_ref().as_raw_fd();
+        sys::set_timestamping(fd, sys::RECV_TIMESTAMPING, enabled)
+    }
+
+    /// Enables or disables transmit timestamps, read back with [`recv_send_timestamp`].
+    ///
+    /// The datagrams sent from then on are numbered from zero, and each gets stamped as
+    /// it leaves, by the kernel and by the network card under the same conditions as
+    /// [`set_recv_timestamps`].
+    ///
+    /// [`recv_send_timestamp`]: struct.Async.html#method.recv_send_timestamp
+    /// [`set_recv_timestamps`]: struct.Async.html#method.set_recv_timestamps
+    pub fn set_send_timestamps(&self, enabled: bool) -> io::Result<()> {
+        let fd = self.get_ref().as_raw_fd();
+        sys::set_timestamping(fd, sys::SEND_TIMESTAMPING, enabled)
+    }
+
+    /// Receives the next transmit timestamp enabled with [`set_send_timestamps`].
+    ///
+    /// The timestamps come back on the error queue of the socket, which wakes its readers
+    /// as incoming datagrams do, so this is best called from the same task that receives
+    /// them. Errors queued for earlier datagrams are returned as such.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, SendMeta};
+    /// use std::net::UdpSocket;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let socket = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0))?;
+    /// socket.set_send_timestamps(true)?;
+    ///
+    /// socket.send_msg(b"hello", ([127, 0, 0, 1], 8000), &SendMeta::new()).await?;
+    /// let stamp = socket.recv_send_timestamp().await?;
+    /// println!("datagram {} sent at {:?}", stamp.id(), stamp.timestamp());
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [`set_send_timestamps`]: struct.Async.html#method.set_send_timestamps
+    pub async fn recv_send_timestamp(&self) -> io::Result<SendTimestamp> {
+        self.read_with(|io| sys::recv_send_timestamp(io.as_raw_fd()))
+            .await
+    }
+
+    /// Enables or disables reporting, through [`recv_msg`], of the local address each
+    /// datagram was sent to.
+    ///
+    /// [`recv_msg`]: struct.Async.html#method.recv_msg
+    pub fn set_recv_pktinfo(&self, enabled: bool) -> io::Result<()> {
+        let fd = self.get_ref().as_raw_fd();
+        if self.is_ipv6()? {
+            sys::set_socket_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, enabled as _)
+        } else {
+            sys::set_socket_option(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, enabled as _)
+        }
+    }
+
+    /// Enables or disables reporting, through [`recv_msg`], of the TOS byte (and with it,
+    /// the ECN bits) of each datagram.
+    ///
+    /// [`recv_msg`]: struct.Async.html#method.recv_msg
+    pub fn set_recv_tos(&self, enabled: bool) -> io::Result<()> {
+        let fd = self.get_ref().as_raw_fd();
+        if self.is_ipv6()? {
+            sys::set_socket_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, enabled as _)
+        } else {
+            sys::set_socket_option(fd, libc::IPPROTO_IP, libc::IP_RECVTOS, enabled as _)
+        }
+    }
+
+    /// Enables or disables UDP generic receive offload.
+    ///
+    /// With it enabled the kernel may hand many datagrams from the same flow to
+    /// [`recv_msg`] in a single buffer, and [`RecvMeta::segment_size`] tells how to split
+    /// them apart again. Datagrams can be sent in batches the same way with
+    /// [`SendMeta::segment_size`].
+    ///
+    /// [`recv_msg`]: struct.Async.html#method.recv_msg
+    /// [`RecvMeta::segment_size`]: struct.RecvMeta.html#method.segment_size
+    /// [`SendMeta::segment_size`]: struct.SendMeta.html#method.segment_size
+    pub fn set_recv_gro(&self, enabled: bool) -> io::Result<()> {
+        let fd = self.get_ref().as_raw_fd();
+        sys::set_socket_option(fd, libc::IPPROTO_UDP, sys::UDP_GRO, enabled as _)
+    }
+
+    /// Caps the rate at which the kernel sends the datagrams of this socket, in bytes per
+    /// second, or lifts the cap with `None`.
+    ///
+    /// The kernel spaces the datagrams out itself, with a precision timers in user space
+    /// can't match, which is what protocols like QUIC need to pace their packets. It is
+    /// enforced by the `fq` queueing discipline, and by TCP-style internal pacing
+    /// otherwise. Rates above 4GB/s are not capped. To pace each connection sharing the
+    /// socket on its own, use a [`Pacer`] instead.
+    ///
+    /// [`Pacer`]: struct.Pacer.html
+    pub fn set_max_pacing_rate(&self, rate: Option<u64>) -> io::Result<()> {
+        let fd = self.get_ref().as_raw_fd();
+        // the kernel reads the rate as unsigned, with all bits set meaning no cap
+        let rate = rate.unwrap_or(u64::MAX).min(u32::MAX as u64) as u32;
+        sys::set_socket_option(fd, libc::SOL_SOCKET, sys::SO_MAX_PACING_RATE, rate as _)
+    }
+
+    /// Receives a single datagram message together with its ancillary data.
+    ///
+    /// Returns the number of bytes read, the address the message came from and the
+    /// ancillary data that was enabled with [`set_recv_timestamps`], [`set_recv_pktinfo`]
+    /// and [`set_recv_tos`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::Async;
+    /// use std::net::UdpSocket;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let socket = Async::<UdpSocket>::bind(([127, 0, 0, 1], 8000))?;
+    /// socket.set_recv_timestamps(true)?;
+    ///
+    /// let mut buf = [0u8; 1024];
+    /// let (len, addr, meta) = socket.recv_msg(&mut buf).await?;
+    /// println!("received at {:?}", meta.timestamp());
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [`set_recv_timestamps`]: struct.Async.html#method.set_recv_timestamps
+    /// [`set_recv_pktinfo`]: struct.Async.html#method.set_recv_pktinfo
+    /// [`set_recv_tos`]: struct.Async.html#method.set_recv_tos
+    pub async fn recv_msg(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, RecvMeta)> {
+        self.read_with(|io| sys::recv_msg(io.as_raw_fd(), buf))
+            .await
+    }
+
+    /// Sends data to the specified address, attaching the ancillary data in `meta`.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, SendMeta};
+    /// use std::net::UdpSocket;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let socket = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0))?;
+    ///
+    /// let msg = b"hello";
+    /// let meta = SendMeta::new().ecn(0b10);
+    /// let len = socket.send_msg(msg, ([127, 0, 0, 1], 8000), &meta).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn send_msg<A: Into<SocketAddr>>(
+        &self,
+        buf: &[u8],
+        addr: A,
+        meta: &SendMeta,
+    ) -> io::Result<usize> {
+        let addr = addr.into();
+        self.write_with(|io| sys::send_msg(io.as_raw_fd(), buf, addr, meta))
+            .await
+    }
 }
 
 impl Async<UnixListener> {
@@ -377,7 +734,7 @@ impl Async<UnixListener> {
     /// # std::io::Result::Ok(()) });
     /// ```
     pub async fn accept(&self) -> io::Result<(Async<UnixStream>, UnixSocketAddr)> {
-        let (stream, addr) = self.read_with(|io| io.accept()).await?;
+        let (stream, addr) = self.accept_with(|io| io.accept()).await?;
         Ok((Async::new(stream)?, addr))
     }
 
@@ -408,6 +765,29 @@ impl Async<UnixListener> {
             Some((res, listener))
         }))
     }
+
+    /// Stops accepting connections until [`resume_accepting`] is called, like
+    /// [`Async<TcpListener>::pause_accepting`] does.
+    ///
+    /// [`resume_accepting`]: struct.Async.html#method.resume_accepting
+    /// [`Async<TcpListener>::pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn pause_accepting(&self) {
+        self.pause_intake();
+    }
+
+    /// Resumes accepting connections after [`pause_accepting`]
+    ///
+    /// [`pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn resume_accepting(&self) {
+        self.resume_intake();
+    }
+
+    /// Returns whether accepting connections is paused. See [`pause_accepting`]
+    ///
+    /// [`pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn is_accepting_paused(&self) -> bool {
+        self.is_intake_paused()
+    }
 }
 
 impl Async<UnixStream> {
@@ -464,6 +844,92 @@ impl Async<UnixStream> {
     }
 }
 
+impl Async<UnixStream> {
+    /// Transfers a connected TCP stream to the executor that holds the other end of
+    /// this UDS stream, along with the bytes already read from it that the other
+    /// executor has to see, like those read to find out which executor the connection
+    /// belongs to.
+    ///
+    /// The reactor of every executor unshares its file descriptor table from the rest of
+    /// the process when it starts, so a descriptor number means nothing, or another file,
+    /// to other executors, and the stream can't simply be moved to another thread.
+    /// Instead, the file descriptor is sent over this UDS stream, which installs it in
+    /// the table of the receiving side, and is reconstructed there with
+    /// [`recv_tcp_stream`]. Once this returns the stream is no longer registered with
+    /// the current executor.
+    ///
+    /// The UDS pair has to be created before the executors are, so the tables of both
+    /// of them have it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, LocalExecutor};
+    /// use std::net::TcpListener;
+    /// use std::os::unix::net::UnixStream;
+    ///
+    /// let (sender, receiver) = UnixStream::pair().unwrap();
+    ///
+    /// let handle = LocalExecutor::spawn_executor("receiver", None, move || async move {
+    ///     let channel = Async::new(receiver).unwrap();
+    ///     let (stream, buffered) = channel.recv_tcp_stream().await.unwrap();
+    ///     println!("Got stream from {:?}", stream.get_ref().peer_addr());
+    ///     println!("It starts with {:?}", buffered);
+    /// }).unwrap();
+    ///
+    /// let ex = LocalExecutor::new(None).unwrap();
+    /// ex.run(async move {
+    ///     let channel = Async::new(sender).unwrap();
+    ///     let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 8000)).unwrap();
+    ///     let (stream, _) = listener.accept().await.unwrap();
+    ///     channel.send_tcp_stream(stream, &[]).await.unwrap();
+    /// });
+    /// handle.join().unwrap();
+    /// ```
+    ///
+    /// [`recv_tcp_stream`]: struct.Async.html#method.recv_tcp_stream
+    pub async fn send_tcp_stream(
+        &self,
+        stream: Async<TcpStream>,
+        buffered: &[u8],
+    ) -> io::Result<()> {
+        if buffered.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many buffered bytes",
+            ));
+        }
+        // The descriptor and the bytes that go with it make up a frame, which is sent
+        // whole before the next handoff over this stream starts
+        let _handoff = self.handoff.lock().await;
+        let fd = stream.as_raw_fd();
+        self.write_with(|io| sys::send_fd(io.as_raw_fd(), fd))
+            .await?;
+        // The in-flight message holds a reference to the socket on behalf of the
+        // receiver, so our copy can go.
+        drop(stream);
+        self.send_all(&(buffered.len() as u32).to_be_bytes())
+            .await?;
+        self.send_all(buffered).await
+    }
+
+    /// Receives a TCP stream sent with [`send_tcp_stream`] by another executor, and
+    /// registers it with the current executor. Returns it along with the bytes the
+    /// sender had already read from it, which come before anything read from the stream.
+    ///
+    /// [`send_tcp_stream`]: struct.Async.html#method.send_tcp_stream
+    pub async fn recv_tcp_stream(&self) -> io::Result<(Async<TcpStream>, Vec<u8>)> {
+        let _handoff = self.handoff.lock().await;
+        let fd = self.read_with(|io| sys::recv_fd(io.as_raw_fd())).await?;
+        let stream = Async::new(unsafe { TcpStream::from_raw_fd(fd) })?;
+        let mut len = [0u8; 4];
+        self.recv_exact(&mut len).await?;
+        let mut buffered = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.recv_exact(&mut buffered).await?;
+        Ok((stream, buffered))
+    }
+}
+
 impl Async<UnixDatagram> {
     /// Creates a UDS datagram socket bound to the specified path.
     ///
@@ -608,3 +1074,735 @@ impl Async<UnixDatagram> {
         self.write_with(|io| io.send(buf)).await
     }
 }
+
+// From linux/vm_sockets.h
+const AF_VSOCK: libc::c_int = 40;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct SockaddrVm {
+    family: libc::sa_family_t,
+    reserved: u16,
+    port: u32,
+    cid: u32,
+    zero: [u8; 4],
+}
+
+/// The address of a VSOCK socket, made of a context id (CID) and a port.
+///
+/// The context id names the machine: every virtual machine gets its own, and the host
+/// is always [`VsockAddr::CID_HOST`].
+///
+/// [`VsockAddr::CID_HOST`]: struct.VsockAddr.html#associatedconstant.CID_HOST
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VsockAddr {
+    cid: u32,
+    port: u32,
+}
+
+impl VsockAddr {
+    /// Binds to every context id of the local machine
+    pub const CID_ANY: u32 = u32::MAX;
+
+    /// The hypervisor
+    pub const CID_HYPERVISOR: u32 = 0;
+
+    /// The local machine, for loopback connections
+    pub const CID_LOCAL: u32 = 1;
+
+    /// The host, as seen from a virtual machine
+    pub const CID_HOST: u32 = 2;
+
+    /// Asks the kernel to pick a free port when binding
+    pub const PORT_ANY: u32 = u32::MAX;
+
+    /// Creates an address from a context id and a port.
+    pub fn new(cid: u32, port: u32) -> VsockAddr {
+        VsockAddr { cid, port }
+    }
+
+    /// The context id of the machine
+    pub fn cid(&self) -> u32 {
+        self.cid
+    }
+
+    /// The port on that machine
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    fn to_sockaddr(&self) -> SockAddr {
+        let raw = SockaddrVm {
+            family: AF_VSOCK as libc::sa_family_t,
+            port: self.port,
+            cid: self.cid,
+            ..Default::default()
+        };
+        unsafe {
+            SockAddr::from_raw_parts(
+                &raw as *const SockaddrVm as *const libc::sockaddr,
+                mem::size_of::<SockaddrVm>() as libc::socklen_t,
+            )
+        }
+    }
+
+    fn from_sockaddr(addr: &SockAddr) -> io::Result<VsockAddr> {
+        if addr.family() != AF_VSOCK as libc::sa_family_t
+            || (addr.len() as usize) < mem::size_of::<SockaddrVm>()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a VSOCK address",
+            ));
+        }
+        let raw = unsafe { *(addr.as_ptr() as *const SockaddrVm) };
+        Ok(VsockAddr::new(raw.cid, raw.port))
+    }
+}
+
+impl fmt::Display for VsockAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vsock:{}:{}", self.cid, self.port)
+    }
+}
+
+fn vsock_socket(ty: Type) -> io::Result<Socket> {
+    Socket::new(Domain::from(AF_VSOCK), ty, None)
+}
+
+/// A VSOCK socket listening for connections, to be used as an [`Async`] like a
+/// [`TcpListener`].
+///
+/// [`Async`]: struct.Async.html
+/// [`TcpListener`]: https://doc.rust-lang.org/std/net/struct.TcpListener.html
+#[derive(Debug)]
+pub struct VsockListener(Socket);
+
+impl VsockListener {
+    /// The address the listener is bound to
+    pub fn local_addr(&self) -> io::Result<VsockAddr> {
+        VsockAddr::from_sockaddr(&self.0.local_addr()?)
+    }
+}
+
+impl AsRawFd for VsockListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// A VSOCK stream between a virtual machine and its host, to be used as an [`Async`]
+/// like a [`TcpStream`].
+///
+/// [`Async`]: struct.Async.html
+/// [`TcpStream`]: https://doc.rust-lang.org/std/net/struct.TcpStream.html
+#[derive(Debug)]
+pub struct VsockStream(Socket);
+
+impl VsockStream {
+    /// The address of the local end of the stream
+    pub fn local_addr(&self) -> io::Result<VsockAddr> {
+        VsockAddr::from_sockaddr(&self.0.local_addr()?)
+    }
+
+    /// The address of the remote end of the stream
+    pub fn peer_addr(&self) -> io::Result<VsockAddr> {
+        VsockAddr::from_sockaddr(&self.0.peer_addr()?)
+    }
+
+    /// Shuts down the reading, writing, or both halves of the stream.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.0.shutdown(how)
+    }
+}
+
+impl AsRawFd for VsockStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Read for VsockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Read for &VsockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&self.0).read(buf)
+    }
+}
+
+impl Write for VsockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Write for &VsockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&self.0).flush()
+    }
+}
+
+/// A VSOCK datagram socket, to be used as an [`Async`] like a [`UdpSocket`].
+///
+/// Not every hypervisor supports VSOCK datagrams, VMware does but KVM doesn't.
+///
+/// [`Async`]: struct.Async.html
+/// [`UdpSocket`]: https://doc.rust-lang.org/std/net/struct.UdpSocket.html
+#[derive(Debug)]
+pub struct VsockDatagram(Socket);
+
+impl VsockDatagram {
+    /// The address the socket is bound to
+    pub fn local_addr(&self) -> io::Result<VsockAddr> {
+        VsockAddr::from_sockaddr(&self.0.local_addr()?)
+    }
+
+    /// Connects the socket to a remote address, so that `send` and `recv` can be used.
+    pub fn connect(&self, addr: VsockAddr) -> io::Result<()> {
+        self.0.connect(&addr.to_sockaddr())
+    }
+}
+
+impl AsRawFd for VsockDatagram {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Async<VsockListener> {
+    /// Creates a VSOCK listener bound to the specified address.
+    ///
+    /// Binding with [`VsockAddr::PORT_ANY`] will request an available port from the OS.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, VsockAddr, VsockListener};
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let addr = VsockAddr::new(VsockAddr::CID_ANY, 1024);
+    /// let listener = Async::<VsockListener>::bind(addr)?;
+    /// println!("Listening on {}", listener.get_ref().local_addr()?);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [`VsockAddr::PORT_ANY`]: struct.VsockAddr.html#associatedconstant.PORT_ANY
+    pub fn bind(addr: VsockAddr) -> io::Result<Async<VsockListener>> {
+        let socket = vsock_socket(Type::stream())?;
+        socket.bind(&addr.to_sockaddr())?;
+        socket.listen(128)?;
+        Ok(Async::new(VsockListener(socket))?)
+    }
+
+    /// Accepts a new incoming VSOCK connection.
+    ///
+    /// When a connection is established, it will be returned as a VSOCK stream together
+    /// with its remote address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, VsockAddr, VsockListener};
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let addr = VsockAddr::new(VsockAddr::CID_ANY, 1024);
+    /// let listener = Async::<VsockListener>::bind(addr)?;
+    /// let (stream, addr) = listener.accept().await?;
+    /// println!("Accepted client: {}", addr);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn accept(&self) -> io::Result<(Async<VsockStream>, VsockAddr)> {
+        let (socket, addr) = self.accept_with(|io| io.0.accept()).await?;
+        Ok((
+            Async::new(VsockStream(socket))?,
+            VsockAddr::from_sockaddr(&addr)?,
+        ))
+    }
+
+    /// Returns a stream of incoming VSOCK connections.
+    ///
+    /// The stream is infinite, i.e. it never stops with a [`None`].
+    pub fn incoming(&self) -> impl Stream<Item = io::Result<Async<VsockStream>>> + Unpin + '_ {
+        Box::pin(stream::unfold(self, |listener| async move {
+            let res = listener.accept().await.map(|(stream, _)| stream);
+            Some((res, listener))
+        }))
+    }
+
+    /// Stops accepting connections until [`resume_accepting`] is called, like
+    /// [`Async<TcpListener>::pause_accepting`] does.
+    ///
+    /// [`resume_accepting`]: struct.Async.html#method.resume_accepting
+    /// [`Async<TcpListener>::pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn pause_accepting(&self) {
+        self.pause_intake();
+    }
+
+    /// Resumes accepting connections after [`pause_accepting`]
+    ///
+    /// [`pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn resume_accepting(&self) {
+        self.resume_intake();
+    }
+
+    /// Returns whether accepting connections is paused. See [`pause_accepting`]
+    ///
+    /// [`pause_accepting`]: struct.Async.html#method.pause_accepting
+    pub fn is_accepting_paused(&self) -> bool {
+        self.is_intake_paused()
+    }
+}
+
+impl Async<VsockStream> {
+    /// Creates a VSOCK connection to the specified address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, VsockAddr, VsockStream};
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// // An agent in a virtual machine, talking to the host
+    /// let addr = VsockAddr::new(VsockAddr::CID_HOST, 1024);
+    /// let stream = Async::<VsockStream>::connect(addr).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn connect(addr: VsockAddr) -> io::Result<Async<VsockStream>> {
+        let socket = vsock_socket(Type::stream())?;
+
+        // Begin async connect and ignore the inevitable "in progress" error.
+        socket.set_nonblocking(true)?;
+        socket.connect(&addr.to_sockaddr()).or_else(|err| {
+            if err.raw_os_error() == Some(libc::EINPROGRESS) {
+                Ok(())
+            } else {
+                Err(err)
+            }
+        })?;
+        let stream = Async::new(VsockStream(socket))?;
+
+        // The stream becomes writable when connected.
+        stream.writable().await?;
+
+        // Check if there was an error while connecting.
+        match stream.get_ref().0.take_error()? {
+            None => Ok(stream),
+            Some(err) => Err(err),
+        }
+    }
+}
+
+impl Async<VsockDatagram> {
+    /// Creates a VSOCK datagram socket bound to the specified address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, VsockAddr, VsockDatagram};
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let addr = VsockAddr::new(VsockAddr::CID_ANY, VsockAddr::PORT_ANY);
+    /// let socket = Async::<VsockDatagram>::bind(addr)?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn bind(addr: VsockAddr) -> io::Result<Async<VsockDatagram>> {
+        let socket = vsock_socket(Type::dgram())?;
+        socket.bind(&addr.to_sockaddr())?;
+        Ok(Async::new(VsockDatagram(socket))?)
+    }
+
+    /// Receives a single datagram message from the socket.
+    ///
+    /// Returns the number of bytes read and the address the message came from.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, VsockAddr)> {
+        let (len, addr) = self.read_with(|io| io.0.recv_from(buf)).await?;
+        Ok((len, VsockAddr::from_sockaddr(&addr)?))
+    }
+
+    /// Sends data to the specified address.
+    ///
+    /// Returns the number of bytes written.
+    pub async fn send_to(&self, buf: &[u8], addr: VsockAddr) -> io::Result<usize> {
+        let addr = addr.to_sockaddr();
+        self.write_with(|io| io.0.send_to(buf, &addr)).await
+    }
+
+    /// Receives a single datagram message from the connected peer.
+    ///
+    /// The [`connect`] method connects this socket to a remote address. This method will
+    /// fail if the socket is not connected.
+    ///
+    /// [`connect`]: struct.VsockDatagram.html#method.connect
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_with(|io| io.0.recv(buf)).await
+    }
+
+    /// Sends data to the connected peer.
+    ///
+    /// The [`connect`] method connects this socket to a remote address. This method will
+    /// fail if the socket is not connected.
+    ///
+    /// [`connect`]: struct.VsockDatagram.html#method.connect
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.write_with(|io| io.0.send(buf)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn tcp_stream_handoff() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = crate::LocalExecutor::spawn_executor("receiver", None, move || async move {
+            let channel = Async::new(receiver).unwrap();
+            let (mut stream, mut message) = channel.recv_tcp_stream().await.unwrap();
+            assert_eq!(&message, b"hel");
+            let mut rest = [0u8; 2];
+            stream.read_exact(&mut rest).await.unwrap();
+            message.extend_from_slice(&rest);
+            stream.write_all(&message).await.unwrap();
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            let listener = Async::new(listener).unwrap();
+            let channel = Async::new(sender).unwrap();
+            let mut client = Async::<TcpStream>::connect(addr).await.unwrap();
+            let (mut server, _) = listener.accept().await.unwrap();
+            client.write_all(b"shard-1;hello").await.unwrap();
+
+            // reading the shard key reads past it
+            let mut head = [0u8; 11];
+            server.read_exact(&mut head).await.unwrap();
+            assert_eq!(&head[..8], b"shard-1;");
+            channel.send_tcp_stream(server, &head[8..]).await.unwrap();
+
+            let mut buf = [0u8; 5];
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+        });
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn concurrent_handoffs_dont_interleave() {
+        test_executor!(async move {
+            use std::rc::Rc;
+
+            let (sender, receiver) = Async::<UnixStream>::pair().unwrap();
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+            let mut sends = Vec::new();
+            let mut clients = Vec::new();
+            let sender = Rc::new(sender);
+            for fill in 0..2u8 {
+                clients.push(Async::<TcpStream>::connect(addr).await.unwrap());
+                let (server, _) = listener.accept().await.unwrap();
+                let sender = sender.clone();
+                // more than the socket buffers hold, so the sends park halfway through
+                let buffered = vec![fill; 1 << 20];
+                sends.push(Local::local(async move {
+                    sender.send_tcp_stream(server, &buffered).await.unwrap();
+                }));
+            }
+            for _ in 0..2 {
+                let (_, buffered) = receiver.recv_tcp_stream().await.unwrap();
+                assert_eq!(buffered.len(), 1 << 20);
+                assert!(buffered.iter().all(|b| *b == buffered[0]));
+            }
+            futures::future::join_all(sends).await;
+        });
+    }
+
+    #[test]
+    fn tcp_stream_send_file() {
+        let mut path = std::env::temp_dir();
+        path.push("tcp_stream_send_file");
+        let contents: Vec<u8> = (0..8192).map(|x| (x % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        test_executor!(async move {
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+            let mut client = Async::<TcpStream>::connect(addr).await.unwrap();
+            let (server, _) = listener.accept().await.unwrap();
+
+            let file = std::fs::File::open(&path).unwrap();
+            let mut reported = 0;
+            let sent = server
+                .send_file_with_progress(&file, 100, 1_000_000, |x| reported = x)
+                .await
+                .unwrap();
+            assert_eq!(sent, 8092);
+            assert_eq!(reported, 8092);
+            drop(server);
+
+            let mut buf = Vec::new();
+            client.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(&buf[..], &contents[100..]);
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+
+    #[test]
+    fn tcp_incoming_filtered() {
+        test_executor!(async move {
+            use futures_lite::stream::StreamExt;
+
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+
+            let mut rejected = Async::<TcpStream>::connect(addr).await.unwrap();
+            let mut accepted = Async::<TcpStream>::connect(addr).await.unwrap();
+            let accepted_port = accepted.get_ref().local_addr().unwrap().port();
+
+            let mut incoming = listener.incoming_filtered(|peer| peer.port() == accepted_port);
+            let mut stream = incoming.next().await.unwrap().unwrap();
+            assert_eq!(stream.get_ref().peer_addr().unwrap().port(), accepted_port);
+
+            let mut buf = Vec::new();
+            rejected.read_to_end(&mut buf).await.unwrap();
+            assert!(buf.is_empty());
+
+            stream.write_all(b"hello").await.unwrap();
+            drop(stream);
+            accepted.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(&buf[..], b"hello");
+        });
+    }
+
+    #[test]
+    fn tcp_accept_pause_resume() {
+        test_executor!(async move {
+            use crate::{Local, Timer};
+            use std::cell::Cell;
+            use std::rc::Rc;
+            use std::time::Duration;
+
+            let listener = Rc::new(Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap());
+            let addr = listener.get_ref().local_addr().unwrap();
+            let accepted = Rc::new(Cell::new(0));
+
+            let l = listener.clone();
+            let a = accepted.clone();
+            let acceptor = Local::local(async move {
+                for _ in 0..2 {
+                    l.accept().await.unwrap();
+                    a.set(a.get() + 1);
+                }
+            });
+
+            // paused while the acceptor waits for a connection
+            Local::later().await;
+            listener.pause_accepting();
+            assert!(listener.is_accepting_paused());
+            let _first = Async::<TcpStream>::connect(addr).await.unwrap();
+            Timer::new(Duration::from_millis(50)).await;
+            assert_eq!(accepted.get(), 0);
+
+            listener.resume_accepting();
+            while accepted.get() == 0 {
+                Local::later().await;
+            }
+
+            // the connection waits in the backlog until intake resumes
+            listener.pause_accepting();
+            let _second = Async::<TcpStream>::connect(addr).await.unwrap();
+            Timer::new(Duration::from_millis(50)).await;
+            assert_eq!(accepted.get(), 1);
+            listener.resume_accepting();
+            acceptor.await;
+            assert_eq!(accepted.get(), 2);
+        });
+    }
+
+    #[test]
+    fn tcp_accept_keeps_one_waker_per_waiter() {
+        test_executor!(async move {
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            listener.pause_accepting();
+
+            {
+                let accept = listener.accept();
+                futures::pin_mut!(accept);
+                for _ in 0..10 {
+                    assert!(futures::poll!(accept.as_mut()).is_pending());
+                }
+                assert_eq!(listener.intake_waiters(), 1);
+            }
+
+            // waiters that give up leave no waker behind
+            assert_eq!(listener.intake_waiters(), 0);
+        });
+    }
+
+    #[test]
+    fn udp_ancillary_data() {
+        test_executor!(async move {
+            let receiver = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let sender = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = receiver.get_ref().local_addr().unwrap();
+
+            receiver.set_recv_timestamps(true).unwrap();
+            receiver.set_recv_pktinfo(true).unwrap();
+            receiver.set_recv_tos(true).unwrap();
+
+            let meta = SendMeta::new().tos(0x20).ecn(0b01);
+            let sent = sender.send_msg(b"ping", addr, &meta).await.unwrap();
+            assert_eq!(sent, 4);
+
+            let mut buf = [0u8; 16];
+            let (len, from, meta) = receiver.recv_msg(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"ping");
+            assert_eq!(from, sender.get_ref().local_addr().unwrap());
+            assert_eq!(meta.destination(), Some(addr.ip()));
+            assert_eq!(meta.tos(), Some(0x21));
+            assert_eq!(meta.ecn(), Some(0b01));
+            let elapsed = meta.timestamp().unwrap().elapsed().unwrap();
+            assert!(elapsed < std::time::Duration::from_secs(10));
+        });
+    }
+
+    #[test]
+    fn udp_send_timestamps() {
+        test_executor!(async move {
+            let receiver = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let sender = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = receiver.get_ref().local_addr().unwrap();
+
+            sender.set_send_timestamps(true).unwrap();
+            for _ in 0..2 {
+                sender
+                    .send_msg(b"ping", addr, &SendMeta::new())
+                    .await
+                    .unwrap();
+            }
+
+            for id in 0..2 {
+                let stamp = sender.recv_send_timestamp().await.unwrap();
+                assert_eq!(stamp.id(), id);
+                let elapsed = stamp.timestamp().unwrap().elapsed().unwrap();
+                assert!(elapsed < std::time::Duration::from_secs(10));
+            }
+        });
+    }
+
+    #[test]
+    fn udp_segmentation_offload() {
+        test_executor!(async move {
+            let receiver = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let sender = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = receiver.get_ref().local_addr().unwrap();
+
+            let data: Vec<u8> = (0..250).map(|x| x as u8).collect();
+            let meta = SendMeta::new().segment_size(100);
+            let sent = sender.send_msg(&data, addr, &meta).await.unwrap();
+            assert_eq!(sent, data.len());
+
+            // Without GRO on the receiving side we get the datagrams one by one
+            let mut buf = [0u8; 1024];
+            for chunk in data.chunks(100) {
+                let (len, _, meta) = receiver.recv_msg(&mut buf).await.unwrap();
+                assert_eq!(&buf[..len], chunk);
+                assert_eq!(meta.segment_size(), None);
+            }
+        });
+    }
+
+    #[test]
+    fn vsock_addresses_round_trip() {
+        let addr = VsockAddr::new(VsockAddr::CID_HOST, 1024);
+        assert_eq!(VsockAddr::from_sockaddr(&addr.to_sockaddr()).unwrap(), addr);
+        assert_eq!(addr.to_string(), "vsock:2:1024");
+
+        let inet: SocketAddr = ([127, 0, 0, 1], 80).into();
+        assert!(VsockAddr::from_sockaddr(&inet.into()).is_err());
+    }
+
+    #[test]
+    fn vsock_loopback_stream() {
+        test_executor!(async move {
+            // Loopback needs the vsock_loopback module, which many hosts don't load
+            let any = VsockAddr::new(VsockAddr::CID_LOCAL, VsockAddr::PORT_ANY);
+            let listener = match Async::<VsockListener>::bind(any) {
+                Ok(listener) => listener,
+                Err(_) => return,
+            };
+            let port = listener.get_ref().local_addr().unwrap().port();
+            let addr = VsockAddr::new(VsockAddr::CID_LOCAL, port);
+            let mut client = match Async::<VsockStream>::connect(addr).await {
+                Ok(client) => client,
+                Err(_) => return,
+            };
+            let (mut server, _) = listener.accept().await.unwrap();
+            assert_eq!(client.get_ref().peer_addr().unwrap(), addr);
+
+            client.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+        });
+    }
+
+    #[test]
+    fn send_all_and_recv_exact_loop_over_short_io() {
+        test_executor!(async move {
+            let (reader, writer) = Async::<UnixStream>::pair().unwrap();
+            // Larger than the socket buffers, so the writes come up short
+            let data: Vec<u8> = (0..4 << 20).map(|x| x as u8).collect();
+            let expected = data.clone();
+            let writes = Local::local(async move {
+                writer.send_all(&data).await.unwrap();
+                writer
+            });
+            let mut buf = vec![0u8; 4 << 20];
+            reader.recv_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, expected);
+
+            drop(writes.await);
+            let err = reader.recv_exact(&mut buf[..1]).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn dropped_pending_read_is_orphaned() {
+        test_executor!(async move {
+            let (reader, _writer) = Async::<UnixStream>::pair().unwrap();
+            let before = Local::executor_stats().total_orphaned_io();
+            let task = Local::local(async move {
+                let mut reader = reader;
+                let mut buf = [0u8; 1];
+                reader.read(&mut buf).await.unwrap();
+            });
+
+            // Let the read submit its poll before giving up on it
+            crate::Timer::new(std::time::Duration::from_millis(10)).await;
+            assert!(task.cancel().await.is_none());
+            assert_eq!(Local::executor_stats().total_orphaned_io(), before + 1);
+
+            // The poll is canceled, and its completion releases the source
+            crate::Timer::new(std::time::Duration::from_millis(10)).await;
+            assert_eq!(Local::executor_stats().orphaned_io(), 0);
+        });
+    }
+}
@@ -0,0 +1,147 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::Timer;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Spaces out sends so that they don't exceed a rate, for protocols like QUIC that pace
+/// their packets instead of sending a whole congestion window at once.
+///
+/// The pacer is a token bucket: up to `burst` bytes can go out back to back, and after
+/// that sends wait on a timer for the bucket to refill at `rate` bytes per second. Timers
+/// fire with millisecond resolution, so the burst should be at least a millisecond's
+/// worth of data at the rate, or the rate won't be reached.
+///
+/// The kernel can pace a socket too, with
+/// [`set_max_pacing_rate`](struct.Async.html#method.set_max_pacing_rate), which is more
+/// precise but can't tell the connections sharing a socket apart.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, Pacer};
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     // 10MB/s, with bursts of up to 10 datagrams
+///     let pacer = Pacer::new(10 << 20, 12_000);
+///     for _ in 0..20 {
+///         pacer.pace(1200).await;
+///         // send a 1200 byte datagram
+///     }
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Pacer {
+    rate: Cell<u64>,
+    burst: Cell<u64>,
+    tokens: Cell<f64>,
+    refilled: Cell<Instant>,
+}
+
+impl Pacer {
+    /// Creates a pacer that allows `rate` bytes per second, in bursts of up to `burst`
+    /// bytes. A rate of zero doesn't pace at all.
+    pub fn new(rate: u64, burst: u64) -> Pacer {
+        Pacer {
+            rate: Cell::new(rate),
+            burst: Cell::new(burst),
+            tokens: Cell::new(burst as f64),
+            refilled: Cell::new(Instant::now()),
+        }
+    }
+
+    /// The rate, in bytes per second
+    pub fn rate(&self) -> u64 {
+        self.rate.get()
+    }
+
+    /// The most bytes that can go out back to back
+    pub fn burst(&self) -> u64 {
+        self.burst.get()
+    }
+
+    /// Changes the rate and the burst, as a congestion controller would when its window
+    /// changes. The bytes already allowed are kept, up to the new burst.
+    pub fn set_rate(&self, rate: u64, burst: u64) {
+        self.refill(Instant::now());
+        self.rate.set(rate);
+        self.burst.set(burst);
+        self.tokens.set(self.tokens.get().min(burst as f64));
+    }
+
+    fn refill(&self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled.get());
+        self.refilled.set(now);
+        let tokens = self.tokens.get() + elapsed.as_secs_f64() * self.rate.get() as f64;
+        self.tokens.set(tokens.min(self.burst.get() as f64));
+    }
+
+    /// How long to wait before `bytes` can be sent, or `None` if they can be sent right
+    /// away. Doesn't take the bytes from the bucket, [`consume`] does.
+    ///
+    /// A send larger than the burst can go out once the bucket is full.
+    ///
+    /// [`consume`]: struct.Pacer.html#method.consume
+    pub fn delay(&self, bytes: usize) -> Option<Duration> {
+        let rate = self.rate.get();
+        if rate == 0 {
+            return None;
+        }
+        self.refill(Instant::now());
+        let needed = (bytes as f64).min(self.burst.get() as f64);
+        let missing = needed - self.tokens.get();
+        if missing <= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(missing / rate as f64))
+        }
+    }
+
+    /// Takes `bytes` from the bucket, for data that was sent.
+    pub fn consume(&self, bytes: usize) {
+        if self.rate.get() == 0 {
+            return;
+        }
+        self.refill(Instant::now());
+        self.tokens.set(self.tokens.get() - bytes as f64);
+    }
+
+    /// Waits until `bytes` can be sent, and takes them from the bucket.
+    pub async fn pace(&self, bytes: usize) {
+        while let Some(delay) = self.delay(bytes) {
+            Timer::new(delay).await;
+        }
+        self.consume(bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pacer_holds_sends_to_its_rate() {
+        test_executor!(async move {
+            // 100kB/s in bursts of 10kB
+            let pacer = Pacer::new(100_000, 10_000);
+            assert!(pacer.delay(10_000).is_none());
+            pacer.consume(10_000);
+            let delay = pacer.delay(5_000).unwrap();
+            assert!(delay > Duration::from_millis(40), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(50), "{:?}", delay);
+
+            // another 10kB need 100ms to come in
+            let start = Instant::now();
+            pacer.pace(10_000).await;
+            assert!(start.elapsed() >= Duration::from_millis(90));
+
+            pacer.set_rate(0, 0);
+            assert!(pacer.delay(1 << 30).is_none());
+        });
+    }
+}
@@ -22,8 +22,8 @@
 //! no thread context switch is necessary when going between task execution and I/O.
 //!
 
-use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt;
 use std::io;
@@ -36,16 +36,27 @@ use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::task::{Poll, Waker};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use futures_lite::*;
 
+use crate::dma_pool::DmaLease;
+use crate::file_id::{FileId, FileRegistry};
+use crate::hot_path;
+use crate::io_engine::IoEngine;
+use crate::rng::Rng;
 use crate::sys;
 use crate::sys::{DmaBuffer, PollableStatus, Source, SourceType};
-use crate::IoRequirements;
+use crate::timer::{ClockJump, TimerStats};
+use crate::timer_wheel::TimerWheel;
+use crate::{IoRequirements, Latency, RingPolicy, TimerBackend};
 
 thread_local!(static LOCAL_REACTOR: Reactor = Reactor::new());
 
+// Whether LOCAL_REACTOR was created on this thread, which can't be asked of the thread
+// local without creating it
+thread_local!(static REACTOR_CREATED: Cell<bool> = Cell::new(false));
+
 /// Waits for a notification.
 pub(crate) struct Parker {
     inner: Rc<Inner>,
@@ -101,22 +112,89 @@ impl Inner {
     fn park(&self, timeout: Option<Duration>) -> bool {
         // If the timeout is zero, then there is no need to actually block.
         // Process available I/O events.
+        let _reactor = hot_path::enter("reactor");
         let reactor_lock = Reactor::get().lock();
         let _ = reactor_lock.react(timeout);
         return false;
     }
 }
 
+/// How many timers registered by task queues that are not latency sensitive we
+/// expire per reactor loop, by default.
+pub(crate) const DEFAULT_MAX_BULK_TIMER_EXPIRATIONS: usize = 256;
+
+/// How far apart the clocks have to drift in between two readings to be taken for a
+/// suspend or a step of the wall clock, rather than for the time it takes to read them.
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// How often the clocks are read to look for jumps, at most. Reading them on every loop
+/// would cost two more clock reads per loop for something that rarely happens.
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Readings of the clocks, taken when timers are processed, at most every
+/// `CLOCK_CHECK_INTERVAL`.
+#[derive(Debug, Clone, Copy)]
+struct ClockReadings {
+    monotonic: Instant,
+    boottime: Duration,
+    realtime: SystemTime,
+}
+
+impl ClockReadings {
+    fn now() -> ClockReadings {
+        ClockReadings {
+            monotonic: Instant::now(),
+            boottime: sys::boottime(),
+            realtime: SystemTime::now(),
+        }
+    }
+
+    /// The discontinuity of the clocks in between `self` and the later readings `now`,
+    /// if any. The boot time clock runs ahead of the monotonic clock while the system is
+    /// suspended, and the wall clock runs apart from the boot time clock when it is set.
+    fn jump_to(&self, now: &ClockReadings) -> Option<ClockJump> {
+        let monotonic = now.monotonic.saturating_duration_since(self.monotonic);
+        let boottime = now.boottime.checked_sub(self.boottime).unwrap_or_default();
+        if boottime > monotonic + CLOCK_JUMP_THRESHOLD {
+            return Some(ClockJump::Suspended(boottime - monotonic));
+        }
+        match now.realtime.duration_since(self.realtime) {
+            Ok(realtime) if realtime > boottime + CLOCK_JUMP_THRESHOLD => {
+                Some(ClockJump::WallClockForward(realtime - boottime))
+            }
+            Ok(realtime) if realtime + CLOCK_JUMP_THRESHOLD < boottime => {
+                Some(ClockJump::WallClockBackward(boottime - realtime))
+            }
+            Ok(_) => None,
+            Err(err) => {
+                let back = err.duration() + boottime;
+                Some(ClockJump::WallClockBackward(back)).filter(|_| back > CLOCK_JUMP_THRESHOLD)
+            }
+        }
+    }
+}
+
 struct Timers {
     timer_id: u64,
-    timers_by_id: HashMap<u64, Instant>,
+    /// Whether each registered timer is latency sensitive, and its key in the wheel
+    /// that holds it.
+    timers_by_id: HashMap<u64, (bool, usize)>,
 
-    /// An ordered map of registered timers.
-    ///
-    /// Timers are in the order in which they fire. The `usize` in this type is a timer ID used to
-    /// distinguish timers that fire at the same time. The `Waker` represents the task awaiting the
-    /// timer.
-    timers: BTreeMap<(Instant, u64), Waker>,
+    /// The registered timers, in a timing wheel so that arming and cancelling them
+    /// takes the same time no matter how many there are.
+    timers: TimerWheel,
+
+    /// Same as above, but for timers registered by latency sensitive task queues.
+    /// Those are always expired first, and in full.
+    latency_timers: TimerWheel,
+
+    /// Maximum number of timers in `timers` that are expired in a single reactor loop
+    max_bulk_expirations: usize,
+
+    /// How many timers are armed in the rings instead, with `TimerBackend::Ring`.
+    ring_timers: usize,
+
+    stats: TimerStats,
 }
 
 impl Timers {
@@ -124,53 +202,128 @@ impl Timers {
         Timers {
             timer_id: 0,
             timers_by_id: HashMap::new(),
-            timers: BTreeMap::new(),
+            timers: TimerWheel::new(),
+            latency_timers: TimerWheel::new(),
+            max_bulk_expirations: DEFAULT_MAX_BULK_TIMER_EXPIRATIONS,
+            ring_timers: 0,
+            stats: TimerStats::default(),
         }
     }
 
+    // Either wheel may end up holding all the timers, so both make room for them
+    fn preallocate(&mut self, expected: usize) {
+        let additional = expected.saturating_sub(self.timers_by_id.len());
+        self.timers_by_id.reserve(additional);
+        self.timers.reserve(additional);
+        self.latency_timers.reserve(additional);
+        stat! { self.stats.capacity = self.capacity(); }
+    }
+
+    // How many timers can be armed without allocating, whichever wheel they go to
+    #[cfg_attr(not(feature = "stats"), allow(dead_code))]
+    fn capacity(&self) -> usize {
+        std::cmp::min(
+            self.timers_by_id.capacity(),
+            std::cmp::min(self.timers.capacity(), self.latency_timers.capacity()),
+        )
+    }
+
+    fn stats(&self) -> TimerStats {
+        let mut stats = self.stats.clone();
+        stats.armed = self.timers_by_id.len() + self.ring_timers;
+        let (total, max) = self.timers.lateness();
+        let (latency_total, latency_max) = self.latency_timers.lateness();
+        stats.total_fire_latency = total + latency_total;
+        stats.max_fire_latency = std::cmp::max(max, latency_max);
+        stats.max_latency_sensitive_fire_latency = latency_max;
+        stats
+    }
+
     fn new_id(&mut self) -> u64 {
         self.timer_id += 1;
         self.timer_id
     }
 
+    fn wheel(&mut self, latency_sensitive: bool) -> &mut TimerWheel {
+        if latency_sensitive {
+            &mut self.latency_timers
+        } else {
+            &mut self.timers
+        }
+    }
+
     fn remove(&mut self, id: u64) {
-        if let Some(when) = self.timers_by_id.remove(&id) {
-            self.timers.remove(&(when, id));
+        if let Some((latency_sensitive, key)) = self.timers_by_id.remove(&id) {
+            self.wheel(latency_sensitive).remove(key, id);
         }
     }
 
-    fn insert(&mut self, id: u64, when: Instant, waker: Waker) {
-        if let Some(when) = self.timers_by_id.get_mut(&id) {
-            self.timers.remove(&(*when, id));
+    fn insert(
+        &mut self,
+        id: u64,
+        when: Instant,
+        slack: Duration,
+        waker: Waker,
+        latency_sensitive: bool,
+    ) {
+        self.remove(id);
+        stat! {
+            if self.timers_by_id.len() == self.timers_by_id.capacity()
+                || self.wheel(latency_sensitive).is_full()
+            {
+                self.stats.allocations += 1;
+            }
+        }
+        let wheel = self.wheel(latency_sensitive);
+        let fire_at = wheel.coalesce(when, when + slack);
+        let key = wheel.insert(id, fire_at, waker);
+        self.timers_by_id.insert(id, (latency_sensitive, key));
+        stat! {
+            self.stats.capacity = self.capacity();
+            self.stats.high_water_mark =
+                std::cmp::max(self.stats.high_water_mark, self.timers_by_id.len());
         }
-        self.timers_by_id.insert(id, when);
-        self.timers.insert((when, id), waker);
     }
 
-    fn process_timers(&mut self, wakers: &mut Vec<Waker>) -> Option<Duration> {
-        let now = Instant::now();
+    fn process_timers(&mut self, now: Instant, wakers: &mut Vec<Waker>) -> Option<Duration> {
+        // Latency sensitive timers go first, and all of them that are ready fire.
+        let mut fired = self.latency_timers.expire(now, usize::MAX, wakers);
+
+        // Then the other timers, but only up to a limit so a burst of them can't delay
+        // the latency sensitive ones. Whatever is left over fires in the next loop.
+        let bulk = self.timers.expire(now, self.max_bulk_expirations, wakers);
+        fired += bulk;
+        stat! {
+            if bulk == self.max_bulk_expirations
+                && self
+                    .timers
+                    .next_expiration()
+                    .map_or(false, |next| next <= now)
+            {
+                self.stats.deferred_loops += 1;
+            }
 
-        // Split timers into ready and pending timers.
-        let pending = self.timers.split_off(&(now, 0));
-        let ready = mem::replace(&mut self.timers, pending);
+            self.stats.loops += 1;
+            self.stats.fired += fired as u64;
+            self.stats.last_loop_fired = fired;
+            self.stats.max_fired_per_loop = std::cmp::max(self.stats.max_fired_per_loop, fired);
+        }
 
         // Calculate the duration until the next event.
-        let dur = if ready.is_empty() {
-            // Duration until the next timer.
-            self.timers
-                .keys()
-                .next()
-                .map(|(when, _)| when.saturating_duration_since(now))
-        } else {
+        if fired > 0 {
             // Timers are about to fire right now.
-            Some(Duration::from_secs(0))
-        };
-        // Add wakers to the list.
-        for (_, waker) in ready {
-            wakers.push(waker);
+            return Some(Duration::from_secs(0));
         }
 
-        dur
+        // Duration until the next timer must fire.
+        let next = match (
+            self.latency_timers.next_expiration(),
+            self.timers.next_expiration(),
+        ) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+        next.map(|when| when.saturating_duration_since(now))
     }
 }
 
@@ -186,9 +339,45 @@ pub(crate) struct Reactor {
 
     timers: RefCell<Timers>,
 
+    /// The time when timers were last processed, for coarse timers.
+    coarse_now: Cell<Instant>,
+
+    /// The engine that carries out file I/O instead of the rings, if any.
+    io_engine: RefCell<Option<Rc<dyn IoEngine>>>,
+
+    /// Where everything running on this thread draws random numbers from.
+    rng: Rng,
+
+    /// Where timers are armed.
+    timer_backend: Cell<TimerBackend>,
+
+    /// How close the next timer has to be for the reactor to spin until it fires
+    /// instead of sleeping, if it ever does.
+    busy_poll_threshold: Cell<Option<Duration>>,
+
+    /// Current registration of every file and socket.
+    files: RefCell<FileRegistry>,
+
     /// I/O Requirements of the task currently executing.
     current_io_requirements: RefCell<IoRequirements>,
 
+    /// The clocks as of the last time they were read, to tell when the system was
+    /// suspended or the wall clock was set in between.
+    clock_readings: Cell<ClockReadings>,
+
+    /// When the clocks are read again.
+    next_clock_check: Cell<Instant>,
+
+    /// How long the system was suspended while this reactor was running, which timers
+    /// count as if the monotonic clock hadn't stopped.
+    time_suspended: Cell<Duration>,
+
+    /// How many times the clocks jumped.
+    clock_jumps: Cell<u64>,
+
+    /// Called every time the clocks jump.
+    clock_jump_handlers: RefCell<Vec<Rc<dyn Fn(ClockJump)>>>,
+
     /// Whether there are events in the latency ring.
     ///
     /// There will be events if the head and tail of the CQ ring are different.
@@ -206,11 +395,23 @@ pub(crate) struct Reactor {
 impl Reactor {
     fn new() -> Reactor {
         let sys = sys::Reactor::new().expect("cannot initialize I/O event notification");
+        REACTOR_CREATED.with(|created| created.set(true));
         let (preempt_ptr_head, preempt_ptr_tail) = sys.preempt_pointers();
         Reactor {
             sys,
             timers: RefCell::new(Timers::new()),
+            coarse_now: Cell::new(Instant::now()),
+            io_engine: RefCell::new(None),
+            rng: Rng::default(),
+            timer_backend: Cell::new(TimerBackend::Wheel),
+            busy_poll_threshold: Cell::new(None),
+            files: RefCell::new(FileRegistry::default()),
             current_io_requirements: RefCell::new(IoRequirements::default()),
+            clock_readings: Cell::new(ClockReadings::now()),
+            next_clock_check: Cell::new(Instant::now() + CLOCK_CHECK_INTERVAL),
+            time_suspended: Cell::new(Duration::from_secs(0)),
+            clock_jumps: Cell::new(0),
+            clock_jump_handlers: RefCell::new(Vec::new()),
             preempt_ptr_head,
             preempt_ptr_tail: preempt_ptr_tail as _,
         }
@@ -225,6 +426,23 @@ impl Reactor {
         }
     }
 
+    /// Returns the reactor of the current thread, unless there is none. Unlike
+    /// [`get`], it never creates one, which would unshare the file descriptor table of
+    /// the thread.
+    ///
+    /// [`get`]: struct.Reactor.html#method.get
+    pub(crate) fn try_get() -> Option<&'static Reactor> {
+        if !REACTOR_CREATED
+            .try_with(|created| created.get())
+            .unwrap_or(false)
+        {
+            return None;
+        }
+        LOCAL_REACTOR
+            .try_with(|r| unsafe { &*(r as *const Reactor) })
+            .ok()
+    }
+
     #[inline(always)]
     // FIXME: This is a bit less efficient than it needs, because the scoped thread local key
     // does lazy initialization. Every time we call into this, we are paying to test if this
@@ -271,15 +489,40 @@ impl Reactor {
         self.sys.alloc_dma_buffer(size)
     }
 
+    /// Writes the range of `buf` that starts at `offset`, holding the memory of the
+    /// buffer until the write completes.
     pub(crate) fn write_dma(
         &self,
         raw: RawFd,
         buf: &DmaBuffer,
+        offset: usize,
+        len: usize,
+        pos: u64,
+        pollable: PollableStatus,
+        dsync: bool,
+    ) -> Pin<Box<Source>> {
+        assert!(offset + len <= buf.len());
+        let source = self.new_source(raw, SourceType::DmaWrite(pollable));
+        // The memory outlives the write even if the future issuing it is dropped
+        source.keep_alive(buf.hold());
+        let ptr = unsafe { buf.as_ptr().add(offset) };
+        self.sys.write_dma(&source.as_ref(), ptr, len, pos, dsync);
+        source
+    }
+
+    pub(crate) fn write_dma_lease(
+        &self,
+        raw: RawFd,
+        lease: &DmaLease,
         pos: u64,
         pollable: PollableStatus,
     ) -> Pin<Box<Source>> {
         let source = self.new_source(raw, SourceType::DmaWrite(pollable));
-        self.sys.write_dma(&source.as_ref(), buf, pos);
+        // The lease outlives the write even if the future issuing it is dropped
+        source.keep_alive(Box::new(lease.clone()));
+        let bytes = lease.as_bytes();
+        self.sys
+            .write_dma(&source.as_ref(), bytes.as_ptr(), bytes.len(), pos, false);
         source
     }
 
@@ -295,6 +538,41 @@ impl Reactor {
         source
     }
 
+    /// Reads `len` bytes at `pos` into `buf`, starting at `offset`. The buffer is kept
+    /// alive until the read completes.
+    pub(crate) fn read_dma_into(
+        &self,
+        raw: RawFd,
+        buf: &Rc<DmaBuffer>,
+        offset: usize,
+        len: usize,
+        pos: u64,
+        pollable: PollableStatus,
+    ) -> Pin<Box<Source>> {
+        assert!(offset + len <= buf.len());
+        let source = self.new_source(raw, SourceType::DmaRead(pollable, None));
+        source.keep_alive(Box::new(buf.clone()));
+        let ptr = unsafe { buf.as_mut_ptr().add(offset) };
+        self.sys.read_dma_into(&source.as_ref(), ptr, len, pos);
+        source
+    }
+
+    /// Reads into the range of a leased buffer, holding a lease on it until the read
+    /// completes.
+    pub(crate) fn read_dma_lease(
+        &self,
+        raw: RawFd,
+        lease: &DmaLease,
+        pos: u64,
+        pollable: PollableStatus,
+    ) -> Pin<Box<Source>> {
+        let source = self.new_source(raw, SourceType::DmaRead(pollable, None));
+        source.keep_alive(Box::new(lease.clone()));
+        self.sys
+            .read_dma_into(&source.as_ref(), lease.as_mut_ptr(), lease.len(), pos);
+        source
+    }
+
     pub(crate) fn fdatasync(&self, raw: RawFd) -> Pin<Box<Source>> {
         let source = self.new_source(raw, SourceType::FdataSync);
         self.sys.fdatasync(&source.as_ref());
@@ -349,18 +627,70 @@ impl Reactor {
         source
     }
 
+    pub(crate) fn unlink_at(&self, dir: RawFd, path: &Path) -> Pin<Box<Source>> {
+        let path = CString::new(path.as_os_str().as_bytes()).expect("path contained null!");
+
+        let source = self.new_source(dir, SourceType::Unlink(path));
+        self.sys.unlink_at(&source.as_ref());
+        source
+    }
+
+    /// Runs `job`, a system call on `raw` that may block for long, in a helper thread,
+    /// and completes the source returned with its result. It is called `name` in
+    /// reports.
+    pub(crate) fn run_blocking(
+        &self,
+        raw: RawFd,
+        name: &'static str,
+        job: impl FnOnce() -> io::Result<usize> + Send + 'static,
+    ) -> Pin<Box<Source>> {
+        let source = self.new_source(raw, SourceType::Blocking(name));
+        self.sys.run_blocking(&source.as_ref(), Box::new(job));
+        source
+    }
+
     pub(crate) fn insert_pollable_io(&self, raw: RawFd) -> io::Result<Pin<Box<Source>>> {
         let source = self.new_source(raw, SourceType::PollableFd);
         self.sys.insert(raw)?;
         Ok(source)
     }
 
-    /*
-    /// Deregisters an I/O source from the reactor.
-    pub(crate) fn cancel_io(&self, source: &Source) {
-        self.sys.cancel_io(source)
+    /// Cancels the polls in flight for a source that is going away.
+    pub(crate) fn cancel_poll(&self, source: &sys::InnerSource) {
+        self.sys.cancel_poll(source)
+    }
+
+    /// Registers a file or socket, giving it an id that tells it apart from other
+    /// resources that get the same file descriptor once it is closed. Files are
+    /// registered with the path they were opened with, for reporting.
+    pub(crate) fn register_file(&self, fd: RawFd, path: Option<&Path>) -> FileId {
+        self.files.borrow_mut().register(fd, path)
+    }
+
+    /// Returns the path of the file currently registered with `fd`, if it has one.
+    pub(crate) fn file_path(&self, fd: RawFd) -> Option<Rc<Path>> {
+        self.files.borrow().path(fd)
+    }
+
+    /// Marks a file or socket as closed, so operations issued with its id fail.
+    pub(crate) fn unregister_file(&self, id: FileId) {
+        self.files.borrow_mut().unregister(id)
+    }
+
+    /// Like [`unregister_file`], for files and sockets dropped anywhere: on threads
+    /// without a reactor there is nothing to unregister them from.
+    ///
+    /// [`unregister_file`]: struct.Reactor.html#method.unregister_file
+    pub(crate) fn unregister_dropped_file(id: FileId) {
+        if let Some(reactor) = Reactor::try_get() {
+            reactor.unregister_file(id);
+        }
+    }
+
+    /// Returns the file descriptor of a file or socket, if it was not closed yet.
+    pub(crate) fn check_file(&self, id: FileId) -> io::Result<RawFd> {
+        Ok(self.files.borrow().check(id)?)
     }
-    */
 
     /// Registers a timer in the reactor.
     ///
@@ -370,12 +700,181 @@ impl Reactor {
         timers.new_id()
     }
 
-    /// Registers a timer in the reactor.
+    /// The time when timers were last processed, which is at most a loop old.
+    pub(crate) fn coarse_now(&self) -> Instant {
+        self.coarse_now.get()
+    }
+
+    /// Registers a timer in the reactor, to fire at `when` or up to `slack` later, along
+    /// with other timers.
+    pub(crate) fn insert_timer(&self, id: u64, when: Instant, slack: Duration, waker: &Waker) {
+        let latency_sensitive = match self.current_io_requirements.borrow().latency_req {
+            Latency::Matters(_) => true,
+            Latency::NotImportant => false,
+        };
+        let mut timers = self.timers.borrow_mut();
+        timers.insert(id, when, slack, waker.clone(), latency_sensitive);
+    }
+
+    /// Sets the engine that carries out the storage I/O of this reactor, instead of the
+    /// rings, or gives it back to the rings with `None`.
+    pub(crate) fn set_io_engine(&self, engine: Option<Rc<dyn IoEngine>>) {
+        *self.io_engine.borrow_mut() = engine;
+    }
+
+    pub(crate) fn rng(&self) -> &Rng {
+        &self.rng
+    }
+
+    pub(crate) fn io_engine(&self) -> Option<Rc<dyn IoEngine>> {
+        self.io_engine.borrow().clone()
+    }
+
+    /// Sets where timers are armed from now on. Kernels that can't take timeouts
+    /// through io_uring keep using the timer wheel.
+    pub(crate) fn set_timer_backend(&self, backend: TimerBackend) {
+        match backend {
+            TimerBackend::Ring if !self.sys.supports_timers() => {}
+            backend => self.timer_backend.set(backend),
+        }
+    }
+
+    pub(crate) fn timer_backend(&self) -> TimerBackend {
+        self.timer_backend.get()
+    }
+
+    /// Spins instead of sleeping when the next timer is due in `threshold` or less.
+    pub(crate) fn set_busy_poll_threshold(&self, threshold: Option<Duration>) {
+        self.busy_poll_threshold.set(threshold);
+    }
+
+    pub(crate) fn busy_poll_threshold(&self) -> Option<Duration> {
+        self.busy_poll_threshold.get()
+    }
+
+    /// Arms a timer in the rings, that completes the source returned after `dur`. The
+    /// timer counts as armed until [`release_ring_timer`] is called for it.
+    ///
+    /// [`release_ring_timer`]: struct.Reactor.html#method.release_ring_timer
+    pub(crate) fn arm_ring_timer(&self, dur: Duration) -> Pin<Box<Source>> {
+        let source = self.new_source(-1, SourceType::RingTimer);
+        self.sys.arm_timer(&source.as_ref(), dur);
+        let mut timers = self.timers.borrow_mut();
+        timers.ring_timers += 1;
+        stat! {
+            let armed = timers.timers_by_id.len() + timers.ring_timers;
+            timers.stats.high_water_mark = std::cmp::max(timers.stats.high_water_mark, armed);
+        }
+        source
+    }
+
+    /// Stops counting a timer armed with [`arm_ring_timer`] as armed.
     ///
-    /// Returns the inserted timer's ID.
-    pub(crate) fn insert_timer(&self, id: u64, when: Instant, waker: &Waker) {
+    /// [`arm_ring_timer`]: struct.Reactor.html#method.arm_ring_timer
+    pub(crate) fn release_ring_timer(&self) {
+        self.timers.borrow_mut().ring_timers -= 1;
+    }
+
+    pub(crate) fn cancel_ring_timer(&self, source: &sys::InnerSource) {
+        self.sys.cancel_timer(source)
+    }
+
+    /// Sets how storage I/O is spread over the rings of this reactor.
+    pub(crate) fn set_ring_policy(&self, policy: RingPolicy) {
+        self.sys.set_ring_policy(policy);
+    }
+
+    pub(crate) fn ring_policy(&self) -> RingPolicy {
+        self.sys.ring_policy()
+    }
+
+    /// Sets how many timers registered by task queues that are not latency sensitive
+    /// can be expired in a single reactor loop.
+    pub(crate) fn set_max_bulk_timer_expirations(&self, max: usize) {
+        let mut timers = self.timers.borrow_mut();
+        timers.max_bulk_expirations = std::cmp::max(max, 1);
+    }
+
+    /// Returns how many timers registered by task queues that are not latency
+    /// sensitive can be expired in a single reactor loop.
+    pub(crate) fn max_bulk_timer_expirations(&self) -> usize {
+        self.timers.borrow().max_bulk_expirations
+    }
+
+    /// Preallocates space so `expected` timers can be armed at the same time, latency
+    /// sensitive or not, without the reactor allocating memory to track them. Timers
+    /// armed in the rings are not tracked by the reactor, and are not covered.
+    pub(crate) fn preallocate_timers(&self, expected: usize) {
         let mut timers = self.timers.borrow_mut();
-        timers.insert(id, when, waker.clone());
+        timers.preallocate(expected);
+    }
+
+    /// Returns statistics about the timers registered in this reactor.
+    pub(crate) fn timer_stats(&self) -> TimerStats {
+        let mut stats = self.timers.borrow().stats();
+        stats.clock_jumps = self.clock_jumps.get();
+        stats.time_suspended = self.time_suspended.get();
+        stats
+    }
+
+    /// How long the system was suspended while this reactor was running.
+    pub(crate) fn time_suspended(&self) -> Duration {
+        self.time_suspended.get()
+    }
+
+    /// Registers a function to call every time the clocks jump.
+    pub(crate) fn on_clock_jump(&self, handler: impl Fn(ClockJump) + 'static) {
+        self.clock_jump_handlers.borrow_mut().push(Rc::new(handler));
+    }
+
+    /// Makes the reactor believe the system was suspended for `duration` right before
+    /// its last loop, so the next one sees it.
+    #[cfg(test)]
+    pub(crate) fn pretend_suspended(&self, duration: Duration) {
+        self.pretend_suspended_unnoticed(duration);
+        self.next_clock_check
+            .set(self.clock_readings.get().monotonic);
+    }
+
+    /// Like `pretend_suspended`, but the reactor only looks at the clocks when they are
+    /// due to be checked, or when the kernel tells it they changed.
+    #[cfg(test)]
+    pub(crate) fn pretend_suspended_unnoticed(&self, duration: Duration) {
+        let mut readings = self.clock_readings.get();
+        readings.boottime = readings.boottime.checked_sub(duration).unwrap();
+        readings.realtime -= duration;
+        self.clock_readings.set(readings);
+        self.next_clock_check
+            .set(readings.monotonic + Duration::from_secs(3600));
+    }
+
+    /// Looks for a jump of the clocks since the last time they were read. After a
+    /// suspend, every timer is brought forward by the time spent suspended, which counts
+    /// towards them, so the ones that expired while the system was suspended fire when
+    /// timers are processed next, and only those.
+    ///
+    /// The kernel wakes a sleeping reactor up when the system resumes or the wall clock
+    /// is set, so jumps are seen right away rather than once the next timer is due.
+    fn check_clocks(&self, readings: ClockReadings) {
+        let jump = match self.clock_readings.replace(readings).jump_to(&readings) {
+            Some(jump) => jump,
+            None => return,
+        };
+        stat! { self.clock_jumps.set(self.clock_jumps.get() + 1); }
+        if let ClockJump::Suspended(duration) = jump {
+            self.time_suspended
+                .set(self.time_suspended.get() + duration);
+            let mut timers = self.timers.borrow_mut();
+            let now = readings.monotonic;
+            timers.timers.bring_forward(duration, now);
+            timers.latency_timers.bring_forward(duration, now);
+        }
+
+        // Handlers may register more handlers
+        let handlers = self.clock_jump_handlers.borrow().clone();
+        for handler in handlers {
+            handler(jump);
+        }
     }
 
     /// Deregisters a timer from the reactor.
@@ -384,6 +883,14 @@ impl Reactor {
         timers.remove(id);
     }
 
+    /// Deregisters many timers from the reactor at once.
+    pub(crate) fn remove_timers(&self, ids: impl IntoIterator<Item = u64>) {
+        let mut timers = self.timers.borrow_mut();
+        for id in ids {
+            timers.remove(id);
+        }
+    }
+
     /// Locks the reactor, potentially blocking if the lock is held by another thread.
     fn lock(&self) -> ReactorLock<'_> {
         let reactor = self;
@@ -394,8 +901,16 @@ impl Reactor {
     ///
     /// Returns the duration until the next timer before this method was called.
     fn process_timers(&self, wakers: &mut Vec<Waker>) -> Option<Duration> {
+        let mut now = Instant::now();
+        if self.sys.clocks_changed() || now >= self.next_clock_check.get() {
+            let readings = ClockReadings::now();
+            now = readings.monotonic;
+            self.next_clock_check.set(now + CLOCK_CHECK_INTERVAL);
+            self.check_clocks(readings);
+        }
+        self.coarse_now.set(now);
         let mut timers = self.timers.borrow_mut();
-        timers.process_timers(wakers)
+        timers.process_timers(now, wakers)
     }
 }
 
@@ -416,11 +931,66 @@ impl ReactorLock<'_> {
         // Process ready timers.
         let next_timer = self.reactor.process_timers(&mut wakers);
 
+        // Sleeping and waking up takes longer than the next timer has to wait: spin
+        // until it is due, or until something else happens.
+        let res = match (timeout, next_timer, self.reactor.busy_poll_threshold()) {
+            (None, Some(next), Some(threshold)) if next <= threshold && wakers.is_empty() => {
+                self.spin(&mut wakers, next)
+            }
+            _ => self.sleep(&mut wakers, timeout, next_timer),
+        };
+
+        // Wake up ready tasks.
+        for waker in wakers {
+            // Don't let a panicking waker blow everything up.
+            let _ = panic::catch_unwind(|| waker.wake());
+        }
+
+        // Report the operations that completed too late
+        crate::slow_io::flush();
+
+        res
+    }
+
+    /// Polls for events without sleeping until there are some or `next_timer` elapsed.
+    ///
+    /// If the timer is gone by then, or was pushed back, spinning goes on only for as
+    /// long as the next timer is within the busy poll threshold, and the reactor sleeps
+    /// otherwise.
+    fn spin(&self, wakers: &mut Vec<Waker>, next_timer: Duration) -> io::Result<()> {
+        let mut deadline = Instant::now() + next_timer;
+        loop {
+            self.reactor.sys.poll(wakers)?;
+            if Instant::now() >= deadline {
+                let next_timer = self.reactor.process_timers(wakers);
+                if !wakers.is_empty() {
+                    return Ok(());
+                }
+                match (next_timer, self.reactor.busy_poll_threshold()) {
+                    (Some(next), Some(threshold)) if next <= threshold => {
+                        deadline = Instant::now() + next;
+                    }
+                    _ => return self.sleep(wakers, None, next_timer),
+                }
+            }
+            if !wakers.is_empty() {
+                return Ok(());
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    fn sleep(
+        &self,
+        wakers: &mut Vec<Waker>,
+        timeout: Option<Duration>,
+        next_timer: Option<Duration>,
+    ) -> io::Result<()> {
         // Block on I/O events.
-        let res = match self.reactor.sys.wait(&mut wakers, timeout, next_timer) {
+        match self.reactor.sys.wait(wakers, timeout, next_timer) {
             // We slept, so don't wait for the next loop to process timers
             Ok(true) => {
-                self.reactor.process_timers(&mut wakers);
+                self.reactor.process_timers(wakers);
                 Ok(())
             }
 
@@ -432,15 +1002,7 @@ impl ReactorLock<'_> {
 
             // An actual error occureed.
             Err(err) => Err(err),
-        };
-
-        // Wake up ready tasks.
-        for waker in wakers {
-            // Don't let a panicking waker blow everything up.
-            let _ = panic::catch_unwind(|| waker.wake());
         }
-
-        res
     }
 }
 
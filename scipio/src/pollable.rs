@@ -3,15 +3,19 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::future::Future;
 use std::io::{self, IoSlice, IoSliceMut, Read, Write};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{Context, Poll, Waker};
 
+use futures::lock::Mutex as AsyncMutex;
 use futures_lite::io::{AsyncRead, AsyncWrite};
 use futures_lite::{future, pin};
 
+use crate::file_id::FileId;
 use crate::parking::Reactor;
 use crate::sys::{self, Source};
 
@@ -67,8 +71,39 @@ pub struct Async<T> {
     /// A source registered in the reactor.
     source: Pin<Box<Source>>,
 
+    /// The id of the I/O handle in the reactor.
+    id: FileId,
+
     /// The inner I/O handle.
     io: Option<Box<T>>,
+
+    /// Whether listeners stopped accepting connections.
+    intake: Intake,
+
+    /// Held while a TCP stream is handed off over a UDS stream, so the frames of
+    /// concurrent handoffs don't interleave.
+    pub(crate) handoff: AsyncMutex<()>,
+}
+
+// Whether accepting connections is paused, and the tasks waiting for it to resume
+#[derive(Debug, Default)]
+struct Intake {
+    paused: Cell<bool>,
+    // The waker of each task waiting, by waiter
+    waiters: RefCell<HashMap<u64, Waker>>,
+    next_waiter: Cell<u64>,
+}
+
+// The place of a task waiting for intake to resume, given up when it stops waiting
+struct IntakeWaiter<'a> {
+    intake: &'a Intake,
+    id: u64,
+}
+
+impl Drop for IntakeWaiter<'_> {
+    fn drop(&mut self) {
+        self.intake.waiters.borrow_mut().remove(&self.id);
+    }
 }
 
 impl<T: AsRawFd> Async<T> {
@@ -98,7 +133,10 @@ impl<T: AsRawFd> Async<T> {
     pub fn new(io: T) -> io::Result<Async<T>> {
         Ok(Async {
             source: Reactor::get().insert_pollable_io(io.as_raw_fd())?,
+            id: Reactor::get().register_file(io.as_raw_fd(), None),
             io: Some(Box::new(io)),
+            intake: Intake::default(),
+            handoff: AsyncMutex::new(()),
         })
     }
 }
@@ -144,6 +182,13 @@ impl<T> Async<T> {
         self.io.as_mut().unwrap()
     }
 
+    /// Returns the id of the I/O handle in the reactor. See [`FileId`]
+    ///
+    /// [`FileId`]: struct.FileId.html
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
     /// Unwraps the inner non-blocking I/O handle.
     ///
     /// # Examples
@@ -180,6 +225,7 @@ impl<T> Async<T> {
     /// # std::io::Result::Ok(()) });
     /// ```
     pub async fn readable(&self) -> io::Result<()> {
+        Reactor::get().check_file(self.id)?;
         self.source.readable().await
     }
 
@@ -202,9 +248,83 @@ impl<T> Async<T> {
     /// # std::io::Result::Ok(()) });
     /// ```
     pub async fn writable(&self) -> io::Result<()> {
+        Reactor::get().check_file(self.id)?;
         self.source.writable().await
     }
 
+    /// Stops accepting connections: the poll waiting for the listener to be readable is
+    /// taken out of the kernel, and `accept_with` doesn't call its `op` again until
+    /// intake resumes. Connections queue up in the kernel backlog in the meantime.
+    pub(crate) fn pause_intake(&self) {
+        if !self.intake.paused.replace(true) && self.source.is_inflight() {
+            // Wakes up the task waiting for connections, which then waits for intake to
+            // resume instead
+            Reactor::get().cancel_poll(&self.source);
+        }
+    }
+
+    pub(crate) fn resume_intake(&self) {
+        if self.intake.paused.replace(false) {
+            let waiters: Vec<_> = self.intake.waiters.borrow_mut().drain().collect();
+            for (_, waker) in waiters {
+                waker.wake();
+            }
+        }
+    }
+
+    pub(crate) fn is_intake_paused(&self) -> bool {
+        self.intake.paused.get()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn intake_waiters(&self) -> usize {
+        self.intake.waiters.borrow().len()
+    }
+
+    async fn intake_resumed(&self) {
+        if !self.intake.paused.get() {
+            return;
+        }
+        let waiter = IntakeWaiter {
+            intake: &self.intake,
+            id: self.intake.next_waiter.get(),
+        };
+        self.intake.next_waiter.set(waiter.id + 1);
+        future::poll_fn(|cx| {
+            if self.intake.paused.get() {
+                let mut waiters = self.intake.waiters.borrow_mut();
+                let waker = waiters
+                    .entry(waiter.id)
+                    .or_insert_with(|| cx.waker().clone());
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await
+    }
+
+    /// Like [`read_with`], but for accepting connections, which can be paused.
+    ///
+    /// [`read_with`]: struct.Async.html#method.read_with
+    pub(crate) async fn accept_with<R>(
+        &self,
+        op: impl FnMut(&T) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let mut op = op;
+        loop {
+            self.intake_resumed().await;
+            match op(self.get_ref()) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                res => return res,
+            }
+            optimistic(self.readable()).await?;
+        }
+    }
+
     /// Performs a read operation asynchronously.
     ///
     /// The I/O handle is registered in the reactor and put in non-blocking mode. This function
@@ -349,8 +469,103 @@ impl<T> Async<T> {
     }
 }
 
+impl<T> Async<T>
+where
+    for<'a> &'a T: Read,
+{
+    /// Reads exactly enough bytes to fill `buf`, issuing as many reads as it takes.
+    ///
+    /// Fails with [`io::ErrorKind::UnexpectedEof`] if the other side closes the stream
+    /// before `buf` is full. Reads interrupted by a signal are retried.
+    ///
+    /// Bytes are only taken from the socket when they can be copied into `buf` right
+    /// away, so if the returned future is dropped, or fails, no byte is lost in flight:
+    /// they are all in `buf`. How many made it there is unknown, though, so the stream
+    /// is usually best closed then, as it was left in the middle of a message.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::Async;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let stream = Async::<TcpStream>::connect(([127, 0, 0, 1], 8000)).await?;
+    /// let mut header = [0u8; 16];
+    /// stream.recv_exact(&mut header).await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [`io::ErrorKind::UnexpectedEof`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub async fn recv_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let unfilled = &mut buf[filled..];
+            match self.read_with(|io| (&*io).read(unfilled)).await {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the stream ended before the buffer was full",
+                    ))
+                }
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Async<T>
+where
+    for<'a> &'a T: Write,
+{
+    /// Writes all of `buf`, issuing as many writes as it takes.
+    ///
+    /// Fails with [`io::ErrorKind::WriteZero`] if the socket stops accepting bytes
+    /// before all of `buf` was written. Writes interrupted by a signal are retried.
+    ///
+    /// If the returned future is dropped, or fails, a prefix of `buf` of unknown length
+    /// may have been written already, so the stream is usually best closed then, as
+    /// the other side got part of a message.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::Async;
+    /// use std::net::TcpStream;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let stream = Async::<TcpStream>::connect(([127, 0, 0, 1], 8000)).await?;
+    /// stream.send_all(b"hello").await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    ///
+    /// [`io::ErrorKind::WriteZero`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+    pub async fn send_all(&self, buf: &[u8]) -> io::Result<()> {
+        let mut written = 0;
+        while written < buf.len() {
+            let unwritten = &buf[written..];
+            match self.write_with(|io| (&*io).write(unwritten)).await {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "the stream stopped accepting bytes",
+                    ))
+                }
+                Ok(n) => written += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<T> Drop for Async<T> {
     fn drop(&mut self) {
+        Reactor::unregister_dropped_file(self.id);
         if self.io.is_some() {
             // Drop the I/O handle to close it.
             self.io.take();
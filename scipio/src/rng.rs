@@ -0,0 +1,173 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A fast pseudo random number generator, owned by each executor.
+///
+/// Everything in scipio that makes random choices, like timer jitter and the
+/// [`QueueBalancer`], draws from the generator of the executor it runs in, so seeding
+/// it with [`LocalExecutor::seed_rng`] makes those choices repeat from one run to the
+/// next. Tasks get to it through [`Local::rng`].
+///
+/// This is a xorshift64* generator: it is not suitable for anything that needs to be
+/// unpredictable, like cryptography.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, Local};
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+/// ex.seed_rng(42);
+///
+/// ex.run(async {
+///     let dice = Local::rng().below(6) + 1;
+///     assert!(dice >= 1 && dice <= 6);
+/// });
+/// ```
+///
+/// [`QueueBalancer`]: struct.QueueBalancer.html
+/// [`LocalExecutor::seed_rng`]: struct.LocalExecutor.html#method.seed_rng
+/// [`Local::rng`]: type.Local.html#method.rng
+#[derive(Debug)]
+pub struct Rng {
+    seed: Cell<u64>,
+    // never zero
+    state: Cell<u64>,
+}
+
+fn entropy_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    // threads created at the same time still get different sequences
+    let thread = &nanos as *const u64 as u64;
+    nanos ^ thread.rotate_left(32)
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(entropy_seed())
+    }
+}
+
+impl Rng {
+    /// Creates a generator that produces the same sequence every time for the same `seed`.
+    pub fn new(seed: u64) -> Rng {
+        let rng = Rng {
+            seed: Cell::new(0),
+            state: Cell::new(1),
+        };
+        rng.reseed(seed);
+        rng
+    }
+
+    /// Restarts the sequence from `seed`.
+    pub fn reseed(&self, seed: u64) {
+        self.seed.set(seed);
+        // spread the bits of small seeds around, and stay away from zero
+        let state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        self.state.set(if state == 0 { 1 } else { state });
+    }
+
+    /// The seed the current sequence started from, so a run can be replayed.
+    pub fn seed(&self) -> u64 {
+        self.seed.get()
+    }
+
+    /// Returns a random `u64`.
+    pub fn u64(&self) -> u64 {
+        let mut x = self.state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a random `u32`.
+    pub fn u32(&self) -> u32 {
+        (self.u64() >> 32) as u32
+    }
+
+    /// Returns a random number between 0 and `n`, `n` excluded. Returns 0 if `n` is 0.
+    pub fn below(&self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        ((self.u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// Returns a random number between 0.0 and 1.0, 1.0 excluded.
+    pub fn f64(&self) -> f64 {
+        (self.u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns true with probability `p`.
+    pub fn chance(&self, p: f64) -> bool {
+        self.f64() < p
+    }
+
+    /// Puts the elements of `slice` in a random order.
+    pub fn shuffle<T>(&self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            slice.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let a = Rng::new(42);
+        let b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.u64(), b.u64());
+        }
+
+        a.reseed(7);
+        let first: Vec<_> = (0..10).map(|_| a.u64()).collect();
+        a.reseed(7);
+        let again: Vec<_> = (0..10).map(|_| a.u64()).collect();
+        assert_eq!(first, again);
+        assert_eq!(a.seed(), 7);
+    }
+
+    #[test]
+    fn values_stay_in_range() {
+        let rng = Rng::new(0);
+        for _ in 0..1000 {
+            assert!(rng.below(10) < 10);
+            let f = rng.f64();
+            assert!(f >= 0.0 && f < 1.0);
+        }
+        assert_eq!(rng.below(0), 0);
+
+        let mut v: Vec<u32> = (0..32).collect();
+        rng.shuffle(&mut v);
+        v.sort();
+        assert_eq!(v, (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn executor_rng_can_be_seeded() {
+        use crate::{Local, LocalExecutor};
+
+        let ex = LocalExecutor::new(None).unwrap();
+        ex.seed_rng(1234);
+        let first = ex.run(async { (0..10).map(|_| Local::rng().u64()).collect::<Vec<_>>() });
+        ex.seed_rng(1234);
+        let again = ex.run(async { (0..10).map(|_| Local::rng().u64()).collect::<Vec<_>>() });
+        assert_eq!(first, again);
+        assert_eq!(ex.rng().seed(), 1234);
+    }
+}
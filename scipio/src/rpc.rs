@@ -0,0 +1,536 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::bridge::bridge;
+use crate::error::ExecutorGone;
+use crate::monitor::Owner;
+use crate::{Local, Multiplexer, Timer};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either};
+use futures::lock::Mutex as AsyncMutex;
+use futures::{SinkExt, StreamExt};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Turns values into bytes and back, so they can be sent to other processes.
+///
+/// RPC clients and servers that talk over the network are created with a codec, which
+/// lets applications pick the serialization format of their protocols. With the
+/// `bincode-codec` feature enabled, [`BincodeCodec`] handles every type that implements
+/// serde's `Serialize` and `Deserialize`.
+///
+/// [`BincodeCodec`]: struct.BincodeCodec.html
+pub trait Codec<T> {
+    /// Encodes `value` into bytes
+    fn encode(&self, value: &T) -> io::Result<Vec<u8>>;
+
+    /// Decodes a value from the bytes produced by `encode`
+    fn decode(&self, bytes: &[u8]) -> io::Result<T>;
+}
+
+/// A [`Codec`] for serde types, using the bincode format
+///
+/// [`Codec`]: trait.Codec.html
+#[cfg(feature = "bincode-codec")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode-codec")]
+impl<T> Codec<T> for BincodeCodec
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn encode(&self, value: &T) -> io::Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<T> {
+        bincode::deserialize(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// Why an RPC call failed
+#[derive(Debug)]
+pub enum RpcError {
+    /// No response arrived before the deadline of the call
+    DeadlineExceeded,
+    /// The server went away before responding
+    Disconnected,
+    /// The executor serving the calls went away, for instance because it panicked
+    ExecutorGone(ExecutorGone),
+    /// The request or the response could not be transferred
+    Io(io::Error),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::DeadlineExceeded => {
+                write!(f, "the call did not complete before its deadline")
+            }
+            RpcError::Disconnected => write!(f, "the server went away before responding"),
+            RpcError::ExecutorGone(gone) => write!(f, "the call failed: {}", gone),
+            RpcError::Io(err) => write!(f, "the call failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RpcError::Io(err) => Some(err),
+            RpcError::ExecutorGone(gone) => Some(gone),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RpcError {
+    fn from(err: io::Error) -> RpcError {
+        RpcError::Io(err)
+    }
+}
+
+impl From<RpcError> for io::Error {
+    fn from(err: RpcError) -> io::Error {
+        match err {
+            RpcError::DeadlineExceeded => io::Error::new(io::ErrorKind::TimedOut, err),
+            RpcError::Disconnected | RpcError::ExecutorGone(_) => {
+                io::Error::new(io::ErrorKind::BrokenPipe, err)
+            }
+            RpcError::Io(err) => err,
+        }
+    }
+}
+
+// Runs `call`, giving up once `deadline` elapses, or earlier if the caller runs under
+// a deadline of its own that passes first
+async fn with_deadline<T>(
+    call: impl Future<Output = Result<T, RpcError>>,
+    deadline: Duration,
+) -> Result<T, RpcError> {
+    let mut when = Instant::now() + deadline;
+    if let Some(enclosing) = Local::deadline() {
+        when = std::cmp::min(when, enclosing);
+    }
+    futures::pin_mut!(call);
+    match future::select(call, Timer::at(when)).await {
+        Either::Left((res, _)) => res,
+        Either::Right(_) => Err(RpcError::DeadlineExceeded),
+    }
+}
+
+type Call<Req, Resp> = (Req, oneshot::Sender<Resp>);
+
+/// Creates an RPC client and server that talk to each other within the process, usually
+/// from different executors. Requests and responses are moved between them, without
+/// being encoded.
+///
+/// At most `capacity` requests wait for the server before calls start waiting too.
+///
+/// Once the server is serving, calls watch the executor it runs in: if it goes away,
+/// for instance because it panicked, pending and future calls fail with
+/// [`RpcError::ExecutorGone`] instead of waiting for their deadline.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{rpc_channel, LocalExecutor};
+/// use std::time::Duration;
+///
+/// let (client, server) = rpc_channel::<u64, u64>(16);
+///
+/// let handle = LocalExecutor::spawn_executor("server", None, move || async move {
+///     server.serve(|x| async move { x * 2 }).await;
+/// })
+/// .unwrap();
+///
+/// let local_ex = LocalExecutor::new(None).unwrap();
+/// local_ex.run(async move {
+///     let response = client.call(21, Duration::from_secs(1)).await.unwrap();
+///     assert_eq!(response, 42);
+/// });
+/// handle.join().unwrap();
+/// ```
+///
+/// [`RpcError::ExecutorGone`]: enum.RpcError.html#variant.ExecutorGone
+pub fn rpc_channel<Req, Resp>(capacity: usize) -> (RpcClient<Req, Resp>, RpcServer<Req, Resp>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    let owner = Owner::default();
+    (
+        RpcClient {
+            sender: Arc::new(AsyncMutex::new(sender)),
+            server: owner.clone(),
+        },
+        RpcServer { receiver, owner },
+    )
+}
+
+/// The client side of an [`rpc_channel`]. It can be cloned and sent to other executors.
+///
+/// [`rpc_channel`]: fn.rpc_channel.html
+#[derive(Debug)]
+pub struct RpcClient<Req, Resp> {
+    // Shared by all clones: mpsc gives each sender a slot of its own on top of the
+    // channel's buffer, so cloning it per call would make the channel unbounded.
+    sender: Arc<AsyncMutex<mpsc::Sender<Call<Req, Resp>>>>,
+    server: Owner,
+}
+
+impl<Req, Resp> Clone for RpcClient<Req, Resp> {
+    fn clone(&self) -> Self {
+        RpcClient {
+            sender: self.sender.clone(),
+            server: self.server.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> RpcClient<Req, Resp> {
+    /// Sends a request to the server and waits for its response, for at most
+    /// `deadline`.
+    pub async fn call(&self, request: Req, deadline: Duration) -> Result<Resp, RpcError> {
+        let sender = self.sender.clone();
+        let server = self.server.clone();
+        with_deadline(
+            async move {
+                let (reply, response) = oneshot::channel();
+                let call = async move {
+                    sender
+                        .lock()
+                        .await
+                        .send((request, reply))
+                        .await
+                        .map_err(|_| RpcError::Disconnected)?;
+                    response.await.map_err(|_| RpcError::Disconnected)
+                };
+                futures::pin_mut!(call);
+                let gone = future::poll_fn(|cx| server.poll_gone(cx));
+                match bridge(future::select(call, gone)).await? {
+                    // The executor of the server is marked gone before its tasks are
+                    // dropped, which is what disconnects the call
+                    Either::Left((Err(RpcError::Disconnected), _)) => match server.check() {
+                        Err(gone) => Err(RpcError::ExecutorGone(gone)),
+                        Ok(()) => Err(RpcError::Disconnected),
+                    },
+                    Either::Left((res, _)) => res,
+                    Either::Right((gone, _)) => Err(RpcError::ExecutorGone(gone)),
+                }
+            },
+            deadline,
+        )
+        .await
+    }
+}
+
+/// The server side of an [`rpc_channel`]
+///
+/// [`rpc_channel`]: fn.rpc_channel.html
+#[derive(Debug)]
+pub struct RpcServer<Req, Resp> {
+    receiver: mpsc::Receiver<Call<Req, Resp>>,
+    owner: Owner,
+}
+
+impl<Req: 'static, Resp: 'static> RpcServer<Req, Resp> {
+    /// Handles requests until all clients are dropped. Every request is handled in a
+    /// task of its own, so slow requests don't hold back the others.
+    pub async fn serve<F, Fut>(mut self, handler: F)
+    where
+        F: Fn(Req) -> Fut,
+        Fut: Future<Output = Resp> + 'static,
+    {
+        self.owner.claim();
+        while let Ok(Some((request, reply))) = bridge(self.receiver.next()).await {
+            let response = handler(request);
+            Local::local(async move {
+                // The client may have given up on the call already
+                let _ = reply.send(response.await);
+            })
+            .detach();
+        }
+    }
+}
+
+/// A client that calls an RPC server in another process, through a [`Multiplexer`]
+///
+/// Every call takes a channel of its own in the multiplexer, so calls don't wait for
+/// each other.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{serve_rpc, Async, Codec, LocalExecutor, Local, Multiplexer, RemoteRpcClient};
+/// use std::io;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// struct Be64;
+///
+/// impl Codec<u64> for Be64 {
+///     fn encode(&self, value: &u64) -> io::Result<Vec<u8>> {
+///         Ok(value.to_be_bytes().to_vec())
+///     }
+///
+///     fn decode(&self, bytes: &[u8]) -> io::Result<u64> {
+///         let mut buf = [0u8; 8];
+///         buf.copy_from_slice(bytes);
+///         Ok(u64::from_be_bytes(buf))
+///     }
+/// }
+///
+/// let local_ex = LocalExecutor::new(None).unwrap();
+/// local_ex.run(async move {
+///     let (a, b) = Async::<UnixStream>::pair().unwrap();
+///     let server = Multiplexer::new(b, false, 64 << 10);
+///     Local::local(serve_rpc(server, Be64, |x: u64| async move { x + 1 })).detach();
+///
+///     let client = RemoteRpcClient::new(Multiplexer::new(a, true, 64 << 10), Be64);
+///     let response: u64 = client.call(&41, Duration::from_secs(1)).await.unwrap();
+///     assert_eq!(response, 42);
+/// });
+/// ```
+///
+/// [`Multiplexer`]: struct.Multiplexer.html
+#[derive(Debug)]
+pub struct RemoteRpcClient<Req, Resp, C> {
+    mux: Multiplexer,
+    codec: C,
+    _types: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req, Resp, C> RemoteRpcClient<Req, Resp, C>
+where
+    C: Codec<Req> + Codec<Resp>,
+{
+    /// Creates a client that sends its calls through `mux`, encoded with `codec`. The
+    /// multiplexer must not be used for anything else.
+    pub fn new(mux: Multiplexer, codec: C) -> RemoteRpcClient<Req, Resp, C> {
+        RemoteRpcClient {
+            mux,
+            codec,
+            _types: PhantomData,
+        }
+    }
+
+    /// Sends a request to the server and waits for its response, for at most
+    /// `deadline`.
+    pub async fn call(&self, request: &Req, deadline: Duration) -> Result<Resp, RpcError> {
+        with_deadline(
+            async move {
+                let channel = self.mux.open()?;
+                let bytes = Codec::<Req>::encode(&self.codec, request)?;
+                channel.send(&bytes).await?;
+                let bytes = channel.recv().await.ok_or(RpcError::Disconnected)?;
+                match bytes.split_first() {
+                    Some((&RESPONSE_OK, payload)) => {
+                        Ok(Codec::<Resp>::decode(&self.codec, payload)?)
+                    }
+                    Some((&RESPONSE_ERROR, message)) => Err(RpcError::Io(io::Error::new(
+                        io::ErrorKind::Other,
+                        String::from_utf8_lossy(message).into_owned(),
+                    ))),
+                    _ => Err(RpcError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "malformed response",
+                    ))),
+                }
+            },
+            deadline,
+        )
+        .await
+    }
+}
+
+// Every response starts with one of these, followed by the encoded response or by the
+// reason the server could not produce one
+const RESPONSE_OK: u8 = 0;
+const RESPONSE_ERROR: u8 = 1;
+
+/// Serves the calls of a [`RemoteRpcClient`] that arrive through `mux`, until the
+/// multiplexer is closed. Every request is handled in a task of its own, so slow
+/// requests don't hold back the others.
+///
+/// Requests that can't be decoded, and responses that can't be encoded or don't fit
+/// in the window of the multiplexer, fail the call with [`RpcError::Io`] on the client.
+///
+/// [`RemoteRpcClient`]: struct.RemoteRpcClient.html
+/// [`RpcError::Io`]: enum.RpcError.html#variant.Io
+pub async fn serve_rpc<Req, Resp, C, F, Fut>(mux: Multiplexer, codec: C, handler: F)
+where
+    Req: 'static,
+    Resp: 'static,
+    C: Codec<Req> + Codec<Resp> + 'static,
+    F: Fn(Req) -> Fut + 'static,
+    Fut: Future<Output = Resp> + 'static,
+{
+    let codec = Rc::new(codec);
+    let handler = Rc::new(handler);
+    while let Some(channel) = mux.accept().await {
+        let codec = codec.clone();
+        let handler = handler.clone();
+        Local::local(async move {
+            let request = match channel.recv().await {
+                Some(bytes) => Codec::<Req>::decode(&*codec, &bytes),
+                None => return,
+            };
+            let sent = match request {
+                Ok(request) => {
+                    let response = handler(request).await;
+                    match Codec::<Resp>::encode(&*codec, &response) {
+                        Ok(bytes) => {
+                            let mut frame = Vec::with_capacity(1 + bytes.len());
+                            frame.push(RESPONSE_OK);
+                            frame.extend_from_slice(&bytes);
+                            channel.send(&frame).await
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+                Err(err) => Err(err),
+            };
+            if let Err(err) = sent {
+                let mut frame = vec![RESPONSE_ERROR];
+                frame.extend_from_slice(err.to_string().as_bytes());
+                // The client may have given up on the call already
+                let _ = channel.send(&frame).await;
+            }
+        })
+        .detach();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Async, LocalExecutor};
+    use std::os::unix::net::UnixStream;
+
+    struct Utf8;
+
+    impl Codec<String> for Utf8 {
+        fn encode(&self, value: &String) -> io::Result<Vec<u8>> {
+            Ok(value.as_bytes().to_vec())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> io::Result<String> {
+            String::from_utf8(bytes.to_vec())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+    }
+
+    #[test]
+    fn rpc_between_executors() {
+        let (client, server) = rpc_channel::<u32, u32>(4);
+        let handle = LocalExecutor::spawn_executor("rpc-server", None, move || async move {
+            server
+                .serve(|x| async move {
+                    if x == 0 {
+                        Timer::new(Duration::from_millis(200)).await;
+                    }
+                    x + 1
+                })
+                .await;
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            let deadline = Duration::from_secs(5);
+            assert_eq!(client.call(1, deadline).await.unwrap(), 2);
+            match client.call(0, Duration::from_millis(10)).await {
+                Err(RpcError::DeadlineExceeded) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+            assert_eq!(client.call(2, deadline).await.unwrap(), 3);
+        });
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn calls_fail_once_the_server_executor_is_poisoned() {
+        let (client, server) = rpc_channel::<u32, u32>(4);
+        let handle = LocalExecutor::spawn_executor("rpc-server", None, move || async move {
+            // The call is never answered, and another task brings the executor down
+            server
+                .serve(|_| {
+                    Local::local(async move { panic!("the server went away") }).detach();
+                    future::pending::<u32>()
+                })
+                .await;
+        })
+        .unwrap();
+
+        test_executor!(async move {
+            let deadline = Duration::from_secs(5);
+            match client.call(1, deadline).await {
+                Err(RpcError::ExecutorGone(gone)) => assert!(gone.poisoned()),
+                other => panic!("unexpected result: {:?}", other),
+            }
+            // Later calls don't wait for their deadline either
+            let started = Instant::now();
+            match client.call(2, deadline).await {
+                Err(RpcError::ExecutorGone(_)) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+            assert!(started.elapsed() < deadline);
+        });
+        assert!(handle.join().is_err());
+    }
+
+    #[test]
+    fn rpc_over_multiplexer() {
+        test_executor!(async move {
+            let (a, b) = Async::<UnixStream>::pair().unwrap();
+            let server = Multiplexer::new(b, false, 1024);
+            Local::local(serve_rpc(server, Utf8, |name: String| async move {
+                format!("hello {}", name)
+            }))
+            .detach();
+
+            let client = RemoteRpcClient::new(Multiplexer::new(a, true, 1024), Utf8);
+            let calls = vec!["a".to_string(), "b".to_string()];
+            let responses = futures::future::join_all(
+                calls
+                    .iter()
+                    .map(|name| client.call(name, Duration::from_secs(5))),
+            )
+            .await;
+            let responses: Vec<String> = responses.into_iter().map(|r| r.unwrap()).collect();
+            assert_eq!(responses, vec!["hello a", "hello b"]);
+        });
+    }
+
+    #[test]
+    fn responses_that_cannot_be_sent_fail_the_call() {
+        test_executor!(async move {
+            let (a, b) = Async::<UnixStream>::pair().unwrap();
+            let server = Multiplexer::new(b, false, 1024);
+            Local::local(serve_rpc(server, Utf8, |len: String| async move {
+                "x".repeat(len.parse().unwrap())
+            }))
+            .detach();
+
+            let client =
+                RemoteRpcClient::<String, String, _>::new(Multiplexer::new(a, true, 1024), Utf8);
+            let deadline = Duration::from_secs(5);
+            assert_eq!(
+                client.call(&"3".to_string(), deadline).await.unwrap(),
+                "xxx"
+            );
+            // Larger than the window of the multiplexer
+            let started = Instant::now();
+            match client.call(&"4096".to_string(), deadline).await {
+                Err(RpcError::Io(_)) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+            assert!(started.elapsed() < deadline);
+        });
+    }
+}
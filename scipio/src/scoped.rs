@@ -0,0 +1,149 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use futures::future::{FutureExt, LocalBoxFuture};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+#[derive(Debug)]
+struct Slot<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Spawns futures that may borrow from the stack of the task that created the scope.
+///
+/// Created by [`Local::scope`], which doesn't complete before every future spawned in
+/// the scope did.
+///
+/// [`Local::scope`]: struct.Task.html#method.scope
+pub struct Scope<'a> {
+    spawned: RefCell<Vec<LocalBoxFuture<'a, ()>>>,
+}
+
+impl fmt::Debug for Scope<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("spawned", &self.spawned.borrow().len())
+            .finish()
+    }
+}
+
+impl<'a> Scope<'a> {
+    /// Spawns a future in this scope. It starts running once the scope is awaited, along
+    /// with the other futures spawned in it, and returns a [`ScopedTask`] that outputs
+    /// what the future does.
+    ///
+    /// [`ScopedTask`]: struct.ScopedTask.html
+    pub fn spawn<T: 'a>(&self, future: impl Future<Output = T> + 'a) -> ScopedTask<T> {
+        let slot = Rc::new(RefCell::new(Slot {
+            result: None,
+            waker: None,
+        }));
+        let task_slot = slot.clone();
+        let future = async move {
+            let result = future.await;
+            let mut slot = task_slot.borrow_mut();
+            slot.result = Some(result);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        };
+        self.spawned.borrow_mut().push(future.boxed_local());
+        ScopedTask { slot }
+    }
+}
+
+/// A future spawned in a [`Scope`]. Awaiting it outputs what the future does.
+///
+/// It completes once the future it stands for does, which needs the scope to be awaited:
+/// it can be awaited from another future spawned in the same scope, or after the scope
+/// completed.
+///
+/// [`Scope`]: struct.Scope.html
+pub struct ScopedTask<T> {
+    slot: Rc<RefCell<Slot<T>>>,
+}
+
+impl<T> fmt::Debug for ScopedTask<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopedTask")
+            .field("done", &self.slot.borrow().result.is_some())
+            .finish()
+    }
+}
+
+impl<T> Future for ScopedTask<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut slot = self.slot.borrow_mut();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// The futures spawned in the scope are polled by the future of the scope itself, not
+// spawned as tasks of their own. Forgetting the future of the scope then leaks them
+// without ever polling them again, so they can't outlive what they borrow.
+pub(crate) async fn scope<'a, F, R>(f: F) -> R
+where
+    F: FnOnce(&Scope<'a>) -> R,
+{
+    let scope = Scope {
+        spawned: RefCell::new(Vec::new()),
+    };
+    let result = f(&scope);
+    let mut running: FuturesUnordered<_> = scope.spawned.into_inner().into_iter().collect();
+    while running.next().await.is_some() {}
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Local, Timer};
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    #[test]
+    fn scoped_futures_borrow_from_the_stack() {
+        test_executor!(async move {
+            let cell = Cell::new(0);
+            let counter = &cell;
+            let names = vec!["a".to_string(), "b".to_string()];
+
+            let lengths = Local::scope(|s| {
+                let slow = s.spawn(async {
+                    Timer::new(Duration::from_millis(10)).await;
+                    counter.set(counter.get() + 1);
+                });
+                let fast = s.spawn(async {
+                    counter.set(counter.get() + 1);
+                    names.iter().map(|name| name.len()).sum::<usize>()
+                });
+                s.spawn(async move {
+                    slow.await;
+                    counter.set(counter.get() * 10);
+                });
+                fast
+            })
+            .await;
+
+            // every future is done by the time the scope is
+            assert_eq!(cell.get(), 20);
+            assert_eq!(lengths.await, 2);
+        });
+    }
+}
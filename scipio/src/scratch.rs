@@ -0,0 +1,456 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::error::Error;
+use crate::sys::{self, DmaBuffer};
+use crate::{DmaFile, Local, Result, TaskQueueHandle};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Scratch directories are locked with flock by the scratch space that owns them for as
+// long as it lives, and the kernel releases the lock when the process dies, so leftovers
+// of processes that died can be told apart from the directories of live ones. Unlike
+// process ids, that works no matter how many pid namespaces share the root.
+const SCRATCH_PREFIX: &str = "scipio-scratch-";
+
+static SCRATCH_SPACES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default)]
+struct Quota {
+    limit: Option<u64>,
+    used: u64,
+}
+
+#[derive(Debug)]
+struct ScratchInner {
+    dir: PathBuf,
+    // Holds the lock on the directory, released after it is removed
+    _lock: File,
+    quotas: RefCell<HashMap<TaskQueueHandle, Quota>>,
+    next_entry: Cell<u64>,
+}
+
+impl ScratchInner {
+    fn next_path(&self, kind: &str) -> PathBuf {
+        let id = self.next_entry.get();
+        self.next_entry.set(id + 1);
+        self.dir.join(format!("{}-{}", kind, id))
+    }
+
+    fn charge(&self, queue: TaskQueueHandle, bytes: u64) -> io::Result<()> {
+        let mut quotas = self.quotas.borrow_mut();
+        let quota = quotas.entry(queue).or_default();
+        match quota.limit {
+            Some(limit) if quota.used + bytes > limit => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "scratch space quota exceeded",
+            )),
+            _ => {
+                quota.used += bytes;
+                Ok(())
+            }
+        }
+    }
+
+    fn release(&self, queue: TaskQueueHandle, bytes: u64) {
+        if let Some(quota) = self.quotas.borrow_mut().get_mut(&queue) {
+            quota.used -= bytes;
+        }
+    }
+}
+
+impl Drop for ScratchInner {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Removes the scratch directories under `root` that nobody holds the lock of. This is
+// only housekeeping, so whatever can't be removed is left for the next time.
+//
+// `root_lock` is the lock on the root, which scratch spaces hold from the moment they
+// create their directory until they lock it, so they are never taken for leftovers.
+fn recover(root: &Path, root_lock: File) {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut leftovers = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let is_scratch = entry
+            .file_name()
+            .to_str()
+            .map_or(false, |name| name.starts_with(SCRATCH_PREFIX));
+        if !is_scratch {
+            continue;
+        }
+        if let Ok(Some(lock)) = sys::lock_dir(&entry.path(), false) {
+            leftovers.push((entry.path(), lock));
+        }
+    }
+    // Other scratch spaces can be created while the leftovers go, which can take long.
+    // They are still locked, so nobody else goes for them.
+    drop(root_lock);
+    for (leftover, _lock) in leftovers {
+        let _ = std::fs::remove_dir_all(leftover);
+    }
+}
+
+// Creates and locks a scratch directory under `root`, whose lock is held.
+fn create_locked_dir(root: &Path) -> io::Result<(PathBuf, File)> {
+    loop {
+        let dir = root.join(format!(
+            "{}{}-{}",
+            SCRATCH_PREFIX,
+            std::process::id(),
+            SCRATCH_SPACES.fetch_add(1, Ordering::Relaxed)
+        ));
+        match std::fs::create_dir(&dir) {
+            Ok(()) => {}
+            // A process with the same id in another pid namespace got there first
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+        return match sys::lock_dir(&dir, false) {
+            Ok(Some(lock)) => Ok((dir, lock)),
+            Ok(None) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "scratch directory locked by somebody else",
+            )),
+            Err(err) => {
+                let _ = std::fs::remove_dir(&dir);
+                Err(err)
+            }
+        };
+    }
+}
+
+/// Hands out temporary files and directories under a configured root, for operators that
+/// spill to disk.
+///
+/// Each scratch space owns a directory of its own under the root, which is removed with
+/// everything in it once the scratch space and all files and directories handed out by
+/// it are dropped. Scratch files are removed as soon as they are closed or dropped, so
+/// the space used by a task is given back when the task completes, even if it completes
+/// by being cancelled. Creating a scratch space also removes the directories left behind
+/// by processes that crashed before cleaning up after themselves, on a best effort basis.
+/// Every scratch space holds a `flock` on its directory to tell it apart from those.
+///
+/// The bytes written to scratch files are charged to the task queue of the task that
+/// created them. Task queues can be given a quota, and writes that would take a task
+/// queue over its quota fail instead of filling up the disk.
+///
+/// Setting up a scratch space and removing directories are synchronous operations that
+/// block the reactor. Files are created and written to asynchronously.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{DmaFile, Local, LocalExecutor, ScratchSpace};
+///
+/// let ex = LocalExecutor::new(None).unwrap();
+/// ex.run(async move {
+///     let scratch = ScratchSpace::new(std::env::temp_dir()).unwrap();
+///     scratch.set_quota(Local::current_task_queue(), Some(1 << 20));
+///
+///     let file = scratch.create_file().await.unwrap();
+///     let buf = DmaFile::alloc_dma_buffer(4096);
+///     file.write_dma(&buf, 0).await.unwrap();
+///     assert_eq!(scratch.usage(Local::current_task_queue()), 4096);
+///     file.close().await.unwrap();
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScratchSpace {
+    inner: Rc<ScratchInner>,
+}
+
+impl ScratchSpace {
+    /// Creates a scratch space under `root`, which must exist.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<ScratchSpace> {
+        let root = root.as_ref();
+        let root_lock = sys::lock_dir(root, true)?.unwrap();
+        let (dir, lock) = create_locked_dir(root)?;
+        recover(root, root_lock);
+        Ok(ScratchSpace {
+            inner: Rc::new(ScratchInner {
+                dir,
+                _lock: lock,
+                quotas: RefCell::new(HashMap::new()),
+                next_entry: Cell::new(0),
+            }),
+        })
+    }
+
+    /// Returns the directory that holds the files and directories of this scratch space.
+    pub fn path(&self) -> &Path {
+        &self.inner.dir
+    }
+
+    /// Limits the bytes the files created by tasks in `queue` can hold at once. `None`
+    /// lifts the limit. Lowering the quota below the current usage doesn't affect the
+    /// data already written, but no more can be written until the usage goes down.
+    pub fn set_quota(&self, queue: TaskQueueHandle, bytes: Option<u64>) {
+        self.inner
+            .quotas
+            .borrow_mut()
+            .entry(queue)
+            .or_default()
+            .limit = bytes;
+    }
+
+    /// Returns the bytes held by the scratch files created by tasks in `queue`.
+    pub fn usage(&self, queue: TaskQueueHandle) -> u64 {
+        self.inner
+            .quotas
+            .borrow()
+            .get(&queue)
+            .map_or(0, |quota| quota.used)
+    }
+
+    /// Creates a new scratch file, charged to the task queue of the current task.
+    pub async fn create_file(&self) -> Result<ScratchFile> {
+        let path = self.inner.next_path("file");
+        let file = DmaFile::create_new(&path).await?;
+        Ok(ScratchFile {
+            space: self.inner.clone(),
+            queue: Local::current_task_queue(),
+            file: Some(file),
+            path,
+            size: Cell::new(0),
+        })
+    }
+
+    /// Creates a new scratch directory. What is written to it is not charged to any
+    /// task queue.
+    pub fn create_dir(&self) -> io::Result<ScratchDir> {
+        let path = self.inner.next_path("dir");
+        std::fs::create_dir(&path)?;
+        Ok(ScratchDir {
+            _space: self.inner.clone(),
+            path,
+        })
+    }
+}
+
+/// A temporary file handed out by a [`ScratchSpace`]. It is removed when closed or
+/// dropped.
+///
+/// Scratch files should be closed with [`close`], as dropping them closes the file
+/// synchronously.
+///
+/// [`ScratchSpace`]: struct.ScratchSpace.html
+/// [`close`]: struct.ScratchFile.html#method.close
+#[derive(Debug)]
+pub struct ScratchFile {
+    space: Rc<ScratchInner>,
+    queue: TaskQueueHandle,
+    file: Option<DmaFile>,
+    path: PathBuf,
+    // the furthest byte written so far, which is what is charged to the queue
+    size: Cell<u64>,
+}
+
+impl ScratchFile {
+    fn file(&self) -> &DmaFile {
+        self.file.as_ref().unwrap()
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the bytes charged to the task queue for this file, which is the end of
+    /// its furthest write.
+    pub fn size(&self) -> u64 {
+        self.size.get()
+    }
+
+    /// Writes `buf` at `pos`, like [`DmaFile::write_dma`]. Fails without writing if
+    /// growing the file that far would take the task queue of the file over its quota.
+    /// Writes that fail or fall short give back what they didn't grow the file by.
+    ///
+    /// [`DmaFile::write_dma`]: struct.DmaFile.html#method.write_dma
+    pub async fn write_dma(&self, buf: &DmaBuffer, pos: u64) -> Result<usize> {
+        let end = pos + buf.len() as u64;
+        let growth = end.saturating_sub(self.size.get());
+        if let Err(inner) = self.space.charge(self.queue, growth) {
+            return Err(Error {
+                inner,
+                op: "Writing",
+                path: Some(self.path.clone()),
+                fd: Some(self.file().as_raw_fd()),
+            });
+        }
+        self.size.set(self.size.get() + growth);
+        let res = self.file().write_dma(buf, pos).await;
+        let written = match &res {
+            Ok(written) => pos + *written as u64,
+            Err(_) => pos,
+        };
+        // Unless another write grew the file past this one in the meantime, in which
+        // case the gap counts as part of the file
+        if written < end && self.size.get() == end {
+            let kept = std::cmp::max(written, end - growth);
+            self.size.set(kept);
+            self.space.release(self.queue, end - kept);
+        }
+        res
+    }
+
+    /// Reads from the file, like [`DmaFile::read_dma`].
+    ///
+    /// [`DmaFile::read_dma`]: struct.DmaFile.html#method.read_dma
+    pub async fn read_dma(&self, pos: u64, size: usize) -> Result<DmaBuffer> {
+        self.file().read_dma(pos, size).await
+    }
+
+    /// Reads from an aligned position in the file, like [`DmaFile::read_dma_aligned`].
+    ///
+    /// [`DmaFile::read_dma_aligned`]: struct.DmaFile.html#method.read_dma_aligned
+    pub async fn read_dma_aligned(&self, pos: u64, size: usize) -> Result<DmaBuffer> {
+        self.file().read_dma_aligned(pos, size).await
+    }
+
+    /// Closes and removes the file, giving its bytes back to its task queue. The file is
+    /// removed even if closing it fails.
+    pub async fn close(mut self) -> Result<()> {
+        let mut file = self.file.take().unwrap();
+        let closed = file.close().await;
+        let removed = DmaFile::remove(&self.path).await;
+        closed.and(removed)
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        self.space.release(self.queue, self.size.get());
+        if let Some(file) = self.file.take() {
+            drop(file);
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A temporary directory handed out by a [`ScratchSpace`]. It is removed with everything
+/// in it when dropped.
+///
+/// [`ScratchSpace`]: struct.ScratchSpace.html
+#[derive(Debug)]
+pub struct ScratchDir {
+    _space: Rc<ScratchInner>,
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    /// Returns the path of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scratch_files_respect_quotas() {
+        test_executor!(async move {
+            let root = std::env::temp_dir().join("scratch_files_respect_quotas");
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir(&root).unwrap();
+
+            let scratch = ScratchSpace::new(&root).unwrap();
+            let queue = Local::current_task_queue();
+            scratch.set_quota(queue, Some(8192));
+
+            let file = scratch.create_file().await.unwrap();
+            let buf = DmaFile::alloc_dma_buffer(4096);
+            file.write_dma(&buf, 0).await.unwrap();
+            file.write_dma(&buf, 4096).await.unwrap();
+            // rewriting doesn't grow the file
+            file.write_dma(&buf, 0).await.unwrap();
+            assert_eq!(scratch.usage(queue), 8192);
+            assert!(file.write_dma(&buf, 8192).await.is_err());
+
+            let path = file.path().to_path_buf();
+            file.close().await.unwrap();
+            assert!(!path.exists());
+            assert_eq!(scratch.usage(queue), 0);
+
+            let dir = scratch.create_dir().unwrap();
+            std::fs::write(dir.path().join("spill"), b"data").unwrap();
+            let dir_path = dir.path().to_path_buf();
+            drop(dir);
+            assert!(!dir_path.exists());
+
+            let space_path = scratch.path().to_path_buf();
+            drop(scratch);
+            assert!(!space_path.exists());
+            std::fs::remove_dir_all(&root).unwrap();
+        });
+    }
+
+    #[test]
+    fn failed_writes_give_their_quota_back() {
+        test_executor!(async move {
+            let root = std::env::temp_dir().join("failed_writes_give_their_quota_back");
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir(&root).unwrap();
+
+            let scratch = ScratchSpace::new(&root).unwrap();
+            let queue = Local::current_task_queue();
+            let file = scratch.create_file().await.unwrap();
+            let buf = DmaFile::alloc_dma_buffer(4096);
+            file.write_dma(&buf, 0).await.unwrap();
+
+            // no file can grow past the largest offset there is
+            let past_the_end = i64::MAX as u64 + 1 - 4096;
+            assert!(file.write_dma(&buf, past_the_end).await.is_err());
+            assert_eq!(file.size(), 4096);
+            assert_eq!(scratch.usage(queue), 4096);
+
+            file.close().await.unwrap();
+            assert_eq!(scratch.usage(queue), 0);
+            drop(scratch);
+            std::fs::remove_dir_all(&root).unwrap();
+        });
+    }
+
+    #[test]
+    fn leftovers_of_dead_processes_are_removed() {
+        let root = std::env::temp_dir().join("leftovers_of_dead_processes_are_removed");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir(&root).unwrap();
+        // nobody holds the lock of this one
+        let leftover = root.join(format!("{}{}-0", SCRATCH_PREFIX, std::process::id()));
+        std::fs::create_dir(&leftover).unwrap();
+        std::fs::write(leftover.join("file-0"), b"data").unwrap();
+
+        let scratch = ScratchSpace::new(&root).unwrap();
+        assert!(!leftover.exists());
+        assert!(scratch.path().exists());
+
+        // but a live scratch space holds the lock of its own
+        let other = ScratchSpace::new(&root).unwrap();
+        assert!(scratch.path().exists());
+        drop(other);
+        drop(scratch);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
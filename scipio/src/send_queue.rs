@@ -0,0 +1,366 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::memory_budget::BudgetTicket;
+use crate::ConnectionBudget;
+use futures::future;
+use futures_lite::io::AsyncWrite;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A write queue that buffers outbound data for a stream and applies backpressure
+/// based on high and low watermarks.
+///
+/// Writes to the queue are accepted as long as the amount of buffered data is below the
+/// high watermark. Once it crosses that mark the queue is considered congested and stays
+/// that way until the stream drains it down to the low watermark. That way a slow
+/// consumer cannot make a fast producer buffer an unbounded amount of data, and the
+/// producer is not woken up for every byte that leaves the queue either.
+///
+/// `SendQueue` implements [`AsyncWrite`] itself, and writes to it block while it is
+/// congested. Producers that prefer to handle backpressure explicitly can use
+/// [`enqueue`] and [`writable_again`] instead.
+///
+/// The data buffered can be charged to a [`ConnectionBudget`], with [`set_budget`]. Writes
+/// are cut short to what fits in the budget. When nothing fits they wait for the queue to
+/// drain, or, if other connections hold the memory, for their turn to get some back.
+///
+/// [`AsyncWrite`]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncWrite.html
+/// [`enqueue`]: struct.SendQueue.html#method.enqueue
+/// [`writable_again`]: struct.SendQueue.html#method.writable_again
+/// [`ConnectionBudget`]: struct.ConnectionBudget.html
+/// [`set_budget`]: struct.SendQueue.html#method.set_budget
+#[derive(Debug)]
+pub struct SendQueue<S> {
+    stream: S,
+    buffer: VecDeque<u8>,
+    low_watermark: usize,
+    high_watermark: usize,
+    congested: bool,
+    budget: Option<ConnectionBudget>,
+    ticket: BudgetTicket,
+}
+
+impl<S: AsyncWrite + Unpin> SendQueue<S> {
+    /// Wraps `stream` in a queue that becomes congested once more than `high_watermark`
+    /// bytes are buffered and stops being congested once that goes down to `low_watermark`
+    /// bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `low_watermark` is larger than `high_watermark`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Async, SendQueue};
+    /// use std::net::TcpStream;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let stream = Async::<TcpStream>::connect(([127, 0, 0, 1], 8000)).await?;
+    /// let queue = SendQueue::new(stream, 16 << 10, 64 << 10);
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub fn new(stream: S, low_watermark: usize, high_watermark: usize) -> SendQueue<S> {
+        assert!(
+            low_watermark <= high_watermark,
+            "low watermark must not be above the high watermark"
+        );
+        SendQueue {
+            stream,
+            buffer: VecDeque::new(),
+            low_watermark,
+            high_watermark,
+            congested: false,
+            budget: None,
+            ticket: BudgetTicket::default(),
+        }
+    }
+
+    /// Returns the amount of bytes buffered and not yet written to the stream.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns true if the queue crossed its high watermark and did not drain down to the
+    /// low watermark yet.
+    pub fn is_congested(&self) -> bool {
+        self.congested
+    }
+
+    /// Charges the data buffered in the queue, from now on, to `budget`. The data already
+    /// buffered is charged right away, even if it doesn't fit.
+    pub fn set_budget(&mut self, budget: ConnectionBudget) {
+        budget.charge(self.buffer.len());
+        self.ticket = BudgetTicket::default();
+        self.budget = Some(budget);
+    }
+
+    /// Returns the budget the queue charges its data to, if it has one.
+    pub fn budget(&self) -> Option<&ConnectionBudget> {
+        self.budget.as_ref()
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Writing to it directly will interleave with data still buffered in the queue.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Consumes the queue and returns the underlying stream. Any data still buffered is
+    /// lost, so call [`flush`] first if that matters.
+    ///
+    /// [`flush`]: https://docs.rs/futures-lite/0.1/futures_lite/io/trait.AsyncWriteExt.html#method.flush
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Adds `data` to the queue regardless of how much is already buffered.
+    ///
+    /// Returns true if the queue is congested after that, in which case the caller should
+    /// wait for [`writable_again`] before producing more data. Like any other buffered
+    /// writer, data that does not congest the queue is only written out once the queue
+    /// is flushed.
+    ///
+    /// [`writable_again`]: struct.SendQueue.html#method.writable_again
+    pub fn enqueue(&mut self, data: &[u8]) -> bool {
+        if let Some(budget) = &self.budget {
+            budget.charge(data.len());
+        }
+        self.buffer.extend(data);
+        self.update_congestion();
+        self.congested
+    }
+
+    /// Adds `data` to the queue like [`enqueue`], unless it doesn't fit in the budget of
+    /// the queue, in which case nothing is added and an error is returned.
+    ///
+    /// [`enqueue`]: struct.SendQueue.html#method.enqueue
+    pub fn try_enqueue(&mut self, data: &[u8]) -> io::Result<bool> {
+        if let Some(budget) = &self.budget {
+            budget.try_reserve(data.len())?;
+        }
+        self.buffer.extend(data);
+        self.update_congestion();
+        Ok(self.congested)
+    }
+
+    /// Writes buffered data to the stream until the queue is no longer congested.
+    ///
+    /// Returns immediately if the queue is not congested.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures_lite::io::AsyncWriteExt;
+    /// use scipio::{Async, SendQueue};
+    /// use std::net::TcpStream;
+    ///
+    /// # futures_lite::future::block_on(async {
+    /// let stream = Async::<TcpStream>::connect(([127, 0, 0, 1], 8000)).await?;
+    /// let mut queue = SendQueue::new(stream, 16 << 10, 64 << 10);
+    /// for _ in 0..1000 {
+    ///     if queue.enqueue(b"some data") {
+    ///         queue.writable_again().await?;
+    ///     }
+    /// }
+    /// queue.flush().await?;
+    /// # std::io::Result::Ok(()) });
+    /// ```
+    pub async fn writable_again(&mut self) -> io::Result<()> {
+        future::poll_fn(|cx| {
+            if !self.congested {
+                return Poll::Ready(Ok(()));
+            }
+            let low_watermark = self.low_watermark;
+            self.poll_drain(cx, low_watermark)
+        })
+        .await
+    }
+
+    fn update_congestion(&mut self) {
+        if self.buffer.len() > self.high_watermark {
+            self.congested = true;
+        } else if self.buffer.len() <= self.low_watermark {
+            self.congested = false;
+        }
+    }
+
+    // Writes buffered data until no more than `target` bytes are left.
+    fn poll_drain(&mut self, cx: &mut Context<'_>, target: usize) -> Poll<io::Result<()>> {
+        while self.buffer.len() > target {
+            let (data, _) = self.buffer.as_slices();
+            let res = Pin::new(&mut self.stream).poll_write(cx, data);
+            match res {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                Poll::Ready(Ok(written)) => {
+                    self.buffer.drain(..written);
+                    if let Some(budget) = &self.budget {
+                        budget.release(written);
+                    }
+                    self.update_congestion();
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SendQueue<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.congested {
+            let low_watermark = self.low_watermark;
+            futures::ready!(self.poll_drain(cx, low_watermark))?;
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let len = loop {
+            let this = &mut *self;
+            let budget = match &this.budget {
+                Some(budget) => budget,
+                None => break buf.len(),
+            };
+            if budget.available() == 0 && !this.buffer.is_empty() {
+                // Writing out what is buffered gives memory back to the budget
+                futures::ready!(this.poll_drain(cx, 0))?;
+                continue;
+            }
+            futures::ready!(budget.poll_fit(cx, 1, &mut this.ticket));
+            break cmp::min(buf.len(), budget.available());
+        };
+        self.enqueue(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.poll_drain(cx, 0))?;
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.poll_drain(cx, 0))?;
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Async, BudgetExceeded, MemoryBudget};
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn send_queue_watermarks() {
+        test_executor!(async move {
+            let (writer, mut reader) = Async::<UnixStream>::pair().unwrap();
+            let mut queue = SendQueue::new(writer, 1024, 4096);
+
+            let chunk = [1u8; 1024];
+            for _ in 0..4 {
+                assert!(!queue.enqueue(&chunk));
+            }
+            assert!(queue.enqueue(&chunk));
+            assert_eq!(queue.buffered(), 5120);
+
+            queue.writable_again().await.unwrap();
+            assert!(!queue.is_congested());
+            assert!(queue.buffered() <= 1024);
+
+            queue.write_all(&chunk).await.unwrap();
+            queue.flush().await.unwrap();
+            assert_eq!(queue.buffered(), 0);
+
+            let mut buf = vec![0u8; 6144];
+            reader.read_exact(&mut buf).await.unwrap();
+            assert!(buf.iter().all(|x| *x == 1));
+        });
+    }
+
+    #[test]
+    fn send_queue_budget() {
+        test_executor!(async move {
+            let (writer, mut reader) = Async::<UnixStream>::pair().unwrap();
+            let budget = MemoryBudget::new(2048);
+            let mut queue = SendQueue::new(writer, 1024, 4096);
+            queue.set_budget(budget.connection());
+
+            let chunk = [1u8; 1024];
+            assert!(!queue.try_enqueue(&chunk).unwrap());
+            assert!(!queue.try_enqueue(&chunk).unwrap());
+            assert_eq!(budget.used(), 2048);
+            let err = queue.try_enqueue(&chunk).unwrap_err();
+            assert!(err.get_ref().unwrap().is::<BudgetExceeded>());
+            assert_eq!(queue.buffered(), 2048);
+
+            // writes wait for the queue to drain to fit in the budget
+            queue.write_all(&chunk).await.unwrap();
+            assert_eq!(budget.used(), 1024);
+            queue.flush().await.unwrap();
+            assert_eq!(budget.used(), 0);
+
+            // and are cut short to what fits
+            queue.try_enqueue(&chunk).unwrap();
+            assert_eq!(queue.write(&[1u8; 4096]).await.unwrap(), 1024);
+            queue.flush().await.unwrap();
+
+            let mut buf = vec![0u8; 5120];
+            reader.read_exact(&mut buf).await.unwrap();
+            drop(queue);
+            assert_eq!(budget.connections(), 0);
+        });
+    }
+
+    #[test]
+    fn send_queue_waits_for_the_total_budget() {
+        test_executor!(async move {
+            let budget = MemoryBudget::with_total_limit(4096, 2048);
+            let (first, mut first_reader) = Async::<UnixStream>::pair().unwrap();
+            let (second, mut second_reader) = Async::<UnixStream>::pair().unwrap();
+            let mut first = SendQueue::new(first, 4096, 4096);
+            first.set_budget(budget.connection());
+            let mut second = SendQueue::new(second, 4096, 4096);
+            second.set_budget(budget.connection());
+
+            first.enqueue(&[1u8; 2048]);
+            let writer = crate::Local::local(async move {
+                // Nothing fits until the first queue gives its memory back
+                let written = second.write(&[2u8; 1024]).await.unwrap();
+                second.flush().await.unwrap();
+                written
+            });
+            crate::Local::later().await;
+            assert_eq!(budget.used(), 2048);
+
+            first.flush().await.unwrap();
+            assert_eq!(writer.await, 1024);
+            assert_eq!(budget.used(), 0);
+
+            let mut buf = vec![0u8; 2048];
+            first_reader.read_exact(&mut buf).await.unwrap();
+            let mut buf = vec![0u8; 1024];
+            second_reader.read_exact(&mut buf).await.unwrap();
+            assert!(buf.iter().all(|x| *x == 2));
+        });
+    }
+}
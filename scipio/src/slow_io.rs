@@ -0,0 +1,301 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+// Nothing is recorded or reported without the `io-tracing` feature
+#![cfg_attr(not(feature = "io-tracing"), allow(dead_code))]
+use crate::TaskQueueHandle;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(Default)]
+struct SlowIoWatch {
+    threshold: Cell<Option<Duration>>,
+    handler: RefCell<Option<Rc<dyn Fn(&SlowIo)>>>,
+    // Reported once the reactor is done processing completions, so handlers are free to
+    // submit I/O of their own
+    pending: RefCell<Vec<SlowIo>>,
+    reported: Cell<u64>,
+}
+
+thread_local!(static SLOW_IO: SlowIoWatch = SlowIoWatch::default());
+
+pub(crate) fn set_threshold(threshold: Option<Duration>) {
+    SLOW_IO.with(|watch| watch.threshold.set(threshold));
+}
+
+pub(crate) fn threshold() -> Option<Duration> {
+    SLOW_IO.with(|watch| watch.threshold.get())
+}
+
+pub(crate) fn set_handler(handler: Option<Rc<dyn Fn(&SlowIo)>>) {
+    SLOW_IO.with(|watch| *watch.handler.borrow_mut() = handler);
+}
+
+pub(crate) fn reported() -> u64 {
+    SLOW_IO.with(|watch| watch.reported.get())
+}
+
+/// Whether operations have to be timed to find out if they are slow
+pub(crate) fn enabled() -> bool {
+    threshold().is_some()
+}
+
+pub(crate) fn is_slow(took: Duration) -> bool {
+    // try_with: the reactor's own sources complete while the thread goes away
+    SLOW_IO
+        .try_with(|watch| match watch.threshold.get() {
+            Some(threshold) => took >= threshold,
+            None => false,
+        })
+        .unwrap_or(false)
+}
+
+pub(crate) fn report(slow: SlowIo) {
+    SLOW_IO.with(|watch| watch.pending.borrow_mut().push(slow));
+}
+
+/// Hands the operations found to be slow since the last call to the handler, or prints
+/// them if there is none.
+pub(crate) fn flush() {
+    let (pending, handler) = SLOW_IO.with(|watch| {
+        let pending = std::mem::replace(&mut *watch.pending.borrow_mut(), Vec::new());
+        watch
+            .reported
+            .set(watch.reported.get() + pending.len() as u64);
+        (pending, watch.handler.borrow().clone())
+    });
+    for slow in pending {
+        match &handler {
+            Some(handler) => handler(&slow),
+            None => eprintln!("{}", slow),
+        }
+    }
+}
+
+/// An I/O operation that took longer than the threshold set with
+/// [`LocalExecutor::set_slow_io_threshold`] to complete, as reported to the handler set
+/// with [`LocalExecutor::on_slow_io`].
+///
+/// The time it took is split between the time it waited in the executor to be submitted
+/// to the kernel, and the time the kernel took to complete it after that.
+///
+/// [`LocalExecutor::set_slow_io_threshold`]: struct.LocalExecutor.html#method.set_slow_io_threshold
+/// [`LocalExecutor::on_slow_io`]: struct.LocalExecutor.html#method.on_slow_io
+#[derive(Debug, Clone)]
+pub struct SlowIo {
+    operation: &'static str,
+    fd: RawFd,
+    path: Option<PathBuf>,
+    peer: Option<SocketAddr>,
+    result: Result<usize, io::ErrorKind>,
+    queue: TaskQueueHandle,
+    tag: Option<u64>,
+    ring: &'static str,
+    queued: Duration,
+    in_kernel: Duration,
+}
+
+// The address of the other end, if the descriptor is a connected socket
+pub(crate) fn fd_peer(fd: RawFd) -> Option<SocketAddr> {
+    if fd < 0 {
+        return None;
+    }
+    // getpeername() works on any socket, and fails on anything else
+    let stream = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(fd) });
+    stream.peer_addr().ok()
+}
+
+impl SlowIo {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        operation: &'static str,
+        fd: RawFd,
+        path: Option<PathBuf>,
+        result: &io::Result<usize>,
+        queue: usize,
+        tag: Option<u64>,
+        ring: &'static str,
+        queued: Duration,
+        in_kernel: Duration,
+    ) -> SlowIo {
+        SlowIo {
+            operation,
+            fd,
+            path,
+            peer: fd_peer(fd),
+            result: match result {
+                Ok(bytes) => Ok(*bytes),
+                Err(err) => Err(err.kind()),
+            },
+            queue: TaskQueueHandle::from_index(queue),
+            tag,
+            ring,
+            queued,
+            in_kernel,
+        }
+    }
+
+    /// What the operation was, like "read", "write" or "poll"
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// The file descriptor the operation was on, or -1 if there was none, like for opens
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// The path of the file the operation was on, if it was on a file
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The address of the other end, if the operation was on a connected socket
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// The result of the operation: how many bytes were transferred for reads and
+    /// writes, or the kind of error it failed with.
+    pub fn result(&self) -> Result<usize, io::ErrorKind> {
+        self.result
+    }
+
+    /// The task queue whose task submitted the operation
+    pub fn queue(&self) -> TaskQueueHandle {
+        self.queue
+    }
+
+    /// The tag of the operation, if it was submitted within [`Local::with_io_tag`]
+    ///
+    /// [`Local::with_io_tag`]: type.Local.html#method.with_io_tag
+    pub fn tag(&self) -> Option<u64> {
+        self.tag
+    }
+
+    /// The name of the ring the operation went through, like "main" or "latency"
+    pub fn ring(&self) -> &'static str {
+        self.ring
+    }
+
+    /// How long the operation waited in the executor before being submitted to the kernel
+    pub fn queued(&self) -> Duration {
+        self.queued
+    }
+
+    /// How long the kernel took to complete the operation once submitted
+    pub fn in_kernel(&self) -> Duration {
+        self.in_kernel
+    }
+
+    /// How long the operation took overall
+    pub fn took(&self) -> Duration {
+        self.queued + self.in_kernel
+    }
+}
+
+impl fmt::Display for SlowIo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "slow {} on fd {}", self.operation, self.fd)?;
+        if let Some(path) = &self.path {
+            write!(f, " ({})", path.display())?;
+        }
+        if let Some(peer) = &self.peer {
+            write!(f, " (peer {})", peer)?;
+        }
+        match self.result {
+            Ok(bytes) => write!(f, ": {} bytes", bytes)?,
+            Err(kind) => write!(f, ": failed with {:?}", kind)?,
+        }
+        write!(
+            f,
+            " in {:?} ({:?} queued, {:?} in the {} ring), task queue {:?}",
+            self.took(),
+            self.queued,
+            self.in_kernel,
+            self.ring,
+            self.queue
+        )?;
+        if let Some(tag) = self.tag {
+            write!(f, ", tag {:#x}", tag)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "io-tracing"))]
+mod test {
+    use crate::{Async, LocalExecutor};
+    use std::cell::RefCell;
+    use std::net::{TcpListener, TcpStream};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn slow_io_is_reported() {
+        let local_ex = LocalExecutor::new(None).unwrap();
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let r = reports.clone();
+        local_ex.on_slow_io(move |slow| r.borrow_mut().push(slow.clone()));
+        // everything is slow
+        local_ex.set_slow_io_threshold(Some(Duration::from_nanos(0)));
+        assert_eq!(local_ex.slow_io_threshold(), Some(Duration::from_nanos(0)));
+
+        let addr = local_ex.run(async {
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+            let accept = crate::Local::local(async move {
+                listener.accept().await.unwrap();
+            });
+            crate::Local::with_io_tag(42, async move {
+                Async::<TcpStream>::connect(addr).await.unwrap();
+            })
+            .await;
+            accept.await;
+            addr
+        });
+
+        let reports = reports.borrow();
+        assert!(!reports.is_empty());
+        assert_eq!(local_ex.slow_io_reported(), reports.len() as u64);
+        let connect = reports.iter().find(|slow| slow.tag() == Some(42)).unwrap();
+        assert_eq!(connect.operation(), "poll");
+        assert_eq!(connect.peer(), Some(addr));
+        assert!(connect.took() >= connect.in_kernel());
+        assert!(connect.to_string().contains("tag 0x2a"));
+    }
+
+    #[test]
+    fn slow_file_io_is_reported_with_its_path() {
+        let local_ex = LocalExecutor::new(None).unwrap();
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let r = reports.clone();
+        local_ex.on_slow_io(move |slow| r.borrow_mut().push(slow.clone()));
+        local_ex.set_slow_io_threshold(Some(Duration::from_nanos(0)));
+
+        let path = std::env::temp_dir().join("slow_file_io_is_reported_with_its_path");
+        let file_path = path.clone();
+        local_ex.run(async move {
+            let mut file = crate::DmaFile::create(&file_path).await.unwrap();
+            file.fdatasync().await.unwrap();
+            file.close().await.unwrap();
+        });
+        std::fs::remove_file(&path).unwrap();
+
+        let reports = reports.borrow();
+        let sync = reports
+            .iter()
+            .find(|slow| slow.operation() == "fdatasync")
+            .unwrap();
+        assert_eq!(sync.path(), Some(path.as_path()));
+    }
+}
@@ -0,0 +1,328 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::executor::ShardExit;
+use crate::{ExecutorConfig, LocalExecutor, PoolConfig, WatchdogReport, WatchdogTerminated};
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{Builder, JoinHandle};
+
+/// Why a supervised shard died
+#[derive(Debug, Clone)]
+pub enum ShardFailure {
+    /// Something running in the shard panicked, with this message
+    Panicked(String),
+    /// The watchdog of the shard terminated it for being stuck, with this last report.
+    /// See [`WatchdogAction::Terminate`]
+    ///
+    /// [`WatchdogAction::Terminate`]: enum.WatchdogAction.html#variant.Terminate
+    Stalled(WatchdogReport),
+}
+
+impl ShardFailure {
+    fn from_payload(payload: Box<dyn Any + Send>) -> ShardFailure {
+        if let Some(terminated) = payload.downcast_ref::<WatchdogTerminated>() {
+            return ShardFailure::Stalled(terminated.report.clone());
+        }
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast_ref::<&'static str>() {
+                Some(message) => message.to_string(),
+                None => "<unknown panic>".to_string(),
+            },
+        };
+        ShardFailure::Panicked(message)
+    }
+}
+
+impl fmt::Display for ShardFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardFailure::Panicked(message) => write!(f, "panicked: {}", message),
+            ShardFailure::Stalled(report) => write!(f, "terminated by the watchdog: {}", report),
+        }
+    }
+}
+
+/// What a shard of a [`Supervisor`] is started for, as handed to the factory of its
+/// future.
+///
+/// [`Supervisor`]: struct.Supervisor.html
+#[derive(Debug, Clone)]
+pub struct ShardStart {
+    shard_id: usize,
+    restarts: u64,
+    failure: Option<ShardFailure>,
+}
+
+impl ShardStart {
+    /// The shard id of the executor, as returned by [`Local::shard_id`]
+    ///
+    /// [`Local::shard_id`]: type.Local.html#method.shard_id
+    pub fn shard_id(&self) -> usize {
+        self.shard_id
+    }
+
+    /// How many times the shard was restarted so far, 0 the first time it starts
+    pub fn restarts(&self) -> u64 {
+        self.restarts
+    }
+
+    /// Why the previous executor of the shard died, if this is a restart. The state it
+    /// left behind has to be recovered from wherever it was kept outside of it.
+    pub fn failure(&self) -> Option<&ShardFailure> {
+        self.failure.as_ref()
+    }
+}
+
+// Everything needed to start the shards of a pool, again and again
+struct Shards<G> {
+    name: String,
+    configs: Vec<ExecutorConfig>,
+    factory: Arc<G>,
+    exited: mpsc::Sender<ShardExit>,
+}
+
+impl<G, F, T> Shards<G>
+where
+    G: Fn(ShardStart) -> F + Send + Sync + 'static,
+    F: Future<Output = T> + 'static,
+{
+    // Spawns a shard, which waits for `gate` before running, if given
+    fn spawn(
+        &self,
+        start: ShardStart,
+        gate: Option<mpsc::Receiver<()>>,
+    ) -> io::Result<JoinHandle<()>> {
+        let factory = self.factory.clone();
+        let shard_id = start.shard_id;
+        LocalExecutor::spawn_supervised_shard(
+            &self.name,
+            &self.configs,
+            shard_id,
+            move || factory(start),
+            self.exited.clone(),
+            gate,
+        )
+        .map(|(handle, _)| handle)
+    }
+}
+
+/// Runs a pool of executors, one per shard, and restarts the shards that die.
+///
+/// A shard dies when something running in it panics, or when its watchdog terminates
+/// it for being stuck. Its executor is then created again, in a new thread, bound to the
+/// same CPU and with the same task queues, and runs a new future created by the factory
+/// the supervisor was given. The factory learns with [`ShardStart`] which shard it
+/// creates a future for and why the previous one died, so it can recover whatever state
+/// the shard keeps outside of the executor. The other shards keep running meanwhile.
+///
+/// Shards that complete their future are not restarted. A shard that dies more than
+/// `max_restarts` times is given up on, and so is one whose executor can't be created
+/// again.
+///
+/// Panics are only survivable if they unwind: with `panic = "abort"` the whole process
+/// goes down with the shard, as usual.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{ExecutorConfig, PoolConfig, Supervisor};
+///
+/// let config = PoolConfig {
+///     name: "shard".to_string(),
+///     executors: vec![ExecutorConfig::default(); 2],
+/// };
+/// let supervisor = Supervisor::spawn(config, 3, |start| async move {
+///     if start.shard_id() == 1 && start.restarts() == 0 {
+///         panic!("shard 1 crashes once");
+///     }
+/// })
+/// .unwrap();
+///
+/// supervisor.join().unwrap();
+/// ```
+///
+/// [`ShardStart`]: struct.ShardStart.html
+#[derive(Debug)]
+pub struct Supervisor {
+    restarts: Arc<AtomicU64>,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+impl Supervisor {
+    /// Validates `config`, starts its shards, each running a future created by
+    /// `factory`, and watches over them from a thread of its own.
+    ///
+    /// Fails if the configuration is not valid or the shards couldn't be started.
+    pub fn spawn<G, F, T>(
+        config: PoolConfig,
+        max_restarts: u64,
+        factory: G,
+    ) -> io::Result<Supervisor>
+    where
+        G: Fn(ShardStart) -> F + Send + Sync + 'static,
+        F: Future<Output = T> + 'static,
+    {
+        config.validate()?;
+        let (exited, exits) = mpsc::channel();
+        let shards = Shards {
+            name: config.name.clone(),
+            configs: config.executors,
+            factory: Arc::new(factory),
+            exited,
+        };
+        let started = LocalExecutor::start_together(shards.configs.len(), |shard_id, gate| {
+            let start = ShardStart {
+                shard_id,
+                restarts: 0,
+                failure: None,
+            };
+            shards.spawn(start, Some(gate)).map(|handle| (handle, ()))
+        })?;
+        let mut handles: Vec<_> = started
+            .into_iter()
+            .map(|(handle, _)| Some(handle))
+            .collect();
+
+        let restarts = Arc::new(AtomicU64::new(0));
+        let total = restarts.clone();
+        let thread = Builder::new()
+            .name(format!("{}-supervisor", config.name))
+            .spawn(move || {
+                let mut running = handles.len();
+                let mut counts = vec![0; handles.len()];
+                let mut error = None;
+                while running > 0 {
+                    // Never fails: `shards` holds a sender
+                    let (shard_id, result) = exits.recv().unwrap();
+                    if let Some(handle) = handles[shard_id].take() {
+                        let _ = handle.join();
+                    }
+                    let failure = match result {
+                        Ok(()) => {
+                            running -= 1;
+                            continue;
+                        }
+                        Err(payload) => ShardFailure::from_payload(payload),
+                    };
+                    if counts[shard_id] >= max_restarts {
+                        error = Some(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!(
+                                "shard {} died {} times, last {}",
+                                shard_id,
+                                counts[shard_id] + 1,
+                                failure
+                            ),
+                        ));
+                        running -= 1;
+                        continue;
+                    }
+                    counts[shard_id] += 1;
+                    total.fetch_add(1, Ordering::Relaxed);
+                    let start = ShardStart {
+                        shard_id,
+                        restarts: counts[shard_id],
+                        failure: Some(failure),
+                    };
+                    match shards.spawn(start, None) {
+                        Ok(handle) => handles[shard_id] = Some(handle),
+                        Err(err) => {
+                            error = Some(err);
+                            running -= 1;
+                        }
+                    }
+                }
+                error.map_or(Ok(()), Err)
+            })?;
+
+        Ok(Supervisor { restarts, thread })
+    }
+
+    /// How many times shards were restarted so far
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Waits until every shard completed its future or was given up on.
+    ///
+    /// Fails with the reason the last shard that was given up on couldn't go on.
+    pub fn join(self) -> io::Result<()> {
+        self.thread
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "the supervisor panicked"))?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Local, WatchdogAction};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    fn pool(shards: usize) -> PoolConfig {
+        PoolConfig {
+            name: "supervised".to_string(),
+            executors: vec![ExecutorConfig::default(); shards],
+        }
+    }
+
+    #[test]
+    fn supervisor_restarts_dead_shards() {
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let s = starts.clone();
+        let supervisor = Supervisor::spawn(pool(3), 2, move |start| {
+            s.lock().unwrap().push(start.clone());
+            async move {
+                assert_eq!(Local::shard_id(), start.shard_id());
+                assert_eq!(Local::shard_count(), 3);
+                match (start.shard_id(), start.restarts()) {
+                    (1, 0) => panic!("crash"),
+                    (2, 0) => {
+                        Local::set_watchdog(Duration::from_millis(20), |_| {
+                            WatchdogAction::Terminate
+                        })
+                        .unwrap();
+                        std::thread::sleep(Duration::from_millis(100));
+                        Local::later().await;
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .unwrap();
+        supervisor.join().unwrap();
+
+        let starts = starts.lock().unwrap();
+        assert_eq!(starts.len(), 5);
+        let restarted: Vec<_> = starts.iter().filter(|s| s.restarts() == 1).collect();
+        assert_eq!(restarted.len(), 2);
+        for start in restarted {
+            match (start.shard_id(), start.failure().unwrap()) {
+                (1, ShardFailure::Panicked(message)) => assert_eq!(message, "crash"),
+                (2, ShardFailure::Stalled(report)) => assert_eq!(report.shard_id, 2),
+                (shard_id, failure) => panic!("shard {} {}", shard_id, failure),
+            }
+        }
+    }
+
+    #[test]
+    fn supervisor_gives_up_on_crash_loops() {
+        let supervisor = Supervisor::spawn(pool(2), 2, |start| async move {
+            if start.shard_id() == 0 {
+                panic!("always");
+            }
+        })
+        .unwrap();
+        let err = supervisor.join().unwrap_err();
+        assert!(err.to_string().contains("shard 0 died 3 times"));
+    }
+}
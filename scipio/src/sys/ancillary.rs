@@ -0,0 +1,433 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::RawFd;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use socket2::SockAddr;
+
+// Large enough for all the control messages we know about at once.
+// Kept as u64 so the control messages are properly aligned.
+const CONTROL_BUFFER_WORDS: usize = 32;
+
+// UDP segmentation offload options, from linux/udp.h
+pub(crate) const UDP_SEGMENT: libc::c_int = 103;
+pub(crate) const UDP_GRO: libc::c_int = 104;
+
+// From asm-generic/socket.h. SCM_TIMESTAMPING shares its value with SO_TIMESTAMPING.
+pub(crate) const SO_MAX_PACING_RATE: libc::c_int = 47;
+const SO_TIMESTAMPING: libc::c_int = 37;
+
+// Timestamping flags, from linux/net_tstamp.h
+const SOF_TIMESTAMPING_TX_HARDWARE: u32 = 1 << 0;
+const SOF_TIMESTAMPING_TX_SOFTWARE: u32 = 1 << 1;
+const SOF_TIMESTAMPING_RX_HARDWARE: u32 = 1 << 2;
+const SOF_TIMESTAMPING_RX_SOFTWARE: u32 = 1 << 3;
+const SOF_TIMESTAMPING_SOFTWARE: u32 = 1 << 4;
+const SOF_TIMESTAMPING_RAW_HARDWARE: u32 = 1 << 6;
+const SOF_TIMESTAMPING_OPT_ID: u32 = 1 << 7;
+const SOF_TIMESTAMPING_OPT_TSONLY: u32 = 1 << 11;
+
+// Flags generating receive and transmit timestamps. Transmit timestamps are numbered, and
+// come back on the error queue without a copy of the datagram.
+pub(crate) const RECV_TIMESTAMPING: u32 =
+    SOF_TIMESTAMPING_RX_SOFTWARE | SOF_TIMESTAMPING_RX_HARDWARE;
+pub(crate) const SEND_TIMESTAMPING: u32 = SOF_TIMESTAMPING_TX_SOFTWARE
+    | SOF_TIMESTAMPING_TX_HARDWARE
+    | SOF_TIMESTAMPING_OPT_ID
+    | SOF_TIMESTAMPING_OPT_TSONLY;
+
+// From linux/errqueue.h
+const SO_EE_ORIGIN_TIMESTAMPING: u8 = 4;
+
+// Not exported by all libc versions we build with.
+#[repr(C)]
+struct In6Pktinfo {
+    ipi6_addr: libc::in6_addr,
+    ipi6_ifindex: libc::c_uint,
+}
+
+#[repr(C)]
+struct SockExtendedErr {
+    ee_errno: u32,
+    ee_origin: u8,
+    ee_type: u8,
+    ee_code: u8,
+    ee_pad: u8,
+    ee_info: u32,
+    ee_data: u32,
+}
+
+// The software timestamp, a deprecated one that is always zero, and the hardware one.
+#[repr(C)]
+struct ScmTimestamping {
+    ts: [libc::timespec; 3],
+}
+
+/// Ancillary data received together with a datagram.
+///
+/// Each field is only present if the corresponding socket option was enabled
+/// before the datagram arrived.
+#[derive(Debug, Clone, Default)]
+pub struct RecvMeta {
+    pub(crate) timestamp: Option<SystemTime>,
+    pub(crate) hardware_timestamp: Option<SystemTime>,
+    pub(crate) destination: Option<IpAddr>,
+    pub(crate) tos: Option<u8>,
+    pub(crate) segment_size: Option<usize>,
+}
+
+impl RecvMeta {
+    /// The time at which the kernel received the datagram.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.timestamp
+    }
+
+    /// The time at which the network card received the datagram, by its own clock. Only
+    /// present if the card supports timestamping, and it was turned on for the device.
+    pub fn hardware_timestamp(&self) -> Option<SystemTime> {
+        self.hardware_timestamp
+    }
+
+    /// The local address the datagram was sent to.
+    pub fn destination(&self) -> Option<IpAddr> {
+        self.destination
+    }
+
+    /// The TOS byte (IPv4) or traffic class (IPv6) of the datagram.
+    pub fn tos(&self) -> Option<u8> {
+        self.tos
+    }
+
+    /// The ECN codepoint of the datagram: the two low bits of its TOS byte.
+    pub fn ecn(&self) -> Option<u8> {
+        self.tos.map(|tos| tos & 0b11)
+    }
+
+    /// If the kernel coalesced many datagrams into this one, the size of each of them.
+    /// Only the last one may be shorter.
+    pub fn segment_size(&self) -> Option<usize> {
+        self.segment_size
+    }
+}
+
+/// When a datagram sent with transmit timestamps enabled left the host.
+#[derive(Debug, Clone, Default)]
+pub struct SendTimestamp {
+    id: u32,
+    timestamp: Option<SystemTime>,
+    hardware_timestamp: Option<SystemTime>,
+}
+
+impl SendTimestamp {
+    /// Which datagram this is about: the datagrams sent since transmit timestamps were
+    /// enabled are numbered from zero.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The time at which the kernel handed the datagram to the network card.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.timestamp
+    }
+
+    /// The time at which the network card sent the datagram, by its own clock. Only
+    /// present if the card supports timestamping, and it was turned on for the device.
+    pub fn hardware_timestamp(&self) -> Option<SystemTime> {
+        self.hardware_timestamp
+    }
+}
+
+/// Ancillary data to be attached to an outgoing datagram.
+#[derive(Debug, Clone, Default)]
+pub struct SendMeta {
+    source: Option<IpAddr>,
+    tos: Option<u8>,
+    segment_size: Option<u16>,
+}
+
+impl SendMeta {
+    /// Creates an empty set of ancillary data.
+    pub fn new() -> SendMeta {
+        SendMeta::default()
+    }
+
+    /// Sends the datagram from `source`, which must be one of the local addresses.
+    pub fn source(mut self, source: IpAddr) -> SendMeta {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the TOS byte (IPv4) or traffic class (IPv6) of the datagram.
+    pub fn tos(mut self, tos: u8) -> SendMeta {
+        self.tos = Some(tos);
+        self
+    }
+
+    /// Sets the ECN codepoint of the datagram, keeping the rest of its TOS byte.
+    pub fn ecn(mut self, ecn: u8) -> SendMeta {
+        let tos = self.tos.unwrap_or(0) & !0b11;
+        self.tos = Some(tos | (ecn & 0b11));
+        self
+    }
+
+    /// Asks the kernel to split the buffer into datagrams of `segment_size` bytes each,
+    /// with only the last one allowed to be shorter.
+    pub fn segment_size(mut self, segment_size: u16) -> SendMeta {
+        self.segment_size = Some(segment_size);
+        self
+    }
+}
+
+pub(crate) fn set_socket_option(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    syscall!(setsockopt(
+        fd,
+        level,
+        name,
+        &value as *const libc::c_int as *const libc::c_void,
+        std::mem::size_of::<libc::c_int>() as libc::socklen_t
+    ))?;
+    Ok(())
+}
+
+fn get_socket_option(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    syscall!(getsockopt(
+        fd,
+        level,
+        name,
+        &mut value as *mut libc::c_int as *mut libc::c_void,
+        &mut len
+    ))?;
+    Ok(value)
+}
+
+// Turns the timestamps generated by `flags` on or off, leaving the others as they are.
+pub(crate) fn set_timestamping(fd: RawFd, flags: u32, enabled: bool) -> io::Result<()> {
+    let mut current = get_socket_option(fd, libc::SOL_SOCKET, SO_TIMESTAMPING)? as u32;
+    if enabled {
+        current |= flags;
+    } else {
+        current &= !flags;
+    }
+    // report both kinds of timestamps for as long as any are generated
+    let reporting = SOF_TIMESTAMPING_SOFTWARE | SOF_TIMESTAMPING_RAW_HARDWARE;
+    if current & (RECV_TIMESTAMPING | SEND_TIMESTAMPING) != 0 {
+        current |= reporting;
+    } else {
+        current &= !reporting;
+    }
+    set_socket_option(fd, libc::SOL_SOCKET, SO_TIMESTAMPING, current as _)
+}
+
+fn system_time(ts: &libc::timespec) -> Option<SystemTime> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+fn socket_addr(addr: &SockAddr) -> io::Result<SocketAddr> {
+    addr.as_inet()
+        .map(SocketAddr::V4)
+        .or_else(|| addr.as_inet6().map(SocketAddr::V6))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported address family"))
+}
+
+unsafe fn write_control_message<T>(
+    cmsg: *mut libc::cmsghdr,
+    level: libc::c_int,
+    ty: libc::c_int,
+    value: T,
+) -> usize {
+    let size = std::mem::size_of::<T>() as u32;
+    (*cmsg).cmsg_level = level;
+    (*cmsg).cmsg_type = ty;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(size) as _;
+    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut T, value);
+    libc::CMSG_SPACE(size) as usize
+}
+
+pub(crate) fn recv_msg(sock: RawFd, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, RecvMeta)> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut control = [0u64; CONTROL_BUFFER_WORDS];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut storage as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as _;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let received = syscall!(recvmsg(sock, &mut msg, 0))? as usize;
+    let addr = unsafe {
+        SockAddr::from_raw_parts(
+            &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+            msg.msg_namelen,
+        )
+    };
+
+    let mut meta = RecvMeta::default();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::SOL_SOCKET, SO_TIMESTAMPING) => {
+                    let stamps = std::ptr::read_unaligned(data as *const ScmTimestamping);
+                    meta.timestamp = system_time(&stamps.ts[0]);
+                    meta.hardware_timestamp = system_time(&stamps.ts[2]);
+                }
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info = std::ptr::read_unaligned(data as *const libc::in_pktinfo);
+                    let ip = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+                    meta.destination = Some(IpAddr::V4(ip));
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    let info = std::ptr::read_unaligned(data as *const In6Pktinfo);
+                    let ip = Ipv6Addr::from(info.ipi6_addr.s6_addr);
+                    meta.destination = Some(IpAddr::V6(ip));
+                }
+                (libc::IPPROTO_IP, libc::IP_TOS) => {
+                    meta.tos = Some(*data);
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    let tclass = std::ptr::read_unaligned(data as *const libc::c_int);
+                    meta.tos = Some(tclass as u8);
+                }
+                (libc::IPPROTO_UDP, UDP_GRO) => {
+                    let segment_size = std::ptr::read_unaligned(data as *const libc::c_int);
+                    meta.segment_size = Some(segment_size as usize);
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((received, socket_addr(&addr)?, meta))
+}
+
+// Reads the next transmit timestamp off the error queue of `sock`.
+pub(crate) fn recv_send_timestamp(sock: RawFd) -> io::Result<SendTimestamp> {
+    let mut control = [0u64; CONTROL_BUFFER_WORDS];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    syscall!(recvmsg(sock, &mut msg, libc::MSG_ERRQUEUE))?;
+
+    let mut stamp = SendTimestamp::default();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::SOL_SOCKET, SO_TIMESTAMPING) => {
+                    let stamps = std::ptr::read_unaligned(data as *const ScmTimestamping);
+                    stamp.timestamp = system_time(&stamps.ts[0]);
+                    stamp.hardware_timestamp = system_time(&stamps.ts[2]);
+                }
+                (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+                    let err = std::ptr::read_unaligned(data as *const SockExtendedErr);
+                    if err.ee_origin != SO_EE_ORIGIN_TIMESTAMPING {
+                        // an error about an earlier datagram rather than a timestamp
+                        return Err(io::Error::from_raw_os_error(err.ee_errno as i32));
+                    }
+                    stamp.id = err.ee_data;
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok(stamp)
+}
+
+pub(crate) fn send_msg(
+    sock: RawFd,
+    buf: &[u8],
+    addr: SocketAddr,
+    meta: &SendMeta,
+) -> io::Result<usize> {
+    let is_ipv6 = addr.is_ipv6();
+    let addr = SockAddr::from(addr);
+    let mut control = [0u64; CONTROL_BUFFER_WORDS];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = addr.as_ptr() as *mut libc::c_void;
+    msg.msg_namelen = addr.len();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let mut control_len = 0;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if let Some(tos) = meta.tos {
+            let tos = tos as libc::c_int;
+            control_len += if is_ipv6 {
+                write_control_message(cmsg, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)
+            } else {
+                write_control_message(cmsg, libc::IPPROTO_IP, libc::IP_TOS, tos)
+            };
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        match meta.source {
+            Some(IpAddr::V4(ip)) => {
+                let info = libc::in_pktinfo {
+                    ipi_ifindex: 0,
+                    ipi_spec_dst: libc::in_addr {
+                        s_addr: u32::from(ip).to_be(),
+                    },
+                    ipi_addr: libc::in_addr { s_addr: 0 },
+                };
+                control_len +=
+                    write_control_message(cmsg, libc::IPPROTO_IP, libc::IP_PKTINFO, info);
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            Some(IpAddr::V6(ip)) => {
+                let info = In6Pktinfo {
+                    ipi6_addr: libc::in6_addr {
+                        s6_addr: ip.octets(),
+                    },
+                    ipi6_ifindex: 0,
+                };
+                control_len +=
+                    write_control_message(cmsg, libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, info);
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            None => {}
+        }
+        if let Some(segment_size) = meta.segment_size {
+            control_len +=
+                write_control_message(cmsg, libc::IPPROTO_UDP, UDP_SEGMENT, segment_size);
+        }
+    }
+
+    msg.msg_controllen = control_len as _;
+    if control_len == 0 {
+        msg.msg_control = std::ptr::null_mut();
+    }
+    let sent = syscall!(sendmsg(sock, &msg, 0))?;
+    Ok(sent as usize)
+}
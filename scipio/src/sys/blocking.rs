@@ -0,0 +1,184 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+//! Helper threads for the system calls that can block for long and have no asynchronous
+//! counterpart in the rings, so they don't stall every task of the executor.
+//!
+//! The helpers are spawned by the reactor thread, after it unshared its file descriptor
+//! table, so they share that table: file descriptors mean the same to them as to the
+//! executor. They hand results back through an eventfd the reactor polls in its rings,
+//! and the reactor completes the sources the operations were issued for like it
+//! completes those of the kernel.
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// The most helper threads a reactor starts.
+const MAX_HELPERS: usize = 4;
+
+/// How long a helper waits for more work before it exits.
+const HELPER_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Work for a helper: a system call and the source to complete with its result.
+pub(crate) struct BlockingJob {
+    pub(crate) user_data: u64,
+    pub(crate) job: Box<dyn FnOnce() -> io::Result<usize> + Send>,
+}
+
+/// A raw pointer handed to a helper. Whoever issues the job guarantees the memory stays
+/// valid until the job completes, and isn't used by anything else meanwhile.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SendPtr<T>(pub(crate) *mut T);
+
+unsafe impl<T> Send for SendPtr<T> {}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<BlockingJob>,
+    completed: Vec<(u64, io::Result<usize>)>,
+    helpers: usize,
+    idle: usize,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    available: Condvar,
+    // Written by the helpers when they complete a job. They share the file descriptor
+    // table of the reactor, and the last of them to go closes it.
+    eventfd: RawFd,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.eventfd) };
+    }
+}
+
+/// The helper threads of a reactor.
+pub(crate) struct BlockingPool {
+    shared: Arc<Shared>,
+    // Jobs issued and not completed yet
+    outstanding: AtomicUsize,
+}
+
+impl std::fmt::Debug for BlockingPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockingPool")
+            .field("outstanding", &self.outstanding.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl BlockingPool {
+    pub(crate) fn new() -> io::Result<BlockingPool> {
+        let eventfd = syscall!(eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC))?;
+        Ok(BlockingPool {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State::default()),
+                available: Condvar::new(),
+                eventfd,
+            }),
+            outstanding: AtomicUsize::new(0),
+        })
+    }
+
+    /// The eventfd that becomes readable when jobs complete.
+    pub(crate) fn eventfd(&self) -> RawFd {
+        self.shared.eventfd
+    }
+
+    /// Whether jobs were issued that didn't complete yet.
+    pub(crate) fn has_outstanding(&self) -> bool {
+        self.outstanding.load(Ordering::Relaxed) > 0
+    }
+
+    /// Hands `job` to a helper, starting one if they are all busy.
+    pub(crate) fn submit(&self, job: BlockingJob) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queue.push_back(job);
+        if state.idle < state.queue.len() && state.helpers < MAX_HELPERS {
+            let shared = self.shared.clone();
+            let spawned = std::thread::Builder::new()
+                .name("scipio-blocking".into())
+                .spawn(move || helper(shared));
+            match spawned {
+                Ok(_) => state.helpers += 1,
+                // Busy helpers get to it eventually, unless there are none
+                Err(err) if state.helpers == 0 => {
+                    state.queue.pop_back();
+                    return Err(err);
+                }
+                Err(_) => {}
+            }
+        }
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        drop(state);
+        self.shared.available.notify_one();
+        Ok(())
+    }
+
+    /// Takes the results of the jobs that completed since the last call.
+    pub(crate) fn take_completed(&self) -> Vec<(u64, io::Result<usize>)> {
+        if !self.has_outstanding() {
+            return Vec::new();
+        }
+        let mut counter = [0u8; 8];
+        let _ = super::read_fd(self.shared.eventfd, &mut counter);
+        let completed = std::mem::take(&mut self.shared.state.lock().unwrap().completed);
+        self.outstanding
+            .fetch_sub(completed.len(), Ordering::Relaxed);
+        completed
+    }
+}
+
+impl Drop for BlockingPool {
+    fn drop(&mut self) {
+        // Helpers in the middle of a job finish it, but nobody collects the result
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.available.notify_all();
+    }
+}
+
+fn helper(shared: Arc<Shared>) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if state.closed {
+            break;
+        }
+        let job = match state.queue.pop_front() {
+            Some(job) => job,
+            None => {
+                state.idle += 1;
+                let (guard, timeout) = shared
+                    .available
+                    .wait_timeout(state, HELPER_IDLE_TIMEOUT)
+                    .unwrap();
+                state = guard;
+                state.idle -= 1;
+                if timeout.timed_out() && state.queue.is_empty() {
+                    break;
+                }
+                continue;
+            }
+        };
+        drop(state);
+        let result = (job.job)();
+        state = shared.state.lock().unwrap();
+        state.completed.push((job.user_data, result));
+        let value = 1u64;
+        unsafe {
+            libc::write(
+                shared.eventfd,
+                &value as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+    }
+    state.helpers -= 1;
+}
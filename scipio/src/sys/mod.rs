@@ -3,17 +3,23 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::ffi::CString;
 use std::io;
 use std::marker::PhantomPinned;
 use std::mem::ManuallyDrop;
 use std::net::{Shutdown, TcpStream};
+use std::ops::Deref;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{FromRawFd, RawFd};
 use std::path::Path;
 use std::pin::Pin;
+use std::ptr::NonNull;
 use std::task::Waker;
+#[cfg(feature = "io-tracing")]
+use std::time::Instant;
+use std::time::{Duration, SystemTime};
 
 macro_rules! syscall {
     ($fn:ident $args:tt) => {{
@@ -82,14 +88,392 @@ pub(crate) fn duplicate_file(fd: RawFd) -> io::Result<RawFd> {
     syscall!(dup(fd))
 }
 
+/// What the request queue of a block device tells about it in sysfs.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DeviceQueue {
+    /// The largest I/O the device accepts, as configured in max_sectors_kb.
+    pub(crate) max_io_size: Option<usize>,
+    /// Whether the device has a volatile write cache, and whether it supports Force
+    /// Unit Access writes.
+    pub(crate) write_cache: Option<(bool, bool)>,
+}
+
+lazy_static! {
+    // sysfs is read once per device, rather than every time a file is opened
+    static ref DEVICE_QUEUES: std::sync::Mutex<std::collections::HashMap<u64, DeviceQueue>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+// Reads an attribute of the request queue of the block device `dev`
+fn device_queue_attr(dev: &str, attr: &str) -> Option<String> {
+    // Partitions don't have a queue of their own, their disk does
+    ["queue", "../queue"]
+        .iter()
+        .filter_map(|queue| std::fs::read_to_string(format!("{}/{}/{}", dev, queue, attr)).ok())
+        .map(|value| value.trim().to_string())
+        .next()
+}
+
+fn read_device_queue(st_dev: u64) -> DeviceQueue {
+    let (major, minor) = (nix::sys::stat::major(st_dev), nix::sys::stat::minor(st_dev));
+    let dev = format!("/sys/dev/block/{}:{}", major, minor);
+    let max_io_size = device_queue_attr(&dev, "max_sectors_kb")
+        .and_then(|kb| kb.parse::<usize>().ok())
+        .map(|kb| kb << 10);
+    let write_cache = device_queue_attr(&dev, "write_cache").map(|cache| {
+        let fua = device_queue_attr(&dev, "fua").map_or(false, |fua| fua == "1");
+        (cache == "write back", fua)
+    });
+    DeviceQueue {
+        max_io_size,
+        write_cache,
+    }
+}
+
+/// What the request queue of the block device holding `fd` tells about it. Empty if
+/// the file is not backed by a block device.
+///
+/// Only the first file opened on each device pays for reading sysfs.
+pub(crate) fn device_queue(fd: RawFd) -> DeviceQueue {
+    let st_dev = match nix::sys::stat::fstat(fd) {
+        Ok(st) => st.st_dev as u64,
+        Err(_) => return DeviceQueue::default(),
+    };
+    if let Some(queue) = DEVICE_QUEUES.lock().unwrap().get(&st_dev) {
+        return *queue;
+    }
+    let queue = read_device_queue(st_dev);
+    DEVICE_QUEUES.lock().unwrap().insert(st_dev, queue);
+    queue
+}
+
+lazy_static! {
+    static ref ALLOWED_CPUS: Vec<usize> = read_allowed_cpus();
+}
+
+fn read_allowed_cpus() -> Vec<usize> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    if unsafe { libc::sched_getaffinity(0, size, &mut set) } == -1 {
+        return vec![0];
+    }
+    (0..libc::CPU_SETSIZE as usize)
+        .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+        .collect()
+}
+
+/// The CPUs the process may run on, which cgroups, taskset and the like may restrict
+/// to a few of those in the system. The affinity is read the first time this is called,
+/// which happens before any executor binds its thread to a CPU of its own.
+pub(crate) fn allowed_cpus() -> &'static [usize] {
+    &ALLOWED_CPUS
+}
+
+// The address of `name` in the abstract namespace of Unix sockets
+fn abstract_address(name: &[u8]) -> (libc::sockaddr_un, libc::socklen_t) {
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    assert!(name.len() < addr.sun_path.len());
+    // The leading null byte is what puts the name in the abstract namespace
+    for (dst, src) in addr.sun_path[1..].iter_mut().zip(name) {
+        *dst = *src as libc::c_char;
+    }
+    let len = std::mem::size_of::<libc::sa_family_t>() + 1 + name.len();
+    (addr, len as libc::socklen_t)
+}
+
+/// Creates a datagram socket bound to `name` in the abstract namespace of Unix sockets.
+///
+/// Unlike a file descriptor, which only means something in the file descriptor table
+/// it was created in, the name reaches the socket from any thread: see [`send_wakeup`].
+pub(crate) fn bind_wakeup_socket(name: &[u8]) -> io::Result<RawFd> {
+    let fd = syscall!(socket(
+        libc::AF_UNIX,
+        libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+        0
+    ))?;
+    let (addr, len) = abstract_address(name);
+    if let Err(err) = syscall!(bind(fd, &addr as *const _ as *const libc::sockaddr, len)) {
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+// A socket to send wakeups from, created in the file descriptor table of the thread that
+// uses it, and closed with it.
+struct WakeupSender(RawFd);
+
+impl Drop for WakeupSender {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+thread_local!(static WAKEUP_SENDER: RefCell<Option<WakeupSender>> = RefCell::new(None));
+
+fn send_wakeup_from(sock: RawFd, name: &[u8]) -> io::Result<()> {
+    let (addr, len) = abstract_address(name);
+    let byte = 0u8;
+    syscall!(sendto(
+        sock,
+        &byte as *const u8 as *const libc::c_void,
+        1,
+        libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+        &addr as *const _ as *const libc::sockaddr,
+        len
+    ))?;
+    Ok(())
+}
+
+fn wakeup_sender() -> io::Result<WakeupSender> {
+    syscall!(socket(
+        libc::AF_UNIX,
+        libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+        0
+    ))
+    .map(WakeupSender)
+}
+
+/// Sends a datagram to the socket bound to `name` by [`bind_wakeup_socket`], from any
+/// thread, whatever its file descriptor table. Fails with `ECONNREFUSED` if the socket
+/// is gone.
+pub(crate) fn send_wakeup(name: &[u8]) -> io::Result<()> {
+    let cached = WAKEUP_SENDER.try_with(|sender| {
+        let mut sender = sender.borrow_mut();
+        if sender.is_none() {
+            *sender = Some(wakeup_sender()?);
+        }
+        send_wakeup_from(sender.as_ref().unwrap().0, name)
+    });
+    match cached {
+        Ok(res) => res,
+        // The thread is going away and its sender with it: use one just this time
+        Err(_) => send_wakeup_from(wakeup_sender()?.0, name),
+    }
+}
+
+/// Reads the boot time clock, which unlike the monotonic clock keeps counting while the
+/// system is suspended.
+pub(crate) fn boottime() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts);
+    }
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+pub(crate) fn create_timerfd() -> io::Result<RawFd> {
+    syscall!(timerfd_create(
+        libc::CLOCK_MONOTONIC,
+        libc::TFD_NONBLOCK | libc::TFD_CLOEXEC
+    ))
+}
+
+/// Arms a timerfd to expire once, `dur` from now.
+pub(crate) fn arm_timerfd(fd: RawFd, dur: Duration) -> io::Result<()> {
+    // A zero value would disarm the timer instead
+    let dur = std::cmp::max(dur, Duration::from_nanos(1));
+    let spec = libc::itimerspec {
+        it_interval: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        it_value: libc::timespec {
+            tv_sec: dur.as_secs() as libc::time_t,
+            tv_nsec: dur.subsec_nanos() as libc::c_long,
+        },
+    };
+    syscall!(timerfd_settime(fd, 0, &spec, std::ptr::null_mut()))?;
+    Ok(())
+}
+
+pub(crate) fn create_realtime_timerfd() -> io::Result<RawFd> {
+    syscall!(timerfd_create(
+        libc::CLOCK_REALTIME,
+        libc::TFD_NONBLOCK | libc::TFD_CLOEXEC
+    ))
+}
+
+// Not in every version of libc
+const TFD_TIMER_CANCEL_ON_SET: libc::c_int = 1 << 1;
+
+/// Arms a realtime timerfd to expire once at `when`. Reads fail with `ECANCELED` if the
+/// clock is set before that.
+pub(crate) fn arm_timerfd_at(fd: RawFd, when: SystemTime) -> io::Result<()> {
+    let since_epoch = when
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    // A zero value would disarm the timer instead
+    let since_epoch = std::cmp::max(since_epoch, Duration::from_nanos(1));
+    let spec = libc::itimerspec {
+        it_interval: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        it_value: libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+        },
+    };
+    let flags = libc::TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET;
+    syscall!(timerfd_settime(fd, flags, &spec, std::ptr::null_mut()))?;
+    Ok(())
+}
+
+/// Creates an inotify instance watching `dir` for the events in `mask`.
+pub(crate) fn inotify_watch_dir(dir: &Path, mask: u32) -> io::Result<RawFd> {
+    let fd = syscall!(inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC))?;
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    if let Err(err) = syscall!(inotify_add_watch(fd, dir.as_ptr(), mask)) {
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+pub(crate) fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let len = syscall!(read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()))?;
+    Ok(len as usize)
+}
+
+pub(crate) fn send_file(sock: RawFd, file: RawFd, offset: u64, len: usize) -> io::Result<usize> {
+    let mut offset = offset as libc::off_t;
+    let sent = syscall!(sendfile(sock, file, &mut offset, len))?;
+    Ok(sent as usize)
+}
+
+// Builds a message header carrying a single byte of data and room for exactly one
+// file descriptor in its control buffer.
+fn fd_passing_msghdr(
+    data: &mut [u8; 1],
+    iov: &mut libc::iovec,
+    cmsg_buf: &mut Vec<u8>,
+) -> libc::msghdr {
+    let space = unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) } as usize;
+    cmsg_buf.resize(space, 0);
+    iov.iov_base = data.as_mut_ptr() as *mut libc::c_void;
+    iov.iov_len = data.len();
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = iov as *mut libc::iovec;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    msg
+}
+
+/// Sends a file descriptor over a Unix socket, using SCM_RIGHTS
+pub(crate) fn send_fd(sock: RawFd, fd: RawFd) -> io::Result<()> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: std::ptr::null_mut(),
+        iov_len: 0,
+    };
+    let mut cmsg_buf = Vec::new();
+    let msg = fd_passing_msghdr(&mut data, &mut iov, &mut cmsg_buf);
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+    }
+    syscall!(sendmsg(sock, &msg, libc::MSG_NOSIGNAL))?;
+    Ok(())
+}
+
+/// Receives a file descriptor sent with [`send_fd`] over a Unix socket.
+///
+/// The descriptor is installed in the file descriptor table of the calling thread, and
+/// is only valid there. Fails if the message carried anything but exactly one file
+/// descriptor, closing whatever it did carry.
+pub(crate) fn recv_fd(sock: RawFd) -> io::Result<RawFd> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: std::ptr::null_mut(),
+        iov_len: 0,
+    };
+    let mut cmsg_buf = Vec::new();
+    let mut msg = fd_passing_msghdr(&mut data, &mut iov, &mut cmsg_buf);
+    let received = syscall!(recvmsg(sock, &mut msg, libc::MSG_CMSG_CLOEXEC))?;
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for idx in 0..len / std::mem::size_of::<RawFd>() {
+                    fds.push(std::ptr::read_unaligned(data.add(idx)));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    let failure = if received == 0 {
+        Some((io::ErrorKind::UnexpectedEof, "peer closed the channel"))
+    } else if msg.msg_flags & libc::MSG_CTRUNC != 0 || fds.len() > 1 {
+        // The kernel drops the descriptors that don't fit, but installs the others
+        Some((
+            io::ErrorKind::InvalidData,
+            "message carried more than one file descriptor",
+        ))
+    } else if fds.is_empty() {
+        Some((
+            io::ErrorKind::InvalidData,
+            "message did not carry a file descriptor",
+        ))
+    } else {
+        None
+    };
+    match failure {
+        Some((kind, what)) => {
+            for fd in fds {
+                unsafe { libc::close(fd) };
+            }
+            Err(io::Error::new(kind, what))
+        }
+        None => Ok(fds[0]),
+    }
+}
+
+/// Opens the directory at `path` and takes an exclusive `flock` on it, held until the
+/// file returned is closed. Returns `None` if somebody else holds it and `wait` is false.
+pub(crate) fn lock_dir(path: &Path, wait: bool) -> io::Result<Option<std::fs::File>> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = syscall!(open(
+        path.as_ptr(),
+        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC
+    ))?;
+    let dir = unsafe { std::fs::File::from_raw_fd(fd) };
+    let operation = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    match syscall!(flock(fd, operation)) {
+        Ok(_) => Ok(Some(dir)),
+        Err(err) if err.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
 pub(crate) fn sync_open(path: &Path, flags: libc::c_int, mode: libc::c_int) -> io::Result<RawFd> {
     let path = path.as_os_str().as_bytes().as_ptr();
     syscall!(open(path as _, flags, mode))
 }
 
+mod ancillary;
+mod blocking;
 mod posix_buffers;
 mod uring;
 
+pub use self::ancillary::*;
 pub use self::posix_buffers::*;
 pub use self::uring::*;
 use crate::IoRequirements;
@@ -109,15 +493,44 @@ pub(crate) enum SourceType {
     DmaRead(PollableStatus, Option<DmaBuffer>),
     PollableFd,
     Open(CString),
+    Unlink(CString),
     FdataSync,
     Fallocate,
     Close,
     LinkRings(bool),
     Statx(CString, Box<RefCell<libc::statx>>),
     Timeout(bool),
+    RingTimer,
+    Blocking(&'static str),
+    BlockingDone,
+    ClockChange,
     Invalid,
 }
 
+impl SourceType {
+    /// What operations on this source are called in reports
+    #[cfg_attr(not(feature = "io-tracing"), allow(dead_code))]
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            SourceType::DmaWrite(_) => "write",
+            SourceType::DmaRead(..) => "read",
+            SourceType::PollableFd => "poll",
+            SourceType::Open(_) => "open",
+            SourceType::Unlink(_) => "unlink",
+            SourceType::FdataSync => "fdatasync",
+            SourceType::Fallocate => "fallocate",
+            SourceType::Close => "close",
+            SourceType::LinkRings(_) => "link rings",
+            SourceType::Statx(..) => "statx",
+            SourceType::Timeout(_) | SourceType::RingTimer => "timeout",
+            SourceType::Blocking(name) => name,
+            SourceType::BlockingDone => "blocking completions",
+            SourceType::ClockChange => "clock changes",
+            SourceType::Invalid => "unknown",
+        }
+    }
+}
+
 /// Tasks interested in events on a source.
 #[derive(Debug)]
 pub(crate) struct Wakers {
@@ -137,9 +550,9 @@ impl Wakers {
     }
 }
 
-/// A registered source of I/O events.
+/// The part of a source the kernel refers to while operations are in flight.
 #[derive(Debug)]
-pub struct Source {
+pub struct InnerSource {
     /// Raw file descriptor on Unix platforms.
     pub(crate) raw: RawFd,
 
@@ -150,6 +563,247 @@ pub struct Source {
 
     io_requirements: IoRequirements,
 
+    /// Tag of the task that submitted the last operation of this source.
+    #[cfg(feature = "io-tracing")]
+    tag: Cell<Option<u64>>,
+
+    /// When the last operation of this source was queued, and handed to the kernel, if
+    /// it is being timed.
+    #[cfg(feature = "io-tracing")]
+    timing: Cell<Option<(Instant, Option<Instant>)>>,
+
+    /// When the oldest operation still in flight was submitted, and whether it was
+    /// handed to the kernel already, for the listing of in-flight operations.
+    #[cfg(feature = "io-tracing")]
+    in_flight_since: Cell<Option<(Instant, bool)>>,
+
+    /// The path of the file the operations are on, looked up when they are submitted,
+    /// as by the time they complete the file may be closed already.
+    #[cfg(feature = "io-tracing")]
+    file_path: RefCell<Option<std::rc::Rc<Path>>>,
+
+    /// Operations submitted on behalf of this source that did not complete yet.
+    inflight: Cell<usize>,
+
+    /// Whether the source was dropped while operations were still in flight.
+    orphaned: Cell<bool>,
+
+    /// Resources the kernel may access until the operations of this source complete.
+    /// They are released together with the source, which outlives those operations.
+    keepalive: RefCell<Vec<Box<dyn Any>>>,
+}
+
+impl InnerSource {
+    pub(crate) fn as_ptr(&self) -> *const InnerSource {
+        self as *const InnerSource
+    }
+
+    /// Keeps `resource` alive until the operations of this source complete, even if the
+    /// source is dropped before that.
+    pub(crate) fn keep_alive(&self, resource: Box<dyn Any>) {
+        self.keepalive.borrow_mut().push(resource);
+    }
+
+    /// Whether the kernel has operations of this source that didn't complete yet.
+    pub(crate) fn is_inflight(&self) -> bool {
+        self.inflight.get() > 0
+    }
+
+    /// Accounts for an operation submitted on behalf of this source.
+    pub(crate) fn add_inflight(&self) {
+        self.inflight.set(self.inflight.get() + 1);
+        #[cfg(feature = "io-tracing")]
+        self.trace_submission();
+    }
+
+    #[cfg(feature = "io-tracing")]
+    fn describe_in_flight(&self) -> crate::in_flight_io::InFlightIo {
+        let (since, in_kernel) = self
+            .in_flight_since
+            .get()
+            .unwrap_or_else(|| (Instant::now(), false));
+        crate::in_flight_io::InFlightIo::new(
+            self.source_type.name(),
+            self.raw,
+            self.path(),
+            self.io_requirements.io_handle,
+            self.tag.get(),
+            since.elapsed(),
+            in_kernel,
+            self.orphaned.get(),
+        )
+    }
+
+    #[cfg(feature = "io-tracing")]
+    fn path(&self) -> Option<std::path::PathBuf> {
+        match &self.source_type {
+            SourceType::Open(path) | SourceType::Unlink(path) | SourceType::Statx(path, _) => {
+                Some(Path::new(std::ffi::OsStr::from_bytes(path.as_bytes())).to_owned())
+            }
+            _ => self.file_path.borrow().as_deref().map(Path::to_path_buf),
+        }
+    }
+
+    #[cfg(feature = "io-tracing")]
+    fn trace_submission(&self) {
+        // Timeouts are meant to take long, there is nothing to learn from timing them
+        let tag = match self.source_type {
+            SourceType::LinkRings(_)
+            | SourceType::Timeout(_)
+            | SourceType::RingTimer
+            | SourceType::BlockingDone
+            | SourceType::ClockChange => {
+                self.tag.set(None);
+                self.timing.set(None);
+                return;
+            }
+            _ => crate::io_tag::current(),
+        };
+        if let Some(tag) = tag {
+            crate::io_tag::record_submission(tag);
+        }
+        self.tag.set(tag);
+        if self.inflight.get() == 1 {
+            if self.raw >= 0 {
+                let path = crate::parking::Reactor::get().file_path(self.raw);
+                *self.file_path.borrow_mut() = path;
+            }
+            self.in_flight_since.set(Some((Instant::now(), false)));
+            IN_FLIGHT_IO.with(|sources| sources.borrow_mut().insert(self.as_ptr()));
+        }
+        // Operations nobody looks into don't pay for reading the clock
+        let timed = tag.is_some() || crate::slow_io::enabled();
+        self.timing.set(if timed {
+            Some((Instant::now(), None))
+        } else {
+            None
+        });
+    }
+
+    /// Records that the last operation of this source was handed to the kernel.
+    #[cfg(feature = "io-tracing")]
+    pub(crate) fn mark_submitted(&self) {
+        if let Some((queued_at, None)) = self.timing.get() {
+            self.timing.set(Some((queued_at, Some(Instant::now()))));
+        }
+        if let Some((since, false)) = self.in_flight_since.get() {
+            self.in_flight_since.set(Some((since, true)));
+        }
+    }
+
+    /// Operations are only traced with the `io-tracing` feature.
+    #[cfg(not(feature = "io-tracing"))]
+    pub(crate) fn mark_submitted(&self) {}
+
+    /// Accounts the completion of an operation to the tag it was submitted with, if any,
+    /// and reports it if it was slow.
+    #[cfg(feature = "io-tracing")]
+    pub(crate) fn trace_completion(&self, ring: &'static str, result: &io::Result<usize>) {
+        let (queued_at, submitted_at) = match self.timing.get() {
+            Some(timing) => timing,
+            None => return,
+        };
+        let submitted_at = submitted_at.unwrap_or(queued_at);
+        let queued = submitted_at.saturating_duration_since(queued_at);
+        let in_kernel = submitted_at.elapsed();
+        if let Some(tag) = self.tag.get() {
+            crate::io_tag::record_completion(tag, queued + in_kernel, result.is_err());
+        }
+        if crate::slow_io::is_slow(queued + in_kernel) {
+            crate::slow_io::report(crate::slow_io::SlowIo::new(
+                self.source_type.name(),
+                self.raw,
+                self.path(),
+                result,
+                self.io_requirements.io_handle,
+                self.tag.get(),
+                ring,
+                queued,
+                in_kernel,
+            ));
+        }
+    }
+
+    #[cfg(not(feature = "io-tracing"))]
+    pub(crate) fn trace_completion(&self, _ring: &'static str, _result: &io::Result<usize>) {}
+
+    /// Accounts for the completion of an operation submitted on behalf of this source.
+    ///
+    /// Returns false if the source was orphaned and this was its last operation, in
+    /// which case it was released and must not be used anymore.
+    pub(crate) unsafe fn complete_inflight(this: *mut InnerSource) -> bool {
+        let inflight = (*this).inflight.get() - 1;
+        (*this).inflight.set(inflight);
+        #[cfg(feature = "io-tracing")]
+        {
+            if inflight == 0 && (*this).in_flight_since.take().is_some() {
+                // try_with: the reactor's own sources complete while the thread goes away
+                let _ = IN_FLIGHT_IO
+                    .try_with(|sources| sources.borrow_mut().remove(&(this as *const _)));
+            }
+        }
+        if inflight == 0 && (*this).orphaned.get() {
+            drop(Box::from_raw(this));
+            stat! {
+                ORPHANED_IO.with(|orphans| orphans.current.set(orphans.current.get() - 1));
+            }
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+struct OrphanedIo {
+    current: Cell<u64>,
+    total: Cell<u64>,
+}
+
+thread_local!(static ORPHANED_IO: OrphanedIo = OrphanedIo::default());
+
+/// Returns how many sources were dropped while the kernel still had operations in flight
+/// for them and are still waiting for those to complete, and how many were ever dropped
+/// that way in this thread.
+pub(crate) fn orphaned_io() -> (u64, u64) {
+    ORPHANED_IO.with(|orphans| (orphans.current.get(), orphans.total.get()))
+}
+
+// The sources with operations in flight. They stay valid while they are here, as they are
+// only released once their last operation completes, which takes them out.
+#[cfg(feature = "io-tracing")]
+thread_local!(static IN_FLIGHT_IO: RefCell<std::collections::HashSet<*const InnerSource>> = RefCell::new(std::collections::HashSet::new()));
+
+/// Lists the operations submitted in this thread that did not complete yet, oldest first.
+#[cfg(feature = "io-tracing")]
+pub(crate) fn in_flight_io() -> Vec<crate::in_flight_io::InFlightIo> {
+    let mut listing: Vec<_> = IN_FLIGHT_IO.with(|sources| {
+        sources
+            .borrow()
+            .iter()
+            .map(|source| unsafe { (**source).describe_in_flight() })
+            .collect()
+    });
+    listing.sort_by(|a, b| b.age().cmp(&a.age()));
+    listing
+}
+
+/// Operations are only tracked with the `io-tracing` feature.
+#[cfg(not(feature = "io-tracing"))]
+pub(crate) fn in_flight_io() -> Vec<crate::in_flight_io::InFlightIo> {
+    Vec::new()
+}
+
+/// A registered source of I/O events.
+///
+/// Operations submitted to the kernel refer to the source and to the buffers it owns
+/// until they complete. If the source is dropped before that, for instance because the
+/// future waiting for the operation was dropped on a timeout, its state is parked and
+/// only released once the kernel is done with it, so a late completion can never write
+/// into memory that was reused.
+#[derive(Debug)]
+pub struct Source {
+    inner: NonNull<InnerSource>,
+
     _pin: PhantomPinned,
 }
 
@@ -160,45 +814,91 @@ impl Source {
         raw: RawFd,
         source_type: SourceType,
     ) -> Pin<Box<Source>> {
-        let b = Box::new(Source {
-            _pin: PhantomPinned,
+        let inner = Box::new(InnerSource {
             raw,
             wakers: RefCell::new(Wakers::new()),
             source_type,
             io_requirements: ioreq,
+            #[cfg(feature = "io-tracing")]
+            tag: Cell::new(None),
+            #[cfg(feature = "io-tracing")]
+            timing: Cell::new(None),
+            #[cfg(feature = "io-tracing")]
+            in_flight_since: Cell::new(None),
+            #[cfg(feature = "io-tracing")]
+            file_path: RefCell::new(None),
+            inflight: Cell::new(0),
+            orphaned: Cell::new(false),
+            keepalive: RefCell::new(Vec::new()),
+        });
+        let b = Box::new(Source {
+            _pin: PhantomPinned,
+            inner: NonNull::from(Box::leak(inner)),
         });
         b.into()
     }
 
-    pub(crate) fn as_ptr(self: Pin<&Self>) -> *const Self {
-        self.get_ref() as *const Self
+    pub(crate) fn as_ptr(self: Pin<&Self>) -> *const InnerSource {
+        self.inner.as_ptr()
+    }
+
+    /// The state of the source, for the reactor to fill in what an operation produced,
+    /// like the buffer of a read. Nothing may borrow the state while it is written.
+    pub(crate) fn inner_ptr(&self) -> *mut InnerSource {
+        self.inner.as_ptr()
     }
 
     pub(crate) fn update_source_type(self: Pin<&mut Self>, source_type: SourceType) {
         unsafe {
-            let source = self.get_unchecked_mut();
+            let source = self.get_unchecked_mut().inner.as_mut();
             source.source_type = source_type;
         }
     }
 
     pub(crate) fn extract_source_type(self: Pin<&mut Self>) -> SourceType {
         unsafe {
-            let source = self.get_unchecked_mut();
+            let source = self.get_unchecked_mut().inner.as_mut();
             let invalid = SourceType::Invalid;
             std::mem::replace(&mut source.source_type, invalid)
         }
     }
 }
 
+impl Deref for Source {
+    type Target = InnerSource;
+
+    fn deref(&self) -> &InnerSource {
+        unsafe { self.inner.as_ref() }
+    }
+}
+
 impl Drop for Source {
     fn drop(&mut self) {
-        let w = self.wakers.get_mut();
-        if !w.waiters.is_empty() {
-            panic!("Attempting to release a source with pending waiters!");
-            // This cancellation will be problematic, because
-            // the operation may still be in-flight. If it returns
-            // later it will point to garbage memory.
-            //    crate::parking::Reactor::get().cancel_io(self);
+        if self.inflight.get() == 0 {
+            unsafe { drop(Box::from_raw(self.inner.as_ptr())) };
+            return;
+        }
+
+        // The kernel still refers to us. Whoever was waiting for the result is gone, so
+        // forget about them, and leave it to the completion of the last operation to
+        // release the source.
+        self.wakers.borrow_mut().waiters.clear();
+        self.orphaned.set(true);
+        // try_with: the reactor's own sources are dropped while the thread goes away
+        stat! {
+            let _ = ORPHANED_IO.try_with(|orphans| {
+                orphans.current.set(orphans.current.get() + 1);
+                orphans.total.set(orphans.total.get() + 1);
+            });
+        }
+
+        // Polls may never complete on their own, so cancel them.
+        if let SourceType::PollableFd = self.source_type {
+            crate::parking::Reactor::get().cancel_poll(self);
+        }
+        // Neither do timers, and there is no point in waiting for them
+        if let SourceType::RingTimer = self.source_type {
+            crate::parking::Reactor::get().cancel_ring_timer(self);
         }
     }
 }
@@ -9,10 +9,29 @@
 // that are pre-registered for I/O uring.
 
 use aligned_alloc::{aligned_alloc, aligned_free};
+use std::any::Any;
+use std::rc::Rc;
 
+// The memory of a buffer, which the kernel may still be reading from or writing to
+// after the buffer itself is gone
 #[derive(Debug)]
-pub struct PosixDmaBuffer {
+struct Allocation {
     data: *mut u8,
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        if !self.data.is_null() {
+            unsafe {
+                aligned_free(self.data as *mut ());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PosixDmaBuffer {
+    alloc: Rc<Allocation>,
     trim: usize,
     size: usize,
 }
@@ -25,7 +44,7 @@ impl PosixDmaBuffer {
             return None;
         }
         Some(PosixDmaBuffer {
-            data,
+            alloc: Rc::new(Allocation { data }),
             size,
             trim: 0,
         })
@@ -52,24 +71,20 @@ impl PosixDmaBuffer {
     }
 
     pub fn as_mut_ptr(&self) -> *mut u8 {
-        unsafe { self.data.add(self.trim) }
+        unsafe { self.alloc.data.add(self.trim) }
     }
 
     pub fn as_ptr(&self) -> *const u8 {
-        unsafe { self.data.add(self.trim) }
+        unsafe { self.alloc.data.add(self.trim) }
     }
 
-    pub fn memset(&self, value: u8) {
-        unsafe { std::ptr::write_bytes(self.as_mut_ptr(), value, self.size) }
+    // Holds the memory of the buffer for as long as the returned value lives, even if
+    // the buffer is dropped before
+    pub(crate) fn hold(&self) -> Box<dyn Any> {
+        Box::new(self.alloc.clone())
     }
-}
 
-impl Drop for PosixDmaBuffer {
-    fn drop(&mut self) {
-        if !self.data.is_null() {
-            unsafe {
-                aligned_free(self.data as *mut ());
-            }
-        }
+    pub fn memset(&self, value: u8) {
+        unsafe { std::ptr::write_bytes(self.as_mut_ptr(), value, self.size) }
     }
 }
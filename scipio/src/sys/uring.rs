@@ -5,7 +5,7 @@
 //
 use nix::poll::PollFlags;
 use rlimit::Resource;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::ffi::CStr;
@@ -14,11 +14,13 @@ use std::io::{Error, ErrorKind};
 use std::os::unix::io::RawFd;
 use std::pin::Pin;
 use std::task::Waker;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use crate::error::UnsupportedOperation;
+use crate::sys::blocking::{BlockingJob, BlockingPool};
 use crate::sys::posix_buffers::PosixDmaBuffer;
-use crate::sys::{PollableStatus, Source, SourceType};
-use crate::{IoRequirements, Latency};
+use crate::sys::{InnerSource, PollableStatus, Source, SourceType};
+use crate::{IoRequirements, Latency, RingPolicy};
 
 use uring_sys::IoRingOp;
 
@@ -38,15 +40,18 @@ enum UringOpDescriptor {
     Cancel(*const u8),
     Write(*const u8, usize, u64),
     WriteFixed(*const u8, usize, u64, usize),
+    WriteDsync(*const u8, usize, u64),
     ReadFixed(u64, usize),
     Read(*mut u8, usize, u64),
     Open(*const u8, libc::c_int, u32),
+    Unlink(*const u8),
     Close,
     FDataSync,
     Fallocate(u64, u64, libc::c_int),
     Statx(*const u8, *mut libc::statx),
     Timeout(u64),
-    TimeoutRemove(*const Source),
+    Timer(*const uring_sys::__kernel_timespec),
+    TimeoutRemove(*const InnerSource),
 }
 
 #[derive(Debug)]
@@ -56,58 +61,243 @@ struct UringDescriptor {
     args: UringOpDescriptor,
 }
 
-pub fn check_supported_operations(ops: &[uring_sys::IoRingOp]) -> bool {
+// The io_uring operations, indexed by opcode, in the order kernels added them
+static URING_OP_NAMES: &[&str] = &[
+    "IORING_OP_NOP",
+    "IORING_OP_READV",
+    "IORING_OP_WRITEV",
+    "IORING_OP_FSYNC",
+    "IORING_OP_READ_FIXED",
+    "IORING_OP_WRITE_FIXED",
+    "IORING_OP_POLL_ADD",
+    "IORING_OP_POLL_REMOVE",
+    "IORING_OP_SYNC_FILE_RANGE",
+    "IORING_OP_SENDMSG",
+    "IORING_OP_RECVMSG",
+    "IORING_OP_TIMEOUT",
+    "IORING_OP_TIMEOUT_REMOVE",
+    "IORING_OP_ACCEPT",
+    "IORING_OP_ASYNC_CANCEL",
+    "IORING_OP_LINK_TIMEOUT",
+    "IORING_OP_CONNECT",
+    "IORING_OP_FALLOCATE",
+    "IORING_OP_OPENAT",
+    "IORING_OP_CLOSE",
+    "IORING_OP_FILES_UPDATE",
+    "IORING_OP_STATX",
+    "IORING_OP_READ",
+    "IORING_OP_WRITE",
+    "IORING_OP_FADVISE",
+    "IORING_OP_MADVISE",
+    "IORING_OP_SEND",
+    "IORING_OP_RECV",
+    "IORING_OP_OPENAT2",
+    "IORING_OP_EPOLL_CTL",
+    "IORING_OP_SPLICE",
+    "IORING_OP_PROVIDE_BUFFERS",
+    "IORING_OP_REMOVE_BUFFERS",
+    "IORING_OP_TEE",
+    "IORING_OP_SHUTDOWN",
+    "IORING_OP_RENAMEAT",
+    "IORING_OP_UNLINKAT",
+];
+
+// Newer than the operations iou knows about, so it is prepared by hand
+const IORING_OP_UNLINKAT: usize = 36;
+
+fn opcode(op: IoRingOp) -> usize {
+    unsafe { *{ &op as *const IoRingOp as *const libc::c_int } as usize }
+}
+
+// The first Linux version that supports an operation
+fn min_kernel(opcode: usize) -> (u32, u32) {
+    match opcode {
+        0..=7 => (5, 1),
+        8 => (5, 2),
+        9..=10 => (5, 3),
+        11 => (5, 4),
+        12..=16 => (5, 5),
+        17..=29 => (5, 6),
+        30..=32 => (5, 7),
+        33 => (5, 8),
+        _ => (5, 11),
+    }
+}
+
+fn kernel_version() -> Option<(u32, u32)> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    syscall!(uname(&mut uts)).ok()?;
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) }
+        .to_str()
+        .ok()?;
+    let mut numbers = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse::<u32>());
+    Some((numbers.next()?.ok()?, numbers.next()?.ok()?))
+}
+
+// Which operations the kernel supports, indexed by opcode. Kernels older than 5.6 can't
+// be probed, so for them it is deduced from the kernel version.
+fn probe_operations() -> Vec<bool> {
     unsafe {
         let probe = uring_sys::io_uring_get_probe();
         if probe.is_null() {
-            panic!("Failed to register a probe. The most likely reason is that your kernel witnessed Romulus killing Remus (too old!!)");
-        }
-
-        let mut ret = true;
-        for op in ops {
-            let opint = *{ op as *const uring_sys::IoRingOp as *const libc::c_int };
-            let sup = uring_sys::io_uring_opcode_supported(probe, opint) > 0;
-            ret &= sup;
-            if !sup {
-                println!("Yo kernel is so old it was with Hannibal when he crossed the Alps! Missing {:?}", op);
-            }
+            let version = kernel_version().unwrap_or((5, 1));
+            return (0..URING_OP_NAMES.len())
+                .map(|op| min_kernel(op) <= version)
+                .collect();
         }
+        let supported = (0..URING_OP_NAMES.len())
+            .map(|op| uring_sys::io_uring_opcode_supported(probe, op as libc::c_int) > 0)
+            .collect();
         uring_sys::io_uring_free_probe(probe);
-        if !ret {
-            eprintln!("Your kernel is older than Caesar. Bye");
-            std::process::exit(1);
+        supported
+    }
+}
+
+lazy_static! {
+    static ref SUPPORTED_URING_OPS: Vec<bool> = probe_operations();
+}
+
+#[cfg(test)]
+thread_local!(static FORCE_FALLBACKS: std::cell::Cell<bool> = std::cell::Cell::new(false));
+
+/// Makes the reactor of this thread emulate every operation it can emulate, as if the
+/// kernel didn't support them.
+#[cfg(test)]
+pub(crate) fn force_fallbacks(force: bool) {
+    FORCE_FALLBACKS.with(|f| f.set(force));
+}
+
+fn is_supported(op: usize) -> bool {
+    #[cfg(test)]
+    {
+        if FORCE_FALLBACKS.with(|f| f.get())
+            && !CORE_URING_OPS.iter().any(|core| opcode(*core) == op)
+        {
+            return false;
         }
-        ret
+    }
+    SUPPORTED_URING_OPS.get(op).cloned().unwrap_or(false)
+}
+
+fn unsupported(op: usize) -> UnsupportedOperation {
+    UnsupportedOperation {
+        operation: URING_OP_NAMES[op],
+        min_kernel: min_kernel(op),
     }
 }
 
-static SCIPIO_URING_OPS: &[IoRingOp] = &[
-    IoRingOp::IORING_OP_NOP,
-    IoRingOp::IORING_OP_READV,
-    IoRingOp::IORING_OP_WRITEV,
-    IoRingOp::IORING_OP_FSYNC,
-    IoRingOp::IORING_OP_READ_FIXED,
-    IoRingOp::IORING_OP_WRITE_FIXED,
+// The operations the reactor can't work without. File operations are emulated with
+// system calls in blocking helpers on kernels that lack them, and timeouts with timerfds.
+static CORE_URING_OPS: &[IoRingOp] = &[
     IoRingOp::IORING_OP_POLL_ADD,
     IoRingOp::IORING_OP_POLL_REMOVE,
-    IoRingOp::IORING_OP_SENDMSG,
-    IoRingOp::IORING_OP_RECVMSG,
-    IoRingOp::IORING_OP_TIMEOUT,
-    IoRingOp::IORING_OP_TIMEOUT_REMOVE,
-    IoRingOp::IORING_OP_ACCEPT,
-    IoRingOp::IORING_OP_CONNECT,
-    IoRingOp::IORING_OP_FALLOCATE,
-    IoRingOp::IORING_OP_OPENAT,
-    IoRingOp::IORING_OP_CLOSE,
-    IoRingOp::IORING_OP_STATX,
-    IoRingOp::IORING_OP_READ,
-    IoRingOp::IORING_OP_WRITE,
-    IoRingOp::IORING_OP_SEND,
-    IoRingOp::IORING_OP_RECV,
 ];
 
-lazy_static! {
-    static ref IO_URING_RECENT_ENOUGH: bool = check_supported_operations(SCIPIO_URING_OPS);
+// Timeouts are only of use to the reactor if it can remove them too, which Linux 5.4
+// can't do
+fn ring_timeouts_supported() -> bool {
+    is_supported(opcode(IoRingOp::IORING_OP_TIMEOUT))
+        && is_supported(opcode(IoRingOp::IORING_OP_TIMEOUT_REMOVE))
+}
+
+fn check_core_operations() -> io::Result<()> {
+    match CORE_URING_OPS.iter().find(|op| !is_supported(opcode(**op))) {
+        Some(op) => Err(unsupported(opcode(*op)).into()),
+        None => Ok(()),
+    }
+}
+
+impl UringOpDescriptor {
+    fn opcode(&self) -> usize {
+        let op = match self {
+            UringOpDescriptor::PollAdd(_) => IoRingOp::IORING_OP_POLL_ADD,
+            UringOpDescriptor::PollRemove(_) => IoRingOp::IORING_OP_POLL_REMOVE,
+            UringOpDescriptor::Cancel(_) => IoRingOp::IORING_OP_ASYNC_CANCEL,
+            UringOpDescriptor::Write(..)
+            | UringOpDescriptor::WriteFixed(..)
+            | UringOpDescriptor::WriteDsync(..) => IoRingOp::IORING_OP_WRITE,
+            UringOpDescriptor::Read(..) | UringOpDescriptor::ReadFixed(..) => {
+                IoRingOp::IORING_OP_READ
+            }
+            UringOpDescriptor::Open(..) => IoRingOp::IORING_OP_OPENAT,
+            UringOpDescriptor::Unlink(_) => return IORING_OP_UNLINKAT,
+            UringOpDescriptor::Close => IoRingOp::IORING_OP_CLOSE,
+            UringOpDescriptor::FDataSync => IoRingOp::IORING_OP_FSYNC,
+            UringOpDescriptor::Fallocate(..) => IoRingOp::IORING_OP_FALLOCATE,
+            UringOpDescriptor::Statx(..) => IoRingOp::IORING_OP_STATX,
+            UringOpDescriptor::Timeout(_) | UringOpDescriptor::Timer(_) => {
+                IoRingOp::IORING_OP_TIMEOUT
+            }
+            UringOpDescriptor::TimeoutRemove(_) => IoRingOp::IORING_OP_TIMEOUT_REMOVE,
+        };
+        opcode(op)
+    }
+}
+
+// An operation the kernel can't take through io_uring, handed to a blocking helper.
+// Like for the kernel, the memory its pointers refer to is kept alive by its source
+// until it completes, and nothing else uses it meanwhile.
+struct EmulatedOp(UringOpDescriptor);
+
+unsafe impl Send for EmulatedOp {}
+
+impl EmulatedOp {
+    // Performs the operation with the equivalent system call, which blocks the helper
+    // but gives the same result.
+    fn run(&self, fd: RawFd) -> io::Result<usize> {
+        emulate_operation(fd, &self.0)
+    }
+}
+
+fn emulate_operation(fd: RawFd, op: &UringOpDescriptor) -> io::Result<usize> {
+    let res = match *op {
+        UringOpDescriptor::Read(ptr, len, pos) => {
+            syscall!(pread(fd, ptr as *mut libc::c_void, len, pos as libc::off_t))? as usize
+        }
+        UringOpDescriptor::Write(ptr, len, pos)
+        | UringOpDescriptor::WriteFixed(ptr, len, pos, _) => syscall!(pwrite(
+            fd,
+            ptr as *const libc::c_void,
+            len,
+            pos as libc::off_t
+        ))? as usize,
+        UringOpDescriptor::WriteDsync(ptr, len, pos) => {
+            let ret = syscall!(pwrite(
+                fd,
+                ptr as *const libc::c_void,
+                len,
+                pos as libc::off_t
+            ))?;
+            syscall!(fdatasync(fd))?;
+            ret as usize
+        }
+        UringOpDescriptor::Open(path, flags, mode) => {
+            syscall!(openat(fd, path as *const libc::c_char, flags, mode))? as usize
+        }
+        UringOpDescriptor::Unlink(path) => {
+            syscall!(unlinkat(fd, path as *const libc::c_char, 0))? as usize
+        }
+        UringOpDescriptor::Close => syscall!(close(fd))? as usize,
+        UringOpDescriptor::FDataSync => syscall!(fdatasync(fd))? as usize,
+        UringOpDescriptor::Fallocate(offset, size, flags) => syscall!(fallocate(
+            fd,
+            flags,
+            offset as libc::off_t,
+            size as libc::off_t
+        ))? as usize,
+        UringOpDescriptor::Statx(path, buf) => syscall!(syscall(
+            libc::SYS_statx,
+            -1,
+            path as *const libc::c_char,
+            libc::AT_STATX_SYNC_AS_STAT | libc::AT_NO_AUTOMOUNT,
+            0x7ff,
+            buf
+        ))? as usize,
+        _ => return Err(unsupported(op.opcode()).into()),
+    };
+    Ok(res)
 }
 
 fn fill_sqe<F>(sqe: &mut iou::SubmissionQueueEvent<'_>, op: &UringDescriptor, buffer_allocation: F)
@@ -116,6 +306,9 @@ where
 {
     let mut user_data = op.user_data;
     unsafe {
+        if user_data != 0 {
+            (*(user_data as *const InnerSource)).mark_submitted();
+        }
         match op.args {
             UringOpDescriptor::PollAdd(events) => {
                 sqe.prep_poll_add(op.fd, events);
@@ -146,6 +339,16 @@ where
                     iou::OpenMode::from_bits_truncate(mode),
                 );
             }
+            UringOpDescriptor::Unlink(path) => {
+                uring_sys::io_uring_prep_rw(
+                    IORING_OP_UNLINKAT as _,
+                    sqe.raw_mut(),
+                    op.fd,
+                    path as _,
+                    0,
+                    0,
+                );
+            }
             UringOpDescriptor::FDataSync => {
                 sqe.prep_fsync(op.fd, iou::FsyncFlags::FSYNC_DATASYNC);
             }
@@ -169,6 +372,9 @@ where
                 };
                 sqe.prep_timeout(&timeout);
             }
+            UringOpDescriptor::Timer(timeout) => {
+                sqe.prep_timeout(&*timeout);
+            }
             UringOpDescriptor::TimeoutRemove(timer) => {
                 sqe.prep_timeout_remove(timer as _);
             }
@@ -181,7 +387,7 @@ where
 
                 //sqe.prep_read_fixed(op.fd, buf.as_mut_bytes(), pos, slabidx);
                 sqe.prep_read(op.fd, buf.as_mut_bytes(), pos);
-                let source = &mut *(op.user_data as *mut Source);
+                let source = &mut *(op.user_data as *mut InnerSource);
                 if let SourceType::DmaRead(pollable, _) = &source.source_type {
                     source.source_type = SourceType::DmaRead(*pollable, Some(buf));
                 } else {
@@ -194,6 +400,12 @@ where
                 //sqe.prep_write_fixed(op.fd, buf, pos, buf_index);
                 sqe.prep_write(op.fd, buf, pos);
             }
+            UringOpDescriptor::WriteDsync(ptr, len, pos) => {
+                let buf = std::slice::from_raw_parts(ptr, len);
+                sqe.prep_write(op.fd, buf, pos);
+                // Durable on completion, through FUA where the device supports it
+                sqe.raw_mut().cmd_flags.rw_flags = libc::RWF_DSYNC as _;
+            }
         }
     }
 
@@ -207,11 +419,12 @@ trait UringCommon {
     fn consume_one_event(&mut self, wakers: &mut Vec<Waker>) -> Option<()>;
     fn name(&self) -> &'static str;
 
-    fn add_to_submission_queue(&mut self, source: &Source, descriptor: UringOpDescriptor) {
+    fn add_to_submission_queue(&mut self, source: &InnerSource, descriptor: UringOpDescriptor) {
+        source.add_inflight();
         self.submission_queue().push_back(UringDescriptor {
             args: descriptor,
             fd: source.raw,
-            user_data: source as *const Source as _,
+            user_data: source.as_ptr() as _,
         });
     }
 
@@ -265,6 +478,45 @@ impl PollRing {
     }
 }
 
+// A poll ring that is only set up the first time something is queued into it, as most
+// executors never need it.
+#[derive(Default)]
+struct LazyPollRing {
+    ring: Option<PollRing>,
+    // setting the ring up failed, so there is no point in trying again
+    unavailable: bool,
+}
+
+impl LazyPollRing {
+    fn get(&mut self) -> Option<&mut PollRing> {
+        if self.ring.is_none() && !self.unavailable {
+            match PollRing::new(128) {
+                Ok(ring) => self.ring = Some(ring),
+                Err(_) => self.unavailable = true,
+            }
+        }
+        self.ring.as_mut()
+    }
+
+    fn can_sleep(&self) -> bool {
+        self.ring.as_ref().map_or(true, PollRing::can_sleep)
+    }
+
+    fn consume_submission_queue(&mut self) -> io::Result<usize> {
+        match self.ring.as_mut() {
+            Some(ring) => ring.consume_submission_queue(),
+            None => Ok(0),
+        }
+    }
+
+    fn consume_completion_queue(&mut self, wakers: &mut Vec<Waker>) -> usize {
+        match self.ring.as_mut() {
+            Some(ring) => ring.consume_completion_queue(wakers),
+            None => 0,
+        }
+    }
+}
+
 impl UringCommon for PollRing {
     fn name(&self) -> &'static str {
         "poll"
@@ -282,7 +534,7 @@ impl UringCommon for PollRing {
     }
 
     fn consume_one_event(&mut self, wakers: &mut Vec<Waker>) -> Option<()> {
-        process_one_event(self.ring.peek_for_cqe(), |_| None, wakers).and_then(|x| {
+        process_one_event(self.name(), self.ring.peek_for_cqe(), |_| None, wakers).and_then(|x| {
             self.completed += 1;
             Some(x)
         })
@@ -319,7 +571,7 @@ struct SleepableRing {
 
 impl SleepableRing {
     fn new(size: usize, name: &'static str) -> io::Result<Self> {
-        assert_eq!(*IO_URING_RECENT_ENOUGH, true);
+        check_core_operations()?;
         Ok(SleepableRing {
             //     ring: iou::IoUring::new_with_flags(size as _, iou::SetupFlags::IOPOLL)?,
             ring: iou::IoUring::new(size as _)?,
@@ -355,7 +607,10 @@ impl SleepableRing {
         wakers: &mut Vec<Waker>,
         d: Duration,
     ) -> io::Result<()> {
-        let src = source.as_ref().as_ptr() as *const Source;
+        if source.raw >= 0 {
+            return self.rearm_preempt_timerfd(source, d);
+        }
+        let src = source.as_ref().as_ptr();
 
         match source.source_type {
             SourceType::Timeout(false) => {} // not armed, do nothing
@@ -380,6 +635,7 @@ impl SleepableRing {
         source
             .as_mut()
             .update_source_type(SourceType::Timeout(true));
+        source.add_inflight();
 
         // This assumes SQEs will be processed in the order they are
         // seen. Because remove does not do anything asynchronously
@@ -395,6 +651,32 @@ impl SleepableRing {
         Ok(())
     }
 
+    // On kernels that can't remove ring timeouts the preempt timer is a timerfd: it is
+    // set again in place, and polled in the ring until it expires.
+    fn rearm_preempt_timerfd(
+        &mut self,
+        source: &mut Pin<Box<Source>>,
+        d: Duration,
+    ) -> io::Result<()> {
+        // An expiration the poll did not see yet would complete the next poll right away
+        let mut expirations = [0u8; 8];
+        let _ = super::read_fd(source.raw, &mut expirations);
+        super::arm_timerfd(source.raw, d)?;
+
+        if let SourceType::Timeout(false) = source.source_type {
+            source
+                .as_mut()
+                .update_source_type(SourceType::Timeout(true));
+            source.add_inflight();
+            self.submission_queue().push_front(UringDescriptor {
+                args: UringOpDescriptor::PollAdd(read_flags()),
+                fd: source.raw,
+                user_data: source.as_ref().as_ptr() as _,
+            });
+        }
+        Ok(())
+    }
+
     fn sleep(&mut self, link: &mut Pin<Box<Source>>) -> io::Result<usize> {
         match link.source_type {
             SourceType::LinkRings(true) => {} // nothing to do
@@ -402,10 +684,11 @@ impl SleepableRing {
                 if let Some(mut sqe) = self.ring.next_sqe() {
                     link.as_mut()
                         .update_source_type(SourceType::LinkRings(true));
+                    link.add_inflight();
 
                     let op = UringDescriptor {
                         fd: link.as_ref().raw,
-                        user_data: link.as_ref().as_ptr() as *const Source as u64,
+                        user_data: link.as_ref().as_ptr() as u64,
                         args: UringOpDescriptor::PollAdd(common_flags() | read_flags()),
                     };
                     fill_sqe(&mut sqe, &op, |size| PosixDmaBuffer::new(size));
@@ -419,32 +702,52 @@ impl SleepableRing {
 }
 
 fn process_one_event<F>(
+    ring: &'static str,
     cqe: Option<iou::CompletionQueueEvent>,
     try_process: F,
     wakers: &mut Vec<Waker>,
 ) -> Option<()>
 where
-    F: FnOnce(&mut Source) -> Option<()>,
+    F: FnOnce(&mut InnerSource) -> Option<()>,
 {
     if let Some(value) = cqe {
-        // No user data is POLL_REMOVE or CANCEL, we won't process.
-        if value.user_data() == 0 {
-            return Some(());
-        }
+        complete_source(ring, value.user_data(), value.result(), try_process, wakers);
+        return Some(());
+    }
+    None
+}
 
-        let source = unsafe {
-            let s = value.user_data() as *mut Source;
-            &mut *s
-        };
+// Completes an operation submitted on behalf of the source `user_data` points to, be it
+// by the kernel or by a blocking helper.
+fn complete_source<F>(
+    ring: &'static str,
+    user_data: u64,
+    result: io::Result<usize>,
+    try_process: F,
+    wakers: &mut Vec<Waker>,
+) where
+    F: FnOnce(&mut InnerSource) -> Option<()>,
+{
+    // No user data is POLL_REMOVE or CANCEL, we won't process.
+    if user_data == 0 {
+        return;
+    }
 
-        if let None = try_process(source) {
-            let mut w = source.wakers.borrow_mut();
-            w.result = Some(value.result());
-            wakers.append(&mut w.waiters);
+    let source = unsafe {
+        let s = user_data as *mut InnerSource;
+        (*s).trace_completion(ring, &result);
+        // Nobody is waiting for the result of an orphaned source
+        if !InnerSource::complete_inflight(s) {
+            return;
         }
-        return Some(());
+        &mut *s
+    };
+
+    if let None = try_process(source) {
+        let mut w = source.wakers.borrow_mut();
+        w.result = Some(result);
+        wakers.append(&mut w.waiters);
     }
-    None
 }
 
 impl UringCommon for SleepableRing {
@@ -462,6 +765,7 @@ impl UringCommon for SleepableRing {
 
     fn consume_one_event(&mut self, wakers: &mut Vec<Waker>) -> Option<()> {
         process_one_event(
+            self.name,
             self.ring.peek_for_cqe(),
             |source| match source.source_type {
                 SourceType::LinkRings(true) => {
@@ -504,8 +808,18 @@ pub struct Reactor {
     main_ring: RefCell<SleepableRing>,
     latency_ring: RefCell<SleepableRing>,
     poll_ring: RefCell<PollRing>,
+    // polled storage I/O of latency sensitive task queues, with RingPolicy::SeparateLatency
+    latency_poll_ring: RefCell<LazyPollRing>,
+    ring_policy: Cell<RingPolicy>,
     link_rings_src: RefCell<Pin<Box<Source>>>,
     timeout_src: RefCell<Pin<Box<Source>>>,
+    // system calls that may block for long, run by helper threads
+    blocking: BlockingPool,
+    // polls the eventfd the helpers signal completions through
+    blocking_src: RefCell<Pin<Box<Source>>>,
+    // polls a wall clock timerfd that is cancelled when the clock is set or the system
+    // resumes from a suspend, so the reactor wakes up to check the clocks
+    clock_src: RefCell<Pin<Box<Source>>>,
 }
 
 fn common_flags() -> PollFlags {
@@ -513,6 +827,13 @@ fn common_flags() -> PollFlags {
 }
 
 /// Epoll flags for all possible readability events.
+// Arms the wall clock timerfd the reactor polls so it never expires, and is only
+// cancelled when the clock is set or the system resumes.
+fn arm_clock_timerfd(fd: RawFd) -> io::Result<()> {
+    const CENTURY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+    super::arm_timerfd_at(fd, SystemTime::now() + CENTURY)
+}
+
 fn read_flags() -> PollFlags {
     PollFlags::POLLIN | PollFlags::POLLPRI
 }
@@ -550,23 +871,49 @@ macro_rules! queue_request_into_ring {
 
 macro_rules! queue_storage_io_request {
     ($self:expr, $source:ident, $op:expr) => {{
+        let op = $op;
+        if !is_supported(op.opcode()) {
+            $self.emulate($source, op);
+            return;
+        }
         let pollable = match $source.source_type {
             SourceType::DmaRead(p, _) => p,
             SourceType::DmaWrite(p) => p,
             _ => panic!("SourceType should declare if it supports poll operations"),
         };
-        match pollable {
-            PollableStatus::Pollable => queue_request_into_ring!($self.poll_ring, $source, $op),
-            PollableStatus::NonPollable => queue_request_into_ring!($self.main_ring, $source, $op),
+        let separate = match ($self.ring_policy.get(), $source.io_requirements.latency_req) {
+            (RingPolicy::SeparateLatency, Latency::Matters(_)) => true,
+            _ => false,
+        };
+        match (pollable, separate) {
+            (PollableStatus::Pollable, false) => {
+                queue_request_into_ring!($self.poll_ring, $source, op)
+            }
+            (PollableStatus::Pollable, true) => match $self.latency_poll_ring.borrow_mut().get() {
+                Some(ring) => ring.add_to_submission_queue($source, op),
+                // without a ring of their own, they share the other one
+                None => queue_request_into_ring!($self.poll_ring, $source, op),
+            },
+            (PollableStatus::NonPollable, false) => {
+                queue_request_into_ring!($self.main_ring, $source, op)
+            }
+            (PollableStatus::NonPollable, true) => {
+                queue_request_into_ring!($self.latency_ring, $source, op)
+            }
         }
     }};
 }
 
 macro_rules! queue_standard_request {
     ($self:expr, $source:ident, $op:expr) => {{
+        let op = $op;
+        if !is_supported(op.opcode()) {
+            $self.emulate($source, op);
+            return;
+        }
         match $source.io_requirements.latency_req {
-            Latency::NotImportant => queue_request_into_ring!($self.main_ring, $source, $op),
-            Latency::Matters(_) => queue_request_into_ring!($self.latency_ring, $source, $op),
+            Latency::NotImportant => queue_request_into_ring!($self.main_ring, $source, op),
+            Latency::Matters(_) => queue_request_into_ring!($self.latency_ring, $source, op),
         }
     }};
 }
@@ -602,11 +949,33 @@ impl Reactor {
         let main_ring = SleepableRing::new(128, "main")?;
         let latency_ring = SleepableRing::new(128, "latency")?;
         let link_fd = latency_ring.ring_fd();
+        // Created after the file descriptor table is unshared, like its helpers
+        let blocking = BlockingPool::new()?;
+        let blocking_fd = blocking.eventfd();
+        let clock_fd = super::create_realtime_timerfd()?;
+        if let Err(err) = arm_clock_timerfd(clock_fd) {
+            unsafe { libc::close(clock_fd) };
+            return Err(err);
+        }
+        // Without ring timeouts the latency ring is preempted by polling a timerfd
+        let preempt_fd = if ring_timeouts_supported() {
+            -1
+        } else {
+            match super::create_timerfd() {
+                Ok(fd) => fd,
+                Err(err) => {
+                    unsafe { libc::close(clock_fd) };
+                    return Err(err);
+                }
+            }
+        };
 
         Ok(Reactor {
             main_ring: RefCell::new(main_ring),
             latency_ring: RefCell::new(latency_ring),
             poll_ring: RefCell::new(PollRing::new(128)?),
+            latency_poll_ring: RefCell::new(LazyPollRing::default()),
+            ring_policy: Cell::new(RingPolicy::Shared),
             link_rings_src: RefCell::new(Source::new(
                 IoRequirements::default(),
                 link_fd,
@@ -614,12 +983,116 @@ impl Reactor {
             )),
             timeout_src: RefCell::new(Source::new(
                 IoRequirements::default(),
-                -1,
+                preempt_fd,
                 SourceType::Timeout(false),
             )),
+            blocking,
+            blocking_src: RefCell::new(Source::new(
+                IoRequirements::default(),
+                blocking_fd,
+                SourceType::BlockingDone,
+            )),
+            clock_src: RefCell::new(Source::new(
+                IoRequirements::default(),
+                clock_fd,
+                SourceType::ClockChange,
+            )),
         })
     }
 
+    /// Runs `job`, a system call that may block for long, in a helper thread, and
+    /// completes `source` with its result. Whatever the job refers to has to be kept
+    /// alive by the source until it completes.
+    pub(crate) fn run_blocking(
+        &self,
+        source: &Source,
+        job: Box<dyn FnOnce() -> io::Result<usize> + Send>,
+    ) {
+        let user_data = source.as_ptr() as u64;
+        match self.blocking.submit(BlockingJob { user_data, job }) {
+            Ok(()) => {
+                source.add_inflight();
+                source.mark_submitted();
+            }
+            Err(err) => source.wakers.borrow_mut().result = Some(Err(err)),
+        }
+    }
+
+    // Makes sure the reactor wakes up when a blocking helper completes a job
+    fn arm_blocking_poll(&self, ring: &mut SleepableRing) {
+        if !self.blocking.has_outstanding() {
+            return;
+        }
+        let source = self.blocking_src.borrow();
+        if !source.is_inflight() {
+            ring.add_to_submission_queue(&source, UringOpDescriptor::PollAdd(read_flags()));
+        }
+    }
+
+    // Makes sure the reactor wakes up when the clocks change under it, unless it has
+    // yet to notice the last change
+    fn arm_clock_poll(&self, ring: &mut SleepableRing) {
+        let source = self.clock_src.borrow();
+        if !source.is_inflight() && source.wakers.borrow().result.is_none() {
+            ring.add_to_submission_queue(&source, UringOpDescriptor::PollAdd(read_flags()));
+        }
+    }
+
+    /// Whether the wall clock was set, or the system resumed from a suspend, since the
+    /// last call. The kernel wakes the reactor up when that happens.
+    pub(crate) fn clocks_changed(&self) -> bool {
+        let source = self.clock_src.borrow();
+        if source.wakers.borrow_mut().result.take().is_none() {
+            return false;
+        }
+        // Reads fail with ECANCELED until the timerfd is armed again
+        let mut expirations = [0u8; 8];
+        let _ = super::read_fd(source.raw, &mut expirations);
+        let _ = arm_clock_timerfd(source.raw);
+        true
+    }
+
+    // Completes the sources of the jobs the blocking helpers are done with
+    fn complete_blocking(&self, wakers: &mut Vec<Waker>) {
+        for (user_data, result) in self.blocking.take_completed() {
+            complete_source("blocking", user_data, result, |_| None, wakers);
+        }
+    }
+
+    // Runs an operation the kernel doesn't support in a blocking helper, which
+    // completes it like the kernel would
+    fn emulate(&self, source: &Source, op: UringOpDescriptor) {
+        let op = match op {
+            UringOpDescriptor::ReadFixed(pos, len) => {
+                // The buffer goes with the source, like when the kernel reads into it
+                let buf = self.alloc_dma_buffer(len);
+                let ptr = buf.as_mut_ptr();
+                let inner = source.inner_ptr();
+                unsafe {
+                    match (*inner).source_type {
+                        SourceType::DmaRead(pollable, _) => {
+                            (*inner).source_type = SourceType::DmaRead(pollable, Some(buf));
+                        }
+                        _ => panic!("Expected DmaRead source type"),
+                    }
+                }
+                UringOpDescriptor::Read(ptr, len, pos)
+            }
+            op => op,
+        };
+        let fd = source.raw;
+        let op = EmulatedOp(op);
+        self.run_blocking(source, Box::new(move || op.run(fd)));
+    }
+
+    pub(crate) fn set_ring_policy(&self, policy: RingPolicy) {
+        self.ring_policy.set(policy);
+    }
+
+    pub(crate) fn ring_policy(&self) -> RingPolicy {
+        self.ring_policy.get()
+    }
+
     pub(crate) fn alloc_dma_buffer(&self, size: usize) -> DmaBuffer {
         let mut poll_ring = self.poll_ring.borrow_mut();
         poll_ring.alloc_dma_buffer(size)
@@ -637,9 +1110,24 @@ impl Reactor {
         queue_standard_request!(self, source, UringOpDescriptor::PollAdd(flags));
     }
 
-    pub(crate) fn write_dma(&self, source: &Source, buf: &DmaBuffer, pos: u64) {
-        //        let op = UringOpDescriptor::WriteFixed(buf.as_ptr() as *const u8, buf.len(), pos, buf.slabidx);
-        let op = UringOpDescriptor::WriteFixed(buf.as_ptr() as *const u8, buf.len(), pos, 0);
+    pub(crate) fn write_dma(
+        &self,
+        source: &Source,
+        buf: *const u8,
+        len: usize,
+        pos: u64,
+        dsync: bool,
+    ) {
+        let op = match dsync {
+            true => UringOpDescriptor::WriteDsync(buf, len, pos),
+            //        false => UringOpDescriptor::WriteFixed(buf, len, pos, buf.slabidx),
+            false => UringOpDescriptor::WriteFixed(buf, len, pos, 0),
+        };
+        queue_storage_io_request!(self, source, op);
+    }
+
+    pub(crate) fn read_dma_into(&self, source: &Source, buf: *mut u8, len: usize, pos: u64) {
+        let op = UringOpDescriptor::Read(buf, len, pos);
         queue_storage_io_request!(self, source, op);
     }
 
@@ -683,20 +1171,77 @@ impl Reactor {
         queue_standard_request!(self, source, op);
     }
 
+    pub(crate) fn unlink_at(&self, source: &Source) {
+        let pathptr = match &source.source_type {
+            SourceType::Unlink(cstring) => cstring.as_c_str().as_ptr(),
+            _ => panic!("Wrong source type!"),
+        };
+        queue_standard_request!(self, source, UringOpDescriptor::Unlink(pathptr as _));
+    }
+
+    /// Whether timers can be armed in the rings with [`arm_timer`]
+    ///
+    /// [`arm_timer`]: struct.Reactor.html#method.arm_timer
+    pub(crate) fn supports_timers(&self) -> bool {
+        ring_timeouts_supported()
+    }
+
+    /// Arms a timeout that completes `source` with `ETIME` after `dur`, so the kernel
+    /// wakes the ring up by itself when a timer expires.
+    pub(crate) fn arm_timer(&self, source: &Source, dur: Duration) {
+        // The kernel reads the timeout when the request is submitted, not when it is
+        // queued, so it lives with the source.
+        let timeout = Box::new(uring_sys::__kernel_timespec {
+            tv_sec: dur.as_secs() as _,
+            tv_nsec: dur.subsec_nanos() as _,
+        });
+        let op = UringOpDescriptor::Timer(&*timeout as *const _);
+        source.keep_alive(timeout);
+        queue_standard_request!(self, source, op);
+    }
+
+    /// Removes the timeout armed for `source` by [`arm_timer`]. It completes with
+    /// `ECANCELED`, which is what eventually releases an orphaned source.
+    ///
+    /// [`arm_timer`]: struct.Reactor.html#method.arm_timer
+    pub(crate) fn cancel_timer(&self, source: &InnerSource) {
+        let op = UringDescriptor {
+            fd: -1,
+            user_data: 0,
+            args: UringOpDescriptor::TimeoutRemove(source.as_ptr()),
+        };
+        match source.io_requirements.latency_req {
+            Latency::NotImportant => self.main_ring.borrow_mut().submission_queue().push_back(op),
+            Latency::Matters(_) => self
+                .latency_ring
+                .borrow_mut()
+                .submission_queue()
+                .push_back(op),
+        }
+    }
+
     pub(crate) fn insert(&self, fd: RawFd) -> io::Result<()> {
         add_flag(fd, libc::O_NONBLOCK)
     }
 
-    /*
-    pub(crate) fn cancel_io(&self, source : &Source) {
-        let source_ptr = source as *const Source;
-        let op = match source.source_type {
-            sys::SourceType::PollableFd => UringOpDescriptor::PollRemove(source_ptr as _),
-            _ => UringOpDescriptor::Cancel(source_ptr as _),
+    /// Cancels the polls in flight for `source`. They complete with `ECANCELED`, which
+    /// is what eventually releases an orphaned source.
+    pub(crate) fn cancel_poll(&self, source: &InnerSource) {
+        let op = UringDescriptor {
+            fd: -1,
+            // The removal itself does not refer to the source
+            user_data: 0,
+            args: UringOpDescriptor::PollRemove(source.as_ptr() as _),
         };
-        self.add_to_submission_queue(source, op);
+        match source.io_requirements.latency_req {
+            Latency::NotImportant => self.main_ring.borrow_mut().submission_queue().push_back(op),
+            Latency::Matters(_) => self
+                .latency_ring
+                .borrow_mut()
+                .submission_queue()
+                .push_back(op),
+        }
     }
-    */
 
     // We want to go to sleep but we can only go to sleep in one of the rings,
     // as we only have one thread. There are more than one sleepable rings, so
@@ -718,6 +1263,7 @@ impl Reactor {
         timer_expiration: Option<Duration>,
     ) -> io::Result<bool> {
         let mut poll_ring = self.poll_ring.borrow_mut();
+        let mut lat_poll_ring = self.latency_poll_ring.borrow_mut();
         let mut main_ring = self.main_ring.borrow_mut();
         let mut lat_ring = self.latency_ring.borrow_mut();
 
@@ -729,11 +1275,14 @@ impl Reactor {
                 false
             }
         };
-        flush_rings!(main_ring, lat_ring, poll_ring)?;
-        should_sleep &= poll_ring.can_sleep();
+        self.arm_blocking_poll(&mut main_ring);
+        self.arm_clock_poll(&mut main_ring);
+        flush_rings!(main_ring, lat_ring, lat_poll_ring, poll_ring)?;
+        should_sleep &= poll_ring.can_sleep() && lat_poll_ring.can_sleep();
 
         if should_sleep {
-            consume_rings!(into wakers; lat_ring, poll_ring, main_ring);
+            consume_rings!(into wakers; lat_ring, lat_poll_ring, poll_ring, main_ring);
+            self.complete_blocking(wakers);
         }
         // If we generated any event so far, we can't sleep. Need to handle them.
         should_sleep &= wakers.len() == 0;
@@ -748,7 +1297,8 @@ impl Reactor {
             self.link_rings_and_sleep(&mut main_ring)?;
         }
 
-        consume_rings!(into wakers; lat_ring, poll_ring, main_ring);
+        consume_rings!(into wakers; lat_ring, lat_poll_ring, poll_ring, main_ring);
+        self.complete_blocking(wakers);
         // A Note about need_preempt:
         //
         // If in the last call to consume_rings! some events completed, the tail and
@@ -759,6 +1309,21 @@ impl Reactor {
         Ok(should_sleep)
     }
 
+    /// Submits what is queued and collects what completed, without ever sleeping.
+    pub(crate) fn poll(&self, wakers: &mut Vec<Waker>) -> io::Result<()> {
+        let mut poll_ring = self.poll_ring.borrow_mut();
+        let mut lat_poll_ring = self.latency_poll_ring.borrow_mut();
+        let mut main_ring = self.main_ring.borrow_mut();
+        let mut lat_ring = self.latency_ring.borrow_mut();
+
+        self.arm_blocking_poll(&mut main_ring);
+        self.arm_clock_poll(&mut main_ring);
+        flush_rings!(main_ring, lat_ring, lat_poll_ring, poll_ring)?;
+        consume_rings!(into wakers; lat_ring, lat_poll_ring, poll_ring, main_ring);
+        self.complete_blocking(wakers);
+        Ok(())
+    }
+
     pub(crate) fn preempt_pointers(&self) -> (*const u32, *const u32) {
         let mut lat_ring = self.latency_ring.borrow_mut();
         let cq = &lat_ring.ring.raw_mut().cq;
@@ -767,5 +1332,11 @@ impl Reactor {
 }
 
 impl Drop for Reactor {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        unsafe { libc::close(self.clock_src.borrow().raw) };
+        let preempt_fd = self.timeout_src.borrow().raw;
+        if preempt_fd >= 0 {
+            unsafe { libc::close(preempt_fd) };
+        }
+    }
 }
@@ -40,49 +40,17 @@ impl<R, T> JoinHandle<R, T> {
     ///
     /// When a task is canceled, its future will not be polled again.
     pub fn cancel(&self) {
-        let ptr = self.raw_task.as_ptr();
-        let header = ptr as *const Header;
-
-        unsafe {
-            let mut state = (*header).state.load(Ordering::Acquire);
-
-            loop {
-                // If the task has been completed or closed, it can't be canceled.
-                if state & (COMPLETED | CLOSED) != 0 {
-                    break;
-                }
-
-                // If the task is not scheduled nor running, we'll need to schedule it.
-                let new = if state & (SCHEDULED | RUNNING) == 0 {
-                    (state | SCHEDULED | CLOSED) + REFERENCE
-                } else {
-                    state | CLOSED
-                };
-
-                // Mark the task as closed.
-                match (*header).state.compare_exchange_weak(
-                    state,
-                    new,
-                    Ordering::AcqRel,
-                    Ordering::Acquire,
-                ) {
-                    Ok(_) => {
-                        // If the task is not scheduled nor running, schedule it one more time so
-                        // that its future gets dropped by the executor.
-                        if state & (SCHEDULED | RUNNING) == 0 {
-                            ((*header).vtable.schedule)(ptr);
-                        }
-
-                        // Notify the awaiter that the task has been closed.
-                        if state & AWAITER != 0 {
-                            (*header).notify(None);
-                        }
+        unsafe { cancel_raw(self.raw_task.as_ptr()) }
+    }
 
-                        break;
-                    }
-                    Err(s) => state = s,
-                }
-            }
+    /// Returns a handle that cancels the task like [`cancel`] does, and that can be kept
+    /// apart from this `JoinHandle`.
+    ///
+    /// [`cancel`]: struct.JoinHandle.html#method.cancel
+    pub(crate) fn canceller(&self) -> TaskCanceller {
+        TaskCanceller {
+            raw_task: self.raw_task,
+            _waker: self.waker(),
         }
     }
 
@@ -109,6 +77,74 @@ impl<R, T> JoinHandle<R, T> {
     }
 }
 
+/// Cancels the task behind a raw task pointer, like [`JoinHandle::cancel`] does.
+///
+/// [`JoinHandle::cancel`]: struct.JoinHandle.html#method.cancel
+unsafe fn cancel_raw(ptr: *const ()) {
+    let header = ptr as *const Header;
+    let mut state = (*header).state.load(Ordering::Acquire);
+
+    loop {
+        // If the task has been completed or closed, it can't be canceled.
+        if state & (COMPLETED | CLOSED) != 0 {
+            break;
+        }
+
+        // If the task is not scheduled nor running, we'll need to schedule it.
+        let new = if state & (SCHEDULED | RUNNING) == 0 {
+            (state | SCHEDULED | CLOSED) + REFERENCE
+        } else {
+            state | CLOSED
+        };
+
+        // Mark the task as closed.
+        match (*header)
+            .state
+            .compare_exchange_weak(state, new, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                // If the task is not scheduled nor running, schedule it one more time so
+                // that its future gets dropped by the executor.
+                if state & (SCHEDULED | RUNNING) == 0 {
+                    ((*header).vtable.schedule)(ptr);
+                }
+
+                // Notify the awaiter that the task has been closed.
+                if state & AWAITER != 0 {
+                    (*header).notify(None);
+                }
+
+                break;
+            }
+            Err(s) => state = s,
+        }
+    }
+}
+
+/// Cancels a task, like its [`JoinHandle`] does, while the `JoinHandle` stays free to be
+/// awaited. It holds a reference to the task, so it stays valid after the task is done.
+///
+/// [`JoinHandle`]: struct.JoinHandle.html
+#[derive(Debug)]
+pub(crate) struct TaskCanceller {
+    raw_task: NonNull<()>,
+    _waker: Waker,
+}
+
+impl TaskCanceller {
+    /// Cancels the task, unless it is done already
+    pub(crate) fn cancel(&self) {
+        unsafe { cancel_raw(self.raw_task.as_ptr()) }
+    }
+
+    /// Whether the task completed or was canceled
+    pub(crate) fn is_done(&self) -> bool {
+        let header = self.raw_task.as_ptr() as *const Header;
+        let state = unsafe { (*header).state.load(Ordering::Acquire) };
+        state & (COMPLETED | CLOSED) != 0
+    }
+}
+
 impl<R, T> Drop for JoinHandle<R, T> {
     fn drop(&mut self) {
         let ptr = self.raw_task.as_ptr();
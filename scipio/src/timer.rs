@@ -4,14 +4,26 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
 use crate::parking::Reactor;
+use crate::sys;
+use crate::task::join_handle::TaskCanceller;
 use crate::task::JoinHandle;
-use crate::{Local, QueueNotFoundError, Task, TaskQueueHandle};
-use std::cell::RefCell;
+use crate::{
+    bridge, Async, CronSchedule, DeadlineExceeded, Local, QueueNotFoundError, Task,
+    TaskQueueHandle, TimerBackend, TimerCancelled,
+};
+use futures::channel::mpsc;
+use futures::future::poll_fn;
+use futures::{Stream, StreamExt};
+use std::cell::{Cell, RefCell};
+use std::fmt;
 use std::future::Future;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::pin::Pin;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
+use std::sync::{self, Arc};
 use std::task::{Context, Poll, Waker};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug)]
 struct Inner {
@@ -21,23 +33,257 @@ struct Inner {
 
     /// When this timer fires.
     when: Instant,
+
+    /// How much later than `when` the timer may fire, so it fires together with others.
+    slack: Duration,
+
+    /// Whether the timer reads the time the reactor cached instead of the clock.
+    coarse: bool,
+
+    /// Whether the timer was cancelled through a [`TimerHandle`].
+    cancelled: bool,
+
+    /// How long the system had been suspended when `when` was set. The time it spends
+    /// suspended after that counts towards the timer.
+    suspended: Duration,
+
+    /// The timeout armed in the rings, with `TimerBackend::Ring`.
+    ring: Option<Pin<Box<sys::Source>>>,
 }
 
 impl Inner {
+    fn now(&self) -> Instant {
+        if self.coarse {
+            Reactor::get().coarse_now()
+        } else {
+            Instant::now()
+        }
+    }
+
     fn reset(&mut self, dur: Duration) {
+        let now = self.now();
+        self.reset_at(now + dur);
+    }
+
+    // How long the system was suspended since `when` was set
+    fn suspended_since(&self) -> Duration {
+        Reactor::get().time_suspended() - self.suspended
+    }
+
+    // When the timer fires on the monotonic clock, which stops while the system is
+    // suspended
+    fn deadline(&self) -> Instant {
+        let suspended = self.suspended_since();
+        self.when.checked_sub(suspended).unwrap_or(self.when)
+    }
+
+    fn reset_at(&mut self, when: Instant) {
+        self.cancelled = false;
         if let Some(_) = self.waker.as_ref() {
             // Deregister the timer from the reactor.
-            Reactor::get().remove_timer(self.id);
+            self.deregister();
         }
 
         // Update the timeout.
-        self.when = Instant::now() + dur;
+        self.when = when;
+        self.suspended = Reactor::get().time_suspended();
 
-        if let Some(waker) = self.waker.as_mut() {
+        if let Some(waker) = self.waker.clone() {
             // Re-register the timer with the new timeout.
-            Reactor::get().insert_timer(self.id, self.when, waker);
+            self.register(&waker);
+        }
+    }
+
+    // Arms the timer wherever the reactor keeps timers, to wake up `waker` on expiration
+    fn register(&mut self, waker: &Waker) {
+        let reactor = Reactor::get();
+        match reactor.timer_backend() {
+            TimerBackend::Wheel => {
+                reactor.insert_timer(self.id, self.deadline(), self.slack, waker)
+            }
+            TimerBackend::Ring => {
+                // A timeout that completed before the timer expired, which can happen to
+                // coarse timers, is armed again for what is left.
+                let armed = match &self.ring {
+                    Some(source) => source.wakers.borrow().result.is_none(),
+                    None => false,
+                };
+                if !armed {
+                    let left = self.deadline().saturating_duration_since(self.now());
+                    self.release_ring();
+                    self.ring = Some(reactor.arm_ring_timer(left));
+                }
+                let mut wakers = self.ring.as_ref().unwrap().wakers.borrow_mut();
+                wakers.waiters.clear();
+                wakers.waiters.push(waker.clone());
+            }
+        }
+    }
+
+    fn deregister(&mut self) {
+        Reactor::get().remove_timer(self.id);
+        self.release_ring();
+    }
+
+    // Dropping the source removes its timeout from the ring
+    fn release_ring(&mut self) {
+        if self.ring.take().is_some() {
+            Reactor::get().release_ring_timer();
+        }
+    }
+
+    // Cancels the timer like TimerHandle::cancel, except that it leaves it in the timer
+    // wheel and returns its id, if it was armed, and the waker to wake once it is out
+    fn cancel_deferred(&mut self) -> Option<(u64, Waker)> {
+        self.cancelled = true;
+        let waker = self.waker.take()?;
+        self.release_ring();
+        Some((self.id, waker))
+    }
+}
+
+/// Statistics about the timers armed in an executor.
+///
+/// Retrieved through [`LocalExecutor::timer_stats`]
+///
+/// [`LocalExecutor::timer_stats`]: struct.LocalExecutor.html#method.timer_stats
+#[derive(Debug, Clone, Default)]
+pub struct TimerStats {
+    pub(crate) armed: usize,
+    pub(crate) high_water_mark: usize,
+    pub(crate) capacity: usize,
+    pub(crate) allocations: u64,
+    pub(crate) loops: u64,
+    pub(crate) fired: u64,
+    pub(crate) last_loop_fired: usize,
+    pub(crate) max_fired_per_loop: usize,
+    pub(crate) deferred_loops: u64,
+    pub(crate) total_fire_latency: Duration,
+    pub(crate) max_fire_latency: Duration,
+    pub(crate) max_latency_sensitive_fire_latency: Duration,
+    pub(crate) clock_jumps: u64,
+    pub(crate) time_suspended: Duration,
+}
+
+impl TimerStats {
+    /// The number of timers currently armed
+    pub fn armed(&self) -> usize {
+        self.armed
+    }
+
+    /// The largest number of timers that were ever armed at the same time
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+    /// How many timers the reactor can keep track of at the same time without
+    /// allocating memory, whether they are latency sensitive or not
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many times arming a timer required the reactor to allocate memory to keep
+    /// track of it
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
+    /// How many times the reactor looked for timers to fire
+    pub fn loops(&self) -> u64 {
+        self.loops
+    }
+
+    /// How many timers fired, in total
+    pub fn fired(&self) -> u64 {
+        self.fired
+    }
+
+    /// How many timers fired the last time the reactor looked for timers to fire
+    pub fn last_loop_fired(&self) -> usize {
+        self.last_loop_fired
+    }
+
+    /// The largest number of timers that fired in a single reactor loop
+    pub fn max_fired_per_loop(&self) -> usize {
+        self.max_fired_per_loop
+    }
+
+    /// How many reactor loops left timers that were due for the next loop, because more
+    /// of them were due than [`LocalExecutor::set_max_bulk_timer_expirations`] allows
+    ///
+    /// [`LocalExecutor::set_max_bulk_timer_expirations`]: struct.LocalExecutor.html#method.set_max_bulk_timer_expirations
+    pub fn deferred_loops(&self) -> u64 {
+        self.deferred_loops
+    }
+
+    /// The average time between the instant timers were due, slack included, and the
+    /// instant the reactor fired them
+    pub fn mean_fire_latency(&self) -> Duration {
+        if self.fired == 0 {
+            return Duration::from_secs(0);
         }
+        Duration::from_nanos((self.total_fire_latency.as_nanos() / self.fired as u128) as u64)
+    }
+
+    /// The longest time between the instant a timer was due, slack included, and the
+    /// instant the reactor fired it
+    pub fn max_fire_latency(&self) -> Duration {
+        self.max_fire_latency
+    }
+
+    /// Same as [`max_fire_latency`], but only for timers armed by task queues marked as
+    /// [`Latency::Matters`]
+    ///
+    /// [`max_fire_latency`]: struct.TimerStats.html#method.max_fire_latency
+    /// [`Latency::Matters`]: enum.Latency.html
+    pub fn max_latency_sensitive_fire_latency(&self) -> Duration {
+        self.max_latency_sensitive_fire_latency
+    }
+
+    /// How many times the clocks were seen jumping: either the system was suspended, or
+    /// the wall clock was set. See [`ClockJump`]
+    ///
+    /// [`ClockJump`]: enum.ClockJump.html
+    pub fn clock_jumps(&self) -> u64 {
+        self.clock_jumps
     }
+
+    /// How long the system was seen suspended, added up over every suspend
+    pub fn time_suspended(&self) -> Duration {
+        self.time_suspended
+    }
+}
+
+/// A discontinuity of the clocks, seen by the reactor in between two of its loops.
+///
+/// Applications observe them through [`Local::on_clock_jump`]. Only discontinuities of
+/// more than a second are reported.
+///
+/// [`Local::on_clock_jump`]: struct.Task.html#method.on_clock_jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockJump {
+    /// The system was suspended for about this long.
+    ///
+    /// The monotonic clock, behind [`Instant`], stops while the system is suspended, but
+    /// timers count that time: the ones that would have fired in the meantime fire as
+    /// soon as the system resumes, and repeating timers start their schedule over
+    /// instead of lagging behind by the time spent suspended. Timers armed with
+    /// [`TimerBackend::Ring`] don't see suspends.
+    ///
+    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
+    /// [`TimerBackend::Ring`]: enum.TimerBackend.html
+    Suspended(Duration),
+
+    /// The wall clock was set forward by about this much. Timers are not affected, but
+    /// [`WallClockTimer`]s arm themselves again for the same calendar time.
+    ///
+    /// [`WallClockTimer`]: struct.WallClockTimer.html
+    WallClockForward(Duration),
+
+    /// The wall clock was set back by about this much. See [`WallClockForward`]
+    ///
+    /// [`WallClockForward`]: enum.ClockJump.html#variant.WallClockForward
+    WallClockBackward(Duration),
 }
 
 /// A timer that expires after a duration of time.
@@ -84,11 +330,112 @@ impl Timer {
     /// Timer::new(Duration::from_millis(100));
     /// ```
     pub fn new(dur: Duration) -> Timer {
+        Timer::at(Instant::now() + dur)
+    }
+
+    /// Creates a timer that expires after the given duration of time, or up to `slack`
+    /// later.
+    ///
+    /// The reactor fires a timer with slack at a round instant within the time it may
+    /// fire, which other timers whose slack covers the same instant fire at too. Timers
+    /// with some slack, like idle timeouts that don't need to be precise, then fire
+    /// together instead of waking the reactor up one by one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Timer};
+    /// use std::time::Duration;
+    ///
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     // An idle timeout, that doesn't mind firing up to a second late
+    ///     Timer::new_with_slack(Duration::from_millis(100), Duration::from_secs(1)).await;
+    /// });
+    /// ```
+    pub fn new_with_slack(dur: Duration, slack: Duration) -> Timer {
+        let mut timer = Timer::new(dur);
+        timer.set_slack(slack);
+        timer
+    }
+
+    /// Creates a timer that expires after the given duration of time, give or take a
+    /// random fraction of it of up to `jitter`.
+    ///
+    /// A `jitter` of `0.1` makes a timer for a second expire anywhere between 900ms and
+    /// 1.1s from now. Spreading timers created at the same time like that keeps them from
+    /// all firing in the same reactor tick. `jitter` is clamped between `0.0` and `1.0`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Timer};
+    /// use std::time::Duration;
+    ///
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     // Somewhere between 90 and 110 milliseconds
+    ///     Timer::new_with_jitter(Duration::from_millis(100), 0.1).await;
+    /// });
+    /// ```
+    pub fn new_with_jitter(dur: Duration, jitter: f64) -> Timer {
+        let now = Instant::now();
+        Timer::at(jittered(now + dur, dur, jitter))
+    }
+
+    /// Creates a timer that expires after the given duration of time, measured with a
+    /// clock that is only read once per reactor loop.
+    ///
+    /// Reading the clock is cheap, but not free. Workloads with millions of short
+    /// timeouts spend a measurable share of their time in it, when creating and polling
+    /// their timers. Coarse timers use the time the reactor read last instead, so they
+    /// can expire up to the time it takes to run the tasks of one loop early or late.
+    /// That is usually well under a millisecond, but tasks that hog the CPU can make it
+    /// more.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, Timer};
+    /// use std::time::Duration;
+    ///
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     Timer::new_coarse(Duration::from_millis(100)).await;
+    /// });
+    /// ```
+    pub fn new_coarse(dur: Duration) -> Timer {
+        let timer = Timer::at(Reactor::get().coarse_now() + dur);
+        timer.inner.borrow_mut().coarse = true;
+        timer
+    }
+
+    /// Creates a timer that expires at the given instant. A timer created for an instant
+    /// that already passed expires right away.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::Timer;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let deadline = Instant::now() + Duration::from_millis(100);
+    /// Timer::at(deadline);
+    /// ```
+    pub fn at(when: Instant) -> Timer {
         Timer {
             inner: Rc::new(RefCell::new(Inner {
                 id: Reactor::get().register_timer(),
                 waker: None,
-                when: Instant::now() + dur,
+                when,
+                slack: Duration::from_secs(0),
+                coarse: false,
+                cancelled: false,
+                suspended: Reactor::get().time_suspended(),
+                ring: None,
             })),
         }
     }
@@ -96,11 +443,20 @@ impl Timer {
     // Useful in generating repeat timers that have a constant
     // id. Not for external usage.
     fn from_id(id: u64, dur: Duration) -> Timer {
+        Timer::from_id_at(id, Instant::now() + dur)
+    }
+
+    fn from_id_at(id: u64, when: Instant) -> Timer {
         Timer {
             inner: Rc::new(RefCell::new(Inner {
                 id,
                 waker: None,
-                when: Instant::now() + dur,
+                when,
+                slack: Duration::from_secs(0),
+                coarse: false,
+                cancelled: false,
+                suspended: Reactor::get().time_suspended(),
+                ring: None,
             })),
         }
     }
@@ -124,215 +480,1790 @@ impl Timer {
         let mut inner = self.inner.borrow_mut();
         inner.reset(dur);
     }
-}
 
-impl Drop for Timer {
-    fn drop(&mut self) {
+    /// Sets how much later than scheduled the timer may fire. See [`new_with_slack`]
+    ///
+    /// [`new_with_slack`]: struct.Timer.html#method.new_with_slack
+    pub fn set_slack(&mut self, slack: Duration) {
         let mut inner = self.inner.borrow_mut();
-        if let Some(_) = inner.waker.take() {
-            // Deregister the timer from the reactor.
-            Reactor::get().remove_timer(inner.id);
-        }
+        inner.slack = slack;
+        let when = inner.when;
+        inner.reset_at(when);
     }
-}
-
-impl Future for Timer {
-    type Output = Instant;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    /// Resets the timer to expire at the given instant, like [`reset`] does for a
+    /// duration.
+    ///
+    /// [`reset`]: struct.Timer.html#method.reset
+    pub fn reset_at(&mut self, when: Instant) {
         let mut inner = self.inner.borrow_mut();
-
-        if Instant::now() >= inner.when {
-            // Deregister the timer from the reactor if needed
-            Reactor::get().remove_timer(inner.id);
-            Poll::Ready(inner.when)
-        } else {
-            // Register the timer in the reactor.
-            Reactor::get().insert_timer(inner.id, inner.when, cx.waker());
-            inner.waker = Some(cx.waker().clone());
-            Poll::Pending
-        }
+        inner.reset_at(when);
     }
-}
-
-/// The TimerActionOnce struct provides an ergonomic way to fire an action at a
-/// later point in time.
-///
-/// In practice [`Timer`] is hard to use because it will always block the
-/// current task queue. This is rarely what one wants.
-///
-/// The TimerActionOnce creates a timer in the background and executes an action
-/// when the timer expires. It also provides a convenient way to cancel a timer.
-///
-/// [`Timer`]: struct.Timer
-#[derive(Debug)]
-pub struct TimerActionOnce<T> {
-    handle: JoinHandle<T, ()>,
-    inner: Rc<RefCell<Inner>>,
-}
 
-/// The TimerActionRepeat struct provides an ergonomic way to fire a repeated action at
-/// specified intervals, without having to fire new [`TimerActionOnce`] events
-///
-/// [`TimerActionOnce`]: struct.TimerActionOnce
-#[derive(Debug)]
-pub struct TimerActionRepeat {
-    handle: JoinHandle<(), ()>,
-    timer_id: u64,
-}
-
-impl<T: 'static> TimerActionOnce<T> {
-    /// Creates a [`TimerActionOnce`] that will execute the associated future once after some
-    /// time is passed
+    /// Creates a stream that yields every `period`, starting `period` from now.
     ///
-    /// # Arguments
-    ///
-    /// * `when` a [`Duration`] that represents when to execute the action.
-    /// * `action` a Future to be executed after `when` is elapsed.
+    /// The stream reuses the same timer for every tick, like [`TimerActionRepeat`] does,
+    /// so ticking doesn't register new timers with the reactor.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use futures::StreamExt;
+    /// use scipio::{LocalExecutor, Timer};
     /// use std::time::Duration;
     ///
-    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
-    ///         println!("Executed once");
-    ///     });
-    ///     action.join().await;
-    /// }).unwrap();
-    /// handle.join().unwrap();
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     let mut interval = Timer::interval(Duration::from_millis(10));
+    ///     for _ in 0..3 {
+    ///         let tick = interval.next().await.unwrap();
+    ///         println!("tick at {:?}", tick);
+    ///     }
+    /// });
     /// ```
-    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    pub fn do_in(when: Duration, action: impl Future<Output = T> + 'static) -> TimerActionOnce<T> {
-        Self::do_in_into(when, action, Local::current_task_queue()).unwrap()
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat.html
+    pub fn interval(period: Duration) -> TimerInterval {
+        TimerInterval {
+            timer: Timer::new(period),
+            period,
+        }
     }
 
-    /// Creates a [`TimerActionOnce`] that will execute the associated future once after some
-    /// time is passed in a specific Task Queue
-    ///
-    /// # Arguments
+    /// Returns a handle that other tasks in the same executor can cancel this timer with.
     ///
-    /// * `when` a [`Duration`] that represents when to execute the action.
-    /// * `action` a Future to be executed after `when` is elapsed.
-    /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
+    /// A cancelled timer stops waiting right away. Awaiting the timer itself outputs the
+    /// instant it was scheduled for regardless, so tasks that need to tell a cancellation
+    /// apart from an expiration await [`cancellable`] instead. Resetting a timer undoes
+    /// its cancellation.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce, Local, Latency};
+    /// use scipio::{Local, LocalExecutor, Timer};
     /// use std::time::Duration;
     ///
-    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
-    ///     let action = TimerActionOnce::do_in_into(Duration::from_millis(100), async move {
-    ///         println!("Executed once");
-    ///     }, tq).unwrap();
-    ///     action.join().await;
-    /// }).unwrap();
-    /// handle.join().unwrap();
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     let timer = Timer::new(Duration::from_secs(60));
+    ///     let handle = timer.handle();
+    ///     let sleeper = Local::local(async move { timer.cancellable().await });
+    ///     Local::later().await;
+    ///     handle.cancel();
+    ///     assert!(sleeper.await.is_err());
+    /// });
     /// ```
-    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
-    pub fn do_in_into(
-        when: Duration,
-        action: impl Future<Output = T> + 'static,
-        tq: TaskQueueHandle,
-    ) -> Result<TimerActionOnce<T>, QueueNotFoundError> {
-        let timer_id = Reactor::get().register_timer();
-        let timer = Timer::from_id(timer_id, when);
-        let inner = timer.inner.clone();
-
-        let task = Task::local_into(
-            async move {
-                timer.await;
-                action.await
-            },
-            tq,
-        )?;
+    ///
+    /// [`cancellable`]: struct.Timer.html#method.cancellable
+    pub fn handle(&self) -> TimerHandle {
+        TimerHandle {
+            inner: Rc::downgrade(&self.inner),
+        }
+    }
 
-        Ok(TimerActionOnce {
-            handle: task.detach(),
-            inner,
-        })
+    /// Whether the timer was cancelled through a [`TimerHandle`]
+    ///
+    /// [`TimerHandle`]: struct.TimerHandle.html
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.borrow().cancelled
     }
 
-    /// Creates a [`TimerActionOnce`] that will execute the associated future once at a specific time
+    /// Turns this timer into one that outputs an error if it is cancelled through a
+    /// [`TimerHandle`], instead of the instant it was scheduled for.
     ///
-    /// # Arguments
+    /// [`TimerHandle`]: struct.TimerHandle.html
+    pub fn cancellable(self) -> CancellableTimer {
+        CancellableTimer { timer: self }
+    }
+
+    /// Turns this timer into one that outputs a [`TimerFired`], reporting how late it
+    /// fired in addition to when it was scheduled to.
     ///
-    /// * `when` an [`Instant`] that represents when to execute the action.
-    /// * `action` a Future to be executed at time `when`.
+    /// The lateness is computed from the same timestamp the timer takes to decide whether
+    /// it expired, so pacing loops can adapt to it without reading the clock again.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce};
-    /// use std::time::{Instant, Duration};
+    /// use scipio::{LocalExecutor, Timer};
+    /// use std::time::Duration;
     ///
-    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let when = Instant::now().checked_add(Duration::from_millis(100)).unwrap();
-    ///     let action = TimerActionOnce::do_at(when, async move {
-    ///         println!("Executed once");
-    ///     });
-    ///     action.join().await;
-    /// }).unwrap();
-    /// handle.join().unwrap();
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     let mut period = Duration::from_millis(10);
+    ///     for _ in 0..3 {
+    ///         let fired = Timer::new(period).reporting().await;
+    ///         // Compensate for the lateness in the next period
+    ///         period = Duration::from_millis(10)
+    ///             .checked_sub(fired.lateness())
+    ///             .unwrap_or_default();
+    ///     }
+    /// });
     /// ```
-    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    pub fn do_at(when: Instant, action: impl Future<Output = T> + 'static) -> TimerActionOnce<T> {
+    ///
+    /// [`TimerFired`]: struct.TimerFired.html
+    pub fn reporting(self) -> ReportingTimer {
+        ReportingTimer { timer: self }
+    }
+
+    // Returns the instants at which the timer was scheduled to fire and at which it was
+    // found expired.
+    fn poll_fired(&self, cx: &mut Context<'_>) -> Poll<(Instant, Instant)> {
+        let mut inner = self.inner.borrow_mut();
+
+        let now = inner.now();
+        if now >= inner.deadline() || inner.cancelled {
+            // Deregister the timer from the reactor if needed
+            inner.deregister();
+            Poll::Ready((inner.when, now))
+        } else {
+            // Register the timer in the reactor.
+            inner.register(cx.waker());
+            inner.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Waits until the given instant. Returns right away if it already passed.
+///
+/// This is a shorthand for awaiting a [`Timer::at`], for deadline based code.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{sleep_until, LocalExecutor};
+/// use std::time::{Duration, Instant};
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     let deadline = Instant::now() + Duration::from_millis(10);
+///     sleep_until(deadline).await;
+///     assert!(Instant::now() >= deadline);
+/// });
+/// ```
+///
+/// [`Timer::at`]: struct.Timer.html#method.at
+pub async fn sleep_until(when: Instant) {
+    Timer::at(when).await;
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(_) = inner.waker.take() {
+            // Deregister the timer from the reactor.
+            inner.deregister();
+        }
+    }
+}
+
+impl Future for Timer {
+    type Output = Instant;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.poll_fired(cx).map(|(scheduled, _)| scheduled)
+    }
+}
+
+/// When a [`ReportingTimer`] was scheduled to fire, and when it actually did
+///
+/// [`ReportingTimer`]: struct.ReportingTimer.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimerFired {
+    scheduled: Instant,
+    fired: Instant,
+}
+
+impl TimerFired {
+    /// The instant the timer was scheduled to fire at. This is what [`Timer`] outputs.
+    ///
+    /// [`Timer`]: struct.Timer.html
+    pub fn scheduled(&self) -> Instant {
+        self.scheduled
+    }
+
+    /// The instant the timer was found to be expired
+    pub fn fired(&self) -> Instant {
+        self.fired
+    }
+
+    /// How late the timer fired: the time elapsed between [`scheduled`] and [`fired`]
+    ///
+    /// [`scheduled`]: struct.TimerFired.html#method.scheduled
+    /// [`fired`]: struct.TimerFired.html#method.fired
+    pub fn lateness(&self) -> Duration {
+        self.fired.saturating_duration_since(self.scheduled)
+    }
+}
+
+/// A [`Timer`] that outputs a [`TimerFired`] instead of an [`Instant`].
+///
+/// Created with [`Timer::reporting`]
+///
+/// [`Timer`]: struct.Timer.html
+/// [`TimerFired`]: struct.TimerFired.html
+/// [`Timer::reporting`]: struct.Timer.html#method.reporting
+#[derive(Debug)]
+pub struct ReportingTimer {
+    timer: Timer,
+}
+
+impl ReportingTimer {
+    /// Resets the timer to expire after the new duration of time. See [`Timer::reset`]
+    ///
+    /// [`Timer::reset`]: struct.Timer.html#method.reset
+    pub fn reset(&mut self, dur: Duration) {
+        self.timer.reset(dur)
+    }
+}
+
+impl Future for ReportingTimer {
+    type Output = TimerFired;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.timer
+            .poll_fired(cx)
+            .map(|(scheduled, fired)| TimerFired { scheduled, fired })
+    }
+}
+
+/// A handle to cancel a [`Timer`] from another task, created with [`Timer::handle`].
+///
+/// Handles don't keep their timer alive: cancelling a timer that was dropped does
+/// nothing.
+///
+/// [`Timer`]: struct.Timer.html
+/// [`Timer::handle`]: struct.Timer.html#method.handle
+#[derive(Debug, Clone)]
+pub struct TimerHandle {
+    inner: Weak<RefCell<Inner>>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer, waking up the task waiting on it.
+    pub fn cancel(&self) {
+        if let Some(inner) = self.inner.upgrade() {
+            let mut inner = inner.borrow_mut();
+            inner.cancelled = true;
+            if let Some(waker) = inner.waker.take() {
+                inner.deregister();
+                waker.wake();
+            }
+        }
+    }
+
+    /// Cancels many timers at once, like calling [`cancel`] on each handle would, but
+    /// going to the reactor only once for all of them. Returns how many of the timers
+    /// were armed.
+    ///
+    /// This is the way to go when many timers go away together, for instance the
+    /// timeouts of thousands of connections that close at the same time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{Local, LocalExecutor, Timer, TimerHandle};
+    /// use std::time::Duration;
+    ///
+    /// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+    ///
+    /// ex.run(async {
+    ///     let timers: Vec<_> = (0..100).map(|_| Timer::new(Duration::from_secs(60))).collect();
+    ///     let handles: Vec<_> = timers.iter().map(|t| t.handle()).collect();
+    ///     let sleepers: Vec<_> = timers
+    ///         .into_iter()
+    ///         .map(|t| Local::local(async move { t.cancellable().await }))
+    ///         .collect();
+    ///     Local::later().await;
+    ///     assert_eq!(TimerHandle::cancel_all(&handles), 100);
+    ///     for sleeper in sleepers {
+    ///         assert!(sleeper.await.is_err());
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`cancel`]: struct.TimerHandle.html#method.cancel
+    pub fn cancel_all<'a>(handles: impl IntoIterator<Item = &'a TimerHandle>) -> usize {
+        let (ids, wakers): (Vec<_>, Vec<_>) = handles
+            .into_iter()
+            .filter_map(|handle| handle.inner.upgrade())
+            .filter_map(|inner| inner.borrow_mut().cancel_deferred())
+            .unzip();
+        Reactor::get().remove_timers(ids);
+        let cancelled = wakers.len();
+        for waker in wakers {
+            waker.wake();
+        }
+        cancelled
+    }
+
+    /// Whether the timer was cancelled. Timers that were dropped count as cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner
+            .upgrade()
+            .map_or(true, |inner| inner.borrow().cancelled)
+    }
+}
+
+/// A [`Timer`] that outputs an error if it is cancelled, created with
+/// [`Timer::cancellable`].
+///
+/// [`Timer`]: struct.Timer.html
+/// [`Timer::cancellable`]: struct.Timer.html#method.cancellable
+#[derive(Debug)]
+pub struct CancellableTimer {
+    timer: Timer,
+}
+
+impl CancellableTimer {
+    /// Returns a handle to cancel this timer. See [`Timer::handle`]
+    ///
+    /// [`Timer::handle`]: struct.Timer.html#method.handle
+    pub fn handle(&self) -> TimerHandle {
+        self.timer.handle()
+    }
+}
+
+impl Future for CancellableTimer {
+    type Output = Result<Instant, TimerCancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let timer = &self.timer;
+        timer.poll_fired(cx).map(|(scheduled, _)| {
+            if timer.is_cancelled() {
+                Err(TimerCancelled { scheduled })
+            } else {
+                Ok(scheduled)
+            }
+        })
+    }
+}
+
+/// A stream that yields at a fixed period, created with [`Timer::interval`].
+///
+/// Each item is the [`Instant`] the tick was scheduled for. Ticks are scheduled at fixed
+/// multiples of the period from the first one, so they don't drift when the executor is
+/// late to process them. If the executor falls behind by more than a period, the ticks
+/// it missed are skipped rather than delivered in a burst, and the schedule restarts
+/// from the late tick.
+///
+/// The stream never ends.
+///
+/// [`Timer::interval`]: struct.Timer.html#method.interval
+#[derive(Debug)]
+pub struct TimerInterval {
+    timer: Timer,
+    period: Duration,
+}
+
+impl TimerInterval {
+    /// Returns the period of the stream
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Changes the period of the stream. The next tick happens `period` from now.
+    pub fn set_period(&mut self, period: Duration) {
+        self.period = period;
+        self.timer.reset(period);
+    }
+}
+
+impl Stream for TimerInterval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let (scheduled, fired) = futures::ready!(self.timer.poll_fired(cx));
+        let mut inner = self.timer.inner.borrow_mut();
+        let mut next = scheduled + self.period;
+        // After a suspend the schedule starts over, instead of lagging behind by the
+        // time spent suspended
+        if next <= fired || inner.suspended_since() > Duration::from_secs(0) {
+            next = fired + self.period;
+        }
+        inner.when = next;
+        inner.suspended = Reactor::get().time_suspended();
+        drop(inner);
+        Poll::Ready(Some(scheduled))
+    }
+}
+
+// A timerfd, closed when dropped
+#[derive(Debug)]
+struct TimerFd(RawFd);
+
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// A timer armed in the kernel, with a timerfd, instead of in the userspace timer wheel
+/// of the executor.
+///
+/// Each `KernelTimer` costs a file descriptor and a system call to arm, so they are a
+/// poor fit for the many short timeouts of a busy server, which is what [`Timer`] is
+/// for. They are meant for the few deadlines that are far out, or that need to fire as
+/// close as possible to when they were scheduled to: the kernel wakes the executor up
+/// when the timer expires, with the precision of the kernel's high resolution timers.
+///
+/// Like [`Timer`], it is a future that outputs the [`Instant`] at which it was scheduled
+/// to fire, unless the timerfd fails to be armed or read. [`AutoTimer`] picks between
+/// the two based on how far out the deadline is.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{KernelTimer, LocalExecutor};
+/// use std::time::Duration;
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     KernelTimer::new(Duration::from_millis(10)).unwrap().await.unwrap();
+/// });
+/// ```
+///
+/// [`Timer`]: struct.Timer.html
+/// [`AutoTimer`]: struct.AutoTimer.html
+pub struct KernelTimer {
+    fd: Rc<Async<TimerFd>>,
+    when: Instant,
+    waker: Option<Waker>,
+    // waits for the timerfd to expire, while it is armed
+    expired: Option<Pin<Box<dyn Future<Output = io::Result<()>>>>>,
+}
+
+impl fmt::Debug for KernelTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KernelTimer")
+            .field("fd", &self.fd.as_raw_fd())
+            .field("when", &self.when)
+            .finish()
+    }
+}
+
+impl KernelTimer {
+    /// Creates a timer that expires after the given duration of time. Fails if a timerfd
+    /// can't be created.
+    pub fn new(dur: Duration) -> io::Result<KernelTimer> {
+        let fd = TimerFd(sys::create_timerfd()?);
+        Ok(KernelTimer {
+            fd: Rc::new(Async::new(fd)?),
+            when: Instant::now() + dur,
+            waker: None,
+            expired: None,
+        })
+    }
+
+    /// Resets the timer to expire after the new duration of time. Like with
+    /// [`Timer::reset`], the task polling the timer keeps waiting for it.
+    ///
+    /// [`Timer::reset`]: struct.Timer.html#method.reset
+    pub fn reset(&mut self, dur: Duration) {
+        self.when = Instant::now() + dur;
+        // The timerfd is armed again the next time the timer is polled
+        self.expired = None;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for KernelTimer {
+    type Output = io::Result<Instant>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let now = Instant::now();
+            if now >= self.when {
+                self.expired = None;
+                return Poll::Ready(Ok(self.when));
+            }
+
+            if self.expired.is_none() {
+                if let Err(err) = sys::arm_timerfd(self.fd.as_raw_fd(), self.when - now) {
+                    return Poll::Ready(Err(err));
+                }
+                let fd = self.fd.clone();
+                self.expired = Some(Box::pin(async move {
+                    fd.read_with(|timer| {
+                        let mut expirations = [0u8; 8];
+                        sys::read_fd(timer.0, &mut expirations).map(drop)
+                    })
+                    .await
+                }));
+            }
+
+            match self.expired.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Ready(Err(err)) => {
+                    self.expired = None;
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Ready(Ok(())) => self.expired = None,
+                Poll::Pending => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+/// Deadlines at least this far out are armed in the kernel by [`AutoTimer`]
+///
+/// [`AutoTimer`]: struct.AutoTimer.html
+pub const KERNEL_TIMER_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Where an [`AutoTimer`] is armed
+///
+/// [`AutoTimer`]: struct.AutoTimer.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimerKind {
+    /// In the userspace timer wheel of the executor, like a [`Timer`]
+    ///
+    /// [`Timer`]: struct.Timer.html
+    Wheel,
+    /// In the kernel, like a [`KernelTimer`]
+    ///
+    /// [`KernelTimer`]: struct.KernelTimer.html
+    Kernel,
+}
+
+impl TimerKind {
+    /// Returns the kind of timer [`AutoTimer`] uses for a deadline `dur` from now:
+    /// [`Kernel`] for deadlines at least [`KERNEL_TIMER_THRESHOLD`] away, [`Wheel`] for the
+    /// others.
+    ///
+    /// [`AutoTimer`]: struct.AutoTimer.html
+    /// [`Kernel`]: enum.TimerKind.html#variant.Kernel
+    /// [`Wheel`]: enum.TimerKind.html#variant.Wheel
+    /// [`KERNEL_TIMER_THRESHOLD`]: constant.KERNEL_TIMER_THRESHOLD.html
+    pub fn for_duration(dur: Duration) -> TimerKind {
+        if dur >= KERNEL_TIMER_THRESHOLD {
+            TimerKind::Kernel
+        } else {
+            TimerKind::Wheel
+        }
+    }
+}
+
+#[derive(Debug)]
+enum AutoInner {
+    Wheel(Timer),
+    Kernel(KernelTimer),
+}
+
+/// A timer that is either a [`Timer`] or a [`KernelTimer`], chosen by the runtime
+/// according to how far out its deadline is, unless the caller chooses.
+///
+/// Short timeouts, which are usually many and usually cancelled before they fire, go to
+/// the cheap userspace timer wheel. Long ones go to the kernel, which fires them
+/// precisely without the executor having to keep track of them. If the kernel timer
+/// can't be created or fails later on, the timer wheel is used instead.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{AutoTimer, LocalExecutor, TimerKind};
+/// use std::time::Duration;
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     let timer = AutoTimer::new(Duration::from_millis(10));
+///     assert_eq!(timer.kind(), TimerKind::Wheel);
+///     timer.await;
+/// });
+/// ```
+///
+/// [`Timer`]: struct.Timer.html
+/// [`KernelTimer`]: struct.KernelTimer.html
+#[derive(Debug)]
+pub struct AutoTimer {
+    inner: AutoInner,
+}
+
+impl AutoTimer {
+    /// Creates a timer that expires after the given duration of time, of the kind
+    /// returned by [`TimerKind::for_duration`].
+    ///
+    /// [`TimerKind::for_duration`]: enum.TimerKind.html#method.for_duration
+    pub fn new(dur: Duration) -> AutoTimer {
+        AutoTimer::with_kind(dur, TimerKind::for_duration(dur))
+    }
+
+    /// Creates a timer of the given kind that expires after the given duration of time.
+    pub fn with_kind(dur: Duration, kind: TimerKind) -> AutoTimer {
+        let inner = match kind {
+            TimerKind::Kernel => match KernelTimer::new(dur) {
+                Ok(timer) => AutoInner::Kernel(timer),
+                Err(_) => AutoInner::Wheel(Timer::new(dur)),
+            },
+            TimerKind::Wheel => AutoInner::Wheel(Timer::new(dur)),
+        };
+        AutoTimer { inner }
+    }
+
+    /// Returns where the timer is armed.
+    pub fn kind(&self) -> TimerKind {
+        match self.inner {
+            AutoInner::Wheel(_) => TimerKind::Wheel,
+            AutoInner::Kernel(_) => TimerKind::Kernel,
+        }
+    }
+
+    /// Resets the timer to expire after the new duration of time. The timer stays of
+    /// the same kind.
+    pub fn reset(&mut self, dur: Duration) {
+        match &mut self.inner {
+            AutoInner::Wheel(timer) => timer.reset(dur),
+            AutoInner::Kernel(timer) => timer.reset(dur),
+        }
+    }
+}
+
+impl Future for AutoTimer {
+    type Output = Instant;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let when = match &mut self.inner {
+            AutoInner::Wheel(timer) => return Pin::new(timer).poll(cx),
+            AutoInner::Kernel(timer) => match Pin::new(&mut *timer).poll(cx) {
+                Poll::Ready(Ok(when)) => return Poll::Ready(when),
+                Poll::Ready(Err(_)) => timer.when,
+                Poll::Pending => return Poll::Pending,
+            },
+        };
+        // The timerfd failed, so the rest of the wait happens in the timer wheel
+        let mut timer = Timer::at(when);
+        let res = Pin::new(&mut timer).poll(cx);
+        self.inner = AutoInner::Wheel(timer);
+        res
+    }
+}
+
+/// A timer that fires when the wall clock reaches a given [`SystemTime`], even if the
+/// clock is set in the meantime.
+///
+/// Other timers wait on the monotonic clock, so a deadline computed from the wall clock
+/// drifts from it when the wall clock is stepped, by NTP or by hand: a job meant to run
+/// at 03:00 runs an hour early or late after a one hour step. A `WallClockTimer` is
+/// armed in the kernel against the realtime clock, which tells it when the clock is
+/// set, so it arms itself again for the same calendar time.
+///
+/// It is a future that outputs the [`SystemTime`] at which it was scheduled to fire, or
+/// the error the kernel reported if the timerfd couldn't be armed or waited on. Like
+/// [`KernelTimer`], each one costs a file descriptor.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, WallClockTimer};
+/// use std::time::{Duration, SystemTime};
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     let when = SystemTime::now() + Duration::from_millis(10);
+///     WallClockTimer::at(when).unwrap().await.unwrap();
+///     assert!(SystemTime::now() >= when);
+/// });
+/// ```
+///
+/// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+/// [`KernelTimer`]: struct.KernelTimer.html
+pub struct WallClockTimer {
+    fd: Rc<Async<TimerFd>>,
+    when: SystemTime,
+    clock_changes: u64,
+    waker: Option<Waker>,
+    // waits for the timerfd to expire, while it is armed
+    expired: Option<Pin<Box<dyn Future<Output = io::Result<()>>>>>,
+}
+
+impl fmt::Debug for WallClockTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WallClockTimer")
+            .field("fd", &self.fd.as_raw_fd())
+            .field("when", &self.when)
+            .field("clock_changes", &self.clock_changes)
+            .finish()
+    }
+}
+
+impl WallClockTimer {
+    /// Creates a timer that fires when the wall clock reaches `when`. Fails if a timerfd
+    /// can't be created.
+    pub fn at(when: SystemTime) -> io::Result<WallClockTimer> {
+        let fd = TimerFd(sys::create_realtime_timerfd()?);
+        Ok(WallClockTimer {
+            fd: Rc::new(Async::new(fd)?),
+            when,
+            clock_changes: 0,
+            waker: None,
+            expired: None,
+        })
+    }
+
+    /// Resets the timer to fire when the wall clock reaches `when`. Like with
+    /// [`Timer::reset`], the task polling the timer keeps waiting for it.
+    ///
+    /// [`Timer::reset`]: struct.Timer.html#method.reset
+    pub fn reset_at(&mut self, when: SystemTime) {
+        self.when = when;
+        // The timerfd is armed again the next time the timer is polled
+        self.expired = None;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Returns when the timer fires.
+    pub fn when(&self) -> SystemTime {
+        self.when
+    }
+
+    /// Returns how many times the wall clock was set while the timer was armed.
+    pub fn clock_changes(&self) -> u64 {
+        self.clock_changes
+    }
+}
+
+impl Future for WallClockTimer {
+    type Output = io::Result<SystemTime>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            if SystemTime::now() >= self.when {
+                self.expired = None;
+                return Poll::Ready(Ok(self.when));
+            }
+
+            if self.expired.is_none() {
+                if let Err(err) = sys::arm_timerfd_at(self.fd.as_raw_fd(), self.when) {
+                    return Poll::Ready(Err(err));
+                }
+                let fd = self.fd.clone();
+                self.expired = Some(Box::pin(async move {
+                    fd.read_with(|timer| {
+                        let mut expirations = [0u8; 8];
+                        sys::read_fd(timer.0, &mut expirations).map(drop)
+                    })
+                    .await
+                }));
+            }
+
+            match self.expired.as_mut().unwrap().as_mut().poll(cx) {
+                // The clock was set: the timer has to be armed again for the same time
+                Poll::Ready(Err(err)) if err.raw_os_error() == Some(libc::ECANCELED) => {
+                    self.clock_changes += 1;
+                    self.expired = None;
+                }
+                Poll::Ready(Err(err)) => {
+                    self.expired = None;
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Ready(Ok(())) => self.expired = None,
+                Poll::Pending => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+/// The TimerActionOnce struct provides an ergonomic way to fire an action at a
+/// later point in time.
+///
+/// In practice [`Timer`] is hard to use because it will always block the
+/// current task queue. This is rarely what one wants.
+///
+/// The TimerActionOnce creates a timer in the background and executes an action
+/// when the timer expires. It also provides a convenient way to cancel a timer.
+///
+/// [`Timer`]: struct.Timer
+#[derive(Debug)]
+pub struct TimerActionOnce<T> {
+    handle: JoinHandle<T, ()>,
+    inner: Rc<RefCell<Inner>>,
+    // Shared by every handle returned by `rearm_handle`
+    remote: RearmSender,
+}
+
+// The sender behind the handles returned by `rearm_handle`. The action only holds it
+// weakly, so the task applying the requests goes away once every handle is dropped, and
+// closes the channel when it goes away itself, so the handles fail from then on.
+#[derive(Debug, Default)]
+struct RearmSender(RefCell<sync::Weak<mpsc::UnboundedSender<Instant>>>);
+
+impl Drop for RearmSender {
+    fn drop(&mut self) {
+        if let Some(sender) = self.0.get_mut().upgrade() {
+            sender.close_channel();
+        }
+    }
+}
+
+/// A handle to rearm a [`TimerActionOnce`] from any thread, created with
+/// [`TimerActionOnce::rearm_handle`].
+///
+/// Requests travel to the executor that owns the action and wake it up if needed, so a
+/// control thread or another executor can keep pushing a deadline forward, like the one
+/// of a lease that is renewed remotely.
+///
+/// [`TimerActionOnce`]: struct.TimerActionOnce.html
+/// [`TimerActionOnce::rearm_handle`]: struct.TimerActionOnce.html#method.rearm_handle
+#[derive(Debug, Clone)]
+pub struct RearmHandle {
+    sender: Arc<mpsc::UnboundedSender<Instant>>,
+}
+
+impl RearmHandle {
+    /// Rearms the action so it fires at `when`, like [`TimerActionOnce::rearm_at`] does.
+    ///
+    /// Returns false if the action is gone, because it was dropped or its executor went
+    /// away.
+    ///
+    /// [`TimerActionOnce::rearm_at`]: struct.TimerActionOnce.html#method.rearm_at
+    pub fn rearm_at(&self, when: Instant) -> bool {
+        self.sender.unbounded_send(when).is_ok()
+    }
+
+    /// Rearms the action so it fires `dur` from now, like [`TimerActionOnce::rearm_in`]
+    /// does.
+    ///
+    /// Returns false if the action is gone, because it was dropped or its executor went
+    /// away.
+    ///
+    /// [`TimerActionOnce::rearm_in`]: struct.TimerActionOnce.html#method.rearm_in
+    pub fn rearm_in(&self, dur: Duration) -> bool {
+        self.rearm_at(Instant::now() + dur)
+    }
+}
+
+/// The TimerActionRepeat struct provides an ergonomic way to fire a repeated action at
+/// specified intervals, without having to fire new [`TimerActionOnce`] events
+///
+/// `T` is what [`join`] returns once the action stops: nothing for actions that can only
+/// say when to stop, or the error that stopped actions created with [`try_repeat_into`]
+/// and friends.
+///
+/// [`TimerActionOnce`]: struct.TimerActionOnce
+/// [`join`]: struct.TimerActionRepeat.html#method.join
+/// [`try_repeat_into`]: struct.TimerActionRepeat.html#method.try_repeat_into
+#[derive(Debug)]
+pub struct TimerActionRepeat<T = ()> {
+    handle: JoinHandle<T, ()>,
+    timer_id: u64,
+    state: Rc<RepeatState>,
+}
+
+/// How a [`TimerActionRepeat`] schedules the next execution of its action
+///
+/// [`TimerActionRepeat`]: struct.TimerActionRepeat.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatSchedule {
+    /// The next execution happens the returned period after the previous one finishes,
+    /// so the time the action takes adds to the period.
+    FixedDelay,
+
+    /// The next execution happens the returned period after the previous one was due,
+    /// so executions stay on a steady grid no matter how long the action takes. The
+    /// [`MissedTicks`] policy decides what happens when the action falls behind it.
+    ///
+    /// [`MissedTicks`]: enum.MissedTicks.html
+    FixedRate(MissedTicks),
+}
+
+impl Default for RepeatSchedule {
+    fn default() -> Self {
+        RepeatSchedule::FixedDelay
+    }
+}
+
+/// What a fixed rate [`TimerActionRepeat`] does with the executions it missed because
+/// the previous ones ran late.
+///
+/// [`TimerActionRepeat`]: struct.TimerActionRepeat.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTicks {
+    /// Executes the missed ticks back to back until it catches up with the schedule.
+    Burst,
+
+    /// Drops the missed ticks, and executes at the next tick of the schedule.
+    Skip,
+
+    /// Executes right away, and moves the schedule so the following ticks are a full
+    /// period apart from this one.
+    Delay,
+}
+
+impl RepeatSchedule {
+    // When the action executes next, given when its last execution was due, when it
+    // finished, and the period it returned.
+    fn next(self, due: Instant, finished: Instant, period: Duration) -> Instant {
+        let next = due + period;
+        match self {
+            RepeatSchedule::FixedDelay => finished + period,
+            RepeatSchedule::FixedRate(_) if next > finished => next,
+            RepeatSchedule::FixedRate(MissedTicks::Burst) => next,
+            RepeatSchedule::FixedRate(MissedTicks::Delay) => finished,
+            RepeatSchedule::FixedRate(MissedTicks::Skip) => {
+                if period.as_nanos() == 0 {
+                    return finished;
+                }
+                // The next tick is as far after `finished` as what is left of the period
+                // it falls in. Counting the ticks missed instead could overflow for tiny
+                // periods. The remainder is below the period, which is at most the time
+                // elapsed, so it fits.
+                let into_tick = (finished - due).as_nanos() % period.as_nanos();
+                finished + period - Duration::from_nanos(into_tick as u64)
+            }
+        }
+    }
+}
+
+// A random number between -1.0 and 1.0
+fn jitter_sample() -> f64 {
+    Reactor::get().rng().f64() * 2.0 - 1.0
+}
+
+// Moves `at` earlier or later by a random fraction of `period` of up to `jitter`
+fn jittered(at: Instant, period: Duration, jitter: f64) -> Instant {
+    let jitter = jitter.max(0.0).min(1.0);
+    if jitter == 0.0 {
+        return at;
+    }
+    let offset = jitter * jitter_sample();
+    let shift = period.mul_f64(offset.abs());
+    if offset < 0.0 {
+        at.checked_sub(shift).unwrap_or(at)
+    } else {
+        at + shift
+    }
+}
+
+// What the action of a TimerActionRepeat returns: either the period until it executes
+// again, or what the action stops with
+trait RepeatOutcome {
+    type Output;
+
+    fn into_period(self) -> Result<Option<Duration>, Self::Output>;
+
+    // What the action stops with when it is done without failing
+    fn done() -> Self::Output;
+}
+
+impl RepeatOutcome for Option<Duration> {
+    type Output = ();
+
+    fn into_period(self) -> Result<Option<Duration>, ()> {
+        Ok(self)
+    }
+
+    fn done() {}
+}
+
+impl<E> RepeatOutcome for Result<Option<Duration>, E> {
+    type Output = Result<(), E>;
+
+    fn into_period(self) -> Result<Option<Duration>, Result<(), E>> {
+        self.map_err(Err)
+    }
+
+    fn done() -> Result<(), E> {
+        Ok(())
+    }
+}
+
+// What a TimerActionRepeat shares with the task that executes its action
+#[derive(Debug)]
+struct RepeatState {
+    schedule: RepeatSchedule,
+
+    // How many times the action runs at most, and how many times it ran so far
+    limit: Option<u64>,
+    executions: Cell<u64>,
+
+    // Whether the action is paused, and the task waiting to be resumed
+    paused: Cell<bool>,
+    waker: RefCell<Option<Waker>>,
+
+    jitter: Cell<f64>,
+
+    // A period set with `set_period`, that overrides the one the action returns
+    period: Cell<Option<Duration>>,
+
+    // When the last execution was due and when it finished, to compute the next one
+    // again if the period changes
+    last: Cell<(Instant, Instant)>,
+
+    // The timer of the next execution, while the task waits for it
+    timer: RefCell<Option<Rc<RefCell<Inner>>>>,
+}
+
+impl RepeatState {
+    fn new(schedule: RepeatSchedule, limit: Option<u64>) -> RepeatState {
+        let now = Instant::now();
+        RepeatState {
+            schedule,
+            limit,
+            executions: Cell::new(0),
+            paused: Cell::new(false),
+            waker: RefCell::new(None),
+            jitter: Cell::new(0.0),
+            period: Cell::new(None),
+            last: Cell::new((now, now)),
+            timer: RefCell::new(None),
+        }
+    }
+
+    // Moves the next execution, if the task is waiting for it
+    fn rearm_at(&self, when: Instant) {
+        if let Some(inner) = self.timer.borrow().as_ref() {
+            inner.borrow_mut().reset_at(when);
+        }
+    }
+
+    async fn wait_resumed(&self) {
+        poll_fn(|cx| {
+            if self.paused.get() {
+                *self.waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await
+    }
+}
+
+/// The TimerActionSchedule struct fires an action at times given by a schedule, like a
+/// [`CronSchedule`] or a function that computes the next time, instead of at a period.
+///
+/// It drives the action with a single reactor timer, like [`TimerActionRepeat`] does.
+///
+/// [`CronSchedule`]: struct.CronSchedule.html
+/// [`TimerActionRepeat`]: struct.TimerActionRepeat.html
+#[derive(Debug)]
+pub struct TimerActionSchedule {
+    handle: JoinHandle<(), ()>,
+    timer_id: u64,
+}
+
+impl<T: 'static> TimerActionOnce<T> {
+    /// Creates a [`TimerActionOnce`] that will execute the associated future once after some
+    /// time is passed
+    ///
+    /// # Arguments
+    ///
+    /// * `when` a [`Duration`] that represents when to execute the action.
+    /// * `action` a Future to be executed after `when` is elapsed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
+    ///         println!("Executed once");
+    ///     });
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    pub fn do_in(when: Duration, action: impl Future<Output = T> + 'static) -> TimerActionOnce<T> {
+        Self::do_in_into(when, action, Local::current_task_queue()).unwrap()
+    }
+
+    /// Creates a [`TimerActionOnce`] that will execute the associated future once after some
+    /// time is passed in a specific Task Queue
+    ///
+    /// # Arguments
+    ///
+    /// * `when` a [`Duration`] that represents when to execute the action.
+    /// * `action` a Future to be executed after `when` is elapsed.
+    /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce, Local, Latency};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
+    ///     let action = TimerActionOnce::do_in_into(Duration::from_millis(100), async move {
+    ///         println!("Executed once");
+    ///     }, tq).unwrap();
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
+    pub fn do_in_into(
+        when: Duration,
+        action: impl Future<Output = T> + 'static,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionOnce<T>, QueueNotFoundError> {
+        let timer_id = Reactor::get().register_timer();
+        let timer = Timer::from_id(timer_id, when);
+        let inner = timer.inner.clone();
+
+        let task = Task::local_into(
+            async move {
+                timer.await;
+                action.await
+            },
+            tq,
+        )?;
+
+        Ok(TimerActionOnce {
+            handle: task.detach(),
+            inner,
+            remote: RearmSender::default(),
+        })
+    }
+
+    /// Creates a [`TimerActionOnce`] that will execute the associated future once at a specific time
+    ///
+    /// # Arguments
+    ///
+    /// * `when` an [`Instant`] that represents when to execute the action.
+    /// * `action` a Future to be executed at time `when`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::{Instant, Duration};
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let when = Instant::now().checked_add(Duration::from_millis(100)).unwrap();
+    ///     let action = TimerActionOnce::do_at(when, async move {
+    ///         println!("Executed once");
+    ///     });
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    pub fn do_at(when: Instant, action: impl Future<Output = T> + 'static) -> TimerActionOnce<T> {
         Self::do_at_into(when, action, Local::current_task_queue()).unwrap()
     }
 
-    /// Creates a [`TimerActionOnce`] that will execute the associated future once at a specific time
-    /// in a specific Task Queue.
+    /// Creates a [`TimerActionOnce`] that will execute the associated future once at a specific time
+    /// in a specific Task Queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `when` an [`Instant`] that represents when to execute the action.
+    /// * `action` a Future to be executed at time `when`.
+    /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce, Local, Latency};
+    /// use std::time::{Instant, Duration};
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
+    ///     let when = Instant::now().checked_add(Duration::from_millis(100)).unwrap();
+    ///     let action = TimerActionOnce::do_at_into(when, async move {
+    ///         println!("Executed once");
+    ///     }, tq).unwrap();
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
+    pub fn do_at_into(
+        when: Instant,
+        action: impl Future<Output = T> + 'static,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionOnce<T>, QueueNotFoundError> {
+        Self::do_in_into(when.saturating_duration_since(Instant::now()), action, tq)
+    }
+
+    /// Cancel an existing [`TimerActionOnce`] and waits for it to return
+    ///
+    /// If you want to cancel the timer but doesn't want to .await on it,
+    /// prefer [`destroy`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
+    ///         println!("Will not execute this");
+    ///     });
+    ///     action.cancel().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`destroy`]: struct.TimerActionOnce.html#method.destroy
+    pub async fn cancel(self) {
+        self.destroy();
+        self.join().await;
+    }
+
+    /// Cancel an existing [`TimerActionOnce`], without waiting for it to return
+    ///
+    /// This is a non-async version of [`cancel`]. It will remove the timer if
+    /// it hasn't fired already and destroy the [`TimerActionOnce`] releasing the resources
+    /// associated with it, but without blocking the current task. It is still possible
+    /// to [`join`] the task if needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
+    ///         println!("Will not execute this");
+    ///     });
+    ///     action.destroy();
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`cancel`]: struct.TimerActionOnce.html#method.cancel
+    /// [`join`]: struct.TimerActionOnce.html#method.join
+    pub fn destroy(&self) {
+        Reactor::get().remove_timer(self.inner.borrow().id);
+        self.handle.cancel();
+    }
+
+    /// Waits for a [`TimerActionOnce`] to return
+    ///
+    /// Returns an [`Option`] with value None if the task was canceled and Some if
+    /// the action finished successfuly
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
+    ///         println!("Execute this in 100ms");
+    ///     });
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
+    pub async fn join(self) -> Option<T> {
+        self.handle.await
+    }
+
+    /// Waits up to `timeout` for a [`TimerActionOnce`] to return, like [`join`] does.
+    ///
+    /// Fails with [`DeadlineExceeded`] if the action didn't return in time, in which case
+    /// it is left alone: it can still be canceled, or joined again. Once this returned the
+    /// result of the action, joining again returns `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let mut action = TimerActionOnce::do_in(Duration::from_secs(10), async move {
+    ///         println!("Execute this in 10s");
+    ///     });
+    ///     if action.join_timeout(Duration::from_millis(10)).await.is_err() {
+    ///         action.cancel().await;
+    ///     }
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`join`]: struct.TimerActionOnce.html#method.join
+    /// [`DeadlineExceeded`]: struct.DeadlineExceeded.html
+    pub async fn join_timeout(&mut self, timeout: Duration) -> Result<Option<T>, DeadlineExceeded> {
+        Local::with_timeout(timeout, &mut self.handle).await
+    }
+
+    /// Rearm a [`TimerActionOnce`], so it fires in the specified [`Duration`] from now
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
+    ///         println!("hello");
+    ///     });
+    ///     action.rearm_in(Duration::from_millis(100));
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+    pub fn rearm_in(&self, dur: Duration) {
+        let mut inner = self.inner.borrow_mut();
+        inner.reset(dur);
+    }
+
+    /// Rearm a [`TimerActionOnce`], so it fires at the specified [`Instant`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
+    ///         println!("hello");
+    ///     });
+    ///     action.rearm_at(Instant::now());
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
+    pub fn rearm_at(&self, when: Instant) {
+        let mut inner = self.inner.borrow_mut();
+        inner.reset_at(when);
+    }
+
+    /// Returns a handle that can rearm this [`TimerActionOnce`] from any thread. See
+    /// [`RearmHandle`]
+    ///
+    /// The requests are applied by a task spawned in the current task queue the first
+    /// time a handle is requested. It goes away with the action, or once every handle is
+    /// dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
+    ///         println!("lease expired");
+    ///     });
+    ///     let rearm = action.rearm_handle();
+    ///     std::thread::spawn(move || {
+    ///         rearm.rearm_in(Duration::from_millis(100));
+    ///     })
+    ///     .join()
+    ///     .unwrap();
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`RearmHandle`]: struct.RearmHandle.html
+    pub fn rearm_handle(&self) -> RearmHandle {
+        let mut remote = self.remote.0.borrow_mut();
+        if let Some(sender) = remote.upgrade() {
+            return RearmHandle { sender };
+        }
+
+        let (sender, mut receiver) = mpsc::unbounded::<Instant>();
+        let inner = Rc::downgrade(&self.inner);
+        Task::local(async move {
+            // A single bridge serves every request, so remote wakeups only cost one
+            // socket per action
+            let _ = bridge(async move {
+                while let Some(when) = receiver.next().await {
+                    match inner.upgrade() {
+                        Some(inner) => inner.borrow_mut().reset_at(when),
+                        None => break,
+                    }
+                }
+            })
+            .await;
+        })
+        .detach();
+
+        let sender = Arc::new(sender);
+        *remote = Arc::downgrade(&sender);
+        RearmHandle { sender }
+    }
+}
+
+impl TimerActionRepeat {
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly in a specific
+    /// Task Queue until returns None
+    ///
+    /// # Arguments
+    ///
+    /// * `action_gen` a Future to be executed repeatedly. The Future's return value must be
+    /// Option<Duration>. If [`Some`], It will execute again after Duration elapses. If `None`,
+    /// it stops.
+    /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{LocalExecutor, TimerActionRepeat, Latency, Local};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
+    ///     let action = TimerActionRepeat::repeat_into(|| async move {
+    ///         println!("Execute this!");
+    ///         Some(Duration::from_millis(100))
+    ///     }, tq).unwrap();
+    ///     action.join().await; // this never returns
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
+    pub fn repeat_into<G, F>(
+        action_gen: G,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionRepeat, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        Self::repeat_with_schedule_into(action_gen, RepeatSchedule::FixedDelay, tq)
+    }
+
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly in a
+    /// specific Task Queue until it returns None, following the given [`RepeatSchedule`].
+    ///
+    /// # Arguments
+    ///
+    /// * `action_gen` a Future to be executed repeatedly. The Future's return value must be
+    /// Option<Duration>. If [`Some`], It will execute again after the period, counted as
+    /// `schedule` says. If `None`, it stops.
+    /// * `schedule` the [`RepeatSchedule`] that decides when the next execution happens.
+    /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{Latency, Local, LocalExecutor, MissedTicks, RepeatSchedule, TimerActionRepeat};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "metrics");
+    ///     // Flushes every second on the second, however long flushing takes
+    ///     let action = TimerActionRepeat::repeat_with_schedule_into(|| async move {
+    ///         println!("flushing metrics");
+    ///         Some(Duration::from_secs(1))
+    ///     }, RepeatSchedule::FixedRate(MissedTicks::Skip), tq).unwrap();
+    ///     action.join().await; // this never returns
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`RepeatSchedule`]: enum.RepeatSchedule
+    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
+    pub fn repeat_with_schedule_into<G, F>(
+        action_gen: G,
+        schedule: RepeatSchedule,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionRepeat, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        Self::spawn_into(action_gen, schedule, None, tq)
+    }
+
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future at most `n`
+    /// times in a specific Task Queue, or until it returns None.
+    ///
+    /// Once the action ran `n` times, [`join`] returns `Some(())`, as if the last
+    /// execution had returned None.
+    ///
+    /// # Arguments
+    ///
+    /// * `action_gen` a Future to be executed repeatedly. The Future's return value must be
+    /// Option<Duration>. If [`Some`], It will execute again after Duration elapses, unless it
+    /// already executed `n` times. If `None`, it stops.
+    /// * `n` how many times to execute the action at most.
+    /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use scipio::{LocalExecutor, TimerActionRepeat, Latency, Local};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
+    ///     let action = TimerActionRepeat::repeat_n_into(|| async move {
+    ///         println!("Execute this three times");
+    ///         Some(Duration::from_millis(10))
+    ///     }, 3, tq).unwrap();
+    ///     assert!(action.join().await.is_some());
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`join`]: struct.TimerActionRepeat.html#method.join
+    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
+    pub fn repeat_n_into<G, F>(
+        action_gen: G,
+        n: u64,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionRepeat, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        Self::spawn_into(action_gen, RepeatSchedule::FixedDelay, Some(n), tq)
+    }
+
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future at most `n`
+    /// times, or until it returns None. See [`repeat_n_into`]
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`repeat_n_into`]: struct.TimerActionRepeat.html#method.repeat_n_into
+    pub fn repeat_n<G, F>(action_gen: G, n: u64) -> TimerActionRepeat
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        Self::repeat_n_into(action_gen, n, Local::current_task_queue()).unwrap()
+    }
+
+    fn spawn_into<G, F, R>(
+        action_gen: G,
+        schedule: RepeatSchedule,
+        limit: Option<u64>,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionRepeat<R::Output>, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = R> + 'static,
+        R: RepeatOutcome + 'static,
+        R::Output: 'static,
+    {
+        let timer_id = Reactor::get().register_timer();
+        let state = Rc::new(RepeatState::new(schedule, limit));
+        let task_state = state.clone();
+
+        let task = Task::local_into(
+            async move {
+                let state = task_state;
+                let mut due = Instant::now();
+                loop {
+                    if Some(state.executions.get()) == state.limit {
+                        break;
+                    }
+                    state.wait_resumed().await;
+                    let outcome = action_gen().await;
+                    state.executions.set(state.executions.get() + 1);
+                    let period = match outcome.into_period() {
+                        Ok(period) => period,
+                        Err(stopped) => return stopped,
+                    };
+                    if Some(state.executions.get()) == state.limit {
+                        break;
+                    }
+                    if let Some(period) = period {
+                        let period = state.period.get().unwrap_or(period);
+                        let finished = Instant::now();
+                        state.last.set((due, finished));
+                        due = schedule.next(due, finished, period);
+                        // the jitter doesn't move `due`, so fixed rates don't drift
+                        let fire_at = jittered(due, period, state.jitter.get());
+                        let timer = Timer::from_id_at(timer_id, fire_at);
+                        *state.timer.borrow_mut() = Some(timer.inner.clone());
+                        let suspended = Reactor::get().time_suspended();
+                        let fired = timer.await;
+                        state.timer.borrow_mut().take();
+                        // a timer that was moved starts the schedule over from there, and
+                        // so does one that fired after a suspend, instead of lagging behind
+                        // or catching up on the ticks missed while suspended
+                        if Reactor::get().time_suspended() != suspended {
+                            due = Instant::now();
+                        } else if fired != fire_at {
+                            due = fired;
+                        }
+                    } else {
+                        break;
+                    }
+                }
+                R::done()
+            },
+            tq,
+        )?;
+
+        Ok(TimerActionRepeat {
+            handle: task.detach(),
+            timer_id: timer_id,
+            state,
+        })
+    }
+
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly until
+    /// it returns None
+    ///
+    /// # Arguments
+    ///
+    /// * `action_gen` a Future to be executed repeatedly. The Future's return value must be
+    /// Option<Duration>. If [`Some`], It will execute again after Duration elapses. If `None`,
+    /// it stops.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{LocalExecutor, TimerActionRepeat};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionRepeat::repeat(|| async move {
+    ///         println!("Execute this!");
+    ///         Some(Duration::from_millis(100))
+    ///     });
+    ///     action.join().await; // this never returns
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    pub fn repeat<G, F>(action_gen: G) -> TimerActionRepeat
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        Self::repeat_into(action_gen, Local::current_task_queue()).unwrap()
+    }
+
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly until
+    /// it returns None, following the given [`RepeatSchedule`]. See
+    /// [`repeat_with_schedule_into`]
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`RepeatSchedule`]: enum.RepeatSchedule
+    /// [`repeat_with_schedule_into`]: struct.TimerActionRepeat.html#method.repeat_with_schedule_into
+    pub fn repeat_with_schedule<G, F>(action_gen: G, schedule: RepeatSchedule) -> TimerActionRepeat
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        Self::repeat_with_schedule_into(action_gen, schedule, Local::current_task_queue()).unwrap()
+    }
+}
+
+impl<E: 'static> TimerActionRepeat<Result<(), E>> {
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly in a
+    /// specific Task Queue until it returns `Ok(None)` or fails.
+    ///
+    /// Once the action fails it is not executed again, and [`join`] returns the error.
     ///
     /// # Arguments
     ///
-    /// * `when` an [`Instant`] that represents when to execute the action.
-    /// * `action` a Future to be executed at time `when`.
+    /// * `action_gen` a Future to be executed repeatedly. The Future's return value must be
+    /// Result<Option<Duration>, E>. If `Ok(Some)`, It will execute again after Duration
+    /// elapses. If `Ok(None)`, it stops. If `Err`, it stops with that error.
     /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce, Local, Latency};
-    /// use std::time::{Instant, Duration};
+    /// use scipio::{LocalExecutor, TimerActionRepeat, Latency, Local};
+    /// use std::io;
+    /// use std::time::Duration;
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
-    ///     let when = Instant::now().checked_add(Duration::from_millis(100)).unwrap();
-    ///     let action = TimerActionOnce::do_at_into(when, async move {
-    ///         println!("Executed once");
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "flush");
+    ///     let action = TimerActionRepeat::try_repeat_into(|| async move {
+    ///         // flushing failed
+    ///         Err(io::Error::new(io::ErrorKind::Other, "disk full"))
     ///     }, tq).unwrap();
-    ///     action.join().await;
+    ///     let err = action.join().await.unwrap().unwrap_err();
+    ///     assert_eq!(err.to_string(), "disk full");
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`join`]: struct.TimerActionRepeat.html#method.join
     /// [`TaskQueueHandle`]: struct.TaskQueueHandle
-    pub fn do_at_into(
-        when: Instant,
-        action: impl Future<Output = T> + 'static,
+    pub fn try_repeat_into<G, F>(
+        action_gen: G,
         tq: TaskQueueHandle,
-    ) -> Result<TimerActionOnce<T>, QueueNotFoundError> {
-        let now = Instant::now();
-        let dur = {
-            if when > now {
-                when.duration_since(now)
-            } else {
-                Duration::from_micros(0)
-            }
-        };
-        Self::do_in_into(dur, action, tq)
+    ) -> Result<TimerActionRepeat<Result<(), E>>, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Result<Option<Duration>, E>> + 'static,
+    {
+        Self::try_repeat_with_schedule_into(action_gen, RepeatSchedule::FixedDelay, tq)
     }
 
-    /// Cancel an existing [`TimerActionOnce`] and waits for it to return
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly in a
+    /// specific Task Queue until it returns `Ok(None)` or fails, following the given
+    /// [`RepeatSchedule`]. See [`try_repeat_into`]
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`RepeatSchedule`]: enum.RepeatSchedule
+    /// [`try_repeat_into`]: struct.TimerActionRepeat.html#method.try_repeat_into
+    pub fn try_repeat_with_schedule_into<G, F>(
+        action_gen: G,
+        schedule: RepeatSchedule,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionRepeat<Result<(), E>>, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Result<Option<Duration>, E>> + 'static,
+    {
+        TimerActionRepeat::<()>::spawn_into(action_gen, schedule, None, tq)
+    }
+
+    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly
+    /// until it returns `Ok(None)` or fails. See [`try_repeat_into`]
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`try_repeat_into`]: struct.TimerActionRepeat.html#method.try_repeat_into
+    pub fn try_repeat<G, F>(action_gen: G) -> TimerActionRepeat<Result<(), E>>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Result<Option<Duration>, E>> + 'static,
+    {
+        Self::try_repeat_into(action_gen, Local::current_task_queue()).unwrap()
+    }
+}
+
+impl<T> TimerActionRepeat<T> {
+    /// Cancel an existing [`TimerActionRepeat`] and waits for it to return
     ///
     /// If you want to cancel the timer but doesn't want to .await on it,
     /// prefer [`destroy`].
@@ -340,307 +2271,1036 @@ impl<T: 'static> TimerActionOnce<T> {
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use scipio::{LocalExecutor, TimerActionRepeat};
     /// use std::time::Duration;
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
-    ///         println!("Will not execute this");
+    ///     let action = TimerActionRepeat::repeat(|| async move {
+    ///         Some(Duration::from_millis(100))
     ///     });
     ///     action.cancel().await;
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    /// [`destroy`]: struct.TimerActionOnce.html#method.destroy
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`destroy`]: struct.TimerActionRepeat.html#method.destroy
     pub async fn cancel(self) {
         self.destroy();
         self.join().await;
     }
 
-    /// Cancel an existing [`TimerActionOnce`], without waiting for it to return
+    /// Cancel an existing [`TimerActionRepeat`], without waiting for it to return
     ///
     /// This is a non-async version of [`cancel`]. It will remove the timer if
-    /// it hasn't fired already and destroy the [`TimerActionOnce`] releasing the resources
+    /// it hasn't fired already and destroy the [`TimerActionRepeat`] releasing the resources
     /// associated with it, but without blocking the current task. It is still possible
     /// to [`join`] the task if needed.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use scipio::{LocalExecutor, TimerActionRepeat};
     /// use std::time::Duration;
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
-    ///         println!("Will not execute this");
+    ///     let action = TimerActionRepeat::repeat(|| async move {
+    ///         Some(Duration::from_millis(100))
     ///     });
     ///     action.destroy();
-    ///     action.join().await;
+    ///     let v = action.join().await;
+    ///     assert!(v.is_none())
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    /// [`cancel`]: struct.TimerActionOnce.html#method.cancel
-    /// [`join`]: struct.TimerActionOnce.html#method.join
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`cancel`]: struct.TimerActionRepeat.html#method.cancel
+    /// [`join`]: struct.TimerActionRepeat.html#method.join
     pub fn destroy(&self) {
-        Reactor::get().remove_timer(self.inner.borrow().id);
+        Reactor::get().remove_timer(self.timer_id);
         self.handle.cancel();
     }
 
-    /// Waits for a [`TimerActionOnce`] to return
+    /// Stops executing the action, without destroying the [`TimerActionRepeat`] or the
+    /// state the action captured, until [`resume`] is called.
     ///
-    /// Returns an [`Option`] with value None if the task was canceled and Some if
-    /// the action finished successfuly
+    /// An execution of the action that is already under way runs to completion. Pausing
+    /// an action that is paused already does nothing.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// use scipio::{LocalExecutor, TimerActionRepeat};
     /// use std::time::Duration;
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
-    ///         println!("Execute this in 100ms");
+    ///     let action = TimerActionRepeat::repeat(|| async move {
+    ///         println!("Execute this!");
+    ///         Some(Duration::from_millis(100))
     ///     });
-    ///     action.join().await;
+    ///     action.pause();
+    ///     assert!(action.is_paused());
+    ///     action.resume();
+    ///     action.cancel().await;
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    /// [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
-    pub async fn join(self) -> Option<T> {
-        self.handle.await
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`resume`]: struct.TimerActionRepeat.html#method.resume
+    pub fn pause(&self) {
+        self.state.paused.set(true);
     }
 
-    /// Rearm a [`TimerActionOnce`], so it fires in the specified [`Duration`] from now
+    /// Resumes executing an action stopped with [`pause`], picking its schedule back up:
+    /// if the action became due while it was paused it executes right away, and
+    /// otherwise it waits for the rest of its period.
+    ///
+    /// [`pause`]: struct.TimerActionRepeat.html#method.pause
+    pub fn resume(&self) {
+        self.state.paused.set(false);
+        if let Some(waker) = self.state.waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+
+    /// Whether the action is paused
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.get()
+    }
+
+    /// How many times the action was executed so far
+    pub fn executions(&self) -> u64 {
+        self.state.executions.get()
+    }
+
+    /// Moves every following execution earlier or later by a random fraction of its
+    /// period of up to `jitter`, clamped between `0.0` and `1.0`.
+    ///
+    /// Actions created together, like one per connection when a server starts, would
+    /// otherwise keep firing in the same reactor tick for as long as they run. A little
+    /// jitter spreads them out. With a fixed rate [`RepeatSchedule`], executions still
+    /// stay on their grid on average, they only drift around it.
     ///
     /// # Examples
     ///
+    /// ```no_run
+    /// use scipio::{LocalExecutor, TimerActionRepeat};
+    /// use std::time::Duration;
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let action = TimerActionRepeat::repeat(|| async move {
+    ///         println!("Execute this!");
+    ///         Some(Duration::from_secs(1))
+    ///     });
+    ///     // every 900ms to 1.1s
+    ///     action.set_jitter(0.1);
+    ///     action.join().await; // this never returns
+    /// }).unwrap();
+    /// handle.join().unwrap();
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce};
+    /// [`RepeatSchedule`]: enum.RepeatSchedule.html
+    pub fn set_jitter(&self, jitter: f64) {
+        self.state.jitter.set(jitter.max(0.0).min(1.0));
+    }
+
+    /// The jitter set with [`set_jitter`]
+    ///
+    /// [`set_jitter`]: struct.TimerActionRepeat.html#method.set_jitter
+    pub fn jitter(&self) -> f64 {
+        self.state.jitter.get()
+    }
+
+    /// Changes the period of the action, overriding the one it returns from now on.
+    ///
+    /// The execution the action is waiting for is moved to the new period after the
+    /// previous one, as its [`RepeatSchedule`] computes it, so a heartbeat whose interval
+    /// is configured again adjusts right away. If that time already passed the action
+    /// executes right away.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use scipio::{LocalExecutor, TimerActionRepeat};
     /// use std::time::Duration;
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
-    ///         println!("hello");
+    ///     let action = TimerActionRepeat::repeat(|| async move {
+    ///         println!("heartbeat");
+    ///         Some(Duration::from_secs(10))
     ///     });
-    ///     action.rearm_in(Duration::from_millis(100));
+    ///     // the configuration changed
+    ///     action.set_period(Duration::from_secs(1));
+    ///     action.join().await; // this never returns
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+    /// [`RepeatSchedule`]: enum.RepeatSchedule.html
+    pub fn set_period(&self, period: Duration) {
+        self.state.period.set(Some(period));
+        let (due, finished) = self.state.last.get();
+        self.state
+            .rearm_at(self.state.schedule.next(due, finished, period));
+    }
+
+    /// Goes back to the periods the action returns, after [`set_period`]. The execution
+    /// the action is waiting for keeps its time.
+    ///
+    /// [`set_period`]: struct.TimerActionRepeat.html#method.set_period
+    pub fn clear_period(&self) {
+        self.state.period.set(None);
+    }
+
+    /// The period set with [`set_period`], if any
+    ///
+    /// [`set_period`]: struct.TimerActionRepeat.html#method.set_period
+    pub fn period(&self) -> Option<Duration> {
+        self.state.period.get()
+    }
+
+    /// Moves the next execution of the action to `dur` from now, like
+    /// [`TimerActionOnce::rearm_in`] does. The executions after it follow the period as
+    /// usual.
+    ///
+    /// Does nothing if the action is executing, or paused before executing.
+    ///
+    /// [`TimerActionOnce::rearm_in`]: struct.TimerActionOnce.html#method.rearm_in
     pub fn rearm_in(&self, dur: Duration) {
-        let mut inner = self.inner.borrow_mut();
-        inner.reset(dur);
+        self.state.rearm_at(Instant::now() + dur);
     }
 
-    /// Rearm a [`TimerActionOnce`], so it fires at the specified [`Instant`]
+    /// Waits for a [`TimerActionRepeat`] to return
+    ///
+    /// Returns an [`Option`] with value None if the task was canceled and Some(()) if
+    /// the action finished successfuly. Actions created with [`try_repeat_into`] and
+    /// friends return Some with the error they failed with, if they did.
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionOnce};
-    /// use std::time::{Duration, Instant};
+    /// use scipio::{LocalExecutor, TimerActionRepeat};
+    /// use std::time::Duration;
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionOnce::do_in(Duration::from_millis(100), async move {
-    ///         println!("hello");
+    ///     let action = TimerActionRepeat::repeat(|| async move {
+    ///         None
     ///     });
-    ///     action.rearm_at(Instant::now());
+    ///     let v = action.join().await;
+    ///     assert!(v.is_some())
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`TimerActionOnce`]: struct.TimerActionOnce
-    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
-    pub fn rearm_at(&self, when: Instant) {
-        let now = Instant::now();
-        let dur = {
-            if when > now {
-                when.duration_since(now)
-            } else {
-                Duration::from_micros(0)
-            }
-        };
-        self.rearm_in(dur);
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
+    /// [`try_repeat_into`]: struct.TimerActionRepeat.html#method.try_repeat_into
+    pub async fn join(self) -> Option<T> {
+        self.handle.await
+    }
+
+    /// Waits up to `timeout` for a [`TimerActionRepeat`] to return, like [`join`] does.
+    ///
+    /// Fails with [`DeadlineExceeded`] if the action didn't return in time, in which case
+    /// it keeps repeating: it can still be canceled, or joined again.
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
+    /// [`join`]: struct.TimerActionRepeat.html#method.join
+    /// [`DeadlineExceeded`]: struct.DeadlineExceeded.html
+    pub async fn join_timeout(&mut self, timeout: Duration) -> Result<Option<T>, DeadlineExceeded> {
+        Local::with_timeout(timeout, &mut self.handle).await
     }
 }
 
-impl TimerActionRepeat {
-    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly in a specific
-    /// Task Queue until returns None
+impl TimerActionSchedule {
+    /// Creates a [`TimerActionSchedule`] that will execute the associated future in a specific
+    /// Task Queue each time `next` says, until `next` returns None
     ///
     /// # Arguments
     ///
-    /// * `action_gen` a Future to be executed repeatedly. The Future's return value must be
-    /// Option<Duration>. If [`Some`], It will execute again after Duration elapses. If `None`,
-    /// it stops.
+    /// * `next` computes when to execute the action next. It is called with the instant
+    /// the schedule was created, and then with the instant each execution finished, and
+    /// must return a later instant, or `None` to stop.
+    /// * `action_gen` a Future to be executed at each of those instants.
     /// * `tq` the [`TaskQueueHandle`] for the TaskQueue we want.
     ///
     /// # Examples
     ///
+    /// ```
+    /// use scipio::{Latency, Local, LocalExecutor, TimerActionSchedule};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
+    ///     // backs off exponentially, and gives up after a second
+    ///     let start = Instant::now();
+    ///     let action = TimerActionSchedule::schedule_into(move |last| {
+    ///         let next = last + (last - start) + Duration::from_millis(1);
+    ///         Some(next).filter(|next| *next < start + Duration::from_secs(1))
+    ///     }, || async move {
+    ///         println!("Execute this!");
+    ///     }, tq).unwrap();
+    ///     action.join().await;
+    /// }).unwrap();
+    /// handle.join().unwrap();
+    /// ```
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
+    pub fn schedule_into<N, G, F>(
+        next: N,
+        action_gen: G,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionSchedule, QueueNotFoundError>
+    where
+        N: Fn(Instant) -> Option<Instant> + 'static,
+        G: Fn() -> F + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        let timer_id = Reactor::get().register_timer();
+        let mut last = Instant::now();
+
+        let task = Task::local_into(
+            async move {
+                while let Some(when) = next(last) {
+                    Timer::from_id_at(timer_id, when).await;
+                    action_gen().await;
+                    last = Instant::now();
+                }
+            },
+            tq,
+        )?;
+
+        Ok(TimerActionSchedule {
+            handle: task.detach(),
+            timer_id,
+        })
+    }
+
+    /// Creates a [`TimerActionSchedule`] that will execute the associated future each time
+    /// `next` says, until `next` returns None. See [`schedule_into`]
+    ///
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    /// [`schedule_into`]: struct.TimerActionSchedule.html#method.schedule_into
+    pub fn schedule<N, G, F>(next: N, action_gen: G) -> TimerActionSchedule
+    where
+        N: Fn(Instant) -> Option<Instant> + 'static,
+        G: Fn() -> F + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        Self::schedule_into(next, action_gen, Local::current_task_queue()).unwrap()
+    }
+
+    /// Creates a [`TimerActionSchedule`] that will execute the associated future in a specific
+    /// Task Queue at the times matched by a [`CronSchedule`]
+    ///
+    /// The schedule is matched against the wall clock when the schedule is created and
+    /// after each execution, and the action waits on the monotonic clock in between, so
+    /// a change to the wall clock takes effect after the next execution. The wall clock
+    /// is read in UTC, or at the fixed offset given to the schedule with
+    /// [`CronSchedule::utc_offset`].
+    ///
+    /// # Examples
+    ///
     /// ```no_run
-    /// use scipio::{LocalExecutor, TimerActionRepeat, Latency, Local};
-    /// use std::time::Duration;
+    /// use scipio::{CronSchedule, Latency, Local, LocalExecutor, TimerActionSchedule};
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "test");
-    ///     let action = TimerActionRepeat::repeat_into(|| async move {
-    ///         println!("Execute this!");
-    ///         Some(Duration::from_millis(100))
+    ///     let tq = Local::create_task_queue(1, Latency::NotImportant, "maintenance");
+    ///     let nightly = CronSchedule::parse("0 3 * * *").unwrap();
+    ///     let action = TimerActionSchedule::cron_into(nightly, || async move {
+    ///         println!("compacting");
     ///     }, tq).unwrap();
     ///     action.join().await; // this never returns
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
-    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
-    /// [`TaskQueueHandle`]: struct.TaskQueueHandle
-    pub fn repeat_into<G, F>(
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    /// [`CronSchedule`]: struct.CronSchedule.html
+    /// [`CronSchedule::utc_offset`]: struct.CronSchedule.html#method.utc_offset
+    pub fn cron_into<G, F>(
+        schedule: CronSchedule,
         action_gen: G,
         tq: TaskQueueHandle,
-    ) -> Result<TimerActionRepeat, QueueNotFoundError>
+    ) -> Result<TimerActionSchedule, QueueNotFoundError>
     where
         G: Fn() -> F + 'static,
-        F: Future<Output = Option<Duration>> + 'static,
+        F: Future<Output = ()> + 'static,
     {
-        let timer_id = Reactor::get().register_timer();
+        let next = move |_: Instant| {
+            let next = schedule.next_after(SystemTime::now())?;
+            let wait = next.duration_since(SystemTime::now()).unwrap_or_default();
+            Some(Instant::now() + wait)
+        };
+        Self::schedule_into(next, action_gen, tq)
+    }
+
+    /// Creates a [`TimerActionSchedule`] that will execute the associated future at the times
+    /// matched by a [`CronSchedule`]. See [`cron_into`]
+    ///
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    /// [`CronSchedule`]: struct.CronSchedule.html
+    /// [`cron_into`]: struct.TimerActionSchedule.html#method.cron_into
+    pub fn cron<G, F>(schedule: CronSchedule, action_gen: G) -> TimerActionSchedule
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        Self::cron_into(schedule, action_gen, Local::current_task_queue()).unwrap()
+    }
+
+    /// Cancel an existing [`TimerActionSchedule`] and waits for it to return
+    ///
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    pub async fn cancel(self) {
+        self.destroy();
+        self.join().await;
+    }
+
+    /// Cancel an existing [`TimerActionSchedule`], without waiting for it to return. It
+    /// is still possible to [`join`] the task if needed.
+    ///
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    /// [`join`]: struct.TimerActionSchedule.html#method.join
+    pub fn destroy(&self) {
+        Reactor::get().remove_timer(self.timer_id);
+        self.handle.cancel();
+    }
+
+    /// Waits for a [`TimerActionSchedule`] to return
+    ///
+    /// Returns an [`Option`] with value None if the task was canceled and Some(()) if
+    /// the schedule ran out of times to execute the action
+    ///
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    /// [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
+    pub async fn join(self) -> Option<()> {
+        self.handle.await.and_then(|_| Some(()))
+    }
+
+    /// Waits up to `timeout` for a [`TimerActionSchedule`] to return, like [`join`] does.
+    ///
+    /// Fails with [`DeadlineExceeded`] if the schedule didn't run out in time, in which
+    /// case it keeps going: it can still be canceled, or joined again.
+    ///
+    /// [`TimerActionSchedule`]: struct.TimerActionSchedule
+    /// [`join`]: struct.TimerActionSchedule.html#method.join
+    /// [`DeadlineExceeded`]: struct.DeadlineExceeded.html
+    pub async fn join_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<()>, DeadlineExceeded> {
+        let res = Local::with_timeout(timeout, &mut self.handle).await?;
+        Ok(res.and_then(|_| Some(())))
+    }
+}
+
+// Signals waiting to be acted upon, shared by a [`Debouncer`] or [`Throttler`] and the
+// task that runs its action
+#[derive(Debug, Default)]
+struct Signals {
+    // When the last signal that the action didn't see yet arrived
+    pending: Cell<Option<Instant>>,
+    waker: RefCell<Option<Waker>>,
+    executions: Cell<u64>,
+}
+
+impl Signals {
+    fn signal(&self) {
+        self.pending.set(Some(Instant::now()));
+        if let Some(waker) = self.waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+
+    // Waits for a signal, and returns when the latest one arrived
+    async fn next(&self) -> Instant {
+        poll_fn(|cx| match self.pending.get() {
+            Some(at) => Poll::Ready(at),
+            None => {
+                *self.waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    async fn run<F: Future<Output = ()>>(&self, action: F) {
+        self.pending.set(None);
+        self.executions.set(self.executions.get() + 1);
+        action.await;
+    }
+}
+
+/// Runs an action once signals stop arriving for a while.
+///
+/// Every call to [`signal`] pushes the action back, so it runs once `window` has passed
+/// without any signal, however many arrived before that: a burst of changes to a file
+/// causes a single reload, for instance. Signals that arrive while the action runs cause
+/// it to run again, once they quiet down.
+///
+/// The action is canceled when the [`Debouncer`] is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{Debouncer, LocalExecutor, Timer};
+/// use std::time::Duration;
+///
+/// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+///     let reload = Debouncer::new(Duration::from_millis(50), || async move {
+///         println!("reloading");
+///     });
+///     for _ in 0..10 {
+///         reload.signal(); // reloads once, 50ms after the last of these
+///     }
+///     // dropping the debouncer cancels it, so it is kept around until it reloaded
+///     Timer::new(Duration::from_millis(100)).await;
+///     drop(reload);
+/// }).unwrap();
+/// handle.join().unwrap();
+/// ```
+/// [`Debouncer`]: struct.Debouncer.html
+/// [`signal`]: struct.Debouncer.html#method.signal
+#[derive(Debug)]
+pub struct Debouncer {
+    handle: JoinHandle<(), ()>,
+    signals: Rc<Signals>,
+}
 
+impl Debouncer {
+    /// Creates a [`Debouncer`] that runs the action generated by `action_gen` in a specific
+    /// Task Queue once `window` passes without a call to [`signal`]
+    ///
+    /// [`Debouncer`]: struct.Debouncer.html
+    /// [`signal`]: struct.Debouncer.html#method.signal
+    pub fn new_into<G, F>(
+        window: Duration,
+        action_gen: G,
+        tq: TaskQueueHandle,
+    ) -> Result<Debouncer, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        let signals = Rc::new(Signals::default());
+        let state = signals.clone();
         let task = Task::local_into(
             async move {
                 loop {
-                    if let Some(period) = action_gen().await {
-                        Timer::from_id(timer_id, period).await;
-                    } else {
-                        break;
+                    let mut last = state.next().await;
+                    loop {
+                        Timer::at(last + window).await;
+                        match state.pending.get() {
+                            Some(latest) if latest != last => last = latest,
+                            _ => break,
+                        }
                     }
+                    state.run(action_gen()).await;
                 }
             },
             tq,
         )?;
 
-        Ok(TimerActionRepeat {
+        Ok(Debouncer {
             handle: task.detach(),
-            timer_id: timer_id,
+            signals,
         })
     }
 
-    /// Creates a [`TimerActionRepeat`] that will execute the associated future repeatedly until
-    /// it returns None
-    ///
-    /// # Arguments
-    ///
-    /// * `action_gen` a Future to be executed repeatedly. The Future's return value must be
-    /// Option<Duration>. If [`Some`], It will execute again after Duration elapses. If `None`,
-    /// it stops.
+    /// Creates a [`Debouncer`] that runs the action generated by `action_gen` once `window`
+    /// passes without a call to [`signal`]. See [`new_into`]
     ///
-    /// # Examples
+    /// [`Debouncer`]: struct.Debouncer.html
+    /// [`signal`]: struct.Debouncer.html#method.signal
+    /// [`new_into`]: struct.Debouncer.html#method.new_into
+    pub fn new<G, F>(window: Duration, action_gen: G) -> Debouncer
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        Self::new_into(window, action_gen, Local::current_task_queue()).unwrap()
+    }
+
+    /// Signals the [`Debouncer`], pushing its action back until `window` passes without
+    /// another signal
     ///
-    /// ```no_run
-    /// use scipio::{LocalExecutor, TimerActionRepeat};
-    /// use std::time::Duration;
+    /// [`Debouncer`]: struct.Debouncer.html
+    pub fn signal(&self) {
+        self.signals.signal();
+    }
+
+    /// Whether there are signals the action didn't run for yet
+    pub fn is_pending(&self) -> bool {
+        self.signals.pending.get().is_some()
+    }
+
+    /// How many times the action ran, or started running
+    pub fn executions(&self) -> u64 {
+        self.signals.executions.get()
+    }
+}
+
+impl Drop for Debouncer {
+    fn drop(&mut self) {
+        self.handle.cancel();
+    }
+}
+
+/// Runs an action at most once per window, however often it is signaled.
+///
+/// The first call to [`signal`] runs the action right away. Signals that arrive while it
+/// runs, or before `window` passes since it started, are folded into a single execution
+/// when the window ends, so the latest signal is never lost: progress reports or cache
+/// refreshes get rate-limited without going stale.
+///
+/// The action is canceled when the [`Throttler`] is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, Throttler, Timer};
+/// use std::time::Duration;
+///
+/// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+///     let report = Throttler::new(Duration::from_millis(100), || async move {
+///         println!("progress");
+///     });
+///     for _ in 0..10 {
+///         report.signal(); // reports now, and once more 100ms later
+///     }
+///     // dropping the throttler cancels it, so it is kept around until it reported twice
+///     Timer::new(Duration::from_millis(200)).await;
+///     drop(report);
+/// }).unwrap();
+/// handle.join().unwrap();
+/// ```
+/// [`Throttler`]: struct.Throttler.html
+/// [`signal`]: struct.Throttler.html#method.signal
+#[derive(Debug)]
+pub struct Throttler {
+    handle: JoinHandle<(), ()>,
+    signals: Rc<Signals>,
+}
+
+impl Throttler {
+    /// Creates a [`Throttler`] that runs the action generated by `action_gen` in a specific
+    /// Task Queue when [`signal`]ed, at most once per `window`
     ///
-    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionRepeat::repeat(|| async move {
-    ///         println!("Execute this!");
-    ///         Some(Duration::from_millis(100))
-    ///     });
-    ///     action.join().await; // this never returns
-    /// }).unwrap();
-    /// handle.join().unwrap();
-    /// ```
-    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
-    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
-    pub fn repeat<G, F>(action_gen: G) -> TimerActionRepeat
+    /// [`Throttler`]: struct.Throttler.html
+    /// [`signal`]: struct.Throttler.html#method.signal
+    pub fn new_into<G, F>(
+        window: Duration,
+        action_gen: G,
+        tq: TaskQueueHandle,
+    ) -> Result<Throttler, QueueNotFoundError>
     where
         G: Fn() -> F + 'static,
-        F: Future<Output = Option<Duration>> + 'static,
+        F: Future<Output = ()> + 'static,
     {
-        Self::repeat_into(action_gen, Local::current_task_queue()).unwrap()
+        let signals = Rc::new(Signals::default());
+        let state = signals.clone();
+        let task = Task::local_into(
+            async move {
+                let mut last_run: Option<Instant> = None;
+                loop {
+                    state.next().await;
+                    if let Some(last_run) = last_run {
+                        Timer::at(last_run + window).await;
+                    }
+                    last_run = Some(Instant::now());
+                    state.run(action_gen()).await;
+                }
+            },
+            tq,
+        )?;
+
+        Ok(Throttler {
+            handle: task.detach(),
+            signals,
+        })
     }
 
-    /// Cancel an existing [`TimerActionRepeat`] and waits for it to return
-    ///
-    /// If you want to cancel the timer but doesn't want to .await on it,
-    /// prefer [`destroy`].
+    /// Creates a [`Throttler`] that runs the action generated by `action_gen` when
+    /// [`signal`]ed, at most once per `window`. See [`new_into`]
     ///
-    /// # Examples
+    /// [`Throttler`]: struct.Throttler.html
+    /// [`signal`]: struct.Throttler.html#method.signal
+    /// [`new_into`]: struct.Throttler.html#method.new_into
+    pub fn new<G, F>(window: Duration, action_gen: G) -> Throttler
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = ()> + 'static,
+    {
+        Self::new_into(window, action_gen, Local::current_task_queue()).unwrap()
+    }
+
+    /// Signals the [`Throttler`], so its action runs as soon as the current window ends
     ///
-    /// ```
-    /// use scipio::{LocalExecutor, TimerActionRepeat};
-    /// use std::time::Duration;
+    /// [`Throttler`]: struct.Throttler.html
+    pub fn signal(&self) {
+        self.signals.signal();
+    }
+
+    /// Whether there are signals the action didn't run for yet
+    pub fn is_pending(&self) -> bool {
+        self.signals.pending.get().is_some()
+    }
+
+    /// How many times the action ran, or started running
+    pub fn executions(&self) -> u64 {
+        self.signals.executions.get()
+    }
+}
+
+impl Drop for Throttler {
+    fn drop(&mut self) {
+        self.handle.cancel();
+    }
+}
+
+// What a [`WatchdogTimer`] and the task that runs its action agree on
+#[derive(Debug)]
+struct WatchdogState {
+    period: Duration,
+    deadline: Cell<Instant>,
+    expired: Cell<bool>,
+}
+
+impl WatchdogState {
+    // Whether the deadline passed. Once it did, the watchdog stays expired.
+    fn check(&self) -> bool {
+        if !self.expired.get() && Instant::now() >= self.deadline.get() {
+            self.expired.set(true);
+        }
+        self.expired.get()
+    }
+}
+
+/// Runs an action unless it is fed often enough.
+///
+/// Every call to [`feed`] pushes the deadline one `period` away from now. If the
+/// deadline passes before the next call, the watchdog expires and runs its action, like
+/// canceling a task that stopped making progress or tearing down an idle session.
+///
+/// The watchdog decides on its own whether it expired: a call to [`feed`] that comes
+/// after the deadline passed is too late, even if the action didn't get to run yet, and
+/// returns `false`. Feeding an expired watchdog does nothing. A call that comes in time
+/// always keeps the action from running, even if the timer of the old deadline already
+/// fired, so there is no window in which feeding and expiring race.
+///
+/// The action is canceled when the [`WatchdogTimer`] is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, Timer, WatchdogTimer};
+/// use std::time::Duration;
+///
+/// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+///     let watchdog = WatchdogTimer::new(Duration::from_millis(100), async move {
+///         println!("nobody fed the watchdog");
+///     });
+///     for _ in 0..5 {
+///         Timer::new(Duration::from_millis(10)).await;
+///         assert!(watchdog.feed());
+///     }
+///     watchdog.join().await;
+/// }).unwrap();
+/// handle.join().unwrap();
+/// ```
+/// [`feed`]: struct.WatchdogTimer.html#method.feed
+/// [`WatchdogTimer`]: struct.WatchdogTimer.html
+#[derive(Debug)]
+pub struct WatchdogTimer<T> {
+    handle: JoinHandle<T, ()>,
+    state: Rc<WatchdogState>,
+}
+
+impl<T: 'static> WatchdogTimer<T> {
+    /// Creates a [`WatchdogTimer`] that runs `action` in a specific Task Queue unless it is
+    /// fed at least once every `period`, starting now
     ///
-    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionRepeat::repeat(|| async move {
-    ///         Some(Duration::from_millis(100))
-    ///     });
-    ///     action.cancel().await;
-    /// }).unwrap();
-    /// handle.join().unwrap();
-    /// ```
-    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
-    /// [`destroy`]: struct.TimerActionRepeat.html#method.destroy
-    pub async fn cancel(self) {
-        self.destroy();
-        self.join().await;
+    /// [`WatchdogTimer`]: struct.WatchdogTimer.html
+    pub fn new_into(
+        period: Duration,
+        action: impl Future<Output = T> + 'static,
+        tq: TaskQueueHandle,
+    ) -> Result<WatchdogTimer<T>, QueueNotFoundError> {
+        let state = Rc::new(WatchdogState {
+            period,
+            deadline: Cell::new(Instant::now() + period),
+            expired: Cell::new(false),
+        });
+        let watched = state.clone();
+        let task = Task::local_into(
+            async move {
+                // Feeding just moves the deadline, the timer catches up when it fires
+                while !watched.check() {
+                    Timer::at(watched.deadline.get()).await;
+                }
+                action.await
+            },
+            tq,
+        )?;
+
+        Ok(WatchdogTimer {
+            handle: task.detach(),
+            state,
+        })
     }
 
-    /// Cancel an existing [`TimerActionRepeat`], without waiting for it to return
+    /// Creates a [`WatchdogTimer`] that runs `action` unless it is fed at least once every
+    /// `period`, starting now. See [`new_into`]
     ///
-    /// This is a non-async version of [`cancel`]. It will remove the timer if
-    /// it hasn't fired already and destroy the [`TimerActionRepeat`] releasing the resources
-    /// associated with it, but without blocking the current task. It is still possible
-    /// to [`join`] the task if needed.
+    /// [`WatchdogTimer`]: struct.WatchdogTimer.html
+    /// [`new_into`]: struct.WatchdogTimer.html#method.new_into
+    pub fn new(period: Duration, action: impl Future<Output = T> + 'static) -> WatchdogTimer<T> {
+        Self::new_into(period, action, Local::current_task_queue()).unwrap()
+    }
+
+    /// Feeds the [`WatchdogTimer`], pushing its deadline one period away from now.
     ///
-    /// # Examples
+    /// Returns `false`, and does nothing, if the deadline already passed.
     ///
-    /// ```
-    /// use scipio::{LocalExecutor, TimerActionRepeat};
-    /// use std::time::Duration;
+    /// [`WatchdogTimer`]: struct.WatchdogTimer.html
+    pub fn feed(&self) -> bool {
+        if self.state.check() {
+            return false;
+        }
+        self.state.deadline.set(Instant::now() + self.state.period);
+        true
+    }
+
+    /// The instant the [`WatchdogTimer`] expires at, unless it is fed before that
     ///
-    /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionRepeat::repeat(|| async move {
-    ///         Some(Duration::from_millis(100))
-    ///     });
-    ///     action.destroy();
-    ///     let v = action.join().await;
-    ///     assert!(v.is_none())
-    /// }).unwrap();
-    /// handle.join().unwrap();
-    /// ```
-    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
-    /// [`cancel`]: struct.TimerActionRepeat.html#method.cancel
-    /// [`join`]: struct.TimerActionRepeat.html#method.join
-    pub fn destroy(&self) {
-        Reactor::get().remove_timer(self.timer_id);
+    /// [`WatchdogTimer`]: struct.WatchdogTimer.html
+    pub fn deadline(&self) -> Instant {
+        self.state.deadline.get()
+    }
+
+    /// Whether the deadline passed, so the action runs, or already did
+    pub fn has_expired(&self) -> bool {
+        self.state.check()
+    }
+
+    /// Cancel an existing [`WatchdogTimer`], so its action doesn't run if it didn't start
+    /// yet, and waits for it to return
+    ///
+    /// [`WatchdogTimer`]: struct.WatchdogTimer.html
+    pub async fn cancel(self) {
         self.handle.cancel();
+        self.join().await;
     }
 
-    /// Waits for a [`TimerActionRepeat`] to return
+    /// Waits for the [`WatchdogTimer`] to expire and its action to return
     ///
-    /// Returns an [`Option`] with value None if the task was canceled and Some(()) if
-    /// the action finished successfuly
+    /// Returns an [`Option`] with value None if the watchdog was canceled and Some with
+    /// the result of the action otherwise
+    ///
+    /// [`WatchdogTimer`]: struct.WatchdogTimer.html
+    /// [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
+    pub async fn join(mut self) -> Option<T> {
+        (&mut self.handle).await
+    }
+}
+
+impl WatchdogTimer<()> {
+    /// Creates a [`WatchdogTimer`] that cancels `task` unless it is fed at least once
+    /// every `period`, starting now
     ///
     /// # Examples
     ///
     /// ```
-    /// use scipio::{LocalExecutor, TimerActionRepeat};
+    /// use scipio::{Local, LocalExecutor, Timer, WatchdogTimer};
     /// use std::time::Duration;
     ///
     /// let handle = LocalExecutor::spawn_executor("test", None, || async move {
-    ///     let action = TimerActionRepeat::repeat(|| async move {
-    ///         None
-    ///     });
-    ///     let v = action.join().await;
-    ///     assert!(v.is_some())
+    ///     let stuck = Local::local(async move {
+    ///         Timer::new(Duration::from_secs(60)).await;
+    ///     })
+    ///     .detach();
+    ///     let watchdog = WatchdogTimer::cancelling(Duration::from_millis(10), stuck);
+    ///     watchdog.join().await;
     /// }).unwrap();
     /// handle.join().unwrap();
     /// ```
-    /// [`TimerActionRepeat`]: struct.TimerActionRepeat
-    /// [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
-    pub async fn join(self) -> Option<()> {
-        self.handle.await.and_then(|_| Some(()))
+    /// [`WatchdogTimer`]: struct.WatchdogTimer.html
+    pub fn cancelling<R: 'static>(period: Duration, task: JoinHandle<R, ()>) -> WatchdogTimer<()> {
+        Self::new(period, async move { task.cancel() })
+    }
+}
+
+impl<T> Drop for WatchdogTimer<T> {
+    fn drop(&mut self) {
+        self.handle.cancel();
+    }
+}
+
+// An action that a [`TimerScope`] destroys when it goes away
+#[derive(Debug)]
+struct ScopedAction {
+    timer_id: u64,
+    task: TaskCanceller,
+}
+
+/// Destroys the timer actions created through it when it is dropped.
+///
+/// Actions keep running on their own after whatever created them is gone, so a task
+/// that arms timeouts for a connection leaves them behind if it ends early. Creating
+/// them through a [`TimerScope`] owned by the task ties them to it instead: when the
+/// scope goes, the actions that didn't finish yet are destroyed, as if [`destroy`] was
+/// called on each of them. They can still be joined, canceled or rearmed in the
+/// meantime, like any other action.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{LocalExecutor, TimerScope};
+/// use std::time::Duration;
+///
+/// let handle = LocalExecutor::spawn_executor("test", None, || async move {
+///     let scope = TimerScope::new();
+///     let _timeout = scope.do_in(Duration::from_secs(30), async move {
+///         println!("the connection timed out");
+///     });
+///     // The connection ends early, and takes its timeout with it
+///     drop(scope);
+/// }).unwrap();
+/// handle.join().unwrap();
+/// ```
+/// [`TimerScope`]: struct.TimerScope.html
+/// [`destroy`]: struct.TimerActionOnce.html#method.destroy
+#[derive(Debug, Default)]
+pub struct TimerScope {
+    actions: RefCell<Vec<ScopedAction>>,
+}
+
+impl TimerScope {
+    /// Creates an empty [`TimerScope`]
+    ///
+    /// [`TimerScope`]: struct.TimerScope.html
+    pub fn new() -> TimerScope {
+        TimerScope::default()
+    }
+
+    fn adopt<T>(&self, timer_id: u64, handle: &JoinHandle<T, ()>) {
+        let mut actions = self.actions.borrow_mut();
+        // Finished actions have nothing left to destroy
+        actions.retain(|action| !action.task.is_done());
+        actions.push(ScopedAction {
+            timer_id,
+            task: handle.canceller(),
+        });
+    }
+
+    /// Creates a [`TimerActionOnce`] bound to this scope, in a specific Task Queue. See
+    /// [`TimerActionOnce::do_in_into`]
+    ///
+    /// [`TimerActionOnce`]: struct.TimerActionOnce.html
+    /// [`TimerActionOnce::do_in_into`]: struct.TimerActionOnce.html#method.do_in_into
+    pub fn do_in_into<T: 'static>(
+        &self,
+        when: Duration,
+        action: impl Future<Output = T> + 'static,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionOnce<T>, QueueNotFoundError> {
+        let action = TimerActionOnce::do_in_into(when, action, tq)?;
+        self.adopt(action.inner.borrow().id, &action.handle);
+        Ok(action)
+    }
+
+    /// Creates a [`TimerActionOnce`] bound to this scope. See [`TimerActionOnce::do_in`]
+    ///
+    /// [`TimerActionOnce`]: struct.TimerActionOnce.html
+    /// [`TimerActionOnce::do_in`]: struct.TimerActionOnce.html#method.do_in
+    pub fn do_in<T: 'static>(
+        &self,
+        when: Duration,
+        action: impl Future<Output = T> + 'static,
+    ) -> TimerActionOnce<T> {
+        self.do_in_into(when, action, Local::current_task_queue())
+            .unwrap()
+    }
+
+    /// Creates a [`TimerActionOnce`] bound to this scope, in a specific Task Queue. See
+    /// [`TimerActionOnce::do_at_into`]
+    ///
+    /// [`TimerActionOnce`]: struct.TimerActionOnce.html
+    /// [`TimerActionOnce::do_at_into`]: struct.TimerActionOnce.html#method.do_at_into
+    pub fn do_at_into<T: 'static>(
+        &self,
+        when: Instant,
+        action: impl Future<Output = T> + 'static,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionOnce<T>, QueueNotFoundError> {
+        self.do_in_into(when.saturating_duration_since(Instant::now()), action, tq)
+    }
+
+    /// Creates a [`TimerActionOnce`] bound to this scope. See [`TimerActionOnce::do_at`]
+    ///
+    /// [`TimerActionOnce`]: struct.TimerActionOnce.html
+    /// [`TimerActionOnce::do_at`]: struct.TimerActionOnce.html#method.do_at
+    pub fn do_at<T: 'static>(
+        &self,
+        when: Instant,
+        action: impl Future<Output = T> + 'static,
+    ) -> TimerActionOnce<T> {
+        self.do_at_into(when, action, Local::current_task_queue())
+            .unwrap()
+    }
+
+    /// Creates a [`TimerActionRepeat`] bound to this scope, in a specific Task Queue. See
+    /// [`TimerActionRepeat::repeat_into`]
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat.html
+    /// [`TimerActionRepeat::repeat_into`]: struct.TimerActionRepeat.html#method.repeat_into
+    pub fn repeat_into<G, F>(
+        &self,
+        action_gen: G,
+        tq: TaskQueueHandle,
+    ) -> Result<TimerActionRepeat, QueueNotFoundError>
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        let action = TimerActionRepeat::repeat_into(action_gen, tq)?;
+        self.adopt(action.timer_id, &action.handle);
+        Ok(action)
+    }
+
+    /// Creates a [`TimerActionRepeat`] bound to this scope. See
+    /// [`TimerActionRepeat::repeat`]
+    ///
+    /// [`TimerActionRepeat`]: struct.TimerActionRepeat.html
+    /// [`TimerActionRepeat::repeat`]: struct.TimerActionRepeat.html#method.repeat
+    pub fn repeat<G, F>(&self, action_gen: G) -> TimerActionRepeat
+    where
+        G: Fn() -> F + 'static,
+        F: Future<Output = Option<Duration>> + 'static,
+    {
+        self.repeat_into(action_gen, Local::current_task_queue())
+            .unwrap()
+    }
+
+    /// How many of the actions created through this scope didn't finish yet
+    pub fn pending(&self) -> usize {
+        let mut actions = self.actions.borrow_mut();
+        actions.retain(|action| !action.task.is_done());
+        actions.len()
+    }
+
+    /// Destroys the actions created through this scope that didn't finish yet, like
+    /// dropping the scope does, and leaves the scope empty
+    pub fn destroy_all(&self) {
+        let actions = std::mem::replace(&mut *self.actions.borrow_mut(), Vec::new());
+        // Scopes tend to go away with many actions in them, so all of their timers are
+        // removed in one go
+        Reactor::get().remove_timers(actions.iter().map(|action| action.timer_id));
+        for action in actions {
+            action.task.cancel();
+        }
+    }
+}
+
+impl Drop for TimerScope {
+    fn drop(&mut self) {
+        self.destroy_all();
     }
 }
 
@@ -659,6 +3319,134 @@ mod test {
         });
     }
 
+    #[test]
+    fn reporting_timer_reports_lateness() {
+        test_executor!(async move {
+            let start = Instant::now();
+            let fired = Timer::new(Duration::from_millis(50)).reporting().await;
+            assert!(fired.scheduled() >= start + Duration::from_millis(50));
+            assert!(fired.fired() >= fired.scheduled());
+            assert_eq!(fired.lateness(), fired.fired() - fired.scheduled());
+
+            // A timer that fires while the executor is blocked is late by at least as
+            // long as the executor was blocked past its deadline
+            let timer = Timer::new(Duration::from_millis(10)).reporting();
+            std::thread::sleep(Duration::from_millis(50));
+            let fired = timer.await;
+            assert!(fired.lateness() >= Duration::from_millis(40));
+        });
+    }
+
+    #[test]
+    fn timers_at_an_instant() {
+        test_executor!(async move {
+            let deadline = Instant::now() + Duration::from_millis(20);
+            assert_eq!(Timer::at(deadline).await, deadline);
+            assert!(Instant::now() >= deadline);
+
+            // an instant in the past is not an error
+            let past = Instant::now() - Duration::from_secs(1);
+            assert_eq!(Timer::at(past).await, past);
+            sleep_until(past).await;
+
+            let mut timer = Timer::at(Instant::now() + Duration::from_secs(10));
+            let deadline = Instant::now() + Duration::from_millis(10);
+            timer.reset_at(deadline);
+            assert_eq!(timer.await, deadline);
+        });
+    }
+
+    #[test]
+    fn timers_with_slack_fire_together() {
+        test_executor!(async move {
+            let when = Instant::now() + Duration::from_millis(10);
+            let mut first = Timer::at(when);
+            first.set_slack(Duration::from_secs(1));
+            // armed later, but the same window
+            Timer::new(Duration::from_millis(2)).await;
+            let mut second = Timer::at(when);
+            second.set_slack(Duration::from_secs(1));
+            let (first, second) = futures::join!(first.reporting(), second.reporting());
+
+            // both timers fired within their slack, together
+            assert!(first.fired() >= when);
+            assert!(first.fired() < when + Duration::from_millis(1010));
+            let apart = if first.fired() > second.fired() {
+                first.fired() - second.fired()
+            } else {
+                second.fired() - first.fired()
+            };
+            assert!(apart < Duration::from_millis(10), "{:?}", apart);
+        });
+    }
+
+    #[test]
+    fn interval_ticks_at_its_period() {
+        use futures::StreamExt;
+
+        test_executor!(async move {
+            let start = Instant::now();
+            let mut interval = Timer::interval(Duration::from_millis(10));
+            let timer_id = interval.timer.inner.borrow().id;
+            let mut last = start;
+            for i in 1..=5 {
+                let tick = interval.next().await.unwrap();
+                assert!(tick >= start + Duration::from_millis(10) * i);
+                assert!(tick > last);
+                last = tick;
+            }
+            assert_eq!(interval.timer.inner.borrow().id, timer_id);
+
+            // Ticks the executor was too busy to see are skipped
+            std::thread::sleep(Duration::from_millis(35));
+            let late = interval.next().await.unwrap();
+            let next = interval.next().await.unwrap();
+            assert!(next >= late + Duration::from_millis(35));
+        });
+    }
+
+    #[test]
+    fn kernel_and_auto_timers() {
+        test_executor!(async move {
+            let start = Instant::now();
+            let mut timer = KernelTimer::new(Duration::from_secs(10)).unwrap();
+            timer.reset(Duration::from_millis(20));
+            let scheduled = timer.await.unwrap();
+            assert!(scheduled >= start + Duration::from_millis(20));
+            assert!(start.elapsed() >= Duration::from_millis(20));
+            assert!(start.elapsed() < Duration::from_secs(10));
+
+            assert_eq!(
+                AutoTimer::new(Duration::from_millis(1)).kind(),
+                TimerKind::Wheel
+            );
+            assert_eq!(
+                AutoTimer::new(Duration::from_secs(60)).kind(),
+                TimerKind::Kernel
+            );
+            let start = Instant::now();
+            AutoTimer::with_kind(Duration::from_millis(10), TimerKind::Kernel).await;
+            assert!(start.elapsed() >= Duration::from_millis(10));
+        });
+    }
+
+    #[test]
+    fn wall_clock_timers() {
+        test_executor!(async move {
+            let start = SystemTime::now();
+            let mut timer = WallClockTimer::at(start + Duration::from_secs(10)).unwrap();
+            timer.reset_at(start + Duration::from_millis(20));
+            assert_eq!(timer.await.unwrap(), start + Duration::from_millis(20));
+            assert!(SystemTime::now() >= start + Duration::from_millis(20));
+            assert!(SystemTime::now() < start + Duration::from_secs(10));
+
+            // a time that already passed fires right away
+            let timer = WallClockTimer::at(SystemTime::UNIX_EPOCH).unwrap();
+            assert_eq!(timer.clock_changes(), 0);
+            assert_eq!(timer.await.unwrap(), SystemTime::UNIX_EPOCH);
+        });
+    }
+
     #[test]
     fn basic_timer_action_instant_works() {
         make_shared_var_mut!(0, exec1, exec2);
@@ -688,100 +3476,329 @@ mod test {
                 *(exec1.borrow_mut()) = 1;
             });
 
-            Task::<()>::later().await;
-            assert_eq!(*(exec2.borrow()), 1);
+            Task::<()>::later().await;
+            assert_eq!(*(exec2.borrow()), 1);
+        });
+    }
+
+    #[test]
+    fn basic_timer_action_works() {
+        make_shared_var_mut!(0, exec1, exec2);
+
+        test_executor!(async move {
+            let _ = TimerActionOnce::do_in(Duration::from_millis(50), async move {
+                *(exec1.borrow_mut()) = 1;
+            });
+
+            Timer::new(Duration::from_millis(100)).await;
+            assert_eq!(*(exec2.borrow()), 1);
+        });
+    }
+
+    #[test]
+    fn basic_timer_rearm_pending_timer_for_the_past_ok() {
+        test_executor!(async move {
+            let now = Instant::now();
+            let action: TimerActionOnce<usize> =
+                TimerActionOnce::do_in(Duration::from_millis(50), async move {
+                    Timer::new(Duration::from_millis(50)).await;
+                    1
+                });
+
+            Timer::new(Duration::from_millis(60)).await;
+            action.rearm_at(Instant::now().checked_sub(Duration::from_secs(1)).unwrap());
+            let ret = action.join().await;
+            assert_eq!(ret.unwrap(), 1);
+            assert!(now.elapsed().as_millis() >= 100);
+        });
+    }
+
+    #[test]
+    fn basic_timer_rearm_executed_action_ok() {
+        test_executor!(async move {
+            let action: TimerActionOnce<usize> =
+                TimerActionOnce::do_in(Duration::from_millis(1), async move { 1 });
+
+            Timer::new(Duration::from_millis(10)).await;
+            action.rearm_at(
+                Instant::now()
+                    .checked_add(Duration::from_secs(100))
+                    .unwrap(),
+            );
+            let now = Instant::now();
+            let ret = action.join().await;
+            assert_eq!(ret.unwrap(), 1);
+            assert!(now.elapsed().as_millis() <= 10);
+        });
+    }
+
+    #[test]
+    fn basic_timer_rearm_future_timer_ok() {
+        test_executor!(async move {
+            let now = Instant::now();
+            let action: TimerActionOnce<usize> =
+                TimerActionOnce::do_in(Duration::from_millis(10), async move { 1 });
+
+            action.rearm_in(Duration::from_millis(100));
+            let ret = action.join().await;
+            assert_eq!(ret.unwrap(), 1);
+            assert!(now.elapsed().as_millis() >= 100);
+        });
+    }
+
+    #[test]
+    fn basic_timer_action_return_ok() {
+        test_executor!(async move {
+            let now = Instant::now();
+            let action: TimerActionOnce<usize> =
+                TimerActionOnce::do_in(Duration::from_millis(50), async move { 1 });
+
+            let ret = action.join().await;
+            assert_eq!(ret.unwrap(), 1);
+            assert!(now.elapsed().as_millis() >= 50);
+        });
+    }
+
+    #[test]
+    fn basic_timer_action_join_reflects_cancel() {
+        test_executor!(async move {
+            let now = Instant::now();
+            let action: TimerActionOnce<usize> =
+                TimerActionOnce::do_in(Duration::from_millis(50), async move { 1 });
+
+            action.destroy();
+            let ret = action.join().await;
+            assert!(ret.is_none());
+            assert!(now.elapsed().as_millis() < 50);
+        });
+    }
+
+    #[test]
+    fn timer_action_join_timeout_is_bounded() {
+        test_executor!(async move {
+            let mut action: TimerActionOnce<usize> =
+                TimerActionOnce::do_in(Duration::from_millis(50), async move { 1 });
+
+            let now = Instant::now();
+            assert!(action.join_timeout(Duration::from_millis(5)).await.is_err());
+            assert!(now.elapsed() < Duration::from_millis(50));
+
+            // the action is still there after the timeout
+            let ret = action.join_timeout(Duration::from_secs(1)).await;
+            assert_eq!(ret, Ok(Some(1)));
+
+            let mut repeat =
+                TimerActionRepeat::repeat(|| async move { Some(Duration::from_millis(1)) });
+            assert!(repeat.join_timeout(Duration::from_millis(5)).await.is_err());
+            repeat.cancel().await;
+        });
+    }
+
+    #[test]
+    fn timer_action_repeat_n_stops_after_n() {
+        make_shared_var_mut!(0, exec1, exec2);
+
+        test_executor!(async move {
+            let action = TimerActionRepeat::repeat_n(
+                move || {
+                    let exec = exec1.clone();
+                    async move {
+                        *(exec.borrow_mut()) += 1;
+                        Some(Duration::from_millis(1))
+                    }
+                },
+                3,
+            );
+            Timer::new(Duration::from_millis(50)).await;
+            assert_eq!(action.executions(), 3);
+            assert!(action.join().await.is_some());
+            assert_eq!(*(exec2.borrow()), 3);
+
+            // the action can still stop on its own earlier
+            let action = TimerActionRepeat::repeat_n(|| async move { None }, 3);
+            assert!(action.join().await.is_some());
         });
     }
 
     #[test]
-    fn basic_timer_action_works() {
+    fn timer_action_try_repeat_surfaces_errors() {
         make_shared_var_mut!(0, exec1, exec2);
 
         test_executor!(async move {
-            let _ = TimerActionOnce::do_in(Duration::from_millis(50), async move {
-                *(exec1.borrow_mut()) = 1;
+            let action = TimerActionRepeat::try_repeat(move || {
+                let exec = exec1.clone();
+                async move {
+                    let mut exec = exec.borrow_mut();
+                    *exec += 1;
+                    if *exec == 3 {
+                        Err(*exec)
+                    } else {
+                        Ok(Some(Duration::from_millis(1)))
+                    }
+                }
             });
+            assert_eq!(action.join().await, Some(Err(3)));
+            assert_eq!(*(exec2.borrow()), 3);
 
-            Timer::new(Duration::from_millis(100)).await;
-            assert_eq!(*(exec2.borrow()), 1);
+            let action = TimerActionRepeat::try_repeat(|| async move { Ok::<_, ()>(None) });
+            assert_eq!(action.join().await, Some(Ok(())));
+
+            let action = TimerActionRepeat::try_repeat(|| async move {
+                Ok::<_, ()>(Some(Duration::from_secs(1)))
+            });
+            action.destroy();
+            assert_eq!(action.join().await, None);
         });
     }
 
     #[test]
-    fn basic_timer_rearm_pending_timer_for_the_past_ok() {
+    fn debouncer_and_throttler_coalesce_signals() {
         test_executor!(async move {
-            let now = Instant::now();
-            let action: TimerActionOnce<usize> =
-                TimerActionOnce::do_in(Duration::from_millis(50), async move {
-                    Timer::new(Duration::from_millis(50)).await;
-                    1
-                });
+            let debouncer = Debouncer::new(Duration::from_millis(20), || async move {});
+            for _ in 0..5 {
+                debouncer.signal();
+                Timer::new(Duration::from_millis(5)).await;
+            }
+            assert_eq!(debouncer.executions(), 0);
+            assert!(debouncer.is_pending());
+            Timer::new(Duration::from_millis(50)).await;
+            assert_eq!(debouncer.executions(), 1);
+            assert!(!debouncer.is_pending());
 
+            let throttler = Throttler::new(Duration::from_millis(30), || async move {});
+            throttler.signal();
+            Local::later().await;
+            assert_eq!(throttler.executions(), 1);
+            for _ in 0..5 {
+                throttler.signal();
+            }
+            Local::later().await;
+            assert_eq!(throttler.executions(), 1);
             Timer::new(Duration::from_millis(60)).await;
-            action.rearm_at(Instant::now().checked_sub(Duration::from_secs(1)).unwrap());
-            let ret = action.join().await;
-            assert_eq!(ret.unwrap(), 1);
-            assert!(now.elapsed().as_millis() >= 100);
+            assert_eq!(throttler.executions(), 2);
+            assert!(!throttler.is_pending());
         });
     }
 
     #[test]
-    fn basic_timer_rearm_executed_action_ok() {
+    fn watchdog_timer_expires_unless_fed() {
+        make_shared_var_mut!(0, exec1, exec2);
+
         test_executor!(async move {
-            let action: TimerActionOnce<usize> =
-                TimerActionOnce::do_in(Duration::from_millis(1), async move { 1 });
+            let watchdog = WatchdogTimer::new(Duration::from_millis(30), async move {
+                *(exec1.borrow_mut()) += 1;
+            });
+            for _ in 0..5 {
+                Timer::new(Duration::from_millis(10)).await;
+                assert!(watchdog.feed());
+            }
+            assert_eq!(*(exec2.borrow()), 0);
+            assert!(!watchdog.has_expired());
 
-            Timer::new(Duration::from_millis(10)).await;
-            action.rearm_at(
-                Instant::now()
-                    .checked_add(Duration::from_secs(100))
-                    .unwrap(),
-            );
-            let now = Instant::now();
-            let ret = action.join().await;
-            assert_eq!(ret.unwrap(), 1);
-            assert!(now.elapsed().as_millis() <= 10);
+            // The deadline passes while nothing runs: feeding now is too late
+            std::thread::sleep(Duration::from_millis(40));
+            assert!(!watchdog.feed());
+            assert!(watchdog.has_expired());
+            assert_eq!(watchdog.join().await, Some(()));
+            assert_eq!(*(exec2.borrow()), 1);
+
+            let task = Local::local(async move {
+                Timer::new(Duration::from_secs(60)).await;
+                true
+            })
+            .detach();
+            let watchdog = WatchdogTimer::new(Duration::from_millis(10), async move {
+                task.cancel();
+                task.await
+            });
+            assert_eq!(watchdog.join().await, Some(None));
+
+            let watchdog = WatchdogTimer::new(Duration::from_millis(10), async move {});
+            watchdog.cancel().await;
         });
     }
 
     #[test]
-    fn basic_timer_rearm_future_timer_ok() {
+    fn timer_scope_destroys_its_actions() {
+        make_shared_var_mut!(0, exec1, exec2, exec3, exec4);
+
         test_executor!(async move {
-            let now = Instant::now();
-            let action: TimerActionOnce<usize> =
-                TimerActionOnce::do_in(Duration::from_millis(10), async move { 1 });
+            let scope = TimerScope::new();
+            let done = scope.do_in(Duration::from_millis(1), async move {
+                *(exec1.borrow_mut()) += 1;
+            });
+            let timeout = scope.do_in(Duration::from_millis(50), async move {
+                *(exec2.borrow_mut()) += 10;
+            });
+            let repeat = scope.repeat(move || {
+                let exec = exec3.clone();
+                async move {
+                    *(exec.borrow_mut()) += 100;
+                    Some(Duration::from_millis(10))
+                }
+            });
+            assert_eq!(done.join().await, Some(()));
+            assert_eq!(scope.pending(), 2);
 
-            action.rearm_in(Duration::from_millis(100));
-            let ret = action.join().await;
-            assert_eq!(ret.unwrap(), 1);
-            assert!(now.elapsed().as_millis() >= 100);
+            drop(scope);
+            assert_eq!(timeout.join().await, None);
+            assert_eq!(repeat.join().await, None);
+            let before = *(exec4.borrow());
+            Timer::new(Duration::from_millis(100)).await;
+            // Only the action that finished ran, and the repeating one stopped
+            assert_eq!(*(exec4.borrow()), before);
+            assert_eq!(before % 100, 1);
         });
     }
 
     #[test]
-    fn basic_timer_action_return_ok() {
+    fn timer_handles_cancel_in_bulk() {
         test_executor!(async move {
-            let now = Instant::now();
-            let action: TimerActionOnce<usize> =
-                TimerActionOnce::do_in(Duration::from_millis(50), async move { 1 });
+            let armed = Local::timer_stats().armed();
+            let timers: Vec<_> = (0..1000)
+                .map(|_| Timer::new(Duration::from_secs(60)))
+                .collect();
+            let mut handles: Vec<_> = timers.iter().map(|timer| timer.handle()).collect();
+            let sleepers: Vec<_> = timers
+                .into_iter()
+                .map(|timer| Local::local(async move { timer.cancellable().await }))
+                .collect();
+            Local::later().await;
+            assert_eq!(Local::timer_stats().armed(), armed + 1000);
 
-            let ret = action.join().await;
-            assert_eq!(ret.unwrap(), 1);
-            assert!(now.elapsed().as_millis() >= 50);
+            // A handle whose timer is gone is skipped
+            handles.push(Timer::new(Duration::from_secs(60)).handle());
+            assert_eq!(TimerHandle::cancel_all(&handles), 1000);
+            assert_eq!(Local::timer_stats().armed(), armed);
+            for sleeper in sleepers {
+                assert!(sleeper.await.is_err());
+            }
+            assert!(handles.iter().all(|handle| handle.is_cancelled()));
         });
     }
 
     #[test]
-    fn basic_timer_action_join_reflects_cancel() {
+    fn timer_action_rearm_from_another_thread() {
+        make_shared_var_mut!(false, exec1, exec2);
+
         test_executor!(async move {
-            let now = Instant::now();
-            let action: TimerActionOnce<usize> =
-                TimerActionOnce::do_in(Duration::from_millis(50), async move { 1 });
+            let action = TimerActionOnce::do_in(Duration::from_millis(50), async move {
+                *(exec1.borrow_mut()) = true;
+            });
+            let rearm = action.rearm_handle();
+            let renewer = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                assert!(rearm.rearm_in(Duration::from_millis(200)));
+            });
 
-            action.destroy();
-            let ret = action.join().await;
-            assert!(ret.is_none());
-            assert!(now.elapsed().as_millis() < 50);
+            Timer::new(Duration::from_millis(100)).await;
+            assert_eq!(*(exec2.borrow()), false);
+            let late = action.rearm_handle();
+            action.join().await;
+            assert_eq!(*(exec2.borrow()), true);
+            renewer.join().unwrap();
+            // The action is gone
+            assert!(!late.rearm_in(Duration::from_millis(10)));
         });
     }
 
@@ -935,6 +3952,236 @@ mod test {
         });
     }
 
+    #[test]
+    fn repeat_schedules_pick_the_next_execution() {
+        let due = Instant::now();
+        let period = Duration::from_millis(10);
+        let ms = Duration::from_millis;
+
+        // on time, only fixed delay counts from the end of the execution
+        let finished = due + ms(3);
+        assert_eq!(
+            RepeatSchedule::FixedDelay.next(due, finished, period),
+            finished + period
+        );
+        for policy in &[MissedTicks::Burst, MissedTicks::Skip, MissedTicks::Delay] {
+            let schedule = RepeatSchedule::FixedRate(*policy);
+            assert_eq!(schedule.next(due, finished, period), due + period);
+        }
+
+        // 25ms late, two ticks behind
+        let finished = due + ms(25);
+        let rate = RepeatSchedule::FixedRate;
+        assert_eq!(
+            rate(MissedTicks::Burst).next(due, finished, period),
+            due + ms(10)
+        );
+        assert_eq!(
+            rate(MissedTicks::Skip).next(due, finished, period),
+            due + ms(30)
+        );
+        assert_eq!(
+            rate(MissedTicks::Delay).next(due, finished, period),
+            finished
+        );
+
+        // more ticks behind than fit in a u32
+        let finished = due + Duration::from_secs(10);
+        assert_eq!(
+            rate(MissedTicks::Skip).next(due, finished, Duration::from_nanos(1)),
+            finished + Duration::from_nanos(1)
+        );
+        let period = Duration::from_nanos(3);
+        assert_eq!(
+            rate(MissedTicks::Skip).next(due, finished, period),
+            due + Duration::from_nanos(10_000_000_002)
+        );
+    }
+
+    #[test]
+    fn fixed_rate_repeat_does_not_drift() {
+        test_executor!(async move {
+            let start = Instant::now();
+            let runs = Rc::new(RefCell::new(Vec::new()));
+            let r = runs.clone();
+            let action = TimerActionRepeat::repeat_with_schedule(
+                move || {
+                    let runs = r.clone();
+                    async move {
+                        runs.borrow_mut().push(Instant::now());
+                        // the action takes a good part of its period
+                        Timer::new(Duration::from_millis(6)).await;
+                        Some(Duration::from_millis(10))
+                    }
+                },
+                RepeatSchedule::FixedRate(MissedTicks::Skip),
+            );
+            Timer::new(Duration::from_millis(55)).await;
+            action.cancel().await;
+
+            let runs = runs.borrow();
+            for (i, run) in runs.iter().enumerate().skip(1) {
+                assert!(*run >= start + Duration::from_millis(10) * i as u32);
+            }
+            // with a fixed delay, only 4 executions fit in that time
+            assert!(runs.len() >= 5, "{}", runs.len());
+        });
+    }
+
+    #[test]
+    fn timer_action_schedule_follows_its_schedule() {
+        test_executor!(async move {
+            let start = Instant::now();
+            let runs = Rc::new(RefCell::new(Vec::new()));
+            let r = runs.clone();
+            let offsets = [5u64, 20, 30];
+            let action = TimerActionSchedule::schedule(
+                move |last| {
+                    offsets
+                        .iter()
+                        .map(|ms| start + Duration::from_millis(*ms))
+                        .find(|when| *when > last)
+                },
+                move || {
+                    let runs = r.clone();
+                    async move {
+                        runs.borrow_mut().push(Instant::now());
+                    }
+                },
+            );
+            assert!(action.join().await.is_some());
+
+            let runs = runs.borrow();
+            assert_eq!(runs.len(), 3);
+            for (run, ms) in runs.iter().zip(offsets.iter()) {
+                assert!(*run >= start + Duration::from_millis(*ms));
+            }
+        });
+    }
+
+    #[test]
+    fn timer_action_repeat_period_can_change() {
+        test_executor!(async move {
+            make_shared_var_mut!(0, exec1, exec2);
+            let action = TimerActionRepeat::repeat(move || {
+                let ex = exec1.clone();
+                async move {
+                    *(ex.borrow_mut()) += 1;
+                    Some(Duration::from_secs(10))
+                }
+            });
+            Timer::new(Duration::from_millis(10)).await;
+            assert_eq!(*(exec2.borrow()), 1);
+
+            // the pending execution moves too, it doesn't wait the old 10 seconds
+            action.set_period(Duration::from_millis(10));
+            assert_eq!(action.period(), Some(Duration::from_millis(10)));
+            Timer::new(Duration::from_millis(100)).await;
+            assert!(*(exec2.borrow()) > 3);
+
+            action.clear_period();
+            Timer::new(Duration::from_millis(20)).await;
+            let executed = *(exec2.borrow());
+            Timer::new(Duration::from_millis(50)).await;
+            assert_eq!(*(exec2.borrow()), executed);
+
+            action.rearm_in(Duration::from_millis(1));
+            Timer::new(Duration::from_millis(20)).await;
+            assert_eq!(*(exec2.borrow()), executed + 1);
+            action.cancel().await;
+        });
+    }
+
+    #[test]
+    fn timer_action_repeat_pause_and_resume() {
+        make_shared_var_mut!(0, exec1, exec2);
+
+        test_executor!(async move {
+            let action = TimerActionRepeat::repeat(move || {
+                let ex = exec1.clone();
+                async move {
+                    *(ex.borrow_mut()) += 1;
+                    Some(Duration::from_millis(10))
+                }
+            });
+            Timer::new(Duration::from_millis(25)).await;
+            action.pause();
+            let paused_at = *(exec2.borrow());
+            assert!(paused_at > 0);
+            Timer::new(Duration::from_millis(50)).await;
+            assert_eq!(*(exec2.borrow()), paused_at);
+
+            // the state captured by the action survives, and counting goes on from it
+            action.resume();
+            Local::later().await;
+            assert_eq!(*(exec2.borrow()), paused_at + 1);
+            Timer::new(Duration::from_millis(25)).await;
+            assert!(*(exec2.borrow()) > paused_at + 1);
+            action.cancel().await;
+        });
+    }
+
+    #[test]
+    fn coarse_timers_fire() {
+        test_executor!(async move {
+            let start = Instant::now();
+            let mut timer = Timer::new_coarse(Duration::from_millis(10));
+            (&mut timer).await;
+            let elapsed = start.elapsed();
+            // the cached time can be a little behind, but not by a whole timer
+            assert!(elapsed >= Duration::from_millis(5), "{:?}", elapsed);
+
+            // resetting a coarse timer measures from the cached time too
+            timer.reset(Duration::from_millis(10));
+            timer.await;
+            assert!(start.elapsed() >= elapsed + Duration::from_millis(5));
+        });
+    }
+
+    #[test]
+    fn jitter_spreads_expirations_within_bounds() {
+        let at = Instant::now() + Duration::from_secs(10);
+        let period = Duration::from_secs(1);
+        assert_eq!(jittered(at, period, 0.0), at);
+
+        let mut earlier = 0;
+        let mut later = 0;
+        for _ in 0..1000 {
+            let fire_at = jittered(at, period, 0.1);
+            assert!(fire_at >= at - Duration::from_millis(100));
+            assert!(fire_at <= at + Duration::from_millis(100));
+            if fire_at < at {
+                earlier += 1;
+            } else if fire_at > at {
+                later += 1;
+            }
+        }
+        assert!(earlier > 300 && later > 300, "{} {}", earlier, later);
+    }
+
+    #[test]
+    fn jittered_timers_and_actions_fire() {
+        test_executor!(async move {
+            let start = Instant::now();
+            Timer::new_with_jitter(Duration::from_millis(20), 0.5).await;
+            assert!(start.elapsed() >= Duration::from_millis(10));
+
+            make_shared_var_mut!(0, exec1, exec2);
+            let action = TimerActionRepeat::repeat(move || {
+                let ex = exec1.clone();
+                async move {
+                    *(ex.borrow_mut()) += 1;
+                    Some(Duration::from_millis(10))
+                }
+            });
+            action.set_jitter(2.0);
+            assert_eq!(action.jitter(), 1.0);
+            Timer::new(Duration::from_millis(100)).await;
+            assert!(*(exec2.borrow()) > 2);
+            action.cancel().await;
+        });
+    }
+
     #[test]
     fn basic_timer_action_repeat_destruction_works() {
         test_executor!(async move {
@@ -945,4 +4192,264 @@ mod test {
             assert!(v.is_none());
         });
     }
+
+    #[test]
+    fn bulk_timer_expiration_is_bounded_but_all_fire() {
+        test_executor!(async move {
+            Reactor::get().set_max_bulk_timer_expirations(1);
+            let lat_tq = Local::create_task_queue(
+                1,
+                crate::Latency::Matters(Duration::from_millis(1)),
+                "lat",
+            );
+            let log = Rc::new(RefCell::new(Vec::new()));
+
+            // The latency sensitive timer is due first, or together with the others
+            let now = Instant::now();
+            let l = log.clone();
+            let lat = Local::local_into(
+                async move {
+                    Timer::new(Duration::from_millis(10)).await;
+                    l.borrow_mut().push("lat");
+                },
+                lat_tq,
+            )
+            .unwrap();
+            let mut tasks = Vec::new();
+            for _ in 0..10 {
+                let l = log.clone();
+                tasks.push(Local::local(async move {
+                    Timer::new(Duration::from_millis(10)).await;
+                    l.borrow_mut().push("bulk");
+                }));
+            }
+            lat.await;
+            assert!(now.elapsed().as_millis() >= 10);
+            futures::future::join_all(tasks).await;
+
+            // One batch of a single timer per loop, and the latency sensitive task ran
+            // between the first batches instead of after all of them
+            let log = log.borrow();
+            assert_eq!(log.len(), 11);
+            let lat_at = log.iter().position(|entry| *entry == "lat").unwrap();
+            assert!(lat_at <= 1, "{:?}", *log);
+            #[cfg(feature = "stats")]
+            {
+                let stats = Reactor::get().timer_stats();
+                assert!(stats.max_fired_per_loop() <= 2);
+                assert!(stats.deferred_loops() > 0);
+                assert!(stats.loops() >= 10);
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn timer_preallocation_avoids_allocations() {
+        test_executor!(async move {
+            Reactor::get().preallocate_timers(100);
+            let before = Reactor::get().timer_stats();
+            assert!(before.capacity() >= 100);
+
+            let mut tasks = Vec::new();
+            for _ in 0..50 {
+                tasks.push(Local::local(async move {
+                    Timer::new(Duration::from_millis(10)).await;
+                }));
+            }
+            futures::future::join_all(tasks).await;
+
+            let after = Reactor::get().timer_stats();
+            assert_eq!(after.allocations(), before.allocations());
+            assert!(after.high_water_mark() >= 50);
+            assert_eq!(after.armed(), 0);
+
+            // Latency sensitive timers are covered too
+            let tq = Local::create_task_queue(
+                1000,
+                crate::Latency::Matters(Duration::from_millis(1)),
+                "latency",
+            );
+            let mut tasks = Vec::new();
+            for _ in 0..50 {
+                tasks.push(
+                    Local::local_into(
+                        async move {
+                            Timer::new(Duration::from_millis(10)).await;
+                        },
+                        tq,
+                    )
+                    .unwrap(),
+                );
+            }
+            futures::future::join_all(tasks).await;
+            assert_eq!(
+                Reactor::get().timer_stats().allocations(),
+                before.allocations()
+            );
+        });
+    }
+
+    #[test]
+    fn ring_timers_fire_reset_and_cancel() {
+        test_executor!(async move {
+            Reactor::get().set_timer_backend(TimerBackend::Ring);
+
+            let now = Instant::now();
+            Timer::new(Duration::from_millis(10)).await;
+            assert!(now.elapsed() >= Duration::from_millis(10));
+
+            // Reset while being waited on, and dropped before firing
+            let mut timer = Timer::new(Duration::from_secs(60));
+            let handle = timer.handle();
+            futures::future::poll_fn(|cx| {
+                assert!(Pin::new(&mut timer).poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            timer.reset(Duration::from_millis(5));
+            let now = Instant::now();
+            timer.await;
+            assert!(now.elapsed() < Duration::from_secs(60));
+            assert!(handle.is_cancelled());
+
+            // Ring timers count as armed until they are dropped
+            let mut pending = Timer::new(Duration::from_secs(60));
+            futures::future::poll_fn(|cx| {
+                assert!(Pin::new(&mut pending).poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            assert_eq!(Reactor::get().timer_stats().armed(), 1);
+            drop(pending);
+            assert_eq!(Reactor::get().timer_stats().armed(), 0);
+            Reactor::get().set_timer_backend(TimerBackend::Wheel);
+        });
+    }
+
+    #[test]
+    fn timer_handle_cancels_from_another_task() {
+        test_executor!(async move {
+            let timer = Timer::new(Duration::from_secs(60));
+            let handle = timer.handle();
+            let sleeper = Local::local(async move { timer.cancellable().await });
+
+            let canceller = handle.clone();
+            Local::local(async move {
+                Timer::new(Duration::from_millis(1)).await;
+                canceller.cancel();
+            })
+            .detach();
+
+            let now = Instant::now();
+            assert!(sleeper.await.is_err());
+            assert!(now.elapsed() < Duration::from_secs(60));
+            assert!(handle.is_cancelled());
+
+            // Cancelling before the first poll works too, and resetting rearms
+            let mut timer = Timer::new(Duration::from_millis(1));
+            timer.handle().cancel();
+            assert!(timer.is_cancelled());
+            timer.reset(Duration::from_millis(1));
+            assert!(!timer.is_cancelled());
+            assert!(timer.cancellable().await.is_ok());
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn timer_stats_count_fired_timers_and_latency() {
+        test_executor!(async move {
+            let before = Reactor::get().timer_stats();
+            let mut tasks = Vec::new();
+            for _ in 0..10 {
+                tasks.push(Local::local(async move {
+                    Timer::new(Duration::from_millis(5)).await;
+                }));
+            }
+            futures::future::join_all(tasks).await;
+
+            let after = Local::timer_stats();
+            assert_eq!(after.fired() - before.fired(), 10);
+            assert!(after.loops() > before.loops());
+            assert!(after.max_fired_per_loop() >= 1);
+            assert!(after.max_fire_latency() >= after.mean_fire_latency());
+        });
+    }
+
+    #[test]
+    fn timers_count_time_suspended() {
+        test_executor!(async move {
+            let jumps = Rc::new(RefCell::new(Vec::new()));
+            let j = jumps.clone();
+            Local::on_clock_jump(move |jump| j.borrow_mut().push(jump));
+
+            let start = Instant::now();
+            let timer = Local::local(async {
+                Timer::new(Duration::from_secs(10)).await;
+            });
+            let mut later = Box::pin(Timer::new(Duration::from_secs(120)));
+            assert!(futures::poll!(later.as_mut()).is_pending());
+            // let the timer be armed before the suspend
+            Local::later().await;
+            Reactor::get().pretend_suspended(Duration::from_secs(60));
+            timer.await;
+            assert!(start.elapsed() < Duration::from_secs(5));
+            // timers that are still not due after the suspend don't fire
+            assert!(futures::poll!(later.as_mut()).is_pending());
+
+            match jumps.borrow().as_slice() {
+                [ClockJump::Suspended(duration)] => {
+                    assert!(*duration >= Duration::from_secs(59));
+                    assert!(*duration <= Duration::from_secs(61));
+                }
+                other => panic!("unexpected clock jumps {:?}", other),
+            }
+            let stats = Local::timer_stats();
+            #[cfg(feature = "stats")]
+            assert_eq!(stats.clock_jumps(), 1);
+            assert!(stats.time_suspended() >= Duration::from_secs(59));
+        });
+    }
+
+    fn set_wall_clock_to_itself() -> bool {
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe {
+            libc::clock_gettime(libc::CLOCK_REALTIME, &mut now);
+            libc::clock_settime(libc::CLOCK_REALTIME, &now) == 0
+        }
+    }
+
+    #[test]
+    fn suspends_wake_sleeping_reactors() {
+        test_executor!(async move {
+            // The kernel tells about a resume the way it tells about the wall clock being
+            // set, which needs CAP_SYS_TIME to try out.
+            if !set_wall_clock_to_itself() {
+                return;
+            }
+            // let the reactor take in that first change
+            Timer::new(Duration::from_millis(10)).await;
+
+            let start = Instant::now();
+            let timer = Local::local(async {
+                Timer::new(Duration::from_secs(10)).await;
+            });
+            Local::later().await;
+            Reactor::get().pretend_suspended_unnoticed(Duration::from_secs(60));
+            let resume = std::thread::spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                set_wall_clock_to_itself();
+            });
+            // the reactor sleeps until the resume, not until the timer was due
+            timer.await;
+            assert!(start.elapsed() < Duration::from_secs(5));
+            resume.join().unwrap();
+            #[cfg(feature = "stats")]
+            assert_eq!(Local::timer_stats().clock_jumps(), 1);
+        });
+    }
 }
@@ -0,0 +1,430 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+//! A hierarchical timing wheel, where the reactor keeps its timers.
+//!
+//! Time is divided in ticks of a millisecond, counted from the creation of the wheel. The
+//! wheel has `LEVELS` levels of `SLOTS` slots each: a slot in level 0 holds the timers
+//! that fire in one tick, a slot in level 1 the timers that fire in 64 ticks, and so on,
+//! so the wheel reaches a little over two years ahead. Each slot is a doubly linked list
+//! of entries kept in a slab, so inserting and removing a timer is O(1) no matter how
+//! many timers are armed.
+//!
+//! When time reaches a slot in a level above 0, its timers are moved down to the levels
+//! below, until they reach level 0 and fire. Timers in level 0 fire at their exact
+//! instant, not at the start of their tick.
+use std::cmp;
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+const LEVELS: usize = 6;
+const SLOT_BITS: usize = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+
+// How far ahead a timer can be placed. Timers further away are placed this far, and
+// moved again when time gets there. It is one slot short of the range of the top level,
+// so a timer never lands in the top level slot that time is in.
+const MAX_TICKS: u64 = (1 << (LEVELS * SLOT_BITS)) - (1 << ((LEVELS - 1) * SLOT_BITS));
+
+const NONE: usize = usize::MAX;
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    fire_at: Instant,
+    waker: Option<Waker>,
+    // index of the slot in `heads` this entry is linked into, or NONE if it isn't
+    slot: usize,
+    prev: usize,
+    next: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct TimerWheel {
+    start: Instant,
+    // the tick up to which the wheel has been processed
+    elapsed: u64,
+    // the first entry of each slot, level by level
+    heads: Vec<usize>,
+    // one bit per slot that has entries, for each level
+    occupied: [u64; LEVELS],
+    entries: Vec<Entry>,
+    // entries that are not in use, linked through `next`
+    free: usize,
+    // entries to link back after expiring, kept here to avoid allocating
+    deferred: Vec<usize>,
+    // how late the timers fired so far were, added up, and the worst of them
+    total_lateness: Duration,
+    max_lateness: Duration,
+}
+
+fn level_for(elapsed: u64, tick: u64) -> usize {
+    let masked = (elapsed ^ tick) | (SLOTS as u64 - 1);
+    let significant = 63 - masked.leading_zeros() as usize;
+    cmp::min(significant / SLOT_BITS, LEVELS - 1)
+}
+
+impl TimerWheel {
+    pub(crate) fn new() -> TimerWheel {
+        TimerWheel {
+            start: Instant::now(),
+            elapsed: 0,
+            heads: vec![NONE; LEVELS * SLOTS],
+            occupied: [0; LEVELS],
+            entries: Vec::new(),
+            free: NONE,
+            deferred: Vec::new(),
+            total_lateness: Duration::from_secs(0),
+            max_lateness: Duration::from_secs(0),
+        }
+    }
+
+    fn tick_of(&self, instant: Instant) -> u64 {
+        instant.saturating_duration_since(self.start).as_millis() as u64
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        self.start + Duration::from_millis(tick)
+    }
+
+    /// The number of timers the wheel can hold without allocating.
+    #[cfg_attr(not(feature = "stats"), allow(dead_code))]
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    /// Whether inserting another timer will allocate.
+    #[cfg_attr(not(feature = "stats"), allow(dead_code))]
+    pub(crate) fn is_full(&self) -> bool {
+        self.free == NONE && self.entries.len() == self.entries.capacity()
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+        // Any entry may have to be linked back after expiring
+        let entries = self.entries.capacity();
+        self.deferred
+            .reserve(entries.saturating_sub(self.deferred.len()));
+    }
+
+    /// How late, added up, the timers fired so far were, and the latest of them.
+    pub(crate) fn lateness(&self) -> (Duration, Duration) {
+        (self.total_lateness, self.max_lateness)
+    }
+
+    /// Makes every armed timer fire `by` earlier, but not before `now`, like after the
+    /// monotonic clock stopped for that long while the system was suspended.
+    pub(crate) fn bring_forward(&mut self, by: Duration, now: Instant) {
+        let mut moved = std::mem::take(&mut self.deferred);
+        for key in 0..self.entries.len() {
+            if self.entries[key].slot == NONE {
+                continue;
+            }
+            self.unlink(key);
+            let entry = &mut self.entries[key];
+            entry.fire_at = match entry.fire_at.checked_sub(by) {
+                Some(fire_at) if fire_at > now => fire_at,
+                _ => now,
+            };
+            moved.push(key);
+        }
+        for key in moved.drain(..) {
+            self.link(key);
+        }
+        self.deferred = moved;
+    }
+
+    /// Picks the instant between `when` and `deadline` at which a timer fires.
+    ///
+    /// The instant picked is the first one at the coarsest tick boundary within the
+    /// window, so timers with overlapping windows tend to pick the same instant, and fire
+    /// together.
+    pub(crate) fn coalesce(&self, when: Instant, deadline: Instant) -> Instant {
+        let earliest = self.tick_of(when) + 1;
+        let latest = self.tick_of(deadline);
+        if latest < earliest {
+            return when;
+        }
+        for level in (0..LEVELS).rev() {
+            let granularity = 1u64 << (level * SLOT_BITS);
+            let boundary = (earliest + granularity - 1) & !(granularity - 1);
+            if boundary <= latest {
+                return self.instant_of(boundary);
+            }
+        }
+        unreachable!()
+    }
+
+    /// Adds a timer that fires at `fire_at`, and returns its key in the wheel.
+    pub(crate) fn insert(&mut self, id: u64, fire_at: Instant, waker: Waker) -> usize {
+        let entry = Entry {
+            id,
+            fire_at,
+            waker: Some(waker),
+            slot: NONE,
+            prev: NONE,
+            next: NONE,
+        };
+        let key = match self.free {
+            NONE => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+            key => {
+                self.free = self.entries[key].next;
+                self.entries[key] = entry;
+                key
+            }
+        };
+        self.link(key);
+        key
+    }
+
+    /// Removes the timer `id`, which was inserted with the given key. Nothing happens if
+    /// the timer already fired.
+    pub(crate) fn remove(&mut self, key: usize, id: u64) {
+        match self.entries.get(key) {
+            Some(entry) if entry.id == id && entry.slot != NONE => {}
+            _ => return,
+        }
+        self.unlink(key);
+        self.release(key);
+    }
+
+    fn release(&mut self, key: usize) {
+        let entry = &mut self.entries[key];
+        entry.waker = None;
+        entry.next = self.free;
+        self.free = key;
+    }
+
+    fn link(&mut self, key: usize) {
+        let tick = cmp::max(self.tick_of(self.entries[key].fire_at), self.elapsed);
+        let tick = cmp::min(tick, self.elapsed + MAX_TICKS);
+        let level = level_for(self.elapsed, tick);
+        let slot = ((tick >> (level * SLOT_BITS)) as usize) & (SLOTS - 1);
+        let index = level * SLOTS + slot;
+
+        let head = self.heads[index];
+        if head != NONE {
+            self.entries[head].prev = key;
+        }
+        let entry = &mut self.entries[key];
+        entry.slot = index;
+        entry.prev = NONE;
+        entry.next = head;
+        self.heads[index] = key;
+        self.occupied[level] |= 1 << slot;
+    }
+
+    fn unlink(&mut self, key: usize) {
+        let (index, prev, next) = {
+            let entry = &mut self.entries[key];
+            let links = (entry.slot, entry.prev, entry.next);
+            entry.slot = NONE;
+            links
+        };
+        if prev != NONE {
+            self.entries[prev].next = next;
+        } else {
+            self.heads[index] = next;
+        }
+        if next != NONE {
+            self.entries[next].prev = prev;
+        }
+        if self.heads[index] == NONE {
+            self.occupied[index / SLOTS] &= !(1 << (index % SLOTS));
+        }
+    }
+
+    // The level and slot of the next slot that time reaches and has entries, and the
+    // tick at which it starts. Slots in lower levels always start earlier than slots
+    // in the levels above them.
+    fn next_slot(&self) -> Option<(usize, usize, u64)> {
+        for level in 0..LEVELS {
+            let occupied = self.occupied[level];
+            if occupied == 0 {
+                continue;
+            }
+            let shift = level * SLOT_BITS;
+            let position = (self.elapsed >> shift) & (SLOTS as u64 - 1);
+            let distance = occupied.rotate_right(position as u32).trailing_zeros() as u64;
+            let slot = (position + distance) & (SLOTS as u64 - 1);
+            let range = 1u64 << (shift + SLOT_BITS);
+            let mut tick = (self.elapsed & !(range - 1)) + (slot << shift);
+            if slot < position {
+                tick += range;
+            }
+            return Some((level, slot as usize, tick));
+        }
+        None
+    }
+
+    /// Fires the timers due at `now`, up to `limit` of them, pushing their wakers into
+    /// `wakers`. Returns how many fired. Timers left out by the limit fire next time.
+    pub(crate) fn expire(&mut self, now: Instant, limit: usize, wakers: &mut Vec<Waker>) -> usize {
+        let now_tick = self.tick_of(now);
+        let mut fired = 0;
+        let mut caught_up = true;
+
+        while let Some((level, slot, tick)) = self.next_slot() {
+            if tick > now_tick {
+                break;
+            }
+            if fired >= limit {
+                caught_up = false;
+                break;
+            }
+            self.elapsed = cmp::max(self.elapsed, tick);
+
+            let index = level * SLOTS + slot;
+            let mut key = self.heads[index];
+            self.heads[index] = NONE;
+            self.occupied[level] &= !(1 << slot);
+            while key != NONE {
+                let next = self.entries[key].next;
+                self.entries[key].slot = NONE;
+                let fire_at = self.entries[key].fire_at;
+                if fire_at <= now && fired < limit {
+                    stat! {
+                        let late = now - fire_at;
+                        self.total_lateness += late;
+                        self.max_lateness = cmp::max(self.max_lateness, late);
+                    }
+                    wakers.push(self.entries[key].waker.take().unwrap());
+                    self.release(key);
+                    fired += 1;
+                } else if fire_at <= now || self.tick_of(fire_at) <= now_tick {
+                    // Either over the limit, or due later within the current tick. Linking
+                    // them now would put them back in a slot this loop is about to visit.
+                    self.deferred.push(key);
+                } else {
+                    self.link(key);
+                }
+                key = next;
+            }
+        }
+
+        if caught_up {
+            self.elapsed = cmp::max(self.elapsed, now_tick);
+        }
+        let mut deferred = std::mem::replace(&mut self.deferred, Vec::new());
+        for key in deferred.drain(..) {
+            self.link(key);
+        }
+        self.deferred = deferred;
+        fired
+    }
+
+    /// The instant at which the wheel needs to be looked at again: either the instant of
+    /// the next timer to fire, or the instant its timers are due to move down a level.
+    pub(crate) fn next_expiration(&self) -> Option<Instant> {
+        let (level, slot, tick) = self.next_slot()?;
+        if level > 0 {
+            return Some(self.instant_of(tick));
+        }
+        let mut key = self.heads[slot];
+        let mut earliest = None;
+        while key != NONE {
+            let fire_at = self.entries[key].fire_at;
+            earliest = Some(earliest.map_or(fire_at, |e: Instant| cmp::min(e, fire_at)));
+            key = self.entries[key].next;
+        }
+        earliest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::task::noop_waker;
+
+    #[test]
+    fn timers_fire_in_order_across_levels() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        let offsets = [0u64, 3, 63, 64, 100, 4095, 4096, 70_000, 5_000_000];
+        let mut keys = Vec::new();
+        for (id, offset) in offsets.iter().enumerate() {
+            let fire_at = start + Duration::from_millis(*offset) + Duration::from_micros(500);
+            keys.push(wheel.insert(id as u64, fire_at, noop_waker()));
+        }
+
+        // removing a timer is immediate, and removing it again does nothing
+        wheel.remove(keys[4], 4);
+        wheel.remove(keys[4], 4);
+
+        let mut wakers = Vec::new();
+        let mut fired = Vec::new();
+        for (id, offset) in offsets.iter().enumerate() {
+            if id == 4 {
+                continue;
+            }
+            let due = start + Duration::from_millis(*offset) + Duration::from_micros(500);
+            let next = wheel.next_expiration().unwrap();
+            assert!(next <= due, "timer {}", id);
+            // nothing fires before it is due
+            assert_eq!(
+                wheel.expire(due - Duration::from_micros(1), 100, &mut wakers),
+                0
+            );
+            assert_eq!(wheel.expire(due, 100, &mut wakers), 1, "timer {}", id);
+            fired.push(id);
+        }
+        assert_eq!(fired.len(), offsets.len() - 1);
+        assert!(wheel.next_expiration().is_none());
+
+        // the slots of fired timers are reused
+        let capacity = wheel.capacity();
+        for id in 100..108 {
+            wheel.insert(id, start, noop_waker());
+        }
+        assert_eq!(wheel.capacity(), capacity);
+    }
+
+    #[test]
+    fn expirations_respect_the_limit() {
+        let mut wheel = TimerWheel::new();
+        let when = wheel.start + Duration::from_millis(10);
+        for id in 0..10 {
+            wheel.insert(id, when, noop_waker());
+        }
+        let mut wakers = Vec::new();
+        assert_eq!(wheel.expire(when, 4, &mut wakers), 4);
+        assert_eq!(wheel.expire(when, 4, &mut wakers), 4);
+        assert_eq!(wheel.expire(when, 4, &mut wakers), 2);
+        assert_eq!(wakers.len(), 10);
+    }
+
+    #[test]
+    fn timers_are_brought_forward() {
+        let mut wheel = TimerWheel::new();
+        let at = |ms| wheel.start + Duration::from_millis(ms);
+        wheel.insert(0, at(10_000), noop_waker());
+        wheel.insert(1, at(100_000), noop_waker());
+
+        // as if the system was suspended for a minute 20 seconds in
+        wheel.bring_forward(Duration::from_secs(60), at(20_000));
+        let mut wakers = Vec::new();
+        assert_eq!(wheel.expire(at(20_000), 100, &mut wakers), 1);
+        assert!(wheel.next_expiration().unwrap() <= at(40_000));
+        assert_eq!(wheel.expire(at(39_999), 100, &mut wakers), 0);
+        assert_eq!(wheel.expire(at(40_000), 100, &mut wakers), 1);
+        // the timer that was due right away is not taken as late by the suspend
+        assert_eq!(wheel.lateness().1, Duration::from_secs(0));
+    }
+
+    #[test]
+    fn overlapping_windows_coalesce() {
+        let wheel = TimerWheel::new();
+        let at = |ms| wheel.start + Duration::from_millis(ms);
+        let first = wheel.coalesce(at(10), at(1010));
+        let second = wheel.coalesce(at(20), at(900));
+        assert_eq!(first, at(64));
+        assert_eq!(first, second);
+        // without slack, timers fire when they were asked to
+        assert_eq!(wheel.coalesce(at(10), at(10)), at(10));
+    }
+}
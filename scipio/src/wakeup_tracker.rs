@@ -0,0 +1,397 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::Timer;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::mem::ManuallyDrop;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::time::{Duration, Instant};
+
+/// What looks wrong with a task, as reported to the handler set with
+/// [`LocalExecutor::on_lost_wakeup`].
+///
+/// [`LocalExecutor::on_lost_wakeup`]: struct.LocalExecutor.html#method.on_lost_wakeup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeupIssue {
+    /// The task returned `Poll::Pending` without keeping its waker anywhere, and wasn't
+    /// woken up while it was polled. Nothing can wake it up anymore: it hangs for good.
+    NoWaker,
+
+    /// The task handed its waker out, but nobody used it for longer than the tracking
+    /// window. Either it waits for something that takes that long, or whoever holds the
+    /// waker forgot about it.
+    NotWoken,
+
+    /// The task was woken up, but wasn't polled again for longer than the tracking
+    /// window.
+    WokenNotPolled,
+}
+
+/// A task that may have lost a wakeup, as reported to the handler set with
+/// [`LocalExecutor::on_lost_wakeup`].
+///
+/// [`LocalExecutor::on_lost_wakeup`]: struct.LocalExecutor.html#method.on_lost_wakeup
+#[derive(Debug, Clone)]
+pub struct WakeupReport {
+    task: u64,
+    queue: &'static str,
+    issue: WakeupIssue,
+    stuck: Duration,
+}
+
+impl WakeupReport {
+    /// A number that identifies the task within its executor, in spawn order
+    pub fn task(&self) -> u64 {
+        self.task
+    }
+
+    /// The name of the task queue the task was spawned in
+    pub fn queue(&self) -> &'static str {
+        self.queue
+    }
+
+    /// What looks wrong with the task
+    pub fn issue(&self) -> WakeupIssue {
+        self.issue
+    }
+
+    /// How long the task has been in that state
+    pub fn stuck(&self) -> Duration {
+        self.stuck
+    }
+}
+
+impl fmt::Display for WakeupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.issue {
+            WakeupIssue::NoWaker => "is pending but nothing holds its waker",
+            WakeupIssue::NotWoken => "was not woken up",
+            WakeupIssue::WokenNotPolled => "was woken up but not polled",
+        };
+        write!(
+            f,
+            "task {} in queue {} {} for {:?}",
+            self.task, self.queue, what, self.stuck
+        )
+    }
+}
+
+// What the tracked future and the wakers it hands out share. Wakers may travel to other
+// threads, so everything they touch is atomic.
+#[derive(Debug)]
+struct Tracker {
+    id: u64,
+    queue: &'static str,
+    created: Instant,
+    // wakers handed out by the task and not dropped yet
+    wakers: AtomicUsize,
+    // nanoseconds since `created`, plus one, or zero if not woken since the last poll
+    woken_at: AtomicU64,
+    // the same for when the last poll returned pending, or zero if it's not pending
+    pending_since: AtomicU64,
+    reported: AtomicBool,
+    done: AtomicBool,
+}
+
+impl Tracker {
+    fn stamp(&self) -> u64 {
+        self.created.elapsed().as_nanos() as u64 + 1
+    }
+
+    fn since(&self, stamp: u64) -> Duration {
+        self.created.elapsed() - Duration::from_nanos(stamp - 1)
+    }
+}
+
+// The waker handed to the tracked future, which wraps the one of the task
+struct TrackingWaker {
+    tracker: Arc<Tracker>,
+    inner: Waker,
+}
+
+const VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(ptr: *const ()) -> RawWaker {
+    let waker = ManuallyDrop::new(Arc::from_raw(ptr as *const TrackingWaker));
+    waker.tracker.wakers.fetch_add(1, Ordering::AcqRel);
+    let clone: Arc<TrackingWaker> = Arc::clone(&waker);
+    RawWaker::new(Arc::into_raw(clone) as *const (), &VTABLE)
+}
+
+unsafe fn wake(ptr: *const ()) {
+    wake_by_ref(ptr);
+    drop_waker(ptr);
+}
+
+unsafe fn wake_by_ref(ptr: *const ()) {
+    let waker = ManuallyDrop::new(Arc::from_raw(ptr as *const TrackingWaker));
+    let tracker = &waker.tracker;
+    // only the first wakeup since the last poll counts
+    let _ =
+        tracker
+            .woken_at
+            .compare_exchange(0, tracker.stamp(), Ordering::AcqRel, Ordering::Acquire);
+    waker.inner.wake_by_ref();
+}
+
+unsafe fn drop_waker(ptr: *const ()) {
+    let waker = Arc::from_raw(ptr as *const TrackingWaker);
+    waker.tracker.wakers.fetch_sub(1, Ordering::AcqRel);
+}
+
+#[derive(Default)]
+struct Tracking {
+    window: Cell<Option<Duration>>,
+    handler: RefCell<Option<Rc<dyn Fn(&WakeupReport)>>>,
+    tasks: RefCell<Vec<Weak<Tracker>>>,
+    next_id: Cell<u64>,
+    checking: Cell<bool>,
+    reported: Cell<u64>,
+}
+
+thread_local!(static TRACKING: Tracking = Tracking::default());
+
+pub(crate) fn window() -> Option<Duration> {
+    TRACKING.with(|tracking| tracking.window.get())
+}
+
+pub(crate) fn set_handler(handler: Option<Rc<dyn Fn(&WakeupReport)>>) {
+    TRACKING.with(|tracking| *tracking.handler.borrow_mut() = handler);
+}
+
+pub(crate) fn reported() -> u64 {
+    TRACKING.with(|tracking| tracking.reported.get())
+}
+
+fn report(report: WakeupReport) {
+    let handler = TRACKING.with(|tracking| {
+        tracking.reported.set(tracking.reported.get() + 1);
+        tracking.handler.borrow().clone()
+    });
+    match handler {
+        Some(handler) => handler(&report),
+        None => eprintln!("{}", report),
+    }
+}
+
+/// Sets the tracking window, and returns a future that looks for tasks stuck for longer
+/// than that every so often, if nobody does already. It stops once tracking is disabled.
+pub(crate) fn set_window(window: Option<Duration>) -> Option<impl Future<Output = ()>> {
+    let start = TRACKING.with(|tracking| {
+        tracking.window.set(window);
+        window.is_some() && !tracking.checking.replace(window.is_some())
+    });
+    if !start {
+        return None;
+    }
+    Some(async {
+        while let Some(window) = self::window() {
+            Timer::new(window / 2).await;
+            check(window);
+        }
+        TRACKING.with(|tracking| tracking.checking.set(false));
+    })
+}
+
+fn check(window: Duration) {
+    let mut stuck = Vec::new();
+    TRACKING.with(|tracking| {
+        tracking.tasks.borrow_mut().retain(|task| {
+            let tracker = match task.upgrade() {
+                Some(tracker) if !tracker.done.load(Ordering::Acquire) => tracker,
+                _ => return false,
+            };
+            let pending_since = tracker.pending_since.load(Ordering::Acquire);
+            if pending_since == 0 || tracker.reported.load(Ordering::Acquire) {
+                return true;
+            }
+            let woken_at = tracker.woken_at.load(Ordering::Acquire);
+            let (issue, since) = if woken_at != 0 {
+                (WakeupIssue::WokenNotPolled, tracker.since(woken_at))
+            } else {
+                (WakeupIssue::NotWoken, tracker.since(pending_since))
+            };
+            if since >= window {
+                tracker.reported.store(true, Ordering::Release);
+                stuck.push(WakeupReport {
+                    task: tracker.id,
+                    queue: tracker.queue,
+                    issue,
+                    stuck: since,
+                });
+            }
+            true
+        });
+    });
+    for r in stuck {
+        report(r);
+    }
+}
+
+/// A future whose wakeups are tracked, to find the ones that get lost
+pub(crate) struct Tracked<F> {
+    future: Pin<Box<F>>,
+    tracker: Arc<Tracker>,
+    // The shared part of the wakers handed out, to hand it out again while the task's
+    // waker stays the same. It is only kept alive by the wakers the future holds on to:
+    // it holds the task's waker, which would otherwise keep the task alive through its
+    // own future.
+    waker: Weak<TrackingWaker>,
+}
+
+impl<F: Future> Tracked<F> {
+    pub(crate) fn new(queue: &'static str, future: F) -> Tracked<F> {
+        let tracker = TRACKING.with(|tracking| {
+            let id = tracking.next_id.get();
+            tracking.next_id.set(id + 1);
+            let tracker = Arc::new(Tracker {
+                id,
+                queue,
+                created: Instant::now(),
+                wakers: AtomicUsize::new(0),
+                woken_at: AtomicU64::new(0),
+                pending_since: AtomicU64::new(0),
+                reported: AtomicBool::new(false),
+                done: AtomicBool::new(false),
+            });
+            tracking.tasks.borrow_mut().push(Arc::downgrade(&tracker));
+            tracker
+        });
+        Tracked {
+            future: Box::pin(future),
+            tracker,
+            waker: Weak::new(),
+        }
+    }
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let tracker = self.tracker.clone();
+        tracker.woken_at.store(0, Ordering::Release);
+        tracker.pending_since.store(0, Ordering::Release);
+        tracker.reported.store(false, Ordering::Release);
+
+        let shared = match self.waker.upgrade() {
+            Some(shared) if shared.inner.will_wake(cx.waker()) => shared,
+            _ => {
+                let shared = Arc::new(TrackingWaker {
+                    tracker: tracker.clone(),
+                    inner: cx.waker().clone(),
+                });
+                self.waker = Arc::downgrade(&shared);
+                shared
+            }
+        };
+        // The waker handed to the future borrows `shared` for the poll, so it doesn't count
+        // as handed out, and dropping it the usual way would count it as one going away.
+        let raw = RawWaker::new(Arc::as_ptr(&shared) as *const (), &VTABLE);
+        let waker = ManuallyDrop::new(unsafe { Waker::from_raw(raw) });
+        let res = self.future.as_mut().poll(&mut Context::from_waker(&waker));
+        drop(shared);
+        if res.is_ready() {
+            tracker.done.store(true, Ordering::Release);
+            return res;
+        }
+
+        let woken = tracker.woken_at.load(Ordering::Acquire) != 0;
+        if !woken && tracker.wakers.load(Ordering::Acquire) == 0 && window().is_some() {
+            tracker.reported.store(true, Ordering::Release);
+            report(WakeupReport {
+                task: tracker.id,
+                queue: tracker.queue,
+                issue: WakeupIssue::NoWaker,
+                stuck: Duration::from_secs(0),
+            });
+        }
+        tracker
+            .pending_since
+            .store(tracker.stamp(), Ordering::Release);
+        Poll::Pending
+    }
+}
+
+impl<F> Drop for Tracked<F> {
+    fn drop(&mut self) {
+        self.tracker.done.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Local, LocalExecutor};
+    use futures::future::poll_fn;
+
+    #[test]
+    fn lost_wakeups_are_reported() {
+        let local_ex = LocalExecutor::new(None).unwrap();
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let r = reports.clone();
+        local_ex.on_lost_wakeup(move |report| r.borrow_mut().push(report.clone()));
+        local_ex.track_wakeups(Some(Duration::from_millis(30)));
+        assert_eq!(local_ex.wakeup_window(), Some(Duration::from_millis(30)));
+
+        local_ex.run(async {
+            // pending without keeping the waker: it can never complete
+            let _lost = Local::local(poll_fn(|_| Poll::<()>::Pending));
+            // keeps the waker, but nobody uses it
+            let _forgotten = Local::local(poll_fn(|cx| {
+                std::mem::forget(cx.waker().clone());
+                Poll::<()>::Pending
+            }));
+            // timers that fire within the window are fine
+            for _ in 0..10 {
+                Timer::new(Duration::from_millis(10)).await;
+            }
+        });
+        local_ex.track_wakeups(None);
+
+        let reports = reports.borrow();
+        let issues: Vec<_> = reports.iter().map(|r| r.issue()).collect();
+        assert!(issues.contains(&WakeupIssue::NoWaker));
+        assert!(issues.contains(&WakeupIssue::NotWoken));
+        assert!(!issues.contains(&WakeupIssue::WokenNotPolled));
+        assert_eq!(local_ex.lost_wakeups_reported(), reports.len() as u64);
+    }
+
+    #[test]
+    fn tracked_tasks_nobody_can_wake_are_dropped() {
+        struct SetOnDrop(Rc<Cell<bool>>);
+
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let local_ex = LocalExecutor::new(None).unwrap();
+        local_ex.on_lost_wakeup(|_| {});
+        local_ex.track_wakeups(Some(Duration::from_secs(10)));
+
+        let dropped = Rc::new(Cell::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        local_ex.run(async {
+            Local::local(poll_fn(move |_| {
+                let _ = &guard;
+                Poll::<()>::Pending
+            }))
+            .detach();
+            for _ in 0..10 {
+                Local::later().await;
+            }
+        });
+        local_ex.track_wakeups(None);
+        assert!(dropped.get());
+    }
+}
@@ -0,0 +1,515 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+use crate::parking::Reactor;
+use crate::Local;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{Builder, Thread};
+use std::time::{Duration, Instant};
+
+/// What the watchdog does after reporting a stuck executor
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Keep the executor running, and report it again if it gets stuck again after
+    /// making some progress
+    Continue,
+    /// Terminate the executor as soon as its loop regains control.
+    ///
+    /// The executor's thread unwinds with a [`WatchdogTerminated`] payload, which
+    /// supervisors can find in the result of joining the thread to tell a stuck shard
+    /// apart from one that panicked.
+    ///
+    /// [`WatchdogTerminated`]: struct.WatchdogTerminated.html
+    Terminate,
+}
+
+/// The state of a stuck executor, as seen by the watchdog
+#[derive(Debug, Clone)]
+pub struct WatchdogReport {
+    /// The id of the executor
+    pub executor_id: usize,
+    /// The shard id of the executor
+    pub shard_id: usize,
+    /// The name of the thread running the executor, if it has one
+    pub thread_name: Option<String>,
+    /// How long ago the executor loop last made progress
+    pub stalled_for: Duration,
+    /// How many times the executor loop ran so far
+    pub iterations: u64,
+    /// The task queue that was running when the executor got stuck, if any
+    pub task_queue: Option<&'static str>,
+}
+
+impl fmt::Display for WatchdogReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "executor {} (shard {}, thread {}) made no progress for {:?} after {} iterations",
+            self.executor_id,
+            self.shard_id,
+            self.thread_name.as_deref().unwrap_or("<unnamed>"),
+            self.stalled_for,
+            self.iterations
+        )?;
+        match self.task_queue {
+            Some(name) => write!(f, ", running task queue {}", name),
+            None => write!(f, ", outside of any task queue"),
+        }
+    }
+}
+
+/// The payload a thread unwinds with when the watchdog terminates its executor
+#[derive(Debug, Clone)]
+pub struct WatchdogTerminated {
+    /// The last report the watchdog made about the executor
+    pub report: WatchdogReport,
+}
+
+// Shared between an executor and its watchdog thread. The executor beats at every loop
+// iteration while it has work to do, and goes idle before it sleeps: sleeping while
+// waiting for events is not being stuck.
+#[derive(Debug)]
+struct Shared {
+    started: Instant,
+    // Nanoseconds since `started` of the last beat, plus one. Zero means idle.
+    last_beat: AtomicU64,
+    // Same as `last_beat`, for the end of the latest CPU slice declared by a live
+    // `CpuSliceGuard`. Zero means no slice was declared.
+    slice_end: AtomicU64,
+    iterations: AtomicU64,
+    // The index in `task_queues` of the task queue running, plus one. Zero means none.
+    task_queue: AtomicUsize,
+    // The names of the task queues the executor ran so far. Only grows the first time
+    // a task queue runs, so the executor doesn't lock it as it goes from one to another.
+    task_queues: Mutex<Vec<&'static str>>,
+    terminate: Mutex<Option<WatchdogReport>>,
+    terminating: AtomicBool,
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_nanos() as u64 + 1
+    }
+
+    fn stalled_for(&self) -> Option<Duration> {
+        match self.last_beat.load(Ordering::Acquire) {
+            0 => None,
+            beat => {
+                // Declared CPU slices count as progress until they are over
+                let beat = std::cmp::max(beat, self.slice_end.load(Ordering::Acquire));
+                Some(Duration::from_nanos(self.now().saturating_sub(beat)))
+            }
+        }
+    }
+
+    fn task_queue(&self) -> Option<&'static str> {
+        match self.task_queue.load(Ordering::Acquire) {
+            0 => None,
+            index => Some(self.task_queues.lock().unwrap()[index - 1]),
+        }
+    }
+}
+
+// The executor's side of the heartbeat. Only the executor's thread touches it, so what
+// the watchdog thread doesn't need to see is kept in cells.
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    shared: Arc<Shared>,
+    // The end of the slice of every live guard, by guard. Guards can be held across
+    // awaits, so they don't necessarily go away in the order they were created.
+    slices: RefCell<HashMap<u64, u64>>,
+    next_slice: Cell<u64>,
+    // The same names as `shared.task_queues`, to look them up without locking
+    task_queues: RefCell<Vec<&'static str>>,
+}
+
+impl Heartbeat {
+    fn new(shared: Arc<Shared>) -> Heartbeat {
+        Heartbeat {
+            shared,
+            slices: RefCell::new(HashMap::new()),
+            next_slice: Cell::new(0),
+            task_queues: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn beat(&self) {
+        let now = self.shared.now();
+        self.shared.last_beat.store(now, Ordering::Release);
+        self.shared.iterations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn idle(&self) {
+        self.shared.last_beat.store(0, Ordering::Release);
+    }
+
+    pub(crate) fn enter_task_queue(&self, name: Option<&'static str>) {
+        let index = name.map_or(0, |name| self.task_queue_index(name) + 1);
+        self.shared.task_queue.store(index, Ordering::Release);
+    }
+
+    fn task_queue_index(&self, name: &'static str) -> usize {
+        let mut names = self.task_queues.borrow_mut();
+        match names.iter().position(|known| std::ptr::eq(*known, name)) {
+            Some(index) => index,
+            None => {
+                names.push(name);
+                self.shared.task_queues.lock().unwrap().push(name);
+                names.len() - 1
+            }
+        }
+    }
+
+    /// Unwinds the current thread if the watchdog decided to terminate the executor.
+    pub(crate) fn check_terminated(&self) {
+        if self.shared.terminating.load(Ordering::Acquire) {
+            let report = self.shared.terminate.lock().unwrap().take().unwrap();
+            std::panic::resume_unwind(Box::new(WatchdogTerminated { report }));
+        }
+    }
+
+    // Returns the key of the slice, to end it with.
+    fn declare_slice(&self, expected: Duration) -> u64 {
+        let end = self.shared.now() + expected.as_nanos() as u64;
+        let key = self.next_slice.get();
+        self.next_slice.set(key + 1);
+        let mut slices = self.slices.borrow_mut();
+        slices.insert(key, end);
+        self.update_slice_end(&slices);
+        key
+    }
+
+    fn end_slice(&self, key: u64) {
+        let mut slices = self.slices.borrow_mut();
+        slices.remove(&key);
+        self.update_slice_end(&slices);
+    }
+
+    fn update_slice_end(&self, live: &HashMap<u64, u64>) {
+        let end = live.values().copied().max().unwrap_or(0);
+        self.shared.slice_end.store(end, Ordering::Release);
+    }
+}
+
+/// Watches the heartbeat of an executor from a thread of its own. Stops watching when
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    heartbeat: Rc<Heartbeat>,
+    thread: Thread,
+}
+
+impl Watchdog {
+    pub(crate) fn start<F>(
+        executor_id: usize,
+        shard_id: usize,
+        timeout: Duration,
+        callback: F,
+    ) -> io::Result<Watchdog>
+    where
+        F: Fn(&WatchdogReport) -> WatchdogAction + Send + 'static,
+    {
+        let watched = Arc::new(Shared {
+            started: Instant::now(),
+            last_beat: AtomicU64::new(0),
+            slice_end: AtomicU64::new(0),
+            iterations: AtomicU64::new(0),
+            task_queue: AtomicUsize::new(0),
+            task_queues: Mutex::new(Vec::new()),
+            terminate: Mutex::new(None),
+            terminating: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+        });
+        let heartbeat = Rc::new(Heartbeat::new(watched.clone()));
+        let thread_name = std::thread::current().name().map(|x| x.to_string());
+        let interval = std::cmp::max(timeout / 4, Duration::from_millis(1));
+
+        let handle = Builder::new()
+            .name(format!("watchdog-{}", executor_id))
+            .spawn(move || {
+                // Only report an executor once per stall
+                let mut reported_at = None;
+                while !watched.stopped.load(Ordering::Acquire) {
+                    std::thread::park_timeout(interval);
+                    let stalled_for = match watched.stalled_for() {
+                        Some(stalled_for) if stalled_for >= timeout => stalled_for,
+                        _ => continue,
+                    };
+                    let iterations = watched.iterations.load(Ordering::Relaxed);
+                    if reported_at == Some(iterations) {
+                        continue;
+                    }
+                    reported_at = Some(iterations);
+
+                    let report = WatchdogReport {
+                        executor_id,
+                        shard_id,
+                        thread_name: thread_name.clone(),
+                        stalled_for,
+                        iterations,
+                        task_queue: watched.task_queue(),
+                    };
+                    if callback(&report) == WatchdogAction::Terminate {
+                        *watched.terminate.lock().unwrap() = Some(report);
+                        watched.terminating.store(true, Ordering::Release);
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Watchdog {
+            heartbeat,
+            thread: handle.thread().clone(),
+        })
+    }
+
+    pub(crate) fn heartbeat(&self) -> &Rc<Heartbeat> {
+        &self.heartbeat
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.heartbeat.shared.stopped.store(true, Ordering::Release);
+        self.thread.unpark();
+    }
+}
+
+/// Declares that the current task is about to run a long, CPU-heavy computation.
+///
+/// Tasks in an executor are cooperative, so a checksum or compaction loop that runs for
+/// a long time without awaiting starves every other task in the executor. While holding
+/// a `CpuSliceGuard` such loops can cheaply ask [`should_yield`] whether they exhausted
+/// their time slice, and give control back to the executor when they did.
+///
+/// The guard also tells the [watchdog] that the executor is expected to make no
+/// progress for as long as the duration the computation was declared with, so it is not
+/// reported as stuck in the meantime. Computations that overrun their declaration are
+/// reported as usual, once the watchdog timeout elapses past the declared duration.
+///
+/// Guards are created with [`Local::cpu_slice`] and end their declaration when dropped.
+/// Tasks can hold them across awaits, and many tasks can hold one at the same time: the
+/// executor is not reported as stuck until the latest slice still held is over.
+///
+/// # Examples
+///
+/// ```
+/// use scipio::{Local, LocalExecutor};
+/// use std::time::Duration;
+///
+/// let ex = LocalExecutor::new(None).expect("failed to create local executor");
+///
+/// ex.run(async {
+///     let data = vec![1u8; 1 << 20];
+///     let mut checksum = 0u64;
+///
+///     let slice = Local::cpu_slice(Duration::from_millis(100));
+///     for chunk in data.chunks(4096) {
+///         checksum = chunk.iter().fold(checksum, |acc, x| acc.wrapping_add(*x as u64));
+///         slice.yield_if_needed().await;
+///     }
+///     drop(slice);
+///
+///     assert_eq!(checksum, 1 << 20);
+/// });
+/// ```
+///
+/// [`should_yield`]: struct.CpuSliceGuard.html#method.should_yield
+/// [watchdog]: struct.LocalExecutor.html#method.set_watchdog
+/// [`Local::cpu_slice`]: struct.Task.html#method.cpu_slice
+#[derive(Debug)]
+#[must_use = "the CPU slice ends as soon as the guard is dropped"]
+pub struct CpuSliceGuard {
+    // The heartbeat of the executor, and the key of the slice in it
+    heartbeat: Option<(Rc<Heartbeat>, u64)>,
+    // The guard refers to the executor it was created in
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl CpuSliceGuard {
+    pub(crate) fn new(heartbeat: Option<Rc<Heartbeat>>, expected: Duration) -> CpuSliceGuard {
+        CpuSliceGuard {
+            heartbeat: heartbeat.map(|heartbeat| {
+                let key = heartbeat.declare_slice(expected);
+                (heartbeat, key)
+            }),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns true if the current task ran for longer than its time slice and should
+    /// yield, so other tasks can run.
+    ///
+    /// This only reads shared memory, so it is cheap enough to be called at every
+    /// iteration of a tight loop.
+    #[inline]
+    pub fn should_yield(&self) -> bool {
+        Reactor::need_preempt()
+    }
+
+    /// Yields the current task if it ran for longer than its time slice. See
+    /// [`should_yield`]
+    ///
+    /// [`should_yield`]: struct.CpuSliceGuard.html#method.should_yield
+    #[inline]
+    pub async fn yield_if_needed(&self) {
+        if self.should_yield() {
+            Local::later().await;
+        }
+    }
+}
+
+impl Drop for CpuSliceGuard {
+    fn drop(&mut self) {
+        if let Some((heartbeat, key)) = self.heartbeat.take() {
+            heartbeat.end_slice(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Local, LocalExecutor};
+    use std::sync::mpsc;
+
+    #[test]
+    fn watchdog_reports_stuck_executor() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let local_ex = LocalExecutor::new(None).unwrap();
+        local_ex
+            .set_watchdog(Duration::from_millis(20), move |report| {
+                sender.lock().unwrap().send(report.clone()).unwrap();
+                WatchdogAction::Continue
+            })
+            .unwrap();
+
+        local_ex.run(async {
+            // Idle executors are not stuck
+            crate::Timer::new(Duration::from_millis(100)).await;
+            Local::local(async {
+                std::thread::sleep(Duration::from_millis(100));
+            })
+            .await;
+        });
+
+        let report = receiver.try_recv().unwrap();
+        assert_eq!(report.executor_id, local_ex.id());
+        assert!(report.stalled_for >= Duration::from_millis(20));
+        assert_eq!(report.task_queue, Some("default"));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn watchdog_terminates_stuck_executor() {
+        let handle = LocalExecutor::spawn_executor("stuck", None, || async move {
+            Local::set_watchdog(Duration::from_millis(20), |_| WatchdogAction::Terminate).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            Local::later().await;
+            unreachable!("the executor should have been terminated");
+        })
+        .unwrap();
+
+        let payload = handle.join().unwrap_err();
+        let terminated = payload.downcast_ref::<WatchdogTerminated>().unwrap();
+        assert!(terminated.report.stalled_for >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn cpu_slice_suppresses_watchdog_reports() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let local_ex = LocalExecutor::new(None).unwrap();
+        local_ex
+            .set_watchdog(Duration::from_millis(20), move |report| {
+                sender.lock().unwrap().send(report.clone()).unwrap();
+                WatchdogAction::Continue
+            })
+            .unwrap();
+
+        local_ex.run(async {
+            Local::local(async {
+                let slice = Local::cpu_slice(Duration::from_millis(500));
+                std::thread::sleep(Duration::from_millis(100));
+                slice.yield_if_needed().await;
+            })
+            .await;
+        });
+        assert!(receiver.try_recv().is_err());
+
+        local_ex.run(async {
+            Local::local(async {
+                let _slice = Local::cpu_slice(Duration::from_millis(10));
+                std::thread::sleep(Duration::from_millis(100));
+            })
+            .await;
+        });
+        let report = receiver.try_recv().unwrap();
+        assert!(report.stalled_for >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn cpu_slices_held_across_awaits_end_independently() {
+        use futures::channel::oneshot;
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let local_ex = LocalExecutor::new(None).unwrap();
+        local_ex
+            .set_watchdog(Duration::from_millis(20), move |report| {
+                sender.lock().unwrap().send(report.clone()).unwrap();
+                WatchdogAction::Continue
+            })
+            .unwrap();
+
+        // Ending a slice doesn't end the ones other tasks declared after it
+        local_ex.run(async {
+            let short = Local::cpu_slice(Duration::from_millis(10));
+            let (ready, is_ready) = oneshot::channel();
+            let (done, is_done) = oneshot::channel::<()>();
+            let task = Local::local(async move {
+                let long = Local::cpu_slice(Duration::from_millis(500));
+                ready.send(()).unwrap();
+                is_done.await.unwrap();
+                drop(long);
+            });
+            is_ready.await.unwrap();
+            drop(short);
+            std::thread::sleep(Duration::from_millis(100));
+            done.send(()).unwrap();
+            task.await;
+        });
+        assert!(receiver.try_recv().is_err());
+
+        // and a slice that ended doesn't come back when a later one ends
+        local_ex.run(async {
+            let long = Local::cpu_slice(Duration::from_millis(500));
+            let (ready, is_ready) = oneshot::channel();
+            let (done, is_done) = oneshot::channel::<()>();
+            let task = Local::local(async move {
+                let short = Local::cpu_slice(Duration::from_millis(10));
+                ready.send(()).unwrap();
+                is_done.await.unwrap();
+                drop(short);
+            });
+            is_ready.await.unwrap();
+            drop(long);
+            done.send(()).unwrap();
+            task.await;
+            std::thread::sleep(Duration::from_millis(100));
+        });
+        let report = receiver.try_recv().unwrap();
+        assert!(report.stalled_for >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,692 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+//! AF_XDP sockets, that move packets between a network queue and memory shared with the
+//! kernel, bypassing the network stack.
+use crate::parking::Reactor;
+use crate::sys::DmaBuffer;
+use crate::{Async, Timer};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+use std::time::Duration;
+
+// From linux/if_xdp.h, which the libc crate doesn't have yet
+const AF_XDP: libc::c_int = 44;
+const SOL_XDP: libc::c_int = 283;
+const XDP_MMAP_OFFSETS: libc::c_int = 1;
+const XDP_RX_RING: libc::c_int = 2;
+const XDP_TX_RING: libc::c_int = 3;
+const XDP_UMEM_REG: libc::c_int = 4;
+const XDP_UMEM_FILL_RING: libc::c_int = 5;
+const XDP_UMEM_COMPLETION_RING: libc::c_int = 6;
+const XDP_STATISTICS: libc::c_int = 7;
+const XDP_PGOFF_RX_RING: libc::off_t = 0;
+const XDP_PGOFF_TX_RING: libc::off_t = 0x8000_0000;
+const XDP_UMEM_PGOFF_FILL_RING: libc::off_t = 0x1_0000_0000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: libc::off_t = 0x1_8000_0000;
+const XDP_COPY: u16 = 1 << 1;
+const XDP_ZEROCOPY: u16 = 1 << 2;
+
+// How long a sender out of frames waits before looking for completed ones again, at
+// first and at most
+const MIN_COMPLETION_WAIT: Duration = Duration::from_micros(10);
+const MAX_COMPLETION_WAIT: Duration = Duration::from_millis(1);
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct XdpUmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SockaddrXdp {
+    family: u16,
+    flags: u16,
+    ifindex: u32,
+    queue_id: u32,
+    shared_umem_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct XdpRingOffset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct XdpMmapOffsets {
+    rx: XdpRingOffset,
+    tx: XdpRingOffset,
+    fr: XdpRingOffset,
+    cr: XdpRingOffset,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct XdpStatistics {
+    rx_dropped: u64,
+    rx_invalid_descs: u64,
+    tx_invalid_descs: u64,
+}
+
+/// How to set up an [`XdpSocket`]
+///
+/// [`XdpSocket`]: struct.XdpSocket.html
+#[derive(Debug, Clone)]
+pub struct XdpConfig {
+    /// The size of each frame of the shared memory, which bounds the size of a packet.
+    /// Either 2048 or 4096.
+    pub frame_size: usize,
+    /// How many frames to share with the kernel. Half of them are used to receive, and
+    /// the other half to send.
+    pub frames: usize,
+    /// The number of entries of each of the four rings. Must be a power of two.
+    pub ring_size: u32,
+    /// Whether to ask the driver to move packets without copying them. Binding fails if
+    /// the driver doesn't support it. When false, the kernel picks the mode.
+    pub zero_copy: bool,
+}
+
+impl Default for XdpConfig {
+    fn default() -> Self {
+        XdpConfig {
+            frame_size: 2048,
+            frames: 4096,
+            ring_size: 2048,
+            zero_copy: false,
+        }
+    }
+}
+
+/// Statistics about an [`XdpSocket`]
+///
+/// [`XdpSocket`]: struct.XdpSocket.html
+#[derive(Debug, Clone, Default)]
+pub struct XdpStats {
+    received: u64,
+    sent: u64,
+    rx_dropped: u64,
+    invalid_descriptors: u64,
+}
+
+impl XdpStats {
+    /// Packets received
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Packets sent
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Packets the kernel dropped because there were no frames to receive them into
+    pub fn rx_dropped(&self) -> u64 {
+        self.rx_dropped
+    }
+
+    /// Descriptors the kernel rejected, on either ring
+    pub fn invalid_descriptors(&self) -> u64 {
+        self.invalid_descriptors
+    }
+}
+
+// One of the four rings shared with the kernel. The producer and consumer are free
+// running counters, and entries are at the counter masked by the size of the ring.
+#[derive(Debug)]
+struct Ring<T> {
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    entries: *mut T,
+    mask: u32,
+    // our copy of the counter this side moves
+    local: u32,
+    map: *mut libc::c_void,
+    map_len: usize,
+}
+
+impl<T: Copy> Ring<T> {
+    // A ring we produce into, like the fill and transmit rings
+    fn free_entries(&self) -> u32 {
+        let consumer = unsafe { (*self.consumer).load(Ordering::Acquire) };
+        (self.mask + 1) - self.local.wrapping_sub(consumer)
+    }
+
+    fn produce(&mut self, entry: T) {
+        unsafe { ptr::write(self.entries.add((self.local & self.mask) as usize), entry) };
+        self.local = self.local.wrapping_add(1);
+    }
+
+    fn publish(&self) {
+        fence(Ordering::Release);
+        unsafe { (*self.producer).store(self.local, Ordering::Release) };
+    }
+
+    // A ring we consume from, like the receive and completion rings
+    fn consume(&mut self) -> Option<T> {
+        let producer = unsafe { (*self.producer).load(Ordering::Acquire) };
+        if producer == self.local {
+            return None;
+        }
+        let entry = unsafe { ptr::read(self.entries.add((self.local & self.mask) as usize)) };
+        self.local = self.local.wrapping_add(1);
+        unsafe { (*self.consumer).store(self.local, Ordering::Release) };
+        Some(entry)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        if !self.map.is_null() {
+            unsafe { libc::munmap(self.map, self.map_len) };
+        }
+    }
+}
+
+// The socket, closed when dropped
+#[derive(Debug)]
+struct XdpFd(RawFd);
+
+impl AsRawFd for XdpFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for XdpFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn set_option<T>(fd: RawFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            SOL_XDP,
+            name,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn get_option<T: Default>(fd: RawFd, name: libc::c_int) -> io::Result<T> {
+    let mut value = T::default();
+    let mut len = mem::size_of::<T>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            SOL_XDP,
+            name,
+            &mut value as *mut T as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn map_ring<T>(
+    fd: RawFd,
+    offsets: &XdpRingOffset,
+    size: u32,
+    pgoff: libc::off_t,
+) -> io::Result<Ring<T>> {
+    let map_len = offsets.desc as usize + size as usize * mem::size_of::<T>();
+    let map = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            map_len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            fd,
+            pgoff,
+        )
+    };
+    if map == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    let base = map as *mut u8;
+    unsafe {
+        let producer = base.add(offsets.producer as usize) as *const AtomicU32;
+        let consumer = base.add(offsets.consumer as usize) as *const AtomicU32;
+        // Producer rings start from where the kernel expects the producer, and consumer
+        // rings from where it expects the consumer; both are zero on a new socket.
+        Ok(Ring {
+            producer,
+            consumer,
+            entries: base.add(offsets.desc as usize) as *mut T,
+            mask: size - 1,
+            local: 0,
+            map,
+            map_len,
+        })
+    }
+}
+
+// Fields are dropped in declaration order, so the rings are unmapped before the UMEM is
+// freed. The socket is closed before this is dropped, see XdpSocket.
+#[derive(Debug)]
+struct Shared {
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    rx: Ring<XdpDesc>,
+    tx: Ring<XdpDesc>,
+    frame_size: usize,
+    // frames that are free to hand to the kernel for receiving, and for sending
+    rx_frames: Vec<u64>,
+    tx_frames: Vec<u64>,
+    stats: XdpStats,
+    umem: DmaBuffer,
+}
+
+impl Shared {
+    // Hands the free receive frames to the kernel, and takes back the frames it is done
+    // sending from. This is the pumping that keeps packets flowing both ways.
+    fn pump(&mut self) {
+        let mut refilled = false;
+        while !self.rx_frames.is_empty() && self.fill.free_entries() > 0 {
+            let frame = self.rx_frames.pop().unwrap();
+            self.fill.produce(frame);
+            refilled = true;
+        }
+        if refilled {
+            self.fill.publish();
+        }
+        while let Some(frame) = self.completion.consume() {
+            self.tx_frames.push(frame);
+        }
+    }
+
+    // The packet at `addr`. Only the frame is borrowed: the kernel may be writing to
+    // other frames of the UMEM meanwhile, so no reference to it can span them.
+    fn frame(&self, addr: u64, len: usize) -> &[u8] {
+        assert!(addr as usize + len <= self.umem.len());
+        unsafe { std::slice::from_raw_parts(self.umem.as_ptr().add(addr as usize), len) }
+    }
+
+    // Copies `packet` to the frame at `addr`, which the kernel doesn't own.
+    fn fill_frame(&mut self, addr: u64, packet: &[u8]) {
+        assert!(addr as usize + packet.len() <= self.umem.len());
+        unsafe {
+            ptr::copy_nonoverlapping(
+                packet.as_ptr(),
+                self.umem.as_mut_ptr().add(addr as usize),
+                packet.len(),
+            )
+        };
+    }
+}
+
+/// A packet received on an [`XdpSocket`], read right from the memory the network card
+/// wrote it to. Its frame goes back to the kernel, to receive more packets, when it is
+/// dropped.
+///
+/// [`XdpSocket`]: struct.XdpSocket.html
+#[derive(Debug)]
+pub struct XdpFrame {
+    shared: Rc<RefCell<Shared>>,
+    addr: u64,
+    len: usize,
+}
+
+impl XdpFrame {
+    /// Calls `f` with the contents of the packet. The socket can't be used from `f`.
+    pub fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        f(self.shared.borrow().frame(self.addr, self.len))
+    }
+
+    /// The length of the packet
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the packet is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for XdpFrame {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        let frame = self.addr - self.addr % shared.frame_size as u64;
+        shared.rx_frames.push(frame);
+    }
+}
+
+/// An AF_XDP socket, bound to one queue of a network interface.
+///
+/// Packets steered to the queue by an XDP program are written by the kernel, or by the
+/// card itself in zero copy mode, to memory shared with the socket, and handed to it
+/// through a ring, without going through the network stack. Sending works the same
+/// way in reverse. That makes it possible to process packets at line rate from a
+/// single executor, pinned to the core that serves the queue.
+///
+/// The shared memory, or UMEM, is a [`DmaBuffer`] split in frames. The socket keeps the
+/// kernel supplied with free frames and takes back the ones it finished sending from
+/// every time it is used, so no separate task is needed to keep the rings moving.
+///
+/// Loading the XDP program that redirects packets to the socket is up to the
+/// application. Creating the socket needs `CAP_NET_RAW`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use scipio::{LocalExecutor, XdpConfig, XdpSocket};
+///
+/// let ex = LocalExecutor::new(Some(2)).unwrap();
+/// ex.run(async {
+///     let socket = XdpSocket::bind("eth0", 2, XdpConfig::default()).unwrap();
+///     loop {
+///         let packet = socket.recv().await.unwrap();
+///         // bounce it back
+///         let bytes = packet.with_bytes(|bytes| bytes.to_vec());
+///         drop(packet);
+///         socket.send(&bytes).await.unwrap();
+///     }
+/// });
+/// ```
+///
+/// [`DmaBuffer`]: struct.DmaBuffer.html
+#[derive(Debug)]
+pub struct XdpSocket {
+    // Closed before the shared memory goes away, which frames may delay further
+    fd: Async<XdpFd>,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl XdpSocket {
+    /// Creates a socket bound to queue `queue_id` of the network interface called
+    /// `interface`.
+    pub fn bind(interface: &str, queue_id: u32, config: XdpConfig) -> io::Result<XdpSocket> {
+        let bad_config = |what| Err(io::Error::new(io::ErrorKind::InvalidInput, what));
+        if config.frame_size != 2048 && config.frame_size != 4096 {
+            return bad_config("frame size must be 2048 or 4096");
+        }
+        if !config.ring_size.is_power_of_two() {
+            return bad_config("ring size must be a power of two");
+        }
+        if config.frames < 2 {
+            return bad_config("at least two frames are needed");
+        }
+        let name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad interface name"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Allocated first so that on errors it is freed last, once the socket is closed
+        let umem = Reactor::get().alloc_dma_buffer(config.frame_size * config.frames);
+
+        let fd = unsafe { libc::socket(AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = XdpFd(fd);
+        let raw = fd.as_raw_fd();
+
+        let reg = XdpUmemReg {
+            addr: umem.as_ptr() as u64,
+            len: (config.frame_size * config.frames) as u64,
+            chunk_size: config.frame_size as u32,
+            ..Default::default()
+        };
+        set_option(raw, XDP_UMEM_REG, &reg)?;
+        for ring in &[
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ] {
+            set_option(raw, *ring, &config.ring_size)?;
+        }
+
+        let offsets: XdpMmapOffsets = get_option(raw, XDP_MMAP_OFFSETS)?;
+        let size = config.ring_size;
+        let fill = map_ring(raw, &offsets.fr, size, XDP_UMEM_PGOFF_FILL_RING)?;
+        let completion = map_ring(raw, &offsets.cr, size, XDP_UMEM_PGOFF_COMPLETION_RING)?;
+        let rx = map_ring(raw, &offsets.rx, size, XDP_PGOFF_RX_RING)?;
+        let tx = map_ring(raw, &offsets.tx, size, XDP_PGOFF_TX_RING)?;
+
+        let addr = SockaddrXdp {
+            family: AF_XDP as u16,
+            flags: if config.zero_copy { XDP_ZEROCOPY } else { 0 },
+            ifindex,
+            queue_id,
+            shared_umem_fd: 0,
+        };
+        let res = unsafe {
+            libc::bind(
+                raw,
+                &addr as *const SockaddrXdp as *const libc::sockaddr,
+                mem::size_of::<SockaddrXdp>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let frames: Vec<u64> = (0..config.frames)
+            .map(|i| (i * config.frame_size) as u64)
+            .collect();
+        let (rx_frames, tx_frames) = frames.split_at(config.frames / 2);
+        let mut shared = Shared {
+            fill,
+            completion,
+            rx,
+            tx,
+            frame_size: config.frame_size,
+            rx_frames: rx_frames.to_vec(),
+            tx_frames: tx_frames.to_vec(),
+            stats: XdpStats::default(),
+            umem,
+        };
+        shared.pump();
+
+        Ok(XdpSocket {
+            fd: Async::new(fd)?,
+            shared: Rc::new(RefCell::new(shared)),
+        })
+    }
+
+    /// Whether packets are copied between the card and the shared memory, as opposed to
+    /// written to it by the card directly
+    pub fn is_copy_mode(&self) -> io::Result<bool> {
+        let mut addr = SockaddrXdp::default();
+        let mut len = mem::size_of::<SockaddrXdp>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockname(
+                self.fd.as_raw_fd(),
+                &mut addr as *mut SockaddrXdp as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(addr.flags & XDP_COPY != 0 || addr.flags & XDP_ZEROCOPY == 0)
+    }
+
+    /// Receives a packet, waiting for one to arrive if none did yet.
+    pub async fn recv(&self) -> io::Result<XdpFrame> {
+        loop {
+            if let Some(frame) = self.try_recv() {
+                return Ok(frame);
+            }
+            self.fd.readable().await?;
+        }
+    }
+
+    /// Receives a packet if one already arrived.
+    pub fn try_recv(&self) -> Option<XdpFrame> {
+        let mut shared = self.shared.borrow_mut();
+        shared.pump();
+        let desc = shared.rx.consume()?;
+        shared.stats.received += 1;
+        Some(XdpFrame {
+            shared: self.shared.clone(),
+            addr: desc.addr,
+            len: desc.len as usize,
+        })
+    }
+
+    /// Sends a packet, waiting for a frame to copy it into if all of them are in flight.
+    pub async fn send(&self, packet: &[u8]) -> io::Result<()> {
+        let mut backoff = MIN_COMPLETION_WAIT;
+        loop {
+            match self.try_send(packet) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    // Makes sure the kernel works through what is queued already
+                    self.kick()?;
+                    if self.shared.borrow().tx.free_entries() == 0 {
+                        // The socket is writable once the transmit ring has room
+                        self.fd.writable().await?;
+                    } else {
+                        // Every frame is in flight, and nothing tells when the kernel
+                        // hands one back through the completion ring: check back later.
+                        Timer::new(backoff).await;
+                        backoff = std::cmp::min(backoff * 2, MAX_COMPLETION_WAIT);
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Sends a packet if there is a frame to copy it into, and fails with `WouldBlock`
+    /// otherwise.
+    pub fn try_send(&self, packet: &[u8]) -> io::Result<()> {
+        let mut shared = self.shared.borrow_mut();
+        if packet.len() > shared.frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet larger than a frame",
+            ));
+        }
+        shared.pump();
+        if shared.tx.free_entries() == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let addr = match shared.tx_frames.pop() {
+            Some(addr) => addr,
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+        shared.fill_frame(addr, packet);
+        shared.tx.produce(XdpDesc {
+            addr,
+            len: packet.len() as u32,
+            options: 0,
+        });
+        shared.tx.publish();
+        shared.stats.sent += 1;
+        drop(shared);
+        self.kick()
+    }
+
+    // Tells the kernel there are packets to send
+    fn kick(&self) -> io::Result<()> {
+        let res = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                ptr::null(),
+                0,
+            )
+        };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EAGAIN) | Some(libc::EBUSY) | Some(libc::ENOBUFS) => {}
+                _ => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns statistics about this socket
+    pub fn stats(&self) -> XdpStats {
+        let mut stats = self.shared.borrow().stats.clone();
+        if let Ok(kernel) = get_option::<XdpStatistics>(self.fd.as_raw_fd(), XDP_STATISTICS) {
+            stats.rx_dropped = kernel.rx_dropped;
+            stats.invalid_descriptors = kernel.rx_invalid_descs + kernel.tx_invalid_descs;
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A ring over plain memory, with both counters, to play both sides of it
+    fn ring(counters: &mut [AtomicU32; 2], entries: &mut [u64]) -> Ring<u64> {
+        Ring {
+            producer: &counters[0],
+            consumer: &counters[1],
+            entries: entries.as_mut_ptr(),
+            mask: entries.len() as u32 - 1,
+            local: 0,
+            map: ptr::null_mut(),
+            map_len: 0,
+        }
+    }
+
+    #[test]
+    fn rings_wrap_around() {
+        let mut counters = [AtomicU32::new(0), AtomicU32::new(0)];
+        let mut entries = [0u64; 4];
+        let mut producer = ring(&mut counters, &mut entries);
+        let mut consumer = ring(&mut counters, &mut entries);
+
+        for round in 0..3u64 {
+            assert_eq!(producer.free_entries(), 4);
+            for i in 0..4 {
+                producer.produce(round * 4 + i);
+            }
+            assert_eq!(producer.free_entries(), 0);
+            // nothing is visible until published
+            assert_eq!(consumer.consume(), None);
+            producer.publish();
+            for i in 0..4 {
+                assert_eq!(consumer.consume(), Some(round * 4 + i));
+            }
+            assert_eq!(consumer.consume(), None);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// Unless explicitly stated otherwise all files in this repository are licensed under the
+// MIT/Apache-2.0 License, at your convenience
+//
+// This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
+//
+// The counting allocator has to be the global allocator of the whole binary, so it is
+// tested here, in a test binary of its own, rather than next to the library tests.
+use scipio::{CountingAllocator, LocalExecutor};
+use std::alloc::System;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator::new(System);
+
+#[test]
+fn allocations_show_in_executor_stats() {
+    let local_ex = LocalExecutor::new(None).unwrap();
+    let before = local_ex.stats().allocations();
+    local_ex.run(async {
+        let v = vec![1u8; 16];
+        assert_eq!(v.len(), 16);
+    });
+    let stats = local_ex.stats();
+    assert!(stats.allocations() > before);
+    assert!(stats.hot_path_allocations() <= stats.allocations());
+}